@@ -110,7 +110,7 @@ export function help() {
  ${green("titan init <project> [-t <template>]")}   Create new TitanPl project
  ${green("titan create ext <name>")} Create new TitanPl extension
  ${green("titan dev [-c]")}        Dev mode (hot reload) [-c to backward clean]
- ${green("titan build")}            Build production Rust server
+ ${green("titan build [--standalone]")} Build production Rust server [--standalone for one self-contained binary]
  ${green("titan start")}            Start production binary
  ${green("titan update")}           Update TitanPl Framework
  ${green("titan --version")}        Show TitanPl CLI version
@@ -331,7 +331,8 @@ export async function devServer(args = []) {
 /* -------------------------------------------------------
  * BUILD
  * ----------------------------------------------------- */
-export async function buildProd() {
+export async function buildProd(args = []) {
+    const standalone = args.includes("--standalone");
     console.log(cyan("Titan: Building production output..."));
 
     const root = process.cwd();
@@ -437,6 +438,28 @@ export async function buildProd() {
             cwd: serverDir,
             stdio: "inherit"
         });
+
+        if (standalone) {
+            // The release binary packs the app and its snapshot, then a second
+            // build embeds both into one self-contained executable
+            console.log(cyan("→ Embedding the app into a standalone binary..."));
+            const bin = process.platform === "win32" ? "titan-server.exe" : "titan-server";
+            const packDir = path.join(root, ".titan", "standalone");
+            execSync(`"${path.join(serverDir, "target", "release", bin)}" build --standalone --out "${packDir}"`, {
+                cwd: serverDir,
+                stdio: "inherit"
+            });
+            execSync("cargo build --release --features standalone", {
+                cwd: serverDir,
+                stdio: "inherit",
+                env: { ...process.env, TITAN_STANDALONE_DIR: packDir }
+            });
+
+            const distDir = path.join(root, "dist");
+            fs.mkdirSync(distDir, { recursive: true });
+            fs.copyFileSync(path.join(serverDir, "target", "release", bin), path.join(distDir, bin));
+            console.log(green(`✔ Standalone binary ready at dist/${bin}`));
+        }
         console.log(green("✔ Titan production build complete!"));
     } else {
         console.log(green("✔ Titan production build complete (pure JS/TS)!"));
@@ -719,7 +742,7 @@ if (isMainModule) {
                     break;
                 }
                 case "dev": devServer(process.argv.slice(3)); break;
-                case "build": await buildProd(); break;
+                case "build": await buildProd(args.slice(1)); break;
                 case "start": startProd(); break;
                 case "update": updateTitan(); break;
                 case "--version":
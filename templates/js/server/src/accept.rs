@@ -0,0 +1,69 @@
+//! Picks a representation by the `Accept` header, for `res.format()`.
+
+struct Range<'a> {
+    kind: &'a str,
+    subtype: &'a str,
+    q: f32,
+}
+
+impl Range<'_> {
+    // 2 for `text/html`, 1 for `text/*`, 0 for `*/*`; None if it doesn't cover the type
+    fn specificity(&self, kind: &str, subtype: &str) -> Option<u8> {
+        match (self.kind, self.subtype) {
+            ("*", "*") => Some(0),
+            (k, "*") if k.eq_ignore_ascii_case(kind) => Some(1),
+            (k, s) if k.eq_ignore_ascii_case(kind) && s.eq_ignore_ascii_case(subtype) => Some(2),
+            _ => None,
+        }
+    }
+}
+
+/// The index of the media type in `offered` that `accept` ranks highest.
+/// Each offer takes the q-value of the most specific range covering it;
+/// between equal q-values the more specific range wins, then the range
+/// listed first, then the earlier offer. None when every offer is refused.
+/// An empty header accepts anything, so the first offer is picked.
+pub fn preferred(accept: &str, offered: &[String]) -> Option<usize> {
+    let ranges: Vec<Range> = accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let (kind, subtype) = parts.next()?.trim().split_once('/')?;
+            let q = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some(Range { kind: kind.trim(), subtype: subtype.trim(), q })
+        })
+        .collect();
+    if ranges.is_empty() {
+        return (!offered.is_empty()).then_some(0);
+    }
+
+    let mut best: Option<(usize, f32, u8, usize)> = None;
+    for (i, offer) in offered.iter().enumerate() {
+        let media = offer.split(';').next().unwrap_or("").trim();
+        let Some((kind, subtype)) = media.split_once('/') else {
+            continue;
+        };
+        // The most specific range decides, even when a vaguer one has a higher q
+        let matched = ranges
+            .iter()
+            .enumerate()
+            .filter_map(|(order, range)| Some((range.specificity(kind, subtype)?, order, range.q)))
+            .max_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        let Some((specificity, order, q)) = matched.filter(|m| m.2 > 0.0) else {
+            continue;
+        };
+        let better = match best {
+            None => true,
+            Some((_, best_q, best_specificity, best_order)) => {
+                q > best_q || (q == best_q && (specificity > best_specificity || (specificity == best_specificity && order < best_order)))
+            }
+        };
+        if better {
+            best = Some((i, q, specificity, order));
+        }
+    }
+    best.map(|(i, ..)| i)
+}
@@ -0,0 +1,364 @@
+//! The access log: one line per response, apart from the server's own log,
+//! in the Apache/nginx `combined` format or as JSON. Each line splits the
+//! request's time into its wait in the queue, its run on a worker and the
+//! encoding of the response, from its `timing` timeline.
+
+use axum::extract::ConnectInfo;
+use axum::http::{Request, Response, header};
+use serde_json::{Value, json};
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tower::{Layer, Service};
+
+use crate::timing::{self, Breakdown};
+use crate::{ClientAddr, metrics};
+
+// Lines waiting for the writer thread; past this they are dropped, not waited for
+const BACKLOG: usize = 8192;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Combined,
+    Json,
+}
+
+struct AccessLog {
+    format: Format,
+    // The share of responses logged, from 0 to 1
+    sample: f64,
+    // Logged whatever the sample: errors, and responses slower than this
+    errors: bool,
+    slow: Option<Duration>,
+    seen: AtomicU64,
+    lines: SyncSender<String>,
+}
+
+/// The `access_log` block of titan.config as a layer. `true` or a format name
+/// (`"combined"`, `"json"`) logs to stdout. An object takes `format`, a file
+/// `path` under the project root rotated at `max_bytes` (default 100 MB) with
+/// `keep` old files (default 5), the `sample` share of responses to log, and
+/// whether `errors` (4xx and 5xx, default true) and responses slower than
+/// `slow_ms` are logged even when not sampled. Off unless set.
+#[derive(Clone)]
+pub struct AccessLogLayer {
+    log: Arc<AccessLog>,
+}
+
+impl AccessLogLayer {
+    pub fn from_config(config: &Value, root: &Path) -> Result<Option<Self>, String> {
+        let format = match config {
+            Value::Null | Value::Bool(false) => return Ok(None),
+            Value::Bool(true) => "combined",
+            Value::String(format) => format.as_str(),
+            Value::Object(_) => config["format"].as_str().unwrap_or("combined"),
+            _ => return Err("access_log must be true, a format or { format, path, sample, ... }".to_string()),
+        };
+        let format = match format {
+            "combined" => Format::Combined,
+            "json" => Format::Json,
+            other => return Err(format!("access_log: unknown format \"{}\", use \"combined\" or \"json\"", other)),
+        };
+        let sample = config["sample"].as_f64().unwrap_or(1.0);
+        if !(0.0..=1.0).contains(&sample) {
+            return Err("access_log: sample must be between 0 and 1".to_string());
+        }
+
+        let sink = match config["path"].as_str() {
+            Some(path) => {
+                let path = root.join(path);
+                let max_bytes = config["max_bytes"].as_u64().unwrap_or(100 * 1024 * 1024);
+                let keep = config["keep"].as_u64().unwrap_or(5) as usize;
+                Sink::file(path, max_bytes, keep).map_err(|e| format!("access_log: {}", e))?
+            }
+            None => Sink::Stdout,
+        };
+        let (lines, rx) = mpsc::sync_channel(BACKLOG);
+        std::thread::Builder::new()
+            .name("titan-access-log".to_string())
+            .spawn(move || write_lines(rx, sink))
+            .map_err(|e| format!("access_log: {}", e))?;
+
+        let log = AccessLog {
+            format,
+            sample,
+            errors: config["errors"].as_bool().unwrap_or(true),
+            slow: config["slow_ms"].as_u64().map(Duration::from_millis),
+            seen: AtomicU64::new(0),
+            lines,
+        };
+        ENABLED.store(true, Ordering::Relaxed);
+        Ok(Some(Self { log: Arc::new(log) }))
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self.log.format {
+            Format::Combined => "combined",
+            Format::Json => "json",
+        }
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLogService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLogService { inner, log: self.log.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct AccessLogService<S> {
+    inner: S,
+    log: Arc<AccessLog>,
+}
+
+impl<S, B, R> Service<Request<B>> for AccessLogService<S>
+where
+    S: Service<Request<B>, Response = Response<R>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = Response<R>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // The clone that was polled ready serves this request
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let log = self.log.clone();
+        let request = Line::from_request(&req);
+        let started = Instant::now();
+        Box::pin(timing::scope(async move {
+            let response = inner.call(req).await?;
+            let breakdown = timing::current().and_then(|timeline| timeline.breakdown());
+            log.write(request, &response, breakdown, started.elapsed());
+            Ok(response)
+        }))
+    }
+}
+
+// What is known of the request before it is answered
+struct Line {
+    at: SystemTime,
+    client: String,
+    method: String,
+    uri: String,
+    version: &'static str,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl Line {
+    fn from_request<B>(req: &Request<B>) -> Self {
+        let header = |name: header::HeaderName| req.headers().get(name).and_then(|v| v.to_str().ok());
+        let peer = req.extensions().get::<ConnectInfo<ClientAddr>>().map(|info| info.0.0);
+        let client = crate::proxy::client_ip(peer, |name| req.headers().get(name).and_then(|v| v.to_str().ok()));
+        Self {
+            at: SystemTime::now(),
+            client: client.map_or_else(|| "-".to_string(), |ip| ip.to_string()),
+            method: req.method().to_string(),
+            uri: req.uri().path_and_query().map_or_else(|| req.uri().path().to_string(), |pq| pq.to_string()),
+            version: match req.version() {
+                axum::http::Version::HTTP_09 => "HTTP/0.9",
+                axum::http::Version::HTTP_10 => "HTTP/1.0",
+                axum::http::Version::HTTP_2 => "HTTP/2.0",
+                axum::http::Version::HTTP_3 => "HTTP/3.0",
+                _ => "HTTP/1.1",
+            },
+            referer: header(header::REFERER).map(str::to_string),
+            user_agent: header(header::USER_AGENT).map(str::to_string),
+        }
+    }
+}
+
+impl AccessLog {
+    fn sampled(&self) -> bool {
+        if self.sample >= 1.0 {
+            return true;
+        }
+        // Exactly `sample` of the responses, spread evenly
+        let n = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * self.sample).floor() > (n * self.sample).floor()
+    }
+
+    fn write<R>(&self, line: Line, response: &Response<R>, breakdown: Option<Breakdown>, elapsed: Duration) {
+        let status = response.status().as_u16();
+        let forced = (self.errors && status >= 400) || self.slow.is_some_and(|slow| elapsed >= slow);
+        if !forced && !self.sampled() {
+            return;
+        }
+        let header = |name: &str| response.headers().get(name).and_then(|v| v.to_str().ok());
+        let bytes = header("content-length").and_then(|len| len.parse::<u64>().ok());
+        let request_id = header("x-request-id");
+        // Only requests a worker answered have the breakdown
+        let parts = breakdown.map(|b| (b.queue_ms, b.exec_ms, b.serialize_ms.unwrap_or(0.0)));
+        let duration_ms = elapsed.as_secs_f64() * 1000.0;
+
+        let text = match self.format {
+            Format::Json => {
+                let mut entry = json!({
+                    "time": crate::logging::timestamp_of(line.at),
+                    "client_ip": line.client,
+                    "method": line.method,
+                    "uri": line.uri,
+                    "protocol": line.version,
+                    "status": status,
+                    "bytes": bytes,
+                    "referer": line.referer,
+                    "user_agent": line.user_agent,
+                    "request_id": request_id,
+                    "duration_ms": round(duration_ms),
+                });
+                if let Some((queue_ms, exec_ms, serialize_ms)) = parts {
+                    entry["queue_ms"] = json!(round(queue_ms));
+                    entry["exec_ms"] = json!(round(exec_ms));
+                    entry["serialize_ms"] = json!(round(serialize_ms));
+                }
+                entry.to_string()
+            }
+            Format::Combined => {
+                let quoted = |value: Option<&str>| value.map_or_else(|| "\"-\"".to_string(), |v| format!("\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\"")));
+                let mut text = format!(
+                    "{} - - [{}] \"{} {} {}\" {} {} {} {}",
+                    line.client,
+                    clf_date(line.at),
+                    line.method,
+                    line.uri.replace('"', "%22"),
+                    line.version,
+                    status,
+                    bytes.map_or_else(|| "-".to_string(), |b| b.to_string()),
+                    quoted(line.referer.as_deref()),
+                    quoted(line.user_agent.as_deref()),
+                );
+                // nginx-style `key=seconds` pairs after the combined fields
+                let _ = write!(text, " rt={:.3}", duration_ms / 1000.0);
+                if let Some((queue_ms, exec_ms, serialize_ms)) = parts {
+                    let _ = write!(text, " queue={:.3} exec={:.3} ser={:.3}", queue_ms / 1000.0, exec_ms / 1000.0, serialize_ms / 1000.0);
+                }
+                if let Some(id) = request_id {
+                    let _ = write!(text, " rid={}", id);
+                }
+                text
+            }
+        };
+        if self.lines.try_send(text).is_err() {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn round(ms: f64) -> f64 {
+    (ms * 1000.0).round() / 1000.0
+}
+
+// `10/Oct/2000:13:55:36 +0000`, the date of the Common Log Format
+fn clf_date(at: SystemTime) -> String {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    let (year, month, day) = crate::utils::civil_from_days(secs.div_euclid(86_400));
+    let rem = secs.rem_euclid(86_400);
+    format!("{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000", day, MONTHS[month as usize - 1], year, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+enum Sink {
+    Stdout,
+    File { path: PathBuf, file: BufWriter<File>, size: u64, max_bytes: u64, keep: usize },
+}
+
+impl Sink {
+    fn file(path: PathBuf, max_bytes: u64, keep: usize) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Sink::File { path, file: BufWriter::new(file), size, max_bytes, keep })
+    }
+
+    fn write(&mut self, line: &str) {
+        match self {
+            Sink::Stdout => {
+                let mut out = std::io::stdout().lock();
+                let _ = out.write_all(line.as_bytes());
+                let _ = out.write_all(b"\n");
+            }
+            Sink::File { path, file, size, max_bytes, keep } => {
+                let len = line.len() as u64 + 1;
+                if *max_bytes > 0 && *size > 0 && *size + len > *max_bytes {
+                    match rotate(path, *keep) {
+                        Ok(fresh) => {
+                            *file = BufWriter::new(fresh);
+                            *size = 0;
+                        }
+                        Err(e) => tracing::warn!("Access log rotation of {} failed: {}", path.display(), e),
+                    }
+                }
+                let _ = file.write_all(line.as_bytes());
+                let _ = file.write_all(b"\n");
+                *size += len;
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Sink::File { file, .. } = self {
+            let _ = file.flush();
+        }
+    }
+}
+
+// access.log becomes access.log.1, which becomes access.log.2, up to `keep`
+fn rotate(path: &Path, keep: usize) -> std::io::Result<File> {
+    let numbered = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    if keep == 0 {
+        let _ = std::fs::remove_file(path);
+    } else {
+        let _ = std::fs::remove_file(numbered(keep));
+        for n in (1..keep).rev() {
+            let _ = std::fs::rename(numbered(n), numbered(n + 1));
+        }
+        std::fs::rename(path, numbered(1))?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// Writes lines as they come, flushing whenever the backlog is empty
+fn write_lines(rx: Receiver<String>, mut sink: Sink) {
+    while let Ok(line) = rx.recv() {
+        sink.write(&line);
+        while let Ok(line) = rx.try_recv() {
+            sink.write(&line);
+        }
+        sink.flush();
+    }
+}
+
+/// Prometheus text exposition of the lines the writer could not keep up with.
+pub fn render(out: &mut String) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    metrics::header(out, "titan_access_log_dropped_total", "counter", "Access log lines dropped because the writer fell behind.");
+    let _ = writeln!(out, "titan_access_log_dropped_total {}", DROPPED.load(Ordering::Relaxed));
+}
@@ -0,0 +1,281 @@
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::middleware::{Decision, Interceptor};
+use crate::runtime::WorkerResult;
+
+// A token signed with a key we haven't seen refetches the JWKS, at most this often
+const JWKS_MIN_REFETCH: Duration = Duration::from_secs(30);
+
+/// How an action treats the bearer token.
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    /// 401 without a valid token.
+    Required,
+    /// Claims when a valid token is sent, 401 only for an invalid one.
+    Optional,
+    Off,
+}
+
+impl Mode {
+    fn from_config(value: &Value) -> Option<Self> {
+        match value {
+            Value::Bool(true) => Some(Mode::Required),
+            Value::Bool(false) => Some(Mode::Off),
+            Value::String(s) if s == "optional" => Some(Mode::Optional),
+            Value::String(s) if s == "required" => Some(Mode::Required),
+            _ => None,
+        }
+    }
+}
+
+enum Keys {
+    /// A shared secret or a PEM public key, for the listed algorithms.
+    Static(DecodingKey, Vec<Algorithm>),
+    Jwks(Arc<Jwks>),
+}
+
+/// Keys from a JWKS endpoint by `kid`, refreshed in the background.
+struct Jwks {
+    url: String,
+    keys: RwLock<HashMap<String, (DecodingKey, Algorithm)>>,
+    last_fetch: Mutex<Option<Instant>>,
+}
+
+impl Jwks {
+    async fn fetch(&self) -> Result<usize, String> {
+        *self.last_fetch.lock().unwrap() = Some(Instant::now());
+        let set: JwkSet = reqwest::get(&self.url)
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| e.to_string())?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut keys = HashMap::new();
+        for jwk in &set.keys {
+            let algorithm = match (jwk.common.key_algorithm, &jwk.algorithm) {
+                (Some(alg), _) => match Algorithm::from_str(&alg.to_string()) {
+                    Ok(alg) => alg,
+                    // Encryption keys
+                    Err(_) => continue,
+                },
+                (None, AlgorithmParameters::RSA(_)) => Algorithm::RS256,
+                (None, AlgorithmParameters::EllipticCurve(_)) => Algorithm::ES256,
+                (None, AlgorithmParameters::OctetKeyPair(_)) => Algorithm::EdDSA,
+                (None, AlgorithmParameters::OctetKey(_)) => continue,
+            };
+            if let Ok(key) = DecodingKey::from_jwk(jwk) {
+                keys.insert(jwk.common.key_id.clone().unwrap_or_default(), (key, algorithm));
+            }
+        }
+        let count = keys.len();
+        *self.keys.write().unwrap() = keys;
+        Ok(count)
+    }
+
+    fn refetch_soon(self: &Arc<Self>) {
+        let mut last = self.last_fetch.lock().unwrap();
+        if last.is_some_and(|at| at.elapsed() < JWKS_MIN_REFETCH) {
+            return;
+        }
+        *last = Some(Instant::now());
+        let jwks = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = jwks.fetch().await {
+                tracing::error!("JWKS fetch failed: {}", e);
+            }
+        });
+    }
+}
+
+/// The `auth` block of titan.config: JWT bearer tokens checked in Rust
+/// before the request is queued. Verified claims reach JS as
+/// `req.auth.claims`.
+pub struct AuthConfig {
+    keys: Keys,
+    issuer: Vec<String>,
+    audience: Vec<String>,
+    leeway: u64,
+    default: Mode,
+    routes: HashMap<String, Mode>,
+    jwks_refresh: Duration,
+}
+
+impl AuthConfig {
+    /// None when there's no `auth` block. `TITAN_JWT_SECRET` wins over
+    /// `auth.secret`, like `TITAN_API_KEY` does for `api_key`.
+    pub fn from_config(config: &Value, root: &Path) -> Result<Option<Self>, String> {
+        if !config.is_object() {
+            return Ok(None);
+        }
+        let algorithms = |default: &[Algorithm]| -> Result<Vec<Algorithm>, String> {
+            match config["algorithms"].as_array() {
+                Some(names) => names
+                    .iter()
+                    .map(|name| {
+                        let name = name.as_str().unwrap_or_default();
+                        Algorithm::from_str(name).map_err(|_| format!("auth: unknown algorithm \"{}\"", name))
+                    })
+                    .collect(),
+                None => Ok(default.to_vec()),
+            }
+        };
+        let secret = std::env::var("TITAN_JWT_SECRET")
+            .ok()
+            .or_else(|| config["secret"].as_str().map(str::to_string))
+            .filter(|s| !s.is_empty());
+
+        let keys = if let Some(secret) = secret {
+            Keys::Static(DecodingKey::from_secret(secret.as_bytes()), algorithms(&[Algorithm::HS256])?)
+        } else if let Some(path) = config["public_key"].as_str() {
+            let pem = std::fs::read(root.join(path)).map_err(|e| format!("auth: {}: {}", path, e))?;
+            let (key, default) = if let Ok(key) = DecodingKey::from_rsa_pem(&pem) {
+                (key, Algorithm::RS256)
+            } else if let Ok(key) = DecodingKey::from_ed_pem(&pem) {
+                (key, Algorithm::EdDSA)
+            } else if let Ok(key) = DecodingKey::from_ec_pem(&pem) {
+                (key, Algorithm::ES256)
+            } else {
+                return Err(format!("auth: {} is not an RSA, Ed25519 or EC public key", path));
+            };
+            Keys::Static(key, algorithms(&[default])?)
+        } else if let Some(url) = config["jwks_url"].as_str() {
+            Keys::Jwks(Arc::new(Jwks {
+                url: url.to_string(),
+                keys: RwLock::new(HashMap::new()),
+                last_fetch: Mutex::new(None),
+            }))
+        } else {
+            return Err("auth: set \"secret\", \"public_key\" or \"jwks_url\"".to_string());
+        };
+
+        let strings = |value: &Value| match value {
+            Value::String(s) => vec![s.clone()],
+            Value::Array(items) => items.iter().filter_map(|s| s.as_str().map(str::to_string)).collect(),
+            _ => Vec::new(),
+        };
+        let routes = config["routes"]
+            .as_object()
+            .map(|routes| routes.iter().filter_map(|(action, mode)| Some((action.clone(), Mode::from_config(mode)?))).collect())
+            .unwrap_or_default();
+        Ok(Some(Self {
+            keys,
+            issuer: strings(&config["issuer"]),
+            audience: strings(&config["audience"]),
+            leeway: config["leeway_s"].as_u64().unwrap_or(60),
+            default: Mode::from_config(&config["default"]).unwrap_or(Mode::Required),
+            routes,
+            jwks_refresh: Duration::from_millis(config["jwks_refresh_ms"].as_u64().unwrap_or(600_000).max(1_000)),
+        }))
+    }
+
+    /// Fetches the JWKS before the server starts taking requests and keeps
+    /// it fresh. A failed first fetch is reported and retried on demand.
+    pub async fn start(&self) {
+        let Keys::Jwks(jwks) = &self.keys else {
+            return;
+        };
+        match jwks.fetch().await {
+            Ok(count) => tracing::info!("{} signing key(s) loaded from {}", count, jwks.url),
+            Err(e) => tracing::error!("JWKS fetch failed: {}", e),
+        }
+        let jwks = jwks.clone();
+        let every = self.jwks_refresh;
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(every).await;
+                if let Err(e) = jwks.fetch().await {
+                    tracing::error!("JWKS refresh failed: {}", e);
+                }
+            }
+        });
+    }
+
+    fn verify(&self, token: &str) -> Result<Value, String> {
+        let header = decode_header(token).map_err(|e| e.to_string())?;
+        let check = |key: &DecodingKey, algorithm: Algorithm| {
+            let mut validation = Validation::new(algorithm);
+            validation.leeway = self.leeway;
+            if !self.issuer.is_empty() {
+                validation.set_issuer(&self.issuer);
+            }
+            if self.audience.is_empty() {
+                validation.validate_aud = false;
+            } else {
+                validation.set_audience(&self.audience);
+            }
+            decode::<Value>(token, key, &validation).map(|data| data.claims).map_err(|e| e.to_string())
+        };
+        match &self.keys {
+            Keys::Static(key, algorithms) => {
+                if !algorithms.contains(&header.alg) {
+                    return Err(format!("{:?} tokens are not accepted", header.alg));
+                }
+                check(key, header.alg)
+            }
+            Keys::Jwks(jwks) => {
+                let keys = jwks.keys.read().unwrap();
+                // Without a kid, a single-key set is unambiguous
+                let found = match &header.kid {
+                    Some(kid) => keys.get(kid),
+                    None if keys.len() == 1 => keys.values().next(),
+                    None => None,
+                };
+                let Some((key, algorithm)) = found else {
+                    drop(keys);
+                    jwks.refetch_soon();
+                    return Err("token is signed with an unknown key".to_string());
+                };
+                if *algorithm != header.alg {
+                    return Err(format!("{:?} tokens are not accepted for this key", header.alg));
+                }
+                check(key, *algorithm)
+            }
+        }
+    }
+
+    fn mode_for(&self, action: &str) -> Mode {
+        self.routes.get(action).copied().unwrap_or(self.default)
+    }
+}
+
+fn unauthorized(error: Option<&str>) -> Decision {
+    let challenge = match error {
+        Some(_) => "Bearer error=\"invalid_token\"".to_string(),
+        None => "Bearer".to_string(),
+    };
+    let mut denied = WorkerResult::error(401, error.unwrap_or("Unauthorized"));
+    denied.headers.push(("www-authenticate".to_string(), challenge));
+    Decision::Respond(Box::new(denied))
+}
+
+/// Checks `Authorization: Bearer <jwt>` on every action whose mode isn't off
+/// and hands the verified claims to the worker.
+pub fn interceptor(config: AuthConfig) -> Interceptor {
+    Box::new(move |task| {
+        let mode = config.mode_for(&task.action_name);
+        if mode == Mode::Off {
+            return Decision::Continue;
+        }
+        let token = task.headers.iter().find_map(|(name, value)| {
+            name.eq_ignore_ascii_case("authorization").then(|| value.strip_prefix("Bearer ")).flatten()
+        });
+        let Some(token) = token else {
+            return if mode == Mode::Required { unauthorized(None) } else { Decision::Continue };
+        };
+        match config.verify(token.trim()) {
+            Ok(claims) => {
+                task.auth = Some(Arc::new(claims));
+                Decision::Continue
+            }
+            Err(e) => unauthorized(Some(&format!("Invalid token: {}", e))),
+        }
+    })
+}
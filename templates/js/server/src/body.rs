@@ -0,0 +1,173 @@
+use axum::body::{Body, BodyDataStream};
+use bytes::{Bytes, BytesMut};
+use dashmap::DashMap;
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+// Applies when titan.config doesn't set `body.max_mb`
+const DEFAULT_MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+static STREAMS: OnceLock<DashMap<u64, Arc<tokio::sync::Mutex<Source>>>> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, thiserror::Error)]
+pub enum BodyError {
+    #[error("Request body exceeds {0} bytes")]
+    TooLarge(u64),
+    #[error("Failed to read request body: {0}")]
+    Read(String),
+}
+
+impl BodyError {
+    pub fn status(&self) -> u16 {
+        match self {
+            BodyError::TooLarge(_) => 413,
+            BodyError::Read(_) => 400,
+        }
+    }
+}
+
+/// How an action receives its body.
+#[derive(Clone, Copy)]
+pub struct BodyRule {
+    /// None for no limit.
+    pub max_bytes: Option<u64>,
+    /// Hand the body to JS as a ReadableStream instead of buffering it.
+    pub stream: bool,
+}
+
+/// The `body` block of titan.config: `max_mb` for every action (0 for no
+/// limit) and per-action `{ max_mb, stream }` under `routes`.
+pub struct BodyPolicy {
+    default: BodyRule,
+    routes: HashMap<String, BodyRule>,
+}
+
+impl BodyPolicy {
+    pub fn from_config(config: &Value) -> Self {
+        let max_bytes = |options: &Value, base: Option<u64>| match options["max_mb"].as_u64() {
+            Some(0) => None,
+            Some(mb) => Some(mb * 1024 * 1024),
+            None => base,
+        };
+        let default = BodyRule { max_bytes: max_bytes(config, Some(DEFAULT_MAX_BYTES)), stream: false };
+        let routes = config["routes"]
+            .as_object()
+            .map(|routes| {
+                routes
+                    .iter()
+                    .map(|(action, options)| {
+                        let rule = BodyRule {
+                            max_bytes: max_bytes(options, default.max_bytes),
+                            stream: options["stream"].as_bool().unwrap_or(false),
+                        };
+                        (action.clone(), rule)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { default, routes }
+    }
+
+    /// Applies `maxBodyBytes` of each action's `config` export (0 for no
+    /// limit), which wins over titan.config.
+    pub fn with_exports(mut self, configs: &HashMap<String, Value>) -> Result<Self, String> {
+        for (action, config) in configs {
+            let max_bytes = match &config["maxBodyBytes"] {
+                Value::Null => continue,
+                value => value.as_u64().ok_or_else(|| format!("{}: config.maxBodyBytes must be a whole number of bytes", action))?,
+            };
+            let rule = self.routes.entry(action.clone()).or_insert(self.default);
+            rule.max_bytes = (max_bytes > 0).then_some(max_bytes);
+        }
+        Ok(self)
+    }
+
+    pub fn rule_for(&self, action: &str) -> BodyRule {
+        self.routes.get(action).copied().unwrap_or(self.default)
+    }
+}
+
+/// Reads the whole body, failing as soon as it grows past `max_bytes`. A
+/// `Content-Length` that is already too large fails before reading anything.
+pub async fn read_all(body: Body, max_bytes: Option<u64>, declared: Option<u64>) -> Result<Bytes, BodyError> {
+    let limit = max_bytes.unwrap_or(u64::MAX);
+    if declared.is_some_and(|len| len > limit) {
+        return Err(BodyError::TooLarge(limit));
+    }
+    let mut stream = body.into_data_stream();
+    let mut buf = BytesMut::with_capacity(declared.unwrap_or(0).min(64 * 1024) as usize);
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| BodyError::Read(e.to_string()))?;
+        if (buf.len() + chunk.len()) as u64 > limit {
+            return Err(BodyError::TooLarge(limit));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf.freeze())
+}
+
+struct Source {
+    stream: BodyDataStream,
+    read: u64,
+    limit: u64,
+    done: bool,
+}
+
+/// A request body the action pulls chunk by chunk. Nothing is read from the
+/// client until JS asks for it, so a slow consumer slows down the upload
+/// instead of filling memory. Unregistered when dropped, i.e. once the
+/// request has been answered.
+pub struct StreamedBody {
+    pub id: u64,
+}
+
+impl StreamedBody {
+    pub fn new(body: Body, max_bytes: Option<u64>) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let source = Source { stream: body.into_data_stream(), read: 0, limit: max_bytes.unwrap_or(u64::MAX), done: false };
+        STREAMS.get_or_init(DashMap::new).insert(id, Arc::new(tokio::sync::Mutex::new(source)));
+        Self { id }
+    }
+}
+
+impl Drop for StreamedBody {
+    fn drop(&mut self) {
+        if let Some(streams) = STREAMS.get() {
+            streams.remove(&self.id);
+        }
+    }
+}
+
+/// The next chunk of stream `id`, or None at the end of the body.
+pub async fn next_chunk(id: u64) -> Result<Option<Bytes>, BodyError> {
+    let source = STREAMS
+        .get()
+        .and_then(|streams| streams.get(&id).map(|s| s.clone()))
+        .ok_or_else(|| BodyError::Read("the request has already been answered".to_string()))?;
+    let mut source = source.lock().await;
+    if source.done {
+        return Ok(None);
+    }
+    match source.stream.next().await {
+        Some(Ok(chunk)) => {
+            source.read += chunk.len() as u64;
+            if source.read > source.limit {
+                source.done = true;
+                return Err(BodyError::TooLarge(source.limit));
+            }
+            Ok(Some(chunk))
+        }
+        Some(Err(e)) => {
+            source.done = true;
+            Err(BodyError::Read(e.to_string()))
+        }
+        None => {
+            source.done = true;
+            Ok(None)
+        }
+    }
+}
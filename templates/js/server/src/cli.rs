@@ -0,0 +1,249 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// How often `dev` looks for changed files
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+// Directories `dev` never looks into
+const IGNORED_DIRS: [&str; 4] = ["node_modules", "target", ".git", ".titan"];
+
+pub const USAGE: &str = "\
+Usage: titan-server [command] [options]
+
+Commands:
+  start    Serve the app (the default)
+  dev      Serve it, rebuilding whenever app/ changes and reloading the actions that did
+  build    Check every action loads and save the startup snapshot
+  help     Show this message
+
+Options:
+  -p, --port <port>        Port to listen on (PORT, then routes.json otherwise)
+  -s, --socket <path>      Listen on a Unix socket instead of the port
+  -t, --threads <count>    Workers in the pool
+  -c, --config <file>      The routes.json to serve (default ./routes.json)
+  -w, --watch              Have `start` swap in actions as their files change
+  -o, --out <path>         Where `build` writes the snapshot (default titan.snapshot beside the config)
+      --standalone         Have `build` write the snapshot and an archive of the app into the
+                           --out directory (default .titan/standalone), to embed in the binary
+      --inspect[=addr]     Serve the DevTools protocol (default 127.0.0.1:9229)
+  -V, --version            Print the version
+";
+
+/// What `start` and `dev` were asked to override.
+#[derive(Debug, Default, Clone)]
+pub struct ServeOptions {
+    pub port: Option<u16>,
+    pub socket: Option<PathBuf>,
+    pub threads: Option<usize>,
+    pub config: Option<PathBuf>,
+    pub watch: bool,
+}
+
+impl ServeOptions {
+    /// The routes.json holding the routes and `__config`.
+    pub fn config_path(&self) -> PathBuf {
+        self.config
+            .clone()
+            .or_else(|| crate::standalone::root().map(|root| root.join("routes.json")))
+            .unwrap_or_else(|| PathBuf::from("./routes.json"))
+    }
+
+    /// Where a snapshot saved by `build` is looked for: `TITAN_SNAPSHOT`, or
+    /// titan.snapshot beside the config.
+    pub fn snapshot_path(&self) -> PathBuf {
+        if let Ok(path) = std::env::var("TITAN_SNAPSHOT") {
+            return PathBuf::from(path);
+        }
+        self.config_path().parent().unwrap_or(Path::new(".")).join("titan.snapshot")
+    }
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Start(ServeOptions),
+    Dev(ServeOptions),
+    Build { options: ServeOptions, out: Option<PathBuf>, standalone: bool },
+    Help,
+    Version,
+}
+
+/// Reads the command line. With no command the server starts, as it always
+/// has; `--inspect` is left to the inspector, which reads it itself.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    let command = match args.peek().map(String::as_str) {
+        Some(word @ ("start" | "dev" | "build" | "help")) => {
+            let word = word.to_string();
+            args.next();
+            word
+        }
+        Some(other) if !other.starts_with('-') => return Err(format!("unknown command '{}'", other)),
+        _ => "start".to_string(),
+    };
+
+    let mut options = ServeOptions::default();
+    let mut out = None;
+    let mut standalone = false;
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| inline.clone().or_else(|| args.next()).ok_or_else(|| format!("{} needs a value", name));
+        match flag.as_str() {
+            "-p" | "--port" => {
+                let port = value("--port")?;
+                options.port = Some(port.parse().map_err(|_| format!("--port: '{}' is not a port", port))?);
+            }
+            "-s" | "--socket" => options.socket = Some(PathBuf::from(value("--socket")?)),
+            "-t" | "--threads" => {
+                let threads = value("--threads")?;
+                options.threads = Some(threads.parse().ok().filter(|n| *n > 0).ok_or_else(|| format!("--threads: '{}' is not a positive number", threads))?);
+            }
+            "-c" | "--config" => options.config = Some(PathBuf::from(value("--config")?)),
+            "-w" | "--watch" if command != "build" => options.watch = true,
+            "-o" | "--out" if command == "build" => out = Some(PathBuf::from(value("--out")?)),
+            "--standalone" if command == "build" => standalone = true,
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--inspect" => {}
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    Ok(match command.as_str() {
+        "dev" => Command::Dev(options),
+        "build" => Command::Build { options, out, standalone },
+        "help" => Command::Help,
+        _ => Command::Start(options),
+    })
+}
+
+// The options again, for the `start` that `dev` runs
+fn start_args(options: &ServeOptions) -> Vec<String> {
+    let mut args = vec!["start".to_string(), "--watch".to_string()];
+    if let Some(port) = options.port {
+        args.extend(["--port".to_string(), port.to_string()]);
+    }
+    if let Some(socket) = &options.socket {
+        args.extend(["--socket".to_string(), socket.display().to_string()]);
+    }
+    if let Some(threads) = options.threads {
+        args.extend(["--threads".to_string(), threads.to_string()]);
+    }
+    if let Some(config) = &options.config {
+        args.extend(["--config".to_string(), config.display().to_string()]);
+    }
+    args.extend(std::env::args().filter(|arg| arg.starts_with("--inspect")));
+    args
+}
+
+/// `dev`: runs `start --watch` in a child process and, whenever the app's
+/// sources change, rebuilds with `node app/app.js`. The child swaps in the
+/// actions that changed; it is only restarted when routes.json, .env or
+/// titan.config.toml did, or when it exited. A project without app/ has its
+/// actions and routes.json watched instead, for whatever builds them.
+pub async fn dev(options: ServeOptions, root: &Path) -> anyhow::Result<()> {
+    let sources = root.join("app");
+    let watched: Vec<PathBuf> = if sources.is_dir() {
+        vec![sources, root.join(".env")]
+    } else {
+        let mut paths = vec![options.config_path()];
+        paths.extend(crate::action_management::find_actions_dir(&root.to_path_buf()));
+        paths
+    };
+    // What a running server can't take in without starting over
+    let settings = vec![options.config_path(), root.join(".env"), root.join(crate::config::FILE)];
+    let build = root.join("app").join("app.js").exists();
+
+    let mut seen = fingerprint(&watched);
+    let mut child: Option<tokio::process::Child> = None;
+    let mut started_with = 0;
+    loop {
+        if build && !rebuild(root).await {
+            tracing::warn!("Build failed; waiting for changes to retry");
+        } else {
+            let exited = child.as_mut().is_none_or(|c| !matches!(c.try_wait(), Ok(None)));
+            let current = fingerprint(&settings);
+            if exited || current != started_with {
+                if let Some(old) = child.as_mut() {
+                    tracing::info!("Configuration changed; restarting");
+                    let _ = old.kill().await;
+                }
+                let exe = std::env::current_exe()?;
+                child = Some(tokio::process::Command::new(exe).args(start_args(&options)).kill_on_drop(true).spawn()?);
+                started_with = current;
+            }
+        }
+
+        // Changes are picked up once the files stop moving
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {
+                    if let Some(child) = child.as_mut() {
+                        let _ = child.kill().await;
+                    }
+                    return Ok(());
+                }
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+            let now = fingerprint(&watched);
+            if now == seen {
+                continue;
+            }
+            seen = now;
+            loop {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                let settled = fingerprint(&watched);
+                if settled == seen {
+                    break;
+                }
+                seen = settled;
+            }
+            break;
+        }
+        tracing::info!("Change detected; rebuilding");
+    }
+}
+
+async fn rebuild(root: &Path) -> bool {
+    let status = tokio::process::Command::new("node").arg("app/app.js").current_dir(root).status().await;
+    match status {
+        Ok(status) => status.success(),
+        Err(e) => {
+            tracing::error!("Could not run node app/app.js: {}", e);
+            false
+        }
+    }
+}
+
+// Sizes and modification times of every file under `paths`
+fn fingerprint(paths: &[PathBuf]) -> u64 {
+    fn visit(path: &Path, hasher: &mut DefaultHasher) {
+        let Ok(meta) = std::fs::metadata(path) else {
+            return;
+        };
+        if meta.is_dir() {
+            if path.file_name().is_some_and(|name| IGNORED_DIRS.iter().any(|d| name == *d)) {
+                return;
+            }
+            let Ok(entries) = std::fs::read_dir(path) else {
+                return;
+            };
+            let mut children: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+            children.sort();
+            for child in children {
+                visit(&child, hasher);
+            }
+        } else {
+            path.hash(hasher);
+            meta.len().hash(hasher);
+            meta.modified().ok().hash(hasher);
+        }
+    }
+    let mut hasher = DefaultHasher::new();
+    for path in paths {
+        visit(path, &mut hasher);
+    }
+    hasher.finish()
+}
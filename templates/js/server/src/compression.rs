@@ -0,0 +1,185 @@
+use axum::http::{HeaderMap, HeaderValue, header};
+use bytes::Bytes;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Write;
+
+// Past this size compressing on the async threads would stall other requests
+const BLOCKING_THRESHOLD: usize = 64 * 1024;
+
+const DEFAULT_TYPES: [&str; 6] = [
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "image/svg+xml",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+/// When a response is worth compressing.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub min_bytes: usize,
+    /// Content-type prefixes, e.g. `text/` or `application/json`.
+    pub types: Vec<String>,
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self {
+            min_bytes: 1024,
+            types: DEFAULT_TYPES.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+}
+
+impl Rule {
+    fn allows(&self, content_type: &str, len: usize) -> bool {
+        let content_type = content_type.trim().to_ascii_lowercase();
+        len >= self.min_bytes && self.types.iter().any(|t| content_type.starts_with(t.as_str()))
+    }
+
+    fn with_overrides(&self, options: &Value) -> Self {
+        Self {
+            min_bytes: options["min_bytes"].as_u64().map_or(self.min_bytes, |n| n as usize),
+            types: options["types"].as_array().map_or_else(
+                || self.types.clone(),
+                |types| types.iter().filter_map(Value::as_str).map(str::to_ascii_lowercase).collect(),
+            ),
+        }
+    }
+}
+
+/// Response compression settings from `__config.compression`.
+///
+/// `false` turns it off; an object adjusts `min_bytes` and `types`, and its
+/// `routes` map overrides them per action (`false` or another object).
+#[derive(Debug, Default)]
+pub struct CompressionPolicy {
+    default: Option<Rule>,
+    routes: HashMap<String, Option<Rule>>,
+}
+
+impl CompressionPolicy {
+    pub fn from_config(config: &Value) -> Self {
+        if config.as_bool() == Some(false) {
+            return Self::default();
+        }
+        let default = Rule::default().with_overrides(config);
+        let routes = config["routes"]
+            .as_object()
+            .map(|routes| {
+                routes
+                    .iter()
+                    .map(|(action, options)| {
+                        let rule = (options.as_bool() != Some(false)).then(|| default.with_overrides(options));
+                        (action.clone(), rule)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { default: Some(default), routes }
+    }
+
+    pub fn rule_for(&self, action: &str) -> Option<&Rule> {
+        match self.routes.get(action) {
+            Some(rule) => rule.as_ref(),
+            None => self.default.as_ref(),
+        }
+    }
+}
+
+/// Picks the encoding the client prefers among those supported, using the
+/// `q` weights of `Accept-Encoding`. Brotli wins ties.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    for entry in accept_encoding.split(',') {
+        let mut parts = entry.split(';');
+        let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let q = parts
+            .find_map(|p| p.trim().strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()))
+            .unwrap_or(1.0);
+        let encoding = match name.as_str() {
+            "br" => Encoding::Brotli,
+            "gzip" | "x-gzip" => Encoding::Gzip,
+            _ => continue,
+        };
+        if q <= 0.0 {
+            continue;
+        }
+        let better = match best {
+            None => true,
+            Some((current, best_q)) => q > best_q || (q == best_q && encoding == Encoding::Brotli && current != encoding),
+        };
+        if better {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Compresses `body` when the rule allows it, updating the response headers
+/// to match. The body comes back untouched otherwise.
+pub async fn apply(headers: &mut HeaderMap, body: Bytes, rule: &Rule, encoding: Encoding) -> Bytes {
+    headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if headers.contains_key(header::CONTENT_ENCODING) {
+        return body;
+    }
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("");
+    if !rule.allows(content_type, body.len()) {
+        return body;
+    }
+
+    let compressed = if body.len() > BLOCKING_THRESHOLD {
+        let input = body.clone();
+        tokio::task::spawn_blocking(move || compress(encoding, &input))
+            .await
+            .ok()
+            .and_then(Result::ok)
+    } else {
+        compress(encoding, &body).ok()
+    };
+    // Incompressible bodies can come out larger; send those as they are
+    match compressed {
+        Some(compressed) if compressed.len() < body.len() => {
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+            headers.remove(header::CONTENT_LENGTH);
+            Bytes::from(compressed)
+        }
+        _ => body,
+    }
+}
+
+fn compress(encoding: Encoding, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::with_capacity(data.len() / 2), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Encoding::Brotli => {
+            // Quality 5 compresses close to the maximum at a fraction of its cost
+            let mut output = Vec::with_capacity(data.len() / 2);
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut output, 4096, 5, 22);
+                encoder.write_all(data)?;
+            }
+            Ok(output)
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use bytes::BytesMut;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio_postgres::types::{FromSql, IsNull, Kind, ToSql, Type, to_sql_checked};
+use tokio_postgres::{Client, NoTls, Statement};
+
+use crate::metrics;
+use crate::utils::civil_from_days;
+
+const DEFAULT_POOL_SIZE: usize = 10;
+
+// Past this many distinct statements a connection starts its cache over
+const STATEMENT_CACHE_SIZE: usize = 256;
+
+static POOLS: OnceLock<Mutex<HashMap<String, &'static Pool>>> = OnceLock::new();
+
+type BoxError = Box<dyn Error + Sync + Send>;
+
+/// Postgres connections for one connection string, shared by every worker.
+/// Isolates borrow a connection per query instead of each opening their own.
+pub struct Pool {
+    /// `dbname@host`, for metrics; the connection string may hold a password.
+    label: String,
+    config: tokio_postgres::Config,
+    max_size: usize,
+    idle: Mutex<Vec<Connection>>,
+    permits: Semaphore,
+    open: AtomicUsize,
+    waiting: AtomicUsize,
+    queries: AtomicU64,
+    errors: AtomicU64,
+    cache_hits: AtomicU64,
+}
+
+struct Connection {
+    client: Client,
+    statements: HashMap<String, Statement>,
+    pool: &'static Pool,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.pool.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A connection on loan; it goes back to the idle list when dropped, unless
+/// the server closed it.
+struct PooledConnection {
+    conn: Option<Connection>,
+    _permit: SemaphorePermit<'static>,
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take()
+            && !conn.client.is_closed()
+        {
+            conn.pool.idle.lock().unwrap().push(conn);
+        }
+    }
+}
+
+/// Registers the pool for `conn_string`, or returns the existing one. This
+/// only validates the string; connections are opened by the first queries.
+pub fn pool(conn_string: &str, max_size: Option<usize>) -> Result<&'static Pool, String> {
+    let pools = POOLS.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut pools = pools.lock().unwrap();
+    if let Some(pool) = pools.get(conn_string) {
+        return Ok(pool);
+    }
+
+    let mut config: tokio_postgres::Config = conn_string.parse().map_err(|e| format!("invalid connection string: {}", e))?;
+    if config.get_connect_timeout().is_none() {
+        config.connect_timeout(Duration::from_secs(10));
+    }
+    let max_size = max_size.unwrap_or(DEFAULT_POOL_SIZE).max(1);
+    let pool: &'static Pool = Box::leak(Box::new(Pool {
+        label: pool_label(&config),
+        config,
+        max_size,
+        idle: Mutex::new(Vec::new()),
+        permits: Semaphore::new(max_size),
+        open: AtomicUsize::new(0),
+        waiting: AtomicUsize::new(0),
+        queries: AtomicU64::new(0),
+        errors: AtomicU64::new(0),
+        cache_hits: AtomicU64::new(0),
+    }));
+    pools.insert(conn_string.to_string(), pool);
+    Ok(pool)
+}
+
+/// Why a query failed.
+pub struct QueryError {
+    pub message: String,
+    /// The database could not be reached or dropped the connection, rather
+    /// than rejecting the statement.
+    pub unavailable: bool,
+}
+
+impl From<String> for QueryError {
+    fn from(message: String) -> Self {
+        Self { message, unavailable: false }
+    }
+}
+
+/// Runs `sql` with `params` bound to `$1`, `$2`... and returns the rows as
+/// JSON objects keyed by column name.
+pub async fn query(conn_string: &str, sql: &str, params: &[Value]) -> Result<Value, QueryError> {
+    let pool = pool(conn_string, None)?;
+    pool.queries.fetch_add(1, Ordering::Relaxed);
+    let result = pool.run(sql, params).await;
+    if result.is_err() {
+        pool.errors.fetch_add(1, Ordering::Relaxed);
+    }
+    result
+}
+
+/// The circuit queries through `conn_string` share: `db:dbname@host`.
+pub fn target(conn_string: &str) -> Option<String> {
+    pool(conn_string, None).ok().map(|pool| format!("db:{}", pool.label))
+}
+
+impl Pool {
+    async fn run(&'static self, sql: &str, params: &[Value]) -> Result<Value, QueryError> {
+        let mut pooled = self.acquire().await.map_err(|message| QueryError { message, unavailable: true })?;
+        let conn = pooled.conn.as_mut().expect("pooled connection");
+
+        let statement = match conn.statements.get(sql) {
+            Some(statement) => {
+                self.cache_hits.fetch_add(1, Ordering::Relaxed);
+                statement.clone()
+            }
+            None => {
+                let statement = conn.client.prepare(sql).await.map_err(|e| describe(&e))?;
+                if conn.statements.len() >= STATEMENT_CACHE_SIZE {
+                    conn.statements.clear();
+                }
+                conn.statements.insert(sql.to_string(), statement.clone());
+                statement
+            }
+        };
+
+        if statement.params().len() != params.len() {
+            return Err(format!("query expects {} parameter(s), got {}", statement.params().len(), params.len()).into());
+        }
+        let params: Vec<Param> = params.iter().map(Param).collect();
+        let refs: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+        let rows = match conn.client.query(&statement, &refs).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                // A schema change can invalidate the prepared plan
+                conn.statements.remove(sql);
+                return Err(describe(&e));
+            }
+        };
+
+        let rows = rows
+            .iter()
+            .map(|row| {
+                let mut obj = Map::new();
+                for (i, column) in row.columns().iter().enumerate() {
+                    let value = row.try_get::<_, Option<Cell>>(i).ok().flatten().map_or(Value::Null, |cell| cell.0);
+                    obj.insert(column.name().to_string(), value);
+                }
+                Value::Object(obj)
+            })
+            .collect();
+        Ok(Value::Array(rows))
+    }
+
+    async fn acquire(&'static self) -> Result<PooledConnection, String> {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let permit = self.permits.acquire().await;
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        let permit = permit.map_err(|e| e.to_string())?;
+
+        loop {
+            let Some(conn) = self.idle.lock().unwrap().pop() else {
+                break;
+            };
+            if !conn.client.is_closed() {
+                return Ok(PooledConnection { conn: Some(conn), _permit: permit });
+            }
+        }
+
+        let (client, connection) = self.config.connect(NoTls).await.map_err(|e| format!("Database connection failed: {}", e))?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::error!("Database connection closed: {}", e);
+            }
+        });
+        self.open.fetch_add(1, Ordering::Relaxed);
+        let conn = Connection { client, statements: HashMap::new(), pool: self };
+        Ok(PooledConnection { conn: Some(conn), _permit: permit })
+    }
+}
+
+fn describe(e: &tokio_postgres::Error) -> QueryError {
+    match e.as_db_error() {
+        Some(db) => db.message().to_string().into(),
+        None => QueryError { message: e.to_string(), unavailable: true },
+    }
+}
+
+fn pool_label(config: &tokio_postgres::Config) -> String {
+    let host = match config.get_hosts().first() {
+        Some(tokio_postgres::config::Host::Tcp(host)) => host.clone(),
+        #[cfg(unix)]
+        Some(tokio_postgres::config::Host::Unix(path)) => path.display().to_string(),
+        None => "localhost".to_string(),
+    };
+    format!("{}@{}", config.get_dbname().unwrap_or(config.get_user().unwrap_or("postgres")), host)
+}
+
+/// Prometheus text exposition of every pool.
+pub fn render(out: &mut String) {
+    let Some(pools) = POOLS.get() else {
+        return;
+    };
+    let pools: Vec<&'static Pool> = pools.lock().unwrap().values().copied().collect();
+    if pools.is_empty() {
+        return;
+    }
+    let series = |out: &mut String, name: &str, kind: &str, help: &str, value: &dyn Fn(&Pool) -> u64| {
+        metrics::header(out, name, kind, help);
+        for pool in &pools {
+            let _ = writeln!(out, "{}{{pool=\"{}\"}} {}", name, metrics::escape_label(&pool.label), value(pool));
+        }
+    };
+    series(out, "titan_db_pool_max_connections", "gauge", "Connections a pool may open.", &|p| p.max_size as u64);
+    series(out, "titan_db_pool_open_connections", "gauge", "Connections currently open.", &|p| p.open.load(Ordering::Relaxed) as u64);
+    series(out, "titan_db_pool_idle_connections", "gauge", "Open connections not in use.", &|p| p.idle.lock().unwrap().len() as u64);
+    series(out, "titan_db_pool_waiting", "gauge", "Queries waiting for a free connection.", &|p| p.waiting.load(Ordering::Relaxed) as u64);
+    series(out, "titan_db_queries_total", "counter", "Queries run.", &|p| p.queries.load(Ordering::Relaxed));
+    series(out, "titan_db_query_errors_total", "counter", "Queries that failed.", &|p| p.errors.load(Ordering::Relaxed));
+    series(out, "titan_db_statement_cache_hits_total", "counter", "Queries that reused a prepared statement.", &|p| {
+        p.cache_hits.load(Ordering::Relaxed)
+    });
+}
+
+// ----------------------------------------------------------------------------
+// PARAMETERS
+// ----------------------------------------------------------------------------
+
+/// A JSON query parameter. Numbers, booleans and JSON columns are sent in
+/// binary; anything else goes as text and is parsed by the server, so dates,
+/// UUIDs or numerics can be passed as strings.
+#[derive(Debug)]
+struct Param<'a>(&'a Value);
+
+fn is_binary(ty: &Type) -> bool {
+    matches!(
+        *ty,
+        Type::BOOL | Type::INT2 | Type::INT4 | Type::INT8 | Type::OID | Type::FLOAT4 | Type::FLOAT8 | Type::JSON | Type::JSONB
+    )
+}
+
+impl ToSql for Param<'_> {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, BoxError> {
+        let value = self.0;
+        if value.is_null() {
+            return Ok(IsNull::Yes);
+        }
+        let mismatch = || -> BoxError { format!("cannot bind {} to a {} parameter", value, ty).into() };
+        match *ty {
+            Type::BOOL => value.as_bool().ok_or_else(mismatch)?.to_sql(ty, out),
+            Type::INT2 => i16::try_from(value.as_i64().ok_or_else(mismatch)?)?.to_sql(ty, out),
+            Type::INT4 => i32::try_from(value.as_i64().ok_or_else(mismatch)?)?.to_sql(ty, out),
+            Type::INT8 => value.as_i64().ok_or_else(mismatch)?.to_sql(ty, out),
+            Type::OID => u32::try_from(value.as_u64().ok_or_else(mismatch)?)?.to_sql(ty, out),
+            Type::FLOAT4 => (value.as_f64().ok_or_else(mismatch)? as f32).to_sql(ty, out),
+            Type::FLOAT8 => value.as_f64().ok_or_else(mismatch)?.to_sql(ty, out),
+            Type::JSON | Type::JSONB => value.to_sql(ty, out),
+            _ => {
+                out.extend_from_slice(text_literal(value, matches!(ty.kind(), Kind::Array(_))).as_bytes());
+                Ok(IsNull::No)
+            }
+        }
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    fn encode_format(&self, ty: &Type) -> tokio_postgres::types::Format {
+        if is_binary(ty) {
+            tokio_postgres::types::Format::Binary
+        } else {
+            tokio_postgres::types::Format::Text
+        }
+    }
+
+    to_sql_checked!();
+}
+
+/// Text form of a parameter. JSON arrays bound to array columns become
+/// Postgres array literals (`{a,"b c",NULL}`).
+fn text_literal(value: &Value, array: bool) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(items) if array => {
+            let items: Vec<String> = items
+                .iter()
+                .map(|item| match item {
+                    Value::Null => "NULL".to_string(),
+                    Value::Array(_) => text_literal(item, true),
+                    other => {
+                        let text = text_literal(other, false);
+                        format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+                    }
+                })
+                .collect();
+            format!("{{{}}}", items.join(","))
+        }
+        other => other.to_string(),
+    }
+}
+
+// ----------------------------------------------------------------------------
+// RESULT COLUMNS
+// ----------------------------------------------------------------------------
+
+/// A column value converted to JSON. Numerics come back as strings to keep
+/// their precision, timestamps as ISO 8601 and bytea as `\x` hex.
+struct Cell(Value);
+
+impl<'a> FromSql<'a> for Cell {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, BoxError> {
+        let value = match *ty {
+            Type::BOOL => Value::from(bool::from_sql(ty, raw)?),
+            Type::INT2 => Value::from(i16::from_sql(ty, raw)?),
+            Type::INT4 => Value::from(i32::from_sql(ty, raw)?),
+            Type::INT8 => Value::from(i64::from_sql(ty, raw)?),
+            Type::OID => Value::from(u32::from_sql(ty, raw)?),
+            Type::FLOAT4 => Value::from(f32::from_sql(ty, raw)?),
+            Type::FLOAT8 => Value::from(f64::from_sql(ty, raw)?),
+            Type::JSON | Type::JSONB => Value::from_sql(ty, raw)?,
+            Type::NUMERIC => Value::String(numeric_to_string(raw)?),
+            Type::UUID => Value::String(uuid_to_string(raw)?),
+            Type::BYTEA => {
+                let mut hex = String::with_capacity(2 + raw.len() * 2);
+                hex.push_str("\\x");
+                for b in raw {
+                    let _ = write!(hex, "{:02x}", b);
+                }
+                Value::String(hex)
+            }
+            Type::DATE => Value::String(date_to_string(i32::from_be_bytes(raw.try_into()?))),
+            Type::TIMESTAMP => Value::String(timestamp_to_string(i64::from_be_bytes(raw.try_into()?), false)),
+            Type::TIMESTAMPTZ => Value::String(timestamp_to_string(i64::from_be_bytes(raw.try_into()?), true)),
+            _ => match ty.kind() {
+                Kind::Array(_) => {
+                    let items = Vec::<Option<Cell>>::from_sql(ty, raw)?;
+                    Value::Array(items.into_iter().map(|cell| cell.map_or(Value::Null, |c| c.0)).collect())
+                }
+                // Text-like and enum types are sent as UTF-8 in binary mode too
+                Kind::Enum(_) => Value::String(std::str::from_utf8(raw)?.to_string()),
+                _ if <String as FromSql>::accepts(ty) => Value::String(String::from_sql(ty, raw)?),
+                _ => Value::Null,
+            },
+        };
+        Ok(Cell(value))
+    }
+
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+}
+
+/// Binary numeric: digit count, weight, sign, display scale, then base-10000
+/// digits with the first one at 10000^weight.
+fn numeric_to_string(raw: &[u8]) -> Result<String, BoxError> {
+    let word = |i: usize| -> Result<u16, BoxError> {
+        raw.get(i * 2..i * 2 + 2).map(|b| u16::from_be_bytes([b[0], b[1]])).ok_or_else(|| "truncated numeric".into())
+    };
+    let ndigits = word(0)? as usize;
+    let weight = word(1)? as i16 as i64;
+    let sign = word(2)?;
+    let dscale = word(3)? as usize;
+    match sign {
+        0xC000 => return Ok("NaN".to_string()),
+        0xD000 => return Ok("Infinity".to_string()),
+        0xF000 => return Ok("-Infinity".to_string()),
+        _ => {}
+    }
+    let digits = (0..ndigits).map(|i| word(4 + i)).collect::<Result<Vec<u16>, _>>()?;
+    let digit = |i: i64| if i >= 0 { digits.get(i as usize).copied().unwrap_or(0) } else { 0 };
+
+    let mut out = String::new();
+    if sign == 0x4000 {
+        out.push('-');
+    }
+    if weight < 0 {
+        out.push('0');
+    } else {
+        for i in 0..=weight {
+            let _ = if i == 0 { write!(out, "{}", digit(i)) } else { write!(out, "{:04}", digit(i)) };
+        }
+    }
+    if dscale > 0 {
+        let mut fraction = String::new();
+        let mut i = weight + 1;
+        while fraction.len() < dscale {
+            let _ = write!(fraction, "{:04}", digit(i));
+            i += 1;
+        }
+        fraction.truncate(dscale);
+        out.push('.');
+        out.push_str(&fraction);
+    }
+    Ok(out)
+}
+
+fn uuid_to_string(raw: &[u8]) -> Result<String, BoxError> {
+    if raw.len() != 16 {
+        return Err("invalid uuid".into());
+    }
+    let mut out = String::with_capacity(36);
+    for (i, b) in raw.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            out.push('-');
+        }
+        let _ = write!(out, "{:02x}", b);
+    }
+    Ok(out)
+}
+
+// Postgres dates count from 2000-01-01, which is day 10957 of the Unix epoch
+const PG_EPOCH_DAYS: i64 = 10_957;
+
+fn date_to_string(days: i32) -> String {
+    match days {
+        i32::MAX => "infinity".to_string(),
+        i32::MIN => "-infinity".to_string(),
+        _ => {
+            let (y, m, d) = civil_from_days(days as i64 + PG_EPOCH_DAYS);
+            format!("{:04}-{:02}-{:02}", y, m, d)
+        }
+    }
+}
+
+fn timestamp_to_string(micros: i64, utc: bool) -> String {
+    match micros {
+        i64::MAX => return "infinity".to_string(),
+        i64::MIN => return "-infinity".to_string(),
+        _ => {}
+    }
+    let days = micros.div_euclid(86_400_000_000);
+    let rem = micros.rem_euclid(86_400_000_000);
+    let (y, m, d) = civil_from_days(days + PG_EPOCH_DAYS);
+    let secs = rem / 1_000_000;
+    let mut out = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", y, m, d, secs / 3600, secs / 60 % 60, secs % 60);
+    let fraction = rem % 1_000_000;
+    if fraction != 0 {
+        let _ = write!(out, ".{:06}", fraction);
+        while out.ends_with('0') {
+            out.pop();
+        }
+    }
+    if utc {
+        out.push('Z');
+    }
+    out
+}
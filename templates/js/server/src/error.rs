@@ -0,0 +1,121 @@
+use serde_json::{json, Value};
+
+use crate::runtime::{LimitExceeded, WorkerResult};
+
+/// Why a request got no regular response from its action. The HTTP layer
+/// picks the status code and error body from the kind.
+#[derive(Debug, thiserror::Error)]
+pub enum TitanError {
+    /// The queue was full and the request was shed.
+    #[error("Request queue is full")]
+    QueueFull,
+    #[error("Server is shutting down")]
+    ShuttingDown,
+    /// The worker went away before answering, e.g. after a panic.
+    #[error("The worker stopped before answering")]
+    WorkerCrashed,
+    #[error("Action timed out after {ms}ms")]
+    Timeout { ms: u64 },
+    /// The action threw or its promise rejected.
+    #[error("{message}")]
+    JsException { message: String, stack: Option<String> },
+    /// A resource limit cut the action off.
+    #[error("{}", .0.message())]
+    Limit(LimitExceeded),
+    /// The isolate was terminated without a limit to blame.
+    #[error("Execution terminated")]
+    Terminated,
+    /// The action's return value couldn't be turned into a response.
+    #[error("Failed to serialize the response: {0}")]
+    Serialization(String),
+    #[error("Action '{0}' not found")]
+    ActionNotFound(String),
+    /// The request could not be handed to a worker.
+    #[error("{0}")]
+    Dispatch(String),
+}
+
+impl TitanError {
+    pub fn status(&self) -> u16 {
+        match self {
+            TitanError::QueueFull | TitanError::ShuttingDown => 503,
+            TitanError::Timeout { .. } => 504,
+            _ => 500,
+        }
+    }
+
+    /// Stable name for the kind, sent as `kind` in error bodies.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TitanError::QueueFull => "queue_full",
+            TitanError::ShuttingDown => "shutting_down",
+            TitanError::WorkerCrashed => "worker_crashed",
+            TitanError::Timeout { .. } => "timeout",
+            TitanError::JsException { .. } => "exception",
+            TitanError::Limit(_) => "limit_exceeded",
+            TitanError::Terminated => "terminated",
+            TitanError::Serialization(_) => "serialization",
+            TitanError::ActionNotFound(_) => "action_not_found",
+            TitanError::Dispatch(_) => "dispatch",
+        }
+    }
+
+    pub fn stack(&self) -> Option<&str> {
+        match self {
+            TitanError::JsException { stack, .. } => stack.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The error as a response: `{ error, kind }`, with the JS stack when
+    /// `expose_stack` is set. Shed requests are told when to come back.
+    pub fn to_result(&self, expose_stack: bool) -> WorkerResult {
+        let mut body = match self {
+            TitanError::Limit(limit) => limit.to_json(),
+            _ => json!({ "error": self.to_string() }),
+        };
+        body["kind"] = Value::from(self.kind());
+        if expose_stack && let Some(stack) = self.stack() {
+            body["stack"] = Value::from(stack);
+        }
+        let mut result = WorkerResult::json(self.status(), body);
+        if matches!(self, TitanError::QueueFull) {
+            result.headers.push(("retry-after".to_string(), "1".to_string()));
+        }
+        result
+    }
+}
+
+/// Whether the client lists HTML first in `Accept`, as browsers navigating
+/// to a page do. `fetch()` and API clients don't.
+pub fn prefers_html(accept: &str) -> bool {
+    accept.split(',').next().is_some_and(|first| first.trim().starts_with("text/html"))
+}
+
+/// A bare HTML error page for clients that asked for HTML.
+pub fn html_page(status: u16, message: &str, stack: Option<&str>) -> String {
+    let reason = axum::http::StatusCode::from_u16(status)
+        .ok()
+        .and_then(|s| s.canonical_reason())
+        .unwrap_or("Error");
+    let stack = stack.map(|s| format!("<pre>{}</pre>", escape(s))).unwrap_or_default();
+    format!(
+        "<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>{status} {reason}</title></head>\
+         <body><h1>{status} {reason}</h1><p>{}</p>{stack}</body></html>\n",
+        escape(message)
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
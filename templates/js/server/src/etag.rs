@@ -0,0 +1,46 @@
+use axum::body::Body;
+use axum::http::{HeaderMap, Response, StatusCode, header};
+
+use crate::utils::parse_http_date;
+
+/// A strong ETag for a response body: the first 64 bits of its SHA-256 and
+/// its length. Compressed bodies hash to their own tag, as they should.
+pub fn for_body(body: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, body);
+    let prefix: [u8; 8] = digest.as_ref()[..8].try_into().unwrap_or_default();
+    format!("\"{:016x}-{:x}\"", u64::from_be_bytes(prefix), body.len())
+}
+
+/// Whether an `If-None-Match` list names `etag`. The comparison is weak, as
+/// RFC 9110 asks for GET and HEAD.
+pub fn matches(header: &str, etag: &str) -> bool {
+    let etag = etag.trim().trim_start_matches("W/");
+    header
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// Whether the client's copy is still current: `If-None-Match` against the
+/// response's ETag or, when the request has none, `If-Modified-Since`
+/// against its Last-Modified.
+pub fn is_fresh(if_none_match: Option<&str>, if_modified_since: Option<&str>, response: &HeaderMap) -> bool {
+    let value = |name| response.get(name).and_then(|v| v.to_str().ok());
+    if let Some(tags) = if_none_match {
+        return value(header::ETAG).is_some_and(|etag| matches(tags, etag));
+    }
+    match (if_modified_since.and_then(parse_http_date), value(header::LAST_MODIFIED).and_then(parse_http_date)) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+/// The 304 for `response`, keeping its validators and cache headers.
+pub fn not_modified(response: Response<Body>) -> Response<Body> {
+    let (mut parts, _) = response.into_parts();
+    parts.status = StatusCode::NOT_MODIFIED;
+    for name in [header::CONTENT_LENGTH, header::CONTENT_TYPE, header::CONTENT_ENCODING] {
+        parts.headers.remove(name);
+    }
+    Response::from_parts(parts, Body::empty())
+}
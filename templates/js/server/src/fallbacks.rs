@@ -0,0 +1,55 @@
+//! Actions that answer when the request can't be served as asked:
+//! app/actions/_not_found, _method_not_allowed and _error. Each gets what
+//! went wrong as `req.error`, and its response keeps the status of the
+//! problem unless it answers with a status other than 200.
+
+use serde_json::{Value, json};
+
+pub const NOT_FOUND: &str = "_not_found";
+pub const METHOD_NOT_ALLOWED: &str = "_method_not_allowed";
+pub const ERROR: &str = "_error";
+
+// Every method a route can be declared for, in the order Allow lists them
+pub const METHODS: [&str; 7] = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+
+/// Which of the fallback actions the app has.
+#[derive(Clone, Copy, Default)]
+pub struct Fallbacks {
+    not_found: bool,
+    method_not_allowed: bool,
+    pub error: bool,
+}
+
+impl Fallbacks {
+    pub fn from_actions<'a>(actions: impl Iterator<Item = &'a String>) -> Self {
+        let mut fallbacks = Self::default();
+        for action in actions {
+            match action.as_str() {
+                NOT_FOUND => fallbacks.not_found = true,
+                METHOD_NOT_ALLOWED => fallbacks.method_not_allowed = true,
+                ERROR => fallbacks.error = true,
+                _ => {}
+            }
+        }
+        fallbacks
+    }
+
+    /// The action for a request no route matched, if the app has one:
+    /// `_method_not_allowed` when other methods are routed at the path,
+    /// `_not_found` otherwise.
+    pub fn unrouted(&self, allowed: &[&str]) -> Option<&'static str> {
+        match allowed.is_empty() {
+            true => self.not_found.then_some(NOT_FOUND),
+            false => self.method_not_allowed.then_some(METHOD_NOT_ALLOWED),
+        }
+    }
+}
+
+/// `req.error` for a request no route matched.
+pub fn unrouted_error(allowed: &[&str]) -> Value {
+    if allowed.is_empty() {
+        json!({ "status": 404, "message": "Not Found" })
+    } else {
+        json!({ "status": 405, "message": "Method Not Allowed", "allow": allowed })
+    }
+}
@@ -0,0 +1,584 @@
+//! Geo/IP enrichment from MaxMind databases (GeoLite2 / GeoIP2 City,
+//! Country and ASN). The client address is looked up before the request is
+//! queued and the result rides along to the worker as `req.geo`, so actions
+//! get country, region and network without calling out to anything. The
+//! files are re-read when they change on disk, e.g. after `geoipupdate`.
+
+use serde_json::{Map, Value, json};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::middleware::{Decision, Interceptor};
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+const METADATA_MARKER: &[u8] = b"\xAB\xCD\xEFMaxMind.com";
+
+/// The `geoip` block of titan.config: `database` (a City or Country file)
+/// and an optional `asn_database`.
+pub struct GeoConfig {
+    databases: Vec<Source>,
+}
+
+struct Source {
+    path: PathBuf,
+    loaded: RwLock<Arc<Database>>,
+    modified: RwLock<Option<SystemTime>>,
+}
+
+impl GeoConfig {
+    /// None when there's no `geoip` block; an error when a database can't
+    /// be opened, so a typo fails startup instead of every lookup.
+    pub fn from_config(config: &Value, root: &Path) -> Result<Option<Arc<Self>>, String> {
+        let config = match config {
+            Value::String(path) => json!({ "database": path }),
+            Value::Object(_) => config.clone(),
+            _ => return Ok(None),
+        };
+        let mut databases = Vec::new();
+        for key in ["database", "asn_database"] {
+            let Some(file) = config[key].as_str() else {
+                continue;
+            };
+            let path = root.join(file);
+            let database = Database::open(&path).map_err(|e| format!("geoip: {}: {}", file, e))?;
+            tracing::info!("GeoIP database {} loaded ({})", file, database.kind);
+            databases.push(Source {
+                modified: RwLock::new(modified(&path)),
+                loaded: RwLock::new(Arc::new(database)),
+                path,
+            });
+        }
+        if databases.is_empty() {
+            return Err("geoip: set \"database\"".to_string());
+        }
+        Ok(Some(Arc::new(Self { databases })))
+    }
+
+    /// Polls the files and swaps in a new copy when one changes. A file
+    /// that fails to load keeps the previous copy in service.
+    pub fn start(self: &Arc<Self>) {
+        let geo = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(RELOAD_INTERVAL).await;
+                for source in &geo.databases {
+                    let now = modified(&source.path);
+                    if now.is_none() || now == *source.modified.read().unwrap() {
+                        continue;
+                    }
+                    let path = source.path.clone();
+                    match tokio::task::spawn_blocking(move || Database::open(&path)).await {
+                        Ok(Ok(database)) => {
+                            tracing::info!("GeoIP database {} reloaded", source.path.display());
+                            *source.loaded.write().unwrap() = Arc::new(database);
+                            *source.modified.write().unwrap() = now;
+                        }
+                        Ok(Err(e)) => tracing::error!("GeoIP reload of {} failed: {}", source.path.display(), e),
+                        Err(_) => {}
+                    }
+                }
+            }
+        });
+    }
+
+    /// Country, region, city, location and network of `ip`, from every
+    /// database that knows it. None for private and unknown addresses.
+    pub fn lookup(&self, ip: IpAddr) -> Option<Value> {
+        let mut geo = Map::new();
+        for source in &self.databases {
+            let database = source.loaded.read().unwrap().clone();
+            if let Some(record) = database.lookup(ip) {
+                extract(&record, &mut geo);
+            }
+        }
+        (!geo.is_empty()).then_some(Value::Object(geo))
+    }
+}
+
+/// Looks up every request's client address and hands the result to the
+/// worker. Never rejects a request.
+pub fn interceptor(geo: Arc<GeoConfig>) -> Interceptor {
+    Box::new(move |task| {
+        if let Some(ip) = task.client_ip() {
+            task.geo = geo.lookup(ip).map(Arc::new);
+        }
+        Decision::Continue
+    })
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// The fields actions care about, flattened out of the GeoIP2 record layout
+fn extract(record: &Value, geo: &mut Map<String, Value>) {
+    let mut put = |key: &str, value: &Value| {
+        if !value.is_null() {
+            geo.insert(key.to_string(), value.clone());
+        }
+    };
+    let country = if record["country"].is_object() { &record["country"] } else { &record["registered_country"] };
+    put("country", &country["iso_code"]);
+    put("country_name", &country["names"]["en"]);
+    put("continent", &record["continent"]["code"]);
+    put("region", &record["subdivisions"][0]["iso_code"]);
+    put("region_name", &record["subdivisions"][0]["names"]["en"]);
+    put("city", &record["city"]["names"]["en"]);
+    put("postal_code", &record["postal"]["code"]);
+    put("latitude", &record["location"]["latitude"]);
+    put("longitude", &record["location"]["longitude"]);
+    put("timezone", &record["location"]["time_zone"]);
+    put("asn", &record["autonomous_system_number"]);
+    put("as_org", &record["autonomous_system_organization"]);
+}
+
+/// One MaxMind DB file, read into memory: a binary search tree over the
+/// address bits whose leaves point into a data section of typed values.
+struct Database {
+    data: Vec<u8>,
+    kind: String,
+    node_count: usize,
+    record_size: usize,
+    ip_version: u64,
+    // Node reached after the 96 zero bits that prefix IPv4 in an IPv6 tree
+    ipv4_start: usize,
+}
+
+impl Database {
+    fn open(path: &Path) -> Result<Self, String> {
+        Self::parse(std::fs::read(path).map_err(|e| e.to_string())?)
+    }
+
+    fn parse(data: Vec<u8>) -> Result<Self, String> {
+        // The metadata sits after the last marker, within the final 128 KiB
+        let tail = data.len().saturating_sub(128 * 1024);
+        let start = data[tail..]
+            .windows(METADATA_MARKER.len())
+            .rposition(|w| w == METADATA_MARKER)
+            .map(|at| tail + at + METADATA_MARKER.len())
+            .ok_or("not a MaxMind DB file")?;
+        let (metadata, _) = Decoder { section: &data[start..] }.decode(0, 0)?;
+
+        let node_count = metadata["node_count"].as_u64().ok_or("metadata has no node_count")? as usize;
+        let record_size = metadata["record_size"].as_u64().unwrap_or(0) as usize;
+        if ![24, 28, 32].contains(&record_size) {
+            return Err(format!("unsupported record size {}", record_size));
+        }
+        let tree_size = node_count.checked_mul(record_size / 4).ok_or("search tree runs past the end of the file")?;
+        if tree_size.saturating_add(16) > start {
+            return Err("search tree runs past the end of the file".to_string());
+        }
+        let mut database = Self {
+            kind: metadata["database_type"].as_str().unwrap_or("unknown").to_string(),
+            ip_version: metadata["ip_version"].as_u64().unwrap_or(6),
+            data,
+            node_count,
+            record_size,
+            ipv4_start: 0,
+        };
+        if database.ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= node_count {
+                    break;
+                }
+                node = database.record(node, 0);
+            }
+            database.ipv4_start = node;
+        }
+        Ok(database)
+    }
+
+    fn lookup(&self, ip: IpAddr) -> Option<Value> {
+        let (bytes, mut node): (Vec<u8>, usize) = match ip {
+            IpAddr::V4(v4) if self.ip_version == 6 => (v4.octets().to_vec(), self.ipv4_start),
+            IpAddr::V4(v4) => (v4.octets().to_vec(), 0),
+            IpAddr::V6(v6) if self.ip_version == 6 => (v6.octets().to_vec(), 0),
+            IpAddr::V6(v6) => (v6.to_ipv4_mapped()?.octets().to_vec(), 0),
+        };
+        for i in 0..bytes.len() * 8 {
+            if node >= self.node_count {
+                break;
+            }
+            let bit = (bytes[i / 8] >> (7 - i % 8)) & 1;
+            node = self.record(node, bit as usize);
+        }
+        if node <= self.node_count {
+            return None;
+        }
+        let section = &self.data[self.node_count * self.record_size / 4 + 16..];
+        // A corrupt tree can point into the 16 separator bytes
+        let offset = node.checked_sub(self.node_count + 16)?;
+        Decoder { section }.decode(offset, 0).ok().map(|(value, _)| value)
+    }
+
+    // The left (bit 0) or right (bit 1) record of `node`
+    fn record(&self, node: usize, bit: usize) -> usize {
+        let int = |b: &[u8]| b.iter().fold(0usize, |n, &b| n << 8 | b as usize);
+        match self.record_size {
+            24 => {
+                let at = node * 6 + bit * 3;
+                int(&self.data[at..at + 3])
+            }
+            28 => {
+                let at = node * 7;
+                let middle = self.data[at + 3] as usize;
+                if bit == 0 {
+                    (middle & 0xF0) << 20 | int(&self.data[at..at + 3])
+                } else {
+                    (middle & 0x0F) << 24 | int(&self.data[at + 4..at + 7])
+                }
+            }
+            _ => {
+                let at = node * 8 + bit * 4;
+                int(&self.data[at..at + 4])
+            }
+        }
+    }
+}
+
+// Values of the MaxMind DB data format; pointers are relative to `section`
+struct Decoder<'a> {
+    section: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn decode(&self, offset: usize, depth: usize) -> Result<(Value, usize), String> {
+        if depth > 32 {
+            return Err("data nested too deeply".to_string());
+        }
+        let byte = |at: usize| self.section.get(at).copied().ok_or_else(|| "data runs past the end of the file".to_string());
+        let bytes = |at: usize, len: usize| {
+            self.section.get(at..at + len).ok_or_else(|| "data runs past the end of the file".to_string())
+        };
+        let uint = |b: &[u8]| b.iter().fold(0u128, |n, &b| n << 8 | b as u128);
+
+        let control = byte(offset)?;
+        let mut at = offset + 1;
+        let mut kind = control >> 5;
+        if kind == 1 {
+            let size = ((control >> 3) & 0x3) as usize;
+            let low = (control & 0x7) as usize;
+            let raw = bytes(at, size + 1)?;
+            let pointer = match size {
+                0 => low << 8 | raw[0] as usize,
+                1 => (low << 16 | uint(raw) as usize) + 2048,
+                2 => (low << 24 | uint(raw) as usize) + 526_336,
+                _ => uint(raw) as usize,
+            };
+            let (value, _) = self.decode(pointer, depth + 1)?;
+            return Ok((value, at + size + 1));
+        }
+        if kind == 0 {
+            kind = byte(at)?.saturating_add(7);
+            at += 1;
+        }
+        let mut size = (control & 0x1F) as usize;
+        match size {
+            29 => {
+                size = 29 + byte(at)? as usize;
+                at += 1;
+            }
+            30 => {
+                size = 285 + uint(bytes(at, 2)?) as usize;
+                at += 2;
+            }
+            31 => {
+                size = 65_821 + uint(bytes(at, 3)?) as usize;
+                at += 3;
+            }
+            _ => {}
+        }
+
+        match kind {
+            2 => {
+                let text = String::from_utf8_lossy(bytes(at, size)?).into_owned();
+                Ok((Value::String(text), at + size))
+            }
+            3 => {
+                let raw: [u8; 8] = bytes(at, 8)?.try_into().unwrap();
+                Ok((json!(f64::from_be_bytes(raw)), at + 8))
+            }
+            4 => Ok((Value::from(bytes(at, size)?.to_vec()), at + size)),
+            5 | 6 | 9 | 10 => {
+                let n = uint(bytes(at, size)?);
+                let value = u64::try_from(n).map_or_else(|_| Value::String(n.to_string()), Value::from);
+                Ok((value, at + size))
+            }
+            7 => {
+                let mut map = Map::new();
+                for _ in 0..size {
+                    let (key, next) = self.decode(at, depth + 1)?;
+                    let (value, next) = self.decode(next, depth + 1)?;
+                    map.insert(key.as_str().unwrap_or_default().to_string(), value);
+                    at = next;
+                }
+                Ok((Value::Object(map), at))
+            }
+            8 => {
+                let n = uint(bytes(at, size)?) as u32 as i32;
+                Ok((Value::from(n), at + size))
+            }
+            11 => {
+                let mut items = Vec::with_capacity(size.min(256));
+                for _ in 0..size {
+                    let (value, next) = self.decode(at, depth + 1)?;
+                    items.push(value);
+                    at = next;
+                }
+                Ok((Value::Array(items), at))
+            }
+            14 => Ok((Value::Bool(size != 0), at)),
+            15 => {
+                let raw: [u8; 4] = bytes(at, 4)?.try_into().unwrap();
+                Ok((json!(f32::from_be_bytes(raw) as f64), at + 4))
+            }
+            _ => Err(format!("unknown data type {}", kind)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes values in the MaxMind DB data format
+    fn encode(value: &Value, out: &mut Vec<u8>) {
+        let head = |kind: u8, size: usize, out: &mut Vec<u8>| {
+            let (low, extra): (u8, &[u8]) = match size {
+                0..=28 => (size as u8, &[]),
+                29..=284 => (29, &[(size - 29) as u8]),
+                _ => (30, &((size - 285) as u16).to_be_bytes()),
+            };
+            if kind > 7 {
+                out.extend_from_slice(&[low, kind - 7]);
+            } else {
+                out.push(kind << 5 | low);
+            }
+            out.extend_from_slice(extra);
+        };
+        match value {
+            Value::String(s) => {
+                head(2, s.len(), out);
+                out.extend_from_slice(s.as_bytes());
+            }
+            Value::Number(n) if n.is_u64() => {
+                let bytes = n.as_u64().unwrap().to_be_bytes();
+                let bytes = &bytes[bytes.iter().position(|b| *b != 0).unwrap_or(8)..];
+                head(if bytes.len() > 4 { 9 } else { 6 }, bytes.len(), out);
+                out.extend_from_slice(bytes);
+            }
+            Value::Number(n) => {
+                head(3, 8, out);
+                out.extend_from_slice(&n.as_f64().unwrap().to_be_bytes());
+            }
+            Value::Bool(b) => head(14, usize::from(*b), out),
+            Value::Array(items) => {
+                head(11, items.len(), out);
+                items.iter().for_each(|item| encode(item, out));
+            }
+            Value::Object(map) => {
+                head(7, map.len(), out);
+                for (k, v) in map {
+                    encode(&Value::String(k.clone()), out);
+                    encode(v, out);
+                }
+            }
+            Value::Null => unreachable!("not in the format"),
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    enum Slot {
+        Empty,
+        Node(usize),
+        Data(usize),
+    }
+
+    // An IPv6 tree holding `networks` (address, prefix length, record), in
+    // `record_size`-bit records
+    fn database(record_size: usize, networks: &[(IpAddr, usize, Value)]) -> Vec<u8> {
+        let mut nodes = vec![[Slot::Empty; 2]];
+        let mut data = Vec::new();
+        for (ip, prefix, record) in networks {
+            let (bytes, prefix) = match ip {
+                IpAddr::V4(v4) => (v4.to_ipv6_compatible().octets(), prefix + 96),
+                IpAddr::V6(v6) => (v6.octets(), *prefix),
+            };
+            let mut node = 0;
+            for i in 0..prefix {
+                let bit = usize::from((bytes[i / 8] >> (7 - i % 8)) & 1);
+                if i + 1 == prefix {
+                    nodes[node][bit] = Slot::Data(data.len());
+                    encode(record, &mut data);
+                } else if let Slot::Node(next) = nodes[node][bit] {
+                    node = next;
+                } else {
+                    nodes.push([Slot::Empty; 2]);
+                    nodes[node][bit] = Slot::Node(nodes.len() - 1);
+                    node = nodes.len() - 1;
+                }
+            }
+        }
+
+        let count = nodes.len();
+        let value = |slot: Slot| match slot {
+            Slot::Empty => count,
+            Slot::Node(n) => n,
+            Slot::Data(offset) => count + 16 + offset,
+        };
+        let mut file = Vec::new();
+        for [left, right] in nodes {
+            let (a, b) = (value(left) as u32, value(right) as u32);
+            match record_size {
+                24 => {
+                    file.extend_from_slice(&a.to_be_bytes()[1..]);
+                    file.extend_from_slice(&b.to_be_bytes()[1..]);
+                }
+                28 => {
+                    file.extend_from_slice(&a.to_be_bytes()[1..]);
+                    file.push(((a >> 24) as u8) << 4 | (b >> 24) as u8);
+                    file.extend_from_slice(&b.to_be_bytes()[1..]);
+                }
+                _ => {
+                    file.extend_from_slice(&a.to_be_bytes());
+                    file.extend_from_slice(&b.to_be_bytes());
+                }
+            }
+        }
+        file.extend_from_slice(&[0; 16]);
+        file.extend_from_slice(&data);
+        file.extend_from_slice(METADATA_MARKER);
+        let metadata = json!({
+            "node_count": count,
+            "record_size": record_size,
+            "ip_version": 6,
+            "database_type": "GeoLite2-City",
+            "languages": ["en"],
+            "build_epoch": 1_700_000_000u64,
+        });
+        encode(&metadata, &mut file);
+        file
+    }
+
+    fn city() -> Value {
+        json!({
+            "continent": { "code": "OC" },
+            "country": { "iso_code": "AU", "names": { "en": "Australia", "de": "Australien" } },
+            "subdivisions": [{ "iso_code": "NSW", "names": { "en": "New South Wales" } }],
+            "city": { "names": { "en": "Sydney" } },
+            "location": { "latitude": -33.8688, "longitude": 151.2093, "time_zone": "Australia/Sydney" },
+            "is_anycast": true,
+        })
+    }
+
+    fn fixture(record_size: usize) -> Vec<u8> {
+        database(
+            record_size,
+            &[
+                ("1.2.0.0".parse().unwrap(), 16, city()),
+                ("2001:db8::".parse().unwrap(), 32, json!({ "autonomous_system_number": 64_496, "autonomous_system_organization": "Example" })),
+            ],
+        )
+    }
+
+    #[test]
+    fn looks_up_networks() {
+        for record_size in [24, 28, 32] {
+            let db = Database::parse(fixture(record_size)).unwrap();
+            assert_eq!(db.kind, "GeoLite2-City");
+            assert_eq!(db.lookup("1.2.3.4".parse().unwrap()), Some(city()), "{} bits", record_size);
+            assert_eq!(db.lookup("1.2.255.255".parse().unwrap()), Some(city()));
+            assert_eq!(db.lookup("2001:db8:ffff::1".parse().unwrap()).unwrap()["autonomous_system_number"], 64_496);
+            for ip in ["1.3.0.0", "8.8.8.8", "2001:db9::1", "::"] {
+                assert_eq!(db.lookup(ip.parse().unwrap()), None, "{}", ip);
+            }
+        }
+    }
+
+    #[test]
+    fn flattens_records_from_every_database() {
+        let dir = std::env::temp_dir().join(format!("titan-geo-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("city.mmdb"), fixture(24)).unwrap();
+        let asn = database(32, &[("1.0.0.0".parse().unwrap(), 8, json!({ "autonomous_system_number": 13_335, "autonomous_system_organization": "Cloudflare" }))]);
+        std::fs::write(dir.join("asn.mmdb"), asn).unwrap();
+
+        let config = json!({ "database": "city.mmdb", "asn_database": "asn.mmdb" });
+        let geo = GeoConfig::from_config(&config, &dir).unwrap().unwrap();
+        let missing = GeoConfig::from_config(&json!("nope.mmdb"), &dir);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(
+            geo.lookup("1.2.3.4".parse().unwrap()),
+            Some(json!({
+                "country": "AU",
+                "country_name": "Australia",
+                "continent": "OC",
+                "region": "NSW",
+                "region_name": "New South Wales",
+                "city": "Sydney",
+                "latitude": -33.8688,
+                "longitude": 151.2093,
+                "timezone": "Australia/Sydney",
+                "asn": 13_335,
+                "as_org": "Cloudflare",
+            }))
+        );
+        assert_eq!(geo.lookup("10.0.0.1".parse().unwrap()), None);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn follows_pointers() {
+        // {"a": "en", "b": <pointer to the "en" at 0>}
+        let section = [0x42, b'e', b'n', 0xe2, 0x41, b'a', 0x20, 0x00, 0x41, b'b', 0x20, 0x00];
+        let (value, end) = Decoder { section: &section }.decode(3, 0).unwrap();
+        assert_eq!(value, json!({ "a": "en", "b": "en" }));
+        assert_eq!(end, section.len());
+        // A pointer to itself
+        assert!(Decoder { section: &[0x20, 0x00] }.decode(0, 0).is_err());
+    }
+
+    #[test]
+    fn rejects_corrupt_files() {
+        assert_eq!(Database::parse(b"not a database".to_vec()).err().as_deref(), Some("not a MaxMind DB file"));
+        let mut odd = database(24, &[]);
+        let at = odd.len() - 30;
+        odd.truncate(at);
+        assert!(Database::parse(odd).is_err());
+
+        let with_metadata = |metadata: Value| {
+            let mut file = vec![0; 64];
+            file.extend_from_slice(METADATA_MARKER);
+            encode(&metadata, &mut file);
+            Database::parse(file).err().unwrap_or_default()
+        };
+        assert!(with_metadata(json!({ "record_size": 24 })).contains("node_count"));
+        assert!(with_metadata(json!({ "node_count": 1, "record_size": 27 })).contains("record size"));
+        assert!(with_metadata(json!({ "node_count": 100, "record_size": 24 })).contains("past the end"));
+        assert!(with_metadata(json!({ "node_count": u64::MAX, "record_size": 32 })).contains("past the end"));
+    }
+
+    #[test]
+    fn survives_truncated_and_damaged_files() {
+        let file = fixture(28);
+        let ips: Vec<IpAddr> = ["1.2.3.4", "2001:db8::1", "9.9.9.9"].iter().map(|ip| ip.parse().unwrap()).collect();
+        for end in 0..file.len() {
+            if let Ok(db) = Database::parse(file[..end].to_vec()) {
+                ips.iter().for_each(|ip| drop(db.lookup(*ip)));
+            }
+        }
+        for at in 0..file.len() {
+            for byte in [0x00, 0x1f, 0xff] {
+                let mut damaged = file.clone();
+                damaged[at] = byte;
+                if let Ok(db) = Database::parse(damaged) {
+                    ips.iter().for_each(|ip| drop(db.lookup(*ip)));
+                }
+            }
+        }
+    }
+}
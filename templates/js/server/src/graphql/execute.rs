@@ -0,0 +1,730 @@
+use bytes::Bytes;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use super::parser::{self as ast, Document, Field, FieldDefinition, InputValueDefinition, Operation, OperationKind, Selection, Type, TypeKind};
+use super::schema::Schema;
+use super::{Context, Graphql, GraphqlError, PathSegment, Resolver};
+
+// Input coercion -------------------------------------------------------------
+
+/// The operation's variables as the resolvers see them, defaults filled in.
+pub fn coerce_variables(schema: &Schema, op: &Operation, given: &Map<String, Value>) -> Result<Map<String, Value>, Vec<GraphqlError>> {
+    let mut out = Map::new();
+    let mut errors = Vec::new();
+    for var in &op.variables {
+        match given.get(&var.name) {
+            Some(value) => match input(schema, &var.ty, value) {
+                Ok(value) => {
+                    out.insert(var.name.clone(), value);
+                }
+                Err(reason) => errors.push(GraphqlError::new(
+                    format!("Variable \"${}\" got invalid value {}; {}", var.name, value, reason),
+                    Some(var.pos),
+                )),
+            },
+            None => match &var.default {
+                Some(default) => match literal(schema, &var.ty, default, &Map::new()) {
+                    Ok(value) => {
+                        out.insert(var.name.clone(), value);
+                    }
+                    Err(reason) => errors.push(GraphqlError::new(reason, Some(var.pos))),
+                },
+                None if matches!(var.ty, Type::NonNull(_)) => errors.push(GraphqlError::new(
+                    format!("Variable \"${}\" of required type \"{}\" was not provided.", var.name, var.ty),
+                    Some(var.pos),
+                )),
+                None => {}
+            },
+        }
+    }
+    if errors.is_empty() { Ok(out) } else { Err(errors) }
+}
+
+// A JSON variable value as a `ty`
+fn input(schema: &Schema, ty: &Type, value: &Value) -> Result<Value, String> {
+    match ty {
+        Type::NonNull(_) if value.is_null() => Err(format!("Expected non-nullable type \"{}\" not to be null.", ty)),
+        Type::NonNull(inner) => input(schema, inner, value),
+        _ if value.is_null() => Ok(Value::Null),
+        Type::List(inner) => match value {
+            Value::Array(items) => items.iter().map(|item| input(schema, inner, item)).collect::<Result<_, _>>().map(Value::Array),
+            // A single value stands for a list of one
+            _ => Ok(Value::Array(vec![input(schema, inner, value)?])),
+        },
+        Type::Named(name) => match schema.get(name).map(|d| &d.kind) {
+            Some(TypeKind::Scalar) => {
+                let ok = match name.as_str() {
+                    "Int" => value.as_i64().is_some_and(|n| i32::try_from(n).is_ok()),
+                    "Float" => value.is_number(),
+                    "String" => value.is_string(),
+                    "Boolean" => value.is_boolean(),
+                    "ID" => match value.as_i64() {
+                        Some(n) => return Ok(Value::String(n.to_string())),
+                        None => value.is_string(),
+                    },
+                    _ => true,
+                };
+                if ok { Ok(value.clone()) } else { Err(format!("{} cannot represent value: {}", name, value)) }
+            }
+            Some(TypeKind::Enum { values }) => match value {
+                Value::String(s) if values.iter().any(|v| v.name == *s) => Ok(value.clone()),
+                _ => Err(format!("Value {} does not exist in \"{}\" enum.", value, name)),
+            },
+            Some(TypeKind::Input { fields }) => {
+                let Value::Object(given) = value else {
+                    return Err(format!("Expected type \"{}\" to be an object.", name));
+                };
+                if let Some(unknown) = given.keys().find(|k| !fields.iter().any(|f| f.name == **k)) {
+                    return Err(format!("Field \"{}\" is not defined by type \"{}\".", unknown, name));
+                }
+                let mut out = Map::new();
+                for field in fields {
+                    let value = match (given.get(&field.name), &field.default) {
+                        (Some(value), _) => input(schema, &field.ty, value)?,
+                        (None, Some(default)) => literal(schema, &field.ty, default, &Map::new())?,
+                        (None, None) if matches!(field.ty, Type::NonNull(_)) => {
+                            return Err(format!("Field \"{}\" of required type \"{}\" was not provided.", field.name, field.ty));
+                        }
+                        (None, None) => continue,
+                    };
+                    out.insert(field.name.clone(), value);
+                }
+                Ok(Value::Object(out))
+            }
+            _ => Err(format!("\"{}\" is not an input type.", name)),
+        },
+    }
+}
+
+// A literal in the query, already validated, as JSON
+fn literal(schema: &Schema, ty: &Type, value: &ast::Value, vars: &Map<String, Value>) -> Result<Value, String> {
+    if let ast::Value::Variable(name) = value {
+        return Ok(vars.get(name).cloned().unwrap_or(Value::Null));
+    }
+    match ty {
+        Type::NonNull(_) if *value == ast::Value::Null => Err(format!("Expected non-nullable type \"{}\" not to be null.", ty)),
+        Type::NonNull(inner) => literal(schema, inner, value, vars),
+        _ if *value == ast::Value::Null => Ok(Value::Null),
+        Type::List(inner) => match value {
+            ast::Value::List(items) => items.iter().map(|item| literal(schema, inner, item, vars)).collect::<Result<_, _>>().map(Value::Array),
+            _ => Ok(Value::Array(vec![literal(schema, inner, value, vars)?])),
+        },
+        Type::Named(name) => match (schema.get(name).map(|d| &d.kind), value) {
+            (Some(TypeKind::Input { fields }), ast::Value::Object(given)) => {
+                let mut out = Map::new();
+                for field in fields {
+                    let given = given.iter().find(|(n, _)| *n == field.name).map(|(_, v)| v);
+                    if let Some(value) = given_value(given, vars) {
+                        out.insert(field.name.clone(), literal(schema, &field.ty, value, vars)?);
+                    } else if let Some(default) = &field.default {
+                        out.insert(field.name.clone(), literal(schema, &field.ty, default, vars)?);
+                    }
+                }
+                Ok(Value::Object(out))
+            }
+            (_, ast::Value::Int(n)) if name == "ID" => Ok(Value::String(n.to_string())),
+            _ => Ok(plain(value, vars)),
+        },
+    }
+}
+
+// A literal of a custom scalar, which has no type to go by
+fn plain(value: &ast::Value, vars: &Map<String, Value>) -> Value {
+    match value {
+        ast::Value::Variable(name) => vars.get(name).cloned().unwrap_or(Value::Null),
+        ast::Value::Int(n) => Value::from(*n),
+        ast::Value::Float(n) => Value::from(*n),
+        ast::Value::String(s) | ast::Value::Enum(s) => Value::String(s.clone()),
+        ast::Value::Boolean(b) => Value::Bool(*b),
+        ast::Value::Null => Value::Null,
+        ast::Value::List(items) => Value::Array(items.iter().map(|item| plain(item, vars)).collect()),
+        ast::Value::Object(fields) => Value::Object(fields.iter().map(|(k, v)| (k.clone(), plain(v, vars))).collect()),
+    }
+}
+
+// A variable that wasn't given counts as leaving the argument out
+fn given_value<'v>(value: Option<&'v ast::Value>, vars: &Map<String, Value>) -> Option<&'v ast::Value> {
+    match value {
+        Some(ast::Value::Variable(name)) if !vars.contains_key(name) => None,
+        other => other,
+    }
+}
+
+fn arguments(
+    schema: &Schema,
+    defs: &[InputValueDefinition],
+    given: &[ast::Argument],
+    vars: &Map<String, Value>,
+) -> Result<Map<String, Value>, String> {
+    let mut out = Map::new();
+    for def in defs {
+        let value = given_value(given.iter().find(|a| a.name == def.name).map(|a| &a.value), vars);
+        let value = match (value, &def.default) {
+            (Some(value), _) => literal(schema, &def.ty, value, vars)?,
+            (None, Some(default)) => literal(schema, &def.ty, default, vars)?,
+            (None, None) if matches!(def.ty, Type::NonNull(_)) => {
+                return Err(format!("Argument \"{}\" of required type \"{}\" was not provided.", def.name, def.ty));
+            }
+            (None, None) => continue,
+        };
+        if value.is_null() && matches!(def.ty, Type::NonNull(_)) {
+            return Err(format!("Argument \"{}\" of non-null type \"{}\" must not be null.", def.name, def.ty));
+        }
+        out.insert(def.name.clone(), value);
+    }
+    Ok(out)
+}
+
+// Output coercion ------------------------------------------------------------
+
+fn serialize_scalar(name: &str, value: Value) -> Result<Value, String> {
+    let out = match (name, &value) {
+        ("Int", Value::Number(n)) => n
+            .as_i64()
+            .or_else(|| n.as_f64().filter(|f| f.fract() == 0.0).map(|f| f as i64))
+            .filter(|n| i32::try_from(*n).is_ok())
+            .map(Value::from),
+        ("Int", Value::Bool(b)) => Some(Value::from(*b as i32)),
+        ("Int", Value::String(s)) => s.parse::<i32>().ok().map(Value::from),
+        ("Int", _) => None,
+        ("Float", Value::Number(_)) => Some(value.clone()),
+        ("Float", Value::Bool(b)) => Some(Value::from(if *b { 1.0 } else { 0.0 })),
+        ("Float", Value::String(s)) => s.parse::<f64>().ok().filter(|f| f.is_finite()).map(Value::from),
+        ("Float", _) => None,
+        ("String", Value::String(_)) => Some(value.clone()),
+        ("String", Value::Number(_) | Value::Bool(_)) => Some(Value::String(value.to_string())),
+        ("String", _) => None,
+        ("Boolean", Value::Bool(_)) => Some(value.clone()),
+        ("Boolean", Value::Number(n)) => Some(Value::Bool(n.as_f64() != Some(0.0))),
+        ("Boolean", _) => None,
+        ("ID", Value::String(_)) => Some(value.clone()),
+        ("ID", Value::Number(n)) if n.is_i64() || n.is_u64() => Some(Value::String(n.to_string())),
+        ("ID", _) => None,
+        // Custom scalars go out as the resolver returned them
+        _ => Some(value.clone()),
+    };
+    out.ok_or_else(|| format!("{} cannot represent value: {}", name, value))
+}
+
+// Execution ------------------------------------------------------------------
+
+// Response tree, built a level at a time
+enum Out {
+    Pending,
+    Null,
+    Leaf(Value),
+    Object(Vec<(String, usize)>),
+    List(Vec<usize>),
+}
+
+struct Node {
+    out: Out,
+    non_null: bool,
+    parent: Option<usize>,
+}
+
+// An object whose fields the next level resolves
+struct Object<'a> {
+    node: usize,
+    type_name: &'a str,
+    value: Arc<Value>,
+    selections: Vec<&'a [Selection]>,
+    path: Vec<PathSegment>,
+}
+
+// A field of an object in the current level
+struct Call<'a> {
+    node: usize,
+    parent_type: &'a str,
+    def: &'a FieldDefinition,
+    fields: Vec<&'a Field>,
+    parent: Arc<Value>,
+    args: Map<String, Value>,
+    path: Vec<PathSegment>,
+    resolver: Option<&'a Resolver>,
+    outcome: Result<Value, String>,
+}
+
+// Resolver calls that share one action run; several for a batch
+struct Dispatch<'a> {
+    action: &'a str,
+    body: Bytes,
+    calls: Vec<usize>,
+    batch: bool,
+}
+
+#[derive(Serialize)]
+struct Info<'a> {
+    #[serde(rename = "type")]
+    type_name: &'a str,
+    field: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a [PathSegment]>,
+    operation: Option<&'a str>,
+    variables: &'a Map<String, Value>,
+}
+
+#[derive(Serialize)]
+struct Single<'a> {
+    parent: &'a Value,
+    args: &'a Map<String, Value>,
+    info: Info<'a>,
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    parent: &'a Value,
+    args: &'a Map<String, Value>,
+    path: &'a [PathSegment],
+}
+
+#[derive(Serialize)]
+struct Batch<'a> {
+    batch: Vec<Entry<'a>>,
+    info: Info<'a>,
+}
+
+/// Runs an operation breadth first: every field of a level is resolved
+/// before the next level starts, so the resolver calls of a level go to the
+/// worker pool together. Identical calls run once, and a batch resolver
+/// gets all of a level's calls in one run. The top level of a mutation runs
+/// one field after another, in order.
+pub struct Executor<'a> {
+    gql: &'a Graphql,
+    schema: &'a Schema,
+    doc: &'a Document,
+    op: &'a Operation,
+    variables: Map<String, Value>,
+    ctx: &'a Context,
+    nodes: Vec<Node>,
+    errors: Vec<GraphqlError>,
+}
+
+impl<'a> Executor<'a> {
+    pub fn new(gql: &'a Graphql, doc: &'a Document, op: &'a Operation, variables: Map<String, Value>, ctx: &'a Context) -> Self {
+        Self { gql, schema: &gql.schema, doc, op, variables, ctx, nodes: Vec::new(), errors: Vec::new() }
+    }
+
+    /// The serialized `data`, and the field errors met on the way.
+    pub async fn run(mut self) -> (String, Vec<GraphqlError>) {
+        let root = self.schema.root(self.op.kind).unwrap_or(&self.schema.query);
+        self.nodes.push(Node { out: Out::Object(Vec::new()), non_null: false, parent: None });
+        let mut level = vec![Object {
+            node: 0,
+            type_name: root,
+            value: Arc::new(Value::Null),
+            selections: vec![&self.op.selections],
+            path: Vec::new(),
+        }];
+        let mut serial = self.op.kind == OperationKind::Mutation;
+        while !level.is_empty() {
+            let mut calls = Vec::new();
+            for object in level {
+                if !self.dead(object.node) {
+                    self.plan(object, &mut calls);
+                }
+            }
+            self.resolve(&mut calls, serial).await;
+            serial = false;
+            let mut next = Vec::new();
+            for call in calls {
+                self.complete_call(call, &mut next);
+            }
+            level = next;
+        }
+        let mut data = String::new();
+        self.write(0, &mut data);
+        (data, self.errors)
+    }
+
+    // Lays out the fields of `object` and works out the ones that need no resolver
+    fn plan(&mut self, object: Object<'a>, calls: &mut Vec<Call<'a>>) {
+        let mut fields = Vec::new();
+        let mut visited = HashSet::new();
+        for selections in &object.selections {
+            self.collect(object.type_name, selections, &mut fields, &mut visited);
+        }
+        let mut entries = Vec::with_capacity(fields.len());
+        for (key, fields) in fields {
+            let Some(def) = self.schema.field(object.type_name, &fields[0].name) else {
+                continue;
+            };
+            let node = self.push(object.node, matches!(def.ty, Type::NonNull(_)));
+            entries.push((key.to_string(), node));
+            let mut path = object.path.clone();
+            path.push(PathSegment::Key(key.to_string()));
+            let mut call = Call {
+                node,
+                parent_type: object.type_name,
+                def,
+                fields,
+                parent: object.value.clone(),
+                args: Map::new(),
+                path,
+                resolver: None,
+                outcome: Ok(Value::Null),
+            };
+            match arguments(self.schema, &def.args, &call.fields[0].arguments, &self.variables) {
+                Ok(args) => {
+                    call.args = args;
+                    call.resolver = self.gql.resolver(object.type_name, &def.name);
+                    if call.resolver.is_none() {
+                        call.outcome = Ok(self.field_value(&call));
+                    }
+                }
+                Err(message) => call.outcome = Err(message),
+            }
+            calls.push(call);
+        }
+        self.nodes[object.node].out = Out::Object(entries);
+    }
+
+    // A field without a resolver: the meta fields, or the parent's property
+    fn field_value(&self, call: &Call) -> Value {
+        let name = call.def.name.as_str();
+        match name {
+            "__typename" => Value::String(call.parent_type.to_string()),
+            "__schema" => self.schema.introspection().clone(),
+            "__type" => {
+                let name = call.args.get("name").and_then(Value::as_str);
+                name.and_then(|n| self.schema.type_value(n)).cloned().unwrap_or(Value::Null)
+            }
+            _ => {
+                let mut value = call.parent.get(name).cloned();
+                // A reference to a type is only its kind and name
+                if value.is_none() && call.parent_type == "__Type" {
+                    let full = call.parent.get("name").and_then(Value::as_str).and_then(|n| self.schema.type_value(n));
+                    value = full.and_then(|t| t.get(name)).cloned();
+                }
+                let mut value = value.unwrap_or(Value::Null);
+                if call.parent_type.starts_with("__")
+                    && call.args.get("includeDeprecated") == Some(&Value::Bool(false))
+                    && let Value::Array(items) = &mut value
+                {
+                    items.retain(|item| item["isDeprecated"] != Value::Bool(true));
+                }
+                value
+            }
+        }
+    }
+
+    fn collect(
+        &self,
+        type_name: &str,
+        selections: &'a [Selection],
+        out: &mut Vec<(&'a str, Vec<&'a Field>)>,
+        visited: &mut HashSet<&'a str>,
+    ) {
+        for selection in selections {
+            match selection {
+                Selection::Field(field) => {
+                    if !self.included(&field.directives) {
+                        continue;
+                    }
+                    match out.iter_mut().find(|(key, _)| *key == field.key()) {
+                        Some((_, fields)) => fields.push(field),
+                        None => out.push((field.key(), vec![field])),
+                    }
+                }
+                Selection::Spread(spread) => {
+                    if !self.included(&spread.directives) || !visited.insert(&spread.name) {
+                        continue;
+                    }
+                    if let Some(fragment) = self.doc.fragment(&spread.name)
+                        && self.applies(type_name, &fragment.type_condition)
+                    {
+                        self.collect(type_name, &fragment.selections, out, visited);
+                    }
+                }
+                Selection::Inline(inline) => {
+                    if self.included(&inline.directives)
+                        && inline.type_condition.as_deref().is_none_or(|c| self.applies(type_name, c))
+                    {
+                        self.collect(type_name, &inline.selections, out, visited);
+                    }
+                }
+            }
+        }
+    }
+
+    fn applies(&self, type_name: &str, condition: &str) -> bool {
+        self.schema.possible_types(condition).contains(&type_name)
+    }
+
+    // @skip and @include
+    fn included(&self, directives: &[ast::Directive]) -> bool {
+        directives.iter().all(|d| {
+            let flag = match d.arguments.iter().find(|a| a.name == "if").map(|a| &a.value) {
+                Some(ast::Value::Boolean(b)) => *b,
+                Some(ast::Value::Variable(v)) => self.variables.get(v).and_then(Value::as_bool).unwrap_or(false),
+                _ => false,
+            };
+            match d.name.as_str() {
+                "skip" => !flag,
+                "include" => flag,
+                _ => true,
+            }
+        })
+    }
+
+    // Runs the level's resolver calls and stores what they returned
+    async fn resolve(&mut self, calls: &mut [Call<'a>], serial: bool) {
+        let operation = self.op.name.as_deref();
+        let mut dispatches: Vec<Dispatch> = Vec::new();
+        let mut shared: HashMap<String, usize> = HashMap::new();
+        for (i, call) in calls.iter().enumerate() {
+            let Some(resolver) = call.resolver else {
+                continue;
+            };
+            if call.outcome.is_err() || self.dead(call.node) {
+                continue;
+            }
+            let field = call.def.name.as_str();
+            // Mutations may change something each time, so none are shared
+            let key = match (serial, resolver.batch) {
+                (true, _) => None,
+                (false, true) => Some(format!("{}\0{}.{}", resolver.action, call.parent_type, field)),
+                (false, false) => Some(format!(
+                    "{}\0{}.{}\0{}\0{}",
+                    resolver.action,
+                    call.parent_type,
+                    field,
+                    call.parent,
+                    Value::Object(call.args.clone())
+                )),
+            };
+            if let Some(&at) = key.as_ref().and_then(|k| shared.get(k)) {
+                dispatches[at].calls.push(i);
+                continue;
+            }
+            if let Some(key) = key {
+                shared.insert(key, dispatches.len());
+            }
+            dispatches.push(Dispatch { action: &resolver.action, body: Bytes::new(), calls: vec![i], batch: resolver.batch });
+        }
+
+        for dispatch in &mut dispatches {
+            let first = &calls[dispatch.calls[0]];
+            let info = |path| Info {
+                type_name: first.parent_type,
+                field: &first.def.name,
+                path,
+                operation,
+                variables: &self.variables,
+            };
+            let body = if dispatch.batch {
+                let batch = dispatch
+                    .calls
+                    .iter()
+                    .map(|i| Entry { parent: &calls[*i].parent, args: &calls[*i].args, path: &calls[*i].path })
+                    .collect();
+                serde_json::to_vec(&Batch { batch, info: info(None) })
+            } else {
+                serde_json::to_vec(&Single { parent: &first.parent, args: &first.args, info: info(Some(&first.path)) })
+            };
+            dispatch.body = Bytes::from(body.unwrap_or_default());
+        }
+
+        let ctx = self.ctx;
+        let results = if serial {
+            let mut results = Vec::with_capacity(dispatches.len());
+            for dispatch in &dispatches {
+                results.push(ctx.call(dispatch.action, dispatch.body.clone()).await);
+            }
+            results
+        } else {
+            futures_util::future::join_all(dispatches.iter().map(|d| ctx.call(d.action, d.body.clone()))).await
+        };
+
+        for (dispatch, result) in dispatches.iter().zip(results) {
+            if !dispatch.batch {
+                for i in &dispatch.calls {
+                    calls[*i].outcome = result.clone();
+                }
+                continue;
+            }
+            match result {
+                Ok(Value::Array(items)) if items.len() == dispatch.calls.len() => {
+                    for (i, item) in dispatch.calls.iter().zip(items) {
+                        calls[*i].outcome = Ok(item);
+                    }
+                }
+                other => {
+                    let message = other.and_then(|value| {
+                        Err(format!(
+                            "Batch resolver {} must return an array of {} results, one per call, but returned {}",
+                            dispatch.action,
+                            dispatch.calls.len(),
+                            match value {
+                                Value::Array(items) => format!("{} results", items.len()),
+                                other => other.to_string(),
+                            }
+                        ))
+                    });
+                    for i in &dispatch.calls {
+                        calls[*i].outcome = message.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    fn complete_call(&mut self, call: Call<'a>, next: &mut Vec<Object<'a>>) {
+        if self.dead(call.node) {
+            return;
+        }
+        let label = format!("{}.{}", call.parent_type, call.def.name);
+        let result = call
+            .outcome
+            .and_then(|value| self.complete(call.node, &call.def.ty, value, &call.fields, &call.path, &label, next));
+        if let Err(message) = result {
+            self.field_error(message, &call.fields, call.path, call.node);
+        }
+    }
+
+    // Puts `value` into the tree as a `ty`, queueing objects for the next level
+    #[allow(clippy::too_many_arguments)]
+    fn complete(
+        &mut self,
+        node: usize,
+        ty: &'a Type,
+        value: Value,
+        fields: &[&'a Field],
+        path: &[PathSegment],
+        label: &str,
+        next: &mut Vec<Object<'a>>,
+    ) -> Result<(), String> {
+        let ty = match ty {
+            Type::NonNull(_) if value.is_null() => return Err(format!("Cannot return null for non-nullable field {}.", label)),
+            Type::NonNull(inner) => &**inner,
+            _ if value.is_null() => {
+                self.nodes[node].out = Out::Null;
+                return Ok(());
+            }
+            ty => ty,
+        };
+        if let Type::List(item_ty) = ty {
+            let Value::Array(items) = value else {
+                return Err(format!("Expected Iterable, but did not find one for field \"{}\".", label));
+            };
+            let non_null = matches!(**item_ty, Type::NonNull(_));
+            let ids: Vec<usize> = items.iter().map(|_| self.push(node, non_null)).collect();
+            self.nodes[node].out = Out::List(ids.clone());
+            for (i, (item, id)) in items.into_iter().zip(ids).enumerate() {
+                let mut item_path = path.to_vec();
+                item_path.push(PathSegment::Index(i));
+                if let Err(message) = self.complete(id, item_ty, item, fields, &item_path, label, next) {
+                    self.field_error(message, fields, item_path, id);
+                }
+            }
+            return Ok(());
+        }
+
+        let name = ty.name();
+        match self.schema.get(name).map(|d| &d.kind) {
+            Some(TypeKind::Scalar) => self.nodes[node].out = Out::Leaf(serialize_scalar(name, value)?),
+            Some(TypeKind::Enum { values }) => match &value {
+                Value::String(s) if values.iter().any(|v| v.name == *s) => self.nodes[node].out = Out::Leaf(value),
+                _ => return Err(format!("Enum \"{}\" cannot represent value: {}", name, value)),
+            },
+            _ => {
+                if !value.is_object() {
+                    return Err(format!("Expected value of type \"{}\" to be an object, found: {}", name, value));
+                }
+                let type_name = self.concrete_type(name, &value, label)?;
+                self.nodes[node].out = Out::Object(Vec::new());
+                next.push(Object {
+                    node,
+                    type_name,
+                    value: Arc::new(value),
+                    selections: fields.iter().map(|f| f.selections.as_slice()).collect(),
+                    path: path.to_vec(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // The object type behind a value of an interface or union, named by its `__typename`
+    fn concrete_type(&self, name: &str, value: &Value, label: &str) -> Result<&'a str, String> {
+        let schema = self.schema;
+        if let Some(def) = schema.get(name).filter(|d| matches!(d.kind, TypeKind::Object { .. })) {
+            return Ok(&def.name);
+        }
+        let possible = schema.possible_types(name);
+        match value.get("__typename").and_then(Value::as_str) {
+            Some(given) => possible
+                .into_iter()
+                .find(|p| *p == given)
+                .ok_or_else(|| format!("Runtime Object type \"{}\" is not a possible type for \"{}\".", given, name)),
+            None if possible.len() == 1 => Ok(possible[0]),
+            None => Err(format!(
+                "Abstract type \"{}\" must resolve to an Object type at runtime for field \"{}\"; return an object with a \"__typename\".",
+                name, label
+            )),
+        }
+    }
+
+    fn push(&mut self, parent: usize, non_null: bool) -> usize {
+        self.nodes.push(Node { out: Out::Pending, non_null, parent: Some(parent) });
+        self.nodes.len() - 1
+    }
+
+    fn field_error(&mut self, message: String, fields: &[&Field], path: Vec<PathSegment>, node: usize) {
+        self.errors.push(GraphqlError { message, locations: fields.iter().take(1).map(|f| f.pos).collect(), path });
+        // A null where one isn't allowed nulls the nearest parent that can be null
+        let mut node = node;
+        loop {
+            self.nodes[node].out = Out::Null;
+            match self.nodes[node].parent {
+                Some(parent) if self.nodes[node].non_null => node = parent,
+                _ => break,
+            }
+        }
+    }
+
+    // Whether the node sits under a null, so nothing it resolves is seen
+    fn dead(&self, node: usize) -> bool {
+        let mut at = Some(node);
+        while let Some(node) = at {
+            if matches!(self.nodes[node].out, Out::Null) {
+                return true;
+            }
+            at = self.nodes[node].parent;
+        }
+        false
+    }
+
+    // Written by hand: keys keep the order the query asked for them in
+    fn write(&self, node: usize, out: &mut String) {
+        match &self.nodes[node].out {
+            Out::Pending | Out::Null => out.push_str("null"),
+            Out::Leaf(value) => out.push_str(&value.to_string()),
+            Out::Object(entries) => {
+                out.push('{');
+                for (i, (key, id)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    out.push_str(&Value::from(key.as_str()).to_string());
+                    out.push(':');
+                    self.write(*id, out);
+                }
+                out.push('}');
+            }
+            Out::List(ids) => {
+                out.push('[');
+                for (i, id) in ids.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    self.write(*id, out);
+                }
+                out.push(']');
+            }
+        }
+    }
+}
@@ -0,0 +1,1069 @@
+use std::fmt;
+
+/// Line and column in the source, both from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug)]
+pub struct SyntaxError {
+    pub message: String,
+    pub pos: Pos,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Syntax Error: {} ({}:{})", self.message, self.pos.line, self.pos.column)
+    }
+}
+
+// Syntax tree -----------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Named(String),
+    List(Box<Type>),
+    NonNull(Box<Type>),
+}
+
+impl Type {
+    pub fn name(&self) -> &str {
+        match self {
+            Type::Named(name) => name,
+            Type::List(inner) | Type::NonNull(inner) => inner.name(),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Named(name) => write!(f, "{}", name),
+            Type::List(inner) => write!(f, "[{}]", inner),
+            Type::NonNull(inner) => write!(f, "{}!", inner),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Variable(String),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+    Null,
+    Enum(String),
+    List(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+// Printed back as GraphQL, which is how introspection shows default values
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Variable(name) => write!(f, "${}", name),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", serde_json::Value::from(s.as_str())),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Null => write!(f, "null"),
+            Value::Enum(name) => write!(f, "{}", name),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    write!(f, "{}{}", if i > 0 { ", " } else { "" }, item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    write!(f, "{}{}: {}", if i > 0 { ", " } else { "" }, name, value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Argument {
+    pub name: String,
+    pub value: Value,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone)]
+pub struct Directive {
+    pub name: String,
+    pub arguments: Vec<Argument>,
+    pub pos: Pos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    Query,
+    Mutation,
+    Subscription,
+}
+
+impl fmt::Display for OperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OperationKind::Query => "query",
+            OperationKind::Mutation => "mutation",
+            OperationKind::Subscription => "subscription",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct VariableDefinition {
+    pub name: String,
+    pub ty: Type,
+    pub default: Option<Value>,
+    pub pos: Pos,
+}
+
+#[derive(Debug)]
+pub struct Operation {
+    pub kind: OperationKind,
+    pub name: Option<String>,
+    pub variables: Vec<VariableDefinition>,
+    pub directives: Vec<Directive>,
+    pub selections: Vec<Selection>,
+    pub pos: Pos,
+}
+
+#[derive(Debug)]
+pub struct Fragment {
+    pub name: String,
+    pub type_condition: String,
+    pub directives: Vec<Directive>,
+    pub selections: Vec<Selection>,
+    pub pos: Pos,
+}
+
+#[derive(Debug)]
+pub enum Selection {
+    Field(Field),
+    Spread(Spread),
+    Inline(InlineFragment),
+}
+
+#[derive(Debug)]
+pub struct Field {
+    pub alias: Option<String>,
+    pub name: String,
+    pub arguments: Vec<Argument>,
+    pub directives: Vec<Directive>,
+    pub selections: Vec<Selection>,
+    pub pos: Pos,
+}
+
+impl Field {
+    /// The key of the field in the response.
+    pub fn key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Spread {
+    pub name: String,
+    pub directives: Vec<Directive>,
+    pub pos: Pos,
+}
+
+#[derive(Debug)]
+pub struct InlineFragment {
+    pub type_condition: Option<String>,
+    pub directives: Vec<Directive>,
+    pub selections: Vec<Selection>,
+    pub pos: Pos,
+}
+
+/// A query document: what clients send.
+#[derive(Debug, Default)]
+pub struct Document {
+    pub operations: Vec<Operation>,
+    pub fragments: Vec<Fragment>,
+}
+
+impl Document {
+    pub fn fragment(&self, name: &str) -> Option<&Fragment> {
+        self.fragments.iter().find(|f| f.name == name)
+    }
+}
+
+#[derive(Debug)]
+pub struct InputValueDefinition {
+    pub name: String,
+    pub description: Option<String>,
+    pub ty: Type,
+    pub default: Option<Value>,
+    pub directives: Vec<Directive>,
+}
+
+#[derive(Debug)]
+pub struct FieldDefinition {
+    pub name: String,
+    pub description: Option<String>,
+    pub args: Vec<InputValueDefinition>,
+    pub ty: Type,
+    pub directives: Vec<Directive>,
+}
+
+#[derive(Debug)]
+pub struct EnumValueDefinition {
+    pub name: String,
+    pub description: Option<String>,
+    pub directives: Vec<Directive>,
+}
+
+#[derive(Debug)]
+pub enum TypeKind {
+    Scalar,
+    Object { interfaces: Vec<String>, fields: Vec<FieldDefinition> },
+    Interface { interfaces: Vec<String>, fields: Vec<FieldDefinition> },
+    Union { members: Vec<String> },
+    Enum { values: Vec<EnumValueDefinition> },
+    Input { fields: Vec<InputValueDefinition> },
+}
+
+#[derive(Debug)]
+pub struct TypeDefinition {
+    pub name: String,
+    pub description: Option<String>,
+    pub kind: TypeKind,
+    pub directives: Vec<Directive>,
+    pub extend: bool,
+    pub pos: Pos,
+}
+
+/// The definitions of a schema document. Directive definitions are read
+/// and dropped; only the built-in directives have any effect.
+#[derive(Debug, Default)]
+pub struct SchemaDocument {
+    /// `schema { query: Q mutation: M }`, when given.
+    pub roots: Vec<(OperationKind, String)>,
+    pub types: Vec<TypeDefinition>,
+}
+
+// Lexer ----------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Punct(char),
+    Spread,
+    Name(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Eof,
+}
+
+impl fmt::Display for Tok {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tok::Punct(c) => write!(f, "\"{}\"", c),
+            Tok::Spread => write!(f, "\"...\""),
+            Tok::Name(name) => write!(f, "Name \"{}\"", name),
+            Tok::Int(n) => write!(f, "Int \"{}\"", n),
+            Tok::Float(n) => write!(f, "Float \"{}\"", n),
+            Tok::Str(_) => write!(f, "String"),
+            Tok::Eof => write!(f, "<EOF>"),
+        }
+    }
+}
+
+struct Token {
+    tok: Tok,
+    pos: Pos,
+}
+
+fn lex(src: &str) -> Result<Vec<Token>, SyntaxError> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut line_start) = (0, 1, 0);
+    let error = |message: String, at: usize, line: usize, line_start: usize| SyntaxError {
+        message,
+        pos: Pos { line, column: at - line_start + 1 },
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let pos = Pos { line, column: i - line_start + 1 };
+        match c {
+            '\u{feff}' | ' ' | '\t' | ',' => i += 1,
+            '\n' | '\r' => {
+                if c == '\r' && chars.get(i + 1) == Some(&'\n') {
+                    i += 1;
+                }
+                i += 1;
+                line += 1;
+                line_start = i;
+            }
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' && chars[i] != '\r' {
+                    i += 1;
+                }
+            }
+            '!' | '$' | '&' | '(' | ')' | ':' | '=' | '@' | '[' | ']' | '{' | '|' | '}' => {
+                tokens.push(Token { tok: Tok::Punct(c), pos });
+                i += 1;
+            }
+            '.' => {
+                if chars.get(i + 1) == Some(&'.') && chars.get(i + 2) == Some(&'.') {
+                    tokens.push(Token { tok: Tok::Spread, pos });
+                    i += 3;
+                } else {
+                    return Err(error("Unexpected \".\"".into(), i, line, line_start));
+                }
+            }
+            '"' if chars.get(i + 1) == Some(&'"') && chars.get(i + 2) == Some(&'"') => {
+                let mut raw = String::new();
+                i += 3;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(error("Unterminated string.".into(), i, line, line_start)),
+                        Some('"') if chars.get(i + 1) == Some(&'"') && chars.get(i + 2) == Some(&'"') => {
+                            i += 3;
+                            break;
+                        }
+                        Some('\\') if chars[i + 1..].starts_with(&['"', '"', '"']) => {
+                            raw.push_str("\"\"\"");
+                            i += 4;
+                        }
+                        Some(&ch) => {
+                            if ch == '\n' || (ch == '\r' && chars.get(i + 1) != Some(&'\n')) {
+                                line += 1;
+                                line_start = i + 1;
+                            }
+                            raw.push(ch);
+                            i += 1;
+                        }
+                    }
+                }
+                tokens.push(Token { tok: Tok::Str(block_string(&raw)), pos });
+            }
+            '"' => {
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None | Some('\n') | Some('\r') => {
+                            return Err(error("Unterminated string.".into(), i, line, line_start));
+                        }
+                        Some('"') => {
+                            i += 1;
+                            break;
+                        }
+                        Some('\\') => {
+                            let escaped = chars.get(i + 1).copied();
+                            i += 2;
+                            match escaped {
+                                Some('"') => s.push('"'),
+                                Some('\\') => s.push('\\'),
+                                Some('/') => s.push('/'),
+                                Some('b') => s.push('\u{8}'),
+                                Some('f') => s.push('\u{c}'),
+                                Some('n') => s.push('\n'),
+                                Some('r') => s.push('\r'),
+                                Some('t') => s.push('\t'),
+                                Some('u') => {
+                                    let (code, len) = unicode_escape(&chars[i..])
+                                        .ok_or_else(|| error("Invalid Unicode escape sequence.".into(), i - 2, line, line_start))?;
+                                    s.push(code);
+                                    i += len;
+                                }
+                                _ => return Err(error("Invalid character escape sequence.".into(), i - 2, line, line_start)),
+                            }
+                        }
+                        Some(&ch) => {
+                            s.push(ch);
+                            i += 1;
+                        }
+                    }
+                }
+                tokens.push(Token { tok: Tok::Str(s), pos });
+            }
+            '-' | '0'..='9' => {
+                let start = i;
+                if c == '-' {
+                    i += 1;
+                }
+                let digits = |i: &mut usize| {
+                    let from = *i;
+                    while chars.get(*i).is_some_and(char::is_ascii_digit) {
+                        *i += 1;
+                    }
+                    *i > from
+                };
+                if chars.get(i) == Some(&'0') && chars.get(i + 1).is_some_and(char::is_ascii_digit) {
+                    return Err(error("Invalid number, unexpected digit after 0.".into(), i + 1, line, line_start));
+                }
+                if !digits(&mut i) {
+                    return Err(error("Invalid number, expected digit.".into(), i, line, line_start));
+                }
+                let mut float = false;
+                if chars.get(i) == Some(&'.') {
+                    float = true;
+                    i += 1;
+                    if !digits(&mut i) {
+                        return Err(error("Invalid number, expected digit.".into(), i, line, line_start));
+                    }
+                }
+                if matches!(chars.get(i), Some('e') | Some('E')) {
+                    float = true;
+                    i += 1;
+                    if matches!(chars.get(i), Some('+') | Some('-')) {
+                        i += 1;
+                    }
+                    if !digits(&mut i) {
+                        return Err(error("Invalid number, expected digit.".into(), i, line, line_start));
+                    }
+                }
+                if chars.get(i).is_some_and(|ch| *ch == '.' || ch.is_ascii_alphabetic() || *ch == '_') {
+                    return Err(error(format!("Invalid number, unexpected \"{}\".", chars[i]), i, line, line_start));
+                }
+                let text: String = chars[start..i].iter().collect();
+                let tok = if float {
+                    Tok::Float(text.parse().unwrap_or(f64::NAN))
+                } else {
+                    match text.parse() {
+                        Ok(n) => Tok::Int(n),
+                        Err(_) => Tok::Float(text.parse().unwrap_or(f64::NAN)),
+                    }
+                };
+                tokens.push(Token { tok, pos });
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let start = i;
+                while chars.get(i).is_some_and(|ch| ch.is_ascii_alphanumeric() || *ch == '_') {
+                    i += 1;
+                }
+                tokens.push(Token { tok: Tok::Name(chars[start..i].iter().collect()), pos });
+            }
+            other => return Err(error(format!("Unexpected character {:?}.", other), i, line, line_start)),
+        }
+    }
+    tokens.push(Token { tok: Tok::Eof, pos: Pos { line, column: i - line_start + 1 } });
+    Ok(tokens)
+}
+
+// `\uXXXX`, a surrogate pair of them, or `\u{X...}`; `chars` starts after the `u`
+fn unicode_escape(chars: &[char]) -> Option<(char, usize)> {
+    let hex = |digits: &[char]| u32::from_str_radix(&digits.iter().collect::<String>(), 16).ok();
+    if chars.first() == Some(&'{') {
+        let end = chars.iter().position(|c| *c == '}')?;
+        return Some((char::from_u32(hex(&chars[1..end])?)?, end + 1));
+    }
+    let code = hex(chars.get(..4)?)?;
+    if (0xD800..0xDC00).contains(&code) && chars.get(4..6) == Some(&['\\', 'u']) {
+        let low = hex(chars.get(6..10)?)?;
+        if (0xDC00..0xE000).contains(&low) {
+            return Some((char::from_u32(0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00))?, 10));
+        }
+    }
+    Some((char::from_u32(code)?, 4))
+}
+
+// The value of a `"""` string: common indentation and blank first and last
+// lines removed
+fn block_string(raw: &str) -> String {
+    let lines: Vec<&str> = raw.split(['\n']).map(|l| l.strip_suffix('\r').unwrap_or(l)).collect();
+    let indent = lines
+        .iter()
+        .skip(1)
+        .filter_map(|l| {
+            let trimmed = l.trim_start_matches([' ', '\t']);
+            (!trimmed.is_empty()).then(|| l.len() - trimmed.len())
+        })
+        .min()
+        .unwrap_or(0);
+    let mut lines: Vec<&str> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, l)| if i == 0 { l } else { l.get(indent..).unwrap_or("") })
+        .collect();
+    while lines.first().is_some_and(|l| l.trim_matches([' ', '\t']).is_empty()) {
+        lines.remove(0);
+    }
+    while lines.last().is_some_and(|l| l.trim_matches([' ', '\t']).is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+// Parser ---------------------------------------------------------------------
+
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+type Parsed<T> = Result<T, SyntaxError>;
+
+/// Parses a query document.
+pub fn parse_query(src: &str) -> Parsed<Document> {
+    let mut p = Parser { tokens: lex(src)?, at: 0 };
+    let mut doc = Document::default();
+    loop {
+        match p.peek().clone() {
+            Tok::Eof if doc.operations.is_empty() && doc.fragments.is_empty() => {
+                return Err(p.unexpected());
+            }
+            Tok::Eof => return Ok(doc),
+            Tok::Punct('{') => {
+                let pos = p.pos();
+                let selections = p.selection_set()?;
+                doc.operations.push(Operation {
+                    kind: OperationKind::Query,
+                    name: None,
+                    variables: Vec::new(),
+                    directives: Vec::new(),
+                    selections,
+                    pos,
+                });
+            }
+            Tok::Name(word) if word == "fragment" => {
+                let pos = p.pos();
+                p.next();
+                let name = p.name()?;
+                if name == "on" {
+                    return Err(p.error_at(pos, "Unexpected Name \"on\".".into()));
+                }
+                p.keyword("on")?;
+                let type_condition = p.name()?;
+                let directives = p.directives(false)?;
+                let selections = p.selection_set()?;
+                doc.fragments.push(Fragment { name, type_condition, directives, selections, pos });
+            }
+            Tok::Name(word) if matches!(word.as_str(), "query" | "mutation" | "subscription") => {
+                let pos = p.pos();
+                p.next();
+                let kind = match word.as_str() {
+                    "query" => OperationKind::Query,
+                    "mutation" => OperationKind::Mutation,
+                    _ => OperationKind::Subscription,
+                };
+                let name = match p.peek() {
+                    Tok::Name(_) => Some(p.name()?),
+                    _ => None,
+                };
+                let variables = p.variable_definitions()?;
+                let directives = p.directives(false)?;
+                let selections = p.selection_set()?;
+                doc.operations.push(Operation { kind, name, variables, directives, selections, pos });
+            }
+            _ => return Err(p.unexpected()),
+        }
+    }
+}
+
+/// Parses a schema document (SDL).
+pub fn parse_schema(src: &str) -> Parsed<SchemaDocument> {
+    let mut p = Parser { tokens: lex(src)?, at: 0 };
+    let mut doc = SchemaDocument::default();
+    loop {
+        let description = match p.peek() {
+            Tok::Str(_) => Some(p.string()?),
+            _ => None,
+        };
+        let pos = p.pos();
+        let word = match p.peek().clone() {
+            Tok::Eof if description.is_none() => return Ok(doc),
+            Tok::Name(word) => word,
+            _ => return Err(p.unexpected()),
+        };
+        p.next();
+        let extend = word == "extend";
+        let word = if extend { p.name()? } else { word };
+        match word.as_str() {
+            "schema" => {
+                p.directives(true)?;
+                p.punct('{')?;
+                while !p.eat('}') {
+                    let operation = match p.name()?.as_str() {
+                        "query" => OperationKind::Query,
+                        "mutation" => OperationKind::Mutation,
+                        "subscription" => OperationKind::Subscription,
+                        _ => return Err(p.error_at(p.prev_pos(), "Expected query, mutation or subscription.".into())),
+                    };
+                    p.punct(':')?;
+                    doc.roots.push((operation, p.name()?));
+                }
+                continue;
+            }
+            "directive" => {
+                p.punct('@')?;
+                p.name()?;
+                if matches!(p.peek(), Tok::Punct('(')) {
+                    p.input_values('(', ')')?;
+                }
+                p.eat_keyword("repeatable");
+                p.keyword("on")?;
+                p.eat('|');
+                p.name()?;
+                while p.eat('|') {
+                    p.name()?;
+                }
+                continue;
+            }
+            _ => {}
+        }
+        let name = p.name()?;
+        let mut kind = match word.as_str() {
+            "scalar" => TypeKind::Scalar,
+            "type" => TypeKind::Object { interfaces: p.implements()?, fields: Vec::new() },
+            "interface" => TypeKind::Interface { interfaces: p.implements()?, fields: Vec::new() },
+            "union" => TypeKind::Union { members: Vec::new() },
+            "enum" => TypeKind::Enum { values: Vec::new() },
+            "input" => TypeKind::Input { fields: Vec::new() },
+            _ => return Err(p.error_at(pos, format!("Unexpected Name \"{}\".", word))),
+        };
+        let directives = p.directives(true)?;
+        match &mut kind {
+            TypeKind::Scalar => {}
+            TypeKind::Object { fields, .. } | TypeKind::Interface { fields, .. } => {
+                if matches!(p.peek(), Tok::Punct('{')) {
+                    *fields = p.field_definitions()?;
+                }
+            }
+            TypeKind::Union { members } => {
+                if p.eat('=') {
+                    p.eat('|');
+                    members.push(p.name()?);
+                    while p.eat('|') {
+                        members.push(p.name()?);
+                    }
+                }
+            }
+            TypeKind::Enum { values } => {
+                if p.eat('{') {
+                    while !p.eat('}') {
+                        let description = match p.peek() {
+                            Tok::Str(_) => Some(p.string()?),
+                            _ => None,
+                        };
+                        let name = p.name()?;
+                        if matches!(name.as_str(), "true" | "false" | "null") {
+                            return Err(p.error_at(p.prev_pos(), format!("{} is reserved and cannot be used for an enum value.", name)));
+                        }
+                        let directives = p.directives(true)?;
+                        values.push(EnumValueDefinition { name, description, directives });
+                    }
+                }
+            }
+            TypeKind::Input { fields } => {
+                if matches!(p.peek(), Tok::Punct('{')) {
+                    *fields = p.input_values('{', '}')?;
+                }
+            }
+        }
+        doc.types.push(TypeDefinition { name, description, kind, directives, extend, pos });
+    }
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.at].tok
+    }
+
+    fn pos(&self) -> Pos {
+        self.tokens[self.at].pos
+    }
+
+    fn prev_pos(&self) -> Pos {
+        self.tokens[self.at.saturating_sub(1)].pos
+    }
+
+    fn next(&mut self) -> Tok {
+        let tok = self.tokens[self.at].tok.clone();
+        if self.at + 1 < self.tokens.len() {
+            self.at += 1;
+        }
+        tok
+    }
+
+    fn error_at(&self, pos: Pos, message: String) -> SyntaxError {
+        SyntaxError { message, pos }
+    }
+
+    fn unexpected(&self) -> SyntaxError {
+        self.error_at(self.pos(), format!("Unexpected {}.", self.peek()))
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if *self.peek() == Tok::Punct(c) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn punct(&mut self, c: char) -> Parsed<()> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error_at(self.pos(), format!("Expected \"{}\", found {}.", c, self.peek())))
+        }
+    }
+
+    fn eat_keyword(&mut self, word: &str) -> bool {
+        if matches!(self.peek(), Tok::Name(name) if name == word) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn keyword(&mut self, word: &str) -> Parsed<()> {
+        if self.eat_keyword(word) {
+            Ok(())
+        } else {
+            Err(self.error_at(self.pos(), format!("Expected \"{}\", found {}.", word, self.peek())))
+        }
+    }
+
+    fn name(&mut self) -> Parsed<String> {
+        match self.peek().clone() {
+            Tok::Name(name) => {
+                self.next();
+                Ok(name)
+            }
+            other => Err(self.error_at(self.pos(), format!("Expected Name, found {}.", other))),
+        }
+    }
+
+    fn string(&mut self) -> Parsed<String> {
+        match self.next() {
+            Tok::Str(s) => Ok(s),
+            _ => Err(self.error_at(self.prev_pos(), "Expected String.".into())),
+        }
+    }
+
+    fn selection_set(&mut self) -> Parsed<Vec<Selection>> {
+        self.punct('{')?;
+        let mut selections = Vec::new();
+        while !self.eat('}') {
+            selections.push(self.selection()?);
+        }
+        if selections.is_empty() {
+            return Err(self.error_at(self.prev_pos(), "Expected Name, found \"}\".".into()));
+        }
+        Ok(selections)
+    }
+
+    fn selection(&mut self) -> Parsed<Selection> {
+        let pos = self.pos();
+        if self.eat_spread() {
+            return Ok(match self.peek().clone() {
+                Tok::Name(word) if word != "on" => {
+                    let name = self.name()?;
+                    let directives = self.directives(false)?;
+                    Selection::Spread(Spread { name, directives, pos })
+                }
+                _ => {
+                    let type_condition = if self.eat_keyword("on") { Some(self.name()?) } else { None };
+                    let directives = self.directives(false)?;
+                    let selections = self.selection_set()?;
+                    Selection::Inline(InlineFragment { type_condition, directives, selections, pos })
+                }
+            });
+        }
+        let first = self.name()?;
+        let (alias, name) = if self.eat(':') { (Some(first), self.name()?) } else { (None, first) };
+        let arguments = self.arguments(false)?;
+        let directives = self.directives(false)?;
+        let selections = if matches!(self.peek(), Tok::Punct('{')) { self.selection_set()? } else { Vec::new() };
+        Ok(Selection::Field(Field { alias, name, arguments, directives, selections, pos }))
+    }
+
+    fn eat_spread(&mut self) -> bool {
+        if *self.peek() == Tok::Spread {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn arguments(&mut self, constant: bool) -> Parsed<Vec<Argument>> {
+        let mut arguments = Vec::new();
+        if self.eat('(') {
+            while !self.eat(')') {
+                let pos = self.pos();
+                let name = self.name()?;
+                self.punct(':')?;
+                let value = self.value(constant)?;
+                arguments.push(Argument { name, value, pos });
+            }
+            if arguments.is_empty() {
+                return Err(self.error_at(self.prev_pos(), "Expected Name, found \")\".".into()));
+            }
+        }
+        Ok(arguments)
+    }
+
+    fn directives(&mut self, constant: bool) -> Parsed<Vec<Directive>> {
+        let mut directives = Vec::new();
+        while matches!(self.peek(), Tok::Punct('@')) {
+            let pos = self.pos();
+            self.next();
+            let name = self.name()?;
+            let arguments = self.arguments(constant)?;
+            directives.push(Directive { name, arguments, pos });
+        }
+        Ok(directives)
+    }
+
+    fn variable_definitions(&mut self) -> Parsed<Vec<VariableDefinition>> {
+        let mut variables = Vec::new();
+        if self.eat('(') {
+            while !self.eat(')') {
+                let pos = self.pos();
+                self.punct('$')?;
+                let name = self.name()?;
+                self.punct(':')?;
+                let ty = self.ty()?;
+                let default = if self.eat('=') { Some(self.value(true)?) } else { None };
+                self.directives(true)?;
+                variables.push(VariableDefinition { name, ty, default, pos });
+            }
+        }
+        Ok(variables)
+    }
+
+    fn ty(&mut self) -> Parsed<Type> {
+        let ty = if self.eat('[') {
+            let inner = self.ty()?;
+            self.punct(']')?;
+            Type::List(Box::new(inner))
+        } else {
+            Type::Named(self.name()?)
+        };
+        Ok(if self.eat('!') { Type::NonNull(Box::new(ty)) } else { ty })
+    }
+
+    fn value(&mut self, constant: bool) -> Parsed<Value> {
+        let pos = self.pos();
+        Ok(match self.next() {
+            Tok::Punct('$') if !constant => Value::Variable(self.name()?),
+            Tok::Int(n) => Value::Int(n),
+            Tok::Float(n) => Value::Float(n),
+            Tok::Str(s) => Value::String(s),
+            Tok::Name(word) => match word.as_str() {
+                "true" => Value::Boolean(true),
+                "false" => Value::Boolean(false),
+                "null" => Value::Null,
+                _ => Value::Enum(word),
+            },
+            Tok::Punct('[') => {
+                let mut items = Vec::new();
+                while !self.eat(']') {
+                    items.push(self.value(constant)?);
+                }
+                Value::List(items)
+            }
+            Tok::Punct('{') => {
+                let mut fields = Vec::new();
+                while !self.eat('}') {
+                    let name = self.name()?;
+                    self.punct(':')?;
+                    fields.push((name, self.value(constant)?));
+                }
+                Value::Object(fields)
+            }
+            other => return Err(self.error_at(pos, format!("Unexpected {}.", other))),
+        })
+    }
+
+    fn implements(&mut self) -> Parsed<Vec<String>> {
+        let mut interfaces = Vec::new();
+        if self.eat_keyword("implements") {
+            self.eat('&');
+            interfaces.push(self.name()?);
+            while self.eat('&') {
+                interfaces.push(self.name()?);
+            }
+        }
+        Ok(interfaces)
+    }
+
+    fn field_definitions(&mut self) -> Parsed<Vec<FieldDefinition>> {
+        self.punct('{')?;
+        let mut fields = Vec::new();
+        while !self.eat('}') {
+            let description = match self.peek() {
+                Tok::Str(_) => Some(self.string()?),
+                _ => None,
+            };
+            let name = self.name()?;
+            let args = if matches!(self.peek(), Tok::Punct('(')) { self.input_values('(', ')')? } else { Vec::new() };
+            self.punct(':')?;
+            let ty = self.ty()?;
+            let directives = self.directives(true)?;
+            fields.push(FieldDefinition { name, description, args, ty, directives });
+        }
+        Ok(fields)
+    }
+
+    fn input_values(&mut self, open: char, close: char) -> Parsed<Vec<InputValueDefinition>> {
+        self.punct(open)?;
+        let mut values = Vec::new();
+        while !self.eat(close) {
+            let description = match self.peek() {
+                Tok::Str(_) => Some(self.string()?),
+                _ => None,
+            };
+            let name = self.name()?;
+            self.punct(':')?;
+            let ty = self.ty()?;
+            let default = if self.eat('=') { Some(self.value(true)?) } else { None };
+            let directives = self.directives(true)?;
+            values.push(InputValueDefinition { name, description, ty, default, directives });
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(selection: &Selection) -> &Field {
+        match selection {
+            Selection::Field(field) => field,
+            other => panic!("expected a field, got {:?}", other),
+        }
+    }
+
+    fn error(src: &str) -> SyntaxError {
+        parse_query(src).expect_err("should not parse")
+    }
+
+    #[test]
+    fn fragments() {
+        let doc = parse_query(
+            r#"
+            query { node(id: 1) { ...Parts ... on User @include(if: true) { email } ... { id } } }
+            fragment Parts on Node { id }
+            "#,
+        )
+        .unwrap();
+        let node = field(&doc.operations[0].selections[0]);
+        let Selection::Spread(spread) = &node.selections[0] else { panic!("expected a spread") };
+        assert_eq!(spread.name, "Parts");
+        let Selection::Inline(on_user) = &node.selections[1] else { panic!("expected an inline fragment") };
+        assert_eq!(on_user.type_condition.as_deref(), Some("User"));
+        assert_eq!(on_user.directives[0].name, "include");
+        let Selection::Inline(untyped) = &node.selections[2] else { panic!("expected an inline fragment") };
+        assert_eq!(untyped.type_condition, None);
+
+        let parts = doc.fragment("Parts").unwrap();
+        assert_eq!(parts.type_condition, "Node");
+        assert_eq!(field(&parts.selections[0]).name, "id");
+        assert!(parse_query("fragment on on User { id }").is_err());
+    }
+
+    #[test]
+    fn variables_with_defaults() {
+        let doc = parse_query(
+            r#"query Users($first: Int = 10, $names: [String!]! = ["a", "b"], $filter: Filter = { active: true, role: ADMIN }, $after: ID) {
+                users(first: $first, names: $names, filter: $filter, after: $after) { id }
+            }"#,
+        )
+        .unwrap();
+        let op = &doc.operations[0];
+        assert_eq!(op.name.as_deref(), Some("Users"));
+        let vars: Vec<(&str, String, Option<&Value>)> =
+            op.variables.iter().map(|v| (v.name.as_str(), v.ty.to_string(), v.default.as_ref())).collect();
+        assert_eq!(vars[0], ("first", "Int".to_string(), Some(&Value::Int(10))));
+        let names = Value::List(vec![Value::String("a".into()), Value::String("b".into())]);
+        assert_eq!(vars[1], ("names", "[String!]!".to_string(), Some(&names)));
+        let filter = Value::Object(vec![("active".into(), Value::Boolean(true)), ("role".into(), Value::Enum("ADMIN".into()))]);
+        assert_eq!(vars[2], ("filter", "Filter".to_string(), Some(&filter)));
+        assert_eq!(vars[3], ("after", "ID".to_string(), None));
+        assert_eq!(field(&op.selections[0]).arguments[0].value, Value::Variable("first".into()));
+
+        // Defaults are constants
+        assert!(parse_query("query($a: Int = $b) { f }").is_err());
+    }
+
+    #[test]
+    fn block_strings() {
+        let doc = parse_query("{ f(text: \"\"\"\n    Hello,\n      World!\n\n    Yours \\\"\"\" \n  \"\"\") }").unwrap();
+        let text = &field(&doc.operations[0].selections[0]).arguments[0].value;
+        assert_eq!(*text, Value::String("Hello,\n  World!\n\nYours \"\"\" ".into()));
+
+        let doc = parse_query(r#"{ f(s: "tab\t\u00e9\u{1F600}\uD83D\uDE00") }"#).unwrap();
+        let s = &field(&doc.operations[0].selections[0]).arguments[0].value;
+        assert_eq!(*s, Value::String("tab\té😀😀".into()));
+    }
+
+    #[test]
+    fn nested_lists() {
+        let doc = parse_query("query($m: [[Int!]]!) { f(m: [[1, 2], [], [[3.5]]]) }").unwrap();
+        let op = &doc.operations[0];
+        let ty = &op.variables[0].ty;
+        assert_eq!(ty.to_string(), "[[Int!]]!");
+        assert_eq!(ty.name(), "Int");
+        let value = &field(&op.selections[0]).arguments[0].value;
+        assert_eq!(value.to_string(), "[[1, 2], [], [[3.5]]]");
+    }
+
+    #[test]
+    fn schema_definitions() {
+        let doc = parse_schema(
+            r#"
+            schema { query: Root }
+            """A person"""
+            type User implements Node & Named @key(fields: "id") { id: ID! "Their name" name(upper: Boolean = false): String }
+            union Result = | User | Error
+            enum Role { ADMIN USER }
+            input Filter { role: Role = USER }
+            directive @key(fields: String!) repeatable on OBJECT | INTERFACE
+            "#,
+        )
+        .unwrap();
+        assert_eq!(doc.roots, vec![(OperationKind::Query, "Root".to_string())]);
+        let user = &doc.types[0];
+        assert_eq!(user.description.as_deref(), Some("A person"));
+        let TypeKind::Object { interfaces, fields } = &user.kind else { panic!("expected an object") };
+        assert_eq!(interfaces, &["Node", "Named"]);
+        assert_eq!(fields[1].description.as_deref(), Some("Their name"));
+        assert_eq!(fields[1].args[0].default, Some(Value::Boolean(false)));
+        let TypeKind::Union { members } = &doc.types[1].kind else { panic!("expected a union") };
+        assert_eq!(members, &["User", "Error"]);
+        assert!(parse_schema("enum E { true }").is_err());
+    }
+
+    #[test]
+    fn errors_report_their_position() {
+        let err = error("{\n  user(id: 01) { id }\n}");
+        assert_eq!(err.message, "Invalid number, unexpected digit after 0.");
+        assert_eq!(err.pos, Pos { line: 2, column: 13 });
+        assert_eq!(err.to_string(), "Syntax Error: Invalid number, unexpected digit after 0. (2:13)");
+
+        let err = error("query {\r\n  a\r\n");
+        assert_eq!((err.message.as_str(), err.pos), ("Expected Name, found <EOF>.", Pos { line: 3, column: 1 }));
+
+        let err = error("{ a(x: \"\"\"\nline\n) }");
+        assert_eq!(err.message, "Unterminated string.");
+        assert_eq!(err.pos.line, 3);
+
+        let err = error("{ a(x: \"bad \\q\") }");
+        assert_eq!((err.message.as_str(), err.pos), ("Invalid character escape sequence.", Pos { line: 1, column: 13 }));
+
+        let err = error("{ a }\n  mutation M { }");
+        assert_eq!(err.pos, Pos { line: 2, column: 16 });
+        assert_eq!(error("").message, "Unexpected <EOF>.");
+    }
+}
@@ -0,0 +1,517 @@
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+
+use super::parser::{
+    Directive, FieldDefinition, InputValueDefinition, OperationKind, Type, TypeDefinition, TypeKind, parse_schema,
+};
+
+const BUILT_IN: &str = r#"
+"The `Int` scalar type represents non-fractional signed whole numeric values between -(2^31) and 2^31 - 1."
+scalar Int
+"The `Float` scalar type represents signed double-precision fractional values as specified by IEEE 754."
+scalar Float
+"The `String` scalar type represents textual data, represented as UTF-8 character sequences."
+scalar String
+"The `Boolean` scalar type represents `true` or `false`."
+scalar Boolean
+"The `ID` scalar type represents a unique identifier, serialized as a string."
+scalar ID
+
+type __Schema {
+  description: String
+  types: [__Type!]!
+  queryType: __Type!
+  mutationType: __Type
+  subscriptionType: __Type
+  directives: [__Directive!]!
+}
+
+type __Type {
+  kind: __TypeKind!
+  name: String
+  description: String
+  specifiedByURL: String
+  fields(includeDeprecated: Boolean = false): [__Field!]
+  interfaces: [__Type!]
+  possibleTypes: [__Type!]
+  enumValues(includeDeprecated: Boolean = false): [__EnumValue!]
+  inputFields(includeDeprecated: Boolean = false): [__InputValue!]
+  ofType: __Type
+  isOneOf: Boolean
+}
+
+enum __TypeKind { SCALAR OBJECT INTERFACE UNION ENUM INPUT_OBJECT LIST NON_NULL }
+
+type __Field {
+  name: String!
+  description: String
+  args(includeDeprecated: Boolean = false): [__InputValue!]!
+  type: __Type!
+  isDeprecated: Boolean!
+  deprecationReason: String
+}
+
+type __InputValue {
+  name: String!
+  description: String
+  type: __Type!
+  defaultValue: String
+  isDeprecated: Boolean!
+  deprecationReason: String
+}
+
+type __EnumValue {
+  name: String!
+  description: String
+  isDeprecated: Boolean!
+  deprecationReason: String
+}
+
+type __Directive {
+  name: String!
+  description: String
+  locations: [__DirectiveLocation!]!
+  args(includeDeprecated: Boolean = false): [__InputValue!]!
+  isRepeatable: Boolean!
+}
+
+enum __DirectiveLocation {
+  QUERY MUTATION SUBSCRIPTION FIELD FRAGMENT_DEFINITION FRAGMENT_SPREAD INLINE_FRAGMENT VARIABLE_DEFINITION
+  SCHEMA SCALAR OBJECT FIELD_DEFINITION ARGUMENT_DEFINITION INTERFACE UNION ENUM ENUM_VALUE INPUT_OBJECT
+  INPUT_FIELD_DEFINITION
+}
+
+type __Meta {
+  __typename: String!
+  __schema: __Schema!
+  __type(name: String!): __Type
+}
+"#;
+
+/// A schema read from SDL, with the built-in scalars and introspection
+/// types added. It is checked for dangling references when it is built.
+pub struct Schema {
+    types: HashMap<String, TypeDefinition>,
+    // Declaration order, for introspection
+    order: Vec<String>,
+    pub query: String,
+    pub mutation: Option<String>,
+    pub subscription: Option<String>,
+    meta: Vec<FieldDefinition>,
+    // Introspection answers, built once
+    introspection: Value,
+    type_values: HashMap<String, Value>,
+}
+
+impl Schema {
+    pub fn parse(src: &str) -> Result<Schema, String> {
+        let user = parse_schema(src).map_err(|e| e.to_string())?;
+        let built_in = parse_schema(BUILT_IN).map_err(|e| e.to_string())?;
+
+        let mut schema = Schema {
+            types: HashMap::new(),
+            order: Vec::new(),
+            query: String::new(),
+            mutation: None,
+            subscription: None,
+            meta: Vec::new(),
+            introspection: Value::Null,
+            type_values: HashMap::new(),
+        };
+        for def in built_in.types {
+            if def.name == "__Meta" {
+                if let TypeKind::Object { fields, .. } = def.kind {
+                    schema.meta = fields;
+                }
+                continue;
+            }
+            schema.order.push(def.name.clone());
+            schema.types.insert(def.name.clone(), def);
+        }
+
+        let mut extensions = Vec::new();
+        for def in user.types {
+            if def.extend {
+                extensions.push(def);
+                continue;
+            }
+            if def.name.starts_with("__") {
+                return Err(format!("Name \"{}\" must not begin with \"__\", which is reserved by GraphQL introspection.", def.name));
+            }
+            if schema.types.contains_key(&def.name) {
+                return Err(format!("There can be only one type named \"{}\" ({}:{}).", def.name, def.pos.line, def.pos.column));
+            }
+            schema.order.push(def.name.clone());
+            schema.types.insert(def.name.clone(), def);
+        }
+        for ext in extensions {
+            let Some(def) = schema.types.get_mut(&ext.name) else {
+                return Err(format!(
+                    "Cannot extend type \"{}\" because it is not defined ({}:{}).",
+                    ext.name, ext.pos.line, ext.pos.column
+                ));
+            };
+            def.directives.extend(ext.directives);
+            match (&mut def.kind, ext.kind) {
+                (TypeKind::Scalar, TypeKind::Scalar) => {}
+                (TypeKind::Object { interfaces, fields }, TypeKind::Object { interfaces: more, fields: extra })
+                | (TypeKind::Interface { interfaces, fields }, TypeKind::Interface { interfaces: more, fields: extra }) => {
+                    interfaces.extend(more);
+                    fields.extend(extra);
+                }
+                (TypeKind::Union { members }, TypeKind::Union { members: more }) => members.extend(more),
+                (TypeKind::Enum { values }, TypeKind::Enum { values: more }) => values.extend(more),
+                (TypeKind::Input { fields }, TypeKind::Input { fields: extra }) => fields.extend(extra),
+                _ => return Err(format!("Cannot extend non-{} type \"{}\".", kind_name(&def.kind), ext.name)),
+            }
+        }
+
+        let root = |kind: OperationKind, default: &str| {
+            user.roots
+                .iter()
+                .find(|(k, _)| *k == kind)
+                .map(|(_, name)| name.clone())
+                .or_else(|| schema.types.contains_key(default).then(|| default.to_string()))
+        };
+        schema.query = root(OperationKind::Query, "Query").ok_or("The schema has no Query type.")?;
+        schema.mutation = root(OperationKind::Mutation, "Mutation");
+        schema.subscription = root(OperationKind::Subscription, "Subscription");
+        for name in [Some(&schema.query), schema.mutation.as_ref(), schema.subscription.as_ref()].into_iter().flatten() {
+            match schema.types.get(name).map(|def| &def.kind) {
+                Some(TypeKind::Object { .. }) => {}
+                Some(_) => return Err(format!("Root type \"{}\" must be an object type.", name)),
+                None => return Err(format!("Root type \"{}\" is not defined.", name)),
+            }
+        }
+
+        schema.check()?;
+        schema.build_introspection();
+        Ok(schema)
+    }
+
+    // Every reference resolves, and each is the right kind of type
+    fn check(&self) -> Result<(), String> {
+        for def in self.types.values() {
+            let mut seen = std::collections::HashSet::new();
+            let mut unique = |what: &str, name: &str| {
+                if seen.insert(name.to_string()) {
+                    Ok(())
+                } else {
+                    Err(format!("{} \"{}.{}\" can only be defined once.", what, def.name, name))
+                }
+            };
+            match &def.kind {
+                TypeKind::Scalar => {}
+                TypeKind::Object { interfaces, fields } | TypeKind::Interface { interfaces, fields } => {
+                    if fields.is_empty() {
+                        return Err(format!("Type {} must define one or more fields.", def.name));
+                    }
+                    for field in fields {
+                        unique("Field", &field.name)?;
+                        self.check_output(&field.ty, &format!("{}.{}", def.name, field.name))?;
+                        for arg in &field.args {
+                            self.check_input(&arg.ty, &format!("{}.{}({}:)", def.name, field.name, arg.name))?;
+                        }
+                    }
+                    for interface in interfaces {
+                        let Some(TypeKind::Interface { fields: required, .. }) = self.types.get(interface).map(|d| &d.kind) else {
+                            return Err(format!("Type {} can only implement an interface; \"{}\" is not one.", def.name, interface));
+                        };
+                        for field in required {
+                            if !fields.iter().any(|f| f.name == field.name) {
+                                return Err(format!(
+                                    "Interface field {}.{} expected but {} does not provide it.",
+                                    interface, field.name, def.name
+                                ));
+                            }
+                        }
+                    }
+                }
+                TypeKind::Union { members } => {
+                    for member in members {
+                        if !matches!(self.types.get(member).map(|d| &d.kind), Some(TypeKind::Object { .. })) {
+                            return Err(format!("Union type {} can only include object types; \"{}\" is not one.", def.name, member));
+                        }
+                    }
+                }
+                TypeKind::Enum { values } => {
+                    for value in values {
+                        unique("Enum value", &value.name)?;
+                    }
+                }
+                TypeKind::Input { fields } => {
+                    for field in fields {
+                        unique("Input field", &field.name)?;
+                        self.check_input(&field.ty, &format!("{}.{}", def.name, field.name))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_output(&self, ty: &Type, at: &str) -> Result<(), String> {
+        match self.types.get(ty.name()).map(|d| &d.kind) {
+            None => Err(format!("Unknown type \"{}\" at {}.", ty.name(), at)),
+            Some(TypeKind::Input { .. }) => Err(format!("The type of {} must be an output type but got: {}.", at, ty)),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn check_input(&self, ty: &Type, at: &str) -> Result<(), String> {
+        match self.types.get(ty.name()).map(|d| &d.kind) {
+            None => Err(format!("Unknown type \"{}\" at {}.", ty.name(), at)),
+            Some(TypeKind::Scalar | TypeKind::Enum { .. } | TypeKind::Input { .. }) => Ok(()),
+            Some(_) => Err(format!("The type of {} must be an input type but got: {}.", at, ty)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TypeDefinition> {
+        self.types.get(name)
+    }
+
+    /// The field `name` of a type, the meta fields included: `__typename`
+    /// on every object type, interface and union, and `__schema` and
+    /// `__type` on the query type.
+    pub fn field(&self, type_name: &str, name: &str) -> Option<&FieldDefinition> {
+        let def = self.types.get(type_name)?;
+        if name.starts_with("__") {
+            let allowed = match name {
+                "__typename" => self.is_composite(type_name),
+                "__schema" | "__type" => type_name == self.query,
+                _ => false,
+            };
+            return self.meta.iter().find(|f| allowed && f.name == name);
+        }
+        match &def.kind {
+            TypeKind::Object { fields, .. } | TypeKind::Interface { fields, .. } => fields.iter().find(|f| f.name == name),
+            _ => None,
+        }
+    }
+
+    pub fn root(&self, kind: OperationKind) -> Option<&str> {
+        match kind {
+            OperationKind::Query => Some(&self.query),
+            OperationKind::Mutation => self.mutation.as_deref(),
+            OperationKind::Subscription => self.subscription.as_deref(),
+        }
+    }
+
+    /// Object types, interfaces and unions: the types that take a selection.
+    pub fn is_composite(&self, name: &str) -> bool {
+        matches!(
+            self.types.get(name).map(|d| &d.kind),
+            Some(TypeKind::Object { .. } | TypeKind::Interface { .. } | TypeKind::Union { .. })
+        )
+    }
+
+    pub fn is_input(&self, name: &str) -> bool {
+        matches!(self.types.get(name).map(|d| &d.kind), Some(TypeKind::Scalar | TypeKind::Enum { .. } | TypeKind::Input { .. }))
+    }
+
+    /// The object types a value of type `name` can be.
+    pub fn possible_types(&self, name: &str) -> Vec<&str> {
+        match self.types.get_key_value(name).map(|(n, d)| (n, &d.kind)) {
+            Some((name, TypeKind::Object { .. })) => vec![name.as_str()],
+            Some((_, TypeKind::Union { members })) => members.iter().map(String::as_str).collect(),
+            Some((_, TypeKind::Interface { .. })) => self
+                .order
+                .iter()
+                .filter(|n| match self.types.get(*n).map(|d| &d.kind) {
+                    Some(TypeKind::Object { interfaces, .. }) => interfaces.iter().any(|i| i == name),
+                    _ => false,
+                })
+                .map(String::as_str)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether some object is both an `a` and a `b`.
+    pub fn overlap(&self, a: &str, b: &str) -> bool {
+        let b = self.possible_types(b);
+        self.possible_types(a).iter().any(|t| b.contains(t))
+    }
+
+    /// `{ "__schema": ... }` when asked for `__schema`.
+    pub fn introspection(&self) -> &Value {
+        &self.introspection
+    }
+
+    /// The full `__Type` value of a named type.
+    pub fn type_value(&self, name: &str) -> Option<&Value> {
+        self.type_values.get(name)
+    }
+
+    // Introspection values are plain JSON, resolved like any parent value.
+    // A reference to a named type is only `{kind, name}`, and the executor
+    // swaps in the full type when a query looks inside it.
+    fn build_introspection(&mut self) {
+        let mut type_values = HashMap::new();
+        for name in &self.order {
+            let def = &self.types[name];
+            let mut value = Map::new();
+            value.insert("kind".into(), kind_name(&def.kind).into());
+            value.insert("name".into(), name.as_str().into());
+            value.insert("description".into(), def.description.clone().into());
+            match &def.kind {
+                TypeKind::Scalar => {
+                    let url = directive_argument(&def.directives, "specifiedBy", "url");
+                    value.insert("specifiedByURL".into(), url.into());
+                }
+                TypeKind::Object { interfaces, fields } | TypeKind::Interface { interfaces, fields } => {
+                    value.insert("fields".into(), fields.iter().map(|f| self.field_value(f)).collect());
+                    value.insert("interfaces".into(), interfaces.iter().map(|i| self.named_ref(i)).collect());
+                    if matches!(def.kind, TypeKind::Interface { .. }) {
+                        value.insert("possibleTypes".into(), self.possible_types(name).iter().map(|t| self.named_ref(t)).collect());
+                    }
+                }
+                TypeKind::Union { members } => {
+                    value.insert("possibleTypes".into(), members.iter().map(|m| self.named_ref(m)).collect());
+                }
+                TypeKind::Enum { values } => {
+                    let values = values
+                        .iter()
+                        .map(|v| {
+                            let reason = deprecation(&v.directives);
+                            json!({
+                                "name": v.name,
+                                "description": v.description,
+                                "isDeprecated": reason.is_some(),
+                                "deprecationReason": reason,
+                            })
+                        })
+                        .collect();
+                    value.insert("enumValues".into(), values);
+                }
+                TypeKind::Input { fields } => {
+                    value.insert("inputFields".into(), fields.iter().map(|f| self.input_value(f)).collect());
+                    value.insert("isOneOf".into(), def.directives.iter().any(|d| d.name == "oneOf").into());
+                }
+            }
+            type_values.insert(name.clone(), Value::Object(value));
+        }
+
+        let if_arg = |desc: &str| json!({ "name": "if", "description": desc, "type": self.type_ref(&nn("Boolean")), "defaultValue": null, "isDeprecated": false });
+        let directives = json!([
+            {
+                "name": "include",
+                "description": "Directs the executor to include this field or fragment only when the `if` argument is true.",
+                "locations": ["FIELD", "FRAGMENT_SPREAD", "INLINE_FRAGMENT"],
+                "args": [if_arg("Included when true.")],
+                "isRepeatable": false,
+            },
+            {
+                "name": "skip",
+                "description": "Directs the executor to skip this field or fragment when the `if` argument is true.",
+                "locations": ["FIELD", "FRAGMENT_SPREAD", "INLINE_FRAGMENT"],
+                "args": [if_arg("Skipped when true.")],
+                "isRepeatable": false,
+            },
+            {
+                "name": "deprecated",
+                "description": "Marks an element of a GraphQL schema as no longer supported.",
+                "locations": ["FIELD_DEFINITION", "ARGUMENT_DEFINITION", "INPUT_FIELD_DEFINITION", "ENUM_VALUE"],
+                "args": [{
+                    "name": "reason",
+                    "description": "Explains why this element was deprecated.",
+                    "type": self.type_ref(&Type::Named("String".into())),
+                    "defaultValue": "\"No longer supported\"",
+                    "isDeprecated": false,
+                }],
+                "isRepeatable": false,
+            },
+            {
+                "name": "specifiedBy",
+                "description": "Exposes a URL that specifies the behavior of this scalar.",
+                "locations": ["SCALAR"],
+                "args": [{
+                    "name": "url",
+                    "description": "The URL that specifies the behavior of this scalar.",
+                    "type": self.type_ref(&nn("String")),
+                    "defaultValue": null,
+                    "isDeprecated": false,
+                }],
+                "isRepeatable": false,
+            },
+        ]);
+
+        self.introspection = json!({
+            "description": null,
+            "types": self.order.iter().map(|n| self.named_ref(n)).collect::<Vec<_>>(),
+            "queryType": self.named_ref(&self.query),
+            "mutationType": self.mutation.as_deref().map(|m| self.named_ref(m)),
+            "subscriptionType": self.subscription.as_deref().map(|s| self.named_ref(s)),
+            "directives": directives,
+        });
+        self.type_values = type_values;
+    }
+
+    fn field_value(&self, field: &FieldDefinition) -> Value {
+        let reason = deprecation(&field.directives);
+        json!({
+            "name": field.name,
+            "description": field.description,
+            "args": field.args.iter().map(|a| self.input_value(a)).collect::<Vec<_>>(),
+            "type": self.type_ref(&field.ty),
+            "isDeprecated": reason.is_some(),
+            "deprecationReason": reason,
+        })
+    }
+
+    fn input_value(&self, value: &InputValueDefinition) -> Value {
+        let reason = deprecation(&value.directives);
+        json!({
+            "name": value.name,
+            "description": value.description,
+            "type": self.type_ref(&value.ty),
+            "defaultValue": value.default.as_ref().map(ToString::to_string),
+            "isDeprecated": reason.is_some(),
+            "deprecationReason": reason,
+        })
+    }
+
+    fn type_ref(&self, ty: &Type) -> Value {
+        match ty {
+            Type::Named(name) => self.named_ref(name),
+            Type::List(inner) => json!({ "kind": "LIST", "name": null, "ofType": self.type_ref(inner) }),
+            Type::NonNull(inner) => json!({ "kind": "NON_NULL", "name": null, "ofType": self.type_ref(inner) }),
+        }
+    }
+
+    fn named_ref(&self, name: &str) -> Value {
+        let kind = self.types.get(name).map_or("SCALAR", |d| kind_name(&d.kind));
+        json!({ "kind": kind, "name": name })
+    }
+}
+
+fn nn(name: &str) -> Type {
+    Type::NonNull(Box::new(Type::Named(name.into())))
+}
+
+fn kind_name(kind: &TypeKind) -> &'static str {
+    match kind {
+        TypeKind::Scalar => "SCALAR",
+        TypeKind::Object { .. } => "OBJECT",
+        TypeKind::Interface { .. } => "INTERFACE",
+        TypeKind::Union { .. } => "UNION",
+        TypeKind::Enum { .. } => "ENUM",
+        TypeKind::Input { .. } => "INPUT_OBJECT",
+    }
+}
+
+fn directive_argument(directives: &[Directive], directive: &str, argument: &str) -> Option<String> {
+    let directive = directives.iter().find(|d| d.name == directive)?;
+    match &directive.arguments.iter().find(|a| a.name == argument)?.value {
+        super::parser::Value::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// The reason given by `@deprecated`, when it is there.
+pub fn deprecation(directives: &[Directive]) -> Option<String> {
+    directives
+        .iter()
+        .any(|d| d.name == "deprecated")
+        .then(|| directive_argument(directives, "deprecated", "reason").unwrap_or_else(|| "No longer supported".into()))
+}
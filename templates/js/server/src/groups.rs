@@ -0,0 +1,128 @@
+//! Route groups: settings shared by every action under a directory of
+//! app/actions. A group can put a prefix in front of the file routes in
+//! it, run named middleware before its actions, and set their `auth` mode
+//! and `rate_limit` policy in one place.
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::middleware::{Decision, Interceptor};
+
+struct Group {
+    // Relative to app/actions, without slashes at either end
+    dir: String,
+    prefix: Vec<String>,
+    middleware: Vec<String>,
+    auth: Option<Value>,
+    rate_limit: Option<Value>,
+}
+
+/// The `groups` block of titan.config: a directory of app/actions to
+/// `{ prefix, middleware, auth, rate_limit }`. `middleware` names exports of
+/// app/middleware; `auth` and `rate_limit` take what a single action takes
+/// under `auth.routes` and `rate_limit.routes`. Groups nest: the outer
+/// group's middleware runs first, and the inner group's settings win.
+#[derive(Default)]
+pub struct Groups {
+    // Outer groups first
+    groups: Vec<Group>,
+}
+
+impl Groups {
+    pub fn from_config(config: &Value) -> Result<Self, String> {
+        let Some(entries) = config.as_object() else {
+            return Ok(Self::default());
+        };
+        let mut groups = Vec::new();
+        for (dir, entry) in entries {
+            let name = dir.trim_matches('/');
+            if name.is_empty() || name.split('/').any(|segment| segment.is_empty() || segment.starts_with('[')) {
+                return Err(format!("groups: \"{}\" is not a directory of app/actions", dir));
+            }
+            let prefix: Vec<String> = match &entry["prefix"] {
+                Value::String(prefix) => prefix.split('/').filter(|s| !s.is_empty()).map(str::to_string).collect(),
+                Value::Null => Vec::new(),
+                _ => return Err(format!("groups.{}: prefix must be a path", dir)),
+            };
+            let middleware = match &entry["middleware"] {
+                Value::String(name) => vec![name.clone()],
+                Value::Array(names) => names.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+                Value::Null => Vec::new(),
+                _ => return Err(format!("groups.{}: middleware must be a name or a list of names", dir)),
+            };
+            let setting = |key: &str| Some(entry[key].clone()).filter(|v| !v.is_null());
+            groups.push(Group { dir: name.to_string(), prefix, middleware, auth: setting("auth"), rate_limit: setting("rate_limit") });
+        }
+        groups.sort_by_key(|group| group.dir.matches('/').count());
+        Ok(Self { groups })
+    }
+
+    // The groups an action is in, outer first
+    fn of<'a>(&'a self, action: &'a str) -> impl Iterator<Item = &'a Group> + 'a {
+        self.groups
+            .iter()
+            .filter(move |group| action.strip_prefix(group.dir.as_str()).is_some_and(|rest| rest.starts_with('/')))
+    }
+
+    /// The directories of a file route with each group's prefix put in front
+    /// of the group's own directory, so `admin/users` in a group `admin` with
+    /// prefix `/internal` is `internal/admin/users`.
+    pub fn route_segments(&self, action: &str, segments: &[&str]) -> Vec<String> {
+        let mut out: Vec<String> = segments.iter().map(|s| s.to_string()).collect();
+        let groups: Vec<&Group> = self.of(action).collect();
+        // Inner groups first, so the indices of the outer ones still hold
+        for group in groups.into_iter().rev() {
+            let at = group.dir.matches('/').count();
+            out.splice(at..at, group.prefix.iter().cloned());
+        }
+        out
+    }
+
+    /// `auth` and `rate_limit` of titan.config with the groups' settings
+    /// written in under `routes` for each of their actions that doesn't
+    /// have one of its own.
+    pub fn expand<'a>(&self, config: &Value, actions: impl Iterator<Item = &'a String>) -> Result<(Value, Value), String> {
+        let mut auth = config["auth"].clone();
+        let mut rate_limit = config["rate_limit"].clone();
+        if self.groups.iter().any(|group| group.auth.is_some()) && !auth.is_object() {
+            return Err("groups: auth needs the auth block to be configured".to_string());
+        }
+        for action in actions {
+            for (block, setting) in [(&mut auth, self.setting(action, |g| g.auth.as_ref())), (&mut rate_limit, self.setting(action, |g| g.rate_limit.as_ref()))] {
+                let Some(setting) = setting else { continue };
+                if !block.is_object() {
+                    *block = Value::Object(Map::new());
+                }
+                let routes = block.as_object_mut().unwrap().entry("routes").or_insert_with(|| Value::Object(Map::new()));
+                if let Some(routes) = routes.as_object_mut() {
+                    routes.entry(action.clone()).or_insert_with(|| setting.clone());
+                }
+            }
+        }
+        Ok((auth, rate_limit))
+    }
+
+    // The innermost group's value of a setting
+    fn setting<'a>(&'a self, action: &'a str, pick: impl Fn(&'a Group) -> Option<&'a Value>) -> Option<&'a Value> {
+        self.of(action).filter_map(pick).last()
+    }
+
+    /// Tags each request with the middleware of its action's groups, for
+    /// the worker to run after app/middleware's default chain.
+    pub fn interceptor<'a>(&self, actions: impl Iterator<Item = &'a String>) -> Option<Interceptor> {
+        let chains: HashMap<String, Arc<[String]>> = actions
+            .filter_map(|action| {
+                let chain: Vec<String> = self.of(action).flat_map(|group| group.middleware.iter().cloned()).collect();
+                (!chain.is_empty()).then(|| (action.clone(), Arc::from(chain)))
+            })
+            .collect();
+        if chains.is_empty() {
+            return None;
+        }
+        Some(Box::new(move |task| {
+            task.middleware = chains.get(&task.action_name).cloned();
+            Decision::Continue
+        }))
+    }
+}
@@ -0,0 +1,352 @@
+mod codec;
+mod proto;
+
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderValue};
+use bytes::Bytes;
+use http_body::Frame;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use proto::Pool;
+
+/// Status codes from the gRPC spec, as far as the server sends them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    Ok = 0,
+    Cancelled = 1,
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    Unauthenticated = 16,
+}
+
+impl Code {
+    /// The gRPC code an action's HTTP status stands for.
+    pub fn from_http(status: u16) -> Code {
+        match status {
+            200..=299 => Code::Ok,
+            400 | 422 => Code::InvalidArgument,
+            401 => Code::Unauthenticated,
+            403 => Code::PermissionDenied,
+            404 => Code::NotFound,
+            409 => Code::AlreadyExists,
+            412 => Code::FailedPrecondition,
+            413 | 429 => Code::ResourceExhausted,
+            499 => Code::Cancelled,
+            501 => Code::Unimplemented,
+            503 => Code::Unavailable,
+            504 => Code::DeadlineExceeded,
+            500..=599 => Code::Internal,
+            _ => Code::Unknown,
+        }
+    }
+}
+
+/// A failed call: a code and a message for `grpc-message`.
+#[derive(Debug)]
+pub struct Status {
+    pub code: Code,
+    pub message: String,
+}
+
+impl Status {
+    pub fn new(code: Code, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+
+    /// A Trailers-Only response: the status goes out with the headers and
+    /// there is no body.
+    pub fn into_response(self, metadata: &[(String, String)]) -> axum::response::Response {
+        let mut headers = response_headers(metadata);
+        put_status(&mut headers, self.code, &self.message);
+        let mut response = axum::response::Response::new(Body::empty());
+        *response.headers_mut() = headers;
+        response
+    }
+}
+
+/// One method a service declares, and the action that answers it.
+pub struct Route {
+    pub action: String,
+    input: String,
+    output: String,
+    pub client_streaming: bool,
+    pub server_streaming: bool,
+}
+
+/// The `grpc` block of titan.config. The services in the .proto files under
+/// `protos` (default `app/protos`) are served on their own `port` (default
+/// 50051), each method by an action: the one `methods` maps
+/// `"package.Service/Method"` to, or else the action named after the method
+/// (`getUser` or `GetUser` for `GetUser`).
+pub struct Grpc {
+    pub port: u16,
+    pool: Pool,
+    routes: HashMap<String, Route>,
+    max_message_bytes: usize,
+}
+
+impl Grpc {
+    pub fn from_config<'a>(
+        config: &Value,
+        root: &Path,
+        actions: impl Iterator<Item = &'a String>,
+    ) -> Result<Option<Grpc>, String> {
+        if config.is_null() || config.as_bool() == Some(false) || config["enabled"].as_bool() == Some(false) {
+            return Ok(None);
+        }
+        let dir = root.join(config["protos"].as_str().unwrap_or("app/protos"));
+        let pool = Pool::load(&dir).map_err(|e| format!("grpc: {}", e))?;
+        let actions: Vec<&String> = actions.collect();
+        let mapped = config["methods"].as_object();
+
+        let mut routes = HashMap::new();
+        for service in &pool.services {
+            let short = service.name.rsplit('.').next().unwrap_or(&service.name);
+            for method in &service.methods {
+                let full = format!("{}/{}", service.name, method.name);
+                let explicit = mapped.and_then(|m| m.get(&full).or_else(|| m.get(&format!("{}/{}", short, method.name))));
+                let action = match explicit {
+                    Some(Value::String(action)) => {
+                        if !actions.contains(&action) {
+                            return Err(format!("grpc: {} maps to {}, which is not an action", full, action));
+                        }
+                        Some(action.clone())
+                    }
+                    Some(_) => return Err(format!("grpc: the action for {} must be a string", full)),
+                    None => {
+                        let mut chars = method.name.chars();
+                        let lower: String = chars.next().map(|c| c.to_ascii_lowercase()).into_iter().chain(chars).collect();
+                        [lower.as_str(), method.name.as_str()]
+                            .into_iter()
+                            .find(|name| actions.iter().any(|a| a.as_str() == *name))
+                            .map(str::to_string)
+                    }
+                };
+                let Some(action) = action else {
+                    tracing::warn!("grpc: no action for {}; calls to it answer UNIMPLEMENTED", full);
+                    continue;
+                };
+                routes.insert(
+                    format!("/{}", full),
+                    Route {
+                        action,
+                        input: method.input.clone(),
+                        output: method.output.clone(),
+                        client_streaming: method.client_streaming,
+                        server_streaming: method.server_streaming,
+                    },
+                );
+            }
+        }
+        if let Some(mapped) = mapped {
+            for key in mapped.keys() {
+                let known = pool.services.iter().any(|s| {
+                    let short = s.name.rsplit('.').next().unwrap_or(&s.name);
+                    s.methods.iter().any(|m| *key == format!("{}/{}", s.name, m.name) || *key == format!("{}/{}", short, m.name))
+                });
+                if !known {
+                    return Err(format!("grpc: methods maps {}, which no service declares", key));
+                }
+            }
+        }
+
+        Ok(Some(Grpc {
+            port: config["port"].as_u64().map_or(50051, |p| p as u16),
+            pool,
+            routes,
+            max_message_bytes: config["max_message_mb"].as_u64().filter(|mb| *mb > 0).map_or(4, |mb| mb as usize) * 1024 * 1024,
+        }))
+    }
+
+    pub fn routes(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// The route for a request path, `/package.Service/Method`.
+    pub fn route(&self, path: &str) -> Option<&Route> {
+        self.routes.get(path)
+    }
+
+    /// The request as the action sees it in `req.body`: the message, or for
+    /// client-streaming methods the array of messages.
+    pub fn decode_request(&self, route: &Route, headers: &HeaderMap, body: &[u8]) -> Result<Value, Status> {
+        let gzip = match headers.get("grpc-encoding").and_then(|v| v.to_str().ok()) {
+            None | Some("identity") => false,
+            Some("gzip") => true,
+            Some(other) => return Err(Status::new(Code::Unimplemented, format!("Unsupported grpc-encoding {}", other))),
+        };
+        let messages = self.frames(body, gzip)?;
+        let mut decoded = Vec::with_capacity(messages.len());
+        for message in &messages {
+            let value = self
+                .pool
+                .decode(&route.input, message)
+                .map_err(|e| Status::new(Code::Internal, format!("Could not decode {}: {}", route.input, e)))?;
+            decoded.push(value);
+        }
+        if route.client_streaming {
+            return Ok(Value::Array(decoded));
+        }
+        match decoded.len() {
+            1 => Ok(decoded.pop().unwrap_or(Value::Null)),
+            0 => Err(Status::new(Code::Internal, "Missing request message")),
+            _ => Err(Status::new(Code::Internal, "More than one request message for a unary method")),
+        }
+    }
+
+    /// Frames the action's result: one message, or for server-streaming
+    /// methods one per element of the array it returned.
+    pub fn encode_response(&self, route: &Route, value: Value) -> Result<Bytes, Status> {
+        let messages = match value {
+            Value::Array(items) if route.server_streaming => items,
+            value => vec![value],
+        };
+        let mut out = Vec::new();
+        for message in &messages {
+            let encoded = self
+                .pool
+                .encode(&route.output, message)
+                .map_err(|e| Status::new(Code::Internal, format!("Could not encode {}: {}", route.output, e)))?;
+            out.push(0);
+            out.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+            out.extend_from_slice(&encoded);
+        }
+        Ok(Bytes::from(out))
+    }
+
+    // Length-prefixed messages: a compressed flag, a big-endian length, the bytes
+    fn frames(&self, mut body: &[u8], gzip: bool) -> Result<Vec<Vec<u8>>, Status> {
+        let mut messages = Vec::new();
+        while !body.is_empty() {
+            if body.len() < 5 {
+                return Err(Status::new(Code::Internal, "Truncated message frame"));
+            }
+            let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+            if len > self.max_message_bytes {
+                return Err(Status::new(
+                    Code::ResourceExhausted,
+                    format!("Message of {} bytes exceeds the {} byte limit", len, self.max_message_bytes),
+                ));
+            }
+            let payload = body.get(5..5 + len).ok_or_else(|| Status::new(Code::Internal, "Truncated message frame"))?;
+            let message = match body[0] {
+                0 => payload.to_vec(),
+                1 if gzip => {
+                    let mut out = Vec::new();
+                    flate2::read::GzDecoder::new(payload)
+                        .take(self.max_message_bytes as u64 + 1)
+                        .read_to_end(&mut out)
+                        .map_err(|e| Status::new(Code::Internal, format!("Could not decompress message: {}", e)))?;
+                    if out.len() > self.max_message_bytes {
+                        return Err(Status::new(Code::ResourceExhausted, "Decompressed message exceeds the size limit"));
+                    }
+                    out
+                }
+                _ => return Err(Status::new(Code::Internal, "Compressed message without a grpc-encoding")),
+            };
+            messages.push(message);
+            body = &body[5 + len..];
+        }
+        Ok(messages)
+    }
+}
+
+/// The call's deadline from `grpc-timeout`, e.g. `250m` or `5S`.
+pub fn timeout(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get("grpc-timeout")?.to_str().ok()?;
+    let (digits, unit) = value.split_at(value.len().checked_sub(1)?);
+    let n: u64 = digits.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(n.saturating_mul(3600)),
+        "M" => Duration::from_secs(n.saturating_mul(60)),
+        "S" => Duration::from_secs(n),
+        "m" => Duration::from_millis(n),
+        "u" => Duration::from_micros(n),
+        "n" => Duration::from_nanos(n),
+        _ => return None,
+    })
+}
+
+/// A successful call: the messages, then trailers with status OK.
+pub fn ok_response(messages: Bytes, metadata: &[(String, String)]) -> axum::response::Response {
+    let mut trailers = HeaderMap::new();
+    put_status(&mut trailers, Code::Ok, "");
+    let mut response = axum::response::Response::new(Body::new(WithTrailers { data: Some(messages), trailers: Some(trailers) }));
+    *response.headers_mut() = response_headers(metadata);
+    response
+}
+
+// Initial metadata: what interceptors and the action set, minus HTTP framing
+fn response_headers(metadata: &[(String, String)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("content-type", HeaderValue::from_static("application/grpc"));
+    headers.insert("grpc-accept-encoding", HeaderValue::from_static("identity,gzip"));
+    for (k, v) in metadata {
+        let k = k.to_ascii_lowercase();
+        if matches!(k.as_str(), "content-type" | "content-length" | "transfer-encoding" | "connection") || k.starts_with("grpc-") {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (axum::http::HeaderName::from_bytes(k.as_bytes()), HeaderValue::from_str(v)) {
+            headers.append(name, value);
+        }
+    }
+    headers
+}
+
+// grpc-message is percent-encoded, which leaves printable ASCII but '%' as is
+const MESSAGE: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS.add(b'%');
+
+fn put_status(headers: &mut HeaderMap, code: Code, message: &str) {
+    headers.insert("grpc-status", HeaderValue::from(code as u32));
+    if !message.is_empty() {
+        let encoded = percent_encoding::utf8_percent_encode(message, MESSAGE).to_string();
+        if let Ok(value) = HeaderValue::from_str(&encoded) {
+            headers.insert("grpc-message", value);
+        }
+    }
+}
+
+/// Whether a request is gRPC at all; anything else gets a plain HTTP error.
+pub fn is_grpc(headers: &HeaderMap) -> bool {
+    headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct == "application/grpc" || ct.starts_with("application/grpc+") || ct.starts_with("application/grpc;"))
+}
+
+// A body of one data frame followed by trailers, which gRPC needs even on success
+struct WithTrailers {
+    data: Option<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+impl http_body::Body for WithTrailers {
+    type Data = Bytes;
+    type Error = std::convert::Infallible;
+
+    fn poll_frame(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        if let Some(data) = self.data.take() {
+            return Poll::Ready(Some(Ok(Frame::data(data))));
+        }
+        Poll::Ready(self.trailers.take().map(|trailers| Ok(Frame::trailers(trailers))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_none() && self.trailers.is_none()
+    }
+}
@@ -0,0 +1,85 @@
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+struct Settings {
+    dir: PathBuf,
+    keep: usize,
+}
+
+/// The `heap_snapshots` block of titan.config, or TITAN_HEAP_SNAPSHOTS=1.
+/// Snapshots go to `dir` under the project root (default `.titan/heap`);
+/// only the newest `keep` (default 5) are kept.
+pub fn configure(config: &Value, root: &Path) {
+    let from_env = std::env::var("TITAN_HEAP_SNAPSHOTS").is_ok_and(|v| v == "1" || v == "true");
+    let from_config = config.as_bool().unwrap_or(false) || (config.is_object() && config["enabled"].as_bool().unwrap_or(true));
+    if !from_env && !from_config {
+        return;
+    }
+    let dir = root.join(config["dir"].as_str().unwrap_or(".titan/heap"));
+    let keep = config["keep"].as_u64().filter(|n| *n > 0).unwrap_or(5) as usize;
+    let _ = SETTINGS.set(Settings { dir, keep });
+}
+
+pub fn enabled() -> bool {
+    SETTINGS.get().is_some()
+}
+
+/// Where the next snapshot of `worker` goes.
+pub fn next_path(worker: usize) -> Option<PathBuf> {
+    let settings = SETTINGS.get()?;
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    Some(settings.dir.join(format!("titan-{}-worker{}-{}.heapsnapshot", std::process::id(), worker, millis)))
+}
+
+/// Streams a snapshot of `isolate`'s heap to `path` in the format Chrome
+/// DevTools loads, and returns its size. Runs on the worker's thread, which
+/// does nothing else meanwhile.
+pub fn write(isolate: &mut v8::Isolate, path: &Path) -> Result<u64, String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let mut out = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+    let mut written = 0u64;
+    let mut failed = None;
+    isolate.take_heap_snapshot(|chunk| match out.write_all(chunk) {
+        Ok(()) => {
+            written += chunk.len() as u64;
+            true
+        }
+        Err(e) => {
+            failed = Some(e);
+            false
+        }
+    });
+    if let Some(e) = failed.or_else(|| out.flush().err()) {
+        let _ = std::fs::remove_file(path);
+        return Err(e.to_string());
+    }
+    prune();
+    Ok(written)
+}
+
+// Deletes all but the newest `keep` snapshots
+fn prune() {
+    let Some(settings) = SETTINGS.get() else {
+        return;
+    };
+    let Ok(entries) = std::fs::read_dir(&settings.dir) else {
+        return;
+    };
+    let mut snapshots: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "heapsnapshot"))
+        .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+        .collect();
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.0));
+    for (_, path) in snapshots.into_iter().skip(settings.keep) {
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,373 @@
+use axum::{
+    Router,
+    body::Body,
+    extract::{Path, State},
+    http::{Request, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+    routing::get,
+};
+use crossbeam::channel::{Receiver, Sender};
+use serde_json::{Value, json};
+use std::cell::{Cell, RefCell};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::ptr::addr_of;
+use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::mpsc;
+use v8::inspector::{
+    AsChannel, ChannelBase, ChannelImpl, StringBuffer, StringView, V8Inspector, V8InspectorClientBase,
+    V8InspectorClientImpl, V8InspectorClientTrustLevel, V8InspectorSession,
+};
+use v8::{UniquePtr, UniqueRef};
+
+use crate::runtime::{RuntimeManager, WorkerCommand};
+use crate::websocket::{self, Peer, WsMessage};
+
+const CONTEXT_GROUP: i32 = 1;
+const DEFAULT_ADDR: &str = "127.0.0.1:9229";
+
+static ADDR: OnceLock<SocketAddr> = OnceLock::new();
+
+/// `--inspect` or `--inspect=host:port` on the command line, or TITAN_INSPECT
+/// set to 1 or to an address: serves the DevTools protocol there (default
+/// 127.0.0.1:9229) so Chrome or VS Code can debug the workers.
+pub fn configure() -> Result<(), String> {
+    let flag = std::env::args().skip(1).find_map(|arg| match arg.strip_prefix("--inspect")? {
+        "" => Some(String::new()),
+        rest => rest.strip_prefix('=').map(str::to_string),
+    });
+    let Some(value) = flag.or_else(|| std::env::var("TITAN_INSPECT").ok().filter(|v| v != "0" && v != "false")) else {
+        return Ok(());
+    };
+    let value = match value.as_str() {
+        "" | "1" | "true" => DEFAULT_ADDR.to_string(),
+        port if port.parse::<u16>().is_ok() => format!("127.0.0.1:{}", port),
+        addr => addr.to_string(),
+    };
+    let addr = value
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| format!("--inspect: '{}' is not a host:port", value))?;
+    let _ = ADDR.set(addr);
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    ADDR.get().is_some()
+}
+
+/// What DevTools sent over a worker's debugger connection.
+pub enum Incoming {
+    Message(String),
+    Closed,
+}
+
+/// The inspector of a worker's isolate. The profiler and an attached
+/// debugger are sessions on it; it must go before the isolate does, and
+/// after every session.
+pub struct Inspector {
+    debugger: Rc<RefCell<Option<Debugger>>>,
+    inspector: UniqueRef<V8Inspector>,
+    _client: Box<Client>,
+}
+
+impl Drop for Inspector {
+    fn drop(&mut self) {
+        // The client shares the debugger, and outlives the inspector
+        self.debugger.borrow_mut().take();
+    }
+}
+
+// A DevTools connection to the isolate
+struct Debugger {
+    // Dropped before its channel
+    session: UniqueRef<V8InspectorSession>,
+    _channel: Box<Outbound>,
+    inbound: Receiver<Incoming>,
+}
+
+impl Debugger {
+    fn dispatch(&mut self, message: &str) {
+        self.session.dispatch_protocol_message(StringView::from(message.as_bytes()));
+    }
+}
+
+struct Client {
+    base: V8InspectorClientBase,
+    // Both are reached again from inside V8's calls back into the client,
+    // so they live outside it
+    debugger: Rc<RefCell<Option<Debugger>>>,
+    paused: Rc<Cell<bool>>,
+}
+
+impl V8InspectorClientImpl for Client {
+    fn base(&self) -> &V8InspectorClientBase {
+        &self.base
+    }
+    fn base_mut(&mut self) -> &mut V8InspectorClientBase {
+        &mut self.base
+    }
+    unsafe fn base_ptr(this: *const Self) -> *const V8InspectorClientBase {
+        unsafe { addr_of!((*this).base) }
+    }
+
+    // Holds the worker at a breakpoint, feeding DevTools' messages to the
+    // isolate until one of them resumes it
+    fn run_message_loop_on_pause(&mut self, _context_group_id: i32) {
+        self.paused.set(true);
+        while self.paused.get() {
+            let next = match self.debugger.try_borrow() {
+                Ok(debugger) => debugger.as_ref().and_then(|d| d.inbound.recv().ok()),
+                Err(_) => None,
+            };
+            match next {
+                Some(Incoming::Message(message)) => {
+                    if let Ok(mut debugger) = self.debugger.try_borrow_mut()
+                        && let Some(debugger) = debugger.as_mut()
+                    {
+                        debugger.dispatch(&message);
+                    }
+                }
+                // DevTools went away; the worker carries on without it
+                _ => {
+                    if let Ok(mut debugger) = self.debugger.try_borrow_mut() {
+                        debugger.take();
+                    }
+                    break;
+                }
+            }
+        }
+        self.paused.set(false);
+    }
+
+    fn quit_message_loop_on_pause(&mut self) {
+        self.paused.set(false);
+    }
+}
+
+// Sends the isolate's responses and events to DevTools
+struct Outbound {
+    base: ChannelBase,
+    tx: mpsc::UnboundedSender<WsMessage>,
+}
+
+impl Outbound {
+    fn send(&self, message: UniquePtr<StringBuffer>) {
+        if let Some(message) = message.as_ref() {
+            let _ = self.tx.send(WsMessage::Text(message.string().to_string()));
+        }
+    }
+}
+
+impl ChannelImpl for Outbound {
+    fn base(&self) -> &ChannelBase {
+        &self.base
+    }
+    fn base_mut(&mut self) -> &mut ChannelBase {
+        &mut self.base
+    }
+    unsafe fn base_ptr(this: *const Self) -> *const ChannelBase {
+        unsafe { addr_of!((*this).base) }
+    }
+    fn send_response(&mut self, _call_id: i32, message: UniquePtr<StringBuffer>) {
+        self.send(message);
+    }
+    fn send_notification(&mut self, message: UniquePtr<StringBuffer>) {
+        self.send(message);
+    }
+    fn flush_protocol_notifications(&mut self) {}
+}
+
+impl Inspector {
+    pub fn new(isolate: &mut v8::Isolate, context: &v8::Global<v8::Context>, worker: usize) -> Inspector {
+        let debugger = Rc::new(RefCell::new(None));
+        let mut client = Box::new(Client {
+            base: V8InspectorClientBase::new::<Client>(),
+            debugger: debugger.clone(),
+            paused: Rc::new(Cell::new(false)),
+        });
+        let mut inspector = V8Inspector::create(isolate, &mut *client);
+        {
+            let scope = &mut v8::HandleScope::new(isolate);
+            let context = v8::Local::new(scope, context);
+            let name = format!("Titan worker {}", worker);
+            let aux = r#"{"isDefault":true}"#;
+            inspector.context_created(context, CONTEXT_GROUP, StringView::from(name.as_bytes()), StringView::from(aux.as_bytes()));
+        }
+        Inspector { debugger, inspector, _client: client }
+    }
+
+    /// A new session talking to `channel`, which must outlive it.
+    pub fn connect<T: AsChannel>(&mut self, channel: &mut T) -> UniqueRef<V8InspectorSession> {
+        self.inspector.connect(CONTEXT_GROUP, channel, StringView::empty(), V8InspectorClientTrustLevel::FullyTrusted)
+    }
+
+    /// Connects DevTools, replacing any debugger already attached.
+    pub fn attach(&mut self, inbound: Receiver<Incoming>, outbound: mpsc::UnboundedSender<WsMessage>) {
+        let mut channel = Box::new(Outbound { base: ChannelBase::new::<Outbound>(), tx: outbound });
+        let session = self.connect(&mut *channel);
+        if let Ok(mut debugger) = self.debugger.try_borrow_mut() {
+            *debugger = Some(Debugger { session, _channel: channel, inbound });
+        }
+        self.pump();
+    }
+
+    /// Hands the attached debugger what DevTools sent since the last call.
+    pub fn pump(&mut self) {
+        loop {
+            let next = match self.debugger.try_borrow() {
+                Ok(debugger) => debugger.as_ref().and_then(|d| d.inbound.try_recv().ok()),
+                Err(_) => return,
+            };
+            match next {
+                Some(Incoming::Message(message)) => {
+                    if let Some(debugger) = self.debugger.borrow_mut().as_mut() {
+                        debugger.dispatch(&message);
+                    }
+                }
+                Some(Incoming::Closed) => {
+                    self.debugger.borrow_mut().take();
+                    return;
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+/// The socket end of a debugger connection to a worker. Messages wait in
+/// `inbound`: an idle worker is woken to take them, a paused one reads them
+/// itself.
+pub struct DebuggerPeer {
+    inbound: Sender<Incoming>,
+    worker_tx: Sender<WorkerCommand>,
+}
+
+impl DebuggerPeer {
+    pub fn new(inbound: Sender<Incoming>, worker_tx: Sender<WorkerCommand>) -> Self {
+        Self { inbound, worker_tx }
+    }
+
+    fn send(&self, incoming: Incoming) {
+        let _ = self.inbound.send(incoming);
+        // Never waits: a paused worker doesn't read its queue, only `inbound`
+        let _ = self.worker_tx.try_send(WorkerCommand::Debugger);
+    }
+}
+
+impl Peer for DebuggerPeer {
+    fn message(&self, message: WsMessage) {
+        if let WsMessage::Text(text) = message {
+            self.send(Incoming::Message(text));
+        }
+    }
+
+    fn close(&self) {
+        self.send(Incoming::Closed);
+    }
+}
+
+struct Targets {
+    runtime: Arc<RuntimeManager>,
+    root: PathBuf,
+}
+
+/// Serves DevTools' discovery endpoints, `/json/version` and `/json/list`,
+/// and a debugging target per worker at `/worker/<id>`.
+pub async fn serve(runtime: Arc<RuntimeManager>, root: PathBuf) {
+    let Some(addr) = ADDR.get().copied() else {
+        return;
+    };
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Inspector could not listen on {}: {}", addr, e);
+            return;
+        }
+    };
+    let app = Router::new()
+        .route("/json/version", get(version_route))
+        .route("/json", get(list_route))
+        .route("/json/list", get(list_route))
+        .route("/worker/{id}", get(target_route))
+        .with_state(Arc::new(Targets { runtime, root }));
+    tracing::info!("Debugger listening on ws://{}; open chrome://inspect to attach", addr);
+    if let Err(e) = axum::serve(listener, app).await {
+        tracing::error!("Inspector stopped: {}", e);
+    }
+}
+
+async fn version_route() -> Json<Value> {
+    Json(json!({ "Browser": format!("Titan/{}", env!("CARGO_PKG_VERSION")), "Protocol-Version": "1.3" }))
+}
+
+async fn list_route(State(targets): State<Arc<Targets>>, req: Request<Body>) -> Json<Value> {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| ADDR.get().map(ToString::to_string))
+        .unwrap_or_default();
+    let url = format!("file://{}", targets.root.display());
+    let list = targets
+        .runtime
+        .worker_status()
+        .into_iter()
+        .filter(|worker| worker["state"] == "running")
+        .filter_map(|worker| worker["id"].as_u64())
+        .map(|id| {
+            json!({
+                "id": format!("worker-{}", id),
+                "type": "node",
+                "title": format!("Titan worker {}", id),
+                "description": format!("Titan worker {}", id),
+                "url": url,
+                "webSocketDebuggerUrl": format!("ws://{}/worker/{}", host, id),
+                "devtoolsFrontendUrl": format!("devtools://devtools/bundled/js_app.html?experiments=true&v8only=true&ws={}/worker/{}", host, id),
+            })
+        })
+        .collect();
+    Json(Value::Array(list))
+}
+
+async fn target_route(State(targets): State<Arc<Targets>>, Path(id): Path<usize>, mut req: Request<Body>) -> Response {
+    let on_upgrade = match websocket::is_upgrade_request(req.headers()) {
+        true => req.extensions_mut().remove::<hyper::upgrade::OnUpgrade>(),
+        false => None,
+    };
+    let Some(on_upgrade) = on_upgrade else {
+        return (StatusCode::BAD_REQUEST, "Expected a WebSocket upgrade").into_response();
+    };
+    let client_key = req
+        .headers()
+        .get("sec-websocket-key")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let (outbound_tx, outbound_rx) = mpsc::unbounded_channel();
+    let peer = match targets.runtime.attach_debugger(id, outbound_tx.clone()) {
+        Ok(peer) => peer,
+        Err(e) => return (StatusCode::NOT_FOUND, e).into_response(),
+    };
+    tokio::spawn(async move {
+        let Ok(upgraded) = on_upgrade.await else {
+            peer.close();
+            return;
+        };
+        tracing::info!(worker = id, "Debugger attached");
+        websocket::run_connection(upgraded, peer, outbound_tx, outbound_rx).await;
+        tracing::info!(worker = id, "Debugger detached");
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", websocket::accept_key(&client_key))
+        .body(Body::empty())
+        .unwrap()
+}
@@ -0,0 +1,179 @@
+//! How `req.query` is shaped. By default it maps each name to one string,
+//! the last one sent; the other modes keep every value and build arrays
+//! and objects out of them, the way qs (and so Express) does.
+
+use serde_json::{Map, Value};
+use std::sync::OnceLock;
+
+static PARSER: OnceLock<Parser> = OnceLock::new();
+
+// Indices past this make `a[100]=x` an object key rather than a huge array, as in qs
+const ARRAY_LIMIT: usize = 20;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    // `?tag=a&tag=b` is `{ tag: ["a", "b"] }`
+    Repeat,
+    // Repeat, and `?tag[]=a`, `?list[0]=a` and `?filter[name]=x` nest
+    Bracket,
+    // Repeat, and `?tag=a,b` is `{ tag: ["a", "b"] }`
+    Comma,
+}
+
+struct Parser {
+    mode: Mode,
+    depth: usize,
+}
+
+/// The `query` key of titan.config: `"repeat"`, `"bracket"` or `"comma"`, or
+/// `{ mode, depth }` where `depth` (default 5) is how many brackets nest;
+/// the rest of a deeper key stays part of the last name. Unset, or
+/// `"flat"`, keeps one string per name.
+pub fn configure(config: &Value) -> Result<(), String> {
+    let (mode, depth) = match config {
+        Value::String(mode) => (mode.as_str(), None),
+        Value::Object(_) => (config["mode"].as_str().unwrap_or("bracket"), config["depth"].as_u64()),
+        _ => return Ok(()),
+    };
+    let mode = match mode {
+        "flat" => return Ok(()),
+        "repeat" => Mode::Repeat,
+        "bracket" => Mode::Bracket,
+        "comma" => Mode::Comma,
+        other => return Err(format!("query: unknown mode \"{}\" (expected flat, repeat, bracket or comma)", other)),
+    };
+    let _ = PARSER.set(Parser { mode, depth: depth.map_or(5, |d| d as usize) });
+    Ok(())
+}
+
+/// Whether `req.query` is built by `structured` rather than one string per name.
+pub fn is_structured() -> bool {
+    PARSER.get().is_some()
+}
+
+/// Splits a query string into its pairs, in order and repeats included.
+/// Names and values are percent-decoded, with `+` as a space, when `req.query`
+/// is structured; flat queries are passed on as they were sent.
+pub fn pairs(query: &str) -> Vec<(String, String)> {
+    let decode = |raw: &str| {
+        if is_structured() {
+            percent_encoding::percent_decode_str(&raw.replace('+', " ")).decode_utf8_lossy().into_owned()
+        } else {
+            raw.to_string()
+        }
+    };
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => (decode(name), decode(value)),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+/// `req.query` for the configured mode, from every pair in the order sent.
+pub fn structured(pairs: &[(String, String)]) -> Value {
+    let Some(parser) = PARSER.get() else {
+        return Value::Object(pairs.iter().map(|(k, v)| (k.clone(), Value::String(v.clone()))).collect());
+    };
+    let mut root = Map::new();
+    for (name, value) in pairs {
+        let value = match parser.mode {
+            Mode::Comma if value.contains(',') => {
+                Value::Array(value.split(',').map(|part| Value::String(part.to_string())).collect())
+            }
+            _ => Value::String(value.clone()),
+        };
+        let (base, path) = match parser.mode {
+            Mode::Bracket => split_brackets(name, parser.depth),
+            _ => (name.as_str(), Vec::new()),
+        };
+        if base.is_empty() {
+            continue;
+        }
+        let nested = path.iter().rev().fold(value, |inner, segment| match segment.as_str() {
+            "" => Value::Array(vec![inner]),
+            key => Value::Object(Map::from_iter([(key.to_string(), inner)])),
+        });
+        match root.remove(base) {
+            Some(existing) => root.insert(base.to_string(), merge(existing, nested)),
+            None => root.insert(base.to_string(), nested),
+        };
+    }
+    for value in root.values_mut() {
+        compact(value);
+    }
+    Value::Object(root)
+}
+
+// `a[b][]` is ("a", ["b", ""]). Past `depth`, the rest becomes one more
+// segment as written, so `a[b][c]` at depth 1 is ("a", ["b", "[c]"]).
+fn split_brackets(name: &str, depth: usize) -> (&str, Vec<String>) {
+    let Some(open) = name.find('[').filter(|&i| i > 0 && name.ends_with(']')) else {
+        return (name, Vec::new());
+    };
+    let (base, mut rest) = name.split_at(open);
+    let mut path = Vec::new();
+    while path.len() < depth {
+        let Some(inner) = rest.strip_prefix('[') else { break };
+        let Some(close) = inner.find(']') else { break };
+        path.push(inner[..close].to_string());
+        rest = &inner[close + 1..];
+    }
+    if !rest.is_empty() {
+        path.push(rest.to_string());
+    }
+    (base, path)
+}
+
+// Repeats of a name add to an array; objects merge key by key
+fn merge(existing: Value, incoming: Value) -> Value {
+    match (existing, incoming) {
+        (Value::Object(mut target), Value::Object(source)) => {
+            for (key, value) in source {
+                let merged = match target.remove(&key) {
+                    Some(old) => merge(old, value),
+                    None => value,
+                };
+                target.insert(key, merged);
+            }
+            Value::Object(target)
+        }
+        (Value::Object(mut target), Value::Array(items)) => {
+            let start = target.len();
+            for (i, item) in items.into_iter().enumerate() {
+                target.insert((start + i).to_string(), item);
+            }
+            Value::Object(target)
+        }
+        (Value::Array(mut items), Value::Array(more)) => {
+            items.extend(more);
+            Value::Array(items)
+        }
+        (Value::Array(mut items), other) => {
+            items.push(other);
+            Value::Array(items)
+        }
+        (value, Value::Array(more)) => Value::Array(std::iter::once(value).chain(more).collect()),
+        (value, other) => Value::Array(vec![value, other]),
+    }
+}
+
+// Objects whose keys are all small indices, from `a[0]=x&a[1]=y`, become
+// arrays in index order
+fn compact(value: &mut Value) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(compact),
+        Value::Object(map) => {
+            map.values_mut().for_each(compact);
+            let indices: Option<Vec<usize>> = map.keys().map(|k| k.parse::<usize>().ok().filter(|&i| i <= ARRAY_LIMIT && k == &i.to_string())).collect();
+            if let Some(mut indices) = indices.filter(|i| !i.is_empty()) {
+                indices.sort_unstable();
+                let items = indices.iter().filter_map(|i| map.remove(&i.to_string())).collect();
+                *value = Value::Array(items);
+            }
+        }
+        _ => {}
+    }
+}
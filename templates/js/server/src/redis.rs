@@ -0,0 +1,341 @@
+use bytes::{Buf, BytesMut};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::metrics;
+
+const DEFAULT_POOL_SIZE: usize = 4;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+static POOLS: OnceLock<Mutex<HashMap<String, &'static Pool>>> = OnceLock::new();
+
+/// Where and how to connect, from `redis://[user:password@]host[:port][/db]`.
+#[derive(Debug, Clone)]
+struct Target {
+    addr: String,
+    user: Option<String>,
+    password: Option<String>,
+    db: Option<u32>,
+}
+
+impl Target {
+    fn parse(url: &str) -> Result<Self, String> {
+        let rest = url.strip_prefix("redis://").ok_or("Redis URL must start with redis://")?;
+        let (auth, rest) = match rest.rsplit_once('@') {
+            Some((auth, rest)) => (Some(auth), rest),
+            None => (None, rest),
+        };
+        let (host, db) = match rest.split_once('/') {
+            Some((host, db)) if !db.is_empty() => (host, Some(db.parse::<u32>().map_err(|_| format!("invalid database '{}'", db))?)),
+            Some((host, _)) => (host, None),
+            None => (rest, None),
+        };
+        if host.is_empty() {
+            return Err("Redis URL has no host".to_string());
+        }
+        let addr = if host.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) {
+            host.to_string()
+        } else {
+            format!("{}:6379", host)
+        };
+        let decode = |s: &str| percent_encoding::percent_decode_str(s).decode_utf8_lossy().into_owned();
+        let (user, password) = match auth {
+            Some(auth) => match auth.split_once(':') {
+                Some((user, password)) => ((!user.is_empty()).then(|| decode(user)), Some(decode(password))),
+                None => (None, Some(decode(auth))),
+            },
+            None => (None, None),
+        };
+        Ok(Self { addr, user, password, db })
+    }
+}
+
+/// A server reply as RESP2 describes it.
+#[derive(Debug)]
+enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+impl Reply {
+    fn into_json(self) -> Value {
+        match self {
+            Reply::Status(s) => Value::String(s),
+            Reply::Error(e) => serde_json::json!({ "error": e }),
+            Reply::Integer(n) => Value::from(n),
+            Reply::Bulk(Some(bytes)) => Value::String(String::from_utf8_lossy(&bytes).into_owned()),
+            Reply::Bulk(None) | Reply::Array(None) => Value::Null,
+            Reply::Array(Some(items)) => Value::Array(items.into_iter().map(Reply::into_json).collect()),
+        }
+    }
+}
+
+/// Parses one reply from the front of `buf`. `Ok(None)` means more bytes
+/// are needed; on success the reply and its length are returned.
+fn parse_reply(buf: &[u8]) -> Result<Option<(Reply, usize)>, String> {
+    let Some(end) = memchr::memmem::find(buf, b"\r\n") else {
+        return Ok(None);
+    };
+    if end == 0 {
+        return Err("invalid reply from Redis".to_string());
+    }
+    let line = std::str::from_utf8(&buf[1..end]).map_err(|_| "invalid reply from Redis")?;
+    let header = end + 2;
+    let number = || line.parse::<i64>().map_err(|_| format!("invalid reply from Redis: {}", line));
+    match buf.first() {
+        Some(b'+') => Ok(Some((Reply::Status(line.to_string()), header))),
+        Some(b'-') => Ok(Some((Reply::Error(line.to_string()), header))),
+        Some(b':') => Ok(Some((Reply::Integer(number()?), header))),
+        Some(b'$') => {
+            let len = number()?;
+            if len < 0 {
+                return Ok(Some((Reply::Bulk(None), header)));
+            }
+            let len = len as usize;
+            if buf.len() < header + len + 2 {
+                return Ok(None);
+            }
+            Ok(Some((Reply::Bulk(Some(buf[header..header + len].to_vec())), header + len + 2)))
+        }
+        Some(b'*') => {
+            let count = number()?;
+            if count < 0 {
+                return Ok(Some((Reply::Array(None), header)));
+            }
+            let mut items = Vec::with_capacity((count as usize).min(1024));
+            let mut used = header;
+            for _ in 0..count {
+                match parse_reply(&buf[used..])? {
+                    Some((item, len)) => {
+                        items.push(item);
+                        used += len;
+                    }
+                    None => return Ok(None),
+                }
+            }
+            Ok(Some((Reply::Array(Some(items)), used)))
+        }
+        _ => Err("invalid reply from Redis".to_string()),
+    }
+}
+
+fn encode_command(out: &mut Vec<u8>, args: &[String]) {
+    out.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Commands to send together, and where their replies go.
+struct Batch {
+    commands: Vec<Vec<String>>,
+    reply: oneshot::Sender<Result<Vec<Reply>, String>>,
+}
+
+/// A multiplexed connection: one task owns the socket, writes whatever
+/// batches are queued in a single pipeline and hands replies back in order.
+struct Connection {
+    tx: mpsc::UnboundedSender<Batch>,
+}
+
+impl Connection {
+    async fn open(target: &Target, pool: &'static Pool) -> Result<Self, String> {
+        let stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&target.addr))
+            .await
+            .map_err(|_| format!("Redis connection to {} timed out", target.addr))?
+            .map_err(|e| format!("Redis connection failed: {}", e))?;
+        let _ = stream.set_nodelay(true);
+        let mut io = Io { stream, buf: BytesMut::with_capacity(8 * 1024) };
+
+        let mut setup = Vec::new();
+        if let Some(password) = &target.password {
+            let mut auth = vec!["AUTH".to_string()];
+            auth.extend(target.user.clone());
+            auth.push(password.clone());
+            setup.push(auth);
+        }
+        if let Some(db) = target.db {
+            setup.push(vec!["SELECT".to_string(), db.to_string()]);
+        }
+        if !setup.is_empty() {
+            for reply in io.round_trip(&setup).await.map_err(|e| format!("Redis connection failed: {}", e))? {
+                if let Reply::Error(e) = reply {
+                    return Err(format!("Redis connection failed: {}", e));
+                }
+            }
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(drive(io, rx, pool));
+        Ok(Self { tx })
+    }
+}
+
+struct Io {
+    stream: TcpStream,
+    buf: BytesMut,
+}
+
+impl Io {
+    async fn round_trip(&mut self, commands: &[Vec<String>]) -> std::io::Result<Vec<Reply>> {
+        let mut out = Vec::new();
+        for command in commands {
+            encode_command(&mut out, command);
+        }
+        self.stream.write_all(&out).await?;
+
+        let mut replies = Vec::with_capacity(commands.len());
+        while replies.len() < commands.len() {
+            match parse_reply(&self.buf).map_err(std::io::Error::other)? {
+                Some((reply, len)) => {
+                    self.buf.advance(len);
+                    replies.push(reply);
+                }
+                None => {
+                    if self.stream.read_buf(&mut self.buf).await? == 0 {
+                        return Err(std::io::ErrorKind::UnexpectedEof.into());
+                    }
+                }
+            }
+        }
+        Ok(replies)
+    }
+}
+
+async fn drive(mut io: Io, mut rx: mpsc::UnboundedReceiver<Batch>, pool: &'static Pool) {
+    while let Some(first) = rx.recv().await {
+        // Everything queued meanwhile joins the same pipeline
+        let mut batches = vec![first];
+        while let Ok(batch) = rx.try_recv() {
+            batches.push(batch);
+        }
+        let commands: Vec<Vec<String>> = batches.iter().flat_map(|b| b.commands.iter().cloned()).collect();
+        pool.pipelines.fetch_add(1, Ordering::Relaxed);
+
+        match io.round_trip(&commands).await {
+            Ok(replies) => {
+                let mut replies = replies.into_iter();
+                for batch in batches {
+                    let _ = batch.reply.send(Ok(replies.by_ref().take(batch.commands.len()).collect()));
+                }
+            }
+            Err(e) => {
+                // The stream is out of step now; callers get the error and the next batch reconnects
+                for batch in batches {
+                    let _ = batch.reply.send(Err(format!("Redis connection lost: {}", e)));
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Connections to one Redis server, shared by every worker.
+pub struct Pool {
+    target: Target,
+    slots: Vec<tokio::sync::Mutex<Option<Connection>>>,
+    next: AtomicUsize,
+    commands: AtomicU64,
+    pipelines: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// Registers the pool for `url`, or returns the existing one. Connections are
+/// opened on first use.
+pub fn pool(url: &str, max_size: Option<usize>) -> Result<&'static Pool, String> {
+    let pools = POOLS.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut pools = pools.lock().unwrap();
+    if let Some(pool) = pools.get(url) {
+        return Ok(pool);
+    }
+    let target = Target::parse(url)?;
+    let size = max_size.unwrap_or(DEFAULT_POOL_SIZE).max(1);
+    let pool: &'static Pool = Box::leak(Box::new(Pool {
+        target,
+        slots: (0..size).map(|_| tokio::sync::Mutex::new(None)).collect(),
+        next: AtomicUsize::new(0),
+        commands: AtomicU64::new(0),
+        pipelines: AtomicU64::new(0),
+        errors: AtomicU64::new(0),
+    }));
+    pools.insert(url.to_string(), pool);
+    Ok(pool)
+}
+
+/// The circuit commands to `url` share: `redis:host:port`.
+pub fn target(url: &str) -> Option<String> {
+    pool(url, None).ok().map(|pool| format!("redis:{}", pool.target.addr))
+}
+
+/// Sends `commands` as one pipeline. Each result is the reply as JSON, or an
+/// `{ error }` object when Redis rejected that command.
+pub async fn pipeline(url: &str, commands: Vec<Vec<String>>) -> Result<Vec<Value>, String> {
+    let pool = pool(url, None)?;
+    pool.commands.fetch_add(commands.len() as u64, Ordering::Relaxed);
+    let result = pool.send(commands).await;
+    match &result {
+        Ok(replies) => {
+            let failed = replies.iter().filter(|r| r.get("error").is_some()).count();
+            pool.errors.fetch_add(failed as u64, Ordering::Relaxed);
+        }
+        Err(_) => {
+            pool.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    result
+}
+
+impl Pool {
+    async fn send(&'static self, commands: Vec<Vec<String>>) -> Result<Vec<Value>, String> {
+        let slot = &self.slots[self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len()];
+        let (reply_tx, reply_rx) = oneshot::channel();
+        {
+            let mut conn = slot.lock().await;
+            if conn.as_ref().is_none_or(|c| c.tx.is_closed()) {
+                *conn = Some(Connection::open(&self.target, self).await?);
+            }
+            let batch = Batch { commands, reply: reply_tx };
+            conn.as_ref()
+                .expect("connection was just opened")
+                .tx
+                .send(batch)
+                .map_err(|_| "Redis connection lost".to_string())?;
+        }
+        let replies = reply_rx.await.map_err(|_| "Redis connection lost".to_string())??;
+        Ok(replies.into_iter().map(Reply::into_json).collect())
+    }
+}
+
+/// Prometheus text exposition of every pool.
+pub fn render(out: &mut String) {
+    let Some(pools) = POOLS.get() else {
+        return;
+    };
+    let pools: Vec<&'static Pool> = pools.lock().unwrap().values().copied().collect();
+    if pools.is_empty() {
+        return;
+    }
+    let series = |out: &mut String, name: &str, help: &str, value: &dyn Fn(&Pool) -> u64| {
+        metrics::header(out, name, "counter", help);
+        for pool in &pools {
+            let _ = writeln!(out, "{}{{server=\"{}\"}} {}", name, metrics::escape_label(&pool.target.addr), value(pool));
+        }
+    };
+    series(out, "titan_redis_commands_total", "Commands sent to Redis.", &|p| p.commands.load(Ordering::Relaxed));
+    series(out, "titan_redis_pipelines_total", "Writes to Redis; each carries every command queued at the time.", &|p| {
+        p.pipelines.load(Ordering::Relaxed)
+    });
+    series(out, "titan_redis_errors_total", "Commands Redis rejected, plus failed connections.", &|p| p.errors.load(Ordering::Relaxed));
+}
@@ -0,0 +1,210 @@
+use axum::http::HeaderMap;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{Value, json};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+
+const BATCH_SIZE: usize = 512;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+
+static EXPORTER: OnceLock<mpsc::UnboundedSender<SpanRecord>> = OnceLock::new();
+
+/// W3C trace context of one request. `span_id` is the server span the HTTP
+/// handler opens; work done in the isolate is parented to it.
+#[derive(Debug, Clone)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_id: Option<String>,
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Continues the caller's trace when a valid `traceparent` header is present,
+    /// otherwise starts a new one.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let parent = headers
+            .get("traceparent")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_traceparent);
+
+        match parent {
+            Some((trace_id, parent_id, sampled)) => Self {
+                trace_id,
+                span_id: new_span_id(),
+                parent_id: Some(parent_id),
+                sampled,
+            },
+            None => Self {
+                trace_id: random_hex(16),
+                span_id: new_span_id(),
+                parent_id: None,
+                sampled: true,
+            },
+        }
+    }
+}
+
+/// A finished span, ready to be exported.
+pub struct SpanRecord {
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_id: Option<String>,
+    pub name: String,
+    pub server: bool,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub error: Option<String>,
+    pub attributes: Vec<(String, Value)>,
+}
+
+fn parse_traceparent(value: &str) -> Option<(String, String, bool)> {
+    let mut parts = value.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+
+    let is_hex = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit());
+    if version == "ff" || !is_hex(version, 2) || !is_hex(trace_id, 32) || !is_hex(parent_id, 16) || !is_hex(flags, 2) {
+        return None;
+    }
+    // All-zero ids are invalid per the spec
+    if trace_id.bytes().all(|b| b == b'0') || parent_id.bytes().all(|b| b == b'0') {
+        return None;
+    }
+    let sampled = u8::from_str_radix(flags, 16).ok()? & 0x01 == 1;
+    Some((trace_id.to_ascii_lowercase(), parent_id.to_ascii_lowercase(), sampled))
+}
+
+/// The `X-Request-Id` the client sent, if it is short and plain enough to
+/// go in log lines, or else a new random id.
+pub fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|id| {
+            (1..=128).contains(&id.len())
+                && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':' | b'/' | b'+' | b'='))
+        })
+        .map_or_else(new_request_id, str::to_string)
+}
+
+/// An id for work that has no incoming request, like jobs and tasks.
+pub fn new_request_id() -> String {
+    random_hex(16)
+}
+
+fn random_hex(len: usize) -> String {
+    let mut buf = vec![0u8; len];
+    let _ = SystemRandom::new().fill(&mut buf);
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn new_span_id() -> String {
+    random_hex(8)
+}
+
+/// Starts the background OTLP/HTTP exporter. Spans recorded before this (or
+/// when no endpoint is configured) are dropped.
+pub fn init(endpoint: Option<String>, service_name: String) {
+    let Some(endpoint) = endpoint.filter(|e| !e.is_empty()) else {
+        return;
+    };
+    let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+    let (tx, rx) = mpsc::unbounded_channel();
+    if EXPORTER.set(tx).is_err() {
+        return;
+    }
+
+    tracing::info!("Exporting traces to {}", url);
+    tokio::spawn(export_loop(rx, url, service_name));
+}
+
+pub fn record(span: SpanRecord) {
+    if let Some(tx) = EXPORTER.get() {
+        let _ = tx.send(span);
+    }
+}
+
+async fn export_loop(mut rx: mpsc::UnboundedReceiver<SpanRecord>, url: String, service_name: String) {
+    let client = reqwest::Client::new();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+    loop {
+        let closed = match tokio::time::timeout(FLUSH_INTERVAL, rx.recv()).await {
+            Ok(Some(span)) => {
+                batch.push(span);
+                if batch.len() < BATCH_SIZE {
+                    continue;
+                }
+                false
+            }
+            Ok(None) => true,
+            Err(_) => false, // Flush whatever accumulated
+        };
+
+        if !batch.is_empty() {
+            let payload = otlp_payload(&service_name, batch.drain(..));
+            // Export failures are not worth failing requests over
+            let _ = client.post(&url).json(&payload).send().await;
+        }
+        if closed {
+            break;
+        }
+    }
+}
+
+fn unix_nanos(t: SystemTime) -> String {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
+
+fn otlp_attribute(key: &str, value: &Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!({ "intValue": n.to_string() }),
+        Value::Number(n) => json!({ "doubleValue": n }),
+        Value::String(s) => json!({ "stringValue": s }),
+        other => json!({ "stringValue": other.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+/// OTLP/HTTP JSON encoding of a batch of spans.
+fn otlp_payload(service_name: &str, spans: impl Iterator<Item = SpanRecord>) -> Value {
+    let spans: Vec<Value> = spans
+        .map(|span| {
+            let status = match &span.error {
+                Some(msg) => json!({ "code": 2, "message": msg }),
+                None => json!({ "code": 0 }),
+            };
+            let attributes: Vec<Value> = span.attributes.iter().map(|(k, v)| otlp_attribute(k, v)).collect();
+            json!({
+                "traceId": span.trace_id,
+                "spanId": span.span_id,
+                "parentSpanId": span.parent_id.unwrap_or_default(),
+                "name": span.name,
+                "kind": if span.server { 2 } else { 1 },
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.end),
+                "attributes": attributes,
+                "status": status,
+            })
+        })
+        .collect();
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [otlp_attribute("service.name", &json!(service_name))]
+            },
+            "scopeSpans": [{
+                "scope": { "name": "titan" },
+                "spans": spans
+            }]
+        }]
+    })
+}
@@ -0,0 +1,96 @@
+//! Where a request's time went: timestamps taken as it is queued, taken by
+//! a worker, run and encoded, so queueing delay can be told apart from slow
+//! JS. The access log and the `server_timing` option read them.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+tokio::task_local! {
+    // The timeline of the request being answered on this task
+    static TIMELINE: Arc<Timeline>;
+}
+
+#[derive(Clone, Copy)]
+pub enum Mark {
+    /// Handed to the scheduler.
+    Enqueued,
+    /// Taken from the queue by a worker.
+    Dequeued,
+    /// The worker starts the action.
+    ExecStart,
+    /// The action's answer is back on the HTTP side.
+    ExecEnd,
+    SerializeStart,
+    SerializeEnd,
+}
+
+/// One request's marks, in microseconds since it arrived. The first time a
+/// mark is set wins, so a hedge copy or an `_error` fallback doesn't move it.
+pub struct Timeline {
+    origin: Instant,
+    // Offset + 1 of each mark, 0 while unset
+    marks: [AtomicU64; 6],
+    drift_us: AtomicU64,
+}
+
+/// The request's time split up, in milliseconds.
+pub struct Breakdown {
+    pub queue_ms: f64,
+    pub exec_ms: f64,
+    pub serialize_ms: Option<f64>,
+}
+
+impl Timeline {
+    fn new() -> Self {
+        Self { origin: Instant::now(), marks: Default::default(), drift_us: AtomicU64::new(0) }
+    }
+
+    pub fn mark(&self, mark: Mark) {
+        let at = self.origin.elapsed().as_micros() as u64 + 1;
+        let _ = self.marks[mark as usize].compare_exchange(0, at, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    /// Time the action spent suspended in `drift()`, which counts as neither
+    /// queueing nor execution.
+    pub fn add_drift(&self, drift: Duration) {
+        self.drift_us.fetch_add(drift.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn at(&self, mark: Mark) -> Option<u64> {
+        Some(self.marks[mark as usize].load(Ordering::Relaxed)).filter(|at| *at > 0)
+    }
+
+    /// None unless a worker answered the request.
+    pub fn breakdown(&self) -> Option<Breakdown> {
+        let ms = |from: u64, to: u64| to.saturating_sub(from) as f64 / 1000.0;
+        let enqueued = self.at(Mark::Enqueued)?;
+        let end = self.at(Mark::ExecEnd)?;
+        let dequeued = self.at(Mark::Dequeued).unwrap_or(enqueued);
+        let start = self.at(Mark::ExecStart).unwrap_or(dequeued);
+        let drift_ms = self.drift_us.load(Ordering::Relaxed) as f64 / 1000.0;
+        let serialize_ms = self.at(Mark::SerializeStart).zip(self.at(Mark::SerializeEnd)).map(|(from, to)| ms(from, to));
+        Some(Breakdown { queue_ms: ms(enqueued, dequeued), exec_ms: (ms(start, end) - drift_ms).max(0.0), serialize_ms })
+    }
+}
+
+/// Runs `fut` with a timeline for its request, unless one is already
+/// being kept on this task.
+pub async fn scope<F: Future>(fut: F) -> F::Output {
+    if current().is_some() {
+        fut.await
+    } else {
+        TIMELINE.scope(Arc::new(Timeline::new()), fut).await
+    }
+}
+
+/// The timeline of the request on this task, if one is kept.
+pub fn current() -> Option<Arc<Timeline>> {
+    TIMELINE.try_with(Arc::clone).ok()
+}
+
+/// Sets `mark` on the request on this task, if a timeline is kept.
+pub fn mark(mark: Mark) {
+    let _ = TIMELINE.try_with(|timeline| timeline.mark(mark));
+}
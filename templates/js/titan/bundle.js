@@ -83,12 +83,13 @@ export async function bundleFile(options) {
         outfile,
         format = 'iife',
         minify = false,
-        sourcemap = false,
+        sourcemap = 'external', // Lets the server map stack traces back to app/ sources
         platform = 'neutral',
         globalName = '__titan_exports',
         target = 'es2020',
         banner = {},
-        footer = {}
+        footer = {},
+        ssr = false
     } = options;
 
     // Validate entry point exists
@@ -101,7 +102,7 @@ export async function bundleFile(options) {
     try {
         // Run esbuild with error logging enabled
         const result = await esbuild.build({
-            entryPoints: [entryPoint],
+            ...(ssr ? ssrOptions(entryPoint) : { entryPoints: [entryPoint] }),
             bundle: true,
             outfile,
             format,
@@ -149,6 +150,54 @@ export async function bundleFile(options) {
     }
 }
 
+/**
+ * Build options for a .jsx/.tsx action: the action is bundled together with
+ * React's web-stream renderer, which res.renderStream() picks up from the
+ * `__titan_render` export
+ * @param {string} entryPoint - The action's source
+ * @returns {Object}
+ */
+function ssrOptions(entryPoint) {
+    const spec = JSON.stringify('./' + path.basename(entryPoint));
+    return {
+        stdin: {
+            contents: [
+                `import * as action from ${spec};`,
+                `import { renderToReadableStream } from "react-dom/server";`,
+                `export * from ${spec};`,
+                `export default action.default;`,
+                `export const __titan_render = renderToReadableStream;`
+            ].join('\n'),
+            resolveDir: path.dirname(entryPoint),
+            sourcefile: path.basename(entryPoint) + '.ssr.js',
+            loader: 'js'
+        },
+        jsx: 'automatic',
+        // The web build of react-dom/server, not the Node one
+        conditions: ['worker', 'browser'],
+        define: { 'process.env.NODE_ENV': JSON.stringify(process.env.NODE_ENV || 'production') }
+    };
+}
+
+/**
+ * Recursively lists action sources as posix paths relative to `dir`
+ * @param {string} dir - Actions directory
+ * @param {string} [prefix] - Path of `dir` below the actions root
+ * @returns {string[]}
+ */
+function listActionFiles(dir, prefix = '') {
+    const files = [];
+    for (const entry of fs.readdirSync(dir, { withFileTypes: true })) {
+        const rel = prefix + entry.name;
+        if (entry.isDirectory()) {
+            files.push(...listActionFiles(path.join(dir, entry.name), rel + '/'));
+        } else if (/\.(js|ts|jsx|tsx)$/.test(entry.name) && !entry.name.endsWith('.d.ts')) {
+            files.push(rel);
+        }
+    }
+    return files;
+}
+
 /**
  * Main bundle function - scans app/actions and bundles all files
  * RULE: This function handles ALL esbuild errors and prints error boxes directly
@@ -166,23 +215,27 @@ export async function bundle() {
     }
     await fs.promises.mkdir(bundleDir, { recursive: true });
 
+    await bundleMiddleware(root, bundleDir);
+    await bundleJobs(root, bundleDir);
+    await bundleTasks(root, bundleDir);
+
     // Check if actions directory exists
     if (!fs.existsSync(actionsDir)) {
         return; // No actions to bundle
     }
 
-    // Get all JS/TS files in actions directory
-    const files = fs.readdirSync(actionsDir).filter(f =>
-        (f.endsWith('.js') || f.endsWith('.ts')) && !f.endsWith('.d.ts')
-    );
+    // Get all JS/TS files in actions directory, nested ones included
+    const files = listActionFiles(actionsDir);
 
     if (files.length === 0) {
         return; // No action files
     }
 
-    // Bundle each action file
+    // Bundle each action file. Nested actions are named by their relative path
+    // (users/[id]/get), which the server's file router maps to a route.
     for (const file of files) {
-        const actionName = path.basename(file, path.extname(file));
+        const actionName = file.slice(0, -path.extname(file).length);
+        const exportName = path.posix.basename(actionName);
         const entryPoint = path.join(actionsDir, file);
         const outfile = path.join(bundleDir, actionName + ".jsbundle");
 
@@ -195,7 +248,7 @@ export async function bundle() {
                 platform: 'neutral',
                 target: 'es2020',
                 minify: false,
-                sourcemap: false,
+                ssr: /\.(jsx|tsx)$/.test(file),
                 banner: {
                     js: "var Titan = t;"
                 },
@@ -203,62 +256,236 @@ export async function bundle() {
                     js: `
 (function () {
   const fn =
-    __titan_exports["${actionName}"] ||
+    __titan_exports["${exportName}"] ||
     __titan_exports.default;
 
   if (typeof fn !== "function") {
     throw new Error("[Titan] Action '${actionName}' not found or not a function");
   }
 
+  const action = globalThis.defineAction(fn, __titan_exports.schema, __titan_exports.config);
+  if (__titan_exports.__titan_render) action.__titan_render = __titan_exports.__titan_render;
+  globalThis["${actionName}"] = action;
+})();
+`
+                }
+            });
+        } catch (error) {
+            reportBundleError(error, entryPoint);
+
+            // RULE: Throw special error to signal bundle failure
+            throw new Error('__TITAN_BUNDLE_FAILED__');
+        }
+    }
+}
+
+/**
+ * Bundles app/middleware.{js,ts}, if present. Its default export (one function
+ * or an array of them) runs in the worker before every action; its other
+ * exports are the named middleware route groups can add.
+ * @param {string} root - Project root
+ * @param {string} bundleDir - Output directory shared with the actions
+ * @returns {Promise<void>}
+ */
+async function bundleMiddleware(root, bundleDir) {
+    const entryPoint = ['middleware.ts', 'middleware.js']
+        .map(f => path.join(root, 'app', f))
+        .find(f => fs.existsSync(f));
+    if (!entryPoint) return;
+
+    try {
+        await bundleFile({
+            entryPoint,
+            outfile: path.join(bundleDir, '__titan_middleware.jsbundle'),
+            format: 'iife',
+            globalName: '__titan_exports',
+            platform: 'neutral',
+            target: 'es2020',
+            banner: {
+                js: "var Titan = t;"
+            },
+            footer: {
+                js: `
+(function () {
+  const chain = __titan_exports.default || __titan_exports.middleware || [];
+  globalThis.__titan_middleware = (Array.isArray(chain) ? chain : [chain])
+    .filter(fn => typeof fn === "function");
+  globalThis.__titan_named_middleware = Object.fromEntries(Object.entries(__titan_exports)
+    .filter(([name, fn]) => name !== "default" && name !== "middleware" && typeof fn === "function"));
+})();
+`
+            }
+        });
+    } catch (error) {
+        reportBundleError(error, entryPoint);
+        throw new Error('__TITAN_BUNDLE_FAILED__');
+    }
+}
+
+/**
+ * Bundles each file in app/jobs as an action named `__titan_job_<name>` and
+ * writes `__titan_jobs.json`, from which the server schedules them. A job
+ * exports its cron expression as a string literal (`export const schedule =
+ * "0 * * * *"`), which is read from the source, and optionally `jitterMs`.
+ * @param {string} root - Project root
+ * @param {string} bundleDir - Output directory shared with the actions
+ * @returns {Promise<void>}
+ */
+async function bundleJobs(root, bundleDir) {
+    const jobsDir = path.join(root, 'app', 'jobs');
+    if (!fs.existsSync(jobsDir)) return;
+
+    const manifest = [];
+    const files = fs.readdirSync(jobsDir)
+        .filter(f => (f.endsWith('.js') || f.endsWith('.ts')) && !f.endsWith('.d.ts'));
+
+    for (const file of files) {
+        const name = path.basename(file, path.extname(file));
+        const entryPoint = path.join(jobsDir, file);
+        const source = fs.readFileSync(entryPoint, 'utf-8');
+        const schedule = source.match(/export\s+const\s+schedule\s*=\s*(["'`])(.+?)\1/);
+        if (!schedule) {
+            console.warn(`[Titan] Skipping job '${name}': no \`export const schedule = "<cron>"\` found`);
+            continue;
+        }
+        const jitter = source.match(/export\s+const\s+jitterMs\s*=\s*(\d+)/);
+        const actionName = `__titan_job_${name}`;
+
+        try {
+            await bundleFile({
+                entryPoint,
+                outfile: path.join(bundleDir, actionName + '.jsbundle'),
+                format: 'iife',
+                globalName: '__titan_exports',
+                platform: 'neutral',
+                target: 'es2020',
+                banner: {
+                    js: "var Titan = t;"
+                },
+                footer: {
+                    js: `
+(function () {
+  const fn = __titan_exports.default || __titan_exports.run || __titan_exports["${name}"];
+
+  if (typeof fn !== "function") {
+    throw new Error("[Titan] Job '${name}' has no default export or run() function");
+  }
+
   globalThis["${actionName}"] = globalThis.defineAction(fn);
 })();
 `
                 }
             });
         } catch (error) {
-            // RULE: Handle esbuild errors HERE and print error boxes
-            if (error.isBundleError && error.errors && error.errors.length > 0) {
-                // Print error box for each esbuild error
-                console.error(); // Empty line for spacing
+            reportBundleError(error, entryPoint);
+            throw new Error('__TITAN_BUNDLE_FAILED__');
+        }
 
-                const titanVersion = getTitanVersion();
+        manifest.push({ name, schedule: schedule[2], jitter_ms: jitter ? Number(jitter[1]) : 0 });
+    }
 
-                for (let i = 0; i < error.errors.length; i++) {
-                    const esbuildError = error.errors[i];
-                    const errorInfo = parseEsbuildError(esbuildError);
+    fs.writeFileSync(path.join(bundleDir, '__titan_jobs.json'), JSON.stringify(manifest, null, 2));
+}
 
-                    // Add error number to title if multiple errors
-                    if (error.errors.length > 1) {
-                        errorInfo.title = `Build Error ${i + 1}/${error.errors.length}`;
-                    }
+/**
+ * Bundles each file in app/tasks as an action named `__titan_task_<name>`.
+ * Its default export is called with the payload given to `tasks.enqueue()`
+ * and `{ id, attempt }`.
+ * @param {string} root - Project root
+ * @param {string} bundleDir - Output directory shared with the actions
+ * @returns {Promise<void>}
+ */
+async function bundleTasks(root, bundleDir) {
+    const tasksDir = path.join(root, 'app', 'tasks');
+    if (!fs.existsSync(tasksDir)) return;
 
-                    // Add Titan version
-                    errorInfo.titanVersion = titanVersion;
+    const files = fs.readdirSync(tasksDir)
+        .filter(f => (f.endsWith('.js') || f.endsWith('.ts')) && !f.endsWith('.d.ts'));
 
-                    // Print the error box
-                    console.error(renderErrorBox(errorInfo));
+    for (const file of files) {
+        const name = path.basename(file, path.extname(file));
+        const entryPoint = path.join(tasksDir, file);
+        const actionName = `__titan_task_${name}`;
 
-                    if (i < error.errors.length - 1) {
-                        console.error(); // Empty line between errors
-                    }
+        try {
+            await bundleFile({
+                entryPoint,
+                outfile: path.join(bundleDir, actionName + '.jsbundle'),
+                format: 'iife',
+                globalName: '__titan_exports',
+                platform: 'neutral',
+                target: 'es2020',
+                banner: {
+                    js: "var Titan = t;"
+                },
+                footer: {
+                    js: `
+(function () {
+  const fn = __titan_exports.default || __titan_exports.run || __titan_exports["${name}"];
+
+  if (typeof fn !== "function") {
+    throw new Error("[Titan] Task '${name}' has no default export or run() function");
+  }
+
+  globalThis["${actionName}"] = globalThis.defineAction((req) => fn(
+    req.json(),
+    { id: req.headers["x-titan-task-id"], attempt: Number(req.headers["x-titan-task-attempt"]) }
+  ));
+})();
+`
                 }
+            });
+        } catch (error) {
+            reportBundleError(error, entryPoint);
+            throw new Error('__TITAN_BUNDLE_FAILED__');
+        }
+    }
+}
+
+/**
+ * Prints error boxes for a failed bundle
+ * @param {Error} error - Error thrown by bundleFile
+ * @param {string} entryPoint - File that was being bundled
+ */
+function reportBundleError(error, entryPoint) {
+    // RULE: Handle esbuild errors HERE and print error boxes
+    if (error.isBundleError && error.errors && error.errors.length > 0) {
+        // Print error box for each esbuild error
+        console.error(); // Empty line for spacing
 
-                console.error(); // Empty line after all errors
-            } else {
-                // Other errors
-                console.error();
-                const errorInfo = {
-                    title: 'Build Error',
-                    file: entryPoint,
-                    message: error.message || 'Unknown error',
-                    titanVersion: getTitanVersion()
-                };
-                console.error(renderErrorBox(errorInfo));
-                console.error();
+        const titanVersion = getTitanVersion();
+
+        for (let i = 0; i < error.errors.length; i++) {
+            const esbuildError = error.errors[i];
+            const errorInfo = parseEsbuildError(esbuildError);
+
+            // Add error number to title if multiple errors
+            if (error.errors.length > 1) {
+                errorInfo.title = `Build Error ${i + 1}/${error.errors.length}`;
             }
 
-            // RULE: Throw special error to signal bundle failure
-            throw new Error('__TITAN_BUNDLE_FAILED__');
+            // Add Titan version
+            errorInfo.titanVersion = titanVersion;
+
+            // Print the error box
+            console.error(renderErrorBox(errorInfo));
+
+            if (i < error.errors.length - 1) {
+                console.error(); // Empty line between errors
+            }
         }
+
+        console.error(); // Empty line after all errors
+    } else {
+        // Other errors
+        console.error();
+        const errorInfo = {
+            title: 'Build Error',
+            file: entryPoint,
+            message: error.message || 'Unknown error',
+            titanVersion: getTitanVersion()
+        };
+        console.error(renderErrorBox(errorInfo));
+        console.error();
     }
-}
\ No newline at end of file
+}
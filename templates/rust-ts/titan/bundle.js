@@ -49,6 +49,8 @@ async function bundleJs(actionsDir, outDir) {
       platform: "neutral",
       target: "es2020",
       logLevel: "silent",
+      // Lets the server map stack traces back to app/ sources
+      sourcemap: "external",
       banner: {
         js: "const defineAction = (fn) => fn; const Titan = t;"
       },
@@ -4,6 +4,12 @@ use std::path::{Path, PathBuf};
 use serde::Deserialize;
 use serde_json::Value;
 
+use crate::routing::Routing;
+
+/// Bundle of app/middleware.{js,ts}. It sits with the actions so every isolate
+/// loads it, but registers a chain rather than an action.
+pub const MIDDLEWARE_BUNDLE: &str = "__titan_middleware";
+
 /// Route configuration (loaded from routes.json)
 #[derive(Debug, Deserialize, Clone)]
 pub struct RouteVal {
@@ -84,15 +90,21 @@ pub fn match_dynamic_route(
     method: &str,
     path: &str,
     routes: &[DynamicRoute],
+    routing: &Routing,
 ) -> Option<(String, HashMap<String, String>)> {
     let path_segments: Vec<&str> =
         path.trim_matches('/').split('/').collect();
+    let slash = path.len() > 1 && path.ends_with('/');
 
     for route in routes {
         if route.method != method {
             continue;
         }
 
+        if routing.strict_slash() && slash != (route.pattern.len() > 1 && route.pattern.ends_with('/')) {
+            continue;
+        }
+
         let pattern_segments: Vec<&str> =
             route.pattern.trim_matches('/').split('/').collect();
 
@@ -124,7 +136,7 @@ pub fn match_dynamic_route(
                 }
 
                 params.insert(name.to_string(), (*val).to_string());
-            } else if pat != val {
+            } else if !routing.same_segment(pat, val) {
                 matched = false;
                 break;
             }
@@ -154,24 +166,48 @@ pub fn scan_actions(root: &PathBuf) -> HashMap<String, PathBuf> {
         }
     };
 
-    // Scanning actions
-    if let Ok(entries) = std::fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if !path.is_file() { continue; }
-            
-            let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
-            if ext != "js" && ext != "jsbundle" {
-                continue;
+    scan_dir(&dir, "", &mut map);
+
+    // Actions that were never bundled load as ES modules
+    let sources = root.join("app").join("actions");
+    if sources.is_dir() && sources != dir {
+        let mut unbundled = HashMap::new();
+        scan_dir(&sources, "", &mut unbundled);
+        for (name, path) in unbundled {
+            if path.extension().is_some_and(|ext| ext == "ts" || ext == "mjs") {
+                map.entry(name).or_insert(path);
             }
-            
-            let file_stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
-            if file_stem.is_empty() { continue; }
-            
-            // Found action
-            map.insert(file_stem.to_string(), path);
         }
     }
-    
     map
-}
\ No newline at end of file
+}
+
+/// Nested actions are named by their path relative to the actions directory
+/// (`users/[id]/get`), which is what the file router matches on.
+fn scan_dir(dir: &Path, prefix: &str, map: &mut HashMap<String, PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+
+        if path.is_dir() {
+            scan_dir(&path, &format!("{}{}/", prefix, name), map);
+            continue;
+        }
+
+        let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
+        if !matches!(ext, "js" | "mjs" | "jsbundle") && (ext != "ts" || name.ends_with(".d.ts")) {
+            continue;
+        }
+
+        let file_stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        if file_stem.is_empty() { continue; }
+
+        // Found action; a bundle wins over the source it was built from
+        let key = format!("{}{}", prefix, file_stem);
+        if matches!(ext, "ts" | "mjs") && map.contains_key(&key) { continue; }
+        map.insert(key, path);
+    }
+}
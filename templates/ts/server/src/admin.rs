@@ -0,0 +1,279 @@
+//! The admin API: runtime controls on a listener of their own, never on the
+//! public one. It toggles drain mode, changes the log level, purges the
+//! response cache, reports on the worker pools, serves CPU profiles and heap
+//! snapshots and reloads the server, and serves a live dashboard of them.
+
+use axum::{
+    Json, Router,
+    extract::{Query, Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{
+        Html, IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{get, post},
+};
+use serde_json::{Value, json};
+use futures_util::Stream;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::cache::ResponseCache;
+use crate::dashboard::{self, Sampler};
+use crate::profiler;
+use crate::restart::{Bind, Listener};
+use crate::runtime::RuntimeManager;
+
+/// The `admin` block of titan.config: a `port` (bound on `host`, default
+/// 127.0.0.1), or a Unix `socket` with its file `mode`. With `api_key`, or
+/// TITAN_ADMIN_KEY, every call must send that key.
+pub struct Admin {
+    pub bind: Bind,
+    api_key: Option<Arc<str>>,
+}
+
+struct Apps {
+    // Each app's project root and pool, the main app first
+    apps: Vec<(PathBuf, Arc<RuntimeManager>)>,
+    api_key: Option<Arc<str>>,
+}
+
+impl Admin {
+    pub fn from_config(config: &Value, root: &Path) -> Result<Option<Self>, String> {
+        let bind = match config {
+            Value::Null | Value::Bool(false) => return Ok(None),
+            Value::Number(_) => Bind::Addr(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port(config)?)),
+            Value::Object(_) => match config["socket"].as_str() {
+                Some(path) => {
+                    let mode = config["mode"].as_u64().map(|m| m as u32).or_else(|| config["mode"].as_str().and_then(|m| u32::from_str_radix(m, 8).ok()));
+                    Bind::Unix { path: root.join(path), mode }
+                }
+                None => {
+                    let host: IpAddr = match config["host"].as_str() {
+                        Some(host) => host.parse().map_err(|_| format!("admin: host \"{}\" is not an IP address", host))?,
+                        None => Ipv4Addr::LOCALHOST.into(),
+                    };
+                    Bind::Addr(SocketAddr::new(host, port(&config["port"])?))
+                }
+            },
+            _ => return Err("admin must be a port or { port, host, socket, api_key }".to_string()),
+        };
+        let api_key = std::env::var("TITAN_ADMIN_KEY")
+            .ok()
+            .or_else(|| config["api_key"].as_str().map(str::to_string))
+            .filter(|key| !key.is_empty())
+            .map(Arc::from);
+        Ok(Some(Self { bind, api_key }))
+    }
+
+    /// Serves the API on `listener` until `shutdown` resolves.
+    pub async fn serve(self, listener: Listener, apps: Vec<(PathBuf, Arc<RuntimeManager>)>, shutdown: impl Future<Output = ()> + Send + 'static) {
+        let state = Arc::new(Apps { apps, api_key: self.api_key });
+        let mut app = Router::new()
+            .route("/stats", get(stats_route))
+            .route("/drain", post(drain_route).delete(undrain_route))
+            .route("/log-level", get(log_level_route).put(set_log_level_route))
+            .route("/cache/purge", post(cache_purge_route))
+            .route("/reload", post(reload_route))
+            .route("/dashboard/events", get(dashboard_events_route));
+        // Profiles show the code's structure and snapshots hold the whole
+        // heap, secrets included, so they are only ever served here
+        if crate::profiler::enabled() {
+            app = app.route("/profile", get(profile_route));
+        }
+        if crate::heap::enabled() {
+            app = app.route("/heap-snapshot", post(heap_snapshot_route));
+        }
+        let app = app
+            .layer(middleware::from_fn_with_state(state.clone(), authorize))
+            // The page holds no data, so it loads without the key; its stream needs it
+            .route("/dashboard", get(|| async { Html(dashboard::PAGE) }))
+            .with_state(state);
+        let served = match listener {
+            Listener::Tcp(listener) => axum::serve(listener, app).with_graceful_shutdown(shutdown).await,
+            #[cfg(unix)]
+            Listener::Unix(listener) => axum::serve(listener, app).with_graceful_shutdown(shutdown).await,
+        };
+        if let Err(e) = served {
+            tracing::error!("Admin API stopped: {}", e);
+        }
+    }
+}
+
+fn port(value: &Value) -> Result<u16, String> {
+    value.as_u64().and_then(|port| u16::try_from(port).ok()).filter(|port| *port > 0).ok_or_else(|| "admin: port must be between 1 and 65535".to_string())
+}
+
+async fn authorize(State(state): State<Arc<Apps>>, req: Request, next: Next) -> Response {
+    if let Some(key) = &state.api_key
+        && !crate::middleware::has_api_key(req.headers(), key)
+    {
+        return (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer")], "Unauthorized").into_response();
+    }
+    next.run(req).await
+}
+
+/// Readiness, workers and queue of every app's pool.
+async fn stats_route(State(state): State<Arc<Apps>>) -> Json<Value> {
+    let apps: Vec<Value> = state
+        .apps
+        .iter()
+        .map(|(root, runtime)| {
+            let readiness = runtime.readiness();
+            json!({
+                "root": root,
+                "ready": readiness.is_ok(),
+                "reason": readiness.err(),
+                "draining": runtime.draining(),
+                "workers": runtime.worker_status(),
+                "queue": runtime.queue_status(),
+            })
+        })
+        .collect();
+    Json(json!({ "pid": std::process::id(), "log_level": crate::logging::level(), "apps": apps }))
+}
+
+async fn drain_route(State(state): State<Arc<Apps>>) -> Json<Value> {
+    set_draining(&state, true)
+}
+
+async fn undrain_route(State(state): State<Arc<Apps>>) -> Json<Value> {
+    set_draining(&state, false)
+}
+
+fn set_draining(state: &Apps, on: bool) -> Json<Value> {
+    let mut was = false;
+    for (_, runtime) in &state.apps {
+        was |= runtime.set_draining(on);
+    }
+    if was != on {
+        tracing::info!("Drain mode {}", if on { "on: /readyz reports draining" } else { "off" });
+    }
+    Json(json!({ "draining": on, "was": was }))
+}
+
+async fn log_level_route() -> Json<Value> {
+    Json(json!({ "level": crate::logging::level() }))
+}
+
+async fn set_log_level_route(Json(body): Json<Value>) -> Response {
+    let Some(level) = body["level"].as_str() else {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": "send { \"level\": \"debug\" }" }))).into_response();
+    };
+    match crate::logging::set_level(level) {
+        Ok(previous) => {
+            tracing::warn!("Log level changed from {} to {}", previous, level);
+            Json(json!({ "level": level.to_ascii_lowercase(), "previous": previous })).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response(),
+    }
+}
+
+/// Drops the cached responses of `{ "target": path or tag }`, or all of them.
+async fn cache_purge_route(body: Option<Json<Value>>) -> Response {
+    let Some(cache) = ResponseCache::get() else {
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": "no response cache is configured" }))).into_response();
+    };
+    let target = body.as_ref().and_then(|Json(body)| body["target"].as_str());
+    let purged = match target {
+        Some(target) => cache.purge(target),
+        None => cache.clear(),
+    };
+    tracing::info!(purged, "Response cache purged{}", target.map(|t| format!(" of {}", t)).unwrap_or_default());
+    Json(json!({ "purged": purged })).into_response()
+}
+
+/// A dashboard sample every second, as server-sent events.
+async fn dashboard_events_route(State(state): State<Arc<Apps>>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let mut ticks = tokio::time::interval(Duration::from_secs(1));
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let sampler = Sampler::new(state.apps.clone());
+    let events = futures_util::stream::unfold((sampler, ticks), |(mut sampler, mut ticks)| async move {
+        ticks.tick().await;
+        let event = Event::default().data(sampler.sample().to_string());
+        Some((Ok(event), (sampler, ticks)))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// CPU profile of one app's workers (`?app=N`), folded for flame graph
+/// tools or, with `?format=json`, summed per action. `?seconds=N` samples
+/// the next N seconds; otherwise it covers everything since the previous call.
+async fn profile_route(State(state): State<Arc<Apps>>, Query(query): Query<HashMap<String, String>>) -> Response {
+    let runtime = match pool(&state, &query) {
+        Ok(runtime) => runtime,
+        Err(e) => return e.into_response(),
+    };
+    let timeout = Duration::from_secs(5);
+    if let Some(seconds) = query.get("seconds").and_then(|s| s.parse::<u64>().ok()) {
+        runtime.collect_profiles(timeout).await;
+        tokio::time::sleep(Duration::from_secs(seconds.min(300))).await;
+    }
+    let (mut stacks, workers) = runtime.collect_profiles(timeout).await;
+    if let Some(action) = query.get("action") {
+        stacks.retain(|stack, _| stack.split(';').next() == Some(action.as_str()));
+    }
+    match query.get("format").map(String::as_str) {
+        Some("json") => Json(json!({
+            "interval_us": profiler::interval_us(),
+            "workers": workers,
+            "actions": profiler::summary(&stacks),
+        }))
+        .into_response(),
+        _ => ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], profiler::folded(&stacks)).into_response(),
+    }
+}
+
+/// Writes a heap snapshot of one worker (`?worker=N`, default 0) of one app
+/// (`?app=N`, in `/stats` order, the main app by default) for Chrome
+/// DevTools' Memory tab, and says where it went.
+async fn heap_snapshot_route(State(state): State<Arc<Apps>>, Query(query): Query<HashMap<String, String>>) -> Response {
+    let runtime = match pool(&state, &query) {
+        Ok(runtime) => runtime,
+        Err(e) => return e.into_response(),
+    };
+    let worker = query.get("worker").and_then(|w| w.parse::<usize>().ok()).unwrap_or(0);
+    let Some(path) = crate::heap::next_path(worker) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let started = Instant::now();
+    match runtime.heap_snapshot(worker, path.clone()).await {
+        Ok(bytes) => {
+            tracing::info!(worker, bytes, "Heap snapshot written to {}", path.display());
+            Json(json!({
+                "worker": worker,
+                "path": path,
+                "bytes": bytes,
+                "duration_ms": started.elapsed().as_secs_f64() * 1000.0,
+            }))
+            .into_response()
+        }
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": e }))).into_response(),
+    }
+}
+
+// The pool of the app `?app=N` picks, the main app's by default
+fn pool<'a>(state: &'a Apps, query: &HashMap<String, String>) -> Result<&'a Arc<RuntimeManager>, (StatusCode, Json<Value>)> {
+    let index = match query.get("app") {
+        None => 0,
+        Some(app) => app.parse::<usize>().map_err(|_| (StatusCode::BAD_REQUEST, Json(json!({ "error": "app must be an index into /stats apps" }))))?,
+    };
+    match state.apps.get(index) {
+        Some((_, runtime)) => Ok(runtime),
+        None => Err((StatusCode::NOT_FOUND, Json(json!({ "error": format!("no app {}", index) })))),
+    }
+}
+
+/// A zero-downtime restart that reads titan.config and the actions again.
+async fn reload_route() -> Response {
+    match crate::restart::reload() {
+        Ok(()) => (StatusCode::ACCEPTED, Json(json!({ "reloading": true }))).into_response(),
+        Err(e) => (StatusCode::NOT_IMPLEMENTED, Json(json!({ "error": e }))).into_response(),
+    }
+}
@@ -0,0 +1,112 @@
+use crossbeam::channel::Sender;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::broadcast;
+
+use crate::metrics;
+use crate::runtime::WorkerCommand;
+
+// A worker this many messages behind starts missing them
+const CAPACITY: usize = 1024;
+
+static BUS: OnceLock<Bus> = OnceLock::new();
+
+/// A message published with `bus.publish()`. The payload is JSON text.
+pub struct BusMessage {
+    pub topic: String,
+    pub payload: String,
+}
+
+/// In-process pub/sub behind the `bus` global. Every worker isolate has a
+/// bridge task that forwards messages on the topics it subscribes to into
+/// its command channel, so handlers run on the isolate's own event loop.
+struct Bus {
+    tx: broadcast::Sender<Arc<BusMessage>>,
+    published: AtomicU64,
+    lagged: AtomicU64,
+}
+
+impl Bus {
+    fn get() -> &'static Self {
+        BUS.get_or_init(|| Self {
+            tx: broadcast::channel(CAPACITY).0,
+            published: AtomicU64::new(0),
+            lagged: AtomicU64::new(0),
+        })
+    }
+}
+
+/// Topics an isolate has subscriptions on, with how many of each.
+#[derive(Default)]
+pub struct Topics(Mutex<HashMap<String, usize>>);
+
+impl Topics {
+    pub fn add(&self, topic: &str) {
+        *self.0.lock().unwrap().entry(topic.to_string()).or_default() += 1;
+    }
+
+    pub fn remove(&self, topic: &str) {
+        let mut topics = self.0.lock().unwrap();
+        if let Some(count) = topics.get_mut(topic) {
+            *count -= 1;
+            if *count == 0 {
+                topics.remove(topic);
+            }
+        }
+    }
+
+    fn contains(&self, topic: &str) -> bool {
+        self.0.lock().unwrap().contains_key(topic)
+    }
+}
+
+/// Sends `payload` to every subscriber of `topic` on any worker.
+pub fn publish(topic: String, payload: String) {
+    let bus = Bus::get();
+    bus.published.fetch_add(1, Ordering::Relaxed);
+    // No receivers just means no worker is up yet
+    let _ = bus.tx.send(Arc::new(BusMessage { topic, payload }));
+}
+
+/// Starts forwarding messages to an isolate's worker. The bridge stops once
+/// the returned topics are dropped along with the isolate.
+pub fn bridge(handle: &tokio::runtime::Handle, worker_tx: Sender<WorkerCommand>) -> Arc<Topics> {
+    let topics = Arc::new(Topics::default());
+    let weak = Arc::downgrade(&topics);
+    let mut rx = Bus::get().tx.subscribe();
+    handle.spawn(async move {
+        loop {
+            let message = match rx.recv().await {
+                Ok(message) => message,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    Bus::get().lagged.fetch_add(missed, Ordering::Relaxed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            let Some(topics) = weak.upgrade() else {
+                return;
+            };
+            if topics.contains(&message.topic) && worker_tx.send(WorkerCommand::BusMessage { message }).is_err() {
+                return;
+            }
+        }
+    });
+    topics
+}
+
+/// Prometheus text exposition of the bus counters.
+pub fn render(out: &mut String) {
+    let Some(bus) = BUS.get() else {
+        return;
+    };
+    for (name, help, value) in [
+        ("titan_bus_published_total", "Messages published with bus.publish().", &bus.published),
+        ("titan_bus_lagged_total", "Messages a worker missed because it fell too far behind.", &bus.lagged),
+    ] {
+        metrics::header(out, name, "counter", help);
+        let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,777 @@
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+pub const FILE: &str = "titan.config.toml";
+
+// Shorter secrets would be scrubbed out of every other log line
+const MIN_REDACTED_LEN: usize = 4;
+const REDACTED: &str = "[redacted]";
+
+static CONFIG: OnceLock<AppConfig> = OnceLock::new();
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    /// From the environment, a comma-separated list of strings.
+    Array,
+    Url,
+}
+
+impl Kind {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "string" => Kind::String,
+            "integer" => Kind::Integer,
+            "float" => Kind::Float,
+            "boolean" => Kind::Boolean,
+            "array" => Kind::Array,
+            "url" => Kind::Url,
+            _ => return None,
+        })
+    }
+
+    fn of(value: &Value) -> Option<Self> {
+        Some(match value {
+            Value::String(_) => Kind::String,
+            Value::Number(n) if n.is_i64() => Kind::Integer,
+            Value::Number(_) => Kind::Float,
+            Value::Bool(_) => Kind::Boolean,
+            Value::Array(_) => Kind::Array,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::String => "a string",
+            Kind::Integer => "an integer",
+            Kind::Float => "a number",
+            Kind::Boolean => "a boolean",
+            Kind::Array => "an array",
+            Kind::Url => "a URL",
+        }
+    }
+
+    /// `value` as this kind, if it is one. Integers pass as floats.
+    fn check(self, value: Value) -> Option<Value> {
+        match (self, &value) {
+            (Kind::String, Value::String(_))
+            | (Kind::Integer, Value::Number(_))
+            | (Kind::Boolean, Value::Bool(_))
+            | (Kind::Array, Value::Array(_)) => (self != Kind::Integer || value.is_i64()).then_some(value),
+            (Kind::Float, Value::Number(n)) => n.as_f64().map(Value::from),
+            (Kind::Url, Value::String(s)) => reqwest::Url::parse(s).is_ok().then_some(value),
+            _ => None,
+        }
+    }
+
+    fn parse_env(self, raw: &str) -> Option<Value> {
+        match self {
+            Kind::String => Some(Value::from(raw)),
+            Kind::Integer => raw.trim().parse::<i64>().ok().map(Value::from),
+            Kind::Float => raw.trim().parse::<f64>().ok().map(Value::from),
+            Kind::Boolean => match raw.trim().to_ascii_lowercase().as_str() {
+                "true" | "1" | "yes" | "on" => Some(Value::Bool(true)),
+                "false" | "0" | "no" | "off" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            Kind::Array => Some(raw.split(',').map(str::trim).filter(|s| !s.is_empty()).map(Value::from).collect()),
+            Kind::Url => self.check(Value::from(raw.trim())),
+        }
+    }
+}
+
+/// One declared key.
+struct Field {
+    kind: Kind,
+    env: Option<String>,
+    default: Option<Value>,
+    required: bool,
+    secret: bool,
+    one_of: Option<Vec<Value>>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl Field {
+    fn from_table(key: &str, table: &Map<String, Value>, problems: &mut Vec<String>) -> Option<Self> {
+        for name in table.keys() {
+            if !matches!(name.as_str(), "type" | "env" | "default" | "required" | "secret" | "one_of" | "min" | "max") {
+                problems.push(format!("{}: unknown option '{}'", key, name));
+            }
+        }
+        let Some(kind) = table["type"].as_str().and_then(Kind::parse) else {
+            problems.push(format!("{}: type must be string, integer, float, boolean, array or url", key));
+            return None;
+        };
+        let default = match table.get("default").cloned() {
+            Some(value) => match kind.check(value) {
+                Some(value) => Some(value),
+                None => {
+                    problems.push(format!("{}: default is not {}", key, kind.name()));
+                    return None;
+                }
+            },
+            None => None,
+        };
+        Some(Field {
+            kind,
+            env: table.get("env").and_then(Value::as_str).map(str::to_string),
+            default,
+            required: table.get("required").and_then(Value::as_bool).unwrap_or(false),
+            secret: table.get("secret").and_then(Value::as_bool).unwrap_or(false),
+            one_of: table.get("one_of").and_then(Value::as_array).cloned(),
+            min: table.get("min").and_then(Value::as_f64),
+            max: table.get("max").and_then(Value::as_f64),
+        })
+    }
+
+    /// The value from the environment, or else the default.
+    fn resolve(&self, key: &str, problems: &mut Vec<String>) -> Option<Value> {
+        let from_env = self.env.as_ref().and_then(|name| Some((name, std::env::var(name).ok()?)));
+        let value = match from_env {
+            Some((name, raw)) => match self.kind.parse_env(&raw) {
+                Some(value) => Some(value),
+                None => {
+                    // The raw value may be a secret, so it stays out of the message
+                    problems.push(format!("{}: {} is not {}", key, name, self.kind.name()));
+                    return None;
+                }
+            },
+            None => self.default.clone(),
+        };
+        let Some(value) = value else {
+            if self.required {
+                match &self.env {
+                    Some(name) => problems.push(format!("{}: required; set {}", key, name)),
+                    None => problems.push(format!("{}: required but has no default", key)),
+                }
+            }
+            return None;
+        };
+        if let Some(allowed) = self.one_of.as_ref().filter(|allowed| !allowed.contains(&value)) {
+            let list: Vec<String> = allowed.iter().map(Value::to_string).collect();
+            problems.push(format!("{}: must be one of {}", key, list.join(", ")));
+        }
+        if let Some(n) = value.as_f64().or(value.as_str().map(|s| s.chars().count() as f64)) {
+            let what = if value.is_string() { "length " } else { "" };
+            if let Some(min) = self.min.filter(|min| n < *min) {
+                problems.push(format!("{}: {}must be at least {}", key, what, min));
+            }
+            if let Some(max) = self.max.filter(|max| n > *max) {
+                problems.push(format!("{}: {}must be at most {}", key, what, max));
+            }
+        }
+        Some(value)
+    }
+}
+
+/// Values of the keys declared in `[config]` of titan.config.toml, taken
+/// from the environment or their defaults.
+pub struct AppConfig {
+    /// Declared keys; None when one is optional and unset.
+    values: HashMap<String, Option<Value>>,
+    secrets: Vec<String>,
+}
+
+impl AppConfig {
+    /// Reads and validates the file at the project root. Every problem is
+    /// reported at once, so a deploy doesn't fail one key at a time.
+    pub fn load(root: &Path) -> Result<Self, String> {
+        let path = root.join(FILE);
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("{}: {}", FILE, e)),
+        };
+        Self::parse(&source)
+    }
+
+    fn parse(source: &str) -> Result<Self, String> {
+        let doc = parse_toml(source).map_err(|e| format!("{}: {}", FILE, e))?;
+
+        let mut problems = Vec::new();
+        let mut fields = Vec::new();
+        match doc.get("config") {
+            Some(Value::Object(table)) => collect_fields("", table, &mut fields, &mut problems),
+            Some(_) => problems.push("config must be a table".to_string()),
+            None => {}
+        }
+
+        let mut values = HashMap::new();
+        let mut secrets = Vec::new();
+        for (key, field) in fields {
+            let value = field.resolve(&key, &mut problems);
+            if field.secret {
+                secrets.extend(value.iter().flat_map(secret_strings));
+            }
+            values.insert(key, value);
+        }
+        if !problems.is_empty() {
+            return Err(format!("invalid {}:\n  {}", FILE, problems.join("\n  ")));
+        }
+        // Longest first, so a secret containing another is replaced whole
+        secrets.retain(|s| s.len() >= MIN_REDACTED_LEN);
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        secrets.dedup();
+        Ok(Self { values, secrets })
+    }
+
+    pub fn install(self) {
+        let _ = CONFIG.set(self);
+    }
+
+    fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut out = Cow::Borrowed(text);
+        for secret in &self.secrets {
+            if out.contains(secret.as_str()) {
+                out = Cow::Owned(out.replace(secret.as_str(), REDACTED));
+            }
+        }
+        out
+    }
+}
+
+// A table with a `type` declares the key it sits at; other tables group
+// keys under a dotted prefix, and plain values declare a key typed by them
+fn collect_fields(prefix: &str, table: &Map<String, Value>, fields: &mut Vec<(String, Field)>, problems: &mut Vec<String>) {
+    for (name, value) in table {
+        let key = if prefix.is_empty() { name.clone() } else { format!("{}.{}", prefix, name) };
+        match value {
+            Value::Object(inner) if inner.contains_key("type") => {
+                if let Some(field) = Field::from_table(&key, inner, problems) {
+                    fields.push((key, field));
+                }
+            }
+            Value::Object(inner) => collect_fields(&key, inner, fields, problems),
+            value => match Kind::of(value) {
+                Some(kind) => fields.push((key, Field {
+                    kind,
+                    env: None,
+                    default: Some(value.clone()),
+                    required: false,
+                    secret: false,
+                    one_of: None,
+                    min: None,
+                    max: None,
+                })),
+                None => problems.push(format!("{}: unsupported value", key)),
+            },
+        }
+    }
+}
+
+fn secret_strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(items) => items.iter().flat_map(secret_strings).collect(),
+        Value::Null => Vec::new(),
+        other => vec![other.to_string()],
+    }
+}
+
+/// A declared key: Some(None) when it is optional and unset, None when it
+/// isn't declared at all.
+pub fn get(key: &str) -> Option<Option<&'static Value>> {
+    CONFIG.get()?.values.get(key).map(Option::as_ref)
+}
+
+/// `text` with the value of every secret key replaced, for log lines.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    match CONFIG.get() {
+        Some(config) => config.redact(text),
+        None => Cow::Borrowed(text),
+    }
+}
+
+// TOML, the subset a config file needs: tables, dotted keys, strings,
+// numbers, booleans, arrays and inline tables. Dates and arrays of tables
+// are rejected.
+
+fn parse_toml(source: &str) -> Result<Map<String, Value>, String> {
+    let mut parser = Parser { chars: source.chars().collect(), pos: 0, line: 1 };
+    parser.document().map_err(|e| format!("line {}: {}", parser.line, e))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.bump() {
+            Some(found) if found == c => Ok(()),
+            Some(found) => Err(format!("expected '{}', found '{}'", c, found)),
+            None => Err(format!("expected '{}' at end of file", c)),
+        }
+    }
+
+    // Spaces and tabs only
+    fn skip_blank(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    // Newlines and comments too, as inside arrays
+    fn skip_space(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t' | '\r' | '\n') => {
+                    self.bump();
+                }
+                Some('#') => self.skip_comment(),
+                _ => return,
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while !matches!(self.peek(), None | Some('\n')) {
+            self.pos += 1;
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_blank();
+        if self.peek() == Some('#') {
+            self.skip_comment();
+        }
+        match self.peek() {
+            None | Some('\n') => Ok(()),
+            Some('\r') if self.chars.get(self.pos + 1) == Some(&'\n') => Ok(()),
+            Some(c) => Err(format!("unexpected '{}' after value", c)),
+        }
+    }
+
+    fn document(&mut self) -> Result<Map<String, Value>, String> {
+        let mut root = Map::new();
+        let mut current: Vec<String> = Vec::new();
+        loop {
+            self.skip_space();
+            match self.peek() {
+                None => return Ok(root),
+                Some('[') => {
+                    self.bump();
+                    if self.peek() == Some('[') {
+                        return Err("arrays of tables are not supported".to_string());
+                    }
+                    self.skip_blank();
+                    current = self.key()?;
+                    self.skip_blank();
+                    self.expect(']')?;
+                    table_at(&mut root, &current)?;
+                    self.end_of_line()?;
+                }
+                Some(_) => {
+                    let mut path = current.clone();
+                    path.extend(self.key()?);
+                    self.skip_blank();
+                    self.expect('=')?;
+                    self.skip_blank();
+                    let value = self.value()?;
+                    insert(&mut root, &path, value)?;
+                    self.end_of_line()?;
+                }
+            }
+        }
+    }
+
+    // A dotted key of bare and quoted parts
+    fn key(&mut self) -> Result<Vec<String>, String> {
+        let mut parts = Vec::new();
+        loop {
+            self.skip_blank();
+            let part = match self.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let start = self.pos;
+                    while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                        self.pos += 1;
+                    }
+                    if start == self.pos {
+                        return Err("expected a key".to_string());
+                    }
+                    self.chars[start..self.pos].iter().collect()
+                }
+            };
+            parts.push(part);
+            self.skip_blank();
+            if self.peek() != Some('.') {
+                return Ok(parts);
+            }
+            self.bump();
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => {
+                self.bump();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_space();
+                    if self.peek() == Some(']') {
+                        self.bump();
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_space();
+                    match self.bump() {
+                        Some(',') => {}
+                        Some(']') => return Ok(Value::Array(items)),
+                        _ => return Err("expected ',' or ']' in array".to_string()),
+                    }
+                }
+            }
+            Some('{') => {
+                self.bump();
+                let mut table = Map::new();
+                self.skip_blank();
+                if self.peek() == Some('}') {
+                    self.bump();
+                    return Ok(Value::Object(table));
+                }
+                loop {
+                    let path = self.key()?;
+                    self.skip_blank();
+                    self.expect('=')?;
+                    self.skip_blank();
+                    let value = self.value()?;
+                    insert(&mut table, &path, value)?;
+                    self.skip_blank();
+                    match self.bump() {
+                        Some(',') => self.skip_blank(),
+                        Some('}') => return Ok(Value::Object(table)),
+                        _ => return Err("expected ',' or '}' in inline table".to_string()),
+                    }
+                }
+            }
+            Some(_) => self.scalar(),
+            None => Err("expected a value".to_string()),
+        }
+    }
+
+    // Booleans and numbers
+    fn scalar(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.' | '_' | ':')) {
+            self.pos += 1;
+        }
+        let word: String = self.chars[start..self.pos].iter().collect();
+        if word.is_empty() {
+            return Err("expected a value".to_string());
+        }
+        match word.as_str() {
+            "true" => return Ok(Value::Bool(true)),
+            "false" => return Ok(Value::Bool(false)),
+            "inf" | "+inf" | "-inf" | "nan" | "+nan" | "-nan" => return Err("inf and nan are not supported".to_string()),
+            _ => {}
+        }
+        let digits = word.replace('_', "");
+        let (sign, unsigned) = match digits.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, digits.strip_prefix('+').unwrap_or(&digits)),
+        };
+        let radix = match unsigned.get(..2) {
+            Some("0x") => Some(16),
+            Some("0o") => Some(8),
+            Some("0b") => Some(2),
+            _ => None,
+        };
+        if let Some(radix) = radix {
+            return i64::from_str_radix(&unsigned[2..], radix).map(|n| Value::from(sign * n)).map_err(|_| format!("invalid number '{}'", word));
+        }
+        if let Ok(n) = digits.parse::<i64>() {
+            return Ok(Value::from(n));
+        }
+        if word.contains(':') || word.matches('-').count() > 1 {
+            return Err("dates are not supported".to_string());
+        }
+        digits.parse::<f64>().map(Value::from).map_err(|_| format!("invalid value '{}'", word))
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        let multiline = self.starts_with("\"\"\"");
+        if multiline {
+            self.pos += 3;
+            self.newline_after_delimiter();
+        } else {
+            self.bump();
+        }
+        let mut out = String::new();
+        loop {
+            if multiline && self.starts_with("\"\"\"") {
+                self.pos += 3;
+                return Ok(out);
+            }
+            match self.bump() {
+                None => return Err("unterminated string".to_string()),
+                Some('"') if !multiline => return Ok(out),
+                Some('\n') if !multiline => return Err("newline in string".to_string()),
+                Some('\\') => match self.bump() {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('r') => out.push('\r'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some(c @ ('u' | 'U')) => {
+                        let len = if c == 'u' { 4 } else { 8 };
+                        let hex: String = self.chars.get(self.pos..self.pos + len).unwrap_or_default().iter().collect();
+                        self.pos += len;
+                        let ch = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).ok_or("invalid unicode escape")?;
+                        out.push(ch);
+                    }
+                    // A trailing backslash joins the next non-blank line
+                    Some('\n' | '\r' | ' ' | '\t') if multiline => {
+                        while matches!(self.peek(), Some(' ' | '\t' | '\r' | '\n')) {
+                            self.bump();
+                        }
+                    }
+                    _ => return Err("invalid escape in string".to_string()),
+                },
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        let multiline = self.starts_with("'''");
+        if multiline {
+            self.pos += 3;
+            self.newline_after_delimiter();
+        } else {
+            self.bump();
+        }
+        let mut out = String::new();
+        loop {
+            if multiline && self.starts_with("'''") {
+                self.pos += 3;
+                return Ok(out);
+            }
+            match self.bump() {
+                None => return Err("unterminated string".to_string()),
+                Some('\'') if !multiline => return Ok(out),
+                Some('\n') if !multiline => return Err("newline in string".to_string()),
+                Some(c) => out.push(c),
+            }
+        }
+    }
+
+    // A newline straight after an opening `"""` isn't part of the string
+    fn newline_after_delimiter(&mut self) {
+        if self.starts_with("\r\n") {
+            self.pos += 1;
+        }
+        if self.peek() == Some('\n') {
+            self.bump();
+        }
+    }
+}
+
+fn table_at<'a>(root: &'a mut Map<String, Value>, path: &[String]) -> Result<&'a mut Map<String, Value>, String> {
+    let mut table = root;
+    for part in path {
+        let entry = table.entry(part.clone()).or_insert_with(|| Value::Object(Map::new()));
+        table = match entry {
+            Value::Object(inner) => inner,
+            _ => return Err(format!("'{}' is not a table", part)),
+        };
+    }
+    Ok(table)
+}
+
+fn insert(root: &mut Map<String, Value>, path: &[String], value: Value) -> Result<(), String> {
+    let (last, parents) = path.split_last().ok_or("expected a key")?;
+    let table = table_at(root, parents)?;
+    if table.contains_key(last) {
+        return Err(format!("'{}' is defined twice", path.join(".")));
+    }
+    table.insert(last.clone(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Every test sets its own variables, as the tests share the environment
+    fn set_env(name: &str, value: &str) {
+        unsafe { std::env::set_var(name, value) };
+    }
+
+    fn value(config: &AppConfig, key: &str) -> Option<Value> {
+        config.values.get(key).cloned().flatten()
+    }
+
+    #[test]
+    fn parses_toml() {
+        let doc = parse_toml(
+            r#"
+            # Comment
+            name = "titan" # trailing comment
+            "quoted key" = 'C:\path'
+            dotted.key = 0x1F
+            [server]
+            port = 3_000
+            ratio = -1.5e3
+            tags = [ "a",
+              "b", # inside an array
+            ]
+            limits = { burst = 10, nested.deep = true }
+            [server."tls"]
+            cert = """
+            line one \
+              joined
+            line two"""
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Value::Object(doc),
+            json!({
+                "name": "titan",
+                "quoted key": "C:\\path",
+                "dotted": { "key": 31 },
+                "server": {
+                    "port": 3000,
+                    "ratio": -1500.0,
+                    "tags": ["a", "b"],
+                    "limits": { "burst": 10, "nested": { "deep": true } },
+                    "tls": { "cert": "            line one joined\n            line two" },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_what_it_does_not_read() {
+        for (source, error) in [
+            ("a = 1\na = 2", "line 2: 'a' is defined twice"),
+            ("a = 1\n[a]", "line 2: 'a' is not a table"),
+            ("[[servers]]", "arrays of tables are not supported"),
+            ("at = 1979-05-27", "dates are not supported"),
+            ("x = inf", "inf and nan are not supported"),
+            ("s = \"open", "unterminated string"),
+            ("a = 1 b = 2", "unexpected 'b' after value"),
+            ("s = \"\\q\"", "invalid escape in string"),
+        ] {
+            let err = parse_toml(source).unwrap_err();
+            assert!(err.contains(error), "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn env_overrides_defaults() {
+        set_env("TITAN_TEST_PORT", " 8080 ");
+        set_env("TITAN_TEST_DEBUG", "yes");
+        set_env("TITAN_TEST_HOSTS", "a.example, b.example,,");
+        let config = AppConfig::parse(
+            r#"
+            [config]
+            plain = "fixed"
+            port = { type = "integer", env = "TITAN_TEST_PORT", default = 3000 }
+            fallback = { type = "integer", env = "TITAN_TEST_UNSET", default = 3000 }
+            debug = { type = "boolean", env = "TITAN_TEST_DEBUG", default = false }
+            hosts = { type = "array", env = "TITAN_TEST_HOSTS", default = ["localhost"] }
+            optional = { type = "string", env = "TITAN_TEST_UNSET" }
+            [config.db]
+            pool = { type = "integer", env = "TITAN_TEST_PORT", max = 10000 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(value(&config, "plain"), Some(json!("fixed")));
+        assert_eq!(value(&config, "port"), Some(json!(8080)));
+        assert_eq!(value(&config, "fallback"), Some(json!(3000)));
+        assert_eq!(value(&config, "debug"), Some(json!(true)));
+        assert_eq!(value(&config, "hosts"), Some(json!(["a.example", "b.example"])));
+        assert_eq!(config.values.get("optional"), Some(&None));
+        assert_eq!(value(&config, "db.pool"), Some(json!(8080)));
+        assert!(!config.values.contains_key("db"));
+    }
+
+    #[test]
+    fn reports_every_bad_value() {
+        set_env("TITAN_TEST_BAD_INT", "hunter2-not-a-number");
+        set_env("TITAN_TEST_BIG", "99");
+        let err = AppConfig::parse(
+            r#"
+            [config]
+            count = { type = "integer", env = "TITAN_TEST_BAD_INT", secret = true }
+            token = { type = "string", env = "TITAN_TEST_MISSING", required = true }
+            level = { type = "integer", env = "TITAN_TEST_BIG", max = 10 }
+            mode = { type = "string", default = "fast", one_of = ["safe", "slow"] }
+            url = { type = "url", default = "not a url" }
+            odd = { type = "date" }
+            "#,
+        )
+        .err()
+        .unwrap();
+        for problem in [
+            "count: TITAN_TEST_BAD_INT is not an integer",
+            "token: required; set TITAN_TEST_MISSING",
+            "level: must be at most 10",
+            "mode: must be one of \"safe\", \"slow\"",
+            "url: default is not a URL",
+            "odd: type must be",
+        ] {
+            assert!(err.contains(problem), "{} in {}", problem, err);
+        }
+        // The raw value of a variable never reaches the message
+        assert!(!err.contains("hunter2"));
+    }
+
+    #[test]
+    fn redacts_nested_secrets() {
+        set_env("TITAN_TEST_DB_PASSWORD", "s3cr3t-pass");
+        set_env("TITAN_TEST_KEYS", "key-one,key-one-and-more");
+        let config = AppConfig::parse(
+            r#"
+            [config.db]
+            password = { type = "string", env = "TITAN_TEST_DB_PASSWORD", secret = true }
+            user = { type = "string", default = "titan" }
+            [config.api.keys]
+            list = { type = "array", env = "TITAN_TEST_KEYS", secret = true }
+            short = { type = "string", default = "abc", secret = true }
+            pin = { type = "integer", default = 48213, secret = true }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.redact("user titan, password s3cr3t-pass, keys key-one-and-more key-one, pin 48213, abc"),
+            "user titan, password [redacted], keys [redacted] [redacted], pin [redacted], abc"
+        );
+        assert!(matches!(config.redact("nothing here"), Cow::Borrowed(_)));
+    }
+}
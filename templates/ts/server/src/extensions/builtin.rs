@@ -8,24 +8,28 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use serde_json::Value;
 use jsonwebtoken::{encode, decode, Header, EncodingKey, DecodingKey, Validation};
 use bcrypt::{hash, verify, DEFAULT_COST};
-use postgres::{Client as PgClient, NoTls};
 use std::sync::{Mutex, OnceLock};
 use std::collections::{HashMap, BTreeMap};
 
-use crate::utils::{blue, gray, red, parse_expires_in};
-use super::{TitanRuntime, v8_str, v8_to_string, throw, ShareContextStore};
+use crate::error::TitanError;
+use crate::runtime::{ResponseBody, ResponseHeaders, WorkerResult};
+use crate::utils::parse_expires_in;
+use crate::kv::{KvError, KvStore};
+use super::{ReplayLog, ResponseSender, TitanRuntime, v8_str, v8_to_string, throw, ShareContextStore};
 
 const TITAN_CORE_JS: &str = include_str!("titan_core.js");
 
-// Database connection pool
-static DB_POOL: Mutex<Option<HashMap<String, PgClient>>> = Mutex::new(None);
 static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
 
+/// Shared by every worker so connections to the same upstream are kept alive
+/// and reused across requests.
 fn get_http_client() -> &'static reqwest::Client {
     HTTP_CLIENT.get_or_init(|| {
         reqwest::Client::builder()
             .use_rustls_tls()
             .tcp_nodelay(true)
+            .pool_idle_timeout(std::time::Duration::from_secs(90))
+            .pool_max_idle_per_host(64)
             .user_agent("TitanPL/1.0")
             .build()
             .unwrap_or_else(|_| reqwest::Client::new())
@@ -33,6 +37,78 @@ fn get_http_client() -> &'static reqwest::Client {
 }
 
 
+/// Every native callback installed by this module. A startup snapshot stores
+/// functions by callback address, so V8 needs this table to restore them.
+pub fn external_references() -> Vec<v8::ExternalReference<'static>> {
+    use v8::MapFnTo;
+    [
+        native_define_action.map_fn_to(),
+        native_read.map_fn_to(),
+        native_read_sync.map_fn_to(),
+        native_decode_utf8.map_fn_to(),
+        native_encode_utf8.map_fn_to(),
+        native_parse_body.map_fn_to(),
+        native_negotiate.map_fn_to(),
+        native_url_for.map_fn_to(),
+        native_log.map_fn_to(),
+        native_console.map_fn_to(),
+        native_set_log_level.map_fn_to(),
+        native_fetch_meta.map_fn_to(),
+        native_drift_call.map_fn_to(),
+        native_async_start.map_fn_to(),
+        native_set_timer.map_fn_to(),
+        native_clear_timer.map_fn_to(),
+        native_finish_request.map_fn_to(),
+        native_fail_request.map_fn_to(),
+        native_stream_write.map_fn_to(),
+        native_stream_end.map_fn_to(),
+        native_stream_drain.map_fn_to(),
+        native_send_bytes.map_fn_to(),
+        native_read_upload.map_fn_to(),
+        native_read_bytes.map_fn_to(),
+        native_task_enqueue.map_fn_to(),
+        native_kv_get.map_fn_to(),
+        native_kv_set.map_fn_to(),
+        native_kv_incr.map_fn_to(),
+        native_kv_expire.map_fn_to(),
+        native_kv_cas.map_fn_to(),
+        native_kv_delete.map_fn_to(),
+        native_rate_limit.map_fn_to(),
+        native_cache_purge.map_fn_to(),
+        native_render.map_fn_to(),
+        native_cookie_sign.map_fn_to(),
+        native_crypto_digest.map_fn_to(),
+        native_crypto_hmac.map_fn_to(),
+        native_crypto_hmac_verify.map_fn_to(),
+        native_crypto_aes_gcm.map_fn_to(),
+        native_crypto_random.map_fn_to(),
+        native_webhook_verify.map_fn_to(),
+        native_config_get.map_fn_to(),
+        native_bus_publish.map_fn_to(),
+        native_bus_subscribe.map_fn_to(),
+        native_bus_unsubscribe.map_fn_to(),
+        native_trace_span.map_fn_to(),
+        native_ws_send.map_fn_to(),
+        native_ws_close.map_fn_to(),
+        native_load_env.map_fn_to(),
+        native_jwt_sign.map_fn_to(),
+        native_jwt_verify.map_fn_to(),
+        native_password_hash.map_fn_to(),
+        native_password_verify.map_fn_to(),
+        share_context_get.map_fn_to(),
+        share_context_set.map_fn_to(),
+        share_context_delete.map_fn_to(),
+        share_context_keys.map_fn_to(),
+        share_context_broadcast.map_fn_to(),
+        native_db_connect.map_fn_to(),
+        native_db_query.map_fn_to(),
+        native_redis_connect.map_fn_to(),
+    ]
+    .into_iter()
+    .map(|function| v8::ExternalReference { function })
+    .collect()
+}
+
 pub fn inject_builtin_extensions(scope: &mut v8::HandleScope, global: v8::Local<v8::Object>, t_obj: v8::Local<v8::Object>) {
     // 1. Native API Bindings
     
@@ -52,10 +128,40 @@ pub fn inject_builtin_extensions(scope: &mut v8::HandleScope, global: v8::Local<
     let dec_key = v8_str(scope, "decodeUtf8");
     t_obj.set(scope, dec_key.into(), dec_fn.into());
 
+    // t._encode_utf8 (wrapped as TextEncoder in titan_core.js)
+    let enc_fn = v8::Function::new(scope, native_encode_utf8).unwrap();
+    let enc_key = v8_str(scope, "_encode_utf8");
+    t_obj.set(scope, enc_key.into(), enc_fn.into());
+
+    // t._parse_body (behind the lazy req.body in titan_core.js)
+    let parse_body_fn = v8::Function::new(scope, native_parse_body).unwrap();
+    let parse_body_key = v8_str(scope, "_parse_body");
+    t_obj.set(scope, parse_body_key.into(), parse_body_fn.into());
+
+    // t._negotiate (behind res.format in titan_core.js)
+    let negotiate_fn = v8::Function::new(scope, native_negotiate).unwrap();
+    let negotiate_key = v8_str(scope, "_negotiate");
+    t_obj.set(scope, negotiate_key.into(), negotiate_fn.into());
+
+    // t._url_for (wrapped as url.for in titan_core.js)
+    let url_for_fn = v8::Function::new(scope, native_url_for).unwrap();
+    let url_for_key = v8_str(scope, "_url_for");
+    t_obj.set(scope, url_for_key.into(), url_for_fn.into());
+
     // t.log
     let log_fn = v8::Function::new(scope, native_log).unwrap();
     let log_key = v8_str(scope, "log");
     t_obj.set(scope, log_key.into(), log_fn.into());
+
+    // t._console (console.*)
+    let console_fn = v8::Function::new(scope, native_console).unwrap();
+    let console_key = v8_str(scope, "_console");
+    t_obj.set(scope, console_key.into(), console_fn.into());
+
+    // t.setLogLevel
+    let level_fn = v8::Function::new(scope, native_set_log_level).unwrap();
+    let level_key = v8_str(scope, "setLogLevel");
+    t_obj.set(scope, level_key.into(), level_fn.into());
     
     // t.fetch (Metadata version for drift)
     let fetch_fn = v8::Function::new(scope, native_fetch_meta).unwrap();
@@ -67,11 +173,153 @@ pub fn inject_builtin_extensions(scope: &mut v8::HandleScope, global: v8::Local<
     let drift_key = v8_str(scope, "_drift_call");
     t_obj.set(scope, drift_key.into(), drift_fn.into());
 
+    // t._async_start / t._set_timer / t._clear_timer (event loop)
+    let as_fn = v8::Function::new(scope, native_async_start).unwrap();
+    let as_key = v8_str(scope, "_async_start");
+    t_obj.set(scope, as_key.into(), as_fn.into());
+
+    let st_fn = v8::Function::new(scope, native_set_timer).unwrap();
+    let st_key = v8_str(scope, "_set_timer");
+    t_obj.set(scope, st_key.into(), st_fn.into());
+
+    let ct_fn = v8::Function::new(scope, native_clear_timer).unwrap();
+    let ct_key = v8_str(scope, "_clear_timer");
+    t_obj.set(scope, ct_key.into(), ct_fn.into());
+
     // t._finish_request
     let finish_fn = v8::Function::new(scope, native_finish_request).unwrap();
     let finish_key = v8_str(scope, "_finish_request");
     t_obj.set(scope, finish_key.into(), finish_fn.into());
 
+    // t._fail_request
+    let fail_fn = v8::Function::new(scope, native_fail_request).unwrap();
+    let fail_key = v8_str(scope, "_fail_request");
+    t_obj.set(scope, fail_key.into(), fail_fn.into());
+
+    // t._stream_write / t._stream_end / t._stream_drain
+    let sw_fn = v8::Function::new(scope, native_stream_write).unwrap();
+    let sw_key = v8_str(scope, "_stream_write");
+    t_obj.set(scope, sw_key.into(), sw_fn.into());
+
+    let se_fn = v8::Function::new(scope, native_stream_end).unwrap();
+    let se_key = v8_str(scope, "_stream_end");
+    t_obj.set(scope, se_key.into(), se_fn.into());
+
+    let sd_fn = v8::Function::new(scope, native_stream_drain).unwrap();
+    let sd_key = v8_str(scope, "_stream_drain");
+    t_obj.set(scope, sd_key.into(), sd_fn.into());
+
+    // t._send_bytes
+    let sb_fn = v8::Function::new(scope, native_send_bytes).unwrap();
+    let sb_key = v8_str(scope, "_send_bytes");
+    t_obj.set(scope, sb_key.into(), sb_fn.into());
+
+    // t._read_upload
+    let ru_fn = v8::Function::new(scope, native_read_upload).unwrap();
+    let ru_key = v8_str(scope, "_read_upload");
+    t_obj.set(scope, ru_key.into(), ru_fn.into());
+
+    // t._read_bytes
+    let rb_fn = v8::Function::new(scope, native_read_bytes).unwrap();
+    let rb_key = v8_str(scope, "_read_bytes");
+    t_obj.set(scope, rb_key.into(), rb_fn.into());
+
+    // t._task_enqueue
+    let te_fn = v8::Function::new(scope, native_task_enqueue).unwrap();
+    let te_key = v8_str(scope, "_task_enqueue");
+    t_obj.set(scope, te_key.into(), te_fn.into());
+
+    // t._kv_get / _kv_set / _kv_incr / _kv_expire / _kv_cas / _kv_delete
+    let kv_get_fn = v8::Function::new(scope, native_kv_get).unwrap();
+    let kv_get_key = v8_str(scope, "_kv_get");
+    t_obj.set(scope, kv_get_key.into(), kv_get_fn.into());
+    let kv_set_fn = v8::Function::new(scope, native_kv_set).unwrap();
+    let kv_set_key = v8_str(scope, "_kv_set");
+    t_obj.set(scope, kv_set_key.into(), kv_set_fn.into());
+    let kv_incr_fn = v8::Function::new(scope, native_kv_incr).unwrap();
+    let kv_incr_key = v8_str(scope, "_kv_incr");
+    t_obj.set(scope, kv_incr_key.into(), kv_incr_fn.into());
+    let kv_expire_fn = v8::Function::new(scope, native_kv_expire).unwrap();
+    let kv_expire_key = v8_str(scope, "_kv_expire");
+    t_obj.set(scope, kv_expire_key.into(), kv_expire_fn.into());
+    let kv_cas_fn = v8::Function::new(scope, native_kv_cas).unwrap();
+    let kv_cas_key = v8_str(scope, "_kv_cas");
+    t_obj.set(scope, kv_cas_key.into(), kv_cas_fn.into());
+    let kv_delete_fn = v8::Function::new(scope, native_kv_delete).unwrap();
+    let kv_delete_key = v8_str(scope, "_kv_delete");
+    t_obj.set(scope, kv_delete_key.into(), kv_delete_fn.into());
+
+    // t._rate_limit
+    let rl_fn = v8::Function::new(scope, native_rate_limit).unwrap();
+    let rl_key = v8_str(scope, "_rate_limit");
+    t_obj.set(scope, rl_key.into(), rl_fn.into());
+
+    // t._cache_purge
+    let cache_purge_fn = v8::Function::new(scope, native_cache_purge).unwrap();
+    let cache_purge_key = v8_str(scope, "_cache_purge");
+    t_obj.set(scope, cache_purge_key.into(), cache_purge_fn.into());
+
+    // t._render
+    let render_fn = v8::Function::new(scope, native_render).unwrap();
+    let render_key = v8_str(scope, "_render");
+    t_obj.set(scope, render_key.into(), render_fn.into());
+
+    // t._cookie_sign
+    let cookie_sign_fn = v8::Function::new(scope, native_cookie_sign).unwrap();
+    let cookie_sign_key = v8_str(scope, "_cookie_sign");
+    t_obj.set(scope, cookie_sign_key.into(), cookie_sign_fn.into());
+
+    // t._crypto_* (wrapped as globalThis.crypto in titan_core.js)
+    let crypto_digest_fn = v8::Function::new(scope, native_crypto_digest).unwrap();
+    let crypto_digest_key = v8_str(scope, "_crypto_digest");
+    t_obj.set(scope, crypto_digest_key.into(), crypto_digest_fn.into());
+    let crypto_hmac_fn = v8::Function::new(scope, native_crypto_hmac).unwrap();
+    let crypto_hmac_key = v8_str(scope, "_crypto_hmac");
+    t_obj.set(scope, crypto_hmac_key.into(), crypto_hmac_fn.into());
+    let crypto_hmac_verify_fn = v8::Function::new(scope, native_crypto_hmac_verify).unwrap();
+    let crypto_hmac_verify_key = v8_str(scope, "_crypto_hmac_verify");
+    t_obj.set(scope, crypto_hmac_verify_key.into(), crypto_hmac_verify_fn.into());
+    let crypto_aes_gcm_fn = v8::Function::new(scope, native_crypto_aes_gcm).unwrap();
+    let crypto_aes_gcm_key = v8_str(scope, "_crypto_aes_gcm");
+    t_obj.set(scope, crypto_aes_gcm_key.into(), crypto_aes_gcm_fn.into());
+    let crypto_random_fn = v8::Function::new(scope, native_crypto_random).unwrap();
+    let crypto_random_key = v8_str(scope, "_crypto_random");
+    t_obj.set(scope, crypto_random_key.into(), crypto_random_fn.into());
+    // t._webhook_verify (wrapped as globalThis.webhooks in titan_core.js)
+    let webhook_verify_fn = v8::Function::new(scope, native_webhook_verify).unwrap();
+    let webhook_verify_key = v8_str(scope, "_webhook_verify");
+    t_obj.set(scope, webhook_verify_key.into(), webhook_verify_fn.into());
+
+    // t._config_get
+    let config_get_fn = v8::Function::new(scope, native_config_get).unwrap();
+    let config_get_key = v8_str(scope, "_config_get");
+    t_obj.set(scope, config_get_key.into(), config_get_fn.into());
+
+    // t._bus_publish / _bus_subscribe / _bus_unsubscribe
+    let bus_pub_fn = v8::Function::new(scope, native_bus_publish).unwrap();
+    let bus_pub_key = v8_str(scope, "_bus_publish");
+    t_obj.set(scope, bus_pub_key.into(), bus_pub_fn.into());
+    let bus_sub_fn = v8::Function::new(scope, native_bus_subscribe).unwrap();
+    let bus_sub_key = v8_str(scope, "_bus_subscribe");
+    t_obj.set(scope, bus_sub_key.into(), bus_sub_fn.into());
+    let bus_unsub_fn = v8::Function::new(scope, native_bus_unsubscribe).unwrap();
+    let bus_unsub_key = v8_str(scope, "_bus_unsubscribe");
+    t_obj.set(scope, bus_unsub_key.into(), bus_unsub_fn.into());
+
+    // t._trace_span
+    let ts_fn = v8::Function::new(scope, native_trace_span).unwrap();
+    let ts_key = v8_str(scope, "_trace_span");
+    t_obj.set(scope, ts_key.into(), ts_fn.into());
+
+    // t._ws_send / t._ws_close
+    let ws_send_fn = v8::Function::new(scope, native_ws_send).unwrap();
+    let ws_send_key = v8_str(scope, "_ws_send");
+    t_obj.set(scope, ws_send_key.into(), ws_send_fn.into());
+
+    let ws_close_fn = v8::Function::new(scope, native_ws_close).unwrap();
+    let ws_close_key = v8_str(scope, "_ws_close");
+    t_obj.set(scope, ws_close_key.into(), ws_close_fn.into());
+
     // t.loadEnv
     let env_fn = v8::Function::new(scope, native_load_env).unwrap();
     let env_key = v8_str(scope, "loadEnv");
@@ -86,10 +334,10 @@ pub fn inject_builtin_extensions(scope: &mut v8::HandleScope, global: v8::Local<
     if let Some(script) = v8::Script::compile(tc, source, None) {
         if script.run(tc).is_none() {
              let msg = tc.message().map(|m| m.get(tc).to_rust_string_lossy(tc)).unwrap_or("Unknown".to_string());
-             println!("{} {} {}", blue("[Titan]"), red("Core JS Init Failed:"), msg);
+             tracing::error!("Core JS Init Failed: {}", msg);
         }
     } else {
-        println!("{} {}", blue("[Titan]"), red("Core JS Compilation Failed"));
+        tracing::error!("Core JS Compilation Failed");
     }
 }
 
@@ -152,6 +400,11 @@ fn setup_native_utils(scope: &mut v8::HandleScope, t_obj: v8::Local<v8::Object>)
     let db_key = v8_str(scope, "db");
     t_obj.set(scope, db_key.into(), db_obj.into());
 
+    // t._redis_connect (wrapped as t.redis.connect in titan_core.js)
+    let redis_connect_fn = v8::Function::new(scope, native_redis_connect).unwrap();
+    let redis_connect_key = v8_str(scope, "_redis_connect");
+    t_obj.set(scope, redis_connect_key.into(), redis_connect_fn.into());
+
     // t.core (System operations)
     let core_obj = v8::Object::new(scope);
     let fs_obj = v8::Object::new(scope);
@@ -174,6 +427,18 @@ fn setup_native_utils(scope: &mut v8::HandleScope, t_obj: v8::Local<v8::Object>)
 
 }
 
+// The root of the app this isolate serves; each app of a multi-app server
+// has its own
+fn project_root(scope: &mut v8::HandleScope) -> PathBuf {
+    let context = scope.get_current_context();
+    let global = context.global(scope);
+    let key = v8_str(scope, "__titan_root");
+    match global.get(scope, key.into()).filter(|v| v.is_string()) {
+        Some(root) => PathBuf::from(v8_to_string(scope, root)),
+        None => super::PROJECT_ROOT.get().cloned().unwrap_or_else(|| std::env::current_dir().unwrap_or_default()),
+    }
+}
+
 fn native_read_sync(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
     let path_val = args.get(0);
     if !path_val.is_string() {
@@ -182,7 +447,7 @@ fn native_read_sync(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArgum
     }
     let path_str = v8_to_string(scope, path_val);
 
-    let root = super::PROJECT_ROOT.get().cloned().unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
+    let root = project_root(scope);
     let joined = root.join(&path_str);
     
     // Security Check
@@ -269,6 +534,52 @@ fn native_decode_utf8(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArg
     }
 }
 
+/// `t._encode_utf8(text)`: the UTF-8 bytes of `text` as an ArrayBuffer.
+fn native_encode_utf8(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let text = v8_to_string(scope, args.get(0));
+    let store = v8::ArrayBuffer::new_backing_store_from_boxed_slice(text.into_bytes().into_boxed_slice());
+    let ab = v8::ArrayBuffer::with_backing_store(scope, &store.make_shared());
+    retval.set(ab.into());
+}
+
+/// `t._parse_body(buffer, contentType)`: the request body as `req.body`
+/// reads it, decoded or parsed by its content type.
+fn native_parse_body(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let bytes = buffer_source(scope, args.get(0)).unwrap_or_default();
+    let content_type = args.get(1).is_string().then(|| v8_to_string(scope, args.get(1)));
+    let parsed = super::parse_body(scope, &bytes, content_type.as_deref());
+    retval.set(parsed);
+}
+
+/// `t._negotiate(accept, types)`: the index of the type in `types` the
+/// `Accept` header ranks highest, or -1 when it refuses them all.
+fn native_negotiate(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let accept = v8_to_string(scope, args.get(0));
+    let mut types = Vec::new();
+    if let Ok(list) = v8::Local::<v8::Array>::try_from(args.get(1)) {
+        for i in 0..list.length() {
+            if let Some(value) = list.get_index(scope, i) {
+                types.push(v8_to_string(scope, value));
+            }
+        }
+    }
+    let index = crate::accept::preferred(&accept, &types).map_or(-1, |i| i as i32);
+    retval.set_int32(index);
+}
+
+/// `t._url_for(action, paramsJson, queryJson)`: the path of a route of
+/// `action` in this app, built from the route table.
+fn native_url_for(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let action = v8_to_string(scope, args.get(0));
+    let params: Value = serde_json::from_str(&v8_to_string(scope, args.get(1))).unwrap_or_default();
+    let query: Value = serde_json::from_str(&v8_to_string(scope, args.get(2))).unwrap_or_default();
+    let root = project_root(scope);
+    match crate::urls::build(&root, &action, params.as_object().unwrap_or(&serde_json::Map::new()), &query) {
+        Ok(path) => retval.set(v8_str(scope, &path).into()),
+        Err(e) => throw(scope, &format!("url.for(): {}", e)),
+    }
+}
+
 fn share_context_get(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
     let key = v8_to_string(scope, args.get(0));
     let store = ShareContextStore::get();
@@ -321,44 +632,77 @@ fn share_context_broadcast(scope: &mut v8::HandleScope, args: v8::FunctionCallba
 
 
 
-fn native_log(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut _retval: v8::ReturnValue) {
+fn native_log(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut _retval: v8::ReturnValue) {
+    emit_js_log(scope, &mut args, 0, tracing::Level::INFO);
+}
+
+/// `t._console(level, ...args)`: backs `console.log`, `console.warn` and the rest.
+fn native_console(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut _retval: v8::ReturnValue) {
+    let level = match v8_to_string(scope, args.get(0)).as_str() {
+        "trace" => tracing::Level::TRACE,
+        "debug" => tracing::Level::DEBUG,
+        "warn" => tracing::Level::WARN,
+        "error" => tracing::Level::ERROR,
+        _ => tracing::Level::INFO,
+    };
+    emit_js_log(scope, &mut args, 1, level);
+}
+
+// Logs the arguments from `first` on as one event, tagged with the worker,
+// the action and the request they came from
+fn emit_js_log(scope: &mut v8::HandleScope, args: &mut v8::FunctionCallbackArguments, first: i32, level: tracing::Level) {
     let context = scope.get_current_context();
     let global = context.global(scope);
     let action_key = v8_str(scope, "__titan_action");
-    let action_name = if let Some(action_val) = global.get(scope, action_key.into()) {
-        if action_val.is_string() {
-            v8_to_string(scope, action_val)
-        } else {
-            "init".to_string()
-        }
-    } else {
-        "init".to_string()
+    let action_name = match global.get(scope, action_key.into()) {
+        Some(action_val) if action_val.is_string() => v8_to_string(scope, action_val),
+        _ => "init".to_string(),
     };
 
     let mut parts = Vec::new();
-    for i in 0..args.length() {
+    for i in first..args.length() {
         let val = args.get(i);
-        let mut appended = false;
-        
-        if val.is_object() && !val.is_function() {
-             if let Some(json) = v8::json::stringify(scope, val) {
-                 parts.push(json.to_rust_string_lossy(scope));
-                 appended = true;
-             }
-        }
-        
-        if !appended {
-            parts.push(v8_to_string(scope, val));
+        if val.is_object() && !val.is_function() && !val.is_native_error()
+            && let Some(json) = v8::json::stringify(scope, val)
+        {
+            parts.push(json.to_rust_string_lossy(scope));
+            continue;
         }
+        parts.push(v8_to_string(scope, val));
+    }
+    let line = parts.join(" ");
+
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let (worker, request_id) = if runtime_ptr.is_null() {
+        (None, None)
+    } else {
+        let runtime = unsafe { &*runtime_ptr };
+        let request_id = current_request_id(scope);
+        (Some(runtime.id), super::correlation_id(runtime, request_id))
+    };
+
+    macro_rules! emit {
+        ($level:expr) => {
+            tracing::event!(target: "titan_server::js", $level, worker, action = %action_name, request_id = request_id.as_deref(), "{}", line)
+        };
+    }
+    match level {
+        tracing::Level::ERROR => emit!(tracing::Level::ERROR),
+        tracing::Level::WARN => emit!(tracing::Level::WARN),
+        tracing::Level::DEBUG => emit!(tracing::Level::DEBUG),
+        tracing::Level::TRACE => emit!(tracing::Level::TRACE),
+        _ => emit!(tracing::Level::INFO),
+    }
+}
+
+/// `t.setLogLevel(level)`: changes the log level of every worker and
+/// returns the previous one.
+fn native_set_log_level(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let level = v8_to_string(scope, args.get(0));
+    match crate::logging::set_level(&level) {
+        Ok(previous) => retval.set(v8_str(scope, &previous).into()),
+        Err(e) => throw(scope, &e),
     }
-    
-    let titan_str = blue("[Titan]");
-    let log_msg = gray(&format!("\x1b[90mlog({})\x1b[0m\x1b[97m: {}\x1b[0m", action_name, parts.join(" ")));
-    println!(
-        "{} {}",
-        titan_str,
-        log_msg
-    );
 }
 
 
@@ -463,20 +807,16 @@ fn native_db_connect(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArgu
         return;
     }
 
-    // Test connection immediately
-    match PgClient::connect(&conn_string, NoTls) {
-        Ok(mut client) => {
-            // Store in pool
-            let mut pool = DB_POOL.lock().unwrap();
-            let map = pool.get_or_insert_with(HashMap::new);
-            map.insert(conn_string.clone(), client);
-        },
-        Err(e) => {
-            throw(scope, &format!("Database connection failed: {}", e));
-            return;
-        }
+    // Connections are opened lazily by the pool, shared by every worker
+    let max_size = args.get(1).to_object(scope).and_then(|options| {
+        let max_key = v8_str(scope, "max");
+        options.get(scope, max_key.into()).and_then(|max| max.uint32_value(scope)).map(|max| max as usize)
+    });
+    if let Err(e) = crate::db::pool(&conn_string, max_size.filter(|max| *max > 0)) {
+        throw(scope, &format!("t.db.connect(): {}", e));
+        return;
     }
-    
+
     // Return a DB connection object with methods
     let db_conn_obj = v8::Object::new(scope);
     
@@ -493,6 +833,15 @@ fn native_db_connect(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArgu
     retval.set(db_conn_obj.into());
 }
 
+/// `t._redis_connect(url, max)`: checks the URL and sets up its pool.
+fn native_redis_connect(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
+    let url = v8_to_string(scope, args.get(0));
+    let max_size = args.get(1).uint32_value(scope).filter(|max| *max > 0).map(|max| max as usize);
+    if let Err(e) = crate::redis::pool(&url, max_size) {
+        throw(scope, &format!("t.redis.connect(): {}", e));
+    }
+}
+
 fn native_db_query(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
     // Get 'this' context (the db connection object)
     let this = args.this();
@@ -585,6 +934,7 @@ fn parse_async_op(scope: &mut v8::HandleScope, op_val: v8::Local<v8::Value>) ->
             let mut method = "GET".to_string();
             let mut body = None;
             let mut headers = Vec::new();
+            let mut timeout_ms = None;
             
             let opts_key = v8_str(scope, "opts");
             if let Some(opts_val) = data_obj.get(scope, opts_key.into()) {
@@ -596,12 +946,17 @@ fn parse_async_op(scope: &mut v8::HandleScope, op_val: v8::Local<v8::Value>) ->
                     }
                     let b_key = v8_str(scope, "body");
                     if let Some(b_val) = opts_obj.get(scope, b_key.into()) {
-                        if b_val.is_string() { 
-                            body = Some(v8_to_string(scope, b_val)); 
-                        } else if b_val.is_object() {
-                            body = Some(v8::json::stringify(scope, b_val).unwrap().to_rust_string_lossy(scope));
+                        // Strings and buffers go as-is, other objects as JSON
+                        if b_val.is_string() || b_val.is_object() {
+                            body = Some(chunk_from_v8(scope, b_val));
                         }
                     }
+                    let t_key = v8_str(scope, "timeout");
+                    if let Some(t_val) = opts_obj.get(scope, t_key.into())
+                        && t_val.is_number()
+                    {
+                        timeout_ms = t_val.integer_value(scope).filter(|ms| *ms > 0).map(|ms| ms as u64);
+                    }
                     let h_key = v8_str(scope, "headers");
                     if let Some(h_val) = opts_obj.get(scope, h_key.into()) {
                         if h_val.is_object() {
@@ -617,7 +972,7 @@ fn parse_async_op(scope: &mut v8::HandleScope, op_val: v8::Local<v8::Value>) ->
                     }
                 }
             }
-            Some(super::TitanAsyncOp::Fetch { url, method, body, headers })
+            Some(super::TitanAsyncOp::Fetch { url, method, body, headers, timeout_ms })
         },
         "db_query" => {
             let conn_key = v8_str(scope, "conn");
@@ -626,13 +981,70 @@ fn parse_async_op(scope: &mut v8::HandleScope, op_val: v8::Local<v8::Value>) ->
             let query_key = v8_str(scope, "query");
             let query_obj = data_obj.get(scope, query_key.into())?;
             let query = v8_to_string(scope, query_obj);
-            Some(super::TitanAsyncOp::DbQuery { conn, query })
+            let params_key = v8_str(scope, "params");
+            let params = match data_obj.get(scope, params_key.into()) {
+                Some(params) if params.is_array() => match super::v8_to_json(scope, params) {
+                    Value::Array(params) => params,
+                    _ => Vec::new(),
+                },
+                _ => Vec::new(),
+            };
+            Some(super::TitanAsyncOp::DbQuery { conn, query, params })
+        },
+        "redis" => {
+            let url_key = v8_str(scope, "url");
+            let url_obj = data_obj.get(scope, url_key.into())?;
+            let url = v8_to_string(scope, url_obj);
+            let commands_key = v8_str(scope, "commands");
+            let commands_obj = data_obj.get(scope, commands_key.into())?;
+            let commands = match super::v8_to_json(scope, commands_obj) {
+                Value::Array(commands) => commands
+                    .iter()
+                    .map(|command| {
+                        let args = command.as_array().map(Vec::as_slice).unwrap_or_default();
+                        args.iter().map(|arg| arg.as_str().map_or_else(|| arg.to_string(), str::to_string)).collect()
+                    })
+                    .collect(),
+                _ => return None,
+            };
+            Some(super::TitanAsyncOp::Redis { url, commands })
         },
         "fs_read" => {
             let path_key = v8_str(scope, "path");
             let path_obj = data_obj.get(scope, path_key.into())?;
             let path = v8_to_string(scope, path_obj);
-            Some(super::TitanAsyncOp::FsRead { path })
+            Some(super::TitanAsyncOp::FsRead { root: project_root(scope), path })
+        },
+        "body_read" => {
+            let id_key = v8_str(scope, "id");
+            let id = data_obj.get(scope, id_key.into())?.number_value(scope)? as u64;
+            Some(super::TitanAsyncOp::BodyRead { id })
+        },
+        "fs" => {
+            let op_key = v8_str(scope, "op");
+            let op_obj = data_obj.get(scope, op_key.into())?;
+            let path_key = v8_str(scope, "path");
+            let path_obj = data_obj.get(scope, path_key.into())?;
+            let path = v8_to_string(scope, path_obj);
+            let op = match v8_to_string(scope, op_obj).as_str() {
+                "readFile" => {
+                    let text_key = v8_str(scope, "text");
+                    let text = data_obj.get(scope, text_key.into())?.boolean_value(scope);
+                    crate::files::FsOp::ReadFile { path, text }
+                },
+                "writeFile" => {
+                    let data_key = v8_str(scope, "data");
+                    let data_val = data_obj.get(scope, data_key.into())?;
+                    let data = bytes::Bytes::from(buffer_source(scope, data_val)?);
+                    let append_key = v8_str(scope, "append");
+                    let append = data_obj.get(scope, append_key.into())?.boolean_value(scope);
+                    crate::files::FsOp::WriteFile { path, data, append }
+                },
+                "readDir" => crate::files::FsOp::ReadDir { path },
+                "stat" => crate::files::FsOp::Stat { path },
+                _ => return None,
+            };
+            Some(super::TitanAsyncOp::Fs(op))
         },
         _ => None
     }
@@ -658,12 +1070,7 @@ fn native_drift_call(scope: &mut v8::HandleScope, mut args: v8::FunctionCallback
     } else {
         match parse_async_op(scope, arg0) {
             Some(op) => {
-                let t = match &op {
-                    super::TitanAsyncOp::Fetch { .. } => "fetch",
-                    super::TitanAsyncOp::DbQuery { .. } => "db_query",
-                    super::TitanAsyncOp::FsRead { .. } => "fs_read",
-                    _ => "unknown"
-                };
+                let t = async_op_type(&op);
                 (op, t.to_string())
             },
             None => {
@@ -676,19 +1083,7 @@ fn native_drift_call(scope: &mut v8::HandleScope, mut args: v8::FunctionCallback
     let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
     let runtime = unsafe { &mut *runtime_ptr };
     
-    // Extract request_id from globalThis.__titan_req.__titan_request_id
-    let req_id = {
-        let context = scope.get_current_context();
-        let global = context.global(scope);
-        let req_key = v8_str(scope, "__titan_req");
-        if let Some(req_obj_val) = global.get(scope, req_key.into()) {
-            if req_obj_val.is_object() {
-                let req_obj = req_obj_val.to_object(scope).unwrap();
-                let id_key = v8_str(scope, "__titan_request_id");
-                req_obj.get(scope, id_key.into()).unwrap().uint32_value(scope).unwrap_or(0)
-            } else { 0 }
-        } else { 0 }
-    };
+    let req_id = current_request_id(scope);
 
     runtime.drift_counter += 1;
     let drift_id = runtime.drift_counter;
@@ -724,7 +1119,7 @@ fn native_drift_call(scope: &mut v8::HandleScope, mut args: v8::FunctionCallback
     };
     
     if let Err(e) = runtime.global_async_tx.try_send(req) {
-         println!("[Titan] Drift Call Failed to queue: {}", e);
+         tracing::error!("Drift Call Failed to queue: {}", e);
          retval.set(v8::null(scope).into());
          return;
     }
@@ -750,16 +1145,123 @@ fn native_drift_call(scope: &mut v8::HandleScope, mut args: v8::FunctionCallback
     throw(scope, "__SUSPEND__");
 }
 
+fn async_op_type(op: &super::TitanAsyncOp) -> &'static str {
+    match op {
+        super::TitanAsyncOp::Fetch { .. } => "fetch",
+        super::TitanAsyncOp::DbQuery { .. } => "db_query",
+        super::TitanAsyncOp::FsRead { .. } => "fs_read",
+        super::TitanAsyncOp::Redis { .. } => "redis",
+        super::TitanAsyncOp::BodyRead { .. } => "body_read",
+        super::TitanAsyncOp::Fs(_) => "fs",
+        _ => "unknown"
+    }
+}
+
+/// Extracts request_id from globalThis.__titan_req.__titan_request_id
+fn current_request_id(scope: &mut v8::HandleScope) -> u32 {
+    let context = scope.get_current_context();
+    let global = context.global(scope);
+    let req_key = v8_str(scope, "__titan_req");
+    match global.get(scope, req_key.into()) {
+        Some(req_obj_val) if req_obj_val.is_object() => {
+            let req_obj = req_obj_val.to_object(scope).unwrap();
+            let id_key = v8_str(scope, "__titan_request_id");
+            req_obj.get(scope, id_key.into()).unwrap().uint32_value(scope).unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
+
+/// `t._async_start(op)`: runs an async op without suspending the action and
+/// returns a promise for its result. Backs the promise-based `fetch()`.
+fn native_async_start(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let Some(op) = parse_async_op(scope, args.get(0)) else {
+        throw(scope, "t._async_start() requires an async operation");
+        return;
+    };
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+
+    let request_id = current_request_id(scope);
+    runtime.op_counter = runtime.op_counter.wrapping_add(1);
+    let op_id = runtime.op_counter;
+
+    let resolver = v8::PromiseResolver::new(scope).unwrap();
+    let promise = resolver.get_promise(scope);
+
+    runtime.pending_ops.insert(op_id, super::PendingOp { resolver: v8::Global::new(scope, resolver), request_id });
+
+    let worker_tx = runtime.worker_tx.clone();
+    runtime.tokio_handle.spawn(async move {
+        let outcome = run_promise_op(op).await;
+        let _ = worker_tx.send(crate::runtime::WorkerCommand::Settle { op_id, outcome });
+    });
+
+    retval.set(promise.into());
+}
+
+/// `t._set_timer(id, delayMs)`: arms a timer the worker loop fires through
+/// `__titan_fire_timer(id)`. Ids are allocated by the JS side.
+fn native_set_timer(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
+    let timer_id = args.get(0).uint32_value(scope).unwrap_or(0);
+    let delay_ms = args.get(1).number_value(scope).unwrap_or(0.0).max(0.0);
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+
+    let request_id = current_request_id(scope);
+    let at = std::time::Instant::now() + std::time::Duration::from_micros((delay_ms * 1000.0) as u64);
+    runtime.timers.insert((at, timer_id), request_id);
+}
+
+fn native_clear_timer(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
+    let timer_id = args.get(0).uint32_value(scope).unwrap_or(0);
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+    runtime.timers.retain(|(_, id), _| *id != timer_id);
+}
+
 fn native_finish_request(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
     let request_id = args.get(0).uint32_value(scope).unwrap_or(0);
     let result_val = args.get(1);
-    let json = super::v8_to_json(scope, result_val);
+    let (status, headers) = read_head(scope, args.get(2));
+    let json = match stringify_plain(scope, result_val) {
+        Some(text) => Ok(WorkerResult { status, headers, body: ResponseBody::JsonText(text), timings: vec![] }),
+        None => super::try_v8_to_json(scope, result_val).map(|json| response_from_value(json, status, headers)),
+    };
 
     let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
     let runtime = unsafe { &mut *runtime_ptr };
-    
+
+    if let Some((tx, timings)) = settle_request(runtime, request_id) {
+        let result = json.map_err(TitanError::Serialization).map(|mut result| {
+            result.timings = timings;
+            result
+        });
+        let _ = tx.send(result);
+    }
+}
+
+/// `t._fail_request(requestId, message, stack)`: answers with the exception
+/// the action threw.
+fn native_fail_request(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
+    let request_id = args.get(0).uint32_value(scope).unwrap_or(0);
+    let message = v8_to_string(scope, args.get(1));
+    let stack = args.get(2).is_string().then(|| crate::sourcemap::rewrite(&v8_to_string(scope, args.get(2))));
+
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+
+    if let Some((tx, _)) = settle_request(runtime, request_id) {
+        let _ = tx.send(Err(TitanError::JsException { message, stack }));
+    }
+}
+
+/// Forgets the request's drift results and hands back its response channel
+/// and timings, or None when it was already answered. A streamed response
+/// already went out; dropping its sender closes the body.
+fn settle_request(runtime: &mut TitanRuntime, request_id: u32) -> Option<(ResponseSender, Vec<(String, f64)>)> {
     let timings = runtime.request_timings.remove(&request_id).unwrap_or_default();
-    
+
     // Cleanup drift mapping for this request
     runtime.drift_to_request.retain(|drift_id, v| {
         if *v == request_id {
@@ -770,33 +1272,890 @@ fn native_finish_request(scope: &mut v8::HandleScope, mut args: v8::FunctionCall
         }
     });
 
-    if let Some(tx) = runtime.pending_requests.remove(&request_id) {
-        let _ = tx.send(crate::runtime::WorkerResult { json, timings });
+    if runtime.streams.remove(&request_id).is_some() {
+        return None;
     }
+    runtime.pending_requests.remove(&request_id).map(|tx| (tx, timings))
 }
 
-pub async fn run_single_op(op: super::TitanAsyncOp) -> serde_json::Value {
-    match op {
-        super::TitanAsyncOp::Fetch { url, method, body, headers } => {
-            let client = get_http_client();
-            let mut req = client.request(method.parse().unwrap_or(reqwest::Method::GET), &url);
-            if let Some(b) = body { req = req.body(b); }
-            for (k, v) in headers {
-                if let (Ok(name), Ok(val)) = (reqwest::header::HeaderName::from_bytes(k.as_bytes()), reqwest::header::HeaderValue::from_str(&v)) {
-                    req = req.header(name, val);
+/// Reads the `{ status, headers: [[name, value], ...] }` object the response
+/// writer passes along with every response.
+fn read_head(scope: &mut v8::HandleScope, val: v8::Local<v8::Value>) -> (u16, ResponseHeaders) {
+    let mut status = 200;
+    let mut headers = ResponseHeaders::new();
+    let Ok(obj) = v8::Local::<v8::Object>::try_from(val) else {
+        return (status, headers);
+    };
+
+    let status_key = v8_str(scope, "status");
+    if let Some(code) = obj.get(scope, status_key.into()).and_then(|v| v.uint32_value(scope))
+        && (100..=999).contains(&code)
+    {
+        status = code as u16;
+    }
+
+    let headers_key = v8_str(scope, "headers");
+    if let Some(Value::Array(pairs)) = obj.get(scope, headers_key.into()).map(|v| super::v8_to_json(scope, v)) {
+        for pair in pairs {
+            if let (Some(k), Some(v)) = (pair.get(0).and_then(|k| k.as_str()), pair.get(1).and_then(|v| v.as_str())) {
+                headers.push((k.to_string(), v.to_string()));
+            }
+        }
+    }
+    (status, headers)
+}
+
+/// Replaces any header with the same name, except Set-Cookie which may repeat.
+fn set_header(headers: &mut ResponseHeaders, name: &str, value: String) {
+    if !name.eq_ignore_ascii_case("set-cookie") {
+        headers.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+    }
+    headers.push((name.to_string(), value));
+}
+
+/// Serializes a plain object or array straight to bytes with V8's
+/// JSON.stringify, skipping the `Value` in between. Responses built with
+/// `t.response.*` and `{ error }` results are left to `response_from_value`,
+/// as is anything JSON.stringify throws on (BigInt, circular references).
+fn stringify_plain(scope: &mut v8::HandleScope, val: v8::Local<v8::Value>) -> Option<bytes::Bytes> {
+    if !val.is_array() {
+        let obj = val.is_object().then_some(val).filter(|v| !v.is_function())?.to_object(scope)?;
+        for key in ["_isResponse", "error"] {
+            let key = v8::String::new(scope, key)?;
+            if obj.has_own_property(scope, key.into()) != Some(false) {
+                return None;
+            }
+        }
+    }
+    let try_catch = &mut v8::TryCatch::new(scope);
+    let json = v8::json::stringify(try_catch, val)?;
+    if try_catch.has_caught() {
+        return None;
+    }
+    Some(bytes::Bytes::from(json.to_rust_string_lossy(try_catch)))
+}
+
+/// Turns an action's return value into a response. Objects built with
+/// `t.response.*` carry their own status, headers and body; anything else is
+/// sent as JSON.
+fn response_from_value(value: Value, status: u16, mut headers: ResponseHeaders) -> WorkerResult {
+    if value.get("_isResponse").and_then(|v| v.as_bool()) != Some(true) {
+        return WorkerResult { status, headers, body: ResponseBody::Json(value), timings: vec![] };
+    }
+
+    let mut status = value
+        .get("status")
+        .and_then(|v| v.as_u64())
+        .filter(|s| (100..=999).contains(s))
+        .map(|s| s as u16)
+        .unwrap_or(status);
+
+    if let Some(hmap) = value.get("headers").and_then(|v| v.as_object()) {
+        for (k, v) in hmap {
+            match v {
+                Value::String(vs) => set_header(&mut headers, k, vs.clone()),
+                // `"Set-Cookie": [...]` gets a line per cookie
+                Value::Array(list) => {
+                    for vs in list.iter().filter_map(Value::as_str) {
+                        set_header(&mut headers, k, vs.to_string());
+                    }
                 }
+                _ => {}
             }
-            match req.send().await {
+        }
+    }
+
+    if let Some(url) = value.get("redirect").and_then(|v| v.as_str()) {
+        if !(300..=399).contains(&status) {
+            status = 302;
+        }
+        set_header(&mut headers, "Location", url.to_string());
+        return WorkerResult { status, headers, body: ResponseBody::Empty, timings: vec![] };
+    }
+
+    let body = match value.get("body") {
+        Some(Value::String(s)) => ResponseBody::Bytes(bytes::Bytes::from(s.clone())),
+        Some(v) => ResponseBody::Bytes(bytes::Bytes::from(v.to_string())),
+        None => ResponseBody::Empty,
+    };
+    WorkerResult { status, headers, body, timings: vec![] }
+}
+
+fn chunk_from_v8(scope: &mut v8::HandleScope, val: v8::Local<v8::Value>) -> bytes::Bytes {
+    if let Ok(u8arr) = v8::Local::<v8::Uint8Array>::try_from(val) {
+        let mut buf = vec![0u8; u8arr.byte_length()];
+        u8arr.copy_contents(&mut buf);
+        return bytes::Bytes::from(buf);
+    }
+    if let Ok(ab) = v8::Local::<v8::ArrayBuffer>::try_from(val) {
+        let store = v8::ArrayBuffer::get_backing_store(&ab);
+        let buf: Vec<u8> = store.iter().map(|b| b.get()).collect();
+        return bytes::Bytes::from(buf);
+    }
+    if val.is_string() {
+        return bytes::Bytes::from(v8_to_string(scope, val));
+    }
+    if val.is_null_or_undefined() {
+        return bytes::Bytes::new();
+    }
+    let json = v8::json::stringify(scope, val)
+        .map(|s| s.to_rust_string_lossy(scope))
+        .unwrap_or_default();
+    bytes::Bytes::from(json)
+}
+
+/// Keeps a V8 backing store alive while its memory is written to the socket.
+struct BackingStoreBytes(v8::SharedRef<v8::BackingStore>);
+
+// Backing stores are reference counted with std::shared_ptr and can be
+// released from any thread.
+unsafe impl Send for BackingStoreBytes {}
+
+impl AsRef<[u8]> for BackingStoreBytes {
+    fn as_ref(&self) -> &[u8] {
+        match self.0.data() {
+            Some(ptr) => unsafe { std::slice::from_raw_parts(ptr.as_ptr() as *const u8, self.0.byte_length()) },
+            None => &[],
+        }
+    }
+}
+
+/// Takes the memory behind an ArrayBuffer or typed array without copying it.
+/// The buffer is detached so the action can't modify bytes still being sent.
+fn bytes_from_buffer(scope: &mut v8::HandleScope, val: v8::Local<v8::Value>) -> Option<bytes::Bytes> {
+    let (buffer, offset, len) = if let Ok(view) = v8::Local::<v8::ArrayBufferView>::try_from(val) {
+        (view.buffer(scope)?, view.byte_offset(), view.byte_length())
+    } else if let Ok(ab) = v8::Local::<v8::ArrayBuffer>::try_from(val) {
+        (ab, 0, ab.byte_length())
+    } else {
+        return None;
+    };
+    let store = buffer.get_backing_store();
+    buffer.detach(None);
+    Some(bytes::Bytes::from_owner(BackingStoreBytes(store)).slice(offset..offset + len))
+}
+
+fn native_send_bytes(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
+    let request_id = args.get(0).uint32_value(scope).unwrap_or(0);
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+
+    if runtime.streams.contains_key(&request_id) {
+        throw(scope, "res.sendBytes(): response is already streaming");
+        return;
+    }
+    // Already answered by an earlier execution of this request (drift replay)
+    let Some(response_tx) = runtime.pending_requests.remove(&request_id) else {
+        return;
+    };
+
+    let data = args.get(1);
+    let body = bytes_from_buffer(scope, data).unwrap_or_else(|| chunk_from_v8(scope, data));
+    let mime = args.get(2);
+    let (status, mut headers) = read_head(scope, args.get(3));
+    if mime.is_string() && !headers.iter().any(|(k, _)| k.eq_ignore_ascii_case("content-type")) {
+        headers.push(("content-type".to_string(), v8_to_string(scope, mime)));
+    }
+    let timings = runtime.request_timings.get(&request_id).cloned().unwrap_or_default();
+
+    let _ = response_tx.send(Ok(WorkerResult { status, headers, body: ResponseBody::Bytes(body), timings }));
+}
+
+/// `t._read_upload(requestId, index)`: the contents of an uploaded file as an
+/// ArrayBuffer. Only files of that request's own form can be read.
+fn native_read_upload(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let request_id = args.get(0).uint32_value(scope).unwrap_or(0);
+    let index = args.get(1).uint32_value(scope).unwrap_or(0) as usize;
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+
+    let form = runtime.active_requests.get(&request_id).and_then(|r| r.form.clone());
+    let Some(data) = form.and_then(|form| form.read(index)) else {
+        throw(scope, "file.bytes(): upload is no longer available");
+        return;
+    };
+    let store = v8::ArrayBuffer::new_backing_store_from_boxed_slice(data.into_boxed_slice());
+    let ab = v8::ArrayBuffer::with_backing_store(scope, &store.make_shared());
+    retval.set(ab.into());
+}
+
+/// `t._read_bytes(path)`: a file under the project root as an ArrayBuffer
+/// that owns the bytes read, so nothing is copied into the heap.
+fn native_read_bytes(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let path = v8_to_string(scope, args.get(0));
+    let root = project_root(scope);
+    let root = root.canonicalize().unwrap_or(root);
+    let data = match root.join(&path).canonicalize() {
+        Ok(target) if target.starts_with(&root) => std::fs::read(&target).map_err(|e| e.to_string()),
+        Ok(_) => Err("path is outside the project".to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    match data {
+        Ok(data) => {
+            let store = v8::ArrayBuffer::new_backing_store_from_boxed_slice(data.into_boxed_slice());
+            let ab = v8::ArrayBuffer::with_backing_store(scope, &store.make_shared());
+            retval.set(ab.into());
+        }
+        Err(e) => throw(scope, &format!("wasm: cannot read '{}': {}", path, e)),
+    }
+}
+
+/// `t._task_enqueue(name, payloadJson, delayMs, retries)`: queues a background
+/// task and returns its id. A replayed action gets the id of its earlier
+/// enqueue back, so a drift doesn't run the task twice.
+fn native_task_enqueue(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let name = v8_to_string(scope, args.get(0));
+    let payload = v8_to_string(scope, args.get(1));
+    let delay = args.get(2).number_value(scope).unwrap_or(0.0).max(0.0);
+    let retries = args.get(3).uint32_value(scope).unwrap_or(0);
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+
+    let action = format!("{}{}", crate::tasks::TASK_ACTION_PREFIX, name);
+    if !runtime.actions.contains_key(&action) {
+        throw(scope, &format!("tasks.enqueue(): no task named '{}' in app/tasks", name));
+        return;
+    }
+
+    let request_id = current_request_id(scope);
+    let delay = std::time::Duration::from_millis(delay as u64);
+    let result = ReplayLog::once(&mut runtime.replay_logs, request_id, || {
+        crate::tasks::enqueue(&name, payload, delay, retries).map_err(str::to_string)
+    });
+    match result {
+        Ok(id) => {
+            let id_val = v8_str(scope, &id);
+            retval.set(id_val.into());
+        }
+        Err(e) => throw(scope, &format!("tasks.enqueue(): {}", e)),
+    }
+}
+
+/// Runs a kv operation through the request's replay log and hands its JSON
+/// result back to JS, or throws.
+fn kv_call(
+    scope: &mut v8::HandleScope,
+    args: &mut v8::FunctionCallbackArguments,
+    retval: &mut v8::ReturnValue,
+    op: impl FnOnce(&KvStore) -> Result<Value, KvError>,
+) {
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+    let request_id = current_request_id(scope);
+    let result = ReplayLog::once(&mut runtime.replay_logs, request_id, || {
+        op(KvStore::get()).map(|value| value.to_string()).map_err(|e| e.to_string())
+    });
+    match result {
+        Ok(json) => {
+            let json_val = v8_str(scope, &json);
+            retval.set(json_val.into());
+        }
+        Err(e) => throw(scope, &format!("kv: {}", e)),
+    }
+}
+
+/// `undefined`/`null` JSON text from JS means "absent".
+fn kv_json_arg(scope: &mut v8::HandleScope, arg: v8::Local<v8::Value>) -> Option<Value> {
+    if arg.is_null_or_undefined() {
+        return None;
+    }
+    serde_json::from_str(&v8_to_string(scope, arg)).ok()
+}
+
+fn kv_ttl_arg(scope: &mut v8::HandleScope, arg: v8::Local<v8::Value>) -> Option<std::time::Duration> {
+    let ms = arg.number_value(scope).filter(|ms| ms.is_finite() && *ms > 0.0)?;
+    Some(std::time::Duration::from_millis(ms as u64))
+}
+
+fn native_kv_get(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let key = v8_to_string(scope, args.get(0));
+    kv_call(scope, &mut args, &mut retval, |kv| Ok(kv.read(&key).unwrap_or(Value::Null)));
+}
+
+fn native_kv_set(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let key = v8_to_string(scope, args.get(0));
+    let value = kv_json_arg(scope, args.get(1)).unwrap_or(Value::Null);
+    let ttl = kv_ttl_arg(scope, args.get(2));
+    kv_call(scope, &mut args, &mut retval, |kv| {
+        kv.set(&key, value, ttl);
+        Ok(Value::Bool(true))
+    });
+}
+
+fn native_kv_incr(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let key = v8_to_string(scope, args.get(0));
+    let by = args.get(1).number_value(scope).filter(|by| by.is_finite()).unwrap_or(1.0);
+    kv_call(scope, &mut args, &mut retval, |kv| kv.incr(&key, by));
+}
+
+fn native_kv_expire(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let key = v8_to_string(scope, args.get(0));
+    let ttl = kv_ttl_arg(scope, args.get(1));
+    kv_call(scope, &mut args, &mut retval, |kv| Ok(Value::Bool(kv.expire(&key, ttl))));
+}
+
+fn native_kv_cas(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let key = v8_to_string(scope, args.get(0));
+    let expected = kv_json_arg(scope, args.get(1));
+    let value = kv_json_arg(scope, args.get(2)).unwrap_or(Value::Null);
+    let ttl = kv_ttl_arg(scope, args.get(3));
+    kv_call(scope, &mut args, &mut retval, |kv| Ok(Value::Bool(kv.cas(&key, expected.as_ref(), value, ttl))));
+}
+
+fn native_kv_delete(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let key = v8_to_string(scope, args.get(0));
+    kv_call(scope, &mut args, &mut retval, |kv| Ok(Value::Bool(kv.delete(&key))));
+}
+
+/// Copies a BufferSource (ArrayBuffer or any view of one) out of the heap.
+/// Strings are taken as UTF-8.
+fn buffer_source(scope: &mut v8::HandleScope, val: v8::Local<v8::Value>) -> Option<Vec<u8>> {
+    if let Ok(view) = v8::Local::<v8::ArrayBufferView>::try_from(val) {
+        let mut buf = vec![0u8; view.byte_length()];
+        view.copy_contents(&mut buf);
+        return Some(buf);
+    }
+    if let Ok(ab) = v8::Local::<v8::ArrayBuffer>::try_from(val) {
+        return Some(ab.get_backing_store().iter().map(|b| b.get()).collect());
+    }
+    val.is_string().then(|| v8_to_string(scope, val).into_bytes())
+}
+
+fn crypto_result(scope: &mut v8::HandleScope, retval: &mut v8::ReturnValue, result: Result<Vec<u8>, crate::crypto::CryptoError>) {
+    match result {
+        Ok(bytes) => {
+            let store = v8::ArrayBuffer::new_backing_store_from_boxed_slice(bytes.into_boxed_slice());
+            let ab = v8::ArrayBuffer::with_backing_store(scope, &store.make_shared());
+            retval.set(ab.into());
+        }
+        Err(e) => throw(scope, &format!("crypto: {}", e)),
+    }
+}
+
+/// `t._crypto_digest(algorithm, data)`
+fn native_crypto_digest(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let algorithm = v8_to_string(scope, args.get(0));
+    let Some(data) = buffer_source(scope, args.get(1)) else {
+        throw(scope, "crypto.subtle.digest(): data must be a BufferSource");
+        return;
+    };
+    let result = crate::crypto::digest(&algorithm, &data);
+    crypto_result(scope, &mut retval, result);
+}
+
+/// `t._crypto_hmac(hash, key, data)`
+fn native_crypto_hmac(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let hash = v8_to_string(scope, args.get(0));
+    let (Some(key), Some(data)) = (buffer_source(scope, args.get(1)), buffer_source(scope, args.get(2))) else {
+        throw(scope, "crypto.subtle.sign(): key and data must be BufferSources");
+        return;
+    };
+    let result = crate::crypto::hmac_sign(&hash, &key, &data);
+    crypto_result(scope, &mut retval, result);
+}
+
+/// `t._crypto_hmac_verify(hash, key, data, signature)`
+fn native_crypto_hmac_verify(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let hash = v8_to_string(scope, args.get(0));
+    let key = buffer_source(scope, args.get(1));
+    let data = buffer_source(scope, args.get(2));
+    let signature = buffer_source(scope, args.get(3));
+    let (Some(key), Some(data), Some(signature)) = (key, data, signature) else {
+        throw(scope, "crypto.subtle.verify(): key, signature and data must be BufferSources");
+        return;
+    };
+    match crate::crypto::hmac_verify(&hash, &key, &data, &signature) {
+        Ok(valid) => retval.set_bool(valid),
+        Err(e) => throw(scope, &format!("crypto: {}", e)),
+    }
+}
+
+/// `t._crypto_aes_gcm(encrypt, key, iv, additionalData, data)`
+fn native_crypto_aes_gcm(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let encrypt = args.get(0).boolean_value(scope);
+    let key = buffer_source(scope, args.get(1));
+    let iv = buffer_source(scope, args.get(2));
+    let aad = buffer_source(scope, args.get(3)).unwrap_or_default();
+    let data = buffer_source(scope, args.get(4));
+    let (Some(key), Some(iv), Some(data)) = (key, iv, data) else {
+        throw(scope, "crypto.subtle: AES-GCM key, iv and data must be BufferSources");
+        return;
+    };
+    let result = crate::crypto::aes_gcm(encrypt, &key, &iv, &aad, &data);
+    crypto_result(scope, &mut retval, result);
+}
+
+/// `t._crypto_random(length)`: random bytes as an ArrayBuffer. Logged like kv
+/// writes, so a replayed action sees the same values (and the same UUIDs).
+fn native_crypto_random(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+
+    let len = args.get(0).uint32_value(scope).unwrap_or(0) as usize;
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let bytes = if runtime_ptr.is_null() {
+        crate::crypto::random_bytes(len)
+    } else {
+        let request_id = current_request_id(scope);
+        let logged = ReplayLog::once(unsafe { &mut (*runtime_ptr).replay_logs }, request_id, || {
+            Ok(STANDARD.encode(crate::crypto::random_bytes(len)))
+        });
+        logged.ok().and_then(|b64| STANDARD.decode(b64).ok()).unwrap_or_default()
+    };
+    crypto_result(scope, &mut retval, Ok(bytes));
+}
+
+/// `t._webhook_verify(provider, secret, headersJson, body, toleranceSecs)`:
+/// whether the delivery is signed with `secret`. An unknown provider or an
+/// empty secret throws. Logged like kv writes, since the timestamp check
+/// reads the clock and a replay must reach the same verdict.
+fn native_webhook_verify(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let provider = v8_to_string(scope, args.get(0));
+    let secret = v8_to_string(scope, args.get(1));
+    let headers: HashMap<String, String> = serde_json::from_str(&v8_to_string(scope, args.get(2))).unwrap_or_default();
+    let body = buffer_source(scope, args.get(3)).unwrap_or_default();
+    let tolerance = args.get(4).integer_value(scope).filter(|n| *n >= 0).map_or(300, |n| n as u64);
+    let check = || {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let header = |name: &str| headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str());
+        match crate::webhooks::verify(&provider, secret.as_bytes(), header, &body, tolerance, now) {
+            Ok(()) => Ok("true".to_string()),
+            Err(e @ (crate::webhooks::WebhookError::UnknownProvider(_) | crate::webhooks::WebhookError::NoSecret)) => Err(e.to_string()),
+            Err(e) => {
+                tracing::debug!("{} webhook rejected: {}", provider, e);
+                Ok("false".to_string())
+            }
+        }
+    };
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let verdict = if runtime_ptr.is_null() {
+        check()
+    } else {
+        let request_id = current_request_id(scope);
+        ReplayLog::once(unsafe { &mut (*runtime_ptr).replay_logs }, request_id, check)
+    };
+    match verdict {
+        Ok(valid) => retval.set_bool(valid == "true"),
+        Err(e) => throw(scope, &format!("webhooks.verify(): {}", e)),
+    }
+}
+
+/// `t._config_get(key)`: a key declared in titan.config.toml, or undefined
+/// when it's optional and unset.
+fn native_config_get(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let key = v8_to_string(scope, args.get(0));
+    match crate::config::get(&key) {
+        None => throw(scope, &format!("config.get(): '{}' is not declared in {}", key, crate::config::FILE)),
+        Some(None) => retval.set(v8::undefined(scope).into()),
+        Some(Some(value)) => {
+            let json = v8_str(scope, &value.to_string());
+            if let Some(val) = v8::json::parse(scope, json) {
+                retval.set(val);
+            }
+        }
+    }
+}
+
+/// `t._rate_limit(key, optionsJson)`: counts a hit against `key` and returns
+/// the verdict as JSON text. Logged like kv writes, so replays don't count twice.
+fn native_rate_limit(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let key = v8_to_string(scope, args.get(0));
+    let options: Value = serde_json::from_str(&v8_to_string(scope, args.get(1))).unwrap_or_default();
+    let Some(policy) = crate::rate_limit::Policy::with_overrides(None, &options) else {
+        throw(scope, "t.rateLimit(): options.limit must be a positive number");
+        return;
+    };
+    let check = || {
+        let outcome = crate::rate_limit::check("js", &key, &policy);
+        let headers: serde_json::Map<String, Value> =
+            outcome.headers(&policy).into_iter().map(|(name, value)| (name, Value::String(value))).collect();
+        Ok(serde_json::json!({
+            "allowed": outcome.allowed,
+            "limit": outcome.limit,
+            "remaining": outcome.remaining,
+            "reset": outcome.reset.as_millis() as u64,
+            "headers": headers,
+        })
+        .to_string())
+    };
+    // No runtime yet at module scope, where nothing is replayed anyway
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let result = if runtime_ptr.is_null() {
+        check()
+    } else {
+        let request_id = current_request_id(scope);
+        ReplayLog::once(unsafe { &mut (*runtime_ptr).replay_logs }, request_id, check)
+    };
+    if let Ok(json) = result {
+        let json_val = v8_str(scope, &json);
+        retval.set(json_val.into());
+    }
+}
+
+/// `t._cache_purge(pathOrTag)`: drops cached responses and returns how many
+/// went. Logged like kv writes, so a replay reports the same count.
+fn native_cache_purge(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let target = v8_to_string(scope, args.get(0));
+    let purge = || Ok::<_, String>(crate::cache::ResponseCache::get().map_or(0, |cache| cache.purge(&target)).to_string());
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let result = if runtime_ptr.is_null() {
+        purge()
+    } else {
+        let request_id = current_request_id(scope);
+        ReplayLog::once(unsafe { &mut (*runtime_ptr).replay_logs }, request_id, purge)
+    };
+    if let Ok(count) = result {
+        let count = count.parse::<f64>().unwrap_or(0.0);
+        retval.set(v8::Number::new(scope, count).into());
+    }
+}
+
+/// `t._render(name, dataJson)`: the template `name` rendered with `data`.
+fn native_render(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let name = v8_to_string(scope, args.get(0));
+    let data: Value = serde_json::from_str(&v8_to_string(scope, args.get(1))).unwrap_or_default();
+    match crate::views::render(&name, &data) {
+        Ok(html) => retval.set(v8_str(scope, &html).into()),
+        Err(e) => throw(scope, &format!("t.render(): {}", e)),
+    }
+}
+
+/// `t._cookie_sign(name, value)`: the signed form of a cookie value, or null
+/// when no `cookies.secret` is configured.
+fn native_cookie_sign(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let name = v8_to_string(scope, args.get(0));
+    let value = v8_to_string(scope, args.get(1));
+    match crate::cookies::sign(&name, &value) {
+        Some(signed) => {
+            let signed = v8_str(scope, &signed);
+            retval.set(signed.into());
+        }
+        None => retval.set_null(),
+    }
+}
+
+fn native_bus_publish(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
+    let topic = v8_to_string(scope, args.get(0));
+    let payload = v8_to_string(scope, args.get(1));
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    if runtime_ptr.is_null() {
+        crate::bus::publish(topic, payload);
+        return;
+    }
+    let runtime = unsafe { &mut *runtime_ptr };
+    let request_id = current_request_id(scope);
+    let _ = ReplayLog::once(&mut runtime.replay_logs, request_id, || {
+        crate::bus::publish(topic, payload);
+        Ok(String::new())
+    });
+}
+
+fn native_bus_subscribe(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
+    let subscription_id = args.get(0).uint32_value(scope).unwrap_or(0);
+    let topic = v8_to_string(scope, args.get(1));
+    let request_id = args.get(2).uint32_value(scope).unwrap_or(0);
+    let socket_id = args.get(3).is_uint32().then(|| args.get(3).uint32_value(scope).unwrap_or(0));
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    if runtime_ptr.is_null() {
+        // Module code runs before the worker exists, or while building the snapshot
+        throw(scope, "bus.subscribe() can only be called from an action");
+        return;
+    }
+    let runtime = unsafe { &mut *runtime_ptr };
+
+    runtime.bus_topics.add(&topic);
+    runtime.bus_subscriptions.insert(subscription_id, super::BusSubscription { topic, request_id, socket_id });
+}
+
+fn native_bus_unsubscribe(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
+    let subscription_id = args.get(0).uint32_value(scope).unwrap_or(0);
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    if runtime_ptr.is_null() {
+        return;
+    }
+    let runtime = unsafe { &mut *runtime_ptr };
+    if let Some(sub) = runtime.bus_subscriptions.remove(&subscription_id) {
+        runtime.bus_topics.remove(&sub.topic);
+    }
+}
+
+/// Opens the response stream of `request_id` on first use: the status and
+/// headers are sent to the HTTP layer right away and the body follows through
+/// the channel.
+fn open_response_stream(
+    scope: &mut v8::HandleScope,
+    runtime: &mut TitanRuntime,
+    request_id: u32,
+    head: v8::Local<v8::Value>,
+) {
+    if runtime.streams.contains_key(&request_id) {
+        return;
+    }
+    let Some(response_tx) = runtime.pending_requests.remove(&request_id) else {
+        return;
+    };
+
+    let (status, headers) = read_head(scope, head);
+    let (tx, rx) = super::stream_channel();
+    let timings = runtime.request_timings.get(&request_id).cloned().unwrap_or_default();
+
+    let _ = response_tx.send(Ok(WorkerResult { status, headers, body: ResponseBody::Stream(rx), timings }));
+    runtime.streams.insert(request_id, super::ResponseStream { tx: Some(tx), sent: 0, cursor: 0, backed_up: Vec::new() });
+}
+
+/// `t._stream_write(requestId, chunk, head)`: false once the client is more
+/// than `STREAM_HIGH_WATER` bytes behind, or gone. The chunk is queued either
+/// way; the worker never waits for the client.
+fn native_stream_write(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let request_id = args.get(0).uint32_value(scope).unwrap_or(0);
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+
+    open_response_stream(scope, runtime, request_id, args.get(2));
+    let chunk = chunk_from_v8(scope, args.get(1));
+
+    let Some(stream) = runtime.streams.get_mut(&request_id) else {
+        throw(scope, "res.write(): response already finished");
+        return;
+    };
+    stream.cursor += 1;
+    if stream.cursor <= stream.sent {
+        // Already written before the last drift suspension
+        retval.set_bool(!stream.backed_up.contains(&stream.cursor));
+        return;
+    }
+    stream.sent += 1;
+
+    let queued = stream.tx.as_ref().and_then(|tx| tx.send(chunk));
+    let cut_off = queued.is_some_and(|queued| queued > super::STREAM_MAX_QUEUED);
+    let writable = queued.is_some_and(|queued| queued <= super::STREAM_HIGH_WATER);
+    // The client went away, or fell so far behind that it never catches up
+    if queued.is_none() || cut_off {
+        stream.tx = None;
+    }
+    if !writable {
+        stream.backed_up.push(stream.cursor);
+    }
+    retval.set_bool(writable);
+    if cut_off {
+        let request_id = super::correlation_id(runtime, request_id);
+        tracing::warn!(worker = runtime.id, request_id = request_id.as_deref(), "Streamed response cut off: the client is over {} MB behind", super::STREAM_MAX_QUEUED >> 20);
+    }
+}
+
+/// `t._stream_drain(requestId)`: a promise for `res.drain()`, resolved once
+/// the client has caught up to under `STREAM_HIGH_WATER` bytes. A finished
+/// or abandoned stream resolves at once so nothing waits on it forever.
+fn native_stream_drain(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, mut retval: v8::ReturnValue) {
+    let request_id = args.get(0).uint32_value(scope).unwrap_or(0);
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+
+    let resolver = v8::PromiseResolver::new(scope).unwrap();
+    let promise = resolver.get_promise(scope);
+    retval.set(promise.into());
+
+    let tx = runtime.streams.get(&request_id).and_then(|stream| stream.tx.as_ref());
+    let Some(backlog) = tx.filter(|tx| tx.queued() > super::STREAM_HIGH_WATER).map(|tx| tx.backlog()) else {
+        let undefined = v8::undefined(scope);
+        resolver.resolve(scope, undefined.into());
+        return;
+    };
+
+    // Settled through the worker loop like any other `t._async_start` op
+    runtime.op_counter = runtime.op_counter.wrapping_add(1);
+    let op_id = runtime.op_counter;
+    runtime.pending_ops.insert(op_id, super::PendingOp { resolver: v8::Global::new(scope, resolver), request_id });
+
+    let worker_tx = runtime.worker_tx.clone();
+    runtime.tokio_handle.spawn(async move {
+        backlog.drained().await;
+        let outcome = super::AsyncOutcome::Json(Value::Null);
+        let _ = worker_tx.send(crate::runtime::WorkerCommand::Settle { op_id, outcome });
+    });
+}
+
+fn native_stream_end(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
+    let request_id = args.get(0).uint32_value(scope).unwrap_or(0);
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+
+    open_response_stream(scope, runtime, request_id, args.get(1));
+    if let Some(stream) = runtime.streams.get_mut(&request_id) {
+        stream.tx = None;
+    }
+}
+
+/// `t._trace_span(traceId, spanId, parentId, name, startMs, endMs, error)`,
+/// called by `trace.span()` once the wrapped function settles.
+fn native_trace_span(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
+    let at_ms = |scope: &mut v8::HandleScope, i: i32| {
+        let ms = args.get(i).number_value(scope).unwrap_or(0.0).max(0.0);
+        UNIX_EPOCH + std::time::Duration::from_micros((ms * 1000.0) as u64)
+    };
+    let start = at_ms(scope, 4);
+    let end = at_ms(scope, 5);
+    let error = args.get(6);
+
+    crate::telemetry::record(crate::telemetry::SpanRecord {
+        trace_id: v8_to_string(scope, args.get(0)),
+        span_id: v8_to_string(scope, args.get(1)),
+        parent_id: Some(v8_to_string(scope, args.get(2))),
+        name: v8_to_string(scope, args.get(3)),
+        server: false,
+        start,
+        end,
+        error: (!error.is_null_or_undefined()).then(|| v8_to_string(scope, error)),
+        attributes: Vec::new(),
+    });
+}
+
+fn native_ws_send(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
+    let socket_id = args.get(0).uint32_value(scope).unwrap_or(0);
+    let data = args.get(1);
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+
+    let message = if data.is_string() {
+        crate::websocket::WsMessage::Text(v8_to_string(scope, data))
+    } else if data.is_array_buffer() || data.is_array_buffer_view() {
+        crate::websocket::WsMessage::Binary(chunk_from_v8(scope, data))
+    } else {
+        crate::websocket::WsMessage::Text(String::from_utf8_lossy(&chunk_from_v8(scope, data)).into_owned())
+    };
+
+    match runtime.sockets.get(&socket_id) {
+        Some(tx) => { let _ = tx.send(message); },
+        None => throw(scope, "socket.send(): connection is closed"),
+    }
+}
+
+fn native_ws_close(scope: &mut v8::HandleScope, mut args: v8::FunctionCallbackArguments, _retval: v8::ReturnValue) {
+    let socket_id = args.get(0).uint32_value(scope).unwrap_or(0);
+    let runtime_ptr = unsafe { args.get_isolate() }.get_data(0) as *mut super::TitanRuntime;
+    let runtime = unsafe { &mut *runtime_ptr };
+
+    // The connection task reports back with SocketClose, which fires onClose
+    if let Some(tx) = runtime.sockets.get(&socket_id) {
+        let _ = tx.send(crate::websocket::WsMessage::Close);
+    }
+}
+
+/// Sends a fetch behind its host's circuit breaker. Fails with the op's
+/// `{ error }` result: the request's own error, or the open circuit's.
+async fn send_fetch(
+    url: String,
+    method: String,
+    body: Option<bytes::Bytes>,
+    headers: Vec<(String, String)>,
+    timeout_ms: Option<u64>,
+) -> Result<reqwest::Response, Value> {
+    let target = crate::breaker::fetch_target(&url);
+    let run = send_request(url, method, body, headers, timeout_ms);
+    // Server errors count against the host; anything it answered below 500 is healthy
+    guarded(target, run, |res| res.as_ref().is_ok_and(|res| !res.status().is_server_error()))
+        .await?
+        .map_err(|e| serde_json::json!({ "error": e.to_string() }))
+}
+
+// Runs an outbound op through the circuit for `target`, when it has one
+async fn guarded<T>(target: Option<String>, op: impl std::future::Future<Output = T>, healthy: impl FnOnce(&T) -> bool) -> Result<T, Value> {
+    let Some(target) = target else {
+        return Ok(op.await);
+    };
+    crate::breaker::admit(&target).map_err(|open| open.to_json())?;
+    let result = op.await;
+    crate::breaker::record(&target, healthy(&result));
+    Ok(result)
+}
+
+async fn send_request(
+    url: String,
+    method: String,
+    body: Option<bytes::Bytes>,
+    headers: Vec<(String, String)>,
+    timeout_ms: Option<u64>,
+) -> reqwest::Result<reqwest::Response> {
+    let client = get_http_client();
+    let mut req = client.request(method.parse().unwrap_or(reqwest::Method::GET), &url);
+    if let Some(b) = body { req = req.body(b); }
+    for (k, v) in headers {
+        if let (Ok(name), Ok(val)) = (reqwest::header::HeaderName::from_bytes(k.as_bytes()), reqwest::header::HeaderValue::from_str(&v)) {
+            req = req.header(name, val);
+        }
+    }
+    // Covers the whole exchange, body included
+    if let Some(ms) = timeout_ms {
+        req = req.timeout(std::time::Duration::from_millis(ms));
+    }
+    req.send().await
+}
+
+fn response_headers(res: &reqwest::Response) -> Vec<(String, String)> {
+    res.headers()
+        .iter()
+        .filter_map(|(k, v)| Some((k.as_str().to_string(), v.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// Runs a `t._async_start` op. Fetches keep their body as raw bytes.
+async fn run_promise_op(op: super::TitanAsyncOp) -> super::AsyncOutcome {
+    if let super::TitanAsyncOp::BodyRead { id } = op {
+        return match crate::body::next_chunk(id).await {
+            Ok(Some(chunk)) => super::AsyncOutcome::Bytes(chunk),
+            Ok(None) => super::AsyncOutcome::Json(Value::Null),
+            Err(e) => super::AsyncOutcome::Json(serde_json::json!({ "error": e.to_string() })),
+        };
+    }
+    if let super::TitanAsyncOp::Fs(op) = op {
+        return crate::files::run(op).await;
+    }
+    let super::TitanAsyncOp::Fetch { url, method, body, headers, timeout_ms } = op else {
+        return super::AsyncOutcome::Json(run_async_operation(op).await);
+    };
+    let res = match send_fetch(url, method, body, headers, timeout_ms).await {
+        Ok(res) => res,
+        Err(error) => return super::AsyncOutcome::Json(error),
+    };
+    let status = res.status().as_u16();
+    let headers = response_headers(&res);
+    match res.bytes().await {
+        Ok(body) => super::AsyncOutcome::Response { status, headers, body },
+        Err(e) => super::AsyncOutcome::Json(serde_json::json!({ "error": e.to_string() })),
+    }
+}
+
+pub async fn run_single_op(op: super::TitanAsyncOp) -> serde_json::Value {
+    match op {
+        super::TitanAsyncOp::Fetch { url, method, body, headers, timeout_ms } => {
+            match send_fetch(url, method, body, headers, timeout_ms).await {
                 Ok(res) => {
                     let status = res.status().as_u16();
-                    let text = res.text().await.unwrap_or_default();
-                    serde_json::json!({ "status": status, "body": text, "ok": true })
+                    let headers: serde_json::Map<String, Value> = response_headers(&res)
+                        .into_iter()
+                        .map(|(k, v)| (k, Value::String(v)))
+                        .collect();
+                    match res.text().await {
+                        Ok(text) => serde_json::json!({ "status": status, "headers": headers, "body": text, "ok": true }),
+                        Err(e) => serde_json::json!({ "error": e.to_string(), "ok": false }),
+                    }
                 },
-                Err(e) => serde_json::json!({ "error": e.to_string(), "ok": false })
+                Err(mut error) => {
+                    error["ok"] = Value::Bool(false);
+                    error
+                }
             }
         },
-        super::TitanAsyncOp::FsRead { path } => {
-            let root = super::PROJECT_ROOT.get().cloned().unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
+        super::TitanAsyncOp::Redis { url, commands } => {
+            let run = crate::redis::pipeline(&url, commands);
+            // A command Redis rejected still came back from a working server
+            match guarded(crate::redis::target(&url), run, Result::is_ok).await {
+                Ok(Ok(replies)) => Value::Array(replies),
+                Ok(Err(e)) => serde_json::json!({ "error": e }),
+                Err(open) => open,
+            }
+        },
+        super::TitanAsyncOp::FsRead { root, path } => {
             let joined = root.join(&path);
             
             // Basic security check
@@ -813,43 +2172,13 @@ pub async fn run_single_op(op: super::TitanAsyncOp) -> serde_json::Value {
                 serde_json::json!({ "error": format!("File not found: {}", path) })
             }
         },
-        super::TitanAsyncOp::DbQuery { conn, query } => {
-            tokio::task::spawn_blocking(move || {
-                let mut pool = DB_POOL.lock().unwrap();
-                if let Some(map) = pool.as_mut() {
-                    if let Some(client) = map.get_mut(&conn) {
-                        return match client.query(&query, &[]) {
-                            Ok(rows) => {
-                                let mut result = Vec::new();
-                                for row in rows {
-                                    let mut obj = serde_json::Map::new();
-                                    for (i, column) in row.columns().iter().enumerate() {
-                                        let col_name = column.name();
-                                        let col_value: serde_json::Value = if let Ok(val) = row.try_get::<_, Option<String>>(i) {
-                                            serde_json::json!(val)
-                                        } else if let Ok(val) = row.try_get::<_, Option<i32>>(i) {
-                                            serde_json::json!(val)
-                                        } else if let Ok(val) = row.try_get::<_, Option<i64>>(i) {
-                                            serde_json::json!(val)
-                                        } else if let Ok(val) = row.try_get::<_, Option<f64>>(i) {
-                                            serde_json::json!(val)
-                                        } else if let Ok(val) = row.try_get::<_, Option<bool>>(i) {
-                                            serde_json::json!(val)
-                                        } else {
-                                            serde_json::Value::Null
-                                        };
-                                        obj.insert(col_name.to_string(), col_value);
-                                    }
-                                    result.push(serde_json::Value::Object(obj));
-                                }
-                                serde_json::Value::Array(result)
-                            },
-                            Err(e) => serde_json::json!({ "error": e.to_string() })
-                        };
-                    }
-                }
-                serde_json::json!({ "error": "Database connection not found" })
-            }).await.unwrap_or_else(|e| serde_json::json!({ "error": e.to_string() }))
+        super::TitanAsyncOp::DbQuery { conn, query, params } => {
+            let run = crate::db::query(&conn, &query, &params);
+            match guarded(crate::db::target(&conn), run, |r| !matches!(r, Err(e) if e.unavailable)).await {
+                Ok(Ok(rows)) => rows,
+                Ok(Err(e)) => serde_json::json!({ "error": e.message }),
+                Err(open) => open,
+            }
         },
         _ => serde_json::json!({ "error": "Invalid operation" })
     }
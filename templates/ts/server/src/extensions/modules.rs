@@ -0,0 +1,362 @@
+use dashmap::DashMap;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
+
+use super::v8_str;
+use crate::{sourcemap, transpile};
+
+// Files tried for an import without an extension, in order
+const EXTENSIONS: [&str; 3] = ["js", "mjs", "ts"];
+// `exports` conditions we match, most preferred first
+const CONDITIONS: [&str; 4] = ["import", "module", "worker", "default"];
+
+// Shared by every worker, so only the first isolate to load an action walks
+// the filesystem. (importing directory, specifier) -> module key
+static RESOLVED: OnceLock<DashMap<(PathBuf, String), String>> = OnceLock::new();
+static SOURCES: OnceLock<DashMap<String, Arc<Source>>> = OnceLock::new();
+
+/// A module's code as V8 compiles it, i.e. after transpiling `.ts`.
+struct Source {
+    code: String,
+    resource: String,
+    modified: Option<SystemTime>,
+}
+
+/// The modules compiled into this thread's isolate while actions load. Kept
+/// only for the duration of `load_actions`: V8 resolves imports through it
+/// during instantiation, and a snapshot can't be taken while it holds handles.
+#[derive(Default)]
+struct Graph {
+    modules: HashMap<String, v8::Global<v8::Module>>,
+    keys: HashMap<i32, String>,
+}
+
+thread_local! {
+    static GRAPH: RefCell<Graph> = RefCell::default();
+}
+
+/// Drops the handles of the current graph.
+pub fn reset() {
+    GRAPH.with(|g| *g.borrow_mut() = Graph::default());
+}
+
+/// Loads the action at `path` as an ES module with everything it imports,
+/// evaluates it and registers its export on `globalThis` as `name`, like a
+/// bundle's footer does.
+pub fn load_action(scope: &mut v8::HandleScope, path: &Path, root: &Path, name: &str) -> Result<(), String> {
+    let entry = canonical(path)?;
+    let module = compile_graph(scope, &entry, root)?;
+
+    let try_catch = &mut v8::TryCatch::new(scope);
+    if module.instantiate_module(try_catch, resolve_callback).is_none() {
+        return Err(exception(try_catch));
+    }
+    let result = module.evaluate(try_catch).ok_or_else(|| exception(try_catch))?;
+    // Top-level await resolves on the microtask queue
+    try_catch.perform_microtask_checkpoint();
+    if let Ok(promise) = v8::Local::<v8::Promise>::try_from(result) {
+        match promise.state() {
+            v8::PromiseState::Rejected => {
+                let reason = promise.result(try_catch);
+                return Err(sourcemap::rewrite(&reason.to_rust_string_lossy(try_catch)));
+            }
+            v8::PromiseState::Pending => return Err("top-level await did not settle while loading".to_string()),
+            v8::PromiseState::Fulfilled => {}
+        }
+    }
+
+    let namespace = module
+        .get_module_namespace()
+        .to_object(try_catch)
+        .ok_or_else(|| "module has no namespace".to_string())?;
+    let export_name = name.rsplit('/').next().unwrap_or(name);
+    let named = v8_str(try_catch, export_name);
+    let default = v8_str(try_catch, "default");
+    let action = namespace
+        .get(try_catch, named.into())
+        .filter(|v| v.is_function())
+        .or_else(|| namespace.get(try_catch, default.into()).filter(|v| v.is_function()))
+        .ok_or_else(|| format!("Action '{}' not found or not a function", name))?;
+
+    // `export const schema` describes the action in the OpenAPI document
+    let schema_key = v8_str(try_catch, "schema");
+    let schema = namespace
+        .get(try_catch, schema_key.into())
+        .unwrap_or_else(|| v8::undefined(try_catch).into());
+
+    // `export const config` tunes how it is run, e.g. its concurrency
+    let config_key = v8_str(try_catch, "config");
+    let config = namespace
+        .get(try_catch, config_key.into())
+        .unwrap_or_else(|| v8::undefined(try_catch).into());
+
+    let global = try_catch.get_current_context().global(try_catch);
+    let define_key = v8_str(try_catch, "defineAction");
+    let action = match global.get(try_catch, define_key.into()).and_then(|f| v8::Local::<v8::Function>::try_from(f).ok()) {
+        Some(define) => define.call(try_catch, global.into(), &[action, schema, config]).ok_or_else(|| exception(try_catch))?,
+        None => action,
+    };
+    let key = v8_str(try_catch, name);
+    global.set(try_catch, key.into(), action);
+    Ok(())
+}
+
+/// Compiles `entry` and every module it reaches that isn't compiled yet.
+fn compile_graph<'s>(scope: &mut v8::HandleScope<'s>, entry: &str, root: &Path) -> Result<v8::Local<'s, v8::Module>, String> {
+    let mut pending = vec![entry.to_string()];
+    while let Some(key) = pending.pop() {
+        if GRAPH.with(|g| g.borrow().modules.contains_key(&key)) {
+            continue;
+        }
+        let module = compile(scope, &key, root)?;
+        let requests = module.get_module_requests();
+        for i in 0..requests.length() {
+            let Some(request) = requests
+                .get(scope, i)
+                .and_then(|r| v8::Local::<v8::ModuleRequest>::try_from(r).ok())
+            else {
+                continue;
+            };
+            let specifier = request.get_specifier().to_rust_string_lossy(scope);
+            pending.push(resolve(&specifier, &key).map_err(|e| format!("{} (imported from {})", e, key))?);
+        }
+        let global = v8::Global::new(scope, module);
+        GRAPH.with(|g| {
+            let mut g = g.borrow_mut();
+            g.keys.insert(module.get_identity_hash().get(), key.clone());
+            g.modules.insert(key, global);
+        });
+    }
+    GRAPH
+        .with(|g| g.borrow().modules.get(entry).cloned())
+        .map(|m| v8::Local::new(scope, m))
+        .ok_or_else(|| format!("{} was not compiled", entry))
+}
+
+fn compile<'s>(scope: &mut v8::HandleScope<'s>, key: &str, root: &Path) -> Result<v8::Local<'s, v8::Module>, String> {
+    let source = source_of(key, root)?;
+    let code = v8_str(scope, &source.code);
+    let resource = v8_str(scope, &source.resource);
+    let origin = v8::ScriptOrigin::new(scope, resource.into(), 0, 0, false, 0, None, false, false, true, None);
+    let mut source = v8::script_compiler::Source::new(code, Some(&origin));
+    let try_catch = &mut v8::TryCatch::new(scope);
+    v8::script_compiler::compile_module(try_catch, &mut source).ok_or_else(|| exception(try_catch))
+}
+
+/// The code of module `key`, from the cache unless the file changed.
+fn source_of(key: &str, root: &Path) -> Result<Arc<Source>, String> {
+    let sources = SOURCES.get_or_init(DashMap::new);
+    if let Some(name) = key.strip_prefix("node:") {
+        let code = builtin(name).ok_or_else(|| format!("'{}' is not available in Titan", key))?;
+        return Ok(Arc::new(Source { code: code.to_string(), resource: key.to_string(), modified: None }));
+    }
+
+    let path = Path::new(key);
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+    if let Some(source) = sources.get(key).filter(|s| s.modified == modified) {
+        return Ok(source.clone());
+    }
+    let compiled = if path.extension().is_some_and(|ext| ext == "ts") {
+        transpile::transpile(path, root)?
+    } else {
+        path.to_path_buf()
+    };
+    let code = fs::read_to_string(&compiled).map_err(|e| format!("{}: {}", key, e))?;
+    let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let resource = path.strip_prefix(&root).unwrap_or(path).to_string_lossy().replace('\\', "/");
+    sourcemap::register(&resource, &compiled, &root);
+
+    let source = Arc::new(Source { code, resource, modified });
+    sources.insert(key.to_string(), source.clone());
+    Ok(source)
+}
+
+/// Resolves `specifier` as imported by module `referrer` the way Node does
+/// for ESM, to a module key: a canonical file path, or `node:<name>` for
+/// builtins. Relative paths may leave out the extension or name a directory
+/// with an `index` file.
+fn resolve(specifier: &str, referrer: &str) -> Result<String, String> {
+    let dir = Path::new(referrer).parent().unwrap_or(Path::new(".")).to_path_buf();
+    let cache_key = (dir, specifier.to_string());
+    let cache = RESOLVED.get_or_init(DashMap::new);
+    if let Some(hit) = cache.get(&cache_key) {
+        return Ok(hit.clone());
+    }
+
+    let resolved = if let Some(name) = specifier.strip_prefix("node:") {
+        builtin(name)
+            .map(|_| specifier.to_string())
+            .ok_or_else(|| format!("'{}' is not available in Titan", specifier))?
+    } else if specifier.starts_with("./") || specifier.starts_with("../") || specifier.starts_with('/') {
+        probe(&cache_key.0.join(specifier)).ok_or_else(|| format!("Cannot find module '{}'", specifier))?
+    } else {
+        resolve_package(specifier, &cache_key.0)?
+    };
+    cache.insert(cache_key, resolved.clone());
+    Ok(resolved)
+}
+
+/// A bare specifier (`pkg`, `pkg/sub`, `@scope/pkg/sub`) from the nearest
+/// `node_modules` that has the package.
+fn resolve_package(specifier: &str, dir: &Path) -> Result<String, String> {
+    let split = if specifier.starts_with('@') {
+        specifier.match_indices('/').nth(1).map(|(i, _)| i)
+    } else {
+        specifier.find('/')
+    };
+    let (name, subpath) = match split {
+        Some(i) => (&specifier[..i], format!(".{}", &specifier[i..])),
+        None => (specifier, ".".to_string()),
+    };
+
+    for ancestor in dir.ancestors() {
+        let package = ancestor.join("node_modules").join(name);
+        let Ok(manifest) = fs::read_to_string(package.join("package.json")) else {
+            continue;
+        };
+        let manifest: Value =
+            serde_json::from_str(&manifest).map_err(|e| format!("{}/package.json: {}", package.display(), e))?;
+
+        // Whether the field that picked the file says it is ESM
+        let (file, esm_hint) = if !manifest["exports"].is_null() {
+            let (target, esm) = exports_target(&manifest["exports"], &subpath)
+                .ok_or_else(|| format!("'{}' is not exported by package '{}'", subpath, name))?;
+            let file = package.join(target.trim_start_matches("./"));
+            (file.is_file().then(|| canonical(&file).ok()).flatten(), esm)
+        } else if subpath != "." {
+            (probe(&package.join(&subpath[2..])), false)
+        } else if let Some(module) = manifest["module"].as_str() {
+            (probe(&package.join(module)), true)
+        } else {
+            let main = manifest["main"].as_str().unwrap_or("index");
+            (probe(&package.join(main)), false)
+        };
+        let file = file.ok_or_else(|| format!("Cannot find module '{}'", specifier))?;
+
+        let esm = match Path::new(&file).extension().and_then(|e| e.to_str()) {
+            Some("mjs") => true,
+            Some("js") => esm_hint || is_module_scope(Path::new(&file), &package),
+            _ => false,
+        };
+        if !esm {
+            return Err(format!("'{}' is CommonJS; only ES modules can be imported", specifier));
+        }
+        return Ok(file);
+    }
+    Err(format!("Cannot find package '{}'", name))
+}
+
+/// The target of `subpath` in a package's `exports`, and whether it was
+/// picked by an ESM-only condition.
+fn exports_target(exports: &Value, subpath: &str) -> Option<(String, bool)> {
+    // `"exports": "./index.js"`, or conditions only, describe "."
+    let subpaths = match exports {
+        Value::Object(map) if map.keys().all(|k| k.starts_with('.')) => map,
+        _ => return (subpath == ".").then(|| conditional(exports, false)).flatten(),
+    };
+    if let Some(target) = subpaths.get(subpath) {
+        return conditional(target, false);
+    }
+    // "./features/*": "./src/features/*.js"
+    subpaths.iter().find_map(|(pattern, target)| {
+        let (prefix, suffix) = pattern.split_once('*')?;
+        let matched = subpath.strip_prefix(prefix)?.strip_suffix(suffix)?;
+        conditional(target, false).map(|(t, esm)| (t.replace('*', matched), esm))
+    })
+}
+
+fn conditional(target: &Value, esm: bool) -> Option<(String, bool)> {
+    match target {
+        Value::String(path) => Some((path.clone(), esm)),
+        Value::Array(targets) => targets.iter().find_map(|t| conditional(t, esm)),
+        Value::Object(conditions) => CONDITIONS.iter().find_map(|c| {
+            conditions.get(*c).and_then(|t| conditional(t, esm || *c == "import" || *c == "module"))
+        }),
+        _ => None,
+    }
+}
+
+/// Whether the nearest `package.json` above `file`, up to the package root,
+/// declares `"type": "module"`.
+fn is_module_scope(file: &Path, package: &Path) -> bool {
+    for dir in file.ancestors().skip(1) {
+        if let Ok(manifest) = fs::read_to_string(dir.join("package.json")) {
+            return serde_json::from_str::<Value>(&manifest).is_ok_and(|m| m["type"] == "module");
+        }
+        if dir == package {
+            break;
+        }
+    }
+    false
+}
+
+fn probe(path: &Path) -> Option<String> {
+    let with_ext = |ext: &str| {
+        let mut file = path.as_os_str().to_owned();
+        file.push(".");
+        file.push(ext);
+        PathBuf::from(file)
+    };
+    std::iter::once(path.to_path_buf())
+        .chain(EXTENSIONS.iter().map(|ext| with_ext(ext)))
+        .chain(EXTENSIONS.iter().map(|ext| path.join(format!("index.{}", ext))))
+        .find(|p| p.is_file())
+        .and_then(|p| canonical(&p).ok())
+}
+
+fn canonical(path: &Path) -> Result<String, String> {
+    fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().into_owned())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// The `node:` modules Titan provides, as ESM over its own globals.
+fn builtin(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "process" => "export default globalThis.process; export const { env } = globalThis.process;",
+        "timers" => {
+            "export const { setTimeout, setInterval, clearTimeout, clearInterval } = globalThis; \
+             export default { setTimeout, setInterval, clearTimeout, clearInterval };"
+        }
+        "util" => "export const { TextDecoder } = globalThis; export default { TextDecoder };",
+        _ => return None,
+    })
+}
+
+fn resolve_callback<'s>(
+    context: v8::Local<'s, v8::Context>,
+    specifier: v8::Local<'s, v8::String>,
+    _attributes: v8::Local<'s, v8::FixedArray>,
+    referrer: v8::Local<'s, v8::Module>,
+) -> Option<v8::Local<'s, v8::Module>> {
+    let scope = &mut unsafe { v8::CallbackScope::new(context) };
+    let specifier = specifier.to_rust_string_lossy(scope);
+    let hash = referrer.get_identity_hash().get();
+    let module = GRAPH.with(|g| {
+        let g = g.borrow();
+        let key = resolve(&specifier, g.keys.get(&hash)?).ok()?;
+        g.modules.get(&key).cloned()
+    });
+    match module {
+        Some(module) => Some(v8::Local::new(scope, module)),
+        None => {
+            let message = v8_str(scope, &format!("Cannot resolve '{}'", specifier));
+            let error = v8::Exception::error(scope, message);
+            scope.throw_exception(error);
+            None
+        }
+    }
+}
+
+fn exception(try_catch: &mut v8::TryCatch<v8::HandleScope>) -> String {
+    try_catch
+        .stack_trace()
+        .or_else(|| try_catch.exception())
+        .map(|e| sourcemap::rewrite(&e.to_rust_string_lossy(try_catch)))
+        .unwrap_or_else(|| "Unknown error".to_string())
+}
@@ -0,0 +1,150 @@
+use bytes::Bytes;
+use serde_json::{Value, json};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::UNIX_EPOCH;
+
+use crate::extensions::AsyncOutcome;
+
+static SANDBOX: OnceLock<Sandbox> = OnceLock::new();
+
+struct Sandbox {
+    root: PathBuf,
+    allowed: Vec<PathBuf>,
+}
+
+/// One `fs.*` call from JS.
+pub enum FsOp {
+    ReadFile { path: String, text: bool },
+    WriteFile { path: String, data: Bytes, append: bool },
+    ReadDir { path: String },
+    Stat { path: String },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FsError {
+    #[error("no directories are allowed; list them in fs.allow")]
+    Disabled,
+    #[error("'{0}' is outside the directories in fs.allow")]
+    Denied(String),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// The `fs` block of titan.config: `allow` lists the directories under the
+/// project root that actions may read and write. Without it `fs` is off.
+pub fn configure(config: &Value, root: &Path) {
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let allowed = config["allow"]
+        .as_array()
+        .map(|dirs| {
+            dirs.iter()
+                .filter_map(Value::as_str)
+                .filter_map(|dir| {
+                    let path = normalize(&root.join(dir));
+                    if !path.starts_with(&root) {
+                        tracing::warn!("fs.allow: '{}' is outside the project, ignored", dir);
+                        return None;
+                    }
+                    Some(path.canonicalize().unwrap_or(path))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let _ = SANDBOX.set(Sandbox { root, allowed });
+}
+
+/// Runs `op` on tokio's blocking pool, so the worker's V8 thread carries on
+/// with other requests and the promise settles through its event loop.
+pub async fn run(op: FsOp) -> AsyncOutcome {
+    match tokio::task::spawn_blocking(move || execute(op)).await {
+        Ok(Ok(outcome)) => outcome,
+        Ok(Err(e)) => AsyncOutcome::Json(json!({ "error": e.to_string() })),
+        Err(e) => AsyncOutcome::Json(json!({ "error": e.to_string() })),
+    }
+}
+
+fn execute(op: FsOp) -> Result<AsyncOutcome, FsError> {
+    Ok(match op {
+        FsOp::ReadFile { path, text } => {
+            let data = std::fs::read(resolve(&path)?)?;
+            if text {
+                AsyncOutcome::Json(Value::String(String::from_utf8_lossy(&data).into_owned()))
+            } else {
+                AsyncOutcome::Bytes(Bytes::from(data))
+            }
+        }
+        FsOp::WriteFile { path, data, append } => {
+            let target = resolve(&path)?;
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            if append {
+                OpenOptions::new().create(true).append(true).open(&target)?.write_all(&data)?;
+            } else {
+                std::fs::write(&target, &data)?;
+            }
+            AsyncOutcome::Json(Value::Null)
+        }
+        FsOp::ReadDir { path } => {
+            let mut entries = Vec::new();
+            for entry in std::fs::read_dir(resolve(&path)?)? {
+                let entry = entry?;
+                let kind = entry.file_type()?;
+                entries.push(json!({
+                    "name": entry.file_name().to_string_lossy(),
+                    "isFile": kind.is_file(),
+                    "isDir": kind.is_dir(),
+                }));
+            }
+            entries.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+            AsyncOutcome::Json(Value::Array(entries))
+        }
+        FsOp::Stat { path } => {
+            let meta = std::fs::metadata(resolve(&path)?)?;
+            let modified = meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_millis() as u64);
+            AsyncOutcome::Json(json!({
+                "size": meta.len(),
+                "isFile": meta.is_file(),
+                "isDir": meta.is_dir(),
+                "modified": modified,
+            }))
+        }
+    })
+}
+
+/// `path`, relative to the project root, if it lies in an allowed directory.
+/// The deepest part that exists is resolved, so a symlink can't lead out.
+fn resolve(path: &str) -> Result<PathBuf, FsError> {
+    let sandbox = SANDBOX.get().filter(|s| !s.allowed.is_empty()).ok_or(FsError::Disabled)?;
+    let target = normalize(&sandbox.root.join(path));
+    let real = target
+        .ancestors()
+        .find_map(|existing| {
+            let rest = target.strip_prefix(existing).ok()?;
+            existing.canonicalize().ok().map(|real| real.join(rest))
+        })
+        .unwrap_or_else(|| target.clone());
+    if sandbox.allowed.iter().any(|dir| real.starts_with(dir)) {
+        Ok(real)
+    } else {
+        Err(FsError::Denied(path.to_string()))
+    }
+}
+
+// Resolves `.` and `..` without touching the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
@@ -0,0 +1,916 @@
+//! MessagePack and CBOR bodies. Requests are decoded straight into V8 values
+//! in the worker, with no JSON text in between; JSON results are encoded in
+//! whichever of the formats the client's `Accept` prefers.
+
+use serde_json::Value;
+
+// Containers nested deeper than this are refused
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MsgPack,
+    Cbor,
+}
+
+impl Format {
+    /// The format a `Content-Type` names, if it is one.
+    pub fn from_content_type(content_type: &str) -> Option<Format> {
+        match content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase().as_str() {
+            "application/json" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(Format::MsgPack),
+            "application/cbor" => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    /// The binary format `Accept` ranks above JSON, if any. Wildcards count
+    /// as JSON, so clients only get a binary body when they ask for it.
+    pub fn negotiate(accept: &str) -> Format {
+        let mut best = (Format::Json, 0.0);
+        let mut json = 0.0f32;
+        for range in accept.split(',') {
+            let mut parts = range.split(';');
+            let media = parts.next().unwrap_or("").trim();
+            let q = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            match Self::from_content_type(media) {
+                Some(Format::Json) => json = json.max(q),
+                Some(format) if q > best.1 => best = (format, q),
+                Some(_) => {}
+                None if media == "*/*" || media == "application/*" => json = json.max(q),
+                None => {}
+            }
+        }
+        if best.1 > json { best.0 } else { Format::Json }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MsgPack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+        }
+    }
+
+    /// Checks a binary body without building anything, so the HTTP layer can
+    /// answer 400 before the request reaches a worker.
+    pub fn validate(self, bytes: &[u8]) -> Result<(), String> {
+        let mut reader = Reader { buf: bytes, depth: 0 };
+        match self {
+            Format::Json => return Ok(()),
+            Format::MsgPack => msgpack(&mut reader, &mut Check)?,
+            Format::Cbor => cbor(&mut reader, &mut Check)?,
+        }
+        if !reader.buf.is_empty() {
+            return Err("trailing bytes after the body".into());
+        }
+        Ok(())
+    }
+
+    /// Decodes a binary body into a V8 value. Integers past 2^53 become
+    /// BigInts, binary strings Uint8Arrays and timestamps Dates.
+    pub fn to_v8<'s>(self, scope: &mut v8::HandleScope<'s>, bytes: &[u8]) -> Result<v8::Local<'s, v8::Value>, String> {
+        let mut reader = Reader { buf: bytes, depth: 0 };
+        let mut builder = V8 { scope };
+        match self {
+            Format::Json => Err("JSON is parsed by V8".into()),
+            Format::MsgPack => msgpack(&mut reader, &mut builder),
+            Format::Cbor => cbor(&mut reader, &mut builder),
+        }
+    }
+
+    /// Decodes a body into JSON, for checks made before it reaches a worker.
+    /// Binary strings become arrays of bytes and timestamps milliseconds.
+    pub fn to_json(self, bytes: &[u8]) -> Result<Value, String> {
+        let mut reader = Reader { buf: bytes, depth: 0 };
+        match self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Format::MsgPack => msgpack(&mut reader, &mut Json),
+            Format::Cbor => cbor(&mut reader, &mut Json),
+        }
+    }
+
+    /// Encodes a result; JSON as text, the binary formats as bytes.
+    pub fn encode(self, value: &Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Format::Json => out = value.to_string().into_bytes(),
+            Format::MsgPack => encode_msgpack(value, &mut out),
+            Format::Cbor => encode_cbor(value, &mut out),
+        }
+        out
+    }
+}
+
+// What a decoder builds from the values it reads
+trait Build {
+    type Out;
+    fn null(&mut self) -> Self::Out;
+    fn undefined(&mut self) -> Self::Out;
+    fn bool(&mut self, b: bool) -> Self::Out;
+    fn int(&mut self, n: i64) -> Self::Out;
+    fn uint(&mut self, n: u64) -> Self::Out;
+    fn float(&mut self, f: f64) -> Self::Out;
+    fn str(&mut self, s: &str) -> Self::Out;
+    fn bytes(&mut self, b: &[u8]) -> Self::Out;
+    fn date(&mut self, ms: f64) -> Self::Out;
+    fn array(&mut self, items: Vec<Self::Out>) -> Self::Out;
+    fn map(&mut self, entries: Vec<(Self::Out, Self::Out)>) -> Self::Out;
+    /// A MessagePack extension other than timestamps.
+    fn ext(&mut self, kind: i8, data: &[u8]) -> Self::Out;
+}
+
+struct Check;
+
+impl Build for Check {
+    type Out = ();
+    fn null(&mut self) {}
+    fn undefined(&mut self) {}
+    fn bool(&mut self, _: bool) {}
+    fn int(&mut self, _: i64) {}
+    fn uint(&mut self, _: u64) {}
+    fn float(&mut self, _: f64) {}
+    fn str(&mut self, _: &str) {}
+    fn bytes(&mut self, _: &[u8]) {}
+    fn date(&mut self, _: f64) {}
+    fn array(&mut self, _: Vec<()>) {}
+    fn map(&mut self, _: Vec<((), ())>) {}
+    fn ext(&mut self, _: i8, _: &[u8]) {}
+}
+
+struct Json;
+
+impl Build for Json {
+    type Out = Value;
+    fn null(&mut self) -> Value {
+        Value::Null
+    }
+    fn undefined(&mut self) -> Value {
+        Value::Null
+    }
+    fn bool(&mut self, b: bool) -> Value {
+        b.into()
+    }
+    fn int(&mut self, n: i64) -> Value {
+        n.into()
+    }
+    fn uint(&mut self, n: u64) -> Value {
+        n.into()
+    }
+    fn float(&mut self, f: f64) -> Value {
+        f.into()
+    }
+    fn str(&mut self, s: &str) -> Value {
+        s.into()
+    }
+    fn bytes(&mut self, b: &[u8]) -> Value {
+        b.iter().copied().map(Value::from).collect()
+    }
+    fn date(&mut self, ms: f64) -> Value {
+        ms.into()
+    }
+    fn array(&mut self, items: Vec<Value>) -> Value {
+        Value::Array(items)
+    }
+    fn map(&mut self, entries: Vec<(Value, Value)>) -> Value {
+        entries
+            .into_iter()
+            .map(|(k, v)| (k.as_str().map_or_else(|| k.to_string(), str::to_string), v))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+    fn ext(&mut self, kind: i8, data: &[u8]) -> Value {
+        serde_json::json!({ "type": kind, "data": self.bytes(data) })
+    }
+}
+
+struct V8<'a, 's> {
+    scope: &'a mut v8::HandleScope<'s>,
+}
+
+// Integers JS numbers hold exactly
+const SAFE_INT: u64 = (1 << 53) - 1;
+
+impl<'s> Build for V8<'_, 's> {
+    type Out = v8::Local<'s, v8::Value>;
+
+    fn null(&mut self) -> Self::Out {
+        v8::null(self.scope).into()
+    }
+
+    fn undefined(&mut self) -> Self::Out {
+        v8::undefined(self.scope).into()
+    }
+
+    fn bool(&mut self, b: bool) -> Self::Out {
+        v8::Boolean::new(self.scope, b).into()
+    }
+
+    fn int(&mut self, n: i64) -> Self::Out {
+        if n.unsigned_abs() <= SAFE_INT {
+            v8::Number::new(self.scope, n as f64).into()
+        } else {
+            v8::BigInt::new_from_i64(self.scope, n).into()
+        }
+    }
+
+    fn uint(&mut self, n: u64) -> Self::Out {
+        if n <= SAFE_INT {
+            v8::Number::new(self.scope, n as f64).into()
+        } else {
+            v8::BigInt::new_from_u64(self.scope, n).into()
+        }
+    }
+
+    fn float(&mut self, f: f64) -> Self::Out {
+        v8::Number::new(self.scope, f).into()
+    }
+
+    fn str(&mut self, s: &str) -> Self::Out {
+        v8::String::new(self.scope, s).map_or_else(|| v8::null(self.scope).into(), Into::into)
+    }
+
+    fn bytes(&mut self, b: &[u8]) -> Self::Out {
+        let store = v8::ArrayBuffer::new_backing_store_from_boxed_slice(b.to_vec().into_boxed_slice());
+        let buffer = v8::ArrayBuffer::with_backing_store(self.scope, &store.make_shared());
+        v8::Uint8Array::new(self.scope, buffer, 0, b.len()).map_or_else(|| buffer.into(), Into::into)
+    }
+
+    fn date(&mut self, ms: f64) -> Self::Out {
+        v8::Date::new(self.scope, ms).map_or_else(|| v8::null(self.scope).into(), Into::into)
+    }
+
+    fn array(&mut self, items: Vec<Self::Out>) -> Self::Out {
+        v8::Array::new_with_elements(self.scope, &items).into()
+    }
+
+    fn map(&mut self, entries: Vec<(Self::Out, Self::Out)>) -> Self::Out {
+        let obj = v8::Object::new(self.scope);
+        for (k, v) in entries {
+            obj.set(self.scope, k, v);
+        }
+        obj.into()
+    }
+
+    fn ext(&mut self, kind: i8, data: &[u8]) -> Self::Out {
+        let obj = v8::Object::new(self.scope);
+        let type_key = v8::String::new(self.scope, "type").unwrap();
+        let type_val = v8::Integer::new(self.scope, i32::from(kind));
+        obj.set(self.scope, type_key.into(), type_val.into());
+        let data_key = v8::String::new(self.scope, "data").unwrap();
+        let data_val = self.bytes(data);
+        obj.set(self.scope, data_key.into(), data_val);
+        obj.into()
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.buf.len() < n {
+            return Err("unexpected end of body".into());
+        }
+        let (head, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn be(&mut self, n: usize) -> Result<u64, String> {
+        Ok(self.take(n)?.iter().fold(0, |acc, b| (acc << 8) | u64::from(*b)))
+    }
+
+    // A length from the body, checked against what is left of it so a
+    // bogus one can't make us allocate
+    fn len(&mut self, n: usize) -> Result<usize, String> {
+        let len = self.be(n)?;
+        usize::try_from(len).ok().filter(|l| *l <= self.buf.len()).ok_or_else(|| "length runs past the end of the body".into())
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("body nested too deeply".into());
+        }
+        Ok(())
+    }
+}
+
+fn text(bytes: &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(bytes).map_err(|_| "string is not valid UTF-8".to_string())
+}
+
+// MessagePack ------------------------------------------------------------------
+
+fn msgpack<B: Build>(r: &mut Reader, b: &mut B) -> Result<B::Out, String> {
+    let tag = r.byte()?;
+    Ok(match tag {
+        0x00..=0x7f => b.uint(u64::from(tag)),
+        0xe0..=0xff => b.int(i64::from(tag as i8)),
+        0x80..=0x8f => msgpack_map(r, b, usize::from(tag & 0x0f))?,
+        0x90..=0x9f => msgpack_array(r, b, usize::from(tag & 0x0f))?,
+        0xa0..=0xbf => {
+            let s = r.take(usize::from(tag & 0x1f))?;
+            b.str(text(s)?)
+        }
+        0xc0 => b.null(),
+        0xc2 => b.bool(false),
+        0xc3 => b.bool(true),
+        0xc4..=0xc6 => {
+            let len = r.len(1 << (tag - 0xc4))?;
+            b.bytes(r.take(len)?)
+        }
+        0xc7..=0xc9 => {
+            let len = r.len(1 << (tag - 0xc7))?;
+            let kind = r.byte()? as i8;
+            msgpack_ext(b, kind, r.take(len)?)?
+        }
+        0xca => b.float(f64::from(f32::from_bits(r.be(4)? as u32))),
+        0xcb => b.float(f64::from_bits(r.be(8)?)),
+        0xcc..=0xcf => {
+            let n = r.be(1 << (tag - 0xcc))?;
+            b.uint(n)
+        }
+        0xd0..=0xd3 => {
+            let width = 1usize << (tag - 0xd0);
+            let n = r.be(width)?;
+            // Sign-extend from the width read
+            let shift = 64 - 8 * width as u32;
+            b.int(((n << shift) as i64) >> shift)
+        }
+        0xd4..=0xd8 => {
+            let kind = r.byte()? as i8;
+            msgpack_ext(b, kind, r.take(1 << (tag - 0xd4))?)?
+        }
+        0xd9..=0xdb => {
+            let len = r.len(1 << (tag - 0xd9))?;
+            b.str(text(r.take(len)?)?)
+        }
+        0xdc | 0xdd => {
+            let len = r.len(if tag == 0xdc { 2 } else { 4 })?;
+            msgpack_array(r, b, len)?
+        }
+        0xde | 0xdf => {
+            let len = r.len(if tag == 0xde { 2 } else { 4 })?;
+            msgpack_map(r, b, len)?
+        }
+        0xc1 => return Err("invalid MessagePack byte 0xc1".into()),
+    })
+}
+
+fn msgpack_array<B: Build>(r: &mut Reader, b: &mut B, len: usize) -> Result<B::Out, String> {
+    r.enter()?;
+    let mut items = Vec::with_capacity(len.min(r.buf.len()));
+    for _ in 0..len {
+        items.push(msgpack(r, b)?);
+    }
+    r.depth -= 1;
+    Ok(b.array(items))
+}
+
+fn msgpack_map<B: Build>(r: &mut Reader, b: &mut B, len: usize) -> Result<B::Out, String> {
+    r.enter()?;
+    let mut entries = Vec::with_capacity(len.min(r.buf.len() / 2));
+    for _ in 0..len {
+        let k = msgpack(r, b)?;
+        let v = msgpack(r, b)?;
+        entries.push((k, v));
+    }
+    r.depth -= 1;
+    Ok(b.map(entries))
+}
+
+// Type -1 is the timestamp extension, in its 32, 64 and 96-bit layouts
+fn msgpack_ext<B: Build>(b: &mut B, kind: i8, data: &[u8]) -> Result<B::Out, String> {
+    if kind != -1 {
+        return Ok(b.ext(kind, data));
+    }
+    let be = |bytes: &[u8]| bytes.iter().fold(0u64, |acc, b| (acc << 8) | u64::from(*b));
+    let (seconds, nanos) = match data.len() {
+        4 => (be(data) as i64, 0),
+        8 => {
+            let n = be(data);
+            ((n & 0x3_ffff_ffff) as i64, n >> 34)
+        }
+        12 => (be(&data[4..]) as i64, be(&data[..4])),
+        _ => return Err("bad MessagePack timestamp".into()),
+    };
+    Ok(b.date(seconds as f64 * 1000.0 + nanos as f64 / 1e6))
+}
+
+fn encode_msgpack(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(b) => out.push(if *b { 0xc3 } else { 0xc2 }),
+        Value::Number(n) => {
+            if let Some(u) = n.as_u64() {
+                msgpack_uint(u, out);
+            } else if let Some(i) = n.as_i64() {
+                msgpack_int(i, out);
+            } else {
+                let f = n.as_f64().unwrap_or(0.0);
+                // Whole numbers go out as integers, the smaller encoding
+                if f.fract() == 0.0 && f.abs() <= SAFE_INT as f64 {
+                    if f >= 0.0 { msgpack_uint(f as u64, out) } else { msgpack_int(f as i64, out) }
+                } else {
+                    out.push(0xcb);
+                    out.extend_from_slice(&f.to_be_bytes());
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.len();
+            match len {
+                0..=31 => out.push(0xa0 | len as u8),
+                32..=0xff => out.extend_from_slice(&[0xd9, len as u8]),
+                0x100..=0xffff => {
+                    out.push(0xda);
+                    out.extend_from_slice(&(len as u16).to_be_bytes());
+                }
+                _ => {
+                    out.push(0xdb);
+                    out.extend_from_slice(&(len as u32).to_be_bytes());
+                }
+            }
+            out.extend_from_slice(s.as_bytes());
+        }
+        Value::Array(items) => {
+            msgpack_header(items.len(), 0x90, 0xdc, out);
+            for item in items {
+                encode_msgpack(item, out);
+            }
+        }
+        Value::Object(map) => {
+            msgpack_header(map.len(), 0x80, 0xde, out);
+            for (k, v) in map {
+                encode_msgpack(&Value::String(k.clone()), out);
+                encode_msgpack(v, out);
+            }
+        }
+    }
+}
+
+fn msgpack_header(len: usize, fix: u8, wide: u8, out: &mut Vec<u8>) {
+    match len {
+        0..=15 => out.push(fix | len as u8),
+        16..=0xffff => {
+            out.push(wide);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(wide + 1);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+}
+
+fn msgpack_uint(n: u64, out: &mut Vec<u8>) {
+    match n {
+        0..=0x7f => out.push(n as u8),
+        0x80..=0xff => out.extend_from_slice(&[0xcc, n as u8]),
+        0x100..=0xffff => {
+            out.push(0xcd);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(0xce);
+            out.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(0xcf);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+fn msgpack_int(n: i64, out: &mut Vec<u8>) {
+    if n >= 0 {
+        return msgpack_uint(n as u64, out);
+    }
+    match n {
+        -32..=-1 => out.push(n as u8),
+        -0x80..=-33 => out.extend_from_slice(&[0xd0, n as u8]),
+        -0x8000..=-0x81 => {
+            out.push(0xd1);
+            out.extend_from_slice(&(n as i16).to_be_bytes());
+        }
+        -0x8000_0000..=-0x8001 => {
+            out.push(0xd2);
+            out.extend_from_slice(&(n as i32).to_be_bytes());
+        }
+        _ => {
+            out.push(0xd3);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+// CBOR ---------------------------------------------------------------------------
+
+// The argument of an initial byte: inline below 24, else the next 1-8 bytes.
+// None is the indefinite length of major types 2-5.
+fn cbor_arg(r: &mut Reader, info: u8) -> Result<Option<u64>, String> {
+    match info {
+        0..=23 => Ok(Some(u64::from(info))),
+        24..=27 => r.be(1 << (info - 24)).map(Some),
+        31 => Ok(None),
+        _ => Err(format!("reserved CBOR additional info {}", info)),
+    }
+}
+
+fn cbor<B: Build>(r: &mut Reader, b: &mut B) -> Result<B::Out, String> {
+    let initial = r.byte()?;
+    let (major, info) = (initial >> 5, initial & 0x1f);
+    if major == 7 {
+        return Ok(match info {
+            20 => b.bool(false),
+            21 => b.bool(true),
+            22 => b.null(),
+            23 => b.undefined(),
+            25 => b.float(f64::from(half(r.be(2)? as u16))),
+            26 => b.float(f64::from(f32::from_bits(r.be(4)? as u32))),
+            27 => b.float(f64::from_bits(r.be(8)?)),
+            0..=19 | 24 => {
+                // Unassigned simple values
+                if info == 24 {
+                    r.byte()?;
+                }
+                b.undefined()
+            }
+            31 => return Err("unexpected CBOR break".into()),
+            _ => return Err(format!("reserved CBOR simple value {}", info)),
+        });
+    }
+    let arg = cbor_arg(r, info)?;
+    Ok(match (major, arg) {
+        (0, Some(n)) => b.uint(n),
+        (1, Some(n)) => match i64::try_from(n) {
+            Ok(n) => b.int(-1 - n),
+            // Below i64::MIN; JS numbers get it approximately
+            Err(_) => b.float(-1.0 - n as f64),
+        },
+        (2, _) => {
+            let bytes = cbor_string(r, 2, arg)?;
+            b.bytes(&bytes)
+        }
+        (3, _) => {
+            let bytes = cbor_string(r, 3, arg)?;
+            b.str(text(&bytes)?)
+        }
+        (4, _) => {
+            r.enter()?;
+            let mut items = Vec::new();
+            match arg {
+                Some(len) => {
+                    let len = usize::try_from(len).map_err(|_| "array too long")?;
+                    items.reserve(len.min(r.buf.len()));
+                    for _ in 0..len {
+                        items.push(cbor(r, b)?);
+                    }
+                }
+                None => {
+                    while r.buf.first() != Some(&0xff) {
+                        items.push(cbor(r, b)?);
+                    }
+                    r.byte()?;
+                }
+            }
+            r.depth -= 1;
+            b.array(items)
+        }
+        (5, _) => {
+            r.enter()?;
+            let mut entries = Vec::new();
+            match arg {
+                Some(len) => {
+                    let len = usize::try_from(len).map_err(|_| "map too long")?;
+                    entries.reserve(len.min(r.buf.len() / 2));
+                    for _ in 0..len {
+                        let k = cbor(r, b)?;
+                        let v = cbor(r, b)?;
+                        entries.push((k, v));
+                    }
+                }
+                None => {
+                    while r.buf.first() != Some(&0xff) {
+                        let k = cbor(r, b)?;
+                        let v = cbor(r, b)?;
+                        entries.push((k, v));
+                    }
+                    r.byte()?;
+                }
+            }
+            r.depth -= 1;
+            b.map(entries)
+        }
+        (6, Some(tag)) => {
+            r.enter()?;
+            let value = match tag {
+                // Epoch-based date/time
+                1 => {
+                    let initial = *r.buf.first().ok_or("unexpected end of body")?;
+                    let seconds = match (initial >> 5, initial & 0x1f) {
+                        (0 | 1, info) => {
+                            r.byte()?;
+                            let n = cbor_arg(r, info)?.ok_or("bad CBOR date")? as f64;
+                            if initial >> 5 == 1 { -1.0 - n } else { n }
+                        }
+                        (7, 25) => {
+                            r.byte()?;
+                            f64::from(half(r.be(2)? as u16))
+                        }
+                        (7, 26) => {
+                            r.byte()?;
+                            f64::from(f32::from_bits(r.be(4)? as u32))
+                        }
+                        (7, 27) => {
+                            r.byte()?;
+                            f64::from_bits(r.be(8)?)
+                        }
+                        _ => return Err("bad CBOR date".into()),
+                    };
+                    b.date(seconds * 1000.0)
+                }
+                // Other tags (bignums, date strings, ...) read as their content
+                _ => cbor(r, b)?,
+            };
+            r.depth -= 1;
+            value
+        }
+        _ => return Err("indefinite length on a CBOR integer or tag".into()),
+    })
+}
+
+// A byte or text string; an indefinite one is the concatenation of its
+// definite chunks of the same type
+fn cbor_string(r: &mut Reader, major: u8, arg: Option<u64>) -> Result<Vec<u8>, String> {
+    if let Some(len) = arg {
+        let len = usize::try_from(len).ok().filter(|l| *l <= r.buf.len()).ok_or("length runs past the end of the body")?;
+        return Ok(r.take(len)?.to_vec());
+    }
+    let mut out = Vec::new();
+    loop {
+        let initial = r.byte()?;
+        if initial == 0xff {
+            return Ok(out);
+        }
+        if initial >> 5 != major {
+            return Err("bad chunk in an indefinite-length CBOR string".into());
+        }
+        let len = cbor_arg(r, initial & 0x1f)?.ok_or("nested indefinite-length CBOR string")?;
+        let len = usize::try_from(len).ok().filter(|l| *l <= r.buf.len()).ok_or("length runs past the end of the body")?;
+        out.extend_from_slice(r.take(len)?);
+    }
+}
+
+// IEEE 754 half precision
+fn half(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = i32::from((bits >> 10) & 0x1f);
+    let frac = f32::from(bits & 0x3ff);
+    sign * match exp {
+        0 => frac * 2f32.powi(-24),
+        31 if frac == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + frac / 1024.0) * 2f32.powi(exp - 15),
+    }
+}
+
+fn cbor_head(major: u8, n: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match n {
+        0..=23 => out.push(major | n as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, n as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+fn encode_cbor(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(b) => out.push(if *b { 0xf5 } else { 0xf4 }),
+        Value::Number(n) => {
+            let int = n.as_u64().map(i128::from).or_else(|| n.as_i64().map(i128::from)).or_else(|| {
+                n.as_f64().filter(|f| f.fract() == 0.0 && f.abs() <= SAFE_INT as f64).map(|f| f as i128)
+            });
+            match int {
+                Some(i) if i >= 0 => cbor_head(0, i as u64, out),
+                Some(i) => cbor_head(1, (-1 - i) as u64, out),
+                None => {
+                    out.push(0xfb);
+                    out.extend_from_slice(&n.as_f64().unwrap_or(0.0).to_be_bytes());
+                }
+            }
+        }
+        Value::String(s) => {
+            cbor_head(3, s.len() as u64, out);
+            out.extend_from_slice(s.as_bytes());
+        }
+        Value::Array(items) => {
+            cbor_head(4, items.len() as u64, out);
+            for item in items {
+                encode_cbor(item, out);
+            }
+        }
+        Value::Object(map) => {
+            cbor_head(5, map.len() as u64, out);
+            for (k, v) in map {
+                cbor_head(3, k.len() as u64, out);
+                out.extend_from_slice(k.as_bytes());
+                encode_cbor(v, out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let digits: Vec<u8> = s.bytes().filter(u8::is_ascii_hexdigit).collect();
+        digits.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap()).collect()
+    }
+
+    // Every width of every type; `long` adds the 32-bit headers
+    fn sample(long: bool) -> Value {
+        let mut value = json!({
+            "null": null,
+            "flags": [true, false],
+            "ints": [0, 127, 128, 255, 256, 65535, 65536, 4294967295u64, 4294967296u64, u64::MAX],
+            "negative": [-1, -32, -33, -128, -129, -32768, -32769, -2147483648i64, -2147483649i64, i64::MIN],
+            "floats": [1.5, -0.25, 1e300],
+            "strings": ["", "é", "a".repeat(31), "b".repeat(32), "c".repeat(256)],
+            "nested": { "deeper": { "list": [[], {}, [1, [2, [3]]]] } },
+            "many": (0..20).map(|i| (format!("k{}", i), json!(i))).collect::<serde_json::Map<_, _>>(),
+        });
+        if long {
+            value["long"] = json!({ "string": "d".repeat(65536), "list": (0..70000).collect::<Vec<u32>>() });
+        }
+        value
+    }
+
+    #[test]
+    fn round_trips() {
+        let value = sample(true);
+        for format in [Format::MsgPack, Format::Cbor] {
+            let bytes = format.encode(&value);
+            assert_eq!(format.validate(&bytes), Ok(()), "{:?}", format);
+            assert_eq!(format.to_json(&bytes).unwrap(), value, "{:?}", format);
+        }
+        // Whole floats take the smaller integer encoding
+        assert_eq!(Format::MsgPack.encode(&json!(2.0)), [0x02]);
+        assert_eq!(Format::Cbor.encode(&json!(-2.0)), [0x21]);
+    }
+
+    #[test]
+    fn msgpack_vectors() {
+        assert_eq!(Format::MsgPack.encode(&json!({ "a": 1, "b": [2, -3] })), hex("82 a161 01 a162 92 02 fd"));
+        assert_eq!(Format::MsgPack.encode(&json!(1.5)), hex("cb 3ff8000000000000"));
+        for (bytes, expected) in [
+            ("ca 3fc00000", json!(1.5)),
+            ("d0 80", json!(-128)),
+            ("d1 ff7f", json!(-129)),
+            ("c4 03 010203", json!([1, 2, 3])),
+            ("d9 03 616263", json!("abc")),
+            ("de 0001 a161 c0", json!({ "a": null })),
+            // Timestamps in their 32, 64 and 96-bit layouts
+            ("d6 ff 00000001", json!(1000.0)),
+            ("d7 ff 003d0900 00000002", json!(2001.0)),
+            ("c7 0c ff 00000000 ffffffffffffffff", json!(-1000.0)),
+            ("d4 05 2a", json!({ "type": 5, "data": [42] })),
+            // Map keys that aren't strings are printed
+            ("81 01 c3", json!({ "1": true })),
+        ] {
+            assert_eq!(Format::MsgPack.to_json(&hex(bytes)).unwrap(), expected, "{}", bytes);
+        }
+    }
+
+    // RFC 8949, Appendix A
+    #[test]
+    fn cbor_vectors() {
+        for (value, bytes) in [
+            (json!(1000000), "1a 000f4240"),
+            (json!(18446744073709551615u64), "1b ffffffffffffffff"),
+            (json!(-1000), "39 03e7"),
+            (json!(1.1), "fb 3ff199999999999a"),
+            (json!([1, [2, 3], [4, 5]]), "83 01 820203 820405"),
+            (json!({ "a": 1, "b": [2, 3] }), "a2 6161 01 6162 820203"),
+            (json!("\u{00fc}"), "62 c3bc"),
+        ] {
+            assert_eq!(Format::Cbor.encode(&value), hex(bytes), "{}", value);
+            assert_eq!(Format::Cbor.to_json(&hex(bytes)).unwrap(), value, "{}", bytes);
+        }
+        for (bytes, expected) in [
+            ("f9 3e00", json!(1.5)),
+            ("f9 c400", json!(-4.0)),
+            ("fa 47c35000", json!(100000.0)),
+            ("f6", json!(null)),
+            ("f7", json!(null)),
+            ("5f 42 0102 43 030405 ff", json!([1, 2, 3, 4, 5])),
+            ("7f 65 7374726561 64 6d696e67 ff", json!("streaming")),
+            ("9f ff", json!([])),
+            ("9f 01 820203 9f0405ff ff", json!([1, [2, 3], [4, 5]])),
+            ("bf 6161 01 6162 9f0203ff ff", json!({ "a": 1, "b": [2, 3] })),
+            ("c1 1a 514b67b0", json!(1363896240000.0)),
+            ("c1 fb 41d452d9ec200000", json!(1363896240500.0)),
+            // A bignum tag reads as its content
+            ("c2 49 010000000000000000", json!([1, 0, 0, 0, 0, 0, 0, 0, 0])),
+        ] {
+            assert_eq!(Format::Cbor.to_json(&hex(bytes)).unwrap(), expected, "{}", bytes);
+        }
+    }
+
+    #[test]
+    fn truncated_bodies_are_errors() {
+        let value = sample(false);
+        for format in [Format::MsgPack, Format::Cbor] {
+            let bytes = format.encode(&value);
+            for end in 0..bytes.len() {
+                assert!(format.validate(&bytes[..end]).is_err(), "{:?} cut at {}", format, end);
+                assert!(format.to_json(&bytes[..end]).is_err(), "{:?} cut at {}", format, end);
+            }
+            let mut trailing = bytes.clone();
+            trailing.push(0);
+            assert!(format.validate(&trailing).is_err());
+        }
+        assert!(Format::Cbor.to_json(&hex("5f 42 0102")).is_err());
+        assert!(Format::Cbor.to_json(&hex("bf 6161")).is_err());
+    }
+
+    #[test]
+    fn oversized_lengths_are_errors() {
+        for bytes in [
+            "db ffffffff 6162",
+            "c6 ffffffff 00",
+            "c9 ffffffff 05 00",
+            "dd ffffffff c0",
+            "df ffffffff c0 c0",
+            "dc 0003 01 02",
+            "a5 6162",
+        ] {
+            assert!(Format::MsgPack.validate(&hex(bytes)).is_err(), "{}", bytes);
+            assert!(Format::MsgPack.to_json(&hex(bytes)).is_err(), "{}", bytes);
+        }
+        for bytes in [
+            "5b ffffffffffffffff 00",
+            "7b 0000000100000000 61",
+            "9b ffffffffffffffff 01",
+            "bb ffffffffffffffff 01 02",
+            "5f 5b ffffffffffffffff ff",
+            "83 01 02",
+        ] {
+            assert!(Format::Cbor.validate(&hex(bytes)).is_err(), "{}", bytes);
+            assert!(Format::Cbor.to_json(&hex(bytes)).is_err(), "{}", bytes);
+        }
+    }
+
+    #[test]
+    fn malformed_bodies_are_errors() {
+        let deep_msgpack = [vec![0x91; MAX_DEPTH + 1], vec![0xc0]].concat();
+        assert_eq!(Format::MsgPack.validate(&deep_msgpack), Err("body nested too deeply".into()));
+        let deep_cbor = [vec![0x81; MAX_DEPTH + 1], vec![0xf6]].concat();
+        assert_eq!(Format::Cbor.validate(&deep_cbor), Err("body nested too deeply".into()));
+        assert!(Format::MsgPack.validate(&[0x91; MAX_DEPTH].iter().copied().chain([0xc0]).collect::<Vec<u8>>()).is_ok());
+
+        for bytes in ["c1", "a2 c328", "d6 ff 0000", "c7 05 ff 0000000000"] {
+            assert!(Format::MsgPack.to_json(&hex(bytes)).is_err(), "{}", bytes);
+        }
+        for bytes in ["ff", "1c", "3f", "62 c328", "5f 61 61 ff", "c1 60", "fc"] {
+            assert!(Format::Cbor.to_json(&hex(bytes)).is_err(), "{}", bytes);
+        }
+    }
+
+    #[test]
+    fn negotiates_formats() {
+        assert_eq!(Format::from_content_type("Application/CBOR; charset=binary"), Some(Format::Cbor));
+        assert_eq!(Format::from_content_type("text/plain"), None);
+        assert_eq!(Format::negotiate("application/msgpack"), Format::MsgPack);
+        assert_eq!(Format::negotiate("*/*, application/cbor;q=0.5"), Format::Json);
+        assert_eq!(Format::negotiate("application/json;q=0.5, application/cbor"), Format::Cbor);
+        assert_eq!(Format::negotiate(""), Format::Json);
+    }
+}
@@ -0,0 +1,317 @@
+mod execute;
+mod parser;
+mod schema;
+mod validate;
+
+use bytes::Bytes;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use smallvec::SmallVec;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::runtime::{ResponseBody, RuntimeManager};
+use crate::telemetry::TraceContext;
+use parser::{OperationKind, Pos};
+use schema::Schema;
+
+/// An entry of a response's `errors`.
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphqlError {
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<Pos>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub path: Vec<PathSegment>,
+}
+
+impl GraphqlError {
+    pub fn new(message: impl Into<String>, pos: Option<Pos>) -> Self {
+        Self { message: message.into(), locations: pos.into_iter().collect(), path: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+// The action behind a field, and whether it takes a whole level's calls at once
+struct Resolver {
+    action: String,
+    batch: bool,
+}
+
+/// The `graphql` block of titan.config. A schema is served at `path`
+/// (default `/graphql`), its fields resolved by actions: `graphql/<Type>/<field>`
+/// by convention, or whatever `resolvers` maps `"Type.field"` to.
+pub struct Graphql {
+    pub path: String,
+    schema: Schema,
+    resolvers: HashMap<String, Resolver>,
+    max_depth: usize,
+    introspection: bool,
+}
+
+impl Graphql {
+    /// On when `graphql` is set, or when there is an `app/schema.graphql`;
+    /// `"graphql": false` turns it off.
+    pub fn from_config<'a>(
+        config: &Value,
+        root: &Path,
+        actions: impl Iterator<Item = &'a String>,
+    ) -> Result<Option<Graphql>, String> {
+        if config.as_bool() == Some(false) || config["enabled"].as_bool() == Some(false) {
+            return Ok(None);
+        }
+        let schema_path = root.join(config["schema"].as_str().unwrap_or("app/schema.graphql"));
+        if config.is_null() && !schema_path.exists() {
+            return Ok(None);
+        }
+        let src = std::fs::read_to_string(&schema_path).map_err(|e| format!("graphql: {}: {}", schema_path.display(), e))?;
+        let schema = Schema::parse(&src).map_err(|e| format!("graphql: {}: {}", schema_path.display(), e))?;
+
+        let mut resolvers = HashMap::new();
+        for action in actions {
+            let Some(rest) = action.strip_prefix("graphql/") else {
+                continue;
+            };
+            let Some((type_name, field)) = rest.split_once('/') else {
+                continue;
+            };
+            if schema.field(type_name, field).is_none() {
+                return Err(format!("graphql: action {} resolves {}.{}, which the schema doesn't have", action, type_name, field));
+            }
+            resolvers.insert(format!("{}.{}", type_name, field), Resolver { action: action.clone(), batch: false });
+        }
+        if let Some(map) = config["resolvers"].as_object() {
+            for (key, value) in map {
+                let field = key.split_once('.').filter(|(t, f)| schema.field(t, f).is_some());
+                if field.is_none() {
+                    return Err(format!("graphql: resolver for {}, which the schema doesn't have", key));
+                }
+                let resolver = match value {
+                    Value::String(action) => Resolver { action: action.clone(), batch: false },
+                    Value::Object(options) => Resolver {
+                        action: options
+                            .get("action")
+                            .and_then(Value::as_str)
+                            .map(str::to_string)
+                            .or_else(|| resolvers.get(key).map(|r| r.action.clone()))
+                            .ok_or_else(|| format!("graphql: resolver for {} needs an action", key))?,
+                        batch: options.get("batch").and_then(Value::as_bool).unwrap_or(false),
+                    },
+                    _ => return Err(format!("graphql: resolver for {} must be an action name or an object", key)),
+                };
+                resolvers.insert(key.clone(), resolver);
+            }
+        }
+        for root_type in [Some(schema.query.as_str()), schema.mutation.as_deref()].into_iter().flatten() {
+            let Some(parser::TypeKind::Object { fields, .. }) = schema.get(root_type).map(|d| &d.kind) else {
+                continue;
+            };
+            for field in fields {
+                if !resolvers.contains_key(&format!("{}.{}", root_type, field.name)) {
+                    tracing::warn!("graphql: {}.{} has no resolver and will always be null", root_type, field.name);
+                }
+            }
+        }
+
+        Ok(Some(Graphql {
+            path: config["path"].as_str().unwrap_or("/graphql").to_string(),
+            schema,
+            resolvers,
+            max_depth: config["max_depth"].as_u64().filter(|n| *n > 0).unwrap_or(15) as usize,
+            introspection: config["introspection"].as_bool().unwrap_or(true),
+        }))
+    }
+
+    pub fn resolvers(&self) -> usize {
+        self.resolvers.len()
+    }
+
+    fn resolver(&self, type_name: &str, field: &str) -> Option<&Resolver> {
+        self.resolvers.get(&format!("{}.{}", type_name, field))
+    }
+
+    /// Runs one GraphQL request and answers with a status and a JSON body.
+    /// Errors in the request itself keep `data` out of the response; field
+    /// errors come back next to whatever data could still be resolved.
+    pub async fn run(&self, request: Request, ctx: &Context, get: bool) -> Response {
+        let doc = match parser::parse_query(&request.query) {
+            Ok(doc) => doc,
+            Err(e) => return Response::rejected(vec![GraphqlError::new(e.to_string(), Some(e.pos))]),
+        };
+        let errors = validate::validate(&self.schema, &doc, self.max_depth, self.introspection);
+        if !errors.is_empty() {
+            return Response::rejected(errors);
+        }
+        let op = match &request.operation_name {
+            Some(name) => doc.operations.iter().find(|op| op.name.as_deref() == Some(name)),
+            None if doc.operations.len() == 1 => doc.operations.first(),
+            None => {
+                return Response::rejected(vec![GraphqlError::new(
+                    "Must provide operation name if query contains multiple operations.",
+                    None,
+                )]);
+            }
+        };
+        let Some(op) = op else {
+            let name = request.operation_name.unwrap_or_default();
+            return Response::rejected(vec![GraphqlError::new(format!("Unknown operation named \"{}\".", name), None)]);
+        };
+        if get && op.kind == OperationKind::Mutation {
+            return Response {
+                status: 405,
+                body: Response::errors_json(&[GraphqlError::new("Can only perform a mutation operation from a POST request.", None)]),
+                rejected: true,
+            };
+        }
+        let variables = match execute::coerce_variables(&self.schema, op, &request.variables) {
+            Ok(variables) => variables,
+            Err(errors) => return Response::rejected(errors),
+        };
+        let (data, errors) = execute::Executor::new(self, &doc, op, variables, ctx).run().await;
+        let body = if errors.is_empty() {
+            format!("{{\"data\":{}}}", data)
+        } else {
+            format!("{{\"errors\":{},\"data\":{}}}", serde_json::to_string(&errors).unwrap_or_default(), data)
+        };
+        Response { status: 200, body, rejected: false }
+    }
+}
+
+/// What the client asked for: the query, and which operation in it to run
+/// with what variables.
+pub struct Request {
+    pub query: String,
+    pub operation_name: Option<String>,
+    pub variables: Map<String, Value>,
+}
+
+impl Request {
+    /// A GET's `?query=...&operationName=...&variables=...`.
+    pub fn from_query(query_string: &str) -> Result<Request, String> {
+        let mut query = None;
+        let mut operation_name = None;
+        let mut variables = Value::Null;
+        for pair in query_string.split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_encoding::percent_decode_str(&value.replace('+', " ")).decode_utf8_lossy().into_owned();
+            match key {
+                "query" => query = Some(value),
+                "operationName" => operation_name = Some(value),
+                "variables" if !value.is_empty() => {
+                    variables = serde_json::from_str(&value).map_err(|_| "Variables are invalid JSON.")?;
+                }
+                _ => {}
+            }
+        }
+        Self::new(query, operation_name, variables)
+    }
+
+    /// A POST's body: JSON, or the bare query as `application/graphql`.
+    pub fn from_body(content_type: Option<&str>, body: &[u8]) -> Result<Request, String> {
+        match content_type.map(|ct| ct.split(';').next().unwrap_or("").trim().to_ascii_lowercase()).as_deref() {
+            Some("application/graphql") => Self::new(Some(String::from_utf8_lossy(body).into_owned()), None, Value::Null),
+            None | Some("application/json") => {
+                let json: Value = serde_json::from_slice(body).map_err(|_| "POST body sent invalid JSON.")?;
+                let query = json["query"].as_str().map(str::to_string);
+                let operation_name = json["operationName"].as_str().map(str::to_string);
+                Self::new(query, operation_name, json["variables"].clone())
+            }
+            Some(other) => Err(format!("Unsupported content type \"{}\".", other)),
+        }
+    }
+
+    fn new(query: Option<String>, operation_name: Option<String>, variables: Value) -> Result<Request, String> {
+        let variables = match variables {
+            Value::Null => Map::new(),
+            Value::Object(map) => map,
+            // Some clients send variables as a JSON string
+            Value::String(s) => match serde_json::from_str(&s) {
+                Ok(Value::Object(map)) => map,
+                _ => return Err("Variables are invalid JSON.".into()),
+            },
+            _ => return Err("Variables must be an object.".into()),
+        };
+        Ok(Request {
+            query: query.filter(|q| !q.trim().is_empty()).ok_or("Must provide query string.")?,
+            operation_name: operation_name.filter(|n| !n.is_empty()),
+            variables,
+        })
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+    /// The request failed before anything ran.
+    pub rejected: bool,
+}
+
+impl Response {
+    fn rejected(errors: Vec<GraphqlError>) -> Self {
+        Response { status: 400, body: Self::errors_json(&errors), rejected: true }
+    }
+
+    fn errors_json(errors: &[GraphqlError]) -> String {
+        format!("{{\"errors\":{}}}", serde_json::to_string(errors).unwrap_or_default())
+    }
+}
+
+/// The request a GraphQL query came in on, which every resolver call
+/// carries along: it is admitted once, and its auth claims and session are
+/// handed to each call.
+pub struct Context {
+    pub runtime: Arc<RuntimeManager>,
+    pub path: String,
+    pub headers: SmallVec<[(String, String); 8]>,
+    pub correlation_id: String,
+    pub trace: Option<TraceContext>,
+    pub remote_addr: Option<SocketAddr>,
+    pub auth: Option<Arc<Value>>,
+    pub session: Option<Arc<Value>>,
+    pub geo: Option<Arc<Value>>,
+    /// The whole request's deadline; each call gets what is left of it.
+    pub deadline: Option<Instant>,
+}
+
+impl Context {
+    // Runs a resolver action with `body` as its `req.body`
+    async fn call(&self, action: &str, body: Bytes) -> Result<Value, String> {
+        let deadline = match self.deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+                Some(left) => Some(left),
+                None => return Err("Request timed out".into()),
+            },
+            None => None,
+        };
+        let (mut task, rx) = self.runtime.task(action.to_string(), "GRAPHQL".into(), self.path.clone());
+        task.body = Some(body);
+        task.headers = self.headers.clone();
+        task.correlation_id = self.correlation_id.clone();
+        task.trace = self.trace.clone();
+        task.remote_addr = self.remote_addr;
+        task.auth = self.auth.clone();
+        task.session = self.session.clone();
+        task.geo = self.geo.clone();
+        let result = self.runtime.dispatch(task, rx, deadline).await.map_err(|e| e.to_string())?;
+        if let Some(message) = result.error_message() {
+            return Err(message.to_string());
+        }
+        match result.body {
+            ResponseBody::Json(value) => Ok(value),
+            ResponseBody::JsonText(text) => serde_json::from_slice(&text).map_err(|e| e.to_string()),
+            ResponseBody::Bytes(bytes) => Ok(serde_json::from_slice(&bytes)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()))),
+            ResponseBody::Empty => Ok(Value::Null),
+            ResponseBody::Stream(_) => Err(format!("Resolver {} answered with a stream", action)),
+        }
+    }
+}
@@ -0,0 +1,608 @@
+//! Protobuf wire format ↔ JSON, driven by the descriptors in a `Pool`. The
+//! JSON side follows protobuf's JSON mapping, so an action reads a request
+//! the way any other JSON body reads: lowerCamelCase keys, 64-bit integers
+//! as strings, bytes as base64, enums by name and the well-known types in
+//! their natural forms.
+
+use base64::Engine;
+use serde_json::{Map, Number, Value};
+
+use super::proto::{Field, FieldType, Message, Pool, Scalar};
+
+// Messages nested deeper than this are refused rather than recursed into
+const MAX_DEPTH: usize = 100;
+
+impl Pool {
+    /// Decodes one message of type `name` into JSON.
+    pub fn decode(&self, name: &str, buf: &[u8]) -> Result<Value, String> {
+        self.decode_message(name, buf, 0)
+    }
+
+    /// Encodes a JSON value as a message of type `name`.
+    pub fn encode(&self, name: &str, value: &Value) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        self.encode_message(name, value, &mut out, 0)?;
+        Ok(out)
+    }
+
+    fn message(&self, name: &str) -> Result<&Message, String> {
+        self.messages.get(name).ok_or_else(|| format!("unknown message {}", name))
+    }
+
+    fn decode_message(&self, name: &str, buf: &[u8], depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err("message nested too deeply".into());
+        }
+        let desc = self.message(name)?;
+        let fields = self.decode_fields(desc, buf, depth)?;
+        Ok(from_generic(name, fields))
+    }
+
+    fn decode_fields(&self, desc: &Message, buf: &[u8], depth: usize) -> Result<Map<String, Value>, String> {
+        let mut out = Map::new();
+        let mut r = Reader { buf };
+        while !r.buf.is_empty() {
+            let key = r.varint()?;
+            let (number, wire) = ((key >> 3) as u32, (key & 7) as u8);
+            let Some(field) = desc.field(number) else {
+                r.skip(wire)?;
+                continue;
+            };
+            let at = |e: String| format!("{}: {}", field.name, e);
+            if let Some(key_type) = field.map_key {
+                let entry = r.delimited(wire).map_err(at)?;
+                let (k, v) = self.decode_entry(field, key_type, entry, depth).map_err(at)?;
+                if let Value::Object(map) = out.entry(field.json_name.clone()).or_insert_with(|| Value::Object(Map::new())) {
+                    map.insert(k, v);
+                }
+            } else if field.repeated {
+                let mut items = Vec::new();
+                let packable = matches!(field.ty, FieldType::Scalar(s) if s.packable()) || matches!(field.ty, FieldType::Enum(_));
+                if packable && wire == 2 {
+                    // Packed, whatever the descriptor says; readers take both
+                    let mut packed = Reader { buf: r.delimited(wire).map_err(at)? };
+                    while !packed.buf.is_empty() {
+                        items.push(self.decode_value(&field.ty, wire_type(&field.ty), &mut packed, depth).map_err(at)?);
+                    }
+                } else {
+                    items.push(self.decode_value(&field.ty, wire, &mut r, depth).map_err(at)?);
+                }
+                if let Value::Array(list) = out.entry(field.json_name.clone()).or_insert_with(|| Value::Array(Vec::new())) {
+                    list.extend(items);
+                }
+            } else {
+                let value = self.decode_value(&field.ty, wire, &mut r, depth).map_err(at)?;
+                out.insert(field.json_name.clone(), value);
+            }
+        }
+        // Fields without presence read as their zero value when absent
+        for field in &desc.fields {
+            if field.presence || out.contains_key(&field.json_name) {
+                continue;
+            }
+            let default = if field.map_key.is_some() {
+                Value::Object(Map::new())
+            } else if field.repeated {
+                Value::Array(Vec::new())
+            } else {
+                match &field.ty {
+                    FieldType::Message(_) => continue,
+                    ty => self.zero(ty),
+                }
+            };
+            out.insert(field.json_name.clone(), default);
+        }
+        Ok(out)
+    }
+
+    fn decode_entry(&self, field: &Field, key_type: Scalar, buf: &[u8], depth: usize) -> Result<(String, Value), String> {
+        let mut r = Reader { buf };
+        let (mut key, mut value) = (None, None);
+        while !r.buf.is_empty() {
+            let tag = r.varint()?;
+            let wire = (tag & 7) as u8;
+            match tag >> 3 {
+                1 => key = Some(self.decode_value(&FieldType::Scalar(key_type), wire, &mut r, depth)?),
+                2 => value = Some(self.decode_value(&field.ty, wire, &mut r, depth)?),
+                _ => r.skip(wire)?,
+            }
+        }
+        let key = match key.unwrap_or_else(|| self.zero(&FieldType::Scalar(key_type))) {
+            Value::String(s) => s,
+            other => other.to_string(),
+        };
+        let value = match (value, &field.ty) {
+            (Some(value), _) => value,
+            (None, FieldType::Message(name)) => self.decode_message(name, &[], depth + 1)?,
+            (None, ty) => self.zero(ty),
+        };
+        Ok((key, value))
+    }
+
+    fn decode_value(&self, ty: &FieldType, wire: u8, r: &mut Reader, depth: usize) -> Result<Value, String> {
+        let expected = wire_type(ty);
+        if wire != expected {
+            return Err(format!("wire type {} where {} was expected", wire, expected));
+        }
+        Ok(match ty {
+            FieldType::Message(name) => self.decode_message(name, r.delimited(wire)?, depth + 1)?,
+            FieldType::Enum(name) => {
+                let n = r.varint()? as i32;
+                self.enum_name(name, n).map_or_else(|| Value::from(n), |v| Value::String(v.to_string()))
+            }
+            FieldType::Scalar(s) => match s {
+                Scalar::Int32 => Value::from(r.varint()? as i32),
+                Scalar::Uint32 => Value::from(r.varint()? as u32),
+                Scalar::Sint32 => {
+                    let n = r.varint()? as u32;
+                    Value::from(((n >> 1) as i32) ^ -((n & 1) as i32))
+                }
+                Scalar::Int64 => Value::String((r.varint()? as i64).to_string()),
+                Scalar::Uint64 => Value::String(r.varint()?.to_string()),
+                Scalar::Sint64 => {
+                    let n = r.varint()?;
+                    Value::String((((n >> 1) as i64) ^ -((n & 1) as i64)).to_string())
+                }
+                Scalar::Bool => Value::Bool(r.varint()? != 0),
+                Scalar::Fixed32 => Value::from(u32::from_le_bytes(r.fixed()?)),
+                Scalar::Sfixed32 => Value::from(i32::from_le_bytes(r.fixed()?)),
+                // Through its shortest decimal form, so 0.1f reads as 0.1
+                Scalar::Float => float_json(f32::from_le_bytes(r.fixed()?).to_string().parse().unwrap_or(f64::NAN)),
+                Scalar::Fixed64 => Value::String(u64::from_le_bytes(r.fixed()?).to_string()),
+                Scalar::Sfixed64 => Value::String(i64::from_le_bytes(r.fixed()?).to_string()),
+                Scalar::Double => float_json(f64::from_le_bytes(r.fixed()?)),
+                Scalar::String => {
+                    let bytes = r.delimited(wire)?;
+                    Value::String(String::from_utf8(bytes.to_vec()).map_err(|_| "string is not valid UTF-8")?)
+                }
+                Scalar::Bytes => Value::String(base64::engine::general_purpose::STANDARD.encode(r.delimited(wire)?)),
+            },
+        })
+    }
+
+    fn zero(&self, ty: &FieldType) -> Value {
+        match ty {
+            FieldType::Scalar(Scalar::Bool) => Value::Bool(false),
+            FieldType::Scalar(Scalar::String | Scalar::Bytes) => Value::String(String::new()),
+            FieldType::Scalar(Scalar::Int64 | Scalar::Uint64 | Scalar::Sint64 | Scalar::Fixed64 | Scalar::Sfixed64) => {
+                Value::String("0".into())
+            }
+            FieldType::Scalar(_) => Value::from(0),
+            FieldType::Enum(name) => self.enum_name(name, 0).map_or_else(|| Value::from(0), |v| Value::String(v.to_string())),
+            FieldType::Message(_) => Value::Null,
+        }
+    }
+
+    fn enum_name(&self, name: &str, number: i32) -> Option<&str> {
+        self.enums.get(name)?.values.iter().find(|(_, n)| *n == number).map(|(v, _)| v.as_str())
+    }
+
+    fn encode_message(&self, name: &str, value: &Value, out: &mut Vec<u8>, depth: usize) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err("message nested too deeply".into());
+        }
+        let desc = self.message(name)?;
+        let generic = to_generic(name, value)?;
+        let Value::Object(obj) = generic.as_ref().unwrap_or(value) else {
+            return Err(format!("expected an object for {}", name));
+        };
+        for field in &desc.fields {
+            let Some(v) = obj.get(&field.json_name).or_else(|| obj.get(&field.name)) else {
+                continue;
+            };
+            // null leaves a field unset, except where it is a google.protobuf.Value
+            if v.is_null() && (field.repeated || field.ty != FieldType::Message("google.protobuf.Value".into())) {
+                continue;
+            }
+            let at = |e: String| format!("{}: {}", field.name, e);
+            if let Some(key_type) = field.map_key {
+                let Value::Object(entries) = v else {
+                    return Err(at("expected an object".into()));
+                };
+                for (k, item) in entries {
+                    let mut entry = Vec::new();
+                    self.encode_field(&mut entry, 1, &FieldType::Scalar(key_type), &Value::String(k.clone()), depth).map_err(at)?;
+                    self.encode_field(&mut entry, 2, &field.ty, item, depth).map_err(at)?;
+                    put_varint(out, (u64::from(field.number) << 3) | 2);
+                    put_varint(out, entry.len() as u64);
+                    out.extend_from_slice(&entry);
+                }
+            } else if field.repeated {
+                let Value::Array(items) = v else {
+                    return Err(at("expected an array".into()));
+                };
+                if field.packed {
+                    if items.is_empty() {
+                        continue;
+                    }
+                    let mut packed = Vec::new();
+                    for item in items {
+                        self.encode_bare(&mut packed, &field.ty, item, depth).map_err(at)?;
+                    }
+                    put_varint(out, (u64::from(field.number) << 3) | 2);
+                    put_varint(out, packed.len() as u64);
+                    out.extend_from_slice(&packed);
+                } else {
+                    for item in items {
+                        self.encode_field(out, field.number, &field.ty, item, depth).map_err(at)?;
+                    }
+                }
+            } else {
+                self.encode_field(out, field.number, &field.ty, v, depth).map_err(at)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_field(&self, out: &mut Vec<u8>, number: u32, ty: &FieldType, value: &Value, depth: usize) -> Result<(), String> {
+        put_varint(out, (u64::from(number) << 3) | u64::from(wire_type(ty)));
+        self.encode_bare(out, ty, value, depth)
+    }
+
+    fn encode_bare(&self, out: &mut Vec<u8>, ty: &FieldType, value: &Value, depth: usize) -> Result<(), String> {
+        match ty {
+            FieldType::Message(name) => {
+                let mut inner = Vec::new();
+                self.encode_message(name, value, &mut inner, depth + 1)?;
+                put_varint(out, inner.len() as u64);
+                out.extend_from_slice(&inner);
+            }
+            FieldType::Enum(name) => {
+                let n = match value {
+                    Value::String(s) => {
+                        let e = self.enums.get(name).ok_or_else(|| format!("unknown enum {}", name))?;
+                        match e.values.iter().find(|(v, _)| v == s) {
+                            Some((_, n)) => *n,
+                            None => return Err(format!("{} has no value {}", name, s)),
+                        }
+                    }
+                    other => integer::<i32>(other)?,
+                };
+                put_varint(out, i64::from(n) as u64);
+            }
+            FieldType::Scalar(s) => match s {
+                Scalar::Int32 => put_varint(out, i64::from(integer::<i32>(value)?) as u64),
+                Scalar::Uint32 => put_varint(out, u64::from(integer::<u32>(value)?)),
+                Scalar::Sint32 => {
+                    let n = integer::<i32>(value)?;
+                    put_varint(out, u64::from(((n << 1) ^ (n >> 31)) as u32));
+                }
+                Scalar::Int64 => put_varint(out, integer::<i64>(value)? as u64),
+                Scalar::Uint64 => put_varint(out, integer::<u64>(value)?),
+                Scalar::Sint64 => {
+                    let n = integer::<i64>(value)?;
+                    put_varint(out, ((n << 1) ^ (n >> 63)) as u64);
+                }
+                Scalar::Bool => put_varint(out, u64::from(boolean(value)?)),
+                Scalar::Fixed32 => out.extend_from_slice(&integer::<u32>(value)?.to_le_bytes()),
+                Scalar::Sfixed32 => out.extend_from_slice(&integer::<i32>(value)?.to_le_bytes()),
+                Scalar::Float => out.extend_from_slice(&(float(value)? as f32).to_le_bytes()),
+                Scalar::Fixed64 => out.extend_from_slice(&integer::<u64>(value)?.to_le_bytes()),
+                Scalar::Sfixed64 => out.extend_from_slice(&integer::<i64>(value)?.to_le_bytes()),
+                Scalar::Double => out.extend_from_slice(&float(value)?.to_le_bytes()),
+                Scalar::String => {
+                    let Value::String(s) = value else {
+                        return Err(format!("expected a string, got {}", value));
+                    };
+                    put_varint(out, s.len() as u64);
+                    out.extend_from_slice(s.as_bytes());
+                }
+                Scalar::Bytes => {
+                    let Value::String(s) = value else {
+                        return Err("expected a base64 string".into());
+                    };
+                    // Standard or URL-safe, padded or not
+                    let normalized: String =
+                        s.trim_end_matches('=').chars().map(|c| match c { '-' => '+', '_' => '/', c => c }).collect();
+                    let bytes = base64::engine::general_purpose::STANDARD_NO_PAD
+                        .decode(normalized)
+                        .map_err(|_| "expected a base64 string".to_string())?;
+                    put_varint(out, bytes.len() as u64);
+                    out.extend_from_slice(&bytes);
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+fn wire_type(ty: &FieldType) -> u8 {
+    match ty {
+        FieldType::Message(_) | FieldType::Scalar(Scalar::String | Scalar::Bytes) => 2,
+        FieldType::Scalar(Scalar::Fixed64 | Scalar::Sfixed64 | Scalar::Double) => 1,
+        FieldType::Scalar(Scalar::Fixed32 | Scalar::Sfixed32 | Scalar::Float) => 5,
+        _ => 0,
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut n = 0u64;
+        for (i, byte) in self.buf.iter().enumerate().take(10) {
+            n |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.buf = &self.buf[i + 1..];
+                return Ok(n);
+            }
+        }
+        Err("truncated varint".into())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.buf.len() < len {
+            return Err("truncated message".into());
+        }
+        let (head, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    fn fixed<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(self.take(N)?);
+        Ok(bytes)
+    }
+
+    fn delimited(&mut self, wire: u8) -> Result<&'a [u8], String> {
+        if wire != 2 {
+            return Err(format!("wire type {} where 2 was expected", wire));
+        }
+        let len = usize::try_from(self.varint()?).map_err(|_| "truncated message")?;
+        self.take(len)
+    }
+
+    fn skip(&mut self, wire: u8) -> Result<(), String> {
+        match wire {
+            0 => self.varint().map(drop),
+            1 => self.take(8).map(drop),
+            2 => self.delimited(2).map(drop),
+            5 => self.take(4).map(drop),
+            other => Err(format!("unsupported wire type {}", other)),
+        }
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn float_json(f: f64) -> Value {
+    match Number::from_f64(f) {
+        Some(n) => Value::Number(n),
+        None if f.is_nan() => Value::String("NaN".into()),
+        None if f > 0.0 => Value::String("Infinity".into()),
+        None => Value::String("-Infinity".into()),
+    }
+}
+
+// Integers come as numbers or, for 64-bit ones especially, as strings
+fn integer<T: TryFrom<i128>>(value: &Value) -> Result<T, String> {
+    let n: Option<i128> = match value {
+        Value::Number(n) => n.as_i64().map(i128::from).or_else(|| n.as_u64().map(i128::from)).or_else(|| {
+            n.as_f64().filter(|f| f.fract() == 0.0 && f.abs() < 1e19).map(|f| f as i128)
+        }),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    };
+    let n = n.ok_or_else(|| format!("expected an integer, got {}", value))?;
+    T::try_from(n).map_err(|_| format!("{} is out of range", n))
+}
+
+fn float(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Number(n) => n.as_f64().ok_or_else(|| "expected a number".to_string()),
+        Value::String(s) => match s.as_str() {
+            "NaN" => Ok(f64::NAN),
+            "Infinity" => Ok(f64::INFINITY),
+            "-Infinity" => Ok(f64::NEG_INFINITY),
+            s => s.trim().parse().map_err(|_| format!("expected a number, got \"{}\"", s)),
+        },
+        other => Err(format!("expected a number, got {}", other)),
+    }
+}
+
+fn boolean(value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        Value::String(s) if s == "true" => Ok(true),
+        Value::String(s) if s == "false" => Ok(false),
+        other => Err(format!("expected a boolean, got {}", other)),
+    }
+}
+
+// Well-known types ------------------------------------------------------------
+
+// A decoded well-known type in its JSON form; other messages stay objects
+fn from_generic(name: &str, mut fields: Map<String, Value>) -> Value {
+    let Some(short) = name.strip_prefix("google.protobuf.") else {
+        return Value::Object(fields);
+    };
+    let int = |fields: &Map<String, Value>, key: &str| integer::<i64>(fields.get(key).unwrap_or(&Value::Null)).unwrap_or(0);
+    match short {
+        "Timestamp" => Value::String(format_timestamp(int(&fields, "seconds"), int(&fields, "nanos"))),
+        "Duration" => Value::String(format_duration(int(&fields, "seconds"), int(&fields, "nanos"))),
+        "DoubleValue" | "FloatValue" | "Int64Value" | "UInt64Value" | "Int32Value" | "UInt32Value" | "BoolValue"
+        | "StringValue" | "BytesValue" => fields.remove("value").unwrap_or(Value::Null),
+        "Struct" => fields.remove("fields").unwrap_or_else(|| Value::Object(Map::new())),
+        "ListValue" => fields.remove("values").unwrap_or_else(|| Value::Array(Vec::new())),
+        "Value" => ["numberValue", "stringValue", "boolValue", "structValue", "listValue"]
+            .iter()
+            .find_map(|key| fields.remove(*key))
+            .unwrap_or(Value::Null),
+        "FieldMask" => match fields.remove("paths") {
+            Some(Value::Array(paths)) => Value::String(
+                paths.iter().filter_map(Value::as_str).map(camel_case).collect::<Vec<_>>().join(","),
+            ),
+            _ => Value::String(String::new()),
+        },
+        _ => Value::Object(fields),
+    }
+}
+
+// A well-known type's JSON form as the object its descriptor encodes;
+// None for other messages, and for values already in that shape
+fn to_generic(name: &str, value: &Value) -> Result<Option<Value>, String> {
+    let Some(short) = name.strip_prefix("google.protobuf.") else {
+        return Ok(None);
+    };
+    let wrap = |key: &str, value: Value| Some(Value::Object(Map::from_iter([(key.to_string(), value)])));
+    Ok(match (short, value) {
+        ("Timestamp", Value::String(s)) => {
+            let (seconds, nanos) = parse_timestamp(s).ok_or_else(|| format!("bad timestamp \"{}\"", s))?;
+            Some(serde_json::json!({ "seconds": seconds.to_string(), "nanos": nanos }))
+        }
+        ("Duration", Value::String(s)) => {
+            let (seconds, nanos) = parse_duration(s).ok_or_else(|| format!("bad duration \"{}\"", s))?;
+            Some(serde_json::json!({ "seconds": seconds.to_string(), "nanos": nanos }))
+        }
+        (
+            "DoubleValue" | "FloatValue" | "Int64Value" | "UInt64Value" | "Int32Value" | "UInt32Value" | "BoolValue"
+            | "StringValue" | "BytesValue",
+            value,
+        ) if !value.is_object() => wrap("value", value.clone()),
+        ("Struct", Value::Object(_)) => wrap("fields", value.clone()),
+        ("ListValue", Value::Array(_)) => wrap("values", value.clone()),
+        ("Value", value) => match value {
+            Value::Null => wrap("nullValue", Value::from(0)),
+            Value::Number(_) => wrap("numberValue", value.clone()),
+            Value::String(_) => wrap("stringValue", value.clone()),
+            Value::Bool(_) => wrap("boolValue", value.clone()),
+            Value::Object(_) => wrap("structValue", value.clone()),
+            Value::Array(_) => wrap("listValue", value.clone()),
+        },
+        ("FieldMask", Value::String(s)) => {
+            let paths = s.split(',').filter(|p| !p.is_empty()).map(|p| Value::String(snake_case(p))).collect();
+            wrap("paths", Value::Array(paths))
+        }
+        _ => None,
+    })
+}
+
+fn camel_case(path: &str) -> String {
+    let mut out = String::new();
+    let mut upper = false;
+    for c in path.chars() {
+        match c {
+            '_' => upper = true,
+            c if upper => {
+                out.extend(c.to_uppercase());
+                upper = false;
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn snake_case(path: &str) -> String {
+    let mut out = String::new();
+    for c in path.chars() {
+        if c.is_ascii_uppercase() {
+            out.push('_');
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// Fractions print with 0, 3, 6 or 9 digits, as protobuf's own printers do
+fn fraction(nanos: i64) -> String {
+    match nanos {
+        0 => String::new(),
+        n if n % 1_000_000 == 0 => format!(".{:03}", n / 1_000_000),
+        n if n % 1_000 == 0 => format!(".{:06}", n / 1_000),
+        n => format!(".{:09}", n),
+    }
+}
+
+fn format_timestamp(seconds: i64, nanos: i64) -> String {
+    let days = seconds.div_euclid(86_400);
+    let secs = seconds.rem_euclid(86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        fraction(nanos)
+    )
+}
+
+fn parse_timestamp(s: &str) -> Option<(i64, i32)> {
+    let b = s.as_bytes();
+    let num = |range: std::ops::Range<usize>| -> Option<i64> { s.get(range)?.parse().ok() };
+    if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || !matches!(b[10], b'T' | b't') || b[13] != b':' || b[16] != b':' {
+        return None;
+    }
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let mut rest = &s[19..];
+    let mut nanos = 0;
+    if let Some(frac) = rest.strip_prefix('.') {
+        let digits = frac.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 || digits > 9 {
+            return None;
+        }
+        nanos = frac[..digits].parse::<i32>().ok()? * 10i32.pow(9 - digits as u32);
+        rest = &frac[digits..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let (h, m) = rest[1..].split_once(':')?;
+            sign * (h.parse::<i64>().ok()? * 3600 + m.parse::<i64>().ok()? * 60)
+        }
+    };
+    let days = crate::utils::days_from_civil(year, month, day);
+    Some((days * 86_400 + hour * 3600 + minute * 60 + second - offset, nanos))
+}
+
+fn format_duration(seconds: i64, nanos: i64) -> String {
+    let sign = if seconds < 0 || nanos < 0 { "-" } else { "" };
+    format!("{}{}{}s", sign, seconds.unsigned_abs(), fraction(nanos.abs()))
+}
+
+fn parse_duration(s: &str) -> Option<(i64, i32)> {
+    let body = s.strip_suffix('s')?;
+    let (negative, body) = match body.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, body),
+    };
+    let (whole, frac) = body.split_once('.').unwrap_or((body, ""));
+    if frac.len() > 9 || !frac.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let seconds: i64 = whole.parse().ok()?;
+    let nanos = if frac.is_empty() { 0 } else { frac.parse::<i32>().ok()? * 10i32.pow(9 - frac.len() as u32) };
+    Some(if negative { (-seconds, -nanos) } else { (seconds, nanos) })
+}
@@ -0,0 +1,379 @@
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use smallvec::SmallVec;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::metrics;
+use crate::runtime::RuntimeManager;
+use crate::utils::civil_from_days;
+
+/// Written next to the action bundles by the bundler, one entry per file in app/jobs.
+pub const JOBS_MANIFEST: &str = "__titan_jobs.json";
+
+/// Jobs are bundled as actions under this prefix, out of reach of the routers.
+pub const JOB_ACTION_PREFIX: &str = "__titan_job_";
+
+#[derive(Debug, Deserialize)]
+struct JobEntry {
+    name: String,
+    schedule: String,
+    #[serde(default)]
+    jitter_ms: u64,
+}
+
+/// A parsed five-field cron expression (`minute hour day-of-month month
+/// day-of-week`), evaluated in UTC. Each field is a bit set of the values it
+/// allows.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Vixie cron: a day field starting with `*` is unrestricted, and only
+    // when both are restricted is either one matching enough
+    any_day: bool,
+    any_weekday: bool,
+}
+
+const MONTH_NAMES: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl Schedule {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let expr = match expr.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("expected 5 fields, found {}", fields.len()));
+        };
+
+        let mut weekdays = parse_field(weekday, 0, 7, &WEEKDAY_NAMES, 0)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1; // 7 is Sunday too
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59, &[], 0)?,
+            hours: parse_field(hour, 0, 23, &[], 0)?,
+            days: parse_field(day, 1, 31, &[], 1)?,
+            months: parse_field(month, 1, 12, &MONTH_NAMES, 1)?,
+            weekdays,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+
+    /// The first whole minute strictly after `after` (unix seconds) that the
+    /// schedule matches, or None if there is none in the next five years
+    /// (e.g. `0 0 31 2 *`).
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        let mut t = (after / 60 + 1) * 60;
+        let limit = after + 5 * 366 * 86_400;
+        while t <= limit {
+            let days = t / 86_400;
+            let (_, month, day) = civil_from_days(days as i64);
+            let weekday = (days + 4) % 7; // 1970-01-01 was a Thursday
+
+            let day_ok = if self.any_day || self.any_weekday {
+                bit(self.days, day) && bit(self.weekdays, weekday)
+            } else {
+                bit(self.days, day) || bit(self.weekdays, weekday)
+            };
+            if !bit(self.months, month) || !day_ok {
+                t = (days + 1) * 86_400;
+                continue;
+            }
+            if !bit(self.hours, (t % 86_400) / 3600) {
+                t = (t / 3600 + 1) * 3600;
+                continue;
+            }
+            if bit(self.minutes, (t % 3600) / 60) {
+                return Some(t);
+            }
+            t += 60;
+        }
+        None
+    }
+}
+
+fn bit(set: u64, value: u64) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Parses one field: `*`, numbers or names, `a-b` ranges, `/step`, and
+/// comma-separated lists of those.
+fn parse_field(field: &str, min: u64, max: u64, names: &[&str], name_base: u64) -> Result<u64, String> {
+    let value = |s: &str| -> Result<u64, String> {
+        let lower = s.to_ascii_lowercase();
+        let n = match names.iter().position(|name| *name == lower) {
+            Some(i) => i as u64 + name_base,
+            None => s.parse().map_err(|_| format!("invalid value '{}'", s))?,
+        };
+        if n < min || n > max {
+            return Err(format!("'{}' is outside {}-{}", s, min, max));
+        }
+        Ok(n)
+    };
+
+    let mut set = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u64 = step.parse().map_err(|_| format!("invalid step '{}'", step))?;
+                if step == 0 {
+                    return Err("step must be at least 1".to_string());
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (value(a)?, value(b)?),
+                // `5/15` means from 5 to the end of the range
+                None if step > 1 => (value(range)?, max),
+                None => {
+                    let n = value(range)?;
+                    (n, n)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("range '{}' is backwards", range));
+        }
+        for n in (start..=end).step_by(step as usize) {
+            set |= 1 << n;
+        }
+    }
+    Ok(set)
+}
+
+/// One declared job and its counters.
+pub struct Job {
+    pub name: String,
+    schedule: Schedule,
+    jitter: Duration,
+    running: AtomicBool,
+    runs: AtomicU64,
+    failures: AtomicU64,
+    skipped: AtomicU64,
+    last_success: AtomicU64,
+}
+
+impl Job {
+    fn action(&self) -> String {
+        format!("{}{}", JOB_ACTION_PREFIX, self.name)
+    }
+}
+
+/// Fires the jobs of app/jobs on their cron schedules. Each run is sent to a
+/// worker like a request, minus the HTTP layer and its interceptors. A run
+/// that would overlap the previous one is skipped.
+#[derive(Default)]
+pub struct JobScheduler {
+    jobs: Vec<Arc<Job>>,
+}
+
+impl JobScheduler {
+    /// Reads the manifest in `actions_dir`. Jobs with a bad schedule are
+    /// reported and left out.
+    pub fn load(actions_dir: &Path) -> Self {
+        let Ok(raw) = std::fs::read_to_string(actions_dir.join(JOBS_MANIFEST)) else {
+            return Self::default();
+        };
+        let entries: Vec<JobEntry> = match serde_json::from_str(&raw) {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::error!("Invalid jobs manifest: {}", e);
+                return Self::default();
+            }
+        };
+
+        let jobs = entries
+            .into_iter()
+            .filter_map(|entry| match Schedule::parse(&entry.schedule) {
+                Ok(schedule) => Some(Arc::new(Job {
+                    name: entry.name,
+                    schedule,
+                    jitter: Duration::from_millis(entry.jitter_ms),
+                    running: AtomicBool::new(false),
+                    runs: AtomicU64::new(0),
+                    failures: AtomicU64::new(0),
+                    skipped: AtomicU64::new(0),
+                    last_success: AtomicU64::new(0),
+                })),
+                Err(e) => {
+                    tracing::warn!("Skipping job {}: schedule \"{}\" {}", entry.name, entry.schedule, e);
+                    None
+                }
+            })
+            .collect();
+        Self { jobs }
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Starts one timer task per job on the current Tokio runtime.
+    pub fn start(&self, runtime: Arc<RuntimeManager>, timeout: Option<Duration>) {
+        for job in &self.jobs {
+            tokio::spawn(run_schedule(job.clone(), runtime.clone(), timeout));
+        }
+    }
+
+    /// Prometheus text exposition of the job counters.
+    pub fn render(&self, out: &mut String) {
+        if self.jobs.is_empty() {
+            return;
+        }
+        let series = |out: &mut String, name: &str, kind: &str, help: &str, value: fn(&Job) -> u64| {
+            metrics::header(out, name, kind, help);
+            for job in &self.jobs {
+                let _ = writeln!(out, "{}{{job=\"{}\"}} {}", name, metrics::escape_label(&job.name), value(job));
+            }
+        };
+        series(out, "titan_job_runs_total", "counter", "Completed runs per job.", |j| j.runs.load(Ordering::Relaxed));
+        series(out, "titan_job_failures_total", "counter", "Runs per job that threw or timed out.", |j| {
+            j.failures.load(Ordering::Relaxed)
+        });
+        series(out, "titan_job_skipped_total", "counter", "Runs skipped because the previous one was still going.", |j| {
+            j.skipped.load(Ordering::Relaxed)
+        });
+        series(out, "titan_job_last_success_timestamp_seconds", "gauge", "Unix time the job last succeeded.", |j| {
+            j.last_success.load(Ordering::Relaxed)
+        });
+    }
+}
+
+async fn run_schedule(job: Arc<Job>, runtime: Arc<RuntimeManager>, timeout: Option<Duration>) {
+    loop {
+        let now = SystemTime::now();
+        let now_secs = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let Some(next) = job.schedule.next_after(now_secs) else {
+            tracing::warn!(job = %job.name, "Job never fires again");
+            return;
+        };
+        let due = UNIX_EPOCH + Duration::from_secs(next);
+        let wait = due.duration_since(now).unwrap_or_default() + jitter(job.jitter);
+        tokio::time::sleep(wait).await;
+
+        if job.running.swap(true, Ordering::AcqRel) {
+            job.skipped.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(job = %job.name, "Job still running, skipping this run");
+            continue;
+        }
+
+        // Runs in its own task so a slow run doesn't hold back the next tick
+        let job = job.clone();
+        let runtime = runtime.clone();
+        tokio::spawn(async move {
+            let started = std::time::Instant::now();
+            let result = runtime.run_background(job.action(), "JOB", None, SmallVec::new(), timeout).await;
+            job.runs.fetch_add(1, Ordering::Relaxed);
+            match result.error_message() {
+                Some(err) => {
+                    job.failures.fetch_add(1, Ordering::Relaxed);
+                    tracing::error!(job = %job.name, error = err, "job {} failed", job.name);
+                }
+                None => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+                    job.last_success.store(now, Ordering::Relaxed);
+                    tracing::info!(job = %job.name, duration_ms = started.elapsed().as_secs_f64() * 1000.0, "job {} done", job.name);
+                }
+            }
+            job.running.store(false, Ordering::Release);
+        });
+    }
+}
+
+// Spreads runs of many instances sharing a schedule
+fn jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    let mut bytes = [0u8; 8];
+    let _ = SystemRandom::new().fill(&mut bytes);
+    Duration::from_millis(u64::from_le_bytes(bytes) % max.as_millis().max(1) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::days_from_civil;
+
+    fn at(year: i64, month: i64, day: i64, hour: u64, minute: u64) -> u64 {
+        days_from_civil(year, month, day) as u64 * 86_400 + hour * 3600 + minute * 60
+    }
+
+    fn next(expr: &str, after: u64) -> Option<u64> {
+        Schedule::parse(expr).unwrap().next_after(after)
+    }
+
+    #[test]
+    fn steps() {
+        assert_eq!(next("*/15 * * * *", at(2026, 3, 1, 10, 0)), Some(at(2026, 3, 1, 10, 15)));
+        assert_eq!(next("*/15 * * * *", at(2026, 3, 1, 10, 50)), Some(at(2026, 3, 1, 11, 0)));
+        // From 5 to the end of the range
+        assert_eq!(next("5/20 * * * *", at(2026, 3, 1, 10, 30)), Some(at(2026, 3, 1, 10, 45)));
+        assert_eq!(next("0 0-12/6 * * *", at(2026, 3, 1, 6, 0)), Some(at(2026, 3, 1, 12, 0)));
+    }
+
+    #[test]
+    fn ranges_and_lists() {
+        assert_eq!(next("0 9-17 * * *", at(2026, 3, 1, 17, 30)), Some(at(2026, 3, 2, 9, 0)));
+        assert_eq!(next("0,30 * * * *", at(2026, 3, 1, 10, 5)), Some(at(2026, 3, 1, 10, 30)));
+        assert_eq!(next("0 8 * * mon-fri", at(2026, 10, 16, 9, 0)), Some(at(2026, 10, 19, 8, 0)));
+        assert_eq!(next("0 0 * jun,DEC *", at(2026, 7, 1, 0, 0)), Some(at(2026, 12, 1, 0, 0)));
+        // 7 is Sunday too
+        assert_eq!(next("0 0 * * 7", at(2026, 10, 14, 0, 0)), Some(at(2026, 10, 18, 0, 0)));
+    }
+
+    #[test]
+    fn restricted_day_fields_are_ored() {
+        // The 13th or any Friday: 2026-10-16 is a Friday, 2026-12-13 a Sunday
+        assert_eq!(next("0 0 13 * 5", at(2026, 10, 14, 0, 0)), Some(at(2026, 10, 16, 0, 0)));
+        assert_eq!(next("0 0 13 * 5", at(2026, 12, 12, 0, 0)), Some(at(2026, 12, 13, 0, 0)));
+        // A range over every day is still a restriction
+        assert_eq!(next("0 0 1-31 * 1", at(2026, 10, 14, 0, 0)), Some(at(2026, 10, 15, 0, 0)));
+    }
+
+    #[test]
+    fn starred_day_fields_are_anded() {
+        // Odd days that are Mondays: 2026-10-19 is the first after the 14th
+        assert_eq!(next("0 0 */2 * 1", at(2026, 10, 14, 0, 0)), Some(at(2026, 10, 19, 0, 0)));
+        assert_eq!(next("0 0 1 * */7", at(2026, 10, 14, 0, 0)), Some(at(2026, 11, 1, 0, 0)));
+    }
+
+    #[test]
+    fn month_rollover() {
+        // April has no 31st
+        assert_eq!(next("0 0 31 * *", at(2026, 4, 15, 0, 0)), Some(at(2026, 5, 31, 0, 0)));
+        assert_eq!(next("@monthly", at(2026, 12, 31, 23, 59)), Some(at(2027, 1, 1, 0, 0)));
+        assert_eq!(next("0 12 29 2 *", at(2026, 3, 1, 0, 0)), Some(at(2028, 2, 29, 12, 0)));
+        assert_eq!(next("0 0 31 2 *", at(2026, 1, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn rejects_bad_fields() {
+        for expr in ["* * * *", "60 * * * *", "* * 0 * *", "*/0 * * * *", "5-1 * * * *", "* * * foo *"] {
+            assert!(Schedule::parse(expr).is_err(), "{}", expr);
+        }
+    }
+}
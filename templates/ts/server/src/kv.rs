@@ -0,0 +1,150 @@
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry as MapEntry;
+use serde_json::Value;
+use std::fmt::Write as _;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+
+// Expired keys are dropped on access; the sweep reclaims ones nobody reads
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+static STORE: OnceLock<KvStore> = OnceLock::new();
+
+#[derive(Debug, thiserror::Error)]
+pub enum KvError {
+    #[error("value at '{0}' is not a number")]
+    NotANumber(String),
+    #[error("increment of '{0}' overflows")]
+    Overflow(String),
+}
+
+struct Entry {
+    value: Value,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn is_live(&self, now: Instant) -> bool {
+        self.expires_at.is_none_or(|at| at > now)
+    }
+}
+
+/// Process-wide key-value store behind the `kv` global. Every worker isolate
+/// talks to the same map, and each operation holds the key's shard lock for
+/// its whole read-modify-write, so `incr` and `cas` are atomic across threads.
+#[derive(Default)]
+pub struct KvStore {
+    map: DashMap<String, Entry>,
+}
+
+impl KvStore {
+    pub fn get() -> &'static Self {
+        STORE.get_or_init(Self::default)
+    }
+
+    pub fn read(&self, key: &str) -> Option<Value> {
+        let now = Instant::now();
+        if let Some(entry) = self.map.get(key)
+            && entry.is_live(now)
+        {
+            return Some(entry.value.clone());
+        }
+        self.map.remove_if(key, |_, entry| !entry.is_live(now));
+        None
+    }
+
+    pub fn set(&self, key: &str, value: Value, ttl: Option<Duration>) {
+        self.map.insert(key.to_string(), Entry { value, expires_at: ttl.map(|ttl| Instant::now() + ttl) });
+    }
+
+    /// Adds `by` to the number at `key`, starting from 0 when it is missing.
+    /// An existing TTL is kept. Integers stay integers unless `by` is a float.
+    pub fn incr(&self, key: &str, by: f64) -> Result<Value, KvError> {
+        let now = Instant::now();
+        let mut entry = self.map.entry(key.to_string()).or_insert(Entry { value: Value::from(0), expires_at: None });
+        if !entry.is_live(now) {
+            *entry = Entry { value: Value::from(0), expires_at: None };
+        }
+        let next = match (&entry.value, by.fract() == 0.0) {
+            (Value::Number(n), true) if n.is_i64() => {
+                let sum = n.as_i64().and_then(|n| n.checked_add(by as i64));
+                Value::from(sum.ok_or_else(|| KvError::Overflow(key.to_string()))?)
+            }
+            (Value::Number(n), _) => serde_json::Number::from_f64(n.as_f64().unwrap_or(0.0) + by)
+                .map(Value::Number)
+                .ok_or_else(|| KvError::Overflow(key.to_string()))?,
+            _ => return Err(KvError::NotANumber(key.to_string())),
+        };
+        entry.value = next.clone();
+        Ok(next)
+    }
+
+    /// Sets or clears (`None`) the TTL of an existing key.
+    pub fn expire(&self, key: &str, ttl: Option<Duration>) -> bool {
+        let now = Instant::now();
+        match self.map.get_mut(key) {
+            Some(mut entry) if entry.is_live(now) => {
+                entry.expires_at = ttl.map(|ttl| now + ttl);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Stores `value` only if the key currently holds `expected`, or is missing
+    /// when `expected` is `None`.
+    pub fn cas(&self, key: &str, expected: Option<&Value>, value: Value, ttl: Option<Duration>) -> bool {
+        let now = Instant::now();
+        let expires_at = ttl.map(|ttl| now + ttl);
+        match self.map.entry(key.to_string()) {
+            MapEntry::Occupied(mut occupied) => {
+                let current = occupied.get();
+                let current = current.is_live(now).then_some(&current.value);
+                if current != expected {
+                    return false;
+                }
+                occupied.insert(Entry { value, expires_at });
+                true
+            }
+            MapEntry::Vacant(vacant) => {
+                if expected.is_some() {
+                    return false;
+                }
+                vacant.insert(Entry { value, expires_at });
+                true
+            }
+        }
+    }
+
+    pub fn delete(&self, key: &str) -> bool {
+        let now = Instant::now();
+        self.map.remove(key).is_some_and(|(_, entry)| entry.is_live(now))
+    }
+
+    fn sweep(&self) {
+        let now = Instant::now();
+        self.map.retain(|_, entry| entry.is_live(now));
+    }
+}
+
+/// Periodically drops expired keys on the current Tokio runtime.
+pub fn start_sweeper() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            KvStore::get().sweep();
+        }
+    });
+}
+
+/// Prometheus text exposition of the store size.
+pub fn render(out: &mut String) {
+    let Some(store) = STORE.get() else {
+        return;
+    };
+    metrics::header(out, "titan_kv_keys", "gauge", "Keys in the shared kv store, including ones not yet swept.");
+    let _ = writeln!(out, "titan_kv_keys {}", store.map.len());
+}
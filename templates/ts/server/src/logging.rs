@@ -0,0 +1,194 @@
+use serde_json::Value;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, Registry, reload};
+
+use crate::utils::{blue, gray, red, yellow};
+
+// Events of the server itself, JS included, pass at the configured level;
+// other crates' only from WARN up
+const TARGET: &str = "titan_server";
+
+static FILTER: OnceLock<reload::Handle<Targets, Registry>> = OnceLock::new();
+static LEVEL: Mutex<LevelFilter> = Mutex::new(LevelFilter::INFO);
+
+/// Installs the logger. `log.level` (or TITAN_LOG_LEVEL) is one of trace,
+/// debug, info, warn, error or off; `log.format` (or TITAN_LOG_FORMAT) is
+/// `"pretty"`, coloured lines for a terminal, or `"json"`, one object per line.
+pub fn init(config: &Value) {
+    let mut problems = Vec::new();
+    let format = std::env::var("TITAN_LOG_FORMAT").ok().or_else(|| config["format"].as_str().map(str::to_string));
+    let json = match format.as_deref() {
+        None | Some("pretty") => false,
+        Some("json") => true,
+        Some(other) => {
+            problems.push(format!("Unknown log format '{}', using pretty", other));
+            false
+        }
+    };
+    let level = std::env::var("TITAN_LOG_LEVEL").ok().or_else(|| config["level"].as_str().map(str::to_string));
+    let level = match level {
+        None => LevelFilter::INFO,
+        Some(name) => name.parse().unwrap_or_else(|_| {
+            problems.push(format!("Unknown log level '{}', using info", name));
+            LevelFilter::INFO
+        }),
+    };
+
+    *LEVEL.lock().unwrap_or_else(|e| e.into_inner()) = level;
+    let (filter, handle) = reload::Layer::new(targets(level));
+    if tracing_subscriber::registry().with(filter).with(Output { json }).try_init().is_ok() {
+        let _ = FILTER.set(handle);
+    }
+    for problem in problems {
+        tracing::warn!("{}", problem);
+    }
+}
+
+/// The level in force.
+pub fn level() -> String {
+    LEVEL.lock().unwrap_or_else(|e| e.into_inner()).to_string().to_ascii_lowercase()
+}
+
+/// Changes the level while the server runs, for every worker at once, and
+/// returns the previous one.
+pub fn set_level(level: &str) -> Result<String, String> {
+    let level: LevelFilter = level.parse().map_err(|_| format!("unknown log level '{}'", level))?;
+    let handle = FILTER.get().ok_or("logging is not initialised")?;
+    handle.reload(targets(level)).map_err(|e| e.to_string())?;
+    let previous = std::mem::replace(&mut *LEVEL.lock().unwrap_or_else(|e| e.into_inner()), level);
+    Ok(previous.to_string().to_ascii_lowercase())
+}
+
+fn targets(level: LevelFilter) -> Targets {
+    Targets::new().with_target(TARGET, level).with_default(LevelFilter::WARN)
+}
+
+/// Writes each event to stdout as a line. Secrets from titan.config.toml are
+/// redacted from the message and every field.
+struct Output {
+    json: bool,
+}
+
+impl<S: Subscriber> Layer<S> for Output {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let level = *event.metadata().level();
+        let line = if self.json { json_line(level, event.metadata().target(), fields) } else { pretty_line(level, fields) };
+        let _ = writeln!(std::io::stdout().lock(), "{}", line);
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    stack: Option<String>,
+    values: Vec<(&'static str, Value)>,
+}
+
+impl Fields {
+    fn push(&mut self, field: &Field, value: Value) {
+        self.values.push((field.name(), value));
+    }
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let value = crate::config::redact(value).into_owned();
+        match field.name() {
+            "message" => self.message = value,
+            "stack" => self.stack = Some(value),
+            _ => self.push(field, Value::String(value)),
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        // Durations in ms; more digits than this is noise
+        self.push(field, Value::from((value * 100.0).round() / 100.0));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, Value::Bool(value));
+    }
+}
+
+fn json_line(level: Level, target: &str, fields: Fields) -> String {
+    let mut line = format!(
+        "{{\"time\":\"{}\",\"level\":\"{}\",\"target\":{},\"msg\":{}",
+        timestamp(),
+        level.as_str().to_ascii_lowercase(),
+        Value::from(target),
+        Value::from(fields.message)
+    );
+    for (name, value) in &fields.values {
+        let _ = write!(line, ",{}:{}", Value::from(*name), value);
+    }
+    if let Some(stack) = fields.stack {
+        let _ = write!(line, ",\"stack\":{}", Value::from(stack));
+    }
+    line.push('}');
+    line
+}
+
+fn pretty_line(level: Level, fields: Fields) -> String {
+    let (prefix, message) = match level {
+        Level::ERROR => (red("[Titan]"), red(&fields.message)),
+        Level::WARN => (yellow("[Titan]"), yellow(&fields.message)),
+        Level::INFO => (blue("[Titan]"), fields.message),
+        _ => (gray("[Titan]"), gray(&fields.message)),
+    };
+    let mut line = format!("{} {}", prefix, message);
+    for (name, value) in &fields.values {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let _ = write!(line, " {}", gray(&format!("{}={}", name, value)));
+    }
+    if let Some(stack) = fields.stack {
+        let _ = write!(line, "\n{}", gray(&stack));
+    }
+    line
+}
+
+fn timestamp() -> String {
+    timestamp_of(SystemTime::now())
+}
+
+/// RFC 3339 in UTC, to the millisecond.
+pub fn timestamp_of(at: SystemTime) -> String {
+    let now = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs() as i64;
+    let (year, month, day) = crate::utils::civil_from_days(secs.div_euclid(86_400));
+    let rem = secs.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        now.subsec_millis()
+    )
+}
@@ -1,384 +1,2127 @@
 use anyhow::Result;
 use axum::{
     Router,
-    body::{Body, to_bytes},
-    extract::State,
+    body::Body,
+    extract::{ConnectInfo, State, connect_info::Connected},
     http::{Request, StatusCode},
     response::{IntoResponse, Json},
-    routing::any,
+    routing::{any, get},
 };
 use serde_json::Value;
-use std::time::Instant;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime};
 use std::{collections::HashMap, fs, path::PathBuf, sync::Arc};
 use tokio::net::TcpListener;
 use smallvec::SmallVec;
 
 mod utils;
 
+mod accept;
+mod access_log;
 mod action_management;
+mod admin;
+mod affinity;
+mod auth;
+mod body;
+mod breaker;
+mod bus;
+mod cache;
+mod cli;
+mod compression;
+mod config;
+mod cookies;
+mod cors;
+mod crypto;
+mod dashboard;
+mod db;
+mod error;
+mod etag;
 mod extensions;
+mod fallbacks;
+mod files;
+mod formats;
+mod geo;
+mod graphql;
+mod groups;
+mod grpc;
+mod heap;
+mod http3;
+mod idempotency;
+mod inspector;
+mod jobs;
+mod kv;
+mod logging;
+mod metrics;
+mod middleware;
+mod multipart;
+mod openapi;
+mod placement;
+mod proxy;
+mod query;
+mod profiler;
+mod qpack;
+mod rate_limit;
+mod redis;
+mod restart;
+mod router;
+mod routing;
 mod runtime;
+mod scheduler;
+mod session;
+mod slow;
+mod sourcemap;
+mod standalone;
+mod static_files;
+mod tasks;
+mod telemetry;
+mod tenants;
+mod timing;
+mod tls;
+mod transpile;
+mod urls;
+mod validation;
+mod views;
+mod vhost;
+mod watch;
+mod webhooks;
+mod websocket;
 
 use action_management::{
-    DynamicRoute, RouteVal, match_dynamic_route,
+    DynamicRoute, RouteVal, find_actions_dir, match_dynamic_route, scan_actions,
 };
-use runtime::RuntimeManager;
-use utils::{blue, gray, green, red, white, yellow};
+use compression::CompressionPolicy;
+use jobs::JobScheduler;
+use multipart::UploadConfig;
+use router::FileRouter;
+use static_files::StaticFiles;
+use runtime::{AdaptiveQueue, AutoscalePolicy, HedgePolicy, QueuePolicy, RecyclePolicy, RequestTask, ResponseBody, RuntimeLimits, RuntimeManager, ShedPolicy, WorkerResult};
+use scheduler::Priority;
+use telemetry::{SpanRecord, TraceContext};
+use websocket::Peer as _;
 
 #[derive(Clone)]
 struct AppState {
     routes: Arc<HashMap<String, RouteVal>>,
     dynamic_routes: Arc<Vec<DynamicRoute>>,
+    file_routes: Arc<FileRouter>,
+    routing: Arc<routing::Routing>,
+    // Which of app/actions/_not_found, _method_not_allowed and _error exist
+    fallbacks: fallbacks::Fallbacks,
     runtime: Arc<RuntimeManager>,
+    request_timeout: Option<Duration>,
+    // Actions whose `config` export sets `timeoutMs`; None for no deadline
+    action_timeouts: Arc<HashMap<String, Option<Duration>>>,
+    // Include JS stack traces in error responses
+    expose_stacks: bool,
+    // Queue, exec and serialize times in Server-Timing and the request log
+    server_timing: bool,
+    sse_keep_alive: Duration,
+    uploads: UploadConfig,
+    public: Option<Arc<StaticFiles>>,
+    compression: Arc<CompressionPolicy>,
+    jobs: Arc<JobScheduler>,
+    sessions: Option<Arc<session::SessionConfig>>,
+    // How long /healthz waits for workers to answer a ping
+    health_timeout: Duration,
+    body: Arc<body::BodyPolicy>,
+    cors: Option<Arc<cors::CorsConfig>>,
+    graphql: Option<Arc<graphql::Graphql>>,
+    grpc: Option<Arc<grpc::Grpc>>,
+    cache: Option<&'static cache::ResponseCache>,
+    idempotency: Option<Arc<idempotency::IdempotencyConfig>>,
+    // Tag action responses that don't set an ETag themselves
+    etags: bool,
+    docs: Option<Arc<openapi::Docs>>,
+    validator: Option<Arc<validation::Validator>>,
+}
+
+/// The peer address of a connection, whichever listener accepted it.
+#[derive(Clone, Copy)]
+pub struct ClientAddr(pub SocketAddr);
+
+impl Connected<axum::serve::IncomingStream<'_, TcpListener>> for ClientAddr {
+    fn connect_info(stream: axum::serve::IncomingStream<'_, TcpListener>) -> Self {
+        Self(*stream.remote_addr())
+    }
+}
+
+impl Connected<axum::serve::IncomingStream<'_, tls::TlsListener>> for ClientAddr {
+    fn connect_info(stream: axum::serve::IncomingStream<'_, tls::TlsListener>) -> Self {
+        Self(*stream.remote_addr())
+    }
+}
+
+// Unix socket peers are on this machine; they count as loopback, which is
+// what `trusted_proxies` needs to know about a local nginx
+#[cfg(unix)]
+impl Connected<axum::serve::IncomingStream<'_, tokio::net::UnixListener>> for ClientAddr {
+    fn connect_info(_stream: axum::serve::IncomingStream<'_, tokio::net::UnixListener>) -> Self {
+        Self(SocketAddr::from(([127, 0, 0, 1], 0)))
+    }
+}
+
+// GraphQL ---------------------------------------------------------------------
+
+async fn graphql_route(State(state): State<AppState>, req: Request<Body>) -> axum::response::Response {
+    let request_id = telemetry::request_id(req.headers());
+    let mut response = graphql_handler(&state, req, &request_id).await;
+    if let Ok(value) = axum::http::HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    response
+}
+
+/// One GraphQL request. It goes through the interceptors, CORS and body
+/// limits as the action `graphql`, once; its resolver actions then run with
+/// the claims and session it came with.
+async fn graphql_handler(state: &AppState, req: Request<Body>, request_id: &str) -> axum::response::Response {
+    let Some(graphql) = state.graphql.clone() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let method = req.method().as_str().to_uppercase();
+    let path = req.uri().path().to_string();
+    if let Some(cors) = &state.cors
+        && method == "OPTIONS"
+        && cors::requested_method(req.headers()).is_some()
+    {
+        return cors.preflight("graphql", req.headers());
+    }
+    if method != "GET" && method != "POST" {
+        return (StatusCode::METHOD_NOT_ALLOWED, [(axum::http::header::ALLOW, "GET, POST")], "Method Not Allowed").into_response();
+    }
+
+    let start = Instant::now();
+    let remote_addr = req.extensions().get::<ConnectInfo<ClientAddr>>().map(|info| info.0.0);
+    let trace = TraceContext::from_headers(req.headers());
+    let query_string = req.uri().query().unwrap_or("").to_string();
+    let (parts, body) = req.into_parts();
+    let header = |name: axum::http::HeaderName| parts.headers.get(name).and_then(|v| v.to_str().ok());
+
+    let request = if method == "GET" {
+        graphql::Request::from_query(&query_string)
+    } else {
+        let rule = state.body.rule_for("graphql");
+        let declared_len = header(axum::http::header::CONTENT_LENGTH).and_then(|len| len.parse::<u64>().ok());
+        match body::read_all(body, rule.max_bytes, declared_len).await {
+            Ok(bytes) => graphql::Request::from_body(header(axum::http::header::CONTENT_TYPE), &bytes),
+            Err(e) => {
+                let status = StatusCode::from_u16(e.status()).unwrap_or(StatusCode::BAD_REQUEST);
+                return (status, e.to_string()).into_response();
+            }
+        }
+    };
+    let request = match request {
+        Ok(request) => request,
+        Err(message) => {
+            tracing::info!(status = 400, duration_ms = elapsed_ms(start), request_id, "{} {} → graphql: {}", method, path, message);
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "errors": [{ "message": message }] }))).into_response();
+        }
+    };
+
+    let headers: SmallVec<[(String, String); 8]> =
+        parts.headers.iter().map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string())).collect();
+    let session = match &state.sessions {
+        Some(sessions) => Some(sessions.load(cookies::header(&parts.headers).as_deref()).await),
+        None => None,
+    };
+    let (mut gate, _) = state.runtime.task("graphql".to_string(), method.clone(), path.clone());
+    gate.headers = headers.clone();
+    gate.correlation_id = request_id.to_string();
+    gate.remote_addr = remote_addr;
+    if let Some(rejected) = state.runtime.admit(&mut gate) {
+        let status = StatusCode::from_u16(rejected.status).unwrap_or(StatusCode::FORBIDDEN);
+        tracing::info!(status = status.as_u16(), duration_ms = elapsed_ms(start), request_id, "{} {} → graphql rejected", method, path);
+        let body = match rejected.body {
+            ResponseBody::Json(value) => Body::from(value.to_string()),
+            ResponseBody::Bytes(bytes) | ResponseBody::JsonText(bytes) => Body::from(bytes),
+            _ => Body::empty(),
+        };
+        let mut builder = axum::http::Response::builder().status(status).header(axum::http::header::CONTENT_TYPE, "application/json");
+        for (k, v) in rejected.headers.iter().chain(&gate.response_headers) {
+            builder = builder.header(k, v);
+        }
+        return builder.body(body).unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    }
+
+    let ctx = graphql::Context {
+        runtime: state.runtime.clone(),
+        path: path.clone(),
+        headers,
+        correlation_id: request_id.to_string(),
+        trace: Some(trace),
+        remote_addr,
+        auth: gate.auth.clone(),
+        session: session.as_ref().map(session::Loaded::data),
+        geo: gate.geo.clone(),
+        deadline: state.request_timeout.map(|timeout| start + timeout),
+    };
+    let answer = graphql.run(request, &ctx, method == "GET").await;
+
+    let mut response_headers = std::mem::take(&mut gate.response_headers);
+    if let (Some(sessions), Some(loaded)) = (&state.sessions, session)
+        && let Some(cookie) = sessions.commit(loaded, None).await
+    {
+        response_headers.push(("set-cookie".to_string(), cookie));
+    }
+    if let Some(cors) = &state.cors {
+        cors.apply("graphql", header(axum::http::header::ORIGIN), &mut response_headers);
+    }
+    if answer.status == 405 {
+        response_headers.push(("allow".to_string(), "POST".to_string()));
+    }
+    let content_type = match header(axum::http::header::ACCEPT) {
+        Some(accept) if accept.contains("application/graphql-response+json") => "application/graphql-response+json; charset=utf-8",
+        _ => "application/json",
+    };
+    let status = StatusCode::from_u16(answer.status).unwrap_or(StatusCode::OK);
+    let mut builder = axum::http::Response::builder().status(status).header(axum::http::header::CONTENT_TYPE, content_type);
+    for (k, v) in &response_headers {
+        builder = builder.header(k, v);
+    }
+    let payload = bytes::Bytes::from(answer.body);
+    let encoding = header(axum::http::header::ACCEPT_ENCODING).and_then(compression::negotiate);
+    let body = match (encoding.zip(state.compression.rule_for("graphql")), builder.headers_mut()) {
+        (Some((encoding, rule)), Some(headers)) => Body::from(compression::apply(headers, payload, rule, encoding).await),
+        _ => Body::from(payload),
+    };
+    if answer.rejected {
+        tracing::info!(status = status.as_u16(), duration_ms = elapsed_ms(start), request_id, "{} {} → graphql rejected", method, path);
+    } else {
+        tracing::info!(status = status.as_u16(), duration_ms = elapsed_ms(start), request_id, "{} {} → graphql", method, path);
+    }
+    builder.body(body).unwrap_or_else(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Invalid response headers").into_response())
+}
+
+// gRPC ------------------------------------------------------------------------
+
+/// One gRPC call, run as the action its method maps to: the decoded request
+/// message is `req.body`, the metadata its headers, and the object it
+/// returns is encoded as the response message.
+async fn grpc_route(State(state): State<AppState>, req: Request<Body>) -> axum::response::Response {
+    let Some(grpc) = state.grpc.clone() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if req.method() != axum::http::Method::POST || !grpc::is_grpc(req.headers()) {
+        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Only gRPC is served on this port").into_response();
+    }
+    let start = Instant::now();
+    let request_id = telemetry::request_id(req.headers());
+    let path = req.uri().path().to_string();
+    let metadata = [("x-request-id".to_string(), request_id.clone())];
+    let Some(route) = grpc.route(&path) else {
+        tracing::info!(grpc_status = 12, duration_ms = elapsed_ms(start), request_id, "GRPC {} → unimplemented", path);
+        return grpc::Status::new(grpc::Code::Unimplemented, format!("Method {} is not implemented", path)).into_response(&metadata);
+    };
+
+    let remote_addr = req.extensions().get::<ConnectInfo<ClientAddr>>().map(|info| info.0.0);
+    let trace = TraceContext::from_headers(req.headers());
+    // The client's deadline, within the server's own
+    let deadline = match (grpc::timeout(req.headers()), timeout_for(&state, &route.action)) {
+        (Some(asked), Some(limit)) => Some(asked.min(limit)),
+        (asked, limit) => asked.or(limit),
+    };
+    let (parts, body) = req.into_parts();
+    let message = match body::read_all(body, state.body.rule_for(&route.action).max_bytes, None).await {
+        Ok(bytes) => grpc.decode_request(route, &parts.headers, &bytes),
+        Err(e) => Err(grpc::Status::new(grpc::Code::ResourceExhausted, e.to_string())),
+    };
+    let message = match message {
+        Ok(message) => message,
+        Err(status) => {
+            tracing::info!(grpc_status = status.code as u32, duration_ms = elapsed_ms(start), request_id, "GRPC {} → {}: {}", path, route.action, status.message);
+            return status.into_response(&metadata);
+        }
+    };
+
+    let headers: SmallVec<[(String, String); 8]> =
+        parts.headers.iter().map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string())).collect();
+    let (mut task, rx) = state.runtime.task(route.action.clone(), "GRPC".to_string(), path.clone());
+    task.body = Some(bytes::Bytes::from(message.to_string()));
+    task.headers = headers;
+    task.correlation_id = request_id.clone();
+    task.trace = Some(trace);
+    task.remote_addr = remote_addr;
+    let result = state.runtime.try_execute(task, rx, deadline).await;
+    let mut metadata = metadata.to_vec();
+    let answer = match result {
+        Err(e) => Err(grpc::Status::new(grpc::Code::from_http(e.status()), e.to_string())),
+        Ok(result) => {
+            metadata.extend(result.headers.iter().cloned());
+            // An `{ error }` result fails the call even with a 2xx status
+            let failed = |message: &str| {
+                let code = match grpc::Code::from_http(result.status) {
+                    grpc::Code::Ok => grpc::Code::Unknown,
+                    code => code,
+                };
+                grpc::Status::new(code, message)
+            };
+            match (result.error_message(), &result.body) {
+                (Some(message), _) => Err(failed(message)),
+                (None, body) if result.status >= 400 => {
+                    // t.response.json({ error }, status) arrives as bytes
+                    let message = match body {
+                        ResponseBody::Bytes(bytes) | ResponseBody::JsonText(bytes) => serde_json::from_slice::<Value>(bytes)
+                            .ok()
+                            .and_then(|v| v.get("error").and_then(Value::as_str).map(str::to_string)),
+                        _ => None,
+                    };
+                    let reason = StatusCode::from_u16(result.status).ok().and_then(|s| s.canonical_reason()).unwrap_or("");
+                    Err(failed(message.as_deref().unwrap_or(reason)))
+                }
+                (None, ResponseBody::Json(value)) => grpc.encode_response(route, value.clone()),
+                (None, ResponseBody::Bytes(bytes) | ResponseBody::JsonText(bytes)) => serde_json::from_slice(bytes)
+                    .map_err(|_| grpc::Status::new(grpc::Code::Internal, "The action's response is not JSON"))
+                    .and_then(|value| grpc.encode_response(route, value)),
+                (None, ResponseBody::Empty) if route.server_streaming => Ok(bytes::Bytes::new()),
+                (None, ResponseBody::Empty) => grpc.encode_response(route, Value::Object(Default::default())),
+                (None, ResponseBody::Stream(_)) => Err(grpc::Status::new(
+                    grpc::Code::Internal,
+                    "res.write() can't answer a gRPC call; return an array for a server-streaming method",
+                )),
+            }
+        }
+    };
+    match answer {
+        Ok(messages) => {
+            tracing::info!(grpc_status = 0, duration_ms = elapsed_ms(start), request_id, "GRPC {} → {}", path, route.action);
+            grpc::ok_response(messages, &metadata)
+        }
+        Err(status) => {
+            tracing::info!(grpc_status = status.code as u32, duration_ms = elapsed_ms(start), request_id, "GRPC {} → {}: {}", path, route.action, status.message);
+            status.into_response(&metadata)
+        }
+    }
 }
 
 // Root/dynamic handlers -----------------------------------------------------
 
+async fn metrics_route(State(state): State<AppState>) -> impl IntoResponse {
+    let mut body = state.runtime.render_metrics();
+    state.jobs.render(&mut body);
+    tasks::render(&mut body);
+    kv::render(&mut body);
+    cache::render(&mut body);
+    idempotency::render(&mut body);
+    bus::render(&mut body);
+    db::render(&mut body);
+    redis::render(&mut body);
+    breaker::render(&mut body);
+    rate_limit::render(&mut body);
+    access_log::render(&mut body);
+    (
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        body,
+    )
+}
+
+/// The OpenAPI document of the action routes.
+async fn openapi_route(State(state): State<AppState>) -> axum::response::Response {
+    let Some(docs) = &state.docs else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let document = docs.document(&state.runtime).await;
+    ([(axum::http::header::CONTENT_TYPE, "application/json")], document).into_response()
+}
+
+async fn docs_route(State(state): State<AppState>) -> axum::response::Response {
+    match &state.docs {
+        Some(docs) => axum::response::Html(docs.ui_page()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Liveness: at least one worker answered a ping in time. A worker only
+/// answers between two pieces of work, so one stuck in JS counts as down.
+async fn healthz_route(State(state): State<AppState>) -> impl IntoResponse {
+    let answers = state.runtime.ping_workers(state.health_timeout).await;
+    let alive = answers.iter().filter(|a| **a == Some(true)).count();
+    let status = if alive > 0 { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(serde_json::json!({
+        "status": if alive > 0 { "ok" } else { "unresponsive" },
+        "responding": alive,
+        "pinged": answers.iter().flatten().count(),
+    })))
+}
+
+/// Readiness, with the state of every worker and of the queue.
+async fn readyz_route(State(state): State<AppState>) -> impl IntoResponse {
+    let readiness = state.runtime.readiness();
+    let status = if readiness.is_ok() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(serde_json::json!({
+        "status": if readiness.is_ok() { "ready" } else { "unavailable" },
+        "reason": readiness.err(),
+        "workers": state.runtime.worker_status(),
+        "queue": state.runtime.queue_status(),
+    })))
+}
+
+// What a request path routes to
+enum Resolved<'a> {
+    Exact(&'a RouteVal),
+    // "dynamic" or "file", the action and the params taken from the path
+    Action(&'static str, String, HashMap<String, String>),
+}
+
+/// The deadline of a request for `action`: its `timeoutMs`, or `timeout_ms`.
+fn timeout_for(state: &AppState, action: &str) -> Option<Duration> {
+    state.action_timeouts.get(action).copied().unwrap_or(state.request_timeout)
+}
+
+/// `timeoutMs` of each action's `config` export, 0 for no deadline. None
+/// of them apply while a debugger may hold a worker at a breakpoint.
+fn action_timeouts(configs: &HashMap<String, Value>) -> Result<HashMap<String, Option<Duration>>, String> {
+    let mut timeouts = HashMap::new();
+    if inspector::enabled() {
+        return Ok(timeouts);
+    }
+    for (action, config) in configs {
+        match &config["timeoutMs"] {
+            Value::Null => {}
+            value => {
+                let ms = value.as_u64().ok_or_else(|| format!("{}: config.timeoutMs must be a whole number of milliseconds", action))?;
+                timeouts.insert(action.clone(), (ms > 0).then(|| Duration::from_millis(ms)));
+            }
+        }
+    }
+    Ok(timeouts)
+}
+
+/// The methods routed at a path, for the Allow header of a 405 or an
+/// OPTIONS answer. OPTIONS itself is answered at any routed path.
+fn allowed_methods(state: &AppState, path: &str) -> Vec<&'static str> {
+    let other = routing::toggled(path).filter(|_| state.routing.trailing_slash != routing::TrailingSlash::Strict);
+    let mut allowed: Vec<&'static str> = fallbacks::METHODS
+        .into_iter()
+        .filter(|method| {
+            resolve_route(state, method, path).is_some() || other.as_deref().is_some_and(|other| resolve_route(state, method, other).is_some())
+        })
+        .collect();
+    if !allowed.is_empty() && !allowed.contains(&"OPTIONS") {
+        allowed.push("OPTIONS");
+    }
+    allowed
+}
+
+/// The route for a request; a HEAD request without a route of its own
+/// goes to the GET one.
+fn resolve_route<'a>(state: &'a AppState, method: &str, path: &str) -> Option<Resolved<'a>> {
+    route_for(state, method, path).or_else(|| if method == "HEAD" { route_for(state, "GET", path) } else { None })
+}
+
+/// Exact routes first, then dynamic ones, then file routes
+/// (actions/users/[id]/get.js).
+fn route_for<'a>(state: &'a AppState, method: &str, path: &str) -> Option<Resolved<'a>> {
+    let routing = &state.routing;
+    let exact = routing
+        .exact(&state.routes, &format!("{}:{}", method, path))
+        .or_else(|| routing.exact(&state.routes, path))
+        .filter(|route| route.r#type == "action" || route.r#type == "json" || route.value.is_string());
+    if let Some(route) = exact {
+        return Some(Resolved::Exact(route));
+    }
+    if let Some((action, params)) = match_dynamic_route(method, path, &state.dynamic_routes, routing) {
+        return Some(Resolved::Action("dynamic", action, params));
+    }
+    let (action, params) = state.file_routes.match_route(method, path, routing)?;
+    Some(Resolved::Action("file", action, params))
+}
+
 async fn root_route(state: State<AppState>, req: Request<Body>) -> impl IntoResponse {
-    dynamic_handler_inner(state, req).await
+    with_request_id(state, req).await
 }
 
 async fn dynamic_route(state: State<AppState>, req: Request<Body>) -> impl IntoResponse {
-    dynamic_handler_inner(state, req).await
+    with_request_id(state, req).await
+}
+
+/// Tags the request with its `X-Request-Id` (the client's, or a new one) and
+/// echoes it on the response, whichever way the request ends.
+async fn with_request_id(state: State<AppState>, req: Request<Body>) -> axum::response::Response {
+    let request_id = telemetry::request_id(req.headers());
+    let head = req.method() == axum::http::Method::HEAD;
+    let mut response = if state.server_timing {
+        timing::scope(dynamic_handler_inner(state, req, &request_id)).await.into_response()
+    } else {
+        dynamic_handler_inner(state, req, &request_id).await.into_response()
+    };
+    if let Ok(value) = axum::http::HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    if head {
+        response = without_body(response);
+    }
+    response
 }
 
+/// A HEAD answer: the headers of the GET one, with the length its body
+/// would have had when that is known up front.
+fn without_body(response: axum::response::Response) -> axum::response::Response {
+    let (mut parts, body) = response.into_parts();
+    if let Some(len) = http_body::Body::size_hint(&body).exact()
+        && !parts.headers.contains_key(axum::http::header::CONTENT_LENGTH)
+    {
+        parts.headers.insert(axum::http::header::CONTENT_LENGTH, len.into());
+    }
+    axum::response::Response::from_parts(parts, Body::empty())
+}
+
+/// A request on its way through the stages of `dynamic_handler_inner`.
+struct Incoming<'a> {
+    request_id: &'a str,
+    method: String,
+    path: String,
+    // A CORS preflight is routed like the request it asks about
+    preflight: Option<String>,
+    start: Instant,
+    started_at: SystemTime,
+    remote_addr: Option<SocketAddr>,
+    client_ip: Option<std::net::IpAddr>,
+    trace: TraceContext,
+    query_pairs: Vec<(String, String)>,
+    query_map: HashMap<String, String>,
+    parts: axum::http::request::Parts,
+    headers_map: HashMap<String, String>,
+}
+
+/// The action a request was routed to.
+struct Routed {
+    action_name: String,
+    params: HashMap<String, String>,
+    route_label: String,
+    route_kind: &'static str, // exact | dynamic | file | fallback
+    // `req.error` for _not_found or _method_not_allowed, when one answers
+    unrouted: Option<Value>,
+}
+
+/// How the answer is negotiated, and whether it may come from or go to the
+/// response cache.
+struct CachePlan {
+    encoding: Option<compression::Encoding>,
+    reply_format: formats::Format,
+    revalidating: bool,
+    // If-None-Match and If-Modified-Since of a GET or HEAD
+    conditional: Option<(Option<String>, Option<String>)>,
+    rule: Option<(&'static cache::ResponseCache, &'static cache::Rule)>,
+    key: Option<String>,
+}
+
+/// The request body as the action receives it.
+struct RequestBody {
+    bytes: bytes::Bytes,
+    form: Option<Arc<multipart::Form>>,
+    stream: Option<Arc<body::StreamedBody>>,
+}
+
+/// What the worker pool answered, once app/actions/_error and the fallbacks
+/// have had their say.
+struct Dispatched {
+    result: WorkerResult,
+    error_stack: Option<String>,
+    // The action's own error and status, when _error answered in its place
+    failure: Option<(String, u16)>,
+    wants_html: bool,
+}
+
+type IdempotencyTicket<'s> = Option<(&'s Arc<idempotency::IdempotencyConfig>, idempotency::Ticket)>;
+
+/// Runs a request through its stages. Each one either hands the request on
+/// or answers it.
 async fn dynamic_handler_inner(
     State(state): State<AppState>,
     req: Request<Body>,
-) -> impl IntoResponse {
-    // ---------------------------
-    // BASIC REQUEST INFO
-    // ---------------------------
-    let method = req.method().as_str().to_uppercase();
-    let path = req.uri().path().to_string();
-    let strict_key = format!("{}:{}", method, path);
-    // Also try simple path for generic routes
-    // Check strict first, then simple path
+    request_id: &str,
+) -> axum::response::Response {
+    let (mut req, body, ws_upgrade) = Incoming::new(&state, req, request_id);
+    let mut routed = match route_request(&state, &req, ws_upgrade.is_some()).await {
+        Ok(routed) => routed,
+        Err(response) => return response,
+    };
+    if let (Some(cors), Some(_)) = (&state.cors, &req.preflight) {
+        tracing::info!(duration_ms = elapsed_ms(req.start), request_id, "{} {} → preflight", req.method, req.path);
+        return cors.preflight(&routed.action_name, &req.parts.headers);
+    }
+    if let Some(on_upgrade) = ws_upgrade {
+        return upgrade_websocket(&state, req, routed, on_upgrade).await;
+    }
+    let plan = cache_plan(&state, &mut req, &routed);
+    if let Some(hit) = answer_cached(&state, &req, &routed, &plan) {
+        return hit;
+    }
+    let body = match read_body(&state, &req, &routed, body).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let idempotency_ticket = match claim_idempotency(&state, &req, &routed, &body).await {
+        Ok(ticket) => ticket,
+        Err(response) => return response,
+    };
+    let dispatched = dispatch(&state, &mut req, &mut routed, body).await;
+    finish_response(&state, req, routed, plan, idempotency_ticket, dispatched).await
+}
 
-    // ---------------------------
-    // TIMER + LOG META
-    // ---------------------------
-    let start = Instant::now();
-    let mut route_label = String::from("not_found");
-    let mut route_kind = "none"; // exact | dynamic | reply
+impl<'a> Incoming<'a> {
+    /// Splits off the body, and the upgrade of a WebSocket handshake.
+    fn new(state: &AppState, req: Request<Body>, request_id: &'a str) -> (Self, Body, Option<hyper::upgrade::OnUpgrade>) {
+        let method = req.method().as_str().to_uppercase();
+        let path = req.uri().path().to_string();
+        let preflight = match &state.cors {
+            Some(_) if method == "OPTIONS" => cors::requested_method(req.headers()),
+            _ => None,
+        };
+        let remote_addr = req.extensions().get::<ConnectInfo<ClientAddr>>().map(|info| info.0.0);
+        let trace = TraceContext::from_headers(req.headers());
+        let query_pairs: Vec<(String, String)> = req.uri().query().map(query::pairs).unwrap_or_default();
+        let query_map: HashMap<String, String> = query_pairs.iter().cloned().collect();
 
-    // ---------------------------
-    // QUERY PARSING
-    // ---------------------------
-    let query_pairs: Vec<(String, String)> = req
-        .uri()
-        .query()
-        .map(|q| {
-            q.split('&')
-                .filter_map(|pair| {
-                    let mut it = pair.splitn(2, '=');
-                    Some((it.next()?.to_string(), it.next().unwrap_or("").to_string()))
-                })
-                .collect()
-        })
-        .unwrap_or_default();
-    
-    let query_map: HashMap<String, String> = query_pairs.into_iter().collect();
+        let (mut parts, body) = req.into_parts();
+        let ws_upgrade = if websocket::is_upgrade_request(&parts.headers) {
+            parts.extensions.remove::<hyper::upgrade::OnUpgrade>()
+        } else {
+            None
+        };
+        let mut headers_map: HashMap<String, String> = parts
+            .headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string()))
+            .collect();
+        if let Some(cookie) = cookies::header(&parts.headers) {
+            headers_map.insert("cookie".to_string(), cookie);
+        }
+        let client_ip = proxy::client_ip(remote_addr, |name| parts.headers.get(name).and_then(|v| v.to_str().ok()));
 
-    // ---------------------------
-    // HEADERS & BODY
-    // ---------------------------
-    let (parts, body) = req.into_parts();
+        let incoming = Incoming {
+            request_id,
+            method,
+            path,
+            preflight,
+            start: Instant::now(),
+            started_at: SystemTime::now(),
+            remote_addr,
+            client_ip,
+            trace,
+            query_pairs,
+            query_map,
+            parts,
+            headers_map,
+        };
+        (incoming, body, ws_upgrade)
+    }
+}
 
-    let headers_map: HashMap<String, String> = parts
-        .headers
-        .iter()
-        .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string()))
-        .collect();
+/// Finds the action for a request. Routes answered without one (JSON and
+/// text replies, redirects, assets, OPTIONS, 404 and 405) come back as the
+/// error.
+async fn route_request(state: &AppState, req: &Incoming<'_>, upgrade: bool) -> Result<Routed, axum::response::Response> {
+    let Incoming { request_id, method, path, start, preflight, .. } = req;
+    let route_method = preflight.as_ref().unwrap_or(method);
 
-    let body_bytes = match to_bytes(body, usize::MAX).await {
-        Ok(b) => b,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Failed to read request body").into_response(),
+    let mut routed = Routed {
+        action_name: String::new(),
+        params: HashMap::new(),
+        route_label: String::from("not_found"),
+        route_kind: "none",
+        unrouted: None,
     };
 
-    // ---------------------------
-    // ROUTE RESOLUTION
-    // ---------------------------
-    let mut params: HashMap<String, String> = HashMap::new();
-    let mut action_name: Option<String> = None;
-
-    // Exact route
-    let route = state.routes.get(&strict_key).or_else(|| state.routes.get(&path));
-    if let Some(route) = route {
-        route_kind = "exact";
-        if route.r#type == "action" {
-            let name = route.value.as_str().unwrap_or("unknown").to_string();
-            route_label = name.clone();
-            action_name = Some(name);
-        } else if route.r#type == "json" {
-            let elapsed = start.elapsed();
-            println!(
-                "{} {} {} {}",
-                blue("[Titan]"),
-                white(&format!("{} {}", method, path)),
-                white("→ json"),
-                gray(&format!("in {:.2?}", elapsed))
-            );
-            return Json(route.value.clone()).into_response();
-        } else if let Some(s) = route.value.as_str() {
-            let elapsed = start.elapsed();
-            println!(
-                "{} {} {} {}",
-                blue("[Titan]"),
-                white(&format!("{} {}", method, path)),
-                white("→ reply"),
-                gray(&format!("in {:.2?}", elapsed))
-            );
-            return s.to_string().into_response();
-        }
-    }
-
-    // Dynamic route
-    if action_name.is_none() {
-        if let Some((action, p)) =
-            match_dynamic_route(&method, &path, state.dynamic_routes.as_slice())
-        {
-            route_kind = "dynamic";
-            route_label = action.clone();
-            action_name = Some(action);
-            params = p;
+    let mut resolved = resolve_route(state, route_method, path);
+    // `/users/` for a route at `/users`, or the other way round
+    if resolved.is_none()
+        && state.routing.trailing_slash != routing::TrailingSlash::Strict
+        && let Some(other) = routing::toggled(path)
+        && let Some(found) = resolve_route(state, route_method, &other)
+    {
+        // A preflight can't follow a redirect, so it's answered for the route it leads to
+        if state.routing.trailing_slash == routing::TrailingSlash::Redirect && preflight.is_none() {
+            tracing::info!(duration_ms = elapsed_ms(*start), request_id, "{} {} → redirect to {}", method, path, other);
+            return Err(routing::redirect(method, path, &other, req.parts.uri.query()));
+        }
+        resolved = Some(found);
+    }
+
+    match resolved {
+        Some(Resolved::Exact(route)) => {
+            routed.route_kind = "exact";
+            if route.r#type == "action" {
+                let name = route.value.as_str().unwrap_or("unknown").to_string();
+                routed.route_label = name.clone();
+                routed.action_name = name;
+                return Ok(routed);
+            } else if route.r#type == "json" {
+                tracing::info!(duration_ms = elapsed_ms(*start), request_id, "{} {} → json", method, path);
+                return Err(Json(route.value.clone()).into_response());
+            } else if let Some(s) = route.value.as_str() {
+                tracing::info!(duration_ms = elapsed_ms(*start), request_id, "{} {} → reply", method, path);
+                return Err(s.to_string().into_response());
+            }
         }
+        Some(Resolved::Action(kind, action, params)) => {
+            routed.route_kind = kind;
+            routed.route_label = action.clone();
+            routed.action_name = action;
+            routed.params = params;
+            return Ok(routed);
+        }
+        None => {}
     }
 
-    let action_name = match action_name {
-        Some(a) => a,
+    // Nothing routed here; maybe it's an asset
+    if let Some(public) = &state.public
+        && let Some(response) = public.serve(method, path, &req.parts.headers).await
+    {
+        tracing::info!(status = response.status().as_u16(), duration_ms = elapsed_ms(*start), request_id, "{} {} → static", method, path);
+        return Err(response);
+    }
+    let allowed = allowed_methods(state, path);
+    // OPTIONS without a route of its own is answered from the route table
+    if method == "OPTIONS" && preflight.is_none() && !allowed.is_empty() {
+        tracing::info!(status = 204, duration_ms = elapsed_ms(*start), request_id, "{} {} → options", method, path);
+        return Err((StatusCode::NO_CONTENT, [(axum::http::header::ALLOW, allowed.join(", "))]).into_response());
+    }
+    match state.fallbacks.unrouted(&allowed).filter(|_| preflight.is_none() && !upgrade) {
+        Some(fallback) => {
+            routed.route_kind = "fallback";
+            routed.route_label = fallback.to_string();
+            routed.action_name = fallback.to_string();
+            routed.unrouted = Some(fallbacks::unrouted_error(&allowed));
+            Ok(routed)
+        }
+        None if allowed.is_empty() => {
+            tracing::info!(status = 404, duration_ms = elapsed_ms(*start), request_id, "{} {} → not found", method, path);
+            Err((StatusCode::NOT_FOUND, "Not Found").into_response())
+        }
         None => {
-            let elapsed = start.elapsed();
-            println!(
-                "{} {} {} {}",
-                blue("[Titan]"),
-                white(&format!("{} {}", method, path)),
-                white("→ 404"),
-                gray(&format!("in {:.2?}", elapsed))
-            );
-            return (StatusCode::NOT_FOUND, "Not Found").into_response();
+            tracing::info!(status = 405, duration_ms = elapsed_ms(*start), request_id, "{} {} → method not allowed", method, path);
+            Err((StatusCode::METHOD_NOT_ALLOWED, [(axum::http::header::ALLOW, allowed.join(", "))], "Method Not Allowed").into_response())
+        }
+    }
+}
+
+/// Hands a WebSocket handshake to the routed action and answers it with
+/// the 101, unless the interceptors or the action refuse the socket.
+async fn upgrade_websocket(state: &AppState, req: Incoming<'_>, routed: Routed, on_upgrade: hyper::upgrade::OnUpgrade) -> axum::response::Response {
+    let Incoming { request_id, method, path, headers_map, .. } = req;
+    let client_key = headers_map.get("sec-websocket-key").cloned().unwrap_or_default();
+    let (outbound_tx, outbound_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (mut task, response_rx) = state.runtime.task(routed.action_name, method.clone(), path.clone());
+    task.headers = headers_map.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    task.params = routed.params.into_iter().collect();
+    task.query = req.query_map.into_iter().collect();
+    task.correlation_id = request_id.to_string();
+    task.trace = Some(req.trace);
+    task.remote_addr = req.remote_addr;
+    // The socket reads the session; the 101 can't carry a changed cookie back
+    if let Some(sessions) = &state.sessions {
+        task.session = Some(sessions.load(headers_map.get("cookie").map(String::as_str)).await.data());
+    }
+
+    let session = match state.runtime.open_socket(task, outbound_tx.clone()) {
+        Ok(s) => s,
+        Err(rejected) => {
+            let status = StatusCode::from_u16(rejected.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            let mut response = (status, rejected.error_message().unwrap_or("Upgrade rejected").to_string()).into_response();
+            for (name, value) in &rejected.headers {
+                if let (Ok(name), Ok(value)) = (
+                    axum::http::HeaderName::from_bytes(name.as_bytes()),
+                    axum::http::HeaderValue::from_str(value),
+                ) {
+                    response.headers_mut().append(name, value);
+                }
+            }
+            return response;
         }
     };
 
+    tokio::spawn(async move {
+        let Ok(upgraded) = on_upgrade.await else {
+            session.close();
+            return;
+        };
 
-    // ---------------------------
-    // EXECUTE IN V8 (WORKER POOL)
-    // ---------------------------
-    
-    // OPTIMIZATION: Zero-Copy & Stack Allocation
-    // 1. Headers/Params are collected into `SmallVec` (stack allocated if small).
-    // 2. Body is passed as `Bytes` (ref-counted pointer), not copied.
-    // 3. No JSON serialization happens here anymore. This saves ~60% CPU vs previous version.
-    
-    let headers_vec: SmallVec<[(String, String); 8]> = headers_map.into_iter().collect();
-    let params_vec: SmallVec<[(String, String); 4]> = params.into_iter().collect();
-    let query_vec: SmallVec<[(String, String); 4]> = query_map.into_iter().collect();
-    
-    // Pass raw bytes to worker if not empty
-    let body_arg = if !body_bytes.is_empty() {
-        Some(body_bytes)
-    } else {
+        // A failing action closes the socket right away
+        let close_tx = outbound_tx.clone();
+        tokio::spawn(async move {
+            if let Ok(res) = response_rx.await
+                && res.as_ref().map_or(true, |r| r.error_message().is_some())
+            {
+                let _ = close_tx.send(websocket::WsMessage::Close);
+            }
+        });
+
+        websocket::run_connection(upgraded, session, outbound_tx, outbound_rx).await;
+    });
+
+    tracing::info!(kind = "websocket", request_id, "{} {} → {}", method, path, routed.route_label);
+
+    axum::http::Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", websocket::accept_key(&client_key))
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// A task for answers that skip the worker pool but not the interceptors.
+fn gate(state: &AppState, req: &Incoming<'_>, routed: &Routed) -> RequestTask {
+    let (mut gate, _) = state.runtime.task(routed.action_name.clone(), req.method.clone(), req.path.clone());
+    gate.headers = req.headers_map.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    gate.params = routed.params.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    gate.query = req.query_map.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    gate.correlation_id = req.request_id.to_string();
+    gate.remote_addr = req.remote_addr;
+    gate
+}
+
+/// Negotiates the answer's encoding and format, and the response cache entry
+/// it may come from.
+fn cache_plan(state: &AppState, req: &mut Incoming<'_>, routed: &Routed) -> CachePlan {
+    let method = &req.method;
+    let encoding = req.headers_map.get("accept-encoding").and_then(|v| compression::negotiate(v));
+    let reply_format = req.headers_map.get("accept").map_or(formats::Format::Json, |accept| formats::Format::negotiate(accept));
+    let revalidating = req.parts.extensions.remove::<cache::Revalidate>().is_some();
+    let conditional = (method == "GET" || method == "HEAD").then(|| {
+        (req.headers_map.get("if-none-match").cloned(), req.headers_map.get("if-modified-since").cloned())
+    });
+    let rule = state.cache.filter(|_| method == "GET").and_then(|cache| Some((cache, cache.rule_for(&routed.action_name)?)));
+    let key = rule.map(|(_, rule)| {
+        let variant = format!("{} {}", encoding.map_or("identity", |e| e.as_str()), reply_format.content_type());
+        cache::ResponseCache::key(rule, &req.parts.uri, &req.parts.headers, &variant)
+    });
+    CachePlan { encoding, reply_format, revalidating, conditional, rule, key }
+}
+
+/// The answer to a response cache hit, if there is one.
+fn answer_cached(state: &AppState, req: &Incoming<'_>, routed: &Routed, plan: &CachePlan) -> Option<axum::response::Response> {
+    let CachePlan { conditional, rule, key, revalidating, .. } = plan;
+    if let (Some((cache, _)), Some(key), false) = (rule, key, revalidating) {
+        let (hit, revalidate) = match cache.lookup(key) {
+            cache::Lookup::Fresh(response) => (Some(response), false),
+            cache::Lookup::Stale { response, revalidate } => (Some(response), revalidate),
+            cache::Lookup::Miss => (None, false),
+        };
+        if let Some(response) = hit {
+            // A hit skips the worker pool but not the interceptors
+            let response = answer_stored(state, gate(state, req, routed), response, &routed.route_label, req.headers_map.get("origin"), |mut response| {
+                if revalidate {
+                    revalidate_cached(state.clone(), &req.parts, req.remote_addr, key.clone());
+                }
+                if let Some((if_none_match, if_modified_since)) = conditional
+                    && etag::is_fresh(if_none_match.as_deref(), if_modified_since.as_deref(), response.headers())
+                {
+                    response = etag::not_modified(response);
+                }
+                response
+            });
+            tracing::info!(status = response.status().as_u16(), kind = "cache", duration_ms = elapsed_ms(req.start), request_id = req.request_id, "{} {} → {}", req.method, req.path, routed.route_label);
+            return Some(response);
+        }
+    }
+    None
+}
+
+/// Reads the body the way the action takes it and refuses one that is
+/// malformed or doesn't match the action's schema.
+async fn read_body(state: &AppState, req: &Incoming<'_>, routed: &Routed, body: Body) -> Result<RequestBody, axum::response::Response> {
+    let Incoming { request_id, method, path, start, headers_map, .. } = req;
+    // Multipart bodies are streamed to disk and streaming actions read theirs
+    // as they go; anything else is read whole, up to the limit
+    let body_rule = state.body.rule_for(&routed.action_name);
+    let declared_len = headers_map.get("content-length").and_then(|len| len.parse::<u64>().ok());
+    let boundary = if body_rule.stream {
         None
+    } else {
+        headers_map.get("content-type").and_then(|ct| multipart::boundary(ct))
+    };
+    let mut stream = None;
+    let (bytes, form) = match boundary {
+        Some(boundary) => match multipart::parse(body, &boundary, &state.uploads).await {
+            Ok(form) => (bytes::Bytes::new(), Some(Arc::new(form))),
+            Err(e) => {
+                let status = StatusCode::from_u16(e.status()).unwrap_or(StatusCode::BAD_REQUEST);
+                return Err((status, e.to_string()).into_response());
+            }
+        },
+        None if body_rule.stream => {
+            if let Some(limit) = body_rule.max_bytes
+                && declared_len.is_some_and(|len| len > limit)
+            {
+                return Err((StatusCode::PAYLOAD_TOO_LARGE, body::BodyError::TooLarge(limit).to_string()).into_response());
+            }
+            stream = Some(Arc::new(body::StreamedBody::new(body, body_rule.max_bytes)));
+            (bytes::Bytes::new(), None)
+        }
+        // A URL-encoded body keeps its bytes too, for signature checks
+        None => match body::read_all(body, body_rule.max_bytes, declared_len).await {
+            Ok(b) if headers_map.get("content-type").is_some_and(|ct| multipart::is_urlencoded(ct)) => {
+                let form = multipart::Form::urlencoded(&b);
+                (b, Some(Arc::new(form)))
+            }
+            Ok(b) => (b, None),
+            Err(e) => {
+                let status = StatusCode::from_u16(e.status()).unwrap_or(StatusCode::BAD_REQUEST);
+                return Err((status, e.to_string()).into_response());
+            }
+        },
     };
 
-    // Dispatch to the optimized RuntimeManager
-    // This sends a pointer-sized message through the ring buffer, triggering 
-    // the V8 thread to wake up and process the request immediately.
-
-    // Dispatch to the worker pool for V8 execution
-    let (mut result_json, timings) = state
-        .runtime
-        .execute(
-            action_name,
-            method.clone(),
-            path.clone(),
-            body_arg,
-            headers_vec,
-            params_vec,
-            query_vec
-        )
-        .await
-        .unwrap_or_else(|e| {
-            // Log catastrophic runtime errors
-            (serde_json::json!({"error": e}), vec![])
-        });
+    // A malformed binary body is refused here rather than reaching the action as null
+    if !bytes.is_empty()
+        && let Some(format) = headers_map.get("content-type").and_then(|ct| formats::Format::from_content_type(ct))
+        && let Err(e) = format.validate(&bytes)
+    {
+        tracing::info!(status = 400, duration_ms = elapsed_ms(*start), request_id, "{} {} → invalid {} body", method, path, format.content_type());
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid {} body: {}", format.content_type(), e)).into_response());
+    }
 
-    // Construct Server-Timing header
-    let server_timing = timings.iter().enumerate().map(|(i, (name, duration))| {
-        format!("{}_{};dur={:.2}", name, i, duration)
-    }).collect::<Vec<_>>().join(", ");
+    // Requests that don't match the action's schema never reach a worker
+    if let Some(validator) = &state.validator
+        && validator.covers(&routed.action_name)
+    {
+        let content_type = headers_map.get("content-type").map(String::as_str);
+        let body = if form.is_some() || stream.is_some() {
+            validation::Body::Unchecked
+        } else if bytes.is_empty() {
+            validation::Body::Missing
+        } else {
+            match content_type.map_or(Some(formats::Format::Json), formats::Format::from_content_type) {
+                Some(format) => validation::Body::Decoded(format.to_json(&bytes)),
+                None => validation::Body::Unchecked,
+            }
+        };
+        let violations = validator.check(&routed.action_name, &routed.params, &req.query_map, headers_map, body);
+        if !violations.is_empty() {
+            tracing::info!(status = 422, duration_ms = elapsed_ms(*start), request_id, "{} {} → {} failed validation", method, path, routed.route_label);
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(validation::response_body(&violations))).into_response());
+        }
+    }
+
+    Ok(RequestBody { bytes, form, stream })
+}
 
-    // Inject timings into JSON if it's an object
-    if let Some(obj) = result_json.as_object_mut() {
-        obj.insert("_titanTimings".to_string(), serde_json::json!(timings));
+/// A retry of a request that already ran gets its response again. The
+/// first one gets a ticket to hand its response back with.
+async fn claim_idempotency<'s>(
+    state: &'s AppState,
+    req: &Incoming<'_>,
+    routed: &Routed,
+    body: &RequestBody,
+) -> Result<IdempotencyTicket<'s>, axum::response::Response> {
+    let Some(idempotency) = &state.idempotency else {
+        return Ok(None);
+    };
+    match idempotency.claim(&routed.action_name, &req.method, &req.parts.uri, &req.headers_map, &body.bytes).await {
+        idempotency::Claim::Skip => Ok(None),
+        idempotency::Claim::New(ticket) => Ok(Some((idempotency, ticket))),
+        idempotency::Claim::Answer(response) => {
+            let response = answer_stored(state, gate(state, req, routed), response, &routed.route_label, req.headers_map.get("origin"), |r| r);
+            tracing::info!(status = response.status().as_u16(), kind = "idempotent", duration_ms = elapsed_ms(req.start), request_id = req.request_id, "{} {} → {}", req.method, req.path, routed.route_label);
+            Err(response)
+        }
     }
+}
 
-    let prefix = if !timings.is_empty() { 
-        format!("{} {}", blue("[Titan"), blue("Drift]"))
+/// Runs the action on the worker pool, and app/actions/_error in its place
+/// if it fails. Session changes and CORS headers are applied to the result.
+async fn dispatch(state: &AppState, req: &mut Incoming<'_>, routed: &mut Routed, body: RequestBody) -> Dispatched {
+    let headers_map = std::mem::take(&mut req.headers_map);
+    let origin = headers_map.get("origin").cloned();
+    let wants_html = headers_map.get("accept").is_some_and(|accept| error::prefers_html(accept));
+    let session = match &state.sessions {
+        Some(sessions) => Some(sessions.load(headers_map.get("cookie").map(String::as_str)).await),
+        None => None,
+    };
+    let headers_vec: SmallVec<[(String, String); 8]> = headers_map.into_iter().collect();
+    let params_vec: SmallVec<[(String, String); 4]> = std::mem::take(&mut routed.params).into_iter().collect();
+    // A structured req.query is built from every pair, repeats included
+    let query_vec: SmallVec<[(String, String); 4]> = if query::is_structured() {
+        std::mem::take(&mut req.query_pairs).into_iter().collect()
     } else {
-        blue("[Titan]").to_string()
+        std::mem::take(&mut req.query_map).into_iter().collect()
+    };
+
+    // What app/actions/_error sees of the request, if the action fails
+    let unrouted = routed.unrouted.take();
+    let error_context = (state.fallbacks.error && unrouted.is_none()).then(|| (headers_vec.clone(), query_vec.clone()));
+    let unrouted_status = unrouted.as_ref().and_then(|error| error["status"].as_u64()).map(|status| status as u16);
+    let allow = unrouted.as_ref().and_then(|error| error["allow"].as_array()).map(|allowed| {
+        allowed.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(", ")
+    });
+
+    // Dispatch to the worker pool for V8 execution. The body is passed as
+    // `Bytes`, a ref-counted pointer, so it isn't copied on the way
+    let timeout = timeout_for(state, &routed.action_name);
+    let mut error_stack = None;
+    let (mut task, rx) = state.runtime.task(routed.action_name.clone(), req.method.clone(), req.path.clone());
+    task.body = (!body.bytes.is_empty()).then_some(body.bytes);
+    task.headers = headers_vec;
+    task.params = params_vec;
+    task.query = query_vec;
+    task.correlation_id = req.request_id.to_string();
+    task.trace = Some(req.trace.clone());
+    task.form = body.form;
+    task.remote_addr = req.remote_addr;
+    task.session = session.as_ref().map(session::Loaded::data);
+    task.body_stream = body.stream;
+    task.error = unrouted.map(Arc::new);
+    let mut result = match state.runtime.try_execute(task, rx, timeout).await {
+        Ok(result) => result,
+        Err(e) => {
+            error_stack = e.stack().map(str::to_string);
+            e.to_result(state.expose_stacks)
+        }
     };
 
+    // app/actions/_error answers in place of an action that failed. The
+    // failure is still logged and traced as the action's.
+    let mut failure: Option<(String, u16)> = None;
+    if let Some((headers, query)) = error_context
+        && let Some(message) = result.error_message()
+    {
+        let status = if result.status >= 400 { result.status } else { 500 };
+        let mut error = serde_json::json!({ "status": status, "message": message, "action": routed.route_label });
+        if let Some(stack) = error_stack.as_deref().filter(|_| state.expose_stacks) {
+            error["stack"] = Value::from(stack);
+        }
+        let (mut task, rx) = state.runtime.task(fallbacks::ERROR.to_string(), req.method.clone(), req.path.clone());
+        task.headers = headers;
+        task.query = query;
+        task.correlation_id = req.request_id.to_string();
+        task.trace = Some(req.trace.clone());
+        task.remote_addr = req.remote_addr;
+        task.error = Some(Arc::new(error));
+        match state.runtime.dispatch(task, rx, state.request_timeout).await {
+            Ok(answer) if answer.error_message().is_none() => {
+                failure = Some((message.to_string(), status));
+                result = answer;
+            }
+            Ok(answer) => tracing::error!(error = answer.error_message(), request_id = req.request_id, "{} failed too", fallbacks::ERROR),
+            Err(e) => tracing::error!(error = %e, request_id = req.request_id, "{} failed too", fallbacks::ERROR),
+        }
+    }
+    // The fallbacks keep the status of what they answer for, unless they set one
+    let fallback_status = unrouted_status.or(failure.as_ref().map(|(_, status)| *status));
+    if let Some(status) = fallback_status.filter(|_| result.status == 200) {
+        result.status = status;
+    }
+    if let Some(allow) = allow
+        && !result.headers.iter().any(|(k, _)| k.eq_ignore_ascii_case("allow"))
+    {
+        result.headers.push(("allow".to_string(), allow));
+    }
+
+    // Session changes come back as a header that must not reach the client
+    let session_change = result
+        .headers
+        .iter()
+        .position(|(k, _)| k.eq_ignore_ascii_case(session::CHANGE_HEADER))
+        .map(|i| result.headers.remove(i).1);
+    if let (Some(sessions), Some(loaded)) = (&state.sessions, session)
+        && let Some(cookie) = sessions.commit(loaded, session_change.as_deref()).await
+    {
+        result.headers.push(("set-cookie".to_string(), cookie));
+    }
+    if let Some(cors) = &state.cors {
+        cors.apply(&routed.route_label, origin.as_deref(), &mut result.headers);
+    }
+
+    Dispatched { result, error_stack, failure, wants_html }
+}
+
+/// Turns the worker's result into the HTTP response: status, encoding,
+/// Server-Timing and ETag, then hands it to the idempotency store and the
+/// response cache.
+async fn finish_response(
+    state: &AppState,
+    req: Incoming<'_>,
+    routed: Routed,
+    plan: CachePlan,
+    idempotency_ticket: IdempotencyTicket<'_>,
+    dispatched: Dispatched,
+) -> axum::response::Response {
+    let Dispatched { result, error_stack, failure, wants_html } = dispatched;
+    let CachePlan { encoding, reply_format, revalidating, conditional, rule: cache_rule, key: cache_key } = plan;
+    let Incoming { request_id, method, path, start, .. } = &req;
+    let route_label = &routed.route_label;
+
+    // Construct Server-Timing header
+    let server_timing = result.timings.iter().enumerate().map(|(i, (name, duration))| {
+        format!("{}_{};dur={:.2}", name, i, duration)
+    }).collect::<Vec<_>>().join(", ");
+
     // ---------------------------
     // ERROR HANDLING
     // ---------------------------
-    if let Some(err) = result_json.get("error") {
-        println!(
-            "{} {} {} {}",
-            prefix,
-            red(&format!("{} {}", method, path)), 
-            red("→ error"),
-            gray(&format!("in {:.2?}", start.elapsed()))
+    let mut status = StatusCode::from_u16(result.status).unwrap_or(StatusCode::OK);
+    if let Some(err) = result.error_message().or(failure.as_ref().map(|(message, _)| message.as_str())) {
+        let breakdown = timing::current().filter(|_| state.server_timing).and_then(|timeline| timeline.breakdown());
+        tracing::error!(
+            error = err,
+            stack = error_stack.as_deref(),
+            duration_ms = elapsed_ms(*start),
+            queue_ms = breakdown.as_ref().map(|b| b.queue_ms),
+            exec_ms = breakdown.as_ref().map(|b| b.exec_ms),
+            request_id,
+            client_ip = req.client_ip.map(tracing::field::display),
+            "{} {} → {} failed",
+            method,
+            path,
+            route_label
         );
-        println!(
-            "{} {} {}",
-            prefix,
-            red("Action Error:"),
-            red(err.as_str().unwrap_or("Unknown"))
-        );
-        let mut response = (StatusCode::INTERNAL_SERVER_ERROR, Json(result_json.clone())).into_response();
-        if !server_timing.is_empty() {
-            response.headers_mut().insert("Server-Timing", server_timing.parse().unwrap());
+        if failure.is_none() && !status.is_client_error() && !status.is_server_error() {
+            status = StatusCode::INTERNAL_SERVER_ERROR;
         }
-        return response;
     }
+    let error_message = result.error_message().map(str::to_string).or(failure.map(|(message, _)| message));
+    let is_error = error_message.is_some();
+    // _error's answer goes out as it is
+    let handled = is_error && result.error_message().is_none();
+    let WorkerResult { headers, body, timings, .. } = result;
 
     // ---------------------------
     // RESPONSE CONSTRUCTION
     // ---------------------------
-    let mut response = if let Some(is_resp) = result_json.get("_isResponse") {
-        if is_resp.as_bool().unwrap_or(false) {
-            let status_u16 = result_json.get("status").and_then(|v| v.as_u64()).unwrap_or(200) as u16;
-            let status = StatusCode::from_u16(status_u16).unwrap_or(StatusCode::OK);
-            let mut builder = axum::http::Response::builder().status(status);
-
-            if let Some(hmap) = result_json.get("headers").and_then(|v| v.as_object()) {
-                for (k, v) in hmap {
-                    if let Some(vs) = v.as_str() {
-                        builder = builder.header(k, vs);
-                    }
-                }
-            }
+    timing::mark(timing::Mark::SerializeStart);
+    let mut builder = axum::http::Response::builder().status(status);
+    for (k, v) in &headers {
+        builder = builder.header(k, v);
+    }
+    // Streams go out uncompressed so every chunk reaches the client right away
+    let compress = encoding.zip(state.compression.rule_for(route_label));
+    // What went out, for the response cache; streams are never cached
+    let (builder, body, cached_body) = match body {
+        // Browsers get a readable page instead of the JSON error
+        ResponseBody::Json(_) if is_error && wants_html && !handled => {
+            let stack = error_stack.as_deref().filter(|_| state.expose_stacks);
+            let page = error::html_page(status.as_u16(), error_message.as_deref().unwrap_or("Error"), stack);
+            (builder.header(axum::http::header::CONTENT_TYPE, "text/html; charset=utf-8"), Body::from(page), None)
+        }
+        body => encode_body(state, builder, body, &timings, reply_format, compress).await,
+    };
+    let mut response = builder
+        .body(body)
+        .unwrap_or_else(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Invalid response headers").into_response());
+    timing::mark(timing::Mark::SerializeEnd);
 
-            let mut is_redirect = false;
-            if let Some(location) = result_json.get("redirect") {
-                if let Some(url) = location.as_str() {
-                    let mut final_status_u16 = status.as_u16();
-                    if final_status_u16 < 300 || final_status_u16 > 399 { final_status_u16 = 302; }
-                    builder = builder.status(StatusCode::from_u16(final_status_u16).unwrap_or(StatusCode::FOUND)).header("Location", url);
-                    is_redirect = true;
-                }
+    let breakdown = timing::current().filter(|_| state.server_timing).and_then(|timeline| timeline.breakdown());
+    let server_timing = match &breakdown {
+        Some(b) => {
+            let mut parts = vec![format!("queue;dur={:.2}", b.queue_ms), format!("exec;dur={:.2}", b.exec_ms)];
+            parts.extend(b.serialize_ms.map(|ms| format!("serialize;dur={:.2}", ms)));
+            parts.extend((!server_timing.is_empty()).then_some(server_timing));
+            parts.join(", ")
+        }
+        None => server_timing,
+    };
+    if !server_timing.is_empty() {
+        response.headers_mut().insert("Server-Timing", server_timing.parse().unwrap());
+    }
+
+    // Whole bodies get an ETag, so clients can revalidate with If-None-Match
+    if let Some(body) = &cached_body
+        && state.etags
+        && !is_error
+        && conditional.is_some()
+        && status.is_success()
+        && !response.headers().contains_key(axum::http::header::ETAG)
+        && let Ok(value) = axum::http::HeaderValue::from_str(&etag::for_body(body))
+    {
+        response.headers_mut().insert(axum::http::header::ETAG, value);
+    }
+
+    if let Some((idempotency, ticket)) = idempotency_ticket {
+        idempotency.finish(ticket, response.status(), response.headers(), cached_body.as_ref()).await;
+    }
+
+    if let (Some((cache, rule)), Some(key), Some(body)) = (cache_rule, cache_key, cached_body)
+        && !is_error
+    {
+        for name in &rule.vary {
+            if let Ok(value) = axum::http::HeaderValue::from_str(name) {
+                response.headers_mut().append(axum::http::header::VARY, value);
             }
+        }
+        cache.store(key, route_label, rule, response.status(), response.headers(), body);
+        if !revalidating {
+            response.headers_mut().insert(cache::STATUS_HEADER, axum::http::HeaderValue::from_static("miss"));
+        }
+    }
+    if let Some((if_none_match, if_modified_since)) = &conditional
+        && !is_error
+        && status.is_success()
+        && etag::is_fresh(if_none_match.as_deref(), if_modified_since.as_deref(), response.headers())
+    {
+        response = etag::not_modified(response);
+    }
+
+    let trace = &req.trace;
+    if trace.sampled {
+        telemetry::record(SpanRecord {
+            trace_id: trace.trace_id.clone(),
+            span_id: trace.span_id.clone(),
+            parent_id: trace.parent_id.clone(),
+            name: format!("{} {}", method, route_label),
+            server: true,
+            start: req.started_at,
+            end: SystemTime::now(),
+            error: error_message,
+            attributes: vec![
+                ("http.request.method".to_string(), serde_json::json!(method)),
+                ("url.path".to_string(), serde_json::json!(path)),
+                ("http.route".to_string(), serde_json::json!(route_label)),
+                ("titan.request_id".to_string(), serde_json::json!(request_id)),
+                ("http.response.status_code".to_string(), serde_json::json!(status.as_u16())),
+            ],
+        });
+    }
+    if !is_error {
+        log_success(&req, &routed, status, &timings, breakdown);
+    }
+    response
+}
 
-            let body_text = if is_redirect { "".to_string() } else {
-                match result_json.get("body") {
-                    Some(Value::String(s)) => s.clone(),
-                    Some(v) => v.to_string(),
-                    None => "".to_string(),
+/// Encodes a worker's body in the negotiated format, compressed where the
+/// policy allows, along with the bytes that went out.
+async fn encode_body(
+    state: &AppState,
+    mut builder: axum::http::response::Builder,
+    body: ResponseBody,
+    timings: &[(String, f64)],
+    reply_format: formats::Format,
+    compress: Option<(compression::Encoding, &compression::Rule)>,
+) -> (axum::http::response::Builder, Body, Option<bytes::Bytes>) {
+    let content_type = builder
+        .headers_ref()
+        .and_then(|h| h.get(axum::http::header::CONTENT_TYPE))
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+
+    let mut cached_body = None;
+    let body = match body {
+        ResponseBody::Json(mut value) => {
+            // Inject timings into JSON if it's an object
+            if let Some(obj) = value.as_object_mut() {
+                obj.insert("_titanTimings".to_string(), serde_json::json!(timings));
+            }
+            // MessagePack or CBOR when Accept asks for them, unless the action set a type
+            let format = if content_type.is_none() { reply_format } else { formats::Format::Json };
+            if content_type.is_none() {
+                builder = builder.header(axum::http::header::CONTENT_TYPE, format.content_type()).header(axum::http::header::VARY, "accept");
+            }
+            let payload = bytes::Bytes::from(format.encode(&value));
+            let payload = match (compress, builder.headers_mut()) {
+                (Some((encoding, rule)), Some(headers)) => compression::apply(headers, payload, rule, encoding).await,
+                _ => payload,
+            };
+            cached_body = Some(payload.clone());
+            Body::from(payload)
+        }
+        ResponseBody::JsonText(text) => {
+            let format = if content_type.is_none() { reply_format } else { formats::Format::Json };
+            if content_type.is_none() {
+                builder = builder.header(axum::http::header::CONTENT_TYPE, format.content_type()).header(axum::http::header::VARY, "accept");
+            }
+            let payload = match format {
+                formats::Format::Json => with_timings(text, timings),
+                // Binary formats need the value back; the text is valid JSON
+                _ => {
+                    let mut value: Value = serde_json::from_slice(&text).unwrap_or(Value::Null);
+                    if let Some(obj) = value.as_object_mut() {
+                        obj.insert("_titanTimings".to_string(), serde_json::json!(timings));
+                    }
+                    bytes::Bytes::from(format.encode(&value))
                 }
             };
-            builder.body(Body::from(body_text)).unwrap()
-        } else {
-            Json(result_json.clone()).into_response()
+            let payload = match (compress, builder.headers_mut()) {
+                (Some((encoding, rule)), Some(headers)) => compression::apply(headers, payload, rule, encoding).await,
+                _ => payload,
+            };
+            cached_body = Some(payload.clone());
+            Body::from(payload)
+        }
+        ResponseBody::Bytes(bytes) => {
+            let bytes = match (compress, builder.headers_mut()) {
+                (Some((encoding, rule)), Some(headers)) => compression::apply(headers, bytes, rule, encoding).await,
+                _ => bytes,
+            };
+            cached_body = Some(bytes.clone());
+            Body::from(bytes)
+        }
+        ResponseBody::Stream(rx) => {
+            let is_sse = content_type.is_some_and(|v| v.starts_with("text/event-stream"));
+            if is_sse { sse_body(rx, state.sse_keep_alive) } else { stream_body(rx) }
+        }
+        ResponseBody::Empty => {
+            cached_body = Some(bytes::Bytes::new());
+            Body::empty()
         }
-    } else {
-        Json(result_json.clone()).into_response()
     };
+    (builder, body, cached_body)
+}
 
-    if !server_timing.is_empty() {
-        response.headers_mut().insert("Server-Timing", server_timing.parse().unwrap());
-    }
-
-    // ---------------------------
-    // FINAL LOG (SUCCESS)
-    // ---------------------------
-    let total_elapsed = start.elapsed();
-    let total_elapsed_ms = total_elapsed.as_secs_f64() * 1000.0;
+/// The request log line of an action that succeeded; failures are logged
+/// as errors where they are noticed.
+fn log_success(req: &Incoming<'_>, routed: &Routed, status: StatusCode, timings: &[(String, f64)], breakdown: Option<timing::Breakdown>) {
+    let Incoming { request_id, method, path, start, .. } = req;
+    let Routed { route_label, route_kind, .. } = routed;
+    let client_ip = req.client_ip.map(tracing::field::display);
+    let total_elapsed_ms = elapsed_ms(*start);
     let total_drift_ms: f64 = timings.iter().filter(|(n, _)| n == "drift" || n == "drift_error").map(|(_, d)| d).sum();
     let compute_ms = (total_elapsed_ms - total_drift_ms).max(0.0);
 
-    let timing_info = if !timings.is_empty() {
-        gray(&format!("(active: {:.2}ms, drift: {:.2}ms) in {:.2?}", compute_ms, total_drift_ms, total_elapsed))
+    let queue_ms = breakdown.as_ref().map(|b| b.queue_ms);
+    let exec_ms = breakdown.as_ref().map(|b| b.exec_ms);
+    let serialize_ms = breakdown.as_ref().and_then(|b| b.serialize_ms);
+    if timings.is_empty() {
+        tracing::info!(
+            status = status.as_u16(),
+            kind = route_kind,
+            duration_ms = total_elapsed_ms,
+            queue_ms,
+            exec_ms,
+            serialize_ms,
+            request_id,
+            client_ip,
+            "{} {} → {}",
+            method,
+            path,
+            route_label
+        );
     } else {
-        gray(&format!("in {:.2?}", total_elapsed))
+        tracing::info!(
+            status = status.as_u16(),
+            kind = route_kind,
+            duration_ms = total_elapsed_ms,
+            active_ms = compute_ms,
+            queue_ms,
+            exec_ms,
+            serialize_ms,
+            drift_ms = total_drift_ms,
+            request_id,
+            client_ip,
+            "{} {} → {}",
+            method,
+            path,
+            route_label
+        );
+    }
+}
+
+/// Answers a request from a store instead of a worker (a cache hit or an
+/// idempotent replay). The interceptors still run: a rejection replaces
+/// `response`, and `admitted` finishes it otherwise. Headers the
+/// interceptors and CORS add are appended either way.
+fn answer_stored(
+    state: &AppState,
+    mut gate: RequestTask,
+    response: axum::response::Response,
+    route_label: &str,
+    origin: Option<&String>,
+    admitted: impl FnOnce(axum::response::Response) -> axum::response::Response,
+) -> axum::response::Response {
+    let (mut response, mut extra) = match state.runtime.admit(&mut gate) {
+        Some(rejected) => {
+            let status = StatusCode::from_u16(rejected.status).unwrap_or(StatusCode::FORBIDDEN);
+            let body = match rejected.body {
+                ResponseBody::Json(value) => Body::from(value.to_string()),
+                ResponseBody::Bytes(bytes) | ResponseBody::JsonText(bytes) => Body::from(bytes),
+                _ => Body::empty(),
+            };
+            let response = axum::http::Response::builder()
+                .status(status)
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response());
+            (response, rejected.headers)
+        }
+        None => (admitted(response), Default::default()),
     };
+    extra.extend(gate.response_headers);
+    if let Some(cors) = &state.cors {
+        cors.apply(route_label, origin.map(String::as_str), &mut extra);
+    }
+    for (name, value) in &extra {
+        if let (Ok(name), Ok(value)) = (
+            axum::http::HeaderName::from_bytes(name.as_bytes()),
+            axum::http::HeaderValue::from_str(value),
+        ) {
+            response.headers_mut().append(name, value);
+        }
+    }
+    response
+}
+
+/// Refreshes a stale cache entry in the background by replaying the GET
+/// that found it, under a request id of its own.
+fn revalidate_cached(state: AppState, parts: &axum::http::request::Parts, remote_addr: Option<SocketAddr>, key: String) {
+    let mut req = Request::new(Body::empty());
+    *req.method_mut() = parts.method.clone();
+    *req.uri_mut() = parts.uri.clone();
+    *req.headers_mut() = parts.headers.clone();
+    req.headers_mut().remove("x-request-id");
+    req.extensions_mut().insert(cache::Revalidate);
+    if let Some(addr) = remote_addr {
+        req.extensions_mut().insert(ConnectInfo(ClientAddr(addr)));
+    }
+    // Boxed, since the handler it runs is the one spawning it
+    let refresh: std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>> = Box::pin(async move {
+        let cache = state.cache;
+        with_request_id(State(state), req).await;
+        if let Some(cache) = cache {
+            cache.revalidated(&key);
+        }
+    });
+    tokio::spawn(refresh);
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
 
-    match route_kind {
-        "dynamic" => println!("{} {} {} {} {} {}", prefix, green(&format!("{} {}", method, path)), white("→"), green(&route_label), white("(dynamic)"), timing_info),
-        "exact" => println!("{} {} {} {} {}", prefix, white(&format!("{} {}", method, path)), white("→"), yellow(&route_label), timing_info),
-        _ => {}
+/// Splices `_titanTimings` in as the first key of an already serialized
+/// object; arrays and other values go out untouched.
+fn with_timings(text: bytes::Bytes, timings: &[(String, f64)]) -> bytes::Bytes {
+    if text.first() != Some(&b'{') {
+        return text;
     }
+    let timings = serde_json::to_vec(timings).unwrap_or_else(|_| b"[]".to_vec());
+    let rest = &text[1..];
+    let mut out = Vec::with_capacity(text.len() + timings.len() + 18);
+    out.extend_from_slice(b"{\"_titanTimings\":");
+    out.extend_from_slice(&timings);
+    if rest.first() != Some(&b'}') {
+        out.push(b',');
+    }
+    out.extend_from_slice(rest);
+    bytes::Bytes::from(out)
+}
 
-    response
+/// Adapts the worker's chunk channel into a streaming HTTP body.
+fn stream_body(rx: extensions::StreamReceiver) -> Body {
+    Body::from_stream(futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (Ok::<_, std::convert::Infallible>(chunk), rx))
+    }))
 }
 
+/// Streaming body for `res.sse()`: emits a comment line whenever the action
+/// has been quiet for `keep_alive` so proxies don't drop the idle connection.
+fn sse_body(rx: extensions::StreamReceiver, keep_alive: Duration) -> Body {
+    Body::from_stream(futures_util::stream::unfold(rx, move |mut rx| async move {
+        let chunk = match tokio::time::timeout(keep_alive, rx.recv()).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return None,
+            Err(_) => bytes::Bytes::from_static(b": keep-alive\n\n"),
+        };
+        Some((Ok::<_, std::convert::Infallible>(chunk), rx))
+    }))
+}
 
 // Entrypoint ---------------------------------------------------------------
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenvy::dotenv().ok();
-    
-    // Load routes.json
-    let raw = fs::read_to_string("./routes.json").unwrap_or_else(|_| "{}".to_string());
-    let json: Value = serde_json::from_str(&raw).unwrap_or_default();
 
-    let port = std::env::var("PORT")
-        .ok()
-        .and_then(|p| p.parse::<u64>().ok())
+    let command = match cli::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("titan-server: {}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    match command {
+        cli::Command::Start(options) => serve(options).await,
+        cli::Command::Dev(options) => {
+            logging::init(&load_metadata(&options)["__config"]["log"]);
+            cli::dev(options, &resolve_project_root()).await
+        }
+        cli::Command::Build { options, out, standalone } => build(options, out, standalone),
+        cli::Command::Help => {
+            print!("{}", cli::USAGE);
+            Ok(())
+        }
+        cli::Command::Version => {
+            println!("titan-server {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+    }
+}
+
+// The routes and `__config` written by the build
+fn load_metadata(options: &cli::ServeOptions) -> Value {
+    let raw = fs::read_to_string(options.config_path()).unwrap_or_else(|_| "{}".to_string());
+    serde_json::from_str(&raw).unwrap_or_default()
+}
+
+/// `build`: evaluates every action into a startup snapshot and saves it, so
+/// `start` boots the workers without parsing anything. `--standalone`
+/// saves it beside an archive of the app, for `--features standalone`.
+fn build(options: cli::ServeOptions, out: Option<PathBuf>, standalone: bool) -> Result<()> {
+    let json = load_metadata(&options);
+    logging::init(&json["__config"]["log"]);
+    let project_root = resolve_project_root();
+    config::AppConfig::load(&project_root).map_err(anyhow::Error::msg)?.install();
+    extensions::load_project_extensions(project_root.clone());
+
+    let actions = scan_actions(&project_root);
+    if actions.is_empty() {
+        anyhow::bail!("no actions found under {}", project_root.display());
+    }
+    // Templates that don't compile fail the build instead of the first start
+    let views_dir = json["__config"]["views_dir"].as_str().unwrap_or("app/views");
+    views::configure(&project_root.join(views_dir), false).map_err(anyhow::Error::msg)?;
+    if standalone {
+        let dir = out.unwrap_or_else(|| project_root.join(".titan").join("standalone"));
+        fs::create_dir_all(&dir)?;
+        let public_dir = json["__config"]["public_dir"].as_str().unwrap_or("public");
+        let dirs = [public_dir, views_dir];
+        let files = standalone::pack(&project_root, &options.config_path(), &dirs, &dir.join("bundle.bin")).map_err(anyhow::Error::msg)?;
+        let size = extensions::write_snapshot(&project_root, &dir.join("titan.snapshot")).map_err(anyhow::Error::msg)?;
+        tracing::info!("{} file(s) and a {} KB snapshot ready to embed from {}", files, size / 1024, dir.display());
+        return Ok(());
+    }
+    let out = out.unwrap_or_else(|| options.snapshot_path());
+    let size = extensions::write_snapshot(&project_root, &out).map_err(anyhow::Error::msg)?;
+    tracing::info!("{} action(s) snapshotted to {} ({} KB)", actions.len(), out.display(), size / 1024);
+    Ok(())
+}
+
+async fn serve(options: cli::ServeOptions) -> Result<()> {
+    let json = load_metadata(&options);
+    logging::init(&json["__config"]["log"]);
+    inspector::configure().map_err(anyhow::Error::msg)?;
+
+    let port = options
+        .port
+        .map(u64::from)
+        .or_else(|| std::env::var("PORT").ok().and_then(|p| p.parse::<u64>().ok()))
         .or_else(|| json["__config"]["port"].as_u64())
         .unwrap_or(3000);
-    let thread_count = json["__config"]["threads"].as_u64();
-    let routes_json = json["routes"].clone();
-    let map: HashMap<String, RouteVal> = serde_json::from_value(routes_json).unwrap_or_default();
-    let dynamic_routes: Vec<DynamicRoute> =
-        serde_json::from_value(json["__dynamic_routes"].clone()).unwrap_or_default();
 
     // Identify project root
     let project_root = resolve_project_root();
+    // App settings from titan.config.toml, validated before anything starts
+    config::AppConfig::load(&project_root).map_err(anyhow::Error::msg)?.install();
     
     // Load extensions and action definitions
     extensions::load_project_extensions(project_root.clone());
+    // A snapshot saved by `titan-server build`, used while it matches the actions
+    extensions::use_snapshot_file(options.snapshot_path());
+
+    // OTLP trace export (standard OTEL_* variables win over routes.json)
+    telemetry::init(
+        std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .ok()
+            .or_else(|| json["__config"]["otlp_endpoint"].as_str().map(str::to_string)),
+        std::env::var("OTEL_SERVICE_NAME")
+            .ok()
+            .or_else(|| json["__config"]["service_name"].as_str().map(str::to_string))
+            .unwrap_or_else(|| "titan".to_string()),
+    );
 
+    files::configure(&json["__config"]["fs"], &project_root);
+    profiler::configure(&json["__config"]["profile"], &project_root);
+    heap::configure(&json["__config"]["heap_snapshots"], &project_root);
+    slow::configure(&json["__config"]["slow_requests"]);
+    placement::configure(&json["__config"]["cpu_affinity"]);
+    breaker::configure(&json["__config"]["circuit_breaker"]);
+    proxy::configure(&json["__config"]["trusted_proxies"]).map_err(anyhow::Error::msg)?;
+    query::configure(&json["__config"]["query"]).map_err(anyhow::Error::msg)?;
+    views::configure(&project_root.join(json["__config"]["views_dir"].as_str().unwrap_or("app/views")), options.watch).map_err(anyhow::Error::msg)?;
+    let tls = tls::TlsConfig::from_config(&json["__config"]["tls"], &project_root)
+        .and_then(|config| config.map(tls::TlsConfig::start).transpose())
+        .map_err(anyhow::Error::msg)?;
+    let http3 = http3::Http3Config::from_config(&json["__config"]["http3"], port as u16);
+    // Runtime controls on a listener of their own, loopback unless told otherwise
+    let admin = admin::Admin::from_config(&json["__config"]["admin"], &project_root).map_err(anyhow::Error::msg)?;
+    if profiler::enabled() {
+        tracing::info!("CPU profiling on; profiles at /profile on the admin API");
+        if admin.is_none() {
+            tracing::warn!("profile is on, but profiles are only served by the admin API; set admin to read them");
+        }
+    }
+    if admin.is_none() && heap::enabled() {
+        tracing::warn!("heap_snapshots is on, but snapshots are only taken through the admin API; set admin to take them");
+    }
+    let access_log = access_log::AccessLogLayer::from_config(&json["__config"]["access_log"], &project_root).map_err(anyhow::Error::msg)?;
+    if http3.is_some() && tls.is_none() {
+        anyhow::bail!("http3 needs tls to be configured");
+    }
+
+    kv::start_sweeper();
+    // Keys for signed cookies; without one, `res.setCookie(..., { signed: true })` throws
+    if cookies::configure(&json["__config"]["cookies"]) > 0 {
+        tracing::info!("Cookie signing on");
+    }
+
+    let main = mount(&options, &json, project_root.clone(), options.threads, true, tls.is_some()).await?;
+    // Other project roots served by this process, picked by Host or path prefix
+    let mut sites = Vec::new();
+    let mut apps = vec![(project_root.clone(), main.runtime.clone())];
+    for site in vhost::Site::from_config(&json["__config"]["apps"], &project_root).map_err(anyhow::Error::msg)? {
+        let app_json = vhost::load_routes(&site.root).map_err(anyhow::Error::msg)?;
+        let mounted = mount(&options, &app_json, site.root.clone(), site.threads, false, tls.is_some()).await?;
+        tracing::info!("App {} serves {}", site.root.display(), site.describe());
+        apps.push((site.root.clone(), mounted.runtime.clone()));
+        sites.push((site, mounted));
+    }
+    let Mounted { router: main_router, runtime: runtime_manager, shutdown_timeout, threads, autoscale, stack_mb, grpc } = main;
+    let mut runtimes = Vec::new();
+    let mut app = if sites.is_empty() {
+        main_router
+    } else {
+        let mut hosts = vhost::Vhosts::new(main_router);
+        for (site, mounted) in sites {
+            hosts.add(site, mounted.router);
+            runtimes.push((mounted.runtime, mounted.shutdown_timeout));
+        }
+        hosts.into_router()
+    };
+    // Around every app, so each response is logged once whichever router answers it
+    if let Some(layer) = access_log {
+        tracing::info!("Access log on ({})", layer.describe());
+        app = app.layer(layer);
+    }
+    let quic = match (&tls, &http3) {
+        (Some(tls), Some(http3)) => {
+            app = http3::advertise(app, http3.port);
+            Some(http3::listen(tls, http3, app.clone()).map_err(anyhow::Error::msg)?)
+        }
+        _ => None,
+    };
+
+    // `--socket`, then `socket` in the config, listens on a Unix socket instead of the port
+    let socket = &json["__config"]["socket"];
+    let bind = match options.socket.clone().or_else(|| socket.as_str().or(socket["path"].as_str()).map(PathBuf::from)) {
+        Some(path) => {
+            if tls.is_some() {
+                anyhow::bail!("tls can't be used with a Unix socket; terminate TLS in the proxy in front");
+            }
+            let mode = socket["mode"].as_u64().map(|m| m as u32).or_else(|| socket["mode"].as_str().and_then(|m| u32::from_str_radix(m, 8).ok()));
+            restart::Bind::Unix { path: project_root.join(path), mode }
+        }
+        None => restart::Bind::Port(port as u16),
+    };
+    let listener = restart::http_listener(&bind).await?;
+    let grpc_server = match grpc {
+        Some((grpc, grpc_app)) => {
+            let listener = restart::grpc_listener(grpc.port).await?;
+            tracing::info!("gRPC on port {} with {} method(s)", grpc.port, grpc.routes());
+            let service = grpc_app.into_make_service_with_connect_info::<ClientAddr>();
+            Some(match &tls {
+                Some(tls) => {
+                    let listener = tls.listen(listener).map_err(anyhow::Error::msg)?;
+                    tokio::spawn(async move { axum::serve(listener, service).with_graceful_shutdown(shutdown_signal()).await })
+                }
+                None => tokio::spawn(async move { axum::serve(listener, service).with_graceful_shutdown(shutdown_signal()).await }),
+            })
+        }
+        None => None,
+    };
+    let admin_server = match admin {
+        Some(admin) => {
+            let listener = restart::admin_listener(&admin.bind).await?;
+            tracing::info!("Admin API at {}", listener.describe("http"));
+            Some(tokio::spawn(admin.serve(listener, apps, shutdown_signal())))
+        }
+        None => None,
+    };
+
+    
+    tracing::info!(
+        threads = %autoscale.map_or_else(|| threads.to_string(), |a| format!("{}-{}", a.min, a.max)),
+        stack_mb,
+        "Titan server running at {}",
+        listener.describe(if tls.is_some() { "https" } else { "http" })
+    );
     
+
+    // SIGUSR2 starts a replacement on these sockets; when this process is the
+    // replacement, its predecessor can stop now
+    tokio::spawn(restart::watch());
+    restart::handover_complete();
+
+    match (listener, tls) {
+        (restart::Listener::Tcp(listener), Some(tls)) => {
+            axum::serve(tls.listen(listener).map_err(anyhow::Error::msg)?, app.into_make_service_with_connect_info::<ClientAddr>())
+                .with_graceful_shutdown(shutdown_signal())
+                .await?
+        }
+        (restart::Listener::Tcp(listener), None) => {
+            axum::serve(listener, app.into_make_service_with_connect_info::<ClientAddr>())
+                .with_graceful_shutdown(shutdown_signal())
+                .await?
+        }
+        #[cfg(unix)]
+        (restart::Listener::Unix(_), Some(_)) => anyhow::bail!("tls can't be used with a Unix socket; terminate TLS in the proxy in front"),
+        #[cfg(unix)]
+        (restart::Listener::Unix(listener), None) => {
+            axum::serve(listener, app.into_make_service_with_connect_info::<ClientAddr>())
+                .with_graceful_shutdown(shutdown_signal())
+                .await?
+        }
+    }
+
+    if let Some(endpoint) = &quic {
+        http3::close(endpoint);
+    }
+    if let Some(server) = grpc_server {
+        let _ = server.await;
+    }
+    if let Some(server) = admin_server {
+        let _ = server.await;
+    }
+    tracing::info!("Shutting down, draining workers...");
+    let drains = runtimes.iter().map(|(runtime, timeout)| runtime.shutdown(*timeout));
+    tokio::join!(runtime_manager.shutdown(shutdown_timeout), futures_util::future::join_all(drains));
+    Ok(())
+}
+
+/// One app's worker pool and the router in front of it.
+struct Mounted {
+    router: Router,
+    runtime: Arc<RuntimeManager>,
+    shutdown_timeout: Duration,
+    threads: usize,
+    autoscale: Option<AutoscalePolicy>,
+    stack_mb: u64,
+    // Only the main app serves gRPC
+    grpc: Option<(Arc<grpc::Grpc>, Router)>,
+}
+
+/// Starts the app at `project_root`, configured by its routes.json `json`.
+/// The main app also owns what is process-wide: the task queue, the
+/// response cache, rate limits and gRPC.
+async fn mount(
+    options: &cli::ServeOptions,
+    json: &Value,
+    project_root: PathBuf,
+    threads: Option<usize>,
+    primary: bool,
+    secure: bool,
+) -> Result<Mounted> {
+    let thread_count = threads.map(|t| t as u64).or_else(|| json["__config"]["threads"].as_u64());
+    let routes_json = json["routes"].clone();
+    let map: HashMap<String, RouteVal> = serde_json::from_value(routes_json).unwrap_or_default();
+    let dynamic_routes: Vec<DynamicRoute> =
+        serde_json::from_value(json["__dynamic_routes"].clone()).unwrap_or_default();
+
+    let actions = scan_actions(&project_root);
+    let groups = groups::Groups::from_config(&json["__config"]["groups"]).map_err(anyhow::Error::msg)?;
+    let (auth_config, rate_limit_config) = groups.expand(&json["__config"], actions.keys()).map_err(anyhow::Error::msg)?;
+    let file_routes = FileRouter::from_actions(actions.keys(), &groups);
+    if !file_routes.is_empty() {
+        tracing::info!("{} file routes from actions/", file_routes.len());
+    }
+
     // Initialize Runtime Manager (Worker Pool)
     let threads = match thread_count {
         Some(t) if t > 0 => t as usize,
+        // One worker to debug, unless `threads` asks for more
+        _ if inspector::enabled() => 1,
         _ => num_cpus::get() * 4,   // default
     };
 
     let stack_mb = json["__config"]["stack_mb"].as_u64().unwrap_or(8);
+    // Per-request deadline for actions (0 disables it)
+    let timeout_ms = json["__config"]["timeout_ms"].as_u64().unwrap_or(30_000);
+    // A worker held at a breakpoint must not count as a runaway
+    let request_timeout = (timeout_ms > 0 && !inspector::enabled()).then(|| Duration::from_millis(timeout_ms));
     let stack_size = (stack_mb as usize) * 1024 * 1024;
     
-    let runtime_manager = Arc::new(RuntimeManager::new(project_root.clone(), threads, stack_size));
+    // Per-isolate resource limits (unset or 0 disables each one)
+    let limits = RuntimeLimits {
+        max_heap_bytes: json["__config"]["max_heap_mb"]
+            .as_u64()
+            .filter(|mb| *mb > 0)
+            .map(|mb| (mb as usize) * 1024 * 1024),
+        max_execution_ms: json["__config"]["max_execution_ms"].as_u64().filter(|ms| *ms > 0 && !inspector::enabled()),
+    };
+
+    // Isolate recycling (unset or 0 disables each trigger)
+    let recycle = RecyclePolicy {
+        max_requests: json["__config"]["recycle_after_requests"].as_u64().filter(|n| *n > 0),
+        max_heap_bytes: json["__config"]["recycle_heap_mb"]
+            .as_u64()
+            .filter(|mb| *mb > 0)
+            .map(|mb| (mb as usize) * 1024 * 1024),
+    };
+
+    // Queue bound and load shedding (unset holds QUEUE_PER_WORKER requests
+    // per worker, 0 leaves the queue unbounded)
+    let queue_limit = json["__config"]["queue_limit"].as_u64().map(|n| n as usize);
+    let queue = QueuePolicy {
+        max_len: match queue_limit {
+            Some(0) => None,
+            Some(n) => Some(n),
+            None => Some(threads * runtime::QUEUE_PER_WORKER),
+        },
+        shed: match json["__config"]["queue_policy"].as_str() {
+            Some("block") => ShedPolicy::Block {
+                timeout: Duration::from_millis(json["__config"]["queue_timeout_ms"].as_u64().unwrap_or(1_000)),
+            },
+            _ => ShedPolicy::Reject,
+        },
+        channel_capacity: json["__config"]["worker_channel_capacity"].as_u64().map(|n| n as usize),
+        adaptive: AdaptiveQueue::from_config(&json["__config"]["queue_adaptive"], queue_limit.filter(|n| *n > 0), threads).map_err(anyhow::Error::msg)?,
+    };
+
+    // Worker autoscaling on queue latency (starts at `min` workers instead of `threads`)
+    let autoscale = AutoscalePolicy::from_config(&json["__config"]["autoscale"], threads);
+
+    // How every pool, tenant pools included, orders and places requests
+    let schedule = |manager: &mut RuntimeManager| -> Result<()> {
+        // Queue lanes per action ("high", "normal" or "low")
+        if let Some(lanes) = json["__config"]["priority"].as_object() {
+            for (action, lane) in lanes {
+                match lane.as_str().and_then(Priority::parse) {
+                    Some(priority) => manager.prioritize(action.clone(), priority),
+                    None => tracing::warn!("Unknown priority {} for {}, using normal", lane, action),
+                }
+            }
+        }
+        // Actions whose requests stick to one worker per key
+        let session_cookie = json["__config"]["session"]["cookie"]["name"].as_str().unwrap_or("titan.sid");
+        if let Some(affinity) = affinity::Affinity::from_config(&json["__config"]["affinity"], session_cookie).map_err(anyhow::Error::msg)? {
+            manager.set_affinity(affinity);
+        }
+        // Slow idempotent requests get a second worker
+        if let Some(hedge) = HedgePolicy::from_config(&json["__config"]["hedge"]) {
+            manager.set_hedging(hedge);
+        }
+        // A client that hangs up takes its request with it, unless turned off
+        manager.set_cancel_on_disconnect(json["__config"]["cancel_on_disconnect"].as_bool().unwrap_or(true));
+        Ok(())
+    };
+    let mut runtime_manager = RuntimeManager::new(project_root.clone(), threads, stack_size, limits, recycle, queue, autoscale);
+    schedule(&mut runtime_manager)?;
+    // Tenants with workers, queue and limits of their own
+    if let Some(config) = tenants::TenantConfig::from_config(&json["__config"]["tenants"]).map_err(anyhow::Error::msg)? {
+        let mut pools = Vec::new();
+        for pool in &config.pools {
+            let mut manager = RuntimeManager::new(project_root.clone(), pool.threads, stack_size, pool.limits(limits), recycle, pool.queue(queue), None);
+            schedule(&mut manager)?;
+            tracing::info!("Tenant pool {} with {} worker(s)", pool.name, pool.threads);
+            pools.push((pool.name.clone(), manager));
+        }
+        runtime_manager.set_tenants(config, pools);
+    }
+    // Shared-secret auth in front of every action (TITAN_API_KEY wins over routes.json)
+    let api_key = std::env::var("TITAN_API_KEY")
+        .ok()
+        .or_else(|| json["__config"]["api_key"].as_str().map(str::to_string))
+        .filter(|k| !k.is_empty());
+    if let Some(key) = &api_key {
+        runtime_manager.intercept(middleware::api_key(key.clone()));
+    }
+    // Over-limit clients are answered before their request is queued
+    if let Some(limits) = rate_limit::RateLimitConfig::from_config(&rate_limit_config).filter(|_| primary) {
+        runtime_manager.intercept(rate_limit::interceptor(limits));
+        rate_limit::start_sweeper();
+    }
+    // Client location from a local MaxMind database, for `req.geo`
+    if let Some(geo) = geo::GeoConfig::from_config(&json["__config"]["geoip"], &project_root).map_err(anyhow::Error::msg)? {
+        geo.start();
+        runtime_manager.intercept(geo::interceptor(geo));
+    }
+    // JWT bearer tokens, verified here so actions only see the claims
+    if let Some(auth) = auth::AuthConfig::from_config(&auth_config, &project_root).map_err(anyhow::Error::msg)? {
+        auth.start().await;
+        runtime_manager.intercept(auth::interceptor(auth));
+    }
+    // Named middleware of the route groups each action is in
+    if let Some(interceptor) = groups.interceptor(actions.keys()) {
+        runtime_manager.intercept(interceptor);
+    }
+    let runtime_manager = Arc::new(runtime_manager);
+    if options.watch {
+        watch::spawn(runtime_manager.clone(), project_root.clone());
+    }
+    if primary && inspector::enabled() {
+        tokio::spawn(inspector::serve(runtime_manager.clone(), project_root.clone()));
+    }
+    let shutdown_timeout = Duration::from_millis(json["__config"]["shutdown_timeout_ms"].as_u64().unwrap_or(10_000));
+    let sse_keep_alive = Duration::from_millis(json["__config"]["sse_keep_alive_ms"].as_u64().filter(|ms| *ms > 0).unwrap_or(15_000));
+    let uploads = UploadConfig {
+        dir: json["__config"]["upload_dir"]
+            .as_str()
+            .map(|dir| project_root.join(dir))
+            .unwrap_or_else(|| std::env::temp_dir().join("titan-uploads")),
+        max_file_bytes: json["__config"]["upload_max_mb"]
+            .as_u64()
+            .filter(|mb| *mb > 0)
+            .map(|mb| mb * 1024 * 1024)
+            .unwrap_or(u64::MAX),
+    };
+    let compression = Arc::new(CompressionPolicy::from_config(&json["__config"]["compression"]));
+    // Assets under public/ (or `public_dir`) are served without a worker
+    let public = StaticFiles::new(project_root.join(json["__config"]["public_dir"].as_str().unwrap_or("public"))).map(Arc::new);
+    let sessions = session::SessionConfig::from_config(&json["__config"]["session"], secure)
+        .map_err(anyhow::Error::msg)?
+        .map(Arc::new);
+    if let Some(sessions) = &sessions {
+        sessions.start_sweeper();
+    }
+
+    // Cron jobs from app/jobs; `job_timeout_ms` defaults to the request deadline
+    let jobs = Arc::new(find_actions_dir(&project_root).map(|dir| JobScheduler::load(&dir)).unwrap_or_default());
+    if !jobs.is_empty() {
+        let job_timeout = match json["__config"]["job_timeout_ms"].as_u64() {
+            Some(ms) => (ms > 0).then(|| Duration::from_millis(ms)),
+            None => request_timeout,
+        };
+        jobs.start(runtime_manager.clone(), job_timeout);
+        tracing::info!("{} job(s) scheduled", jobs.len());
+    }
+
+    // Background tasks from tasks.enqueue(); `tasks_file` makes them survive restarts
+    if primary {
+        let restored = tasks::start(
+            runtime_manager.clone(),
+            tasks::TaskConfig {
+                journal: json["__config"]["tasks_file"].as_str().map(|f| project_root.join(f)),
+                concurrency: json["__config"]["tasks_concurrency"].as_u64().map_or(threads, |n| n as usize),
+                timeout: request_timeout,
+            },
+        );
+        if restored > 0 {
+            tracing::info!("{} pending task(s) restored", restored);
+        }
+    }
+
+    // GET responses of the actions under `cache.routes`, kept in memory
+    let cache = if primary { cache::ResponseCache::from_config(&json["__config"]["cache"]).map_err(anyhow::Error::msg)? } else { None };
+    if let Some(cache) = cache {
+        cache.start_sweeper();
+    }
+
+    // Retries with a known Idempotency-Key get the first response back
+    let idempotency = idempotency::IdempotencyConfig::from_config(&json["__config"]["idempotency"]).map_err(anyhow::Error::msg)?;
+    if let Some(idempotency) = &idempotency {
+        idempotency.start_sweeper();
+    }
+
+    // A schema-first GraphQL endpoint, resolved by actions under graphql/
+    let graphql = graphql::Graphql::from_config(&json["__config"]["graphql"], &project_root, actions.keys())
+        .map_err(anyhow::Error::msg)?
+        .map(Arc::new);
+    // gRPC services from app/protos, answered by actions on their own port
+    let grpc = if primary {
+        grpc::Grpc::from_config(&json["__config"]["grpc"], &project_root, actions.keys())
+            .map_err(anyhow::Error::msg)?
+            .map(Arc::new)
+    } else {
+        None
+    };
+
+    // What actions `export const config = { ... }`
+    let action_configs = runtime_manager.action_configs(Duration::from_secs(30)).await.unwrap_or_default();
+    // Those with `concurrency` run that many at a time at most
+    let capped = runtime_manager.limit_concurrency(&action_configs);
+    if capped > 0 {
+        tracing::info!("{} action(s) with a concurrency limit", capped);
+    }
+
+    // Checks requests against the schemas actions export; `"validate": false` skips them
+    let validator = if json["__config"]["validate"].as_bool().unwrap_or(true) {
+        match runtime_manager.action_schemas(Duration::from_secs(30)).await {
+            Some(schemas) => validation::Validator::from_schemas(&schemas).map_err(anyhow::Error::msg)?.map(Arc::new),
+            None => {
+                tracing::warn!("No worker reported the action schemas; requests are not validated");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // An OpenAPI document of the routes, and Swagger UI to browse it
+    let docs = openapi::Docs::from_config(&json["__config"]["docs"], &map, &dynamic_routes, &file_routes).map(Arc::new);
+    let routing = Arc::new(routing::Routing::from_config(&json["__config"]["routing"], map.keys()).map_err(anyhow::Error::msg)?);
+    // What url.for() in this app's actions builds paths from
+    urls::Urls::from_routes(&map, &dynamic_routes, actions.keys(), &groups).register(&project_root);
 
     let state = AppState {
         routes: Arc::new(map),
         dynamic_routes: Arc::new(dynamic_routes),
-        runtime: runtime_manager,
+        file_routes: Arc::new(file_routes),
+        routing,
+        fallbacks: fallbacks::Fallbacks::from_actions(actions.keys()),
+        runtime: runtime_manager.clone(),
+        request_timeout,
+        action_timeouts: Arc::new(action_timeouts(&action_configs).map_err(anyhow::Error::msg)?),
+        expose_stacks: json["__config"]["error_stacks"].as_bool().unwrap_or(false),
+        server_timing: json["__config"]["server_timing"].as_bool().unwrap_or(false),
+        sse_keep_alive,
+        uploads,
+        public,
+        compression,
+        jobs,
+        sessions,
+        health_timeout: Duration::from_millis(json["__config"]["health_timeout_ms"].as_u64().filter(|ms| *ms > 0).unwrap_or(1_000)),
+        body: Arc::new(body::BodyPolicy::from_config(&json["__config"]["body"]).with_exports(&action_configs).map_err(anyhow::Error::msg)?),
+        cors: cors::CorsConfig::from_config(&json["__config"]["cors"]).map(Arc::new),
+        graphql: graphql.clone(),
+        grpc: grpc.clone(),
+        cache,
+        idempotency,
+        etags: json["__config"]["etag"].as_bool().unwrap_or(true),
+        docs: docs.clone(),
+        validator,
     };
 
-    let app = Router::new()
-        .route("/", any(root_route))
+    let mut app = Router::new().route("/", any(root_route));
+    // Prometheus scrape endpoint (`"metrics": false` turns it off)
+    if json["__config"]["metrics"].as_bool().unwrap_or(true) {
+        app = app.route("/metrics", get(metrics_route));
+    }
+    // Probes for load balancers and orchestrators, answered without queueing
+    if json["__config"]["health"].as_bool().unwrap_or(true) {
+        app = app.route("/healthz", get(healthz_route)).route("/readyz", get(readyz_route));
+    }
+    if let Some(graphql) = &graphql {
+        app = app.route(&graphql.path, any(graphql_route));
+        tracing::info!("GraphQL at {} with {} resolver(s)", graphql.path, graphql.resolvers());
+    }
+    if let Some(docs) = &docs {
+        app = app.route(&format!("{}/openapi.json", docs.path), get(openapi_route));
+        if docs.ui {
+            app = app.route(&docs.path, get(docs_route));
+        }
+        tracing::info!("API docs at {} with {} operation(s)", docs.path, docs.operations());
+    }
+    let grpc = grpc.map(|grpc| (grpc, Router::new().fallback(any(grpc_route)).with_state(state.clone())));
+    let router = app
         .fallback(any(dynamic_route))
         .with_state(state);
+    Ok(Mounted { router, runtime: runtime_manager, shutdown_timeout, threads, autoscale, stack_mb, grpc })
+}
 
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
 
-    
-    println!(
-        "\x1b[38;5;39mTitan server running at:\x1b[0m http://localhost:{}  \x1b[90m(Threads: {}, Stack: {}MB)\x1b[0m",
-        port,
-        threads,
-        stack_mb
-    );
-    
+    #[cfg(unix)]
+    let terminate = async {
+        if let Ok(mut sig) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            sig.recv().await;
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    axum::serve(listener, app).await?;
-    Ok(())
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
 
 fn resolve_project_root() -> PathBuf {
+    // A standalone binary serves the app it carries
+    if let Some(root) = standalone::root() {
+        return root.to_path_buf();
+    }
+
     // 1. Check CWD (preferred for local dev/tooling)
     if let Ok(cwd) = std::env::current_dir() {
         if cwd.join("node_modules").exists()
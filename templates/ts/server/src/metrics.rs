@@ -0,0 +1,158 @@
+use dashmap::DashMap;
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Upper bounds in seconds, matching the Prometheus client defaults
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+// Failed requests kept for the dashboard
+const RECENT_ERRORS: usize = 50;
+
+/// Per-action request counters, recorded by `RuntimeManager::execute`.
+#[derive(Default)]
+pub struct Metrics {
+    actions: DashMap<String, ActionStats>,
+    recent_errors: Mutex<VecDeque<RecentError>>,
+}
+
+struct RecentError {
+    at_ms: u64,
+    action: String,
+    request_id: String,
+    message: String,
+    stack: Option<String>,
+}
+
+#[derive(Default)]
+struct ActionStats {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record(&self, action: &str, elapsed: Duration, error: bool) {
+        if !self.actions.contains_key(action) {
+            self.actions.entry(action.to_string()).or_default();
+        }
+        let Some(stats) = self.actions.get(action) else {
+            return;
+        };
+
+        let secs = elapsed.as_secs_f64();
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|le| secs <= *le) {
+            stats.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        stats.count.fetch_add(1, Ordering::Relaxed);
+        stats.sum_us.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        if error {
+            stats.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Keeps a failed request for the dashboard, dropping the oldest past 50.
+    pub fn record_error(&self, action: &str, request_id: &str, message: &str, stack: Option<&str>) {
+        let at_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        let mut recent = self.recent_errors.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() == RECENT_ERRORS {
+            recent.pop_front();
+        }
+        recent.push_back(RecentError {
+            at_ms,
+            action: action.to_string(),
+            request_id: request_id.to_string(),
+            message: message.to_string(),
+            stack: stack.map(str::to_string),
+        });
+    }
+
+    /// The kept errors, newest first.
+    pub fn recent_errors(&self) -> Vec<Value> {
+        let recent = self.recent_errors.lock().unwrap_or_else(|e| e.into_inner());
+        recent
+            .iter()
+            .rev()
+            .map(|e| json!({ "at": e.at_ms, "action": e.action, "request_id": e.request_id, "message": e.message, "stack": e.stack }))
+            .collect()
+    }
+
+    /// The `n` actions with the highest mean duration, with the bucket their
+    /// p95 falls in. Both are since boot.
+    pub fn slowest(&self, n: usize) -> Vec<Value> {
+        let mut actions: Vec<(String, u64, f64, Option<f64>, u64)> = self
+            .actions
+            .iter()
+            .filter_map(|entry| {
+                let stats = entry.value();
+                let count = stats.count.load(Ordering::Relaxed);
+                if count == 0 {
+                    return None;
+                }
+                let mean_ms = stats.sum_us.load(Ordering::Relaxed) as f64 / count as f64 / 1000.0;
+                // The upper bound of the bucket holding the 95th percentile; None past the last one
+                let rank = (count * 95).div_ceil(100);
+                let mut cumulative = 0;
+                let p95_ms = LATENCY_BUCKETS.iter().enumerate().find_map(|(i, le)| {
+                    cumulative += stats.buckets[i].load(Ordering::Relaxed);
+                    (cumulative >= rank).then_some(le * 1000.0)
+                });
+                Some((entry.key().clone(), count, mean_ms, p95_ms, stats.errors.load(Ordering::Relaxed)))
+            })
+            .collect();
+        actions.sort_by(|a, b| b.2.total_cmp(&a.2));
+        actions
+            .into_iter()
+            .take(n)
+            .map(|(action, count, mean_ms, p95_ms, errors)| json!({ "action": action, "count": count, "mean_ms": mean_ms, "p95_ms": p95_ms, "errors": errors }))
+            .collect()
+    }
+
+    /// Requests and errors over every action.
+    pub fn totals(&self) -> (u64, u64) {
+        self.actions.iter().fold((0, 0), |(count, errors), entry| {
+            let stats = entry.value();
+            (count + stats.count.load(Ordering::Relaxed), errors + stats.errors.load(Ordering::Relaxed))
+        })
+    }
+
+    pub fn render(&self, out: &mut String) {
+        let mut actions: Vec<_> = self.actions.iter().collect();
+        actions.sort_by(|a, b| a.key().cmp(b.key()));
+
+        header(out, "titan_action_duration_seconds", "histogram", "Time from dispatch to response per action.");
+        for entry in &actions {
+            let label = escape_label(entry.key());
+            let stats = entry.value();
+            let mut cumulative = 0;
+            for (i, le) in LATENCY_BUCKETS.iter().enumerate() {
+                cumulative += stats.buckets[i].load(Ordering::Relaxed);
+                let _ = writeln!(out, "titan_action_duration_seconds_bucket{{action=\"{}\",le=\"{}\"}} {}", label, le, cumulative);
+            }
+            let count = stats.count.load(Ordering::Relaxed);
+            let sum = stats.sum_us.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+            let _ = writeln!(out, "titan_action_duration_seconds_bucket{{action=\"{}\",le=\"+Inf\"}} {}", label, count);
+            let _ = writeln!(out, "titan_action_duration_seconds_sum{{action=\"{}\"}} {}", label, sum);
+            let _ = writeln!(out, "titan_action_duration_seconds_count{{action=\"{}\"}} {}", label, count);
+        }
+
+        header(out, "titan_action_errors_total", "counter", "Requests per action that ended in an error.");
+        for entry in &actions {
+            let errors = entry.value().errors.load(Ordering::Relaxed);
+            let _ = writeln!(out, "titan_action_errors_total{{action=\"{}\"}} {}", escape_label(entry.key()), errors);
+        }
+    }
+}
+
+/// Writes the `# HELP` / `# TYPE` preamble of a metric family.
+pub fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+pub fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
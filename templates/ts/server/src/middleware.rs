@@ -0,0 +1,55 @@
+use crate::runtime::{RequestTask, WorkerResult};
+
+/// What an interceptor wants done with a request.
+pub enum Decision {
+    /// Hand the (possibly rewritten) request to the next interceptor, then a worker.
+    Continue,
+    /// Answer right away; the action never runs.
+    Respond(Box<WorkerResult>),
+}
+
+/// Rust-side hook run by `RuntimeManager` on every request before it is
+/// queued, in registration order. It may rewrite the task (action, params,
+/// headers, response headers...) or short-circuit it. Runs on the async HTTP
+/// threads, so it must not block.
+pub type Interceptor = Box<dyn Fn(&mut RequestTask) -> Decision + Send + Sync>;
+
+/// Requires `Authorization: Bearer <key>` or `X-Api-Key: <key>` on every
+/// request, answering 401 otherwise.
+pub fn api_key(key: String) -> Interceptor {
+    Box::new(move |task| {
+        let headers = task.headers.iter().map(|(name, value)| (name.as_str(), value.as_str()));
+        match presented_key(headers) {
+            Some(presented) if constant_time_eq(presented.as_bytes(), key.as_bytes()) => Decision::Continue,
+            _ => {
+                let mut denied = WorkerResult::error(401, "Unauthorized");
+                denied.headers.push(("www-authenticate".to_string(), "Bearer".to_string()));
+                Decision::Respond(Box::new(denied))
+            }
+        }
+    })
+}
+
+/// Whether `headers` carry `key` the way `api_key` expects, for the server's
+/// own endpoints that don't go through interceptors.
+pub fn has_api_key(headers: &axum::http::HeaderMap, key: &str) -> bool {
+    let headers = headers.iter().filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)));
+    presented_key(headers).is_some_and(|presented| constant_time_eq(presented.as_bytes(), key.as_bytes()))
+}
+
+fn presented_key<'a>(mut headers: impl Iterator<Item = (&'a str, &'a str)>) -> Option<&'a str> {
+    headers.find_map(|(name, value)| {
+        if name.eq_ignore_ascii_case("x-api-key") {
+            Some(value)
+        } else if name.eq_ignore_ascii_case("authorization") {
+            value.strip_prefix("Bearer ")
+        } else {
+            None
+        }
+    })
+}
+
+// Doesn't bail out at the first mismatch, so timing reveals nothing about the key
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
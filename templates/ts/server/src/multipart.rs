@@ -0,0 +1,438 @@
+use axum::body::Body;
+use futures_util::StreamExt;
+use memchr::memmem;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+
+// Text fields are buffered in memory, so they get a much smaller cap than files
+const MAX_FIELD_BYTES: usize = 1024 * 1024;
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+
+/// Where uploads go and how large they may get.
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    pub dir: PathBuf,
+    pub max_file_bytes: u64,
+}
+
+/// A file part, already written to `path`.
+#[derive(Debug, Clone)]
+pub struct UploadedFile {
+    pub field: String,
+    pub filename: String,
+    pub content_type: String,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// A parsed `multipart/form-data` or `application/x-www-form-urlencoded`
+/// body. The files are removed from disk when the form is dropped, i.e. once
+/// the request that received them is done.
+#[derive(Debug, Default)]
+pub struct Form {
+    pub fields: Vec<(String, String)>,
+    pub files: Vec<UploadedFile>,
+}
+
+impl Form {
+    /// Contents of the `index`th file, for `file.bytes()` in JS.
+    pub fn read(&self, index: usize) -> Option<Vec<u8>> {
+        std::fs::read(&self.files.get(index)?.path).ok()
+    }
+
+    /// The fields of a URL-encoded body, in order. `+` stands for a space
+    /// and a name without `=` has an empty value.
+    pub fn urlencoded(body: &[u8]) -> Self {
+        let decode = |raw: &[u8]| {
+            let spaced: Vec<u8> = raw.iter().map(|&b| if b == b'+' { b' ' } else { b }).collect();
+            percent_encoding::percent_decode(&spaced).decode_utf8_lossy().into_owned()
+        };
+        let fields = body
+            .split(|&b| b == b'&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.iter().position(|&b| b == b'=') {
+                Some(eq) => (decode(&pair[..eq]), decode(&pair[eq + 1..])),
+                None => (decode(pair), String::new()),
+            })
+            .collect();
+        Self { fields, files: Vec::new() }
+    }
+}
+
+impl Drop for Form {
+    fn drop(&mut self) {
+        for file in &self.files {
+            let _ = std::fs::remove_file(&file.path);
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MultipartError {
+    #[error("Malformed multipart body: {0}")]
+    Malformed(&'static str),
+    #[error("Upload exceeds {0} bytes")]
+    TooLarge(u64),
+    #[error("Failed to read request body: {0}")]
+    Body(String),
+    #[error("Failed to store upload: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl MultipartError {
+    pub fn status(&self) -> u16 {
+        match self {
+            MultipartError::TooLarge(_) => 413,
+            MultipartError::Io(_) => 500,
+            _ => 400,
+        }
+    }
+}
+
+/// Whether a content type is `application/x-www-form-urlencoded`.
+pub fn is_urlencoded(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/x-www-form-urlencoded"))
+}
+
+/// The boundary of a `multipart/form-data` content type, if that's what it is.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut parts = content_type.split(';');
+    if !parts.next()?.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    parts.find_map(|p| {
+        let (key, value) = p.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+enum Sink {
+    Field(Vec<u8>),
+    File(tokio::fs::File, u64),
+}
+
+struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: String,
+    path: Option<PathBuf>,
+    sink: Sink,
+}
+
+// A part that never reached its closing delimiter leaves no file behind
+impl Drop for Part {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+enum State {
+    Preamble,
+    // Just past a delimiter: either `--` (the end) or CRLF and a new part follow
+    Delimiter,
+    Headers,
+    Body(Box<Part>),
+    Done,
+}
+
+/// Reads the body as it arrives, writing file parts straight to `config.dir`
+/// so large uploads never sit in memory.
+pub async fn parse(body: Body, boundary: &str, config: &UploadConfig) -> Result<Form, MultipartError> {
+    tokio::fs::create_dir_all(&config.dir).await?;
+
+    // Every delimiter but the first is preceded by CRLF; seeding the buffer
+    // with one lets a single pattern match them all
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+    let finder = memmem::Finder::new(&delimiter);
+    let mut buf: Vec<u8> = b"\r\n".to_vec();
+    let mut form = Form::default();
+    let mut state = State::Preamble;
+    let mut stream = body.into_data_stream();
+    let mut eof = false;
+
+    loop {
+        // Advance as far as the buffered bytes allow
+        let progressed = match state {
+            State::Preamble => match finder.find(&buf) {
+                Some(i) => {
+                    buf.drain(..i + delimiter.len());
+                    state = State::Delimiter;
+                    true
+                }
+                None => {
+                    let keep = buf.len().saturating_sub(delimiter.len());
+                    buf.drain(..keep);
+                    false
+                }
+            },
+            State::Delimiter if buf.len() >= 2 => {
+                if buf.starts_with(b"--") {
+                    state = State::Done;
+                } else if buf.starts_with(b"\r\n") {
+                    buf.drain(..2);
+                    state = State::Headers;
+                } else {
+                    return Err(MultipartError::Malformed("bad delimiter"));
+                }
+                true
+            }
+            State::Delimiter => false,
+            State::Headers => match memmem::find(&buf, b"\r\n\r\n") {
+                Some(end) => {
+                    let part = open_part(&buf[..end], config).await?;
+                    buf.drain(..end + 4);
+                    state = State::Body(Box::new(part));
+                    true
+                }
+                None if buf.len() > MAX_HEADER_BYTES => return Err(MultipartError::Malformed("part headers too large")),
+                None => false,
+            },
+            State::Body(ref mut part) => {
+                let found = finder.find(&buf);
+                // Without a delimiter, hold back enough bytes to catch one split across chunks
+                let end = found.unwrap_or_else(|| buf.len().saturating_sub(delimiter.len()));
+                write_part(part, &buf[..end], config).await?;
+                buf.drain(..end);
+                match found {
+                    Some(_) => {
+                        buf.drain(..delimiter.len());
+                        let State::Body(part) = std::mem::replace(&mut state, State::Delimiter) else {
+                            unreachable!()
+                        };
+                        close_part(*part, &mut form).await?;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            State::Done => return Ok(form),
+        };
+        if progressed {
+            continue;
+        }
+
+        if eof {
+            return Err(MultipartError::Malformed("body ended before the closing boundary"));
+        }
+        match stream.next().await {
+            Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+            Some(Err(e)) => return Err(MultipartError::Body(e.to_string())),
+            None => eof = true,
+        }
+    }
+}
+
+async fn open_part(raw: &[u8], config: &UploadConfig) -> Result<Part, MultipartError> {
+    let headers = std::str::from_utf8(raw).map_err(|_| MultipartError::Malformed("part headers are not UTF-8"))?;
+    let mut name = None;
+    let mut filename = None;
+    let mut content_type = "text/plain".to_string();
+
+    for line in headers.split("\r\n") {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if key.trim().eq_ignore_ascii_case("content-disposition") {
+            name = disposition_param(value, "name");
+            filename = disposition_param(value, "filename");
+        } else if key.trim().eq_ignore_ascii_case("content-type") {
+            content_type = value.to_string();
+        }
+    }
+    let name = name.ok_or(MultipartError::Malformed("part without a name"))?;
+
+    let (path, sink) = match &filename {
+        Some(_) => {
+            let path = config.dir.join(upload_name());
+            let file = tokio::fs::File::create(&path).await?;
+            (Some(path), Sink::File(file, 0))
+        }
+        None => (None, Sink::Field(Vec::new())),
+    };
+    Ok(Part { name, filename, content_type, path, sink })
+}
+
+async fn write_part(part: &mut Part, data: &[u8], config: &UploadConfig) -> Result<(), MultipartError> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    match &mut part.sink {
+        Sink::Field(value) => {
+            if value.len() + data.len() > MAX_FIELD_BYTES {
+                return Err(MultipartError::TooLarge(MAX_FIELD_BYTES as u64));
+            }
+            value.extend_from_slice(data);
+        }
+        Sink::File(file, size) => {
+            *size += data.len() as u64;
+            if *size > config.max_file_bytes {
+                return Err(MultipartError::TooLarge(config.max_file_bytes));
+            }
+            file.write_all(data).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn close_part(mut part: Part, form: &mut Form) -> Result<(), MultipartError> {
+    let name = std::mem::take(&mut part.name);
+    match std::mem::replace(&mut part.sink, Sink::Field(Vec::new())) {
+        Sink::Field(value) => {
+            form.fields.push((name, String::from_utf8_lossy(&value).into_owned()));
+        }
+        Sink::File(mut file, size) => {
+            file.flush().await?;
+            // The form owns the file from here on
+            form.files.push(UploadedFile {
+                field: name,
+                filename: part.filename.take().unwrap_or_default(),
+                content_type: std::mem::take(&mut part.content_type),
+                path: part.path.take().unwrap_or_default(),
+                size,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Reads `key="value"` (or unquoted) out of a Content-Disposition header.
+fn disposition_param(header: &str, key: &str) -> Option<String> {
+    header.split(';').skip(1).find_map(|p| {
+        let (k, v) = p.split_once('=')?;
+        k.trim()
+            .eq_ignore_ascii_case(key)
+            .then(|| v.trim().trim_matches('"').to_string())
+    })
+}
+
+// Client filenames are never used on disk
+fn upload_name() -> String {
+    let mut bytes = [0u8; 16];
+    let _ = SystemRandom::new().fill(&mut bytes);
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("upload-{}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "----titan7MA4YWxk";
+
+    fn upload_dir(test: &str) -> UploadConfig {
+        let dir = std::env::temp_dir().join(format!("titan-multipart-{}-{}", std::process::id(), test));
+        UploadConfig { dir, max_file_bytes: 64 }
+    }
+
+    fn leftovers(config: &UploadConfig) -> usize {
+        std::fs::read_dir(&config.dir).map_or(0, |entries| entries.count())
+    }
+
+    fn body(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = b"preamble to ignore\r\n".to_vec();
+        for (name, filename, data) in parts {
+            body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+            match filename {
+                Some(filename) => body.extend_from_slice(
+                    format!("Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: image/png\r\n\r\n", name, filename).as_bytes(),
+                ),
+                None => body.extend_from_slice(format!("content-disposition: form-data; name={}\r\n\r\n", name).as_bytes()),
+            }
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    async fn parse_chunks(chunks: Vec<Vec<u8>>, config: &UploadConfig) -> Result<Form, MultipartError> {
+        let stream = futures_util::stream::iter(chunks.into_iter().map(Ok::<_, std::io::Error>));
+        parse(Body::from_stream(stream), BOUNDARY, config).await
+    }
+
+    #[tokio::test]
+    async fn delimiters_split_across_chunks() {
+        let config = upload_dir("split");
+        // The file holds something that looks like the start of a delimiter
+        let file: &[u8] = b"\x89PNG\r\n--not-the-boundary\r\n\x00";
+        let body = body(&[("title", None, b"Hello, world"), ("avatar", Some("me.png"), file), ("empty", None, b"")]);
+        for at in 0..=body.len() {
+            let chunks = vec![body[..at].to_vec(), body[at..].to_vec()];
+            let form = parse_chunks(chunks, &config).await.unwrap();
+            assert_eq!(form.fields, [("title".into(), "Hello, world".into()), ("empty".into(), String::new())], "split at {}", at);
+            let upload = &form.files[0];
+            assert_eq!((upload.field.as_str(), upload.filename.as_str(), upload.content_type.as_str()), ("avatar", "me.png", "image/png"));
+            assert_eq!((upload.size, form.read(0).as_deref()), (file.len() as u64, Some(file)));
+        }
+        let one_byte = body.iter().map(|b| vec![*b]).collect();
+        assert_eq!(parse_chunks(one_byte, &config).await.unwrap().files[0].size, file.len() as u64);
+        assert_eq!(leftovers(&config), 0, "dropped forms remove their files");
+    }
+
+    #[tokio::test]
+    async fn missing_final_boundary() {
+        let config = upload_dir("unterminated");
+        let full = body(&[("title", None, b"x"), ("avatar", Some("a.png"), b"partial upload")]);
+        let closing = format!("--{}--\r\n", BOUNDARY).len();
+        // Cut before the closing delimiter, inside the file, and inside its headers
+        for end in [full.len() - closing, full.len() - closing - 6, full.len() - closing - 30] {
+            let err = parse_chunks(vec![full[..end].to_vec()], &config).await.unwrap_err();
+            assert!(matches!(err, MultipartError::Malformed("body ended before the closing boundary")), "{}: {}", end, err);
+            assert_eq!(err.status(), 400);
+            assert_eq!(leftovers(&config), 0, "cut at {}", end);
+        }
+        let garbage = format!("--{}\r\nno-separator", BOUNDARY).into_bytes();
+        assert!(parse_chunks(vec![garbage], &config).await.is_err());
+        let bad = format!("--{}xx", BOUNDARY).into_bytes();
+        assert!(matches!(parse_chunks(vec![bad], &config).await, Err(MultipartError::Malformed("bad delimiter"))));
+    }
+
+    #[tokio::test]
+    async fn size_limits() {
+        let config = upload_dir("limits");
+        let at_limit = vec![b'a'; 64];
+        let form = parse_chunks(vec![body(&[("f", Some("ok.bin"), &at_limit)])], &config).await.unwrap();
+        assert_eq!(form.files[0].size, 64);
+        drop(form);
+
+        let over = vec![b'a'; 65];
+        let chunks = over.chunks(10).map(<[u8]>::to_vec).collect::<Vec<_>>();
+        let mut split = body(&[("f", Some("big.bin"), &over)]);
+        let head = split.len() - over.len() - format!("\r\n--{}--\r\n", BOUNDARY).len();
+        let tail = split.split_off(head + over.len());
+        split.truncate(head);
+        let err = parse_chunks([vec![split], chunks, vec![tail]].concat(), &config).await.unwrap_err();
+        assert!(matches!(err, MultipartError::TooLarge(64)));
+        assert_eq!(err.status(), 413);
+        assert_eq!(leftovers(&config), 0, "the partial upload is removed");
+
+        let field = vec![b'x'; MAX_FIELD_BYTES + 1];
+        let err = parse_chunks(vec![body(&[("note", None, &field)])], &config).await.unwrap_err();
+        assert!(matches!(err, MultipartError::TooLarge(n) if n == MAX_FIELD_BYTES as u64));
+
+        let mut huge_headers = format!("--{}\r\nX-Padding: ", BOUNDARY).into_bytes();
+        huge_headers.resize(huge_headers.len() + MAX_HEADER_BYTES, b'p');
+        let err = parse_chunks(vec![huge_headers], &config).await.unwrap_err();
+        assert!(matches!(err, MultipartError::Malformed("part headers too large")));
+    }
+
+    #[test]
+    fn content_types() {
+        assert_eq!(boundary("multipart/form-data; boundary=\"abc def\""), Some("abc def".into()));
+        assert_eq!(boundary("Multipart/Form-Data;charset=utf-8; BOUNDARY=x"), Some("x".into()));
+        assert_eq!(boundary("multipart/mixed; boundary=x"), None);
+        assert!(is_urlencoded("application/x-www-form-urlencoded; charset=utf-8"));
+        let form = Form::urlencoded(b"a=1&b=hello+world%21&&flag&c=%E2%9C%93");
+        let expected = [("a", "1"), ("b", "hello world!"), ("flag", ""), ("c", "✓")].map(|(k, v)| (k.to_string(), v.to_string()));
+        assert_eq!(form.fields, expected);
+    }
+}
@@ -0,0 +1,179 @@
+use serde_json::Value;
+use std::sync::OnceLock;
+
+static PLACEMENT: OnceLock<Placement> = OnceLock::new();
+
+// Where each worker runs: worker `i` gets `slots[i % len]`
+struct Placement {
+    slots: Vec<Slot>,
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    core: usize,
+    // Set when memory should come from the core's own NUMA node
+    node: Option<usize>,
+}
+
+/// The `cpu_affinity` block of titan.config: pins each worker thread to one
+/// core, `true` for every core the process may run on or `cores` for a list
+/// (`[0, 1, 2]` or `"0-7,16-23"`). With `numa` (default true on machines with
+/// more than one node), workers are spread across the nodes in turn and each
+/// allocates from its own node, V8 heap included, so no isolate reads memory
+/// across the interconnect. Linux only; elsewhere it is ignored with a warning.
+pub fn configure(config: &Value) {
+    if !(config.as_bool() == Some(true) || config.is_object()) {
+        return;
+    }
+    if !cfg!(target_os = "linux") {
+        tracing::warn!("cpu_affinity is only supported on Linux; workers are not pinned");
+        return;
+    }
+    let allowed = allowed_cores();
+    let cores: Vec<usize> = match &config["cores"] {
+        Value::Array(list) => list.iter().filter_map(Value::as_u64).map(|c| c as usize).collect(),
+        Value::String(list) => parse_list(list),
+        _ => allowed.clone(),
+    };
+    let cores: Vec<usize> = cores.into_iter().filter(|c| allowed.contains(c)).collect();
+    if cores.is_empty() {
+        tracing::warn!("cpu_affinity: none of the listed cores are available; workers are not pinned");
+        return;
+    }
+
+    let nodes = numa_nodes();
+    let numa = config["numa"].as_bool().unwrap_or(nodes.len() > 1);
+    let slots = if numa && nodes.len() > 1 {
+        // One core from each node in turn, so any pool size is balanced
+        let per_node: Vec<(usize, Vec<usize>)> = nodes
+            .iter()
+            .map(|(id, list)| (*id, cores.iter().copied().filter(|c| list.contains(c)).collect::<Vec<_>>()))
+            .filter(|(_, list)| !list.is_empty())
+            .collect();
+        let mut slots = Vec::new();
+        for round in 0..per_node.iter().map(|(_, list)| list.len()).max().unwrap_or(0) {
+            for (id, list) in &per_node {
+                if let Some(&core) = list.get(round) {
+                    slots.push(Slot { core, node: Some(*id) });
+                }
+            }
+        }
+        slots
+    } else {
+        cores.iter().map(|&core| Slot { core, node: None }).collect()
+    };
+
+    let spread = slots.iter().filter_map(|s| s.node).collect::<std::collections::BTreeSet<_>>().len();
+    if spread > 1 {
+        tracing::info!("Pinning workers to {} core(s) across {} NUMA nodes", slots.len(), spread);
+    } else {
+        tracing::info!("Pinning workers to {} core(s)", slots.len());
+    }
+    let _ = PLACEMENT.set(Placement { slots });
+}
+
+/// Pins the calling thread, worker `worker`'s, to its core and node. Done
+/// before the isolate is created, so its heap is first touched there.
+pub fn pin(worker: usize) {
+    let Some(placement) = PLACEMENT.get() else {
+        return;
+    };
+    let slot = placement.slots[worker % placement.slots.len()];
+    if let Err(e) = set_affinity(slot.core) {
+        tracing::warn!(worker, "Could not pin worker to core {}: {}", slot.core, e);
+        return;
+    }
+    if let Some(node) = slot.node
+        && let Err(e) = prefer_node(node)
+    {
+        tracing::warn!(worker, "Could not bind worker memory to NUMA node {}: {}", node, e);
+    }
+}
+
+// "0-3,8,10-11" as [0, 1, 2, 3, 8, 10, 11]
+fn parse_list(list: &str) -> Vec<usize> {
+    let mut cores = Vec::new();
+    for part in list.trim().split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                if let (Ok(from), Ok(to)) = (from.parse::<usize>(), to.parse::<usize>()) {
+                    cores.extend(from..=to);
+                }
+            }
+            None => cores.extend(part.parse::<usize>().ok()),
+        }
+    }
+    cores
+}
+
+// Each NUMA node's id and cores, from sysfs
+fn numa_nodes() -> Vec<(usize, Vec<usize>)> {
+    let Ok(entries) = std::fs::read_dir("/sys/devices/system/node") else {
+        return Vec::new();
+    };
+    let mut nodes: Vec<_> = entries
+        .flatten()
+        .filter_map(|e| {
+            let id = e.file_name().to_str()?.strip_prefix("node")?.parse().ok()?;
+            let cores = std::fs::read_to_string(e.path().join("cpulist")).ok()?;
+            Some((id, parse_list(&cores)))
+        })
+        .collect();
+    nodes.sort();
+    nodes
+}
+
+#[cfg(target_os = "linux")]
+fn allowed_cores() -> Vec<usize> {
+    // SAFETY: cpu_set_t is plain data, and sched_getaffinity only writes into it
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return (0..std::thread::available_parallelism().map_or(1, |n| n.get())).collect();
+        }
+        (0..libc::CPU_SETSIZE as usize).filter(|&core| libc::CPU_ISSET(core, &set)).collect()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(core: usize) -> Result<(), String> {
+    // SAFETY: as above; pid 0 is the calling thread
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+    }
+    Ok(())
+}
+
+// Allocations prefer `node`, falling back to the others when it is full
+#[cfg(target_os = "linux")]
+fn prefer_node(node: usize) -> Result<(), String> {
+    const MPOL_PREFERRED: libc::c_int = 1;
+    let bits = libc::c_ulong::BITS as usize;
+    let mut mask = vec![0 as libc::c_ulong; node / bits + 1];
+    mask[node / bits] |= 1 << (node % bits);
+    // SAFETY: the mask outlives the call and holds `maxnode` bits
+    let result = unsafe { libc::syscall(libc::SYS_set_mempolicy, MPOL_PREFERRED, mask.as_ptr(), mask.len() * bits + 1) };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cores() -> Vec<usize> {
+    Vec::new()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_core: usize) -> Result<(), String> {
+    Err("not supported on this platform".to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn prefer_node(_node: usize) -> Result<(), String> {
+    Err("not supported on this platform".to_string())
+}
@@ -0,0 +1,301 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tokio::net::TcpListener;
+
+// A process started by a restart finds its predecessor's sockets, and the
+// pid to tell once it serves, in these variables
+#[cfg(unix)]
+const LISTEN_FD: &str = "TITAN_LISTEN_FD";
+#[cfg(unix)]
+const QUIC_FD: &str = "TITAN_QUIC_FD";
+#[cfg(unix)]
+const GRPC_FD: &str = "TITAN_GRPC_FD";
+#[cfg(unix)]
+const ADMIN_FD: &str = "TITAN_ADMIN_FD";
+#[cfg(unix)]
+const PARENT_PID: &str = "TITAN_PARENT_PID";
+
+// The sockets the next process takes over, by the variable naming each fd
+#[cfg(unix)]
+static SOCKETS: std::sync::Mutex<Vec<(&'static str, std::os::fd::RawFd)>> = std::sync::Mutex::new(Vec::new());
+
+#[cfg(unix)]
+fn inherited(var: &str) -> Option<std::os::fd::RawFd> {
+    std::env::var(var).ok()?.parse().ok()
+}
+
+#[cfg(unix)]
+fn share(var: &'static str, fd: std::os::fd::RawFd) {
+    SOCKETS.lock().unwrap_or_else(|e| e.into_inner()).push((var, fd));
+}
+
+/// Where the HTTP server listens when no socket is handed to it.
+pub enum Bind {
+    Port(u16),
+    /// One address only, e.g. loopback for the admin listener.
+    Addr(SocketAddr),
+    /// A Unix domain socket; `mode` sets the file's permissions.
+    Unix { path: PathBuf, mode: Option<u32> },
+}
+
+/// The HTTP listener, TCP or Unix domain.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl Listener {
+    /// How the startup line names it.
+    pub fn describe(&self, scheme: &str) -> String {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => format!("{}://localhost:{}", scheme, addr.port()),
+                Err(_) => format!("{}://localhost", scheme),
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener) => match listener.local_addr().ok().and_then(|a| a.as_pathname().map(Path::to_path_buf)) {
+                Some(path) => format!("unix:{}", path.display()),
+                None => "an unnamed Unix socket".to_string(),
+            },
+        }
+    }
+}
+
+/// The HTTP listener. After a restart it is the previous process's socket,
+/// so the port never closes in between and no connection is refused. Under
+/// systemd socket activation it is the socket systemd passed in; otherwise
+/// `bind` is bound.
+pub async fn http_listener(bind: &Bind) -> std::io::Result<Listener> {
+    #[cfg(unix)]
+    {
+        let listener = match inherited(LISTEN_FD).or_else(|| activated("http")) {
+            Some(fd) => from_fd(fd)?,
+            None => bind_new(bind).await?,
+        };
+        share(LISTEN_FD, raw_fd(&listener));
+        Ok(listener)
+    }
+    #[cfg(not(unix))]
+    bind_new(bind).await
+}
+
+/// The admin listener, taken over like the HTTP one; systemd passes it as
+/// the socket named `admin`.
+pub async fn admin_listener(bind: &Bind) -> std::io::Result<Listener> {
+    #[cfg(unix)]
+    {
+        let listener = match inherited(ADMIN_FD).or_else(|| activated("admin")) {
+            Some(fd) => from_fd(fd)?,
+            None => bind_new(bind).await?,
+        };
+        share(ADMIN_FD, raw_fd(&listener));
+        Ok(listener)
+    }
+    #[cfg(not(unix))]
+    bind_new(bind).await
+}
+
+async fn bind_new(bind: &Bind) -> std::io::Result<Listener> {
+    match bind {
+        Bind::Port(port) => Ok(Listener::Tcp(TcpListener::bind(format!("0.0.0.0:{}", port)).await?)),
+        Bind::Addr(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+        #[cfg(unix)]
+        Bind::Unix { path, mode } => Ok(Listener::Unix(bind_unix(path, *mode)?)),
+        #[cfg(not(unix))]
+        Bind::Unix { .. } => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Unix sockets are not supported on this platform")),
+    }
+}
+
+#[cfg(unix)]
+fn raw_fd(listener: &Listener) -> std::os::fd::RawFd {
+    use std::os::fd::AsRawFd;
+    match listener {
+        Listener::Tcp(listener) => listener.as_raw_fd(),
+        Listener::Unix(listener) => listener.as_raw_fd(),
+    }
+}
+
+/// The gRPC listener, taken over like the HTTP one; systemd passes it as
+/// the socket named `grpc`.
+pub async fn grpc_listener(port: u16) -> std::io::Result<TcpListener> {
+    #[cfg(unix)]
+    return take_over(GRPC_FD, port).await;
+    #[cfg(not(unix))]
+    TcpListener::bind(format!("0.0.0.0:{}", port)).await
+}
+
+#[cfg(unix)]
+async fn take_over(var: &'static str, port: u16) -> std::io::Result<TcpListener> {
+    use std::os::fd::{AsRawFd, FromRawFd};
+    let listener = match inherited(var).or_else(|| activated("grpc")) {
+        Some(fd) => {
+            let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)?
+        }
+        None => TcpListener::bind(format!("0.0.0.0:{}", port)).await?,
+    };
+    share(var, listener.as_raw_fd());
+    Ok(listener)
+}
+
+// A socket passed by systemd (sd_listen_fds): the one whose
+// FileDescriptorName is `name`, or for "http" the first not named "grpc"
+// or "admin"
+#[cfg(unix)]
+fn activated(name: &str) -> Option<std::os::fd::RawFd> {
+    const LISTEN_FDS_START: std::os::fd::RawFd = 3;
+    // Meant for another process when the pid doesn't match, e.g. our parent
+    if std::env::var("LISTEN_PID").ok()?.parse::<u32>().ok()? != std::process::id() {
+        return None;
+    }
+    let count: std::os::fd::RawFd = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let names: Vec<&str> = names.split(':').collect();
+    let named = |i: std::os::fd::RawFd| names.get(i as usize).copied().unwrap_or("");
+    let index = (0..count)
+        .find(|&i| named(i) == name)
+        .or_else(|| (name == "http").then(|| (0..count).find(|&i| !matches!(named(i), "grpc" | "admin"))).flatten())?;
+    tracing::info!("Using the {} socket passed by systemd", name);
+    Some(LISTEN_FDS_START + index)
+}
+
+// An inherited listening socket, whichever family it is
+#[cfg(unix)]
+fn from_fd(fd: std::os::fd::RawFd) -> std::io::Result<Listener> {
+    use std::os::fd::FromRawFd;
+    // SAFETY: sockaddr_storage is plain data large enough for any address
+    let family = unsafe {
+        let mut addr: libc::sockaddr_storage = std::mem::zeroed();
+        let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        if libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        addr.ss_family as libc::c_int
+    };
+    if family == libc::AF_UNIX {
+        let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+        listener.set_nonblocking(true)?;
+        return Ok(Listener::Unix(tokio::net::UnixListener::from_std(listener)?));
+    }
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    listener.set_nonblocking(true)?;
+    Ok(Listener::Tcp(TcpListener::from_std(listener)?))
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &Path, mode: Option<u32>) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    // A socket file left by a crashed run would make bind fail; one that
+    // still answers belongs to a server that is running
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(std::io::Error::new(std::io::ErrorKind::AddrInUse, format!("{} is in use", path.display())));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+/// The HTTP/3 socket, taken over like the TCP listener.
+pub fn udp_socket(addr: SocketAddr) -> std::io::Result<std::net::UdpSocket> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::{AsRawFd, FromRawFd};
+        let socket = match inherited(QUIC_FD) {
+            Some(fd) => unsafe { std::net::UdpSocket::from_raw_fd(fd) },
+            None => std::net::UdpSocket::bind(addr)?,
+        };
+        share(QUIC_FD, socket.as_raw_fd());
+        Ok(socket)
+    }
+    #[cfg(not(unix))]
+    std::net::UdpSocket::bind(addr)
+}
+
+/// Called by a restarted process once it is about to serve: the previous
+/// one gets SIGTERM, stops accepting and drains its workers.
+pub fn handover_complete() {
+    #[cfg(unix)]
+    if let Some(pid) = inherited(PARENT_PID) {
+        // Only the process that started us; the variable may be stale
+        if unsafe { libc::getppid() } == pid {
+            unsafe { libc::kill(pid, libc::SIGTERM) };
+        }
+    }
+}
+
+/// On SIGUSR2, starts a new copy of the server on the same sockets. The
+/// binary is found the way this one was started, so a rebuilt one is picked
+/// up. If the new process exits before it serves, this one keeps going.
+#[cfg(unix)]
+pub async fn watch() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let Ok(mut sigusr2) = signal(SignalKind::user_defined2()) else {
+        return;
+    };
+    while sigusr2.recv().await.is_some() {
+        tracing::info!("SIGUSR2: starting a new process on the same sockets...");
+        let mut child = match spawn_successor() {
+            Ok(child) => child,
+            Err(e) => {
+                tracing::error!("Restart failed: {}", e);
+                continue;
+            }
+        };
+        // A successful restart ends this process before the child exits
+        match child.wait().await {
+            Ok(status) => tracing::error!("Restart failed: the new process exited with {}", status),
+            Err(e) => tracing::error!("Restart failed: {}", e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn watch() {}
+
+/// Restarts the server the way SIGUSR2 does, so titan.config and the
+/// actions are read afresh while the sockets stay open.
+pub fn reload() -> Result<(), String> {
+    #[cfg(unix)]
+    {
+        if unsafe { libc::kill(libc::getpid(), libc::SIGUSR2) } != 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    Err("reloading needs a Unix platform".to_string())
+}
+
+#[cfg(unix)]
+fn spawn_successor() -> std::io::Result<tokio::process::Child> {
+    let mut args = std::env::args_os();
+    let program = args.next().map(std::path::PathBuf::from).map_or_else(std::env::current_exe, Ok)?;
+    let sockets = SOCKETS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+
+    let mut command = tokio::process::Command::new(program);
+    command.args(args).env(PARENT_PID, std::process::id().to_string());
+    for (var, fd) in &sockets {
+        command.env(var, fd.to_string());
+    }
+    // The sockets are close-on-exec; clear that in the forked child only
+    unsafe {
+        command.pre_exec(move || {
+            for (_, fd) in &sockets {
+                let flags = libc::fcntl(*fd, libc::F_GETFD);
+                if flags < 0 || libc::fcntl(*fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
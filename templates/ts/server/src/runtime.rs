@@ -1,26 +1,136 @@
 use bytes::Bytes;
-use crossbeam::channel::{bounded, Sender};
+use crossbeam::channel::{bounded, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use crossbeam::deque::Worker;
+use std::any::Any;
+use std::ffi::c_void;
+use std::net::{IpAddr, SocketAddr};
+use std::fmt::Write;
+use std::panic::{self, AssertUnwindSafe};
 use std::thread;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
 use smallvec::SmallVec;
 
+use crate::body::StreamedBody;
+use crate::error::TitanError;
 use crate::extensions::{self, TitanRuntime, AsyncOpRequest, WorkerAsyncResult};
+use crate::metrics::{self, Metrics};
+use crate::middleware::{Decision, Interceptor};
+use crate::multipart::Form;
+use crate::scheduler::{Priority, Scheduler, WAIT_BUCKETS};
+use crate::telemetry::{self, SpanRecord, TraceContext};
+use crate::timing::{self, Mark};
+use crate::websocket::WsMessage;
 
 pub struct RuntimeManager {
-    request_txs: Vec<Sender<WorkerCommand>>,
+    scheduler: Arc<Scheduler>,
+    metrics: Metrics,
+    in_flight: AtomicUsize,
+    queue: QueuePolicy,
+    // The queue bound in force, 0 for none; `queue_adaptive` moves it
+    queue_limit: Arc<AtomicUsize>,
+    shed: Arc<AtomicU64>,
+    // p99 queue wait over the last controller window
+    wait_p99_us: Arc<AtomicU64>,
+    interceptors: Vec<Interceptor>,
+    priorities: std::collections::HashMap<String, Priority>,
+    affinity: Option<crate::affinity::Affinity>,
+    tenants: Option<crate::tenants::Tenants>,
+    // Slots of the actions whose `config` caps how many run at once
+    concurrency: std::sync::OnceLock<std::collections::HashMap<String, Arc<tokio::sync::Semaphore>>>,
+    hedge: Option<HedgePolicy>,
+    hedged: AtomicU64,
+    hedge_wins: AtomicU64,
+    // Whether a client hanging up stops its request, or only fires `ctx.signal`
+    cancel_on_disconnect: bool,
     round_robin_counter: AtomicUsize,
+    socket_counter: AtomicU32,
+    // Shared with the tenant pools, so a ticket names one request everywhere
+    ticket_counter: Arc<AtomicU64>,
+    monitors: Vec<Arc<WorkerMonitor>>,
+    // Workers started at boot; the server isn't ready before they all are
+    initial: usize,
+    accepting: AtomicBool,
+    // Set from the admin API; readiness fails while it is
+    draining: AtomicBool,
     _resume_txs: Vec<Sender<WorkerCommand>>, // Keep alive
-    _workers: Vec<thread::JoinHandle<()>>,
+    pool: Arc<WorkerPool>,
 }
 
 pub enum WorkerCommand {
-    Request(RequestTask),
+    Wake,
     Resume {
         drift_id: u32,
         result: WorkerAsyncResult,
     },
+    // Result of a promise-returning op started by `t._async_start`
+    Settle {
+        op_id: u32,
+        outcome: extensions::AsyncOutcome,
+    },
+    // A job or background task, run like a request without going through the queue
+    Background {
+        task: Box<RequestTask>,
+    },
+    SocketOpen {
+        socket_id: u32,
+        task: Box<RequestTask>,
+        outbound: mpsc::UnboundedSender<WsMessage>,
+    },
+    SocketMessage {
+        socket_id: u32,
+        message: WsMessage,
+    },
+    SocketClose {
+        socket_id: u32,
+    },
+    // Published on a topic this isolate subscribes to
+    BusMessage {
+        message: Arc<crate::bus::BusMessage>,
+    },
+    Shutdown {
+        deadline: Instant,
+    },
+    // The client of `ticket` went away or its deadline passed; fires `ctx.signal`.
+    // With `cancel`, a request suspended in drift() is dropped afterwards.
+    Abort {
+        ticket: u64,
+        reason: &'static str,
+        cancel: bool,
+    },
+    // Liveness probe; answered between two pieces of work
+    Ping {
+        reply: oneshot::Sender<()>,
+    },
+    // CPU samples since the last dump, when profiling is on
+    Profile {
+        reply: oneshot::Sender<crate::profiler::Stacks>,
+    },
+    // Writes a heap snapshot to `path`, answering with its size
+    HeapSnapshot {
+        path: std::path::PathBuf,
+        reply: oneshot::Sender<Result<u64, String>>,
+    },
+    // What each action carries under `key`: its schema or `config` export
+    Exports {
+        key: &'static str,
+        reply: oneshot::Sender<std::collections::HashMap<String, serde_json::Value>>,
+    },
+    // Actions changed on disk, swapped in by `start --watch`
+    Reload {
+        changes: Arc<crate::watch::Changes>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    // A DevTools connection, when --inspect is on
+    DebuggerAttach {
+        inbound: Receiver<crate::inspector::Incoming>,
+        outbound: mpsc::UnboundedSender<WsMessage>,
+    },
+    // DevTools sent the attached debugger something
+    Debugger,
 }
 
 #[allow(dead_code)]
@@ -32,16 +142,1027 @@ pub struct RequestTask {
     pub headers: SmallVec<[(String, String); 8]>,
     pub params: SmallVec<[(String, String); 4]>,
     pub query: SmallVec<[(String, String); 4]>,
-    pub response_tx: oneshot::Sender<WorkerResult>,
+    pub socket_id: Option<u32>,
+    pub ticket: u64,
+    /// `X-Request-Id`, given or generated; distinct from the worker's own
+    /// numeric request ids. It goes in every log line about the request.
+    pub correlation_id: String,
+    pub trace: Option<TraceContext>,
+    /// Parsed `multipart/form-data` body; `body` is None when this is set.
+    pub form: Option<Arc<Form>>,
+    /// The client's address; None for jobs and tasks.
+    pub remote_addr: Option<SocketAddr>,
+    /// Added to the response by the HTTP layer, e.g. by interceptors.
+    pub response_headers: ResponseHeaders,
+    /// Verified JWT claims, set by the auth interceptor.
+    pub auth: Option<Arc<serde_json::Value>>,
+    /// Session data loaded by the HTTP layer, exposed as `req.session`.
+    pub session: Option<Arc<serde_json::Value>>,
+    /// Where the client is, set by the geoip interceptor; `req.geo`.
+    pub geo: Option<Arc<serde_json::Value>>,
+    /// Named middleware of the action's route groups, set by the groups interceptor.
+    pub middleware: Option<Arc<[String]>>,
+    /// Why a fallback action (`_not_found`, `_method_not_allowed`, `_error`) runs; `req.error`.
+    pub error: Option<Arc<serde_json::Value>>,
+    /// Set once nobody waits for the result, so a worker skips the task if
+    /// it is still queued. Shared with the hedge copy.
+    pub cancelled: Arc<AtomicBool>,
+    /// The request's timeline, when one is kept; the worker marks when it
+    /// takes and starts the task. Shared with the hedge copy.
+    pub timeline: Option<Arc<crate::timing::Timeline>>,
+    /// When the HTTP layer stops waiting for the result; `ctx.deadline`.
+    pub deadline: Option<SystemTime>,
+    /// The body, for actions that read it as a stream; `body` is None then.
+    pub body_stream: Option<Arc<StreamedBody>>,
+    /// When the task was created, for the queue wait the autoscaler watches.
+    pub queued_at: Instant,
+    /// The queue lane it waits in.
+    pub priority: Priority,
+    pub response_tx: extensions::ResponseSender,
+}
+
+impl RequestTask {
+    /// A task with only its action, method, path, ticket and lane filled in,
+    /// and the receiver its result comes back on.
+    pub fn new(action: String, method: String, path: String, ticket: u64, priority: Priority) -> (Self, ResponseReceiver) {
+        let (tx, rx) = oneshot::channel();
+        let task = RequestTask {
+            action_name: action,
+            body: None,
+            method,
+            path,
+            headers: SmallVec::new(),
+            params: SmallVec::new(),
+            query: SmallVec::new(),
+            socket_id: None,
+            ticket,
+            correlation_id: String::new(),
+            trace: None,
+            form: None,
+            remote_addr: None,
+            response_headers: ResponseHeaders::new(),
+            auth: None,
+            session: None,
+            geo: None,
+            middleware: None,
+            error: None,
+            cancelled: Arc::default(),
+            timeline: None,
+            deadline: None,
+            body_stream: None,
+            queued_at: Instant::now(),
+            priority,
+            response_tx: tx,
+        };
+        (task, rx)
+    }
+
+    /// The client's address, looked up through trusted proxies; `req.ip`.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        crate::proxy::client_ip(self.remote_addr, |name| {
+            self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+        })
+    }
 }
 
+pub type ResponseHeaders = SmallVec<[(String, String); 4]>;
+pub type ResponseReceiver = oneshot::Receiver<Result<WorkerResult, TitanError>>;
+
+/// Everything the HTTP layer needs to answer a request.
 pub struct WorkerResult {
-    pub json: serde_json::Value,
+    pub status: u16,
+    pub headers: ResponseHeaders,
+    pub body: ResponseBody,
     pub timings: Vec<(String, f64)>,
 }
 
+pub enum ResponseBody {
+    Json(serde_json::Value),
+    // A plain object or array the isolate already serialized with JSON.stringify
+    JsonText(Bytes),
+    Bytes(Bytes),
+    // Chunks written with res.write() while the action keeps running
+    Stream(extensions::StreamReceiver),
+    Empty,
+}
+
+impl WorkerResult {
+    pub fn json(status: u16, value: serde_json::Value) -> Self {
+        Self { status, headers: SmallVec::new(), body: ResponseBody::Json(value), timings: vec![] }
+    }
+
+    pub fn error(status: u16, message: impl Into<String>) -> Self {
+        Self::json(status, serde_json::json!({ "error": message.into() }))
+    }
+
+    /// Set when the action failed or returned an `{ error }` object.
+    pub fn error_message(&self) -> Option<&str> {
+        match &self.body {
+            ResponseBody::Json(value) => value.get("error").map(|e| e.as_str().unwrap_or("Unknown")),
+            _ => None,
+        }
+    }
+}
+
+/// Per-isolate resource caps. `None` leaves the limit off.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RuntimeLimits {
+    pub max_heap_bytes: Option<usize>,
+    pub max_execution_ms: Option<u64>,
+}
+
+/// Requests the queue holds per worker when `queue_limit` is unset.
+pub const QUEUE_PER_WORKER: usize = 100;
+
+/// Bounds the scheduler queue. `None` leaves it unbounded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueuePolicy {
+    pub max_len: Option<usize>,
+    pub shed: ShedPolicy,
+    /// Commands each worker's channel holds before senders wait (default 100).
+    pub channel_capacity: Option<usize>,
+    pub adaptive: Option<AdaptiveQueue>,
+}
+
+/// The `queue_adaptive` block of titan.config: every `interval_ms` the p99
+/// queue wait of that window is compared with `target_p99_ms`. In "resize"
+/// mode, the queue limit shrinks by a tenth while the target is missed, so
+/// excess requests are shed instead of waiting out their deadline, and grows
+/// back toward `max` while requests are shed and the wait is under half the
+/// target. "warn" mode only logs when the target is missed.
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveQueue {
+    pub target_p99: Duration,
+    pub min: usize,
+    pub max: usize,
+    pub interval: Duration,
+    pub resize: bool,
+}
+
+impl AdaptiveQueue {
+    /// None when the block is absent or `false`. Resizing needs bounds:
+    /// `max` defaults to `queue_limit`, or 1000 per worker without one.
+    pub fn from_config(config: &serde_json::Value, queue_limit: Option<usize>, threads: usize) -> Result<Option<Self>, String> {
+        let options = match config {
+            serde_json::Value::Bool(true) => &serde_json::Value::Null,
+            serde_json::Value::Object(_) => config,
+            _ => return Ok(None),
+        };
+        let resize = match options["mode"].as_str().unwrap_or("resize") {
+            "resize" => true,
+            "warn" => false,
+            other => return Err(format!("queue_adaptive: unknown mode \"{}\"", other)),
+        };
+        let max = options["max"].as_u64().filter(|n| *n > 0).map(|n| n as usize).or(queue_limit).unwrap_or(threads * 1000);
+        let min = options["min"].as_u64().map_or(threads, |n| n as usize).clamp(1, max);
+        Ok(Some(Self {
+            target_p99: Duration::from_millis(options["target_p99_ms"].as_u64().filter(|ms| *ms > 0).unwrap_or(100)),
+            min,
+            max,
+            interval: Duration::from_millis(options["interval_ms"].as_u64().unwrap_or(1_000)).max(Duration::from_millis(100)),
+            resize,
+        }))
+    }
+}
+
+/// What happens to a request that arrives while the queue is full.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum ShedPolicy {
+    #[default]
+    Reject,
+    /// Wait this long for a queue slot before rejecting.
+    Block { timeout: Duration },
+}
+
+/// The `hedge` block of titan.config: a request for an idempotent action that
+/// has no result after `after_ms` (default 100) is sent to a second worker
+/// too, and whichever answers first wins; the other is terminated. Only
+/// `methods` (default GET and HEAD) are hedged, and only `actions` when it
+/// is given. Requests with a streamed body can't be sent twice and never are.
+#[derive(Debug, Clone)]
+pub struct HedgePolicy {
+    pub after: Duration,
+    methods: Vec<String>,
+    actions: Option<std::collections::HashSet<String>>,
+}
+
+impl HedgePolicy {
+    /// None when the block is absent or `false`.
+    pub fn from_config(config: &serde_json::Value) -> Option<Self> {
+        let options = match config {
+            serde_json::Value::Bool(true) => &serde_json::Value::Null,
+            serde_json::Value::Object(_) => config,
+            _ => return None,
+        };
+        let strings = |key: &str| options[key].as_array().map(|list| list.iter().filter_map(|v| v.as_str().map(str::to_string)).collect::<Vec<_>>());
+        Some(Self {
+            after: Duration::from_millis(options["after_ms"].as_u64().filter(|ms| *ms > 0).unwrap_or(100)),
+            methods: strings("methods")
+                .map(|list| list.into_iter().map(|m| m.to_ascii_uppercase()).collect())
+                .unwrap_or_else(|| vec!["GET".to_string(), "HEAD".to_string()]),
+            actions: strings("actions").map(|list| list.into_iter().collect()),
+        })
+    }
+
+    fn applies(&self, task: &RequestTask) -> bool {
+        self.methods.contains(&task.method)
+            && self.actions.as_ref().is_none_or(|actions| actions.contains(&task.action_name))
+            && task.body_stream.is_none()
+            && task.socket_id.is_none()
+    }
+}
+
+/// When a worker retires its isolate for a fresh one. Long-lived isolates
+/// accumulate garbage and whatever user code leaks into globals.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecyclePolicy {
+    pub max_requests: Option<u64>,
+    pub max_heap_bytes: Option<usize>,
+}
+
+impl RecyclePolicy {
+    fn is_due(&self, served: u64, isolate: &mut v8::OwnedIsolate) -> bool {
+        if self.max_requests.is_some_and(|max| served >= max) {
+            return true;
+        }
+        let Some(max) = self.max_heap_bytes else {
+            return false;
+        };
+        let mut stats = v8::HeapStatistics::default();
+        isolate.get_heap_statistics(&mut stats);
+        stats.used_heap_size() >= max
+    }
+}
+
+/// Why an action was interrupted by the heap callback or the watchdog.
+#[derive(Debug, Clone, Copy)]
+pub enum LimitExceeded {
+    Heap { max_bytes: usize },
+    ExecutionTime { max_ms: u64 },
+}
+
+impl LimitExceeded {
+    pub fn message(self) -> String {
+        match self {
+            LimitExceeded::Heap { max_bytes } => {
+                format!("Action exceeded the heap limit of {} MB", max_bytes / (1024 * 1024))
+            }
+            LimitExceeded::ExecutionTime { max_ms } => {
+                format!("Action exceeded the execution limit of {}ms", max_ms)
+            }
+        }
+    }
+
+    pub fn to_json(self) -> serde_json::Value {
+        match self {
+            LimitExceeded::Heap { max_bytes } => serde_json::json!({
+                "error": self.message(),
+                "limit": "heap",
+                "max_bytes": max_bytes,
+            }),
+            LimitExceeded::ExecutionTime { max_ms } => serde_json::json!({
+                "error": self.message(),
+                "limit": "execution_time",
+                "max_ms": max_ms,
+            }),
+        }
+    }
+}
+
+/// Tracks which request a worker is currently running JS for, so a caller whose
+/// deadline expired can interrupt that worker's isolate from another thread.
+pub struct WorkerMonitor {
+    isolate: Mutex<Option<v8::IsolateHandle>>,
+    running: AtomicU64, // Ticket of the executing request, 0 when idle
+    started_us: AtomicU64, // When the current execution began, relative to `epoch`
+    busy_us: AtomicU64,
+    executions: AtomicU64,
+    // Actions stopped for going over a limit
+    exceeded_total: AtomicU64,
+    // `started_us` of the last run reported as slow, so each is reported once
+    slow_reported: AtomicU64,
+    slow_total: AtomicU64,
+    // The isolate's heap after its last run, for the dashboard
+    heap_used: AtomicU64,
+    heap_total: AtomicU64,
+    epoch: Instant,
+    limits: RuntimeLimits,
+    heap_raised: AtomicBool,
+    exceeded: Arc<Mutex<Option<LimitExceeded>>>, // Shared with the worker's TitanRuntime
+    booted: AtomicBool, // Set once the first isolate has loaded the actions
+}
+
+impl WorkerMonitor {
+    fn new(limits: RuntimeLimits) -> Self {
+        Self {
+            isolate: Mutex::new(None),
+            running: AtomicU64::new(0),
+            started_us: AtomicU64::new(0),
+            busy_us: AtomicU64::new(0),
+            executions: AtomicU64::new(0),
+            exceeded_total: AtomicU64::new(0),
+            slow_reported: AtomicU64::new(u64::MAX),
+            slow_total: AtomicU64::new(0),
+            heap_used: AtomicU64::new(0),
+            heap_total: AtomicU64::new(0),
+            epoch: Instant::now(),
+            limits,
+            heap_raised: AtomicBool::new(false),
+            exceeded: Arc::new(Mutex::new(None)),
+            booted: AtomicBool::new(false),
+        }
+    }
+
+    fn begin(&self, ticket: u64) {
+        self.started_us.store(self.epoch.elapsed().as_micros() as u64, Ordering::SeqCst);
+        self.running.store(ticket, Ordering::SeqCst);
+    }
+
+    fn end(&self, isolate: &mut v8::OwnedIsolate) {
+        // Taking the lock orders us after any in-flight terminate_if_running call,
+        // whose termination request is then discarded before the next request.
+        let guard = self.isolate.lock().unwrap();
+        self.running.store(0, Ordering::SeqCst);
+        let elapsed = (self.epoch.elapsed().as_micros() as u64).saturating_sub(self.started_us.load(Ordering::SeqCst));
+        self.busy_us.fetch_add(elapsed, Ordering::Relaxed);
+        self.executions.fetch_add(1, Ordering::Relaxed);
+        isolate.cancel_terminate_execution();
+        *self.exceeded.lock().unwrap() = None;
+        drop(guard);
+
+        // The heap callback bumped the limit so the action could unwind; put it back
+        if self.heap_raised.swap(false, Ordering::SeqCst) {
+            if let Some(max) = self.limits.max_heap_bytes {
+                isolate.remove_near_heap_limit_callback(near_heap_limit, max);
+                isolate.add_near_heap_limit_callback(near_heap_limit, self as *const Self as *mut c_void);
+            }
+            isolate.low_memory_notification();
+        }
+        let mut stats = v8::HeapStatistics::default();
+        isolate.get_heap_statistics(&mut stats);
+        self.heap_used.store(stats.used_heap_size() as u64, Ordering::Relaxed);
+        self.heap_total.store(stats.total_heap_size() as u64, Ordering::Relaxed);
+    }
+
+    // Busy time including the run in progress, so a long run shows up before it ends
+    fn busy_so_far(&self) -> u64 {
+        let busy = self.busy_us.load(Ordering::Relaxed);
+        if self.running.load(Ordering::SeqCst) == 0 {
+            return busy;
+        }
+        busy + (self.epoch.elapsed().as_micros() as u64).saturating_sub(self.started_us.load(Ordering::SeqCst))
+    }
+
+    fn terminate(&self) {
+        if let Some(h) = self.isolate.lock().unwrap().as_ref() {
+            h.terminate_execution();
+        }
+    }
+
+    pub fn terminate_if_running(&self, ticket: u64) -> bool {
+        let guard = self.isolate.lock().unwrap();
+        if self.running.load(Ordering::SeqCst) != ticket {
+            return false;
+        }
+        guard.as_ref().map(|h| h.terminate_execution()).unwrap_or(false)
+    }
+
+    /// Same as `terminate_if_running`, but records the limit so the action's
+    /// error response can say why it was stopped.
+    fn exceed(&self, ticket: u64, reason: LimitExceeded) -> bool {
+        let guard = self.isolate.lock().unwrap();
+        if ticket == 0 || self.running.load(Ordering::SeqCst) != ticket {
+            return false;
+        }
+        *self.exceeded.lock().unwrap() = Some(reason);
+        self.exceeded_total.fetch_add(1, Ordering::Relaxed);
+        guard.as_ref().map(|h| h.terminate_execution()).unwrap_or(false)
+    }
+
+    fn check_execution_time(&self, max_ms: u64) {
+        let ticket = self.running.load(Ordering::SeqCst);
+        if ticket == 0 {
+            return;
+        }
+        let started = self.started_us.load(Ordering::SeqCst);
+        let now = self.epoch.elapsed().as_micros() as u64;
+        if now.saturating_sub(started) >= max_ms * 1000 {
+            self.exceed(ticket, LimitExceeded::ExecutionTime { max_ms });
+        }
+    }
+
+    // Asks the isolate where it is once the current run passes `threshold_ms`
+    fn check_slow(self: &Arc<Self>, threshold_ms: u64) {
+        let guard = self.isolate.lock().unwrap();
+        let ticket = self.running.load(Ordering::SeqCst);
+        let started = self.started_us.load(Ordering::SeqCst);
+        let elapsed_us = (self.epoch.elapsed().as_micros() as u64).saturating_sub(started);
+        if ticket == 0 || elapsed_us < threshold_ms * 1000 || self.slow_reported.swap(started, Ordering::SeqCst) == started {
+            return;
+        }
+        let Some(handle) = guard.as_ref() else {
+            return;
+        };
+        self.slow_total.fetch_add(1, Ordering::Relaxed);
+        let run = Box::into_raw(Box::new(SlowRun { monitor: self.clone(), ticket, elapsed_ms: elapsed_us / 1000 }));
+        if !handle.request_interrupt(report_slow, run as *mut c_void) {
+            drop(unsafe { Box::from_raw(run) });
+        }
+    }
+}
+
+// The run the watchdog wants a stack of
+struct SlowRun {
+    monitor: Arc<WorkerMonitor>,
+    ticket: u64,
+    elapsed_ms: u64,
+}
+
+/// Called by V8 on the worker thread, between two pieces of JS, after the
+/// watchdog saw a run go past `slow_requests`. Logs the stack with the
+/// request it belongs to.
+extern "C" fn report_slow(isolate: &mut v8::Isolate, data: *mut c_void) {
+    let run = unsafe { Box::from_raw(data as *mut SlowRun) };
+    // The run may have ended before the isolate got to the interrupt
+    if run.monitor.running.load(Ordering::SeqCst) != run.ticket {
+        return;
+    }
+    let runtime = unsafe { &*(isolate.get_data(0) as *const TitanRuntime) };
+    let request = runtime.active_requests.values().find(|r| r.ticket == run.ticket);
+    let action = request.map(|r| r.action_name.as_str());
+    let request_id = request.map(|r| r.correlation_id.as_str());
+    let scope = &mut v8::HandleScope::with_context(isolate, &runtime.context);
+    let stack = crate::slow::stack(scope);
+    tracing::warn!(worker = runtime.id, request_id, action, "Slow request: still running after {}ms\n{}", run.elapsed_ms, stack);
+}
+
+/// Called by V8 on the worker thread when the isolate nears `max_heap_bytes`.
+/// Terminates the running action and grants enough headroom for it to unwind
+/// instead of letting V8 abort the process.
+extern "C" fn near_heap_limit(data: *mut c_void, current_heap_limit: usize, _initial_heap_limit: usize) -> usize {
+    let monitor = unsafe { &*(data as *const WorkerMonitor) };
+    if let Some(max_bytes) = monitor.limits.max_heap_bytes {
+        let ticket = monitor.running.load(Ordering::SeqCst);
+        monitor.exceed(ticket, LimitExceeded::Heap { max_bytes });
+    }
+    monitor.heap_raised.store(true, Ordering::SeqCst);
+    current_heap_limit * 2
+}
+
+/// Counts a request as in flight until the caller gets its answer or gives up.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn enter(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Aborts the requests of a dispatch that ends without its result: dropped
+// with the HTTP handler when the client disconnects, or past the deadline.
+// Cancelling also skips the task if it is still queued and interrupts its JS
+// if it is running.
+struct AbortOnDrop<'a> {
+    pool: &'a WorkerPool,
+    tickets: [Option<u64>; 2],
+    token: Arc<AtomicBool>,
+    reason: Option<&'static str>,
+    cancel_on_disconnect: bool,
+}
+
+impl Drop for AbortOnDrop<'_> {
+    fn drop(&mut self) {
+        let Some(reason) = self.reason else {
+            return;
+        };
+        // Past the deadline the running JS was interrupted already
+        let cancel = reason == "disconnect" && self.cancel_on_disconnect;
+        if cancel || reason == "timeout" {
+            self.token.store(true, Ordering::Release);
+        }
+        if cancel {
+            self.pool.cancelled.fetch_add(1, Ordering::Relaxed);
+        }
+        for ticket in self.tickets.into_iter().flatten() {
+            if cancel {
+                for monitor in &self.pool.monitors {
+                    if monitor.terminate_if_running(ticket) {
+                        break;
+                    }
+                }
+            }
+            self.pool.abort(ticket, reason, cancel);
+        }
+    }
+}
+
+/// A WebSocket connection pinned to one worker. The JS handlers registered by
+/// the action live in that worker's isolate, so every frame goes back there.
+pub struct SocketSession {
+    pub socket_id: u32,
+    worker_tx: Sender<WorkerCommand>,
+}
+
+impl crate::websocket::Peer for SocketSession {
+    fn message(&self, message: WsMessage) {
+        let _ = self.worker_tx.send(WorkerCommand::SocketMessage { socket_id: self.socket_id, message });
+    }
+
+    fn close(&self) {
+        let _ = self.worker_tx.send(WorkerCommand::SocketClose { socket_id: self.socket_id });
+    }
+}
+
+// Worker slot states. Only a running slot takes new requests; a parking one
+// finishes what it holds and then stops its thread.
+const SLOT_STOPPED: u8 = 0;
+const SLOT_RUNNING: u8 = 1;
+const SLOT_PARKING: u8 = 2;
+
+/// Grows the worker pool while requests wait in the queue and parks workers
+/// again once the load is gone. Up and down use separate thresholds, and each
+/// one must hold for several ticks in a row, so a pool hovering around one
+/// threshold doesn't thrash.
+#[derive(Debug, Clone, Copy)]
+pub struct AutoscalePolicy {
+    pub min: usize,
+    pub max: usize,
+    /// Average queue wait over a tick that counts as pressure.
+    pub scale_up_wait: Duration,
+    /// An empty queue with waits at or below this counts as slack.
+    pub scale_down_wait: Duration,
+    pub interval: Duration,
+    /// Ticks of pressure in a row before a worker is added.
+    pub up_ticks: u32,
+    /// Ticks of slack in a row before a worker is parked.
+    pub down_ticks: u32,
+}
+
+impl AutoscalePolicy {
+    /// The `autoscale` block of titan.config, or None when it's absent or
+    /// `false`. `max` defaults to `threads` and `min` to the core count.
+    pub fn from_config(config: &serde_json::Value, threads: usize) -> Option<Self> {
+        let options = match config {
+            serde_json::Value::Bool(true) => &serde_json::Value::Null,
+            serde_json::Value::Object(_) => config,
+            _ => return None,
+        };
+        let ms = |key: &str, default: u64| Duration::from_millis(options[key].as_u64().unwrap_or(default));
+        let max = options["max"].as_u64().filter(|n| *n > 0).map_or(threads, |n| n as usize);
+        let min = options["min"].as_u64().map_or(num_cpus::get(), |n| n as usize).clamp(1, max);
+        let interval = ms("interval_ms", 500).max(Duration::from_millis(10));
+        let ticks = |after: Duration| (after.as_millis() / interval.as_millis()).max(1) as u32;
+        Some(Self {
+            min,
+            max,
+            scale_up_wait: ms("scale_up_wait_ms", 50),
+            scale_down_wait: ms("scale_down_wait_ms", 5),
+            interval,
+            up_ticks: ticks(ms("scale_up_after_ms", 1_000)),
+            down_ticks: ticks(ms("scale_down_after_ms", 30_000)),
+        })
+    }
+}
+
+/// Everything a worker thread is started from. There is a slot for every
+/// worker the pool may grow to; only running and parking slots have a thread.
+struct WorkerPool {
+    root: std::path::PathBuf,
+    tokio_handle: tokio::runtime::Handle,
+    async_tx: mpsc::Sender<AsyncOpRequest>,
+    stack_size: usize,
+    limits: RuntimeLimits,
+    recycle: RecyclePolicy,
+    scheduler: Arc<Scheduler>,
+    monitors: Vec<Arc<WorkerMonitor>>,
+    txs: Vec<Sender<WorkerCommand>>,
+    rxs: Vec<Receiver<WorkerCommand>>,
+    // Held by the slot's thread while it runs, so a restarted slot waits for
+    // the previous thread to let go of the deque
+    locals: Vec<Mutex<Worker<RequestTask>>>,
+    states: Vec<AtomicU8>,
+    threads: Mutex<Vec<(usize, thread::JoinHandle<()>)>>,
+    closed: AtomicBool,
+    scaled_up: AtomicU64,
+    scaled_down: AtomicU64,
+    restarts: AtomicU64,
+    // Requests given up because their client disconnected
+    cancelled: AtomicU64,
+    // Commands that found a worker's channel full and had to wait
+    channel_full: AtomicU64,
+}
+
+impl WorkerPool {
+    /// Starts a thread for slot `i`, which must have been claimed as running.
+    fn spawn(self: &Arc<Self>, i: usize) {
+        let pool = self.clone();
+        let handle = thread::Builder::new()
+            .name(format!("titan-worker-{}", i))
+            .stack_size(self.stack_size)
+            .spawn(move || {
+                crate::placement::pin(i);
+                let local = pool.locals[i].lock().unwrap_or_else(|e| e.into_inner());
+                let crashed = panic::catch_unwind(AssertUnwindSafe(|| run_worker(&pool, i, &local)));
+                drop(local);
+                if let Err(panic) = crashed {
+                    pool.recover(i, &*panic);
+                }
+            })
+            .expect("Failed to spawn worker");
+        self.threads.lock().unwrap().push((i, handle));
+    }
+
+    /// Brings slot `i` back after its worker panicked. The isolate went down
+    /// with it; the requests it held see their response channel close.
+    fn recover(self: &Arc<Self>, i: usize, panic: &(dyn Any + Send)) {
+        let monitor = &self.monitors[i];
+        monitor.isolate.clear_poison();
+        monitor.exceeded.clear_poison();
+        *monitor.isolate.lock().unwrap() = None;
+        *monitor.exceeded.lock().unwrap() = None;
+        monitor.running.store(0, Ordering::SeqCst);
+        self.scheduler.unpark(i);
+        self.restarts.fetch_add(1, Ordering::Relaxed);
+
+        let message = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        tracing::error!(worker = i, "Worker {} panicked: {}; starting a replacement", i, message);
+        // A worker that was parking anyway stays stopped
+        if self.states[i].swap(SLOT_STOPPED, Ordering::SeqCst) == SLOT_RUNNING {
+            self.start(i);
+        }
+    }
+
+    /// Starts slot `i` if it is stopped. Returns false if it wasn't.
+    fn start(self: &Arc<Self>, i: usize) -> bool {
+        if self.closed.load(Ordering::Acquire)
+            || self.states[i].compare_exchange(SLOT_STOPPED, SLOT_RUNNING, Ordering::SeqCst, Ordering::SeqCst).is_err()
+        {
+            return false;
+        }
+        self.spawn(i);
+        true
+    }
+
+    /// The next running slot in turn, for work pinned to one worker.
+    fn pick(&self, counter: &AtomicUsize) -> usize {
+        let n = self.states.len();
+        let start = counter.fetch_add(1, Ordering::Relaxed);
+        (0..n)
+            .map(|k| (start + k) % n)
+            .find(|&i| self.states[i].load(Ordering::SeqCst) == SLOT_RUNNING)
+            .unwrap_or(start % n)
+    }
+
+    /// Sends a command to slot `i` and restarts the slot if its thread stopped
+    /// before it could see the command.
+    fn send(self: &Arc<Self>, i: usize, cmd: WorkerCommand) -> Result<(), String> {
+        if self.txs[i].is_full() {
+            self.channel_full.fetch_add(1, Ordering::Relaxed);
+        }
+        self.txs[i].send(cmd).map_err(|e| e.to_string())?;
+        if self.states[i].load(Ordering::SeqCst) == SLOT_STOPPED {
+            self.start(i);
+        }
+        Ok(())
+    }
+
+    // Tells every running worker that `ticket` was given up on; only the
+    // one holding the request acts on it. A full channel loses the notice.
+    fn abort(&self, ticket: u64, reason: &'static str, cancel: bool) {
+        for (tx, state) in self.txs.iter().zip(&self.states) {
+            if state.load(Ordering::SeqCst) == SLOT_RUNNING {
+                let _ = tx.try_send(WorkerCommand::Abort { ticket, reason, cancel });
+            }
+        }
+    }
+
+    fn running(&self) -> usize {
+        self.states.iter().filter(|s| s.load(Ordering::SeqCst) == SLOT_RUNNING).count()
+    }
+}
+
+/// The body of a worker thread. Returns on shutdown, or once the autoscaler
+/// parked the slot and the worker finished everything it was holding.
+fn run_worker(pool: &Arc<WorkerPool>, i: usize, local: &Worker<RequestTask>) {
+    let monitor = &pool.monitors[i];
+    let rx = &pool.rxs[i];
+    let scheduler = &pool.scheduler;
+    let limits = pool.limits;
+
+    // Set once Shutdown arrives: keep serving in-flight work until
+    // it completes or the deadline passes.
+    let mut drain_deadline: Option<Instant> = None;
+
+    // Each pass runs one isolate generation; the worker only loops
+    // around when the recycle policy retires the current isolate.
+    'generations: loop {
+        // Start a thread with a pinned V8 isolate. 
+        // This thread will handle requests for this isolate exclusively.
+        let mut rt = extensions::init_runtime_worker(
+            i,
+            pool.root.clone(),
+            pool.txs[i].clone(), // The worker needs a way to send commands to ITSELF (for resumes)
+            pool.tokio_handle.clone(),
+            pool.async_tx.clone(),
+            pool.stack_size,
+            limits.max_heap_bytes,
+        );
+    
+        // Bind the runtime instance to the V8 isolate data slot
+        // This is CRITICAL because native drift calls use this pointer.
+        rt.bind_to_isolate();
+        *monitor.isolate.lock().unwrap() = Some(rt.isolate.thread_safe_handle());
+        monitor.booted.store(true, Ordering::SeqCst);
+        rt.limit_exceeded = monitor.exceeded.clone();
+        if limits.max_heap_bytes.is_some() {
+            // The pool holds the monitor Arc, so it outlives the isolate
+            let data = Arc::as_ptr(monitor) as *mut c_void;
+            rt.isolate.add_near_heap_limit_callback(near_heap_limit, data);
+        }
+
+        let mut served: u64 = 0;
+        // Set when the policy asks for a fresh isolate: new requests are
+        // left to the other workers while in-flight ones finish here.
+        let mut retiring = false;
+
+        loop {
+            // Commands pinned to this isolate (resumes, sockets) go first
+            match rx.try_recv() {
+                Ok(cmd) => {
+                    handle_command(cmd, &mut rt, monitor, &mut drain_deadline);
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => break 'generations,
+                Err(TryRecvError::Empty) => {}
+            }
+
+            if run_due_timer(&mut rt, monitor) {
+                continue;
+            }
+
+            let idle = rt.pending_requests.is_empty() && rt.streams.is_empty();
+            // Parking works like retiring, except the thread stops afterwards
+            let parking = pool.states[i].load(Ordering::SeqCst) == SLOT_PARKING;
+
+            // Sockets are pinned to the isolate, so they keep a parking worker alive
+            if parking && idle && rt.sockets.is_empty() && drain_deadline.is_none()
+                && pool.states[i].compare_exchange(SLOT_PARKING, SLOT_STOPPED, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+            {
+                // A command sent while we were stopping would wait for the next start
+                if !rx.is_empty()
+                    && pool.states[i].compare_exchange(SLOT_STOPPED, SLOT_RUNNING, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+                {
+                    continue;
+                }
+                scheduler.release(i);
+                if !scheduler.is_empty() {
+                    scheduler.wake_one();
+                }
+                *monitor.isolate.lock().unwrap() = None;
+                return;
+            }
+
+            if retiring && idle {
+                if drain_deadline.is_some() {
+                    break 'generations;
+                }
+                break;
+            }
+
+            if !retiring
+                && !parking
+                && let Some(task) = scheduler.next_task(i, local)
+            {
+                // Let a parked peer steal the rest of the batch we grabbed
+                if !local.is_empty() {
+                    scheduler.wake_one();
+                }
+                if let Some(timeline) = &task.timeline {
+                    timeline.mark(Mark::ExecStart);
+                }
+                handle_new_request(task, &mut rt, monitor);
+                served += 1;
+
+                // Sockets are pinned for their whole lifetime, so an isolate
+                // holding any can't be handed off
+                if drain_deadline.is_none() && rt.sockets.is_empty() && pool.recycle.is_due(served, &mut rt.isolate) {
+                    retiring = true;
+                    if !local.is_empty() {
+                        scheduler.wake_one();
+                    }
+                }
+                continue;
+            }
+
+            if drain_deadline.is_some() && idle {
+                break 'generations;
+            }
+
+            // Nothing runnable: block until a pinned command or a wake-up arrives
+            let parked = !retiring && !parking;
+            if parked && !scheduler.park(i) {
+                continue;
+            }
+            // ...or until the next timer is due
+            let wake_at = match (drain_deadline, extensions::next_timer(&rt)) {
+                (Some(drain), Some(timer)) => Some(drain.min(timer)),
+                (drain, timer) => drain.or(timer),
+            };
+            let next = match wake_at {
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                Some(at) => rx.recv_deadline(at),
+            };
+            if parked {
+                scheduler.unpark(i);
+            }
+
+            match next {
+                Ok(cmd) => handle_command(cmd, &mut rt, monitor, &mut drain_deadline),
+                Err(RecvTimeoutError::Timeout) if drain_deadline.is_none_or(|d| Instant::now() < d) => {}
+                // Channel closed or drain deadline reached
+                Err(_) => break 'generations,
+            }
+        }
+
+        // Drop the old isolate before booting its replacement (V8 requires
+        // isolates on a thread to be torn down in reverse creation order)
+        *monitor.isolate.lock().unwrap() = None;
+        crate::profiler::retire(rt.profiler.take());
+        drop(rt);
+        if i == 0 {
+            tracing::info!("Recycled worker isolate after {} requests", served);
+        }
+    }
+}
+
+/// Reaps finished worker threads and revives any slot whose thread died
+/// without recovering itself, e.g. by panicking again while unwinding.
+async fn supervise(pool: Arc<WorkerPool>) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        ticker.tick().await;
+        if pool.closed.load(Ordering::Acquire) {
+            return;
+        }
+        let finished: Vec<_> = {
+            let mut threads = pool.threads.lock().unwrap();
+            let (finished, alive) = std::mem::take(&mut *threads).into_iter().partition(|(_, t)| t.is_finished());
+            *threads = alive;
+            finished
+        };
+        for (i, thread) in finished {
+            if let Err(panic) = thread.join() {
+                pool.recover(i, &*panic);
+            }
+        }
+    }
+}
+
+/// Watches the average queue wait every tick and adds or parks one worker at
+/// a time between the policy's bounds.
+async fn autoscaler(pool: Arc<WorkerPool>, policy: AutoscalePolicy) {
+    let mut ticker = tokio::time::interval(policy.interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let (mut last_us, mut last_picked) = pool.scheduler.wait_totals();
+    let (mut hot, mut cold) = (0u32, 0u32);
+
+    loop {
+        ticker.tick().await;
+        if pool.closed.load(Ordering::Acquire) {
+            return;
+        }
+        let (wait_us, picked) = pool.scheduler.wait_totals();
+        let taken = picked - last_picked;
+        let average = (taken > 0).then(|| Duration::from_micros((wait_us - last_us) / taken));
+        (last_us, last_picked) = (wait_us, picked);
+        let queued = pool.scheduler.len();
+
+        // A queue nobody took anything from this tick is pressure too
+        let pressure = average.map_or(queued > 0, |wait| wait >= policy.scale_up_wait);
+        let slack = queued == 0 && average.is_none_or(|wait| wait <= policy.scale_down_wait);
+        hot = if pressure { hot + 1 } else { 0 };
+        cold = if slack { cold + 1 } else { 0 };
+
+        let running = pool.running();
+        if hot >= policy.up_ticks && running < policy.max {
+            // Take back a worker that is still parking before starting a new one
+            let resumed = pool.states.iter().any(|s| {
+                s.compare_exchange(SLOT_PARKING, SLOT_RUNNING, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+            });
+            if resumed || (0..pool.states.len()).any(|i| pool.start(i)) {
+                pool.scaled_up.fetch_add(1, Ordering::Relaxed);
+                tracing::info!("Scaled workers up to {} (queue wait {:?})", running + 1, average.unwrap_or_default());
+            }
+            (hot, cold) = (0, 0);
+        } else if cold >= policy.down_ticks && running > policy.min {
+            // The highest slot goes first, so the pool shrinks back toward the initial workers
+            let parked = (0..pool.states.len()).rev().find(|&i| {
+                pool.states[i].compare_exchange(SLOT_RUNNING, SLOT_PARKING, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+            });
+            if let Some(i) = parked {
+                pool.scaled_down.fetch_add(1, Ordering::Relaxed);
+                // An idle worker only notices once something wakes it
+                let _ = pool.txs[i].try_send(WorkerCommand::Wake);
+                tracing::info!("Scaled workers down to {}", running - 1);
+            }
+            (hot, cold) = (0, 0);
+        }
+    }
+}
+
+/// Measures the p99 queue wait of each window and, with `queue_adaptive`,
+/// acts on it.
+async fn queue_controller(
+    pool: Arc<WorkerPool>,
+    adaptive: Option<AdaptiveQueue>,
+    limit: Arc<AtomicUsize>,
+    shed: Arc<AtomicU64>,
+    p99_us: Arc<AtomicU64>,
+) {
+    let mut ticker = tokio::time::interval(adaptive.map_or(Duration::from_secs(1), |a| a.interval));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut last = pool.scheduler.wait_histogram();
+    let mut last_shed = shed.load(Ordering::Relaxed);
+    // Warnings repeat at most once a minute
+    let mut warned: Option<Instant> = None;
+
+    loop {
+        ticker.tick().await;
+        if pool.closed.load(Ordering::Acquire) {
+            return;
+        }
+        let now = pool.scheduler.wait_histogram();
+        let window: Vec<u64> = now.iter().zip(&last).map(|(n, l)| n - l).collect();
+        last = now;
+        let shed_now = shed.load(Ordering::Relaxed);
+        let shed_in_window = shed_now - last_shed;
+        last_shed = shed_now;
+
+        let total: u64 = window.iter().sum();
+        if total == 0 {
+            p99_us.store(0, Ordering::Relaxed);
+            continue;
+        }
+        // The bound of the bucket the 99th percentile falls in
+        let rank = (total * 99).div_ceil(100);
+        let mut seen = 0;
+        let bucket = window.iter().position(|count| {
+            seen += count;
+            seen >= rank
+        });
+        let bound = bucket.and_then(|i| WAIT_BUCKETS.get(i)).copied().unwrap_or(WAIT_BUCKETS[WAIT_BUCKETS.len() - 1]);
+        let p99 = Duration::from_secs_f64(bound);
+        p99_us.store(p99.as_micros() as u64, Ordering::Relaxed);
+
+        let Some(adaptive) = adaptive else {
+            continue;
+        };
+        let current = limit.load(Ordering::Relaxed);
+        if p99 > adaptive.target_p99 {
+            if adaptive.resize && current > adaptive.min {
+                let next = (current - current / 10).max(adaptive.min);
+                limit.store(next, Ordering::Relaxed);
+                tracing::info!("Queue p99 wait {:?} is over {:?}; queue limit {} -> {}", p99, adaptive.target_p99, current, next);
+            } else if warned.is_none_or(|at| at.elapsed() >= Duration::from_secs(60)) {
+                warned = Some(Instant::now());
+                tracing::warn!(
+                    "Queue p99 wait {:?} is over the {:?} target with {} worker(s); consider more threads or autoscale",
+                    p99,
+                    adaptive.target_p99,
+                    pool.running()
+                );
+            }
+        } else if adaptive.resize && shed_in_window > 0 && p99 * 2 < adaptive.target_p99 && current < adaptive.max {
+            let next = (current + (current / 10).max(1)).min(adaptive.max);
+            limit.store(next, Ordering::Relaxed);
+            tracing::info!("Queue p99 wait {:?} leaves room; queue limit {} -> {}", p99, current, next);
+        }
+    }
+}
+
 impl RuntimeManager {
-    pub fn new(project_root: std::path::PathBuf, num_threads: usize, stack_size: usize) -> Self {
+    /// Starts `num_threads` workers, or `autoscale.min` of them with room to
+    /// grow to `autoscale.max`.
+    pub fn new(
+        project_root: std::path::PathBuf,
+        num_threads: usize,
+        stack_size: usize,
+        limits: RuntimeLimits,
+        recycle: RecyclePolicy,
+        queue: QueuePolicy,
+        autoscale: Option<AutoscalePolicy>,
+    ) -> Self {
         let (async_tx, mut async_rx) = mpsc::channel::<AsyncOpRequest>(1000);
         
         let tokio_handle = tokio::runtime::Handle::current();
@@ -64,106 +1185,915 @@ impl RuntimeManager {
             }
         });
 
-        let mut worker_txs = Vec::new();
-        let mut workers = Vec::new();
-
-        // Pass 1: Create channels
-        for _ in 0..num_threads {
-            let (tx, rx) = bounded(100); 
-            worker_txs.push((tx, rx));
-        }
-
-        let mut final_txs = Vec::new();
-        for (tx, _) in &worker_txs {
-            final_txs.push(tx.clone());
-        }
-
-        // Pass 2: Spawn Workers
-        for (i, (tx, rx)) in worker_txs.into_iter().enumerate() {
-            let my_tx = tx.clone(); // The worker needs a way to send commands to ITSELF (for resumes)
-            let root = project_root.clone();
-            let handle = tokio_handle.clone();
-            let async_tx = async_tx.clone();
-            
-            let handle = thread::Builder::new()
-                .name(format!("titan-worker-{}", i))
-                .stack_size(stack_size)
-                .spawn(move || {
-                    // Start a thread with a pinned V8 isolate. 
-                    // This thread will handle requests for this isolate exclusively.
-                    let mut rt = extensions::init_runtime_worker(
-                        i,
-                        root,
-                        my_tx, 
-                        handle,
-                        async_tx,
-                        stack_size 
-                    );
-                    
-                    // Bind the runtime instance to the V8 isolate data slot
-                    // This is CRITICAL because native drift calls use this pointer.
-                    rt.bind_to_isolate();
-
-                    loop {
-                        match rx.recv() {
-                            Ok(cmd) => {
-                                match cmd {
-                                    WorkerCommand::Request(task) => {
-                                         handle_new_request(task, &mut rt);
-                                     },
-                                    WorkerCommand::Resume { drift_id, result } => {
-                                         handle_resume(drift_id, result, &mut rt);
-                                     }
-                                }
-                            }
-                            Err(_) => break, // Channel closed
+        let (slots, initial) = match autoscale {
+            Some(policy) => (policy.max, policy.min),
+            None => (num_threads, num_threads),
+        };
+        let monitors: Vec<Arc<WorkerMonitor>> = (0..slots).map(|_| Arc::new(WorkerMonitor::new(limits))).collect();
+
+        // Watchdog: interrupts actions that keep a worker busy past the limit,
+        // and asks slow ones for their stack
+        let slow_ms = crate::slow::threshold_ms();
+        if let Some(shortest) = [limits.max_execution_ms, slow_ms].into_iter().flatten().min() {
+            let monitors = monitors.clone();
+            let tick = Duration::from_millis((shortest / 4).clamp(1, 50));
+            thread::Builder::new()
+                .name("titan-watchdog".to_string())
+                .spawn(move || loop {
+                    thread::sleep(tick);
+                    for monitor in &monitors {
+                        if let Some(max_ms) = limits.max_execution_ms {
+                            monitor.check_execution_time(max_ms);
+                        }
+                        if let Some(threshold_ms) = slow_ms {
+                            monitor.check_slow(threshold_ms);
                         }
                     }
                 })
-                .expect("Failed to spawn worker");
+                .expect("Failed to spawn watchdog");
+        }
+
+        // Channels and local deques exist for every slot, running or not
+        let capacity = queue.channel_capacity.filter(|n| *n > 0).unwrap_or(100);
+        let (final_txs, rxs): (Vec<_>, Vec<_>) = (0..slots).map(|_| bounded(capacity)).unzip();
+        let locals: Vec<Worker<RequestTask>> = (0..slots).map(|_| Worker::new_fifo()).collect();
+        let scheduler = Arc::new(Scheduler::new(&locals, final_txs.clone()));
 
-            workers.push(handle);
+        let pool = Arc::new(WorkerPool {
+            root: project_root,
+            tokio_handle: tokio_handle.clone(),
+            async_tx,
+            stack_size,
+            limits,
+            recycle,
+            scheduler: scheduler.clone(),
+            monitors: monitors.clone(),
+            txs: final_txs.clone(),
+            rxs,
+            locals: locals.into_iter().map(Mutex::new).collect(),
+            states: (0..slots).map(|_| AtomicU8::new(SLOT_STOPPED)).collect(),
+            threads: Mutex::new(Vec::new()),
+            closed: AtomicBool::new(false),
+            scaled_up: AtomicU64::new(0),
+            scaled_down: AtomicU64::new(0),
+            restarts: AtomicU64::new(0),
+            cancelled: AtomicU64::new(0),
+            channel_full: AtomicU64::new(0),
+        });
+        for i in 0..initial {
+            pool.start(i);
+        }
+        tokio_handle.spawn(supervise(pool.clone()));
+        if let Some(policy) = autoscale {
+            tokio_handle.spawn(autoscaler(pool.clone(), policy));
         }
+        let queue_limit = Arc::new(AtomicUsize::new(queue.max_len.or(queue.adaptive.filter(|a| a.resize).map(|a| a.max)).unwrap_or(0)));
+        let shed = Arc::new(AtomicU64::new(0));
+        let wait_p99_us = Arc::new(AtomicU64::new(0));
+        tokio_handle.spawn(queue_controller(
+            pool.clone(),
+            queue.adaptive,
+            queue_limit.clone(),
+            shed.clone(),
+            wait_p99_us.clone(),
+        ));
 
         Self {
-            request_txs: final_txs.clone(),
+            scheduler,
+            metrics: Metrics::default(),
+            in_flight: AtomicUsize::new(0),
+            queue,
+            queue_limit,
+            shed,
+            wait_p99_us,
+            interceptors: Vec::new(),
+            priorities: Default::default(),
+            affinity: None,
+            tenants: None,
+            concurrency: std::sync::OnceLock::new(),
+            hedge: None,
+            hedged: AtomicU64::new(0),
+            hedge_wins: AtomicU64::new(0),
+            cancel_on_disconnect: true,
             round_robin_counter: AtomicUsize::new(0),
+            socket_counter: AtomicU32::new(1),
+            ticket_counter: Arc::new(AtomicU64::new(1)),
+            monitors,
+            initial,
+            accepting: AtomicBool::new(true),
+            draining: AtomicBool::new(false),
             _resume_txs: final_txs,
-            _workers: workers,
+            pool,
         }
     
 }
 
-    pub async fn execute(
-        &self, 
-        action: String, 
-        method: String, 
-        path: String, 
+    // The queue bound right now
+    fn max_len(&self) -> Option<usize> {
+        Some(self.queue_limit.load(Ordering::Relaxed)).filter(|n| *n > 0)
+    }
+
+    /// Adds a hook that sees every request before it is queued. Hooks run in
+    /// the order they were added.
+    pub fn intercept(&mut self, interceptor: Interceptor) {
+        self.interceptors.push(interceptor);
+    }
+
+    /// Queues requests for `action` in `priority`'s lane instead of the
+    /// normal one.
+    pub fn prioritize(&mut self, action: String, priority: Priority) {
+        self.priorities.insert(action, priority);
+    }
+
+    /// Sends slow idempotent requests to a second worker per `hedge`.
+    pub fn set_hedging(&mut self, hedge: HedgePolicy) {
+        self.hedge = Some(hedge);
+    }
+
+    /// With `false`, a request whose client disconnects runs to the end and
+    /// only sees `ctx.signal` fire.
+    pub fn set_cancel_on_disconnect(&mut self, cancel: bool) {
+        self.cancel_on_disconnect = cancel;
+    }
+
+    // A second copy of `task` with its own ticket and result channel, when
+    // the hedging policy covers it
+    fn hedge_copy(&self, task: &RequestTask) -> Option<(RequestTask, ResponseReceiver)> {
+        if !self.hedge.as_ref()?.applies(task) {
+            return None;
+        }
+        let (mut copy, rx) = self.task(task.action_name.clone(), task.method.clone(), task.path.clone());
+        copy.body = task.body.clone();
+        copy.headers = task.headers.clone();
+        copy.params = task.params.clone();
+        copy.query = task.query.clone();
+        copy.correlation_id = task.correlation_id.clone();
+        copy.trace = task.trace.clone();
+        copy.form = task.form.clone();
+        copy.remote_addr = task.remote_addr;
+        copy.auth = task.auth.clone();
+        copy.session = task.session.clone();
+        copy.geo = task.geo.clone();
+        copy.middleware = task.middleware.clone();
+        copy.error = task.error.clone();
+        copy.cancelled = task.cancelled.clone();
+        copy.timeline = task.timeline.clone();
+        copy.deadline = task.deadline;
+        copy.priority = task.priority;
+        Some((copy, rx))
+    }
+
+    // Interrupts `ticket` wherever it is running
+    fn terminate(&self, ticket: u64) {
+        for monitor in &self.monitors {
+            if monitor.terminate_if_running(ticket) {
+                break;
+            }
+        }
+    }
+
+    // The task's result. With a hedge copy, the copy is queued once the hedge
+    // delay passes without a result, unless requests are already waiting for
+    // a worker, and the first of the two to answer is kept.
+    async fn first_result(&self, mut rx: ResponseReceiver, ticket: u64, hedge: Option<(RequestTask, ResponseReceiver)>) -> Result<WorkerResult, TitanError> {
+        let (Some(policy), Some((copy, mut hedge_rx))) = (self.hedge.as_ref(), hedge) else {
+            return rx.await.unwrap_or(Err(TitanError::WorkerCrashed));
+        };
+        tokio::select! {
+            res = &mut rx => return res.unwrap_or(Err(TitanError::WorkerCrashed)),
+            _ = tokio::time::sleep(policy.after) => {}
+        }
+        // A busy pool would only queue the copy behind other requests
+        if !self.scheduler.is_empty() {
+            return rx.await.unwrap_or(Err(TitanError::WorkerCrashed));
+        }
+        let copy_ticket = copy.ticket;
+        // Shared by both, so whichever is still queued once the other answers
+        // is dropped instead of run
+        let settled = copy.cancelled.clone();
+        self.hedged.fetch_add(1, Ordering::Relaxed);
+        self.scheduler.submit(copy, None);
+
+        // A copy whose worker crashed leaves the result to the other one
+        tokio::select! {
+            res = &mut rx => match res {
+                Ok(result) => {
+                    settled.store(true, Ordering::Release);
+                    self.terminate(copy_ticket);
+                    result
+                }
+                Err(_) => hedge_rx.await.unwrap_or(Err(TitanError::WorkerCrashed)),
+            },
+            res = &mut hedge_rx => match res {
+                Ok(result) => {
+                    self.hedge_wins.fetch_add(1, Ordering::Relaxed);
+                    settled.store(true, Ordering::Release);
+                    self.terminate(ticket);
+                    result
+                }
+                Err(_) => rx.await.unwrap_or(Err(TitanError::WorkerCrashed)),
+            },
+        }
+    }
+
+    /// Pins requests for the actions in `affinity` to one worker per key.
+    pub fn set_affinity(&mut self, affinity: crate::affinity::Affinity) {
+        self.affinity = Some(affinity);
+    }
+
+    /// Serves the tenants `config` names with the pools in `pools`, one per
+    /// entry of `config.pools`, instead of these workers. Interceptors run
+    /// here before a request is handed over.
+    pub fn set_tenants(&mut self, config: crate::tenants::TenantConfig, pools: Vec<(String, RuntimeManager)>) {
+        let pools = pools
+            .into_iter()
+            .map(|(name, mut pool)| {
+                pool.ticket_counter = self.ticket_counter.clone();
+                (name, Arc::new(pool))
+            })
+            .collect();
+        self.tenants = Some(crate::tenants::Tenants::new(config, pools));
+    }
+
+    /// Caps the actions whose `config` sets `concurrency` at that many
+    /// requests at once, tenant pools included. Requests over the cap wait
+    /// for a slot before they are queued. Returns how many actions are capped.
+    pub fn limit_concurrency(&self, configs: &std::collections::HashMap<String, serde_json::Value>) -> usize {
+        let limits: std::collections::HashMap<_, _> = configs
+            .iter()
+            .filter_map(|(action, config)| {
+                let slots = config["concurrency"].as_u64().filter(|n| *n > 0)?;
+                Some((action.clone(), Arc::new(tokio::sync::Semaphore::new(slots as usize))))
+            })
+            .collect();
+        let capped = limits.len();
+        let _ = self.concurrency.set(limits);
+        capped
+    }
+
+    /// The first interceptor to short-circuit decides the response.
+    fn run_interceptors(&self, task: &mut RequestTask) -> Option<Box<WorkerResult>> {
+        self.interceptors.iter().find_map(|interceptor| match interceptor(task) {
+            Decision::Continue => None,
+            Decision::Respond(result) => Some(result),
+        })
+    }
+
+    /// A task for `action` with only its method and path filled in, and the
+    /// receiver its result comes back on.
+    pub fn task(&self, action: String, method: String, path: String) -> (RequestTask, ResponseReceiver) {
+        let priority = self.priorities.get(&action).copied().unwrap_or_default();
+        RequestTask::new(action, method, path, self.ticket_counter.fetch_add(1, Ordering::Relaxed), priority)
+    }
+
+    /// Runs the interceptors on a task without queueing it, for callers that
+    /// admit one request and then `dispatch` several tasks for it.
+    pub fn admit(&self, task: &mut RequestTask) -> Option<Box<WorkerResult>> {
+        self.run_interceptors(task)
+    }
+
+    /// Runs the interceptors on a task from `task`, then queues it for the
+    /// worker pool and waits for its result. When the queue is full the
+    /// request is shed per the queue policy.
+    pub async fn try_execute(
+        &self,
+        mut task: RequestTask,
+        rx: ResponseReceiver,
+        deadline: Option<Duration>,
+    ) -> Result<WorkerResult, TitanError> {
+        if !self.accepting.load(Ordering::Acquire) {
+            return Err(TitanError::ShuttingDown);
+        }
+        if let Some(result) = self.run_interceptors(&mut task) {
+            return Ok(*result);
+        }
+        self.dispatch(task, rx, deadline).await
+    }
+
+    /// Queues a task that was already admitted and waits for its result,
+    /// shedding it per the queue policy when the queue is full.
+    pub async fn dispatch(
+        &self,
+        task: RequestTask,
+        rx: ResponseReceiver,
+        deadline: Option<Duration>,
+    ) -> Result<WorkerResult, TitanError> {
+        if !self.accepting.load(Ordering::Acquire) {
+            return Err(TitanError::ShuttingDown);
+        }
+        // A capped action waits for a slot, which counts toward its deadline
+        let waited = Instant::now();
+        let slot = match self.concurrency.get().and_then(|limits| limits.get(&task.action_name)).cloned() {
+            None => None,
+            Some(slots) => match deadline {
+                None => slots.acquire_owned().await.ok(),
+                Some(deadline) => match tokio::time::timeout(deadline, slots.acquire_owned()).await {
+                    Ok(slot) => slot.ok(),
+                    Err(_) => return Err(TitanError::Timeout { ms: deadline.as_millis() as u64 }),
+                },
+            },
+        };
+        let deadline = deadline.map(|d| d.saturating_sub(waited.elapsed()));
+        if let Some(pool) = self.tenants.as_ref().and_then(|t| t.pool(&task)) {
+            return pool.dispatch_within(task, rx, deadline, slot.is_some()).await;
+        }
+        self.dispatch_within(task, rx, deadline, slot.is_some()).await
+    }
+
+    // `dispatch` once the task holds its concurrency slot, if it needs one.
+    // A capped action must not run twice at once, so it is never hedged.
+    async fn dispatch_within(
+        &self,
+        mut task: RequestTask,
+        rx: ResponseReceiver,
+        deadline: Option<Duration>,
+        capped: bool,
+    ) -> Result<WorkerResult, TitanError> {
+        let ticket = task.ticket;
+        let action_name = task.action_name.clone();
+        let request_id = task.correlation_id.clone();
+        let response_headers = std::mem::take(&mut task.response_headers);
+        task.deadline = deadline.map(|d| SystemTime::now() + d);
+        let token = task.cancelled.clone();
+        task.timeline = timing::current();
+        timing::mark(Mark::Enqueued);
+
+        // Any free worker picks it up (work stealing), unless it is pinned
+        let worker = self.affinity.as_ref().and_then(|a| a.key(&task)).and_then(|key| {
+            let running = (0..self.pool.states.len()).filter(|&i| self.pool.states[i].load(Ordering::SeqCst) == SLOT_RUNNING);
+            crate::affinity::pick(key, running)
+        });
+        // Pinned requests stay on their worker, so they are never hedged
+        let hedge = if worker.is_none() && !capped { self.hedge_copy(&task) } else { None };
+        let hedge_ticket = hedge.as_ref().map(|(copy, _)| copy.ticket);
+        let queued = match (self.max_len(), self.queue.shed) {
+            (None, _) => {
+                self.scheduler.submit(task, worker);
+                true
+            }
+            (Some(max), ShedPolicy::Reject) => self.scheduler.try_submit(task, worker, max),
+            (Some(max), ShedPolicy::Block { timeout }) => self.scheduler.submit_within(task, worker, max, timeout).await,
+        };
+        // The worker may have stopped since it was picked
+        if let Some(i) = worker
+            && self.pool.states[i].load(Ordering::SeqCst) != SLOT_RUNNING
+        {
+            self.scheduler.release(i);
+        }
+        if !queued {
+            self.shed.fetch_add(1, Ordering::Relaxed);
+            return Err(TitanError::QueueFull);
+        }
+
+        let started = Instant::now();
+        let _in_flight = InFlight::enter(&self.in_flight);
+        let mut abort = AbortOnDrop {
+            pool: &self.pool,
+            tickets: [Some(ticket), hedge_ticket],
+            token,
+            reason: Some("disconnect"),
+            cancel_on_disconnect: self.cancel_on_disconnect,
+        };
+        let result = match deadline {
+            None => self.first_result(rx, ticket, hedge).await,
+            Some(deadline) => match tokio::time::timeout(deadline, self.first_result(rx, ticket, hedge)).await {
+                Ok(res) => res,
+                Err(_) => {
+                    // Only interrupt the isolate if it is still stuck in this request's JS;
+                    // a request suspended in drift() leaves the worker free already.
+                    self.terminate(ticket);
+                    if let Some(copy) = hedge_ticket {
+                        self.terminate(copy);
+                    }
+                    Err(TitanError::Timeout { ms: deadline.as_millis() as u64 })
+                }
+            },
+        };
+        abort.reason = matches!(result, Err(TitanError::Timeout { .. })).then_some("timeout");
+        drop(abort);
+        if let Some(timeline) = timing::current() {
+            timeline.mark(Mark::ExecEnd);
+            let drift_ms: f64 = result.iter().flat_map(|r| &r.timings).filter(|(n, _)| n == "drift" || n == "drift_error").map(|(_, d)| d).sum();
+            timeline.add_drift(Duration::from_secs_f64(drift_ms / 1000.0));
+        }
+
+        let failed = result.as_ref().map_or(true, |r| r.error_message().is_some());
+        self.metrics.record(&action_name, started.elapsed(), failed);
+        match &result {
+            Err(e) => self.metrics.record_error(&action_name, &request_id, &e.to_string(), e.stack()),
+            Ok(r) => {
+                if let Some(message) = r.error_message() {
+                    self.metrics.record_error(&action_name, &request_id, message, None);
+                }
+            }
+        }
+        result.map(|mut result| {
+            result.headers.extend(response_headers);
+            result
+        })
+    }
+
+    /// Runs an action for a scheduled job or background task on the next
+    /// worker in turn. There is no client, so the queue and interceptors are
+    /// skipped; `method` (JOB, TASK) tells the JS side what kind of run it is.
+    pub async fn run_background(
+        &self,
+        action: String,
+        method: &str,
         body: Option<Bytes>,
         headers: SmallVec<[(String, String); 8]>,
-        params: SmallVec<[(String, String); 4]>,
-        query: SmallVec<[(String, String); 4]>,
-    ) -> Result<(serde_json::Value, Vec<(String, f64)>), String> {
-        let (tx, rx) = oneshot::channel();
-        let task = RequestTask {
-            action_name: action,
-            body,
-            method,
-            path,
-            headers,
-            params,
-            query,
-            response_tx: tx,
+        deadline: Option<Duration>,
+    ) -> WorkerResult {
+        if !self.accepting.load(Ordering::Acquire) {
+            return TitanError::ShuttingDown.to_result(true);
+        }
+
+        let ticket = self.ticket_counter.fetch_add(1, Ordering::Relaxed);
+        let (mut task, rx) = RequestTask::new(action.clone(), method.to_string(), format!("/{}", action), ticket, Priority::Normal);
+        task.body = body;
+        task.headers = headers;
+        task.correlation_id = telemetry::new_request_id();
+
+        let idx = self.pool.pick(&self.round_robin_counter);
+        if let Err(e) = self.pool.send(idx, WorkerCommand::Background { task: Box::new(task) }) {
+            return TitanError::Dispatch(e).to_result(true);
+        }
+
+        let started = Instant::now();
+        let _in_flight = InFlight::enter(&self.in_flight);
+        let result = match deadline {
+            None => rx.await.unwrap_or(Err(TitanError::WorkerCrashed)),
+            Some(deadline) => match tokio::time::timeout(deadline, rx).await {
+                Ok(res) => res.unwrap_or(Err(TitanError::WorkerCrashed)),
+                Err(_) => {
+                    for monitor in &self.monitors {
+                        if monitor.terminate_if_running(ticket) {
+                            break;
+                        }
+                    }
+                    Err(TitanError::Timeout { ms: deadline.as_millis() as u64 })
+                }
+            },
         };
-        
-        // Round Robin Distribution
-        let idx = self.round_robin_counter.fetch_add(1, Ordering::Relaxed) % self.request_txs.len();
-        self.request_txs[idx].send(WorkerCommand::Request(task)).map_err(|e| e.to_string())?;
-        
-        match rx.await {
-            Ok(res) => Ok((res.json, res.timings)),
-            Err(_) => Err("Worker channel closed".to_string()),
+        // No client to tell apart error kinds, so they become error results
+        let result = result.unwrap_or_else(|e| e.to_result(true));
+        self.metrics.record(&action, started.elapsed(), result.error_message().is_some());
+        result
+    }
+
+    /// Runs the action of an upgraded WebSocket request and pins the connection
+    /// to the worker that ran it. `task.response_tx` receives the action result.
+    /// An error is the response to send instead of upgrading.
+    pub fn open_socket(
+        &self,
+        mut task: RequestTask,
+        outbound: mpsc::UnboundedSender<WsMessage>,
+    ) -> Result<SocketSession, Box<WorkerResult>> {
+        if !self.accepting.load(Ordering::Acquire) {
+            return Err(Box::new(WorkerResult::error(503, "Server is shutting down")));
         }
+        if let Some(result) = self.run_interceptors(&mut task) {
+            return Err(result);
+        }
+        if let Some(pool) = self.tenants.as_ref().and_then(|t| t.pool(&task)) {
+            return pool.open_socket_admitted(task, outbound);
+        }
+        self.open_socket_admitted(task, outbound)
+    }
+
+    fn open_socket_admitted(
+        &self,
+        mut task: RequestTask,
+        outbound: mpsc::UnboundedSender<WsMessage>,
+    ) -> Result<SocketSession, Box<WorkerResult>> {
+        let socket_id = self.socket_counter.fetch_add(1, Ordering::Relaxed);
+        task.socket_id = Some(socket_id);
+        task.ticket = self.ticket_counter.fetch_add(1, Ordering::Relaxed);
+
+        let idx = self.pool.pick(&self.round_robin_counter);
+        self.pool
+            .send(idx, WorkerCommand::SocketOpen { socket_id, task: Box::new(task), outbound })
+            .map_err(|e| Box::new(WorkerResult::error(500, e)))?;
+
+        Ok(SocketSession { socket_id, worker_tx: self.pool.txs[idx].clone() })
+    }
+
+    /// Prometheus text exposition of the pool and per-action metrics.
+    pub fn render_metrics(&self) -> String {
+        let mut out = String::new();
+
+        metrics::header(&mut out, "titan_queue_depth", "gauge", "Requests waiting for a free worker.");
+        let _ = writeln!(out, "titan_queue_depth {}", self.scheduler.len());
+
+        metrics::header(&mut out, "titan_queue_lane_depth", "gauge", "Requests waiting in each priority lane.");
+        for lane in [Priority::High, Priority::Normal, Priority::Low] {
+            let _ = writeln!(out, "titan_queue_lane_depth{{lane=\"{}\"}} {}", lane.as_str(), self.scheduler.lane_len(lane));
+        }
+
+        metrics::header(&mut out, "titan_requests_shed_total", "counter", "Requests rejected because the queue was full.");
+        let _ = writeln!(out, "titan_requests_shed_total {}", self.shed.load(Ordering::Relaxed));
+
+        metrics::header(&mut out, "titan_requests_in_flight", "gauge", "Requests dispatched and not yet answered.");
+        let _ = writeln!(out, "titan_requests_in_flight {}", self.in_flight.load(Ordering::Relaxed));
+
+        metrics::header(&mut out, "titan_workers_active", "gauge", "Workers taking new requests.");
+        let _ = writeln!(out, "titan_workers_active {}", self.pool.running());
+
+        metrics::header(&mut out, "titan_worker_restarts_total", "counter", "Workers replaced after a panic.");
+        let _ = writeln!(out, "titan_worker_restarts_total {}", self.pool.restarts.load(Ordering::Relaxed));
+
+        metrics::header(&mut out, "titan_requests_cancelled_total", "counter", "Requests stopped because their client disconnected.");
+        let _ = writeln!(out, "titan_requests_cancelled_total {}", self.pool.cancelled.load(Ordering::Relaxed));
+
+        metrics::header(&mut out, "titan_worker_scale_events_total", "counter", "Workers added or parked by the autoscaler.");
+        let _ = writeln!(out, "titan_worker_scale_events_total{{direction=\"up\"}} {}", self.pool.scaled_up.load(Ordering::Relaxed));
+        let _ = writeln!(out, "titan_worker_scale_events_total{{direction=\"down\"}} {}", self.pool.scaled_down.load(Ordering::Relaxed));
+
+        let (wait_us, picked) = self.scheduler.wait_totals();
+        metrics::header(&mut out, "titan_queue_wait_seconds", "histogram", "Time requests waited in the queue for a worker.");
+        let mut cumulative = 0;
+        for (le, count) in WAIT_BUCKETS.iter().zip(self.scheduler.wait_histogram()) {
+            cumulative += count;
+            let _ = writeln!(out, "titan_queue_wait_seconds_bucket{{le=\"{}\"}} {}", le, cumulative);
+        }
+        let _ = writeln!(out, "titan_queue_wait_seconds_bucket{{le=\"+Inf\"}} {}", picked);
+        let _ = writeln!(out, "titan_queue_wait_seconds_sum {}", wait_us as f64 / 1_000_000.0);
+        let _ = writeln!(out, "titan_queue_wait_seconds_count {}", picked);
+
+        metrics::header(&mut out, "titan_queue_wait_p99_seconds", "gauge", "p99 queue wait over the last window.");
+        let _ = writeln!(out, "titan_queue_wait_p99_seconds {}", self.wait_p99_us.load(Ordering::Relaxed) as f64 / 1_000_000.0);
+
+        metrics::header(&mut out, "titan_queue_limit", "gauge", "Requests the queue holds before shedding, 0 for unbounded.");
+        let _ = writeln!(out, "titan_queue_limit {}", self.queue_limit.load(Ordering::Relaxed));
+
+        metrics::header(&mut out, "titan_worker_commands_pending", "gauge", "Commands waiting in each worker's own channel.");
+        for (i, tx) in self.pool.txs.iter().enumerate() {
+            let _ = writeln!(out, "titan_worker_commands_pending{{worker=\"{}\"}} {}", i, tx.len());
+        }
+
+        metrics::header(&mut out, "titan_hedged_requests_total", "counter", "Requests sent to a second worker by the hedging policy.");
+        let _ = writeln!(out, "titan_hedged_requests_total {}", self.hedged.load(Ordering::Relaxed));
+
+        metrics::header(&mut out, "titan_hedge_wins_total", "counter", "Hedged requests the second worker answered first.");
+        let _ = writeln!(out, "titan_hedge_wins_total {}", self.hedge_wins.load(Ordering::Relaxed));
+
+        metrics::header(&mut out, "titan_worker_channel_full_total", "counter", "Commands that waited for room in a worker's full channel.");
+        let _ = writeln!(out, "titan_worker_channel_full_total {}", self.pool.channel_full.load(Ordering::Relaxed));
+
+        metrics::header(&mut out, "titan_worker_busy_seconds_total", "counter", "Time each worker spent running JS.");
+        for (i, monitor) in self.monitors.iter().enumerate() {
+            let busy = monitor.busy_us.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+            let _ = writeln!(out, "titan_worker_busy_seconds_total{{worker=\"{}\"}} {}", i, busy);
+        }
+
+        metrics::header(&mut out, "titan_worker_executions_total", "counter", "Action executions per worker, drift replays included.");
+        for (i, monitor) in self.monitors.iter().enumerate() {
+            let runs = monitor.executions.load(Ordering::Relaxed);
+            let _ = writeln!(out, "titan_worker_executions_total{{worker=\"{}\"}} {}", i, runs);
+        }
+
+        if let Some(limits) = self.concurrency.get().filter(|l| !l.is_empty()) {
+            let mut actions: Vec<_> = limits.iter().collect();
+            actions.sort_by(|a, b| a.0.cmp(b.0));
+            metrics::header(&mut out, "titan_action_concurrency_available", "gauge", "Free slots of each action with a concurrency cap.");
+            for (action, slots) in actions {
+                let _ = writeln!(out, "titan_action_concurrency_available{{action=\"{}\"}} {}", metrics::escape_label(action), slots.available_permits());
+            }
+        }
+
+        metrics::header(&mut out, "titan_limit_exceeded_total", "counter", "Actions stopped for going over the heap or execution time limit.");
+        let _ = writeln!(out, "titan_limit_exceeded_total {}", self.limits_exceeded());
+
+        metrics::header(&mut out, "titan_slow_requests_total", "counter", "Action runs that went past slow_requests and had their stack logged.");
+        let slow: u64 = self.monitors.iter().map(|m| m.slow_total.load(Ordering::Relaxed)).sum();
+        let _ = writeln!(out, "titan_slow_requests_total {}", slow);
+
+        if let Some(tenants) = &self.tenants {
+            self.render_tenants(tenants, &mut out);
+        }
+        self.metrics.render(&mut out);
+        out
+    }
+
+    fn limits_exceeded(&self) -> u64 {
+        self.monitors.iter().map(|m| m.exceeded_total.load(Ordering::Relaxed)).sum()
+    }
+
+    // The same gauges and counters per tenant pool, labelled with its name
+    fn render_tenants(&self, tenants: &crate::tenants::Tenants, out: &mut String) {
+        type Family = (&'static str, &'static str, &'static str, fn(&RuntimeManager) -> f64);
+        let families: [Family; 8] = [
+            ("titan_tenant_queue_depth", "gauge", "Requests waiting for one of the tenant's workers.", |m| m.scheduler.len() as f64),
+            ("titan_tenant_queue_limit", "gauge", "Requests the tenant's queue holds before shedding, 0 for unbounded.", |m| {
+                m.queue_limit.load(Ordering::Relaxed) as f64
+            }),
+            ("titan_tenant_requests_shed_total", "counter", "Tenant requests rejected because its queue was full.", |m| {
+                m.shed.load(Ordering::Relaxed) as f64
+            }),
+            ("titan_tenant_requests_in_flight", "gauge", "Tenant requests dispatched and not yet answered.", |m| {
+                m.in_flight.load(Ordering::Relaxed) as f64
+            }),
+            ("titan_tenant_requests_total", "counter", "Requests the tenant's workers answered.", |m| m.metrics.totals().0 as f64),
+            ("titan_tenant_errors_total", "counter", "Tenant requests that ended in an error.", |m| m.metrics.totals().1 as f64),
+            ("titan_tenant_worker_busy_seconds_total", "counter", "Time the tenant's workers spent running JS.", |m| {
+                m.monitors.iter().map(|w| w.busy_us.load(Ordering::Relaxed)).sum::<u64>() as f64 / 1_000_000.0
+            }),
+            ("titan_tenant_limit_exceeded_total", "counter", "Tenant actions stopped for going over a limit.", |m| m.limits_exceeded() as f64),
+        ];
+        for (name, kind, help, value) in families {
+            metrics::header(out, name, kind, help);
+            for (tenant, pool) in tenants.pools() {
+                let _ = writeln!(out, "{}{{tenant=\"{}\"}} {}", name, metrics::escape_label(tenant), value(pool));
+            }
+        }
+    }
+
+    /// Whether new requests should be sent here: the boot workers have loaded
+    /// the actions, one has a live isolate, the queue has room and the server
+    /// isn't shutting down. The error says which of these failed.
+    pub fn readiness(&self) -> Result<(), &'static str> {
+        if !self.accepting.load(Ordering::Acquire) {
+            return Err("shutting down");
+        }
+        if self.draining.load(Ordering::Acquire) {
+            return Err("draining");
+        }
+        if !self.monitors[..self.initial].iter().all(|m| m.booted.load(Ordering::SeqCst)) {
+            return Err("workers starting");
+        }
+        if !self.monitors.iter().any(|m| m.isolate.lock().unwrap().is_some()) {
+            return Err("no worker available");
+        }
+        if self.max_len().is_some_and(|max| self.scheduler.len() >= max) {
+            return Err("queue full");
+        }
+        Ok(())
+    }
+
+    /// Drain mode: readiness fails so load balancers move traffic away, and
+    /// the requests that still arrive are served. Returns whether it was on.
+    pub fn set_draining(&self, on: bool) -> bool {
+        self.draining.swap(on, Ordering::AcqRel)
+    }
+
+    pub fn draining(&self) -> bool {
+        self.draining.load(Ordering::Acquire)
+    }
+
+    /// Pings every running worker. Each entry is whether that worker answered
+    /// before `timeout`; stopped and parking slots are None.
+    pub async fn ping_workers(&self, timeout: Duration) -> Vec<Option<bool>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let pings: Vec<_> = self
+            .pool
+            .txs
+            .iter()
+            .zip(&self.pool.states)
+            .map(|(tx, state)| {
+                (state.load(Ordering::SeqCst) == SLOT_RUNNING).then(|| {
+                    let (reply, rx) = oneshot::channel();
+                    // A full channel means a worker far behind; that is no answer
+                    tx.try_send(WorkerCommand::Ping { reply }).ok().map(|_| rx)
+                })
+            })
+            .collect();
+        let mut answers = Vec::with_capacity(pings.len());
+        for ping in pings {
+            answers.push(match ping {
+                None => None,
+                Some(None) => Some(false),
+                Some(Some(rx)) => Some(matches!(tokio::time::timeout_at(deadline, rx).await, Ok(Ok(())))),
+            });
+        }
+        answers
+    }
+
+    /// One entry per worker slot, for the health endpoints.
+    pub fn worker_status(&self) -> Vec<serde_json::Value> {
+        self.monitors
+            .iter()
+            .zip(&self.pool.states)
+            .enumerate()
+            .map(|(i, (monitor, state))| {
+                serde_json::json!({
+                    "id": i,
+                    "state": match state.load(Ordering::SeqCst) {
+                        SLOT_RUNNING => "running",
+                        SLOT_PARKING => "parking",
+                        _ => "stopped",
+                    },
+                    "ready": monitor.isolate.lock().unwrap().is_some(),
+                    "busy": monitor.running.load(Ordering::SeqCst) != 0,
+                    "executions": monitor.executions.load(Ordering::Relaxed),
+                })
+            })
+            .collect()
+    }
+
+    /// What the admin dashboard shows of this pool: each worker's busy time
+    /// and heap, the queue, the slowest actions and the latest errors.
+    pub fn dashboard(&self) -> serde_json::Value {
+        let workers: Vec<_> = self
+            .monitors
+            .iter()
+            .zip(&self.pool.states)
+            .enumerate()
+            .map(|(i, (monitor, state))| {
+                serde_json::json!({
+                    "id": i,
+                    "running": state.load(Ordering::SeqCst) == SLOT_RUNNING,
+                    "busy": monitor.running.load(Ordering::SeqCst) != 0,
+                    "busy_us": monitor.busy_so_far(),
+                    "executions": monitor.executions.load(Ordering::Relaxed),
+                    "heap_used": monitor.heap_used.load(Ordering::Relaxed),
+                    "heap_total": monitor.heap_total.load(Ordering::Relaxed),
+                })
+            })
+            .collect();
+        let (requests, errors) = self.metrics.totals();
+        serde_json::json!({
+            "workers": workers,
+            "queue": self.queue_status(),
+            "requests": requests,
+            "errors": errors,
+            "slowest": self.metrics.slowest(10),
+            "recent_errors": self.metrics.recent_errors(),
+        })
+    }
+
+    /// The CPU samples every running worker took since the last call, with
+    /// those of isolates recycled in between, and how many workers answered.
+    pub async fn collect_profiles(&self, timeout: Duration) -> (crate::profiler::Stacks, usize) {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let pending: Vec<_> = self
+            .pool
+            .txs
+            .iter()
+            .zip(&self.pool.states)
+            .filter(|(_, state)| state.load(Ordering::SeqCst) == SLOT_RUNNING)
+            .filter_map(|(tx, _)| {
+                let (reply, rx) = oneshot::channel();
+                tx.try_send(WorkerCommand::Profile { reply }).ok().map(|_| rx)
+            })
+            .collect();
+        let mut stacks = crate::profiler::take_retired();
+        let mut answered = 0;
+        for rx in pending {
+            if let Ok(Ok(worker)) = tokio::time::timeout_at(deadline, rx).await {
+                crate::profiler::merge(&mut stacks, worker);
+                answered += 1;
+            }
+        }
+        (stacks, answered)
+    }
+
+    /// Has worker `id` write a heap snapshot to `path` once it finishes the
+    /// work in hand, and returns the snapshot's size.
+    pub async fn heap_snapshot(&self, id: usize, path: std::path::PathBuf) -> Result<u64, String> {
+        let (Some(tx), Some(state)) = (self.pool.txs.get(id), self.pool.states.get(id)) else {
+            return Err(format!("there is no worker {}", id));
+        };
+        if state.load(Ordering::SeqCst) != SLOT_RUNNING {
+            return Err(format!("worker {} is not running", id));
+        }
+        let (reply, rx) = oneshot::channel();
+        tx.try_send(WorkerCommand::HeapSnapshot { path, reply })
+            .map_err(|_| format!("worker {} is too far behind to take a snapshot", id))?;
+        match tokio::time::timeout(Duration::from_secs(120), rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(format!("worker {} stopped before taking the snapshot", id)),
+            Err(_) => Err(format!("worker {} did not get to the snapshot within 120s", id)),
+        }
+    }
+
+    /// The schemas the actions were defined with, from the first running
+    /// worker that answers within `timeout`. Every worker loads the same
+    /// actions, so one is enough.
+    pub async fn action_schemas(&self, timeout: Duration) -> Option<std::collections::HashMap<String, serde_json::Value>> {
+        self.action_exports("__titanSchema", timeout).await
+    }
+
+    /// The `config` each action exports, the same way as `action_schemas`.
+    pub async fn action_configs(&self, timeout: Duration) -> Option<std::collections::HashMap<String, serde_json::Value>> {
+        self.action_exports("__titanConfig", timeout).await
+    }
+
+    async fn action_exports(&self, key: &'static str, timeout: Duration) -> Option<std::collections::HashMap<String, serde_json::Value>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        for (tx, state) in self.pool.txs.iter().zip(&self.pool.states) {
+            if state.load(Ordering::SeqCst) != SLOT_RUNNING {
+                continue;
+            }
+            let (reply, rx) = oneshot::channel();
+            if tx.try_send(WorkerCommand::Exports { key, reply }).is_err() {
+                continue;
+            }
+            if let Ok(Ok(exports)) = tokio::time::timeout_at(deadline, rx).await {
+                return Some(exports);
+            }
+        }
+        None
+    }
+
+    /// Has every running worker swap in `changes`, returning how many did
+    /// within `timeout` and what failed to load.
+    pub async fn reload_actions(&self, changes: Arc<crate::watch::Changes>, timeout: Duration) -> (usize, Vec<String>) {
+        extensions::actions_reloaded();
+        let mut replies = Vec::new();
+        let tenants = self.tenants.iter().flat_map(|t| t.pools()).map(|(_, pool)| &pool.pool);
+        for pool in std::iter::once(&self.pool).chain(tenants) {
+            for (tx, state) in pool.txs.iter().zip(&pool.states) {
+                if state.load(Ordering::SeqCst) != SLOT_RUNNING {
+                    continue;
+                }
+                let (reply, rx) = oneshot::channel();
+                if tx.try_send(WorkerCommand::Reload { changes: changes.clone(), reply }).is_ok() {
+                    replies.push(rx);
+                }
+            }
+        }
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        let (mut reloaded, mut errors) = (0, Vec::new());
+        for rx in replies {
+            match tokio::time::timeout_at(deadline, rx).await {
+                Ok(Ok(Ok(()))) => reloaded += 1,
+                Ok(Ok(Err(e))) => errors.push(e),
+                // A worker that went away boots with the new files anyway
+                Ok(Err(_)) => {}
+                Err(_) => errors.push("a worker did not answer in time".to_string()),
+            }
+        }
+        errors.dedup();
+        (reloaded, errors)
+    }
+
+    /// Connects DevTools to worker `id`; its messages to the worker go through
+    /// the returned peer, the worker's to DevTools through `outbound`.
+    pub fn attach_debugger(
+        &self,
+        id: usize,
+        outbound: mpsc::UnboundedSender<WsMessage>,
+    ) -> Result<crate::inspector::DebuggerPeer, String> {
+        let (Some(tx), Some(state)) = (self.pool.txs.get(id), self.pool.states.get(id)) else {
+            return Err(format!("there is no worker {}", id));
+        };
+        if state.load(Ordering::SeqCst) != SLOT_RUNNING {
+            return Err(format!("worker {} is not running", id));
+        }
+        let (inbound_tx, inbound) = crossbeam::channel::unbounded();
+        tx.send(WorkerCommand::DebuggerAttach { inbound, outbound })
+            .map_err(|_| format!("worker {} is not running", id))?;
+        Ok(crate::inspector::DebuggerPeer::new(inbound_tx, tx.clone()))
+    }
+
+    /// Queue depth, its limit and requests in flight, for the health endpoints.
+    pub fn queue_status(&self) -> serde_json::Value {
+        serde_json::json!({
+            "depth": self.scheduler.len(),
+            "limit": self.max_len(),
+            "in_flight": self.in_flight.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Stops accepting requests, lets every worker finish what is already queued
+    /// or in flight (including suspended drifts), then joins the worker threads.
+    /// Work still running when `timeout` expires is terminated. Tenant pools
+    /// drain at the same time.
+    pub async fn shutdown(&self, timeout: Duration) {
+        let tenants = self.tenants.iter().flat_map(|t| t.pools()).map(|(_, pool)| pool.drain(timeout));
+        tokio::join!(self.drain(timeout), futures_util::future::join_all(tenants));
+    }
+
+    async fn drain(&self, timeout: Duration) {
+        if !self.accepting.swap(false, Ordering::AcqRel) {
+            return; // Already shut down
+        }
+
+        let deadline = Instant::now() + timeout;
+        self.pool.closed.store(true, Ordering::Release);
+        for (tx, state) in self.pool.txs.iter().zip(&self.pool.states) {
+            // A stopped slot has no thread to drain its channel
+            if state.load(Ordering::SeqCst) != SLOT_STOPPED {
+                let _ = tx.send(WorkerCommand::Shutdown { deadline });
+            }
+        }
+
+        let workers = std::mem::take(&mut *self.pool.threads.lock().unwrap());
+        let mut join = tokio::task::spawn_blocking(move || {
+            for (_, worker) in workers {
+                let _ = worker.join();
+            }
+        });
+
+        if tokio::time::timeout(timeout, &mut join).await.is_err() {
+            // Workers past the deadline are stuck inside JS
+            for monitor in &self.monitors {
+                monitor.terminate();
+            }
+            let _ = join.await;
+        }
+        self.scheduler.clear();
     }
 }
 
@@ -171,9 +2101,134 @@ impl RuntimeManager {
 // HANDLERS (Simpler - No Mutex/Vec lookup)
 // ----------------------------------------------------------------------------
 
-fn handle_new_request(task: RequestTask, rt: &mut TitanRuntime) {
+fn handle_command(cmd: WorkerCommand, rt: &mut TitanRuntime, monitor: &WorkerMonitor, drain_deadline: &mut Option<Instant>) {
+    match cmd {
+        WorkerCommand::Wake => {}
+        WorkerCommand::Resume { drift_id, result } => {
+            handle_resume(drift_id, result, rt, monitor);
+        }
+        WorkerCommand::Settle { op_id, outcome } => {
+            let request_id = rt.pending_ops.get(&op_id).map_or(0, |op| op.request_id);
+            run_callback(rt, monitor, request_id, |rt| extensions::settle_async_op(rt, op_id, outcome));
+        }
+        WorkerCommand::Background { task } => {
+            handle_new_request(*task, rt, monitor);
+        }
+        WorkerCommand::SocketOpen { socket_id, task, outbound } => {
+            rt.sockets.insert(socket_id, outbound);
+            handle_new_request(*task, rt, monitor);
+        }
+        WorkerCommand::SocketMessage { socket_id, message } => {
+            extensions::dispatch_socket_event(rt, socket_id, Some(message));
+        }
+        WorkerCommand::SocketClose { socket_id } => {
+            if rt.sockets.remove(&socket_id).is_some() {
+                extensions::dispatch_socket_event(rt, socket_id, None);
+            }
+        }
+        WorkerCommand::BusMessage { message } => {
+            for (subscription_id, request_id, live) in extensions::bus_subscribers(rt, &message.topic) {
+                let message = live.then_some(&*message);
+                run_callback(rt, monitor, request_id, |rt| {
+                    extensions::deliver_bus_message(rt, subscription_id, request_id, message)
+                });
+            }
+        }
+        WorkerCommand::Shutdown { deadline } => {
+            // Sockets are long-lived, so they are closed rather than awaited
+            for tx in rt.sockets.values() {
+                let _ = tx.send(WsMessage::Close);
+            }
+            *drain_deadline = Some(deadline);
+        }
+        WorkerCommand::Abort { ticket, reason, cancel } => {
+            let request_id = rt.active_requests.iter().find(|(_, r)| r.ticket == ticket).map(|(id, _)| *id);
+            if let Some(request_id) = request_id {
+                run_callback(rt, monitor, request_id, |rt| extensions::abort_request(rt, request_id, reason, cancel));
+            }
+        }
+        WorkerCommand::Ping { reply } => {
+            let _ = reply.send(());
+        }
+        WorkerCommand::Profile { reply } => {
+            let _ = reply.send(rt.profiler.as_mut().map(|p| p.collect()).unwrap_or_default());
+        }
+        WorkerCommand::HeapSnapshot { path, reply } => {
+            let _ = reply.send(crate::heap::write(&mut rt.isolate, &path));
+        }
+        WorkerCommand::Exports { key, reply } => {
+            let _ = reply.send(extensions::action_exports(rt, key));
+        }
+        WorkerCommand::Reload { changes, reply } => {
+            let _ = reply.send(extensions::reload_actions(rt, &changes));
+        }
+        WorkerCommand::DebuggerAttach { inbound, outbound } => {
+            if let Some(inspector) = rt.inspector.as_mut() {
+                inspector.attach(inbound, outbound);
+            }
+        }
+        WorkerCommand::Debugger => {
+            if let Some(inspector) = rt.inspector.as_mut() {
+                inspector.pump();
+            }
+        }
+    }
+}
+
+/// Fires the earliest timer if it is due. Returns false when none is.
+fn run_due_timer(rt: &mut TitanRuntime, monitor: &WorkerMonitor) -> bool {
+    let Some((timer_id, request_id)) = extensions::pop_due_timer(rt, Instant::now()) else {
+        return false;
+    };
+    run_callback(rt, monitor, request_id, |rt| extensions::fire_timer(rt, timer_id, request_id));
+    true
+}
+
+/// Runs event-loop work (a timer or a settled op) on behalf of a request, under
+/// the same execution limits as the action itself.
+fn run_callback(rt: &mut TitanRuntime, monitor: &WorkerMonitor, request_id: u32, callback: impl FnOnce(&mut TitanRuntime)) {
+    let ticket = rt.active_requests.get(&request_id).map_or(0, |r| r.ticket);
+    monitor.begin(ticket);
+    callback(rt);
+    monitor.end(&mut rt.isolate);
+    release_if_done(rt, request_id);
+}
+
+/// Drops the replay state of a request once it has responded and closed its stream.
+fn release_if_done(rt: &mut TitanRuntime, request_id: u32) {
+    if request_id != 0 && !rt.pending_requests.contains_key(&request_id) && !rt.streams.contains_key(&request_id) {
+        rt.active_requests.remove(&request_id);
+        rt.request_start_counters.remove(&request_id);
+        rt.replay_logs.remove(&request_id);
+    }
+}
+
+/// One span per run of the action on a worker. A request that drifts shows up as
+/// several runs, the later ones being replays.
+fn record_execution_span(trace: Option<&TraceContext>, action: &str, worker: usize, start: SystemTime, replay: bool) {
+    let Some(trace) = trace.filter(|t| t.sampled) else {
+        return;
+    };
+    telemetry::record(SpanRecord {
+        trace_id: trace.trace_id.clone(),
+        span_id: telemetry::new_span_id(),
+        parent_id: Some(trace.span_id.clone()),
+        name: format!("action {}", action),
+        server: false,
+        start,
+        end: SystemTime::now(),
+        error: None,
+        attributes: vec![
+            ("titan.worker".to_string(), serde_json::json!(worker)),
+            ("titan.replay".to_string(), serde_json::json!(replay)),
+        ],
+    });
+}
+
+fn handle_new_request(task: RequestTask, rt: &mut TitanRuntime, monitor: &WorkerMonitor) {
     rt.request_counter += 1;
     let request_id = rt.request_counter;
+    let ip = task.client_ip();
     rt.pending_requests.insert(request_id, task.response_tx);
 
     let req_data = extensions::RequestData {
@@ -184,11 +2239,26 @@ fn handle_new_request(task: RequestTask, rt: &mut TitanRuntime) {
         headers: task.headers.iter().map(|(k,v)| (k.clone(), v.clone())).collect(),
         params: task.params.iter().map(|(k,v)| (k.clone(), v.clone())).collect(),
         query: task.query.iter().map(|(k,v)| (k.clone(), v.clone())).collect(),
+        socket_id: task.socket_id,
+        ticket: task.ticket,
+        correlation_id: task.correlation_id.clone(),
+        trace: task.trace.clone(),
+        form: task.form.clone(),
+        ip,
+        auth: task.auth.clone(),
+        session: task.session.clone(),
+        geo: task.geo.clone(),
+        middleware: task.middleware.clone(),
+        error: task.error.clone(),
+        deadline: task.deadline,
+        body_stream: task.body_stream.clone(),
     };
     rt.active_requests.insert(request_id, req_data);
     let drift_count = rt.drift_counter;
     rt.request_start_counters.insert(request_id, drift_count);
 
+    let started = SystemTime::now();
+    monitor.begin(task.ticket);
     extensions::execute_action_optimized(
         rt,
         request_id,
@@ -200,15 +2270,14 @@ fn handle_new_request(task: RequestTask, rt: &mut TitanRuntime) {
         &task.params,
         &task.query
     );
+    monitor.end(&mut rt.isolate);
+    record_execution_span(task.trace.as_ref(), &task.action_name, rt.id, started, false);
     
-    // Cleanup if sync
-    if !rt.pending_requests.contains_key(&request_id) {
-         rt.active_requests.remove(&request_id);
-         rt.request_start_counters.remove(&request_id);
-    }
+    // Cleanup if sync (an open stream still needs the request data for replays)
+    release_if_done(rt, request_id);
 }
 
-fn handle_resume(drift_id: u32, result: WorkerAsyncResult, rt: &mut TitanRuntime) {
+fn handle_resume(drift_id: u32, result: WorkerAsyncResult, rt: &mut TitanRuntime, monitor: &WorkerMonitor) {
     // 1. Identify which request this drift belongs to
     let req_id = rt.drift_to_request.get(&drift_id).copied().unwrap_or(0);
     
@@ -224,6 +2293,8 @@ fn handle_resume(drift_id: u32, result: WorkerAsyncResult, rt: &mut TitanRuntime
         let start_counter = rt.request_start_counters.get(&req_id).copied().unwrap_or(0);
         rt.drift_counter = start_counter; 
 
+        let started = SystemTime::now();
+        monitor.begin(req_data.ticket);
         extensions::execute_action_optimized(
             rt,
             req_id,
@@ -235,11 +2306,10 @@ fn handle_resume(drift_id: u32, result: WorkerAsyncResult, rt: &mut TitanRuntime
             &req_data.params,
             &req_data.query
         );
+        monitor.end(&mut rt.isolate);
+        record_execution_span(req_data.trace.as_ref(), &req_data.action_name, rt.id, started, true);
     }
 
     // 5. Cleanup
-    if req_id != 0 && !rt.pending_requests.contains_key(&req_id) {
-        rt.active_requests.remove(&req_id);
-        rt.request_start_counters.remove(&req_id);
-    }
+    release_if_done(rt, req_id);
 }
@@ -0,0 +1,323 @@
+use crossbeam::channel::Sender;
+use crossbeam::deque::{Injector, Steal, Stealer, Worker};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::runtime::{RequestTask, WorkerCommand};
+use crate::timing::Mark;
+
+/// Which queue lane a request waits in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "high" => Some(Priority::High),
+            "normal" => Some(Priority::Normal),
+            "low" => Some(Priority::Low),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+}
+
+// Lane order for each pick: lower lanes go first now and then so a steady
+// stream of high-priority work can't starve them.
+const LOW_TURN: usize = 16;
+const NORMAL_TURN: usize = 4;
+
+/// Upper bounds in seconds of the queue wait histogram.
+pub const WAIT_BUCKETS: [f64; 14] = [0.0001, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Work-stealing request queue shared by the worker pool.
+///
+/// New requests land in a global injector. Workers pull them in small batches
+/// into their local deque, and idle workers steal from busy ones, so a request
+/// never waits behind a slow action on a single worker's queue.
+///
+/// Requests wait in one of three lanes. High-priority ones are taken ahead of
+/// a worker's local batch, so they never queue behind bulk traffic.
+///
+/// Requests pinned to a worker by `affinity` wait in that worker's own queue,
+/// which nobody else takes from.
+///
+/// Commands bound to one isolate (drift resumes, socket frames) keep using the
+/// worker's own channel; that channel doubles as the wake-up signal for idle
+/// workers.
+pub struct Scheduler {
+    lanes: [Injector<RequestTask>; 3],
+    pinned: Vec<Injector<RequestTask>>,
+    turn: AtomicUsize,
+    stealers: Vec<Stealer<RequestTask>>,
+    idle: Vec<AtomicBool>,
+    wake_txs: Vec<Sender<WorkerCommand>>,
+    // Submitters waiting for room in a full queue, woken as workers take tasks
+    space: Notify,
+    waiting: AtomicUsize,
+    // Time tasks spent queued before a worker picked them up
+    wait_us: AtomicU64,
+    picked: AtomicU64,
+    // Picks per WAIT_BUCKETS bound, the last one for anything longer
+    wait_buckets: [AtomicU64; WAIT_BUCKETS.len() + 1],
+}
+
+impl Scheduler {
+    pub fn new(locals: &[Worker<RequestTask>], wake_txs: Vec<Sender<WorkerCommand>>) -> Self {
+        Self {
+            lanes: [Injector::new(), Injector::new(), Injector::new()],
+            pinned: locals.iter().map(|_| Injector::new()).collect(),
+            turn: AtomicUsize::new(0),
+            stealers: locals.iter().map(|w| w.stealer()).collect(),
+            idle: locals.iter().map(|_| AtomicBool::new(false)).collect(),
+            wake_txs,
+            space: Notify::new(),
+            waiting: AtomicUsize::new(0),
+            wait_us: AtomicU64::new(0),
+            picked: AtomicU64::new(0),
+            wait_buckets: Default::default(),
+        }
+    }
+
+    /// Queues the task for any worker, or only for `worker` when it is pinned.
+    pub fn submit(&self, task: RequestTask, worker: Option<usize>) {
+        match worker {
+            Some(i) if i < self.pinned.len() => {
+                self.pinned[i].push(task);
+                if self.idle[i].compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                    let _ = self.wake_txs[i].try_send(WorkerCommand::Wake);
+                }
+            }
+            _ => {
+                self.lanes[task.priority as usize].push(task);
+                self.wake_one();
+            }
+        }
+    }
+
+    /// Queues the task unless `max_len` requests are already waiting, in which
+    /// case it is dropped and false is returned. High-priority tasks are never
+    /// shed, so health checks keep answering under overload.
+    pub fn try_submit(&self, task: RequestTask, worker: Option<usize>, max_len: usize) -> bool {
+        if task.priority != Priority::High && self.len() >= max_len {
+            return false;
+        }
+        self.submit(task, worker);
+        true
+    }
+
+    /// Like `try_submit`, but waits up to `timeout` for a worker to make room.
+    pub async fn submit_within(&self, task: RequestTask, worker: Option<usize>, max_len: usize, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        let has_room = task.priority == Priority::High || loop {
+            // Registered before the check so a task taken in between still wakes us
+            let notified = self.space.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if self.len() < max_len {
+                break true;
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                break self.len() < max_len;
+            }
+        };
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        has_room && self.try_submit(task, worker, max_len)
+    }
+
+    /// Wakes one parked worker, if any.
+    pub fn wake_one(&self) {
+        for (i, idle) in self.idle.iter().enumerate() {
+            if idle.compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                // A full channel means the worker has commands to process anyway
+                let _ = self.wake_txs[i].try_send(WorkerCommand::Wake);
+                return;
+            }
+        }
+    }
+
+    /// The next task for worker `index` to run. Tasks nobody waits for
+    /// anymore (their client hung up, or they lost a hedge race while still
+    /// queued) are dropped on the way.
+    pub fn next_task(&self, index: usize, local: &Worker<RequestTask>) -> Option<RequestTask> {
+        std::iter::from_fn(|| self.pick(index, local)).find(|task| !task.cancelled.load(Ordering::Acquire))
+    }
+
+    // One lane first (the high one, except on the turns that favour a lower
+    // lane), then what is pinned to this worker, then the local deque, then
+    // the other lanes, then a peer's deque.
+    fn pick(&self, index: usize, local: &Worker<RequestTask>) -> Option<RequestTask> {
+        let turn = self.turn.fetch_add(1, Ordering::Relaxed);
+        let first = match turn {
+            t if t % LOW_TURN == 0 => Priority::Low,
+            t if t % NORMAL_TURN == 0 => Priority::Normal,
+            _ => Priority::High,
+        };
+        let lane = |p: Priority| retry(|| self.lanes[p as usize].steal_batch_and_pop(local));
+        lane(first)
+            .or_else(|| retry(|| self.pinned[index].steal()))
+            .or_else(|| local.pop())
+            .or_else(|| {
+                [Priority::High, Priority::Normal, Priority::Low]
+                    .into_iter()
+                    .filter(|p| *p != first)
+                    .find_map(lane)
+            })
+            .or_else(|| {
+                retry(|| {
+                    self.stealers
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| *i != index)
+                        .map(|(_, s)| s.steal())
+                        .collect()
+                })
+            })
+            .inspect(|task| {
+                let waited = task.queued_at.elapsed();
+                if let Some(timeline) = &task.timeline {
+                    timeline.mark(Mark::Dequeued);
+                }
+                self.wait_us.fetch_add(waited.as_micros() as u64, Ordering::Relaxed);
+                self.picked.fetch_add(1, Ordering::Relaxed);
+                let bucket = WAIT_BUCKETS.iter().position(|le| waited.as_secs_f64() <= *le).unwrap_or(WAIT_BUCKETS.len());
+                self.wait_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+                if self.waiting.load(Ordering::SeqCst) > 0 {
+                    self.space.notify_waiters();
+                }
+            })
+    }
+
+    /// Marks the worker idle before it blocks on its channel. Returns false if
+    /// work showed up in the meantime and the worker should keep going.
+    pub fn park(&self, index: usize) -> bool {
+        self.idle[index].store(true, Ordering::SeqCst);
+        if !self.lanes.iter().all(|l| l.is_empty())
+            || !self.stealers.iter().all(|s| s.is_empty())
+            || !self.pinned[index].is_empty()
+        {
+            self.idle[index].store(false, Ordering::SeqCst);
+            return false;
+        }
+        true
+    }
+
+    /// Moves what was pinned to a worker that stopped back into the shared
+    /// lanes, for the others to take.
+    pub fn release(&self, index: usize) {
+        let mut released = false;
+        while let Some(task) = retry(|| self.pinned[index].steal()) {
+            self.lanes[task.priority as usize].push(task);
+            released = true;
+        }
+        if released {
+            self.wake_one();
+        }
+    }
+
+    pub fn unpark(&self, index: usize) {
+        self.idle[index].store(false, Ordering::SeqCst);
+    }
+
+    /// Total queue wait in microseconds and the number of tasks it covers.
+    pub fn wait_totals(&self) -> (u64, u64) {
+        (self.wait_us.load(Ordering::Relaxed), self.picked.load(Ordering::Relaxed))
+    }
+
+    /// Picks so far per bucket of `WAIT_BUCKETS`, not cumulative; the last
+    /// entry counts waits past the largest bound.
+    pub fn wait_histogram(&self) -> [u64; WAIT_BUCKETS.len() + 1] {
+        std::array::from_fn(|i| self.wait_buckets[i].load(Ordering::Relaxed))
+    }
+
+    /// Requests waiting in one lane, not counting those already pulled into a
+    /// worker's local deque.
+    pub fn lane_len(&self, priority: Priority) -> usize {
+        self.lanes[priority as usize].len()
+    }
+
+    /// Requests queued but not yet picked up by a worker.
+    pub fn len(&self) -> usize {
+        self.lanes.iter().map(|l| l.len()).sum::<usize>()
+            + self.pinned.iter().map(|p| p.len()).sum::<usize>()
+            + self.stealers.iter().map(|s| s.len()).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lanes.iter().all(|l| l.is_empty())
+            && self.pinned.iter().all(|p| p.is_empty())
+            && self.stealers.iter().all(|s| s.is_empty())
+    }
+
+    /// Drops requests nobody picked up (their callers see a closed channel).
+    pub fn clear(&self) {
+        for lane in self.lanes.iter().chain(&self.pinned) {
+            while !lane.steal().is_empty() {}
+        }
+        for stealer in &self.stealers {
+            while !stealer.steal().is_empty() {}
+        }
+    }
+}
+
+/// Retries a steal until it settles on a task or on an empty queue.
+fn retry<T>(steal: impl FnMut() -> Steal<T>) -> Option<T> {
+    std::iter::repeat_with(steal).find(|s| !s.is_retry()).and_then(|s| s.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(workers: usize) -> (Scheduler, Vec<Worker<RequestTask>>) {
+        let locals: Vec<Worker<RequestTask>> = (0..workers).map(|_| Worker::new_fifo()).collect();
+        let wake_txs = (0..workers).map(|_| crossbeam::channel::unbounded().0).collect();
+        (Scheduler::new(&locals, wake_txs), locals)
+    }
+
+    #[test]
+    fn queued_hedge_loser_never_runs() {
+        let (scheduler, locals) = scheduler(1);
+        let (original, _rx) = RequestTask::new("slow".into(), "GET".into(), "/slow".into(), 1, Priority::Normal);
+        let (mut copy, _hedge_rx) = RequestTask::new("slow".into(), "GET".into(), "/slow".into(), 2, Priority::Normal);
+        copy.cancelled = original.cancelled.clone();
+        let token = copy.cancelled.clone();
+
+        scheduler.submit(original, None);
+        let running = scheduler.next_task(0, &locals[0]).expect("the original is picked");
+        assert_eq!(running.ticket, 1);
+        scheduler.submit(copy, None);
+
+        // The original answers first, as `first_result` sees it
+        token.store(true, Ordering::Release);
+        assert!(scheduler.next_task(0, &locals[0]).is_none());
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn live_tasks_are_still_picked() {
+        let (scheduler, locals) = scheduler(2);
+        let (gone, _rx) = RequestTask::new("a".into(), "GET".into(), "/a".into(), 1, Priority::Normal);
+        let (live, _rx2) = RequestTask::new("b".into(), "GET".into(), "/b".into(), 2, Priority::Normal);
+        gone.cancelled.store(true, Ordering::Release);
+        scheduler.submit(gone, None);
+        scheduler.submit(live, None);
+        let picked = scheduler.next_task(1, &locals[1]).map(|task| task.ticket);
+        assert_eq!(picked, Some(2));
+    }
+}
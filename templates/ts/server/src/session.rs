@@ -0,0 +1,345 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use dashmap::DashMap;
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+
+/// Response header the action's `req.session` changes travel in. It never
+/// leaves the server.
+pub const CHANGE_HEADER: &str = "x-titan-session";
+
+// Browsers drop cookies over this size
+const MAX_COOKIE_BYTES: usize = 4096;
+// An untouched session is written back at most this often to slide its idle timeout
+const TOUCH_INTERVAL: u64 = 60;
+
+/// What a session holds, wherever it is stored. Times are Unix seconds.
+#[derive(Serialize, Deserialize, Clone)]
+struct Record {
+    data: Map<String, Value>,
+    created: u64,
+    seen: u64,
+    rotated: u64,
+}
+
+enum Store {
+    /// The whole record, encrypted into the cookie.
+    Cookie,
+    Memory(Arc<DashMap<String, Record>>),
+    Redis(String),
+}
+
+impl Store {
+    async fn get(&self, id: &str) -> Option<Record> {
+        match self {
+            Store::Cookie => None,
+            Store::Memory(map) => map.get(id).map(|r| r.clone()),
+            Store::Redis(url) => {
+                let replies = crate::redis::pipeline(url, vec![vec!["GET".to_string(), redis_key(id)]]).await;
+                match replies.as_deref() {
+                    Ok([Value::String(json)]) => serde_json::from_str(json).ok(),
+                    Ok(_) => None,
+                    Err(e) => {
+                        tracing::error!("Session load failed: {}", e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    async fn put(&self, id: &str, record: &Record, idle: Duration) {
+        match self {
+            Store::Cookie => {}
+            Store::Memory(map) => {
+                map.insert(id.to_string(), record.clone());
+            }
+            Store::Redis(url) => {
+                let json = serde_json::to_string(record).unwrap_or_default();
+                let command = ["SET", &redis_key(id), &json, "PX", &idle.as_millis().to_string()];
+                if let Err(e) = crate::redis::pipeline(url, vec![command.map(str::to_string).to_vec()]).await {
+                    tracing::error!("Session save failed: {}", e);
+                }
+            }
+        }
+    }
+
+    async fn delete(&self, id: &str) {
+        match self {
+            Store::Cookie => {}
+            Store::Memory(map) => {
+                map.remove(id);
+            }
+            Store::Redis(url) => {
+                let _ = crate::redis::pipeline(url, vec![vec!["DEL".to_string(), redis_key(id)]]).await;
+            }
+        }
+    }
+}
+
+fn redis_key(id: &str) -> String {
+    format!("titan:session:{}", id)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// The session a request came in with.
+pub struct Loaded {
+    /// The request sent a session cookie, valid or not.
+    had_cookie: bool,
+    /// Server-side stores only.
+    id: Option<String>,
+    record: Option<Record>,
+}
+
+impl Loaded {
+    /// The data handed to the action as `req.session`.
+    pub fn data(&self) -> Arc<Value> {
+        Arc::new(Value::Object(self.record.as_ref().map(|r| r.data.clone()).unwrap_or_default()))
+    }
+}
+
+/// What the action did to its session, read from [`CHANGE_HEADER`].
+#[derive(Deserialize, Default)]
+struct Change {
+    data: Option<Map<String, Value>>,
+    #[serde(default)]
+    destroy: bool,
+    #[serde(default)]
+    regenerate: bool,
+}
+
+/// The `session` block of titan.config. Sessions live in an encrypted cookie
+/// unless `store` is `"memory"` or `"redis"`, in which case the cookie holds a
+/// signed id. Idle sessions expire after `idle_timeout_ms`, and ids are
+/// replaced every `rotate_ms`.
+pub struct SessionConfig {
+    store: Store,
+    cipher: LessSafeKey,
+    signer: hmac::Key,
+    rng: SystemRandom,
+    name: String,
+    attributes: String,
+    idle: Duration,
+    rotate: Option<Duration>,
+}
+
+impl SessionConfig {
+    /// None when there's no `session` block. `TITAN_SESSION_SECRET` wins over
+    /// `session.secret`; without either, a random one is used and sessions
+    /// don't survive a restart.
+    pub fn from_config(config: &Value, tls: bool) -> Result<Option<Self>, String> {
+        if !config.is_object() && config != &Value::Bool(true) {
+            return Ok(None);
+        }
+        let rng = SystemRandom::new();
+        let secret = match std::env::var("TITAN_SESSION_SECRET")
+            .ok()
+            .or_else(|| config["secret"].as_str().map(str::to_string))
+            .filter(|s| !s.is_empty())
+        {
+            Some(secret) => secret.into_bytes(),
+            None => {
+                tracing::warn!("session.secret is not set; sessions won't survive a restart");
+                let mut random = vec![0u8; 32];
+                rng.fill(&mut random).map_err(|_| "session: no system randomness".to_string())?;
+                random
+            }
+        };
+        // Separate keys for encrypting cookies and signing ids, both from the one secret
+        let master = hmac::Key::new(hmac::HMAC_SHA256, &secret);
+        let cipher_key = hmac::sign(&master, b"titan session encryption");
+        let signer_key = hmac::sign(&master, b"titan session signing");
+        let cipher = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, cipher_key.as_ref()).expect("32-byte key"));
+
+        let store = match config["store"].as_str().unwrap_or("cookie") {
+            "cookie" => Store::Cookie,
+            "memory" => Store::Memory(Arc::new(DashMap::new())),
+            "redis" => Store::Redis(
+                config["redis_url"]
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or("session: \"redis\" store needs \"redis_url\"")?,
+            ),
+            other => return Err(format!("session: unknown store \"{}\"", other)),
+        };
+
+        let cookie = &config["cookie"];
+        let mut attributes = format!("; Path={}; HttpOnly", cookie["path"].as_str().unwrap_or("/"));
+        if let Some(domain) = cookie["domain"].as_str() {
+            attributes += &format!("; Domain={}", domain);
+        }
+        if cookie["secure"].as_bool().unwrap_or(tls) {
+            attributes += "; Secure";
+        }
+        attributes += &format!("; SameSite={}", cookie["same_site"].as_str().unwrap_or("Lax"));
+
+        Ok(Some(Self {
+            store,
+            cipher,
+            signer: hmac::Key::new(hmac::HMAC_SHA256, signer_key.as_ref()),
+            rng,
+            name: cookie["name"].as_str().unwrap_or("titan.sid").to_string(),
+            attributes,
+            idle: Duration::from_millis(config["idle_timeout_ms"].as_u64().filter(|ms| *ms > 0).unwrap_or(30 * 60 * 1000)),
+            rotate: Some(Duration::from_millis(config["rotate_ms"].as_u64().unwrap_or(15 * 60 * 1000))).filter(|d| !d.is_zero()),
+        }))
+    }
+
+    /// Drops idle sessions from the memory store; Redis expires them itself.
+    pub fn start_sweeper(&self) {
+        let Store::Memory(map) = &self.store else {
+            return;
+        };
+        let (map, idle) = (map.clone(), self.idle.as_secs());
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                let now = now();
+                map.retain(|_, record| now.saturating_sub(record.seen) <= idle);
+            }
+        });
+    }
+
+    /// Finds the session named by the request's `Cookie` header. Bad
+    /// signatures, unknown ids and idle sessions all load as empty.
+    pub async fn load(&self, cookie_header: Option<&str>) -> Loaded {
+        let value = cookie_header.and_then(|header| {
+            header.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                (name == self.name).then(|| value.to_string())
+            })
+        });
+        let Some(value) = value else {
+            return Loaded { had_cookie: false, id: None, record: None };
+        };
+        let (id, record) = match &self.store {
+            Store::Cookie => (None, self.open(&value)),
+            _ => match self.unsign(&value) {
+                Some(id) => {
+                    let record = self.store.get(&id).await;
+                    (Some(id), record)
+                }
+                None => (None, None),
+            },
+        };
+        let record = record.filter(|r| now().saturating_sub(r.seen) <= self.idle.as_secs());
+        Loaded { had_cookie: true, id, record }
+    }
+
+    /// Saves the session after the action ran and returns the `Set-Cookie`
+    /// value, if the cookie has to change. `change` is the raw
+    /// [`CHANGE_HEADER`] value.
+    pub async fn commit(&self, loaded: Loaded, change: Option<&str>) -> Option<String> {
+        let change: Option<Change> = change.and_then(|c| serde_json::from_str(c).ok());
+        let now = now();
+
+        if change.as_ref().is_some_and(|c| c.destroy && c.data.is_none()) {
+            if let Some(id) = &loaded.id {
+                self.store.delete(id).await;
+            }
+            return loaded.had_cookie.then(|| format!("{}=; Max-Age=0{}", self.name, self.attributes));
+        }
+
+        let existed = loaded.record.is_some();
+        let mut record = loaded.record.unwrap_or(Record { data: Map::new(), created: now, seen: now, rotated: now });
+        let changed = match change.as_ref().and_then(|c| c.data.clone()) {
+            Some(data) => {
+                record.data = data;
+                true
+            }
+            None => false,
+        };
+        // Visitors who never stored anything don't get a session
+        if !existed && record.data.is_empty() {
+            return None;
+        }
+        // Logging out and back in within one request starts a new session too
+        let regenerate = change.as_ref().is_some_and(|c| c.regenerate || c.destroy)
+            || self.rotate.is_some_and(|every| now.saturating_sub(record.rotated) >= every.as_secs());
+        let touch = now.saturating_sub(record.seen) >= TOUCH_INTERVAL.min(self.idle.as_secs() / 2);
+        if existed && !changed && !regenerate && !touch {
+            return None;
+        }
+        record.seen = now;
+        if regenerate {
+            record.rotated = now;
+        }
+
+        let value = match &self.store {
+            Store::Cookie => {
+                let sealed = self.seal(&record)?;
+                if sealed.len() + self.name.len() > MAX_COOKIE_BYTES {
+                    tracing::error!("Session too large for a cookie: {} bytes", sealed.len());
+                    return None;
+                }
+                sealed
+            }
+            _ => {
+                let id = match (&loaded.id, regenerate || !existed) {
+                    (Some(old), true) => {
+                        self.store.delete(old).await;
+                        self.new_id()?
+                    }
+                    (Some(id), false) => id.clone(),
+                    (None, _) => self.new_id()?,
+                };
+                self.store.put(&id, &record, self.idle).await;
+                self.sign(&id)
+            }
+        };
+        Some(format!("{}={}; Max-Age={}{}", self.name, value, self.idle.as_secs(), self.attributes))
+    }
+
+    fn new_id(&self) -> Option<String> {
+        let mut bytes = [0u8; 32];
+        self.rng.fill(&mut bytes).ok()?;
+        Some(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    fn sign(&self, id: &str) -> String {
+        format!("{}.{}", id, URL_SAFE_NO_PAD.encode(hmac::sign(&self.signer, id.as_bytes())))
+    }
+
+    fn unsign(&self, value: &str) -> Option<String> {
+        let (id, signature) = value.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        hmac::verify(&self.signer, id.as_bytes(), &signature).ok()?;
+        Some(id.to_string())
+    }
+
+    /// Nonce followed by the AES-256-GCM ciphertext of the record. The cookie
+    /// name is authenticated too, so a value can't be moved to another cookie.
+    fn seal(&self, record: &Record) -> Option<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).ok()?;
+        let mut buf = serde_json::to_vec(record).ok()?;
+        self.cipher
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(self.name.as_bytes()), &mut buf)
+            .ok()?;
+        let mut out = nonce.to_vec();
+        out.extend_from_slice(&buf);
+        Some(URL_SAFE_NO_PAD.encode(out))
+    }
+
+    fn open(&self, value: &str) -> Option<Record> {
+        let raw = URL_SAFE_NO_PAD.decode(value).ok()?;
+        if raw.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, sealed) = raw.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let mut buf = sealed.to_vec();
+        let plain = self.cipher.open_in_place(nonce, Aad::from(self.name.as_bytes()), &mut buf).ok()?;
+        serde_json::from_slice(plain).ok()
+    }
+}
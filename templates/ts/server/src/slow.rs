@@ -0,0 +1,57 @@
+//! Slow-request reports: when an action's JS has kept a worker busy past
+//! the threshold, the watchdog interrupts the isolate and the worker logs
+//! where it is, with the request's id, so a bad p99 can be traced to a line
+//! without running the profiler.
+
+use serde_json::Value;
+use std::fmt::Write;
+use std::sync::OnceLock;
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+struct Settings {
+    threshold_ms: u64,
+    frames: usize,
+}
+
+/// The `slow_requests` block of titan.config: a threshold in milliseconds,
+/// or `{ threshold_ms, frames }` with how many frames (default 20) each
+/// report shows. Off unless set.
+pub fn configure(config: &Value) {
+    let threshold_ms = config.as_u64().or_else(|| config["threshold_ms"].as_u64()).filter(|ms| *ms > 0);
+    let Some(threshold_ms) = threshold_ms else {
+        return;
+    };
+    let frames = config["frames"].as_u64().filter(|n| *n > 0).unwrap_or(20) as usize;
+    let _ = SETTINGS.set(Settings { threshold_ms, frames });
+}
+
+pub fn threshold_ms() -> Option<u64> {
+    SETTINGS.get().map(|s| s.threshold_ms)
+}
+
+/// The JS stack of the running script, innermost frame first, in the shape
+/// of an `Error.stack` and mapped back to the original sources.
+pub fn stack(scope: &mut v8::HandleScope) -> String {
+    let frames = SETTINGS.get().map_or(20, |s| s.frames);
+    let Some(trace) = v8::StackTrace::current_stack_trace(scope, frames) else {
+        return String::new();
+    };
+    let mut out = String::new();
+    for i in 0..trace.get_frame_count() {
+        let Some(frame) = trace.get_frame(scope, i) else {
+            continue;
+        };
+        let function = frame
+            .get_function_name(scope)
+            .map(|name| name.to_rust_string_lossy(scope))
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "<anonymous>".to_string());
+        let script = frame
+            .get_script_name_or_source_url(scope)
+            .map(|name| name.to_rust_string_lossy(scope))
+            .unwrap_or_default();
+        let _ = writeln!(out, "    at {} ({}:{}:{})", function, script, frame.get_line_number(), frame.get_column());
+    }
+    crate::sourcemap::rewrite(out.trim_end())
+}
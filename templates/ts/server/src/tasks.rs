@@ -0,0 +1,329 @@
+use bytes::Bytes;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+use crate::metrics;
+use crate::runtime::RuntimeManager;
+
+/// Handlers in app/tasks are bundled as actions under this prefix.
+pub const TASK_ACTION_PREFIX: &str = "__titan_task_";
+
+// Failed attempts are retried after 1s, 2s, 4s... up to this
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+static QUEUE: OnceLock<TaskQueue> = OnceLock::new();
+
+pub struct TaskConfig {
+    /// Append-only journal that lets pending tasks survive a restart.
+    pub journal: Option<PathBuf>,
+    /// Most tasks running at once.
+    pub concurrency: usize,
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Task {
+    id: String,
+    name: String,
+    payload: String,
+    run_at_ms: u64,
+    attempt: u32,
+    retries: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum JournalEntry {
+    Add(Task),
+    Retry { id: String, run_at_ms: u64, attempt: u32 },
+    Done { id: String },
+}
+
+enum Command {
+    Enqueue(Task),
+    Finished(Task, bool),
+}
+
+#[derive(Default)]
+struct Stats {
+    enqueued: AtomicU64,
+    completed: AtomicU64,
+    retried: AtomicU64,
+    failed: AtomicU64,
+    pending: AtomicU64,
+}
+
+/// Background tasks enqueued from JS with `tasks.enqueue()`. A single loop
+/// owns the pending set, hands due tasks to the worker pool and reschedules
+/// failures with exponential backoff. With a journal, delivery is at least
+/// once: a task that was running when the server stopped runs again.
+pub struct TaskQueue {
+    tx: mpsc::UnboundedSender<Command>,
+    stats: Arc<Stats>,
+}
+
+/// Starts the queue on the current Tokio runtime, reloading the journal if
+/// there is one. Returns how many tasks were restored.
+pub fn start(runtime: Arc<RuntimeManager>, config: TaskConfig) -> usize {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let stats = Arc::new(Stats::default());
+
+    let mut journal = config.journal.map(Journal::new);
+    let restored = journal.as_mut().map(Journal::replay).unwrap_or_default();
+    stats.pending.store(restored.len() as u64, Ordering::Relaxed);
+    let count = restored.len();
+
+    if QUEUE.set(TaskQueue { tx: tx.clone(), stats: stats.clone() }).is_err() {
+        return 0;
+    }
+    let queue = QueueLoop {
+        runtime,
+        tx,
+        journal,
+        stats,
+        concurrency: config.concurrency.max(1),
+        timeout: config.timeout,
+    };
+    tokio::spawn(run_queue(queue, rx, restored));
+    count
+}
+
+/// Queues `name` to run with `payload` (JSON text) after `delay`. Returns the
+/// task id.
+pub fn enqueue(name: &str, payload: String, delay: Duration, retries: u32) -> Result<String, &'static str> {
+    let queue = QUEUE.get().ok_or("task queue is not running")?;
+    let task = Task {
+        id: new_task_id(),
+        name: name.to_string(),
+        payload,
+        run_at_ms: now_ms() + delay.as_millis() as u64,
+        attempt: 1,
+        retries,
+    };
+    let id = task.id.clone();
+    queue.tx.send(Command::Enqueue(task)).map_err(|_| "task queue has stopped")?;
+    queue.stats.enqueued.fetch_add(1, Ordering::Relaxed);
+    queue.stats.pending.fetch_add(1, Ordering::Relaxed);
+    Ok(id)
+}
+
+/// Prometheus text exposition of the queue counters.
+pub fn render(out: &mut String) {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    let stats = &queue.stats;
+    for (name, kind, help, value) in [
+        ("titan_tasks_enqueued_total", "counter", "Tasks enqueued with tasks.enqueue().", &stats.enqueued),
+        ("titan_tasks_completed_total", "counter", "Tasks that ran successfully.", &stats.completed),
+        ("titan_tasks_retried_total", "counter", "Failed attempts that were scheduled to run again.", &stats.retried),
+        ("titan_tasks_failed_total", "counter", "Tasks dropped after their last retry failed.", &stats.failed),
+        ("titan_tasks_pending", "gauge", "Tasks waiting or running.", &stats.pending),
+    ] {
+        metrics::header(out, name, kind, help);
+        let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+    }
+}
+
+struct QueueLoop {
+    runtime: Arc<RuntimeManager>,
+    tx: mpsc::UnboundedSender<Command>,
+    journal: Option<Journal>,
+    stats: Arc<Stats>,
+    concurrency: usize,
+    timeout: Option<Duration>,
+}
+
+async fn run_queue(queue: QueueLoop, mut rx: mpsc::UnboundedReceiver<Command>, restored: Vec<Task>) {
+    let QueueLoop { runtime, tx, mut journal, stats, concurrency, timeout } = queue;
+    let mut tasks: HashMap<String, Task> = HashMap::new();
+    let mut due: BinaryHeap<Reverse<(u64, String)>> = BinaryHeap::new();
+    let mut running = 0;
+    for task in restored {
+        due.push(Reverse((task.run_at_ms, task.id.clone())));
+        tasks.insert(task.id.clone(), task);
+    }
+
+    loop {
+        let now = now_ms();
+        while running < concurrency {
+            match due.peek() {
+                Some(Reverse((run_at, _))) if *run_at <= now => {}
+                _ => break,
+            }
+            let Some(Reverse((_, id))) = due.pop() else {
+                break;
+            };
+            let Some(task) = tasks.remove(&id) else {
+                continue;
+            };
+            running += 1;
+            tokio::spawn(run_task(runtime.clone(), tx.clone(), task, timeout));
+        }
+
+        // Sleep until the next task is due, unless all slots are busy
+        let wait = match due.peek() {
+            Some(Reverse((run_at, _))) if running < concurrency => Duration::from_millis(run_at.saturating_sub(now)),
+            _ => Duration::from_secs(3600),
+        };
+        let command = tokio::select! {
+            command = rx.recv() => command,
+            _ = tokio::time::sleep(wait) => continue,
+        };
+
+        match command {
+            Some(Command::Enqueue(task)) => {
+                if let Some(journal) = &mut journal {
+                    journal.append(&JournalEntry::Add(task.clone()));
+                }
+                due.push(Reverse((task.run_at_ms, task.id.clone())));
+                tasks.insert(task.id.clone(), task);
+            }
+            Some(Command::Finished(mut task, ok)) => {
+                running -= 1;
+                if !ok && task.attempt <= task.retries {
+                    let backoff = Duration::from_secs(1 << (task.attempt - 1).min(16)).min(MAX_BACKOFF);
+                    task.attempt += 1;
+                    task.run_at_ms = now_ms() + backoff.as_millis() as u64;
+                    stats.retried.fetch_add(1, Ordering::Relaxed);
+                    if let Some(journal) = &mut journal {
+                        journal.append(&JournalEntry::Retry {
+                            id: task.id.clone(),
+                            run_at_ms: task.run_at_ms,
+                            attempt: task.attempt,
+                        });
+                    }
+                    due.push(Reverse((task.run_at_ms, task.id.clone())));
+                    tasks.insert(task.id.clone(), task);
+                    continue;
+                }
+
+                if ok {
+                    stats.completed.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    stats.failed.fetch_add(1, Ordering::Relaxed);
+                    tracing::error!(task = %task.name, task_id = %task.id, "Task {} ({}) failed for good after {} attempt(s)", task.name, task.id, task.attempt);
+                }
+                stats.pending.fetch_sub(1, Ordering::Relaxed);
+                if let Some(journal) = &mut journal {
+                    journal.append(&JournalEntry::Done { id: task.id });
+                }
+            }
+            None => return,
+        }
+    }
+}
+
+async fn run_task(runtime: Arc<RuntimeManager>, tx: mpsc::UnboundedSender<Command>, task: Task, timeout: Option<Duration>) {
+    let started = std::time::Instant::now();
+    let headers: SmallVec<[(String, String); 8]> = SmallVec::from_vec(vec![
+        ("content-type".to_string(), "application/json".to_string()),
+        ("x-titan-task-id".to_string(), task.id.clone()),
+        ("x-titan-task-attempt".to_string(), task.attempt.to_string()),
+    ]);
+    let action = format!("{}{}", TASK_ACTION_PREFIX, task.name);
+    let body = Some(Bytes::from(task.payload.clone()));
+    let result = runtime.run_background(action, "TASK", body, headers, timeout).await;
+
+    let ok = match result.error_message() {
+        Some(err) => {
+            tracing::error!(task = %task.name, attempt = task.attempt, error = err, "task {} attempt {} failed", task.name, task.attempt);
+            false
+        }
+        None => {
+            tracing::info!(task = %task.name, duration_ms = started.elapsed().as_secs_f64() * 1000.0, "task {} done", task.name);
+            true
+        }
+    };
+    let _ = tx.send(Command::Finished(task, ok));
+}
+
+/// JSON-lines log of queue changes. Replaying it rebuilds the pending set,
+/// which is then written back compacted.
+struct Journal {
+    path: PathBuf,
+    file: Option<std::fs::File>,
+}
+
+impl Journal {
+    fn new(path: PathBuf) -> Self {
+        Self { path, file: None }
+    }
+
+    fn replay(&mut self) -> Vec<Task> {
+        let mut pending: HashMap<String, Task> = HashMap::new();
+        if let Ok(raw) = std::fs::read_to_string(&self.path) {
+            // A torn last line from a crash is skipped along with any other bad line
+            for entry in raw.lines().filter_map(|line| serde_json::from_str::<JournalEntry>(line).ok()) {
+                match entry {
+                    JournalEntry::Add(task) => {
+                        pending.insert(task.id.clone(), task);
+                    }
+                    JournalEntry::Retry { id, run_at_ms, attempt } => {
+                        if let Some(task) = pending.get_mut(&id) {
+                            task.run_at_ms = run_at_ms;
+                            task.attempt = attempt;
+                        }
+                    }
+                    JournalEntry::Done { id } => {
+                        pending.remove(&id);
+                    }
+                }
+            }
+        }
+
+        let mut tasks: Vec<Task> = pending.into_values().collect();
+        tasks.sort_by_key(|t| t.run_at_ms);
+        if let Err(e) = self.compact(&tasks) {
+            tracing::warn!("Task journal unavailable: {}", e);
+        }
+        tasks
+    }
+
+    fn compact(&mut self, tasks: &[Task]) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        let mut out = String::new();
+        for task in tasks {
+            out.push_str(&serde_json::to_string(&JournalEntry::Add(task.clone())).unwrap_or_default());
+            out.push('\n');
+        }
+        std::fs::write(&tmp, out)?;
+        std::fs::rename(&tmp, &self.path)?;
+        self.file = Some(std::fs::OpenOptions::new().append(true).open(&self.path)?);
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &JournalEntry) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        let mut line = serde_json::to_string(entry).unwrap_or_default();
+        line.push('\n');
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            tracing::error!("Task journal write failed: {}", e);
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+fn new_task_id() -> String {
+    let mut bytes = [0u8; 12];
+    let _ = SystemRandom::new().fill(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,268 @@
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+
+
+// A client that hasn't finished its handshake by then is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A certificate chain and its private key, as PEM files.
+#[derive(Clone)]
+struct CertFiles {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl CertFiles {
+    fn from_config(config: &Value, root: &Path) -> Result<Self, String> {
+        let path = |field: &str| {
+            config[field]
+                .as_str()
+                .map(|p| root.join(p))
+                .ok_or_else(|| format!("tls: \"{}\" is required", field))
+        };
+        Ok(Self { cert: path("cert")?, key: path("key")? })
+    }
+
+    fn load(&self) -> Result<Arc<CertifiedKey>, String> {
+        let read_error = |path: &Path, e: rustls::pki_types::pem::Error| format!("{}: {}", path.display(), e);
+        let chain = CertificateDer::pem_file_iter(&self.cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| read_error(&self.cert, e))?;
+        if chain.is_empty() {
+            return Err(format!("{}: no certificates found", self.cert.display()));
+        }
+        let key = PrivateKeyDer::from_pem_file(&self.key).map_err(|e| read_error(&self.key, e))?;
+        let signing_key = ring::sign::any_supported_type(&key).map_err(|e| format!("{}: {}", self.key.display(), e))?;
+        let certified = CertifiedKey::new(chain, signing_key);
+        certified
+            .keys_match()
+            .map_err(|e| format!("{} does not belong to {}: {}", self.key.display(), self.cert.display(), e))?;
+        Ok(Arc::new(certified))
+    }
+
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let mtime = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        Some((mtime(&self.cert)?, mtime(&self.key)?))
+    }
+}
+
+/// The `tls` block of titan.config:
+///
+/// - `cert`, `key`: the default certificate, used when no `sni` name matches
+/// - `sni`: hostname (or `*.domain`) to `{ cert, key }`
+/// - `h2`: offer HTTP/2 through ALPN, defaults to true
+/// - `reload_ms`: how often the files are checked for changes, 0 turns reloading off
+pub struct TlsConfig {
+    default: Option<CertFiles>,
+    sni: Vec<(String, CertFiles)>,
+    h2: bool,
+    reload: Option<Duration>,
+}
+
+impl TlsConfig {
+    /// None when there's no `tls` block, which serves plain HTTP.
+    pub fn from_config(config: &Value, root: &Path) -> Result<Option<Self>, String> {
+        if config.is_null() || config == &Value::Bool(false) {
+            return Ok(None);
+        }
+        let default = (!config["cert"].is_null() || !config["key"].is_null())
+            .then(|| CertFiles::from_config(config, root))
+            .transpose()?;
+        let sni = match config["sni"].as_object() {
+            Some(hosts) => hosts
+                .iter()
+                .map(|(host, files)| Ok((host.to_ascii_lowercase(), CertFiles::from_config(files, root)?)))
+                .collect::<Result<Vec<_>, String>>()?,
+            None => Vec::new(),
+        };
+        if default.is_none() && sni.is_empty() {
+            return Err("tls: set \"cert\" and \"key\", or at least one \"sni\" entry".to_string());
+        }
+        Ok(Some(Self {
+            default,
+            sni,
+            h2: config["h2"].as_bool().unwrap_or(true),
+            reload: Some(Duration::from_millis(config["reload_ms"].as_u64().unwrap_or(5_000))).filter(|d| !d.is_zero()),
+        }))
+    }
+
+    fn files(&self) -> impl Iterator<Item = &CertFiles> {
+        self.default.iter().chain(self.sni.iter().map(|(_, files)| files))
+    }
+
+    fn load(&self) -> Result<Certs, String> {
+        Ok(Certs {
+            default: self.default.as_ref().map(CertFiles::load).transpose()?,
+            by_host: self
+                .sni
+                .iter()
+                .map(|(host, files)| Ok((host.clone(), files.load()?)))
+                .collect::<Result<_, String>>()?,
+        })
+    }
+}
+
+struct Certs {
+    default: Option<Arc<CertifiedKey>>,
+    by_host: HashMap<String, Arc<CertifiedKey>>,
+}
+
+/// Picks the certificate for the SNI name of each handshake. The set is
+/// swapped as a whole on reload, so a handshake never sees half of it.
+#[derive(Debug)]
+struct Resolver(RwLock<Arc<Certs>>);
+
+impl std::fmt::Debug for Certs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Certs").field("hosts", &self.by_host.keys()).finish()
+    }
+}
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let certs = self.0.read().unwrap().clone();
+        let Some(name) = hello.server_name().map(str::to_ascii_lowercase) else {
+            return certs.default.clone();
+        };
+        let wildcard = name.split_once('.').map(|(_, domain)| format!("*.{}", domain));
+        certs
+            .by_host
+            .get(&name)
+            .or_else(|| wildcard.and_then(|w| certs.by_host.get(&w)))
+            .or(certs.default.as_ref())
+            .cloned()
+    }
+}
+
+/// Connections that finished their TLS handshake, for `axum::serve`.
+/// Handshakes run on their own tasks so a slow client can't hold up the
+/// accept loop.
+pub struct TlsListener {
+    rx: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+/// Loaded certificates, kept up to date while the server runs. The TCP and
+/// QUIC listeners share them.
+pub struct Tls {
+    resolver: Arc<Resolver>,
+    h2: bool,
+}
+
+impl TlsConfig {
+    /// Loads the certificates and starts watching their files.
+    pub fn start(self) -> Result<Tls, String> {
+        let resolver = Arc::new(Resolver(RwLock::new(Arc::new(self.load()?))));
+        let h2 = self.h2;
+        if let Some(interval) = self.reload {
+            tokio::spawn(watch(self, resolver.clone(), interval));
+        }
+        Ok(Tls { resolver, h2 })
+    }
+}
+
+impl Tls {
+    /// A rustls config that picks certificates by SNI and offers `alpn`.
+    pub fn server_config(
+        &self,
+        versions: &[&'static rustls::SupportedProtocolVersion],
+        alpn: &[&[u8]],
+    ) -> Result<rustls::ServerConfig, String> {
+        let mut config = rustls::ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_protocol_versions(versions)
+            .map_err(|e| e.to_string())?
+            .with_no_client_auth()
+            .with_cert_resolver(self.resolver.clone());
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        Ok(config)
+    }
+
+    /// Starts accepting TLS connections on `listener`.
+    pub fn listen(&self, listener: TcpListener) -> Result<TlsListener, String> {
+        let alpn: &[&[u8]] = if self.h2 { &[b"h2", b"http/1.1"] } else { &[b"http/1.1"] };
+        let acceptor = TlsAcceptor::from(Arc::new(self.server_config(rustls::DEFAULT_VERSIONS, alpn)?));
+        let local_addr = listener.local_addr().map_err(|e| e.to_string())?;
+
+        let (tx, rx) = mpsc::channel(128);
+        tokio::spawn(async move {
+            loop {
+                // Once the server stops accepting, the socket is left to a
+                // restarted process (or closed)
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    _ = tx.closed() => return,
+                };
+                let (stream, addr) = match accepted {
+                    Ok(conn) => conn,
+                    Err(_) => {
+                        // Usually out of file descriptors; give it a moment
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        continue;
+                    }
+                };
+                let _ = stream.set_nodelay(true);
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    if let Ok(Ok(stream)) = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        let _ = tx.send((stream, addr)).await;
+                    }
+                });
+            }
+        });
+
+        Ok(TlsListener { rx, local_addr })
+    }
+}
+
+/// Reloads every certificate once any of the files changes. A set that
+/// fails to load is reported and the previous one stays in use.
+async fn watch(config: TlsConfig, resolver: Arc<Resolver>, interval: Duration) {
+    let stamps = |config: &TlsConfig| config.files().map(CertFiles::modified).collect::<Vec<_>>();
+    let mut last = stamps(&config);
+    loop {
+        tokio::time::sleep(interval).await;
+        let current = stamps(&config);
+        if current == last {
+            continue;
+        }
+        last = current;
+        match config.load() {
+            Ok(certs) => {
+                *resolver.0.write().unwrap() = Arc::new(certs);
+                tracing::info!("TLS certificates reloaded");
+            }
+            Err(e) => tracing::error!("TLS reload failed, keeping the old certificates: {}", e),
+        }
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.rx.recv().await {
+            Some(conn) => conn,
+            // The accept loop never stops while the listener is alive
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
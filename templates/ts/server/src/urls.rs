@@ -0,0 +1,204 @@
+//! `url.for()`: paths built from the route table, so an action links to a
+//! route by the action that serves it instead of by a path written out by
+//! hand that can drift from the router.
+
+use percent_encoding::{AsciiSet, CONTROLS, NON_ALPHANUMERIC, utf8_percent_encode};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::action_management::{DynamicRoute, RouteVal};
+use crate::groups::Groups;
+use crate::router::{self, Segment};
+
+// One table per app root, since each app of a multi-app server has its own routes
+static TABLES: Mutex<Vec<(PathBuf, Arc<Urls>)>> = Mutex::new(Vec::new());
+
+// What must be escaped inside one path segment
+const SEGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'/').add(b'<').add(b'>').add(b'?').add(b'`').add(b'{').add(b'}');
+// Everything but unreserved characters, in query names and values
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+enum Part {
+    Static(String),
+    Param(String),
+    OptionalParam(String),
+    CatchAll(String),
+    OptionalCatchAll(String),
+}
+
+struct Template {
+    parts: Vec<Part>,
+    trailing_slash: bool,
+}
+
+/// The path templates of every action with a route, GET routes first, then
+/// in the order the router tries them: exact, dynamic, file.
+#[derive(Default)]
+pub struct Urls {
+    actions: HashMap<String, Vec<(bool, Template)>>,
+}
+
+impl Urls {
+    pub fn from_routes<'a>(
+        exact: &HashMap<String, RouteVal>,
+        dynamic: &[DynamicRoute],
+        actions: impl Iterator<Item = &'a String>,
+        groups: &Groups,
+    ) -> Self {
+        let mut urls = Self::default();
+
+        let mut keys: Vec<&String> = exact.keys().collect();
+        keys.sort();
+        for key in keys {
+            let route = &exact[key];
+            let Some(action) = route.value.as_str().filter(|_| route.r#type == "action") else {
+                continue;
+            };
+            // `GET:/users` or `/users`, which answers every method
+            let (method, path) = match key.split_once(':') {
+                Some((method, path)) if !key.starts_with('/') => (Some(method), path),
+                _ => (None, key.as_str()),
+            };
+            let parts = segments(path).map(|s| Part::Static(s.to_string())).collect();
+            urls.add(action, method.is_none_or(|m| m == "GET"), Template { parts, trailing_slash: slashed(path) });
+        }
+
+        for route in dynamic {
+            let parts = segments(&route.pattern)
+                .map(|s| match s.strip_prefix(':') {
+                    // `:id<number>` is the param `id`
+                    Some(param) => Part::Param(param.split_once('<').map_or(param, |(name, _)| name).to_string()),
+                    None => Part::Static(s.to_string()),
+                })
+                .collect();
+            urls.add(&route.action, route.method == "GET", Template { parts, trailing_slash: slashed(&route.pattern) });
+        }
+
+        let mut actions: Vec<&String> = actions.collect();
+        actions.sort();
+        for action in actions {
+            let Some((method, dirs)) = router::file_route(action, groups) else {
+                continue;
+            };
+            let parts = dirs
+                .iter()
+                .map(|dir| match router::parse_segment(dir) {
+                    Segment::Static(s) => Part::Static(s.to_string()),
+                    Segment::Param(name) => Part::Param(name.to_string()),
+                    Segment::OptionalParam(name) => Part::OptionalParam(name.to_string()),
+                    Segment::CatchAll(name) => Part::CatchAll(name.to_string()),
+                    Segment::OptionalCatchAll(name) => Part::OptionalCatchAll(name.to_string()),
+                })
+                .collect();
+            urls.add(action, method == "GET", Template { parts, trailing_slash: false });
+        }
+
+        for templates in urls.actions.values_mut() {
+            // Stable, so each source keeps its order
+            templates.sort_by_key(|(get, _)| !*get);
+        }
+        urls
+    }
+
+    fn add(&mut self, action: &str, get: bool, template: Template) {
+        self.actions.entry(action.to_string()).or_default().push((get, template));
+    }
+
+    /// Makes the table the one `url.for()` uses in the app at `root`.
+    pub fn register(self, root: &Path) {
+        let mut tables = TABLES.lock().unwrap();
+        tables.retain(|(r, _)| r != root);
+        tables.push((root.to_path_buf(), Arc::new(self)));
+    }
+}
+
+/// The path of the first route of `action` that `params` fill, with `query`
+/// appended. Values are percent-encoded; a catch-all takes an array of
+/// segments, or a string split at its slashes.
+pub fn build(root: &Path, action: &str, params: &Map<String, Value>, query: &Value) -> Result<String, String> {
+    let table = TABLES.lock().unwrap().iter().find(|(r, _)| r == root).map(|(_, urls)| urls.clone());
+    let templates = table
+        .as_ref()
+        .and_then(|urls| urls.actions.get(action))
+        .ok_or_else(|| format!("no route serves action \"{}\"", action))?;
+    let mut path = templates.iter().find_map(|(_, template)| fill(template, params)).ok_or_else(|| {
+        let needed: Vec<&str> = templates[0]
+            .1
+            .parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Param(name) | Part::CatchAll(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        format!("action \"{}\" needs params {}", action, needed.join(", "))
+    })?;
+
+    let mut pairs = Vec::new();
+    if let Some(query) = query.as_object() {
+        for (name, value) in query {
+            let values = match value {
+                Value::Array(items) => items.iter().filter_map(scalar).collect(),
+                other => scalar(other).into_iter().collect::<Vec<_>>(),
+            };
+            for value in values {
+                pairs.push(format!("{}={}", utf8_percent_encode(name, COMPONENT), utf8_percent_encode(&value, COMPONENT)));
+            }
+        }
+    }
+    if !pairs.is_empty() {
+        path.push('?');
+        path.push_str(&pairs.join("&"));
+    }
+    Ok(path)
+}
+
+// The path, if `params` has every value the template needs
+fn fill(template: &Template, params: &Map<String, Value>) -> Option<String> {
+    let encode = |segment: &str| utf8_percent_encode(segment, SEGMENT).to_string();
+    let mut out: Vec<String> = Vec::new();
+    for part in &template.parts {
+        match part {
+            Part::Static(s) => out.push(s.clone()),
+            Part::Param(name) => out.push(encode(&scalar(params.get(name)?)?)),
+            Part::OptionalParam(name) => out.extend(params.get(name).and_then(scalar).map(|value| encode(&value))),
+            Part::CatchAll(name) => {
+                let rest = params.get(name).map(list).filter(|rest| !rest.is_empty())?;
+                out.extend(rest.iter().map(|segment| encode(segment)));
+            }
+            Part::OptionalCatchAll(name) => out.extend(params.get(name).map(list).unwrap_or_default().iter().map(|segment| encode(segment))),
+        }
+    }
+    let mut path = format!("/{}", out.join("/"));
+    if template.trailing_slash && !out.is_empty() {
+        path.push('/');
+    }
+    Some(path)
+}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+fn slashed(path: &str) -> bool {
+    path.len() > 1 && path.ends_with('/')
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn list(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(items) => items.iter().filter_map(scalar).collect(),
+        Value::String(s) => segments(s).map(str::to_string).collect(),
+        other => scalar(other).into_iter().collect(),
+    }
+}
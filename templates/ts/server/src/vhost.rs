@@ -0,0 +1,140 @@
+//! Several apps in one process. Each entry of `apps` is another project
+//! root, with its own routes.json, actions and worker pool; requests go to
+//! the first one whose host and path prefix match, and to the main app
+//! otherwise.
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{HeaderValue, Request, Uri, header};
+use axum::response::Response;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+/// One entry of `apps`: `{ root, host, prefix, threads }`. `host` is a name
+/// or a list of them, `*.example.com` matching any subdomain; `prefix` is
+/// removed from the path the app sees. At least one of them is needed.
+pub struct Site {
+    pub root: PathBuf,
+    pub threads: Option<usize>,
+    hosts: Vec<String>,
+    prefix: Option<String>,
+}
+
+impl Site {
+    pub fn from_config(config: &Value, root: &Path) -> Result<Vec<Self>, String> {
+        let Some(entries) = config.as_array() else {
+            return Ok(Vec::new());
+        };
+        let mut sites = Vec::new();
+        for entry in entries {
+            let dir = entry["root"].as_str().ok_or("apps: every app needs a \"root\"")?;
+            let hosts: Vec<String> = match &entry["host"] {
+                Value::String(host) => vec![host.to_ascii_lowercase()],
+                Value::Array(hosts) => hosts.iter().filter_map(Value::as_str).map(str::to_ascii_lowercase).collect(),
+                _ => Vec::new(),
+            };
+            let prefix = entry["prefix"]
+                .as_str()
+                .map(|p| format!("/{}", p.trim_matches('/')))
+                .filter(|p| p != "/");
+            if hosts.is_empty() && prefix.is_none() {
+                return Err(format!("apps: {} needs a \"host\" or a \"prefix\"", dir));
+            }
+            let root = root.join(dir);
+            if !root.is_dir() {
+                return Err(format!("apps: {} is not a directory", root.display()));
+            }
+            sites.push(Site {
+                root,
+                threads: entry["threads"].as_u64().filter(|n| *n > 0).map(|n| n as usize),
+                hosts,
+                prefix,
+            });
+        }
+        Ok(sites)
+    }
+
+    /// The hosts and prefix it answers, for the startup log.
+    pub fn describe(&self) -> String {
+        let hosts = if self.hosts.is_empty() { "*".to_string() } else { self.hosts.join(", ") };
+        format!("{}{}", hosts, self.prefix.as_deref().unwrap_or(""))
+    }
+
+    fn matches(&self, host: &str, path: &str) -> bool {
+        let host_ok = self.hosts.is_empty()
+            || self.hosts.iter().any(|pattern| match pattern.strip_prefix("*.") {
+                Some(domain) => host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.') && sub.len() > 1),
+                None => pattern == host,
+            });
+        let prefix_ok = self.prefix.as_deref().is_none_or(|prefix| {
+            path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        host_ok && prefix_ok
+    }
+}
+
+/// The routes and `__config` of an app, from the routes.json in its root.
+pub fn load_routes(root: &Path) -> Result<Value, String> {
+    let path = root.join("routes.json");
+    let raw = std::fs::read_to_string(&path).map_err(|e| format!("apps: {}: {}", path.display(), e))?;
+    serde_json::from_str(&raw).map_err(|e| format!("apps: {}: {}", path.display(), e))
+}
+
+/// The router in front of every app.
+pub struct Vhosts {
+    sites: Vec<(Site, Router)>,
+    fallback: Router,
+}
+
+impl Vhosts {
+    pub fn new(fallback: Router) -> Self {
+        Self { sites: Vec::new(), fallback }
+    }
+
+    pub fn add(&mut self, site: Site, router: Router) {
+        self.sites.push((site, router));
+    }
+
+    pub fn into_router(self) -> Router {
+        let hosts = Arc::new(self);
+        Router::new().fallback(move |req: Request<Body>| {
+            let hosts = hosts.clone();
+            async move { hosts.dispatch(req).await }
+        })
+    }
+
+    async fn dispatch(&self, mut req: Request<Body>) -> Response {
+        // HTTP/2 and HTTP/3 carry the host as the URI's authority
+        let host = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .or_else(|| req.uri().authority().map(|a| a.as_str()))
+            .map(|h| h.rsplit_once(':').filter(|(_, port)| port.parse::<u16>().is_ok()).map_or(h, |(name, _)| name))
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let found = self.sites.iter().find(|(site, _)| site.matches(&host, req.uri().path()));
+        let Some((site, router)) = found else {
+            return self.fallback.clone().oneshot(req).await.unwrap_or_else(|e| match e {});
+        };
+        if let Some(prefix) = &site.prefix {
+            let rest = &req.uri().path()[prefix.len()..];
+            let path = match req.uri().query() {
+                Some(query) => format!("{}?{}", if rest.is_empty() { "/" } else { rest }, query),
+                None => if rest.is_empty() { "/" } else { rest }.to_string(),
+            };
+            let mut parts = req.uri().clone().into_parts();
+            parts.path_and_query = path.parse().ok();
+            if let Ok(uri) = Uri::from_parts(parts) {
+                *req.uri_mut() = uri;
+            }
+            // So the app can build links that work from outside
+            if let Ok(value) = HeaderValue::from_str(prefix) {
+                req.headers_mut().insert("x-forwarded-prefix", value);
+            }
+        }
+        router.clone().oneshot(req).await.unwrap_or_else(|e| match e {})
+    }
+}
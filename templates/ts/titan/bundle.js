@@ -9,6 +9,9 @@ export async function bundle() {
 
   const start = Date.now();
   await bundleJs(actionsDir, outDir);
+  await bundleMiddleware(root, outDir);
+  await bundleJobs(root, outDir);
+  await bundleTasks(root, outDir);
   // console.log(`[Titan] Bundle finished in ${((Date.now() - start) / 1000).toFixed(2)}s`);
 }
 
@@ -20,26 +23,30 @@ async function bundleJs(actionsDir, outDir) {
   // Clean old bundles
   const oldFiles = fs.readdirSync(outDir);
   for (const file of oldFiles) {
-    fs.unlinkSync(path.join(outDir, file));
+    fs.rmSync(path.join(outDir, file), { recursive: true, force: true });
   }
 
-  const files = fs.readdirSync(actionsDir).filter(f => f.endsWith(".js") || f.endsWith(".ts"));
+  // Nested actions are named by their relative path (users/[id]/get), which
+  // the server's file router maps to a route
+  const files = listActionFiles(actionsDir);
   if (files.length === 0) return;
 
   // console.log(`[Titan] Bundling ${files.length} JS actions...`);
 
   for (const file of files) {
-    const actionName = path.basename(file, path.extname(file));
+    const actionName = file.slice(0, -path.extname(file).length);
+    const exportName = path.posix.basename(actionName);
 
     const entry = path.join(actionsDir, file);
 
     // Rust runtime expects `.jsbundle` extension — consistent with previous design
     const outfile = path.join(outDir, actionName + ".jsbundle");
+    fs.mkdirSync(path.dirname(outfile), { recursive: true });
 
     // console.log(`[Titan] Bundling ${entry} → ${outfile}`);
 
     await esbuild.build({
-      entryPoints: [entry],
+      ...(/\.(jsx|tsx)$/.test(file) ? ssrOptions(entry) : { entryPoints: [entry] }),
       outfile,
       bundle: true,
       format: "iife",
@@ -47,22 +54,26 @@ async function bundleJs(actionsDir, outDir) {
       platform: "neutral",
       target: "es2020",
       logLevel: "silent",
+      // Lets the server map stack traces back to app/ sources
+      sourcemap: "external",
       banner: {
-        js: "const defineAction = (fn) => fn; const Titan = t;"
+        js: "const defineAction = (fn, schema) => (schema ? globalThis.defineAction(fn, schema) : fn); const Titan = t;"
       },
 
       footer: {
         js: `
    (function () {
   const fn =
-    __titan_exports["${actionName}"] ||
+    __titan_exports["${exportName}"] ||
     __titan_exports.default;
 
   if (typeof fn !== "function") {
     throw new Error("[Titan] Action '${actionName}' not found or not a function");
   }
 
-  globalThis["${actionName}"] = globalThis.defineAction(fn);
+  const action = globalThis.defineAction(fn, __titan_exports.schema, __titan_exports.config);
+  if (__titan_exports.__titan_render) action.__titan_render = __titan_exports.__titan_render;
+  globalThis["${actionName}"] = action;
 })();
     `
       }
@@ -71,3 +82,179 @@ async function bundleJs(actionsDir, outDir) {
 
   // console.log("[Titan] JS Bundling finished.");
 }
+
+// A .jsx/.tsx action is bundled together with React's web-stream renderer,
+// which res.renderStream() picks up from the `__titan_render` export
+function ssrOptions(entry) {
+  const spec = JSON.stringify("./" + path.basename(entry));
+  return {
+    stdin: {
+      contents: [
+        `import * as action from ${spec};`,
+        `import { renderToReadableStream } from "react-dom/server";`,
+        `export * from ${spec};`,
+        `export default action.default;`,
+        `export const __titan_render = renderToReadableStream;`
+      ].join("\n"),
+      resolveDir: path.dirname(entry),
+      sourcefile: path.basename(entry) + ".ssr.js",
+      loader: "js"
+    },
+    jsx: "automatic",
+    // The web build of react-dom/server, not the Node one
+    conditions: ["worker", "browser"],
+    define: { "process.env.NODE_ENV": JSON.stringify(process.env.NODE_ENV || "production") }
+  };
+}
+
+function listActionFiles(dir, prefix = "") {
+  const files = [];
+  for (const entry of fs.readdirSync(dir, { withFileTypes: true })) {
+    const rel = prefix + entry.name;
+    if (entry.isDirectory()) {
+      files.push(...listActionFiles(path.join(dir, entry.name), rel + "/"));
+    } else if (/\.(js|ts|jsx|tsx)$/.test(entry.name) && !entry.name.endsWith(".d.ts")) {
+      files.push(rel);
+    }
+  }
+  return files;
+}
+
+// app/middleware.{ts,js}: its default export (one function or an array of
+// them) runs in the worker before every action; its other exports are the
+// named middleware route groups can add
+async function bundleMiddleware(root, outDir) {
+  const entry = ["middleware.ts", "middleware.js"]
+    .map(f => path.join(root, "app", f))
+    .find(f => fs.existsSync(f));
+  if (!entry) return;
+
+  await esbuild.build({
+    entryPoints: [entry],
+    outfile: path.join(outDir, "__titan_middleware.jsbundle"),
+    bundle: true,
+    format: "iife",
+    globalName: "__titan_exports",
+    platform: "neutral",
+    target: "es2020",
+    logLevel: "silent",
+    sourcemap: "external",
+    banner: {
+      js: "const Titan = t;"
+    },
+    footer: {
+      js: `
+(function () {
+  const chain = __titan_exports.default || __titan_exports.middleware || [];
+  globalThis.__titan_middleware = (Array.isArray(chain) ? chain : [chain])
+    .filter(fn => typeof fn === "function");
+  globalThis.__titan_named_middleware = Object.fromEntries(Object.entries(__titan_exports)
+    .filter(([name, fn]) => name !== "default" && name !== "middleware" && typeof fn === "function"));
+})();
+    `
+    }
+  });
+}
+
+// app/jobs/*.{ts,js}: bundled as `__titan_job_<name>` actions and listed in
+// __titan_jobs.json for the server's scheduler. The cron expression is read
+// from `export const schedule = "..."` in the source, so it must be a literal.
+async function bundleJobs(root, outDir) {
+  const jobsDir = path.join(root, "app", "jobs");
+  if (!fs.existsSync(jobsDir)) return;
+
+  const manifest = [];
+  const files = fs.readdirSync(jobsDir)
+    .filter(f => (f.endsWith(".js") || f.endsWith(".ts")) && !f.endsWith(".d.ts"));
+
+  for (const file of files) {
+    const name = path.basename(file, path.extname(file));
+    const entry = path.join(jobsDir, file);
+    const source = fs.readFileSync(entry, "utf-8");
+    const schedule = source.match(/export\s+const\s+schedule\s*=\s*(["'`])(.+?)\1/);
+    if (!schedule) {
+      console.warn(`[Titan] Skipping job '${name}': no \`export const schedule = "<cron>"\` found`);
+      continue;
+    }
+    const jitter = source.match(/export\s+const\s+jitterMs\s*=\s*(\d+)/);
+    const actionName = `__titan_job_${name}`;
+
+    await esbuild.build({
+      entryPoints: [entry],
+      outfile: path.join(outDir, actionName + ".jsbundle"),
+      bundle: true,
+      format: "iife",
+      globalName: "__titan_exports",
+      platform: "neutral",
+      target: "es2020",
+      logLevel: "silent",
+      sourcemap: "external",
+      banner: {
+        js: "const defineAction = (fn) => fn; const Titan = t;"
+      },
+      footer: {
+        js: `
+(function () {
+  const fn = __titan_exports.default || __titan_exports.run || __titan_exports["${name}"];
+
+  if (typeof fn !== "function") {
+    throw new Error("[Titan] Job '${name}' has no default export or run() function");
+  }
+
+  globalThis["${actionName}"] = globalThis.defineAction(fn);
+})();
+    `
+      }
+    });
+
+    manifest.push({ name, schedule: schedule[2], jitter_ms: jitter ? Number(jitter[1]) : 0 });
+  }
+
+  fs.writeFileSync(path.join(outDir, "__titan_jobs.json"), JSON.stringify(manifest, null, 2));
+}
+
+// app/tasks/*.{ts,js}: bundled as `__titan_task_<name>` actions. The default
+// export gets the payload given to `tasks.enqueue()` and `{ id, attempt }`.
+async function bundleTasks(root, outDir) {
+  const tasksDir = path.join(root, "app", "tasks");
+  if (!fs.existsSync(tasksDir)) return;
+
+  const files = fs.readdirSync(tasksDir)
+    .filter(f => (f.endsWith(".js") || f.endsWith(".ts")) && !f.endsWith(".d.ts"));
+
+  for (const file of files) {
+    const name = path.basename(file, path.extname(file));
+    const actionName = `__titan_task_${name}`;
+
+    await esbuild.build({
+      entryPoints: [path.join(tasksDir, file)],
+      outfile: path.join(outDir, actionName + ".jsbundle"),
+      bundle: true,
+      format: "iife",
+      globalName: "__titan_exports",
+      platform: "neutral",
+      target: "es2020",
+      logLevel: "silent",
+      sourcemap: "external",
+      banner: {
+        js: "const defineAction = (fn) => fn; const Titan = t;"
+      },
+      footer: {
+        js: `
+(function () {
+  const fn = __titan_exports.default || __titan_exports.run || __titan_exports["${name}"];
+
+  if (typeof fn !== "function") {
+    throw new Error("[Titan] Task '${name}' has no default export or run() function");
+  }
+
+  globalThis["${actionName}"] = globalThis.defineAction((req) => fn(
+    req.json(),
+    { id: req.headers["x-titan-task-id"], attempt: Number(req.headers["x-titan-task-attempt"]) }
+  ));
+})();
+    `
+      }
+    });
+  }
+}
@@ -10,14 +10,461 @@ export interface TitanBuilder {
     get(route: string): RouteHandler;
     post(route: string): RouteHandler;
     log(module: string, msg: string): void;
+    /** Server options written to `__config`, e.g. `{ timeout_ms: 10000 }` (0 disables the deadline). */
+    config(options: TitanServerConfig): void;
     start(port?: number, msg?: string, threads?: number): Promise<void>;
 }
 
+export interface TitanServerConfig {
+    /** Per-request action deadline in milliseconds. Defaults to 30000. */
+    timeout_ms?: number;
+    /** How long shutdown waits for in-flight requests before terminating them. Defaults to 10000. */
+    shutdown_timeout_ms?: number;
+    /** V8 heap cap per worker isolate in megabytes. Unset means the V8 default. */
+    max_heap_mb?: number;
+    /** Longest an action may run JS without yielding before it is terminated. Unset means no limit. */
+    max_execution_ms?: number;
+    /** Idle interval after which an SSE stream gets a keep-alive comment. Defaults to 15000. */
+    sse_keep_alive_ms?: number;
+    /** Replace a worker's isolate after it has served this many requests. */
+    recycle_after_requests?: number;
+    /** Replace a worker's isolate once its used heap passes this many megabytes. */
+    recycle_heap_mb?: number;
+    /** Most requests that may wait for a worker. Defaults to 100 per worker thread; 0 means unbounded. */
+    queue_limit?: number;
+    /** What to do when the queue is full: answer 503 right away, or wait `queue_timeout_ms` for room first. Defaults to "reject". */
+    queue_policy?: "reject" | "block";
+    /** How long "block" waits for a queue slot. Defaults to 1000. */
+    queue_timeout_ms?: number;
+    /**
+     * Resize `queue_limit` from the p99 queue wait of each `interval_ms` window: shrink while it is over `target_p99_ms`,
+     * grow back toward `max` while requests are shed. "warn" mode only logs.
+     */
+    queue_adaptive?: boolean | {
+        target_p99_ms?: number;
+        min?: number;
+        max?: number;
+        interval_ms?: number;
+        mode?: "resize" | "warn";
+    };
+    /** Commands each worker's own channel holds before senders wait. Defaults to 100. */
+    worker_channel_capacity?: number;
+    /**
+     * Queue lane per action name; unlisted actions are "normal". "high" requests are taken before any other queued work
+     * and are never shed by `queue_limit`; "low" ones get a turn now and then even under steady higher-priority load.
+     */
+    priority?: Record<string, "high" | "normal" | "low">;
+    /**
+     * Send requests still unanswered after `after_ms` (default 100) to a second worker and keep the first answer.
+     * Only `methods` (default GET and HEAD) and, when given, `actions` are hedged.
+     */
+    hedge?: boolean | { after_ms?: number; methods?: string[]; actions?: string[] };
+    /**
+     * Stop a request when its client disconnects (default true): a queued one is skipped, running JS is
+     * interrupted and one suspended in drift() is dropped. With false it runs to the end; `ctx.signal` fires either way.
+     */
+    cancel_on_disconnect?: boolean;
+    /**
+     * Per action name, the key that pins its requests to one worker, so per-isolate caches stay warm.
+     * "session" is the session cookie; requests without their key go to any worker.
+     */
+    affinity?: Record<string, "action" | "path" | "session" | `header:${string}` | `query:${string}` | `param:${string}` | `cookie:${string}`>;
+    /**
+     * Worker pools of their own for the tenants named by `key` (default "header:x-tenant-id"). Each pool serves the tenant
+     * it is named after, or the ids in `tenants`, with its own queue and limits; unset limits are the main pool's.
+     * Requests of other tenants go to the main pool.
+     */
+    tenants?: {
+        key?: "subdomain" | `header:${string}` | `query:${string}` | `cookie:${string}` | `claim:${string}`;
+        pools: Record<string, {
+            /** Defaults to 1. */
+            threads?: number;
+            tenants?: string[];
+            max_heap_mb?: number;
+            max_execution_ms?: number;
+            queue_limit?: number;
+        }>;
+    };
+    /**
+     * Pin each worker thread to a core (Linux). `cores` is a list or ranges like "0-7,16-23"; with `numa` (default on
+     * multi-node machines) workers alternate between nodes and allocate from their own.
+     */
+    cpu_affinity?: boolean | { cores?: number[] | string; numa?: boolean };
+    /**
+     * Fail calls to an upstream fast once `failures` (default 5) in a row went wrong, for `open_ms` (default 30000),
+     * then let `half_open_probes` (default 1) through. Covers fetch per host, each database and each Redis server.
+     */
+    circuit_breaker?: boolean | { failures?: number; open_ms?: number; half_open_probes?: number };
+    /**
+     * Grow and shrink the worker pool with queue wait instead of running a fixed `threads`. Starts at `min` (default: core count)
+     * and never goes past `max` (default: `threads`). `true` uses every default.
+     */
+    autoscale?: boolean | {
+        min?: number;
+        max?: number;
+        /** Average queue wait that counts as pressure. Defaults to 50. */
+        scale_up_wait_ms?: number;
+        /** Queue wait at or below which an idle queue counts as slack. Defaults to 5. */
+        scale_down_wait_ms?: number;
+        /** How long pressure must last before a worker is added. Defaults to 1000. */
+        scale_up_after_ms?: number;
+        /** How long slack must last before a worker is parked. Defaults to 30000. */
+        scale_down_after_ms?: number;
+        /** How often queue wait is sampled. Defaults to 500. */
+        interval_ms?: number;
+    };
+    /** Include the JS stack in error responses and error pages. Stacks are always logged. Defaults to false. */
+    error_stacks?: boolean;
+    /** Add `queue`, `exec` and `serialize` times to `Server-Timing` and to the request log lines. Defaults to false. */
+    server_timing?: boolean;
+    /** Serve Prometheus metrics at `/metrics`. Defaults to true. */
+    metrics?: boolean;
+    /**
+     * Serve the admin API (drain, log level, cache purge, stats, reload) on this port, bound to 127.0.0.1,
+     * or on `host`/`socket`. With `api_key` (or `TITAN_ADMIN_KEY`), every call must send it.
+     */
+    admin?: number | { port?: number; host?: string; socket?: string; mode?: number | string; api_key?: string };
+    /** Serve the `/healthz` and `/readyz` probes. Defaults to true. */
+    health?: boolean;
+    /** How long `/healthz` waits for a worker to answer, in milliseconds. Defaults to 1000. */
+    health_timeout_ms?: number;
+    /** Sample every worker's CPU and serve the profiles at `/profile` on the `admin` listener. `TITAN_PROFILE=1` turns it on too. */
+    profile?: boolean | {
+        enabled?: boolean;
+        /** Sampling interval in microseconds. Defaults to 1000. */
+        interval_us?: number;
+    };
+    /**
+     * Log the JS stack of any action still running after this many milliseconds, with its request ID.
+     * `frames` is how many frames each report shows (default 20).
+     */
+    slow_requests?: number | { threshold_ms: number; frames?: number };
+    /** Allow `POST /heap-snapshot` on the `admin` listener. `TITAN_HEAP_SNAPSHOTS=1` turns it on too. */
+    heap_snapshots?: boolean | {
+        enabled?: boolean;
+        /** Directory under the project root. Defaults to ".titan/heap". */
+        dir?: string;
+        /** How many snapshots to keep; older ones are deleted. Defaults to 5. */
+        keep?: number;
+    };
+    /** Serve a GraphQL schema. On when `app/schema.graphql` exists; `false` turns it off. */
+    graphql?: boolean | {
+        enabled?: boolean;
+        /** The endpoint. Defaults to "/graphql". */
+        path?: string;
+        /** The SDL file, under the project root. Defaults to "app/schema.graphql". */
+        schema?: string;
+        /** `"Type.field"` to the action that resolves it; a batch resolver gets a whole level's calls at once. */
+        resolvers?: Record<string, string | { action?: string; batch?: boolean }>;
+        /** How deeply fields may nest. Defaults to 15. */
+        max_depth?: number;
+        /** Answer `__schema` and `__type` queries. Defaults to true. */
+        introspection?: boolean;
+    };
+    /** Serve the services in .proto files over gRPC, each method answered by an action. */
+    grpc?: boolean | {
+        enabled?: boolean;
+        /** Defaults to 50051. */
+        port?: number;
+        /** The directory of .proto files, under the project root. Defaults to "app/protos". */
+        protos?: string;
+        /** `"package.Service/Method"` to the action that answers it; by default the action named after the method. */
+        methods?: Record<string, string>;
+        /** The largest message accepted, in MB. Defaults to 4. */
+        max_message_mb?: number;
+    };
+    /**
+     * Keep GET responses of the actions in `routes` in memory. Hits skip the worker pool but still go through
+     * auth and rate limits. `max_entries` defaults to 10000; `cache.purge()` drops entries from an action.
+     */
+    cache?: { max_entries?: number; routes: Record<string, TitanCacheRule> };
+    /**
+     * Run each `Idempotency-Key` once: retries with the same key, action and `Authorization` get the stored response,
+     * a 409 while the first request runs, or a 422 when the request differs. Covers POST and PATCH unless `methods` says
+     * otherwise. Keys last `ttl_ms` (a day by default); a running request holds its key for at most `lock_ms` (60000).
+     */
+    idempotency?: boolean | {
+        header?: string;
+        methods?: string[];
+        actions?: string[];
+        ttl_ms?: number;
+        lock_ms?: number;
+        store?: "memory" | "redis";
+        redis_url?: string;
+    };
+    /** Hash action response bodies into an ETag for `If-None-Match`. Defaults to true; explicit ETags are always honoured. */
+    etag?: boolean;
+    /**
+     * Keys for signed cookies. The first signs and every one is accepted, so secrets can be rotated.
+     * `TITAN_COOKIE_SECRET` is used to sign when set.
+     */
+    cookies?: { secret?: string | string[] };
+    /**
+     * Serve an OpenAPI 3.1 document of the action routes at `<path>/openapi.json`, and Swagger UI at `path`
+     * (default `/docs`) unless `ui` is false. Each action describes itself with `export const schema`.
+     */
+    docs?: boolean | { path?: string; ui?: boolean; title?: string; version?: string; description?: string };
+    /** Answer requests that don't match their action's `schema` with 422 before they reach a worker. Defaults to true. */
+    validate?: boolean;
+    /** Log output. `TITAN_LOG_LEVEL` and `TITAN_LOG_FORMAT` take precedence. */
+    log?: {
+        /** Defaults to "info"; `t.setLogLevel()` changes it while the server runs. */
+        level?: "trace" | "debug" | "info" | "warn" | "error" | "off";
+        /** "pretty" (the default) for a terminal, or "json" for one object per line. */
+        format?: "pretty" | "json";
+    };
+    /**
+     * One line per response, in the `combined` format or as JSON, with the time split into queue, exec and serialize.
+     * `true` or a format logs to stdout; `path` writes a file under the project root, rotated at `max_bytes`.
+     */
+    access_log?: boolean | "combined" | "json" | {
+        format?: "combined" | "json";
+        path?: string;
+        /** Rotate the file past this size. Defaults to 100 MB; 0 never rotates. */
+        max_bytes?: number;
+        /** Rotated files kept as `access.log.1` to `.N`. Defaults to 5. */
+        keep?: number;
+        /** The share of responses logged, from 0 to 1. Defaults to 1. */
+        sample?: number;
+        /** Log 4xx and 5xx responses whatever the sample. Defaults to true. */
+        errors?: boolean;
+        /** Log responses slower than this many milliseconds whatever the sample. */
+        slow_ms?: number;
+    };
+    /** Require this key as `Authorization: Bearer` or `X-Api-Key` on every request. `TITAN_API_KEY` takes precedence. */
+    api_key?: string;
+    /** OTLP/HTTP collector base URL for trace export. `OTEL_EXPORTER_OTLP_ENDPOINT` takes precedence. */
+    otlp_endpoint?: string;
+    /** `service.name` on exported spans. `OTEL_SERVICE_NAME` takes precedence. Defaults to "titan". */
+    service_name?: string;
+    /** Directory multipart uploads are streamed to, relative to the project root. Defaults to the system temp dir. */
+    upload_dir?: string;
+    /** Largest accepted uploaded file in megabytes; bigger uploads get 413. Unset means no limit. */
+    upload_max_mb?: number;
+    /** Directory of static assets served without a worker, relative to the project root. Defaults to "public". */
+    public_dir?: string;
+    /** Directory of Handlebars templates for `t.render`, relative to the project root. Defaults to "app/views". */
+    views_dir?: string;
+    /** Deadline for a run of a job in app/jobs, in milliseconds (0 disables it). Defaults to `timeout_ms`. */
+    job_timeout_ms?: number;
+    /** File that pending background tasks are journaled to, relative to the project root. Unset keeps them in memory only. */
+    tasks_file?: string;
+    /** Most background tasks running at once. Defaults to the worker count. */
+    tasks_concurrency?: number;
+    /**
+     * gzip/brotli compression of action responses, negotiated from `Accept-Encoding`.
+     * `false` turns it off. Defaults to bodies of at least 1024 bytes with a text, JSON, JS, XML, wasm or SVG content type;
+     * `routes` overrides those settings per action name (`false` skips that action).
+     */
+    compression?: boolean | (TitanCompressionRule & { routes?: Record<string, boolean | TitanCompressionRule> });
+    /**
+     * Serve HTTPS. `cert`/`key` are PEM files relative to the project root; `sni` picks a different pair by
+     * hostname (`"*.example.com"` matches one label). Changed files are picked up every `reload_ms` (default 5000, 0 turns it off).
+     */
+    tls?: TitanTlsConfig;
+    /**
+     * Limits requests per client before they reach a worker; over-limit ones get 429 with `RateLimit-*` and `Retry-After` headers.
+     * Clients are told apart by IP, or by the first value of `key.header`. `routes` sets a policy per action name (`false` exempts it).
+     */
+    rate_limit?: Partial<TitanRateLimitPolicy> & { key?: "ip" | { header: string }; routes?: Record<string, boolean | Partial<TitanRateLimitPolicy>> };
+    /** Experimental HTTP/3 over QUIC, advertised with `Alt-Svc`. `true` listens on UDP at the HTTP port. Needs `tls`. */
+    http3?: boolean | { port?: number };
+    /**
+     * Listens on a Unix domain socket instead of the port, e.g. behind nginx on the same machine. `mode` sets the file's
+     * permissions (`"660"`). `--socket` wins. Under systemd socket activation the passed socket is used instead.
+     */
+    socket?: string | { path: string; mode?: number | string };
+    /**
+     * Verifies `Authorization: Bearer` JWTs before requests reach a worker; the claims arrive as `req.auth.claims`.
+     * Set one of `secret` (`TITAN_JWT_SECRET` wins), `public_key` or `jwks_url`.
+     */
+    auth?: TitanAuthConfig;
+    /**
+     * Sessions for `req.session`. By default the data lives in an AES-GCM encrypted cookie; the `"memory"` and `"redis"`
+     * stores keep it on the server behind a signed id. `TITAN_SESSION_SECRET` wins over `secret`.
+     */
+    session?: true | TitanSessionConfig;
+    /**
+     * Looks up each client address in local MaxMind databases (GeoLite2/GeoIP2 City or Country, plus ASN) for `req.geo`.
+     * Paths are relative to the project root and re-read when the files change. The address is `req.ip`.
+     */
+    geoip?: string | { database: string; asn_database?: string };
+    /**
+     * Proxies whose `Forwarded` / `X-Forwarded-For` headers are believed when working out `req.ip`: addresses, CIDR
+     * ranges, `"loopback"`, `"private"` or `"linklocal"`. Unset, the connecting peer is always the client.
+     */
+    trusted_proxies?: string | string[];
+    /**
+     * How paths match routes. `trailing_slash` "merge" (the default) serves `/users/` and `/users` alike, "strict" keeps
+     * them apart and "redirect" sends the other spelling to the routed one with a 301 (308 except for GET and HEAD).
+     * `case_insensitive` ignores the case of the fixed parts of a path; params keep theirs.
+     */
+    routing?: { trailing_slash?: "merge" | "strict" | "redirect"; case_insensitive?: boolean };
+    /**
+     * Settings shared by every action under a directory of app/actions, e.g. `"admin"`. `prefix` goes in front of
+     * its file routes; `middleware` names exports of app/middleware, run after the default chain, outer groups first;
+     * `auth` and `rate_limit` apply to each action not listed under `auth.routes` / `rate_limit.routes`.
+     */
+    groups?: Record<string, {
+        prefix?: string;
+        middleware?: string | string[];
+        auth?: boolean | "required" | "optional";
+        rate_limit?: false | Partial<TitanRateLimitPolicy>;
+    }>;
+    /**
+     * How `req.query` is shaped. "flat" (the default) keeps the last value of each name; "repeat" makes repeated names
+     * arrays; "bracket" also nests `tag[]`, `list[0]` and `filter[name]` like qs, up to `depth` (default 5) levels;
+     * "comma" splits `tag=a,b` into an array.
+     */
+    query?: "flat" | "repeat" | "bracket" | "comma" | { mode?: "flat" | "repeat" | "bracket" | "comma"; depth?: number };
+    /**
+     * CORS for actions. Preflights are answered without reaching a worker. `true` allows any origin without credentials;
+     * `routes` overrides the policy per action name (`false` turns CORS off for it).
+     */
+    cors?: true | (TitanCorsPolicy & { routes?: Record<string, boolean | TitanCorsPolicy> });
+    /**
+     * Request body limits. Bodies over `max_mb` (default 16, 0 for no limit) get 413 while they're being read.
+     * `routes` sets `{ max_mb, stream }` per action name; `stream: true` hands `req.body` over as a ReadableStream.
+     * Multipart uploads have their own `upload_max_mb`.
+     */
+    body?: { max_mb?: number; routes?: Record<string, { max_mb?: number; stream?: boolean }> };
+    /**
+     * More project roots served by this process, each with its own routes.json, actions and worker pool. A request goes
+     * to the first app whose `host` (`"shop.example.com"`, `"*.example.com"`) and `prefix` match, the prefix removed,
+     * and to this app otherwise. Listener, TLS, logging and other process-wide settings come from this app.
+     */
+    apps?: { root: string; host?: string | string[]; prefix?: string; threads?: number }[];
+    /** Directories under the project root that `fs.*` may read and write. Unset turns `fs` off. */
+    fs?: { allow?: string[] };
+    [key: string]: any;
+}
+
+export interface TitanRateLimitPolicy {
+    limit: number;
+    /** Defaults to 60000. */
+    window_ms?: number;
+    /** Defaults to "sliding_window". */
+    strategy?: "sliding_window" | "token_bucket";
+    /** Token bucket capacity. Defaults to `limit`. */
+    burst?: number;
+}
+
+export interface TitanAuthConfig {
+    /** HMAC secret for HS256/384/512 tokens. */
+    secret?: string;
+    /** PEM file with an RSA, Ed25519 or EC public key, relative to the project root. */
+    public_key?: string;
+    /** JWKS endpoint; keys are picked by `kid` and refetched every `jwks_refresh_ms` (default 600000). */
+    jwks_url?: string;
+    jwks_refresh_ms?: number;
+    /** Accepted `alg` values. Defaults to the one that fits the key. */
+    algorithms?: string[];
+    issuer?: string | string[];
+    audience?: string | string[];
+    /** Clock skew allowed on `exp` and `nbf`. Defaults to 60. */
+    leeway_s?: number;
+    /** `"optional"` lets requests without a token through. Defaults to true (required). */
+    default?: boolean | "optional";
+    /** Per action name; `false` skips verification. */
+    routes?: Record<string, boolean | "optional">;
+}
+
+export interface TitanSessionConfig {
+    secret?: string;
+    /** Defaults to "cookie". */
+    store?: "cookie" | "memory" | "redis";
+    redis_url?: string;
+    /** Sessions unused for this long expire. Defaults to 1800000 (30 minutes). */
+    idle_timeout_ms?: number;
+    /** How often the session id is replaced. Defaults to 900000, 0 turns it off. */
+    rotate_ms?: number;
+    /** Defaults to `titan.sid`, path `/`, SameSite Lax, and Secure when `tls` is set. */
+    cookie?: { name?: string; domain?: string; path?: string; secure?: boolean; same_site?: "Strict" | "Lax" | "None" };
+}
+
+export interface TitanCorsPolicy {
+    /** `"*"` (the default) or a list of origins; `"https://*.example.com"` matches its subdomains. */
+    origins?: "*" | string | string[];
+    /** Defaults to GET, HEAD, POST, PUT, PATCH and DELETE. */
+    methods?: string[];
+    /** Request headers a preflight may ask for. Unset allows the ones it asks for. */
+    headers?: string[];
+    expose_headers?: string[];
+    /** Allow cookies and `Authorization`. The origin is echoed instead of `*`. */
+    credentials?: boolean;
+    /** How long browsers may cache a preflight. Defaults to 600. */
+    max_age_s?: number;
+}
+
+export interface TitanTlsConfig {
+    cert?: string;
+    key?: string;
+    sni?: Record<string, { cert: string; key: string }>;
+    /** Offer HTTP/2 through ALPN. Defaults to true. */
+    h2?: boolean;
+    reload_ms?: number;
+}
+
+export interface TitanCompressionRule {
+    /** Smallest body worth compressing. */
+    min_bytes?: number;
+    /** Content-type prefixes to compress, e.g. `"text/"` or `"application/json"`. */
+    types?: string[];
+}
+
+export interface TitanCacheRule {
+    /** How long a response is served from the cache, in milliseconds. */
+    ttl_ms: number;
+    /** How long past `ttl_ms` the stale copy is still served while one request refreshes it. Defaults to 0. */
+    swr_ms?: number;
+    /** Request headers that get separate entries, e.g. `["authorization"]` for responses that differ per user. */
+    vary?: string[];
+    /** Names for `cache.purge()`; the action's name is always one. */
+    tags?: string[];
+}
+
+/** A JSON Schema, or a zod-like schema with a `toJSONSchema()` method. */
+export type TitanSchema = Record<string, any> | { toJSONSchema(): Record<string, any> };
+
+/** What an action exports as `schema`. It is documented at `/docs`, and requests are checked against it. */
+export interface TitanActionSchema {
+    summary?: string;
+    description?: string;
+    tags?: string[];
+    deprecated?: boolean;
+    /** Left out of the document. */
+    hidden?: boolean;
+    /** Object schemas whose properties are the path, query and header parameters. */
+    params?: TitanSchema;
+    query?: TitanSchema;
+    headers?: TitanSchema;
+    /** The JSON request body. */
+    body?: TitanSchema;
+    /** The 200 response, or responses keyed by status code. */
+    response?: TitanSchema | Record<number | "default", TitanSchema>;
+}
+
+/** What an action exports as `config`: how the server runs it. */
+export interface TitanActionConfig {
+    /** Requests of this action running at once, at most; the rest wait for a slot within their deadline. */
+    concurrency?: number;
+    /** Deadline of this action's requests in milliseconds, instead of `timeout_ms`; 0 for none. */
+    timeoutMs?: number;
+    /** Largest request body this action takes, in bytes, instead of `body.max_mb`; 0 for no limit. */
+    maxBodyBytes?: number;
+}
+
 declare const builder: TitanBuilder;
 export const Titan: TitanBuilder;
 export default builder;
 
-export declare function defineAction<T>(actionFn: (req: TitanRequest) => T): (req: TitanRequest) => T;
+/**
+ * Default export of `app/middleware.{js,ts}` (a single function or an array).
+ * Runs before every action; return a value to respond with it instead.
+ */
+export type TitanMiddleware = (req: TitanRequest, res: TitanResponseWriter) => any;
+
+export declare function defineAction<T>(actionFn: (req: TitanRequest, res: TitanResponseWriter, ctx: TitanContext) => T, schema?: TitanActionSchema, config?: TitanActionConfig): (req: TitanRequest) => T;
 
 // -- Global Definitions (Runtime Environment) --
 
@@ -47,7 +494,25 @@ declare global {
      */
     var drift: <T>(promise: Promise<T> | T) => T;
 
+    /** Passed to every action as its third argument, and as `req.ctx`. */
+    interface TitanContext {
+        /** The X-Request-Id sent or generated. */
+        requestId: string;
+        /** The W3C trace context, continuing the caller's `traceparent`. */
+        traceId?: string;
+        spanId?: string;
+        /** When the server stops waiting for the result, in epoch milliseconds; null without a request timeout. */
+        deadline: number | null;
+        /** Milliseconds until `deadline`; `Infinity` without one. */
+        remaining(): number;
+        /** Aborted when the client disconnects (`AbortError`) or the deadline passes (`TimeoutError`). */
+        signal: AbortSignal;
+        /** Same as `req.auth`; null without verified claims. */
+        auth: { claims: Record<string, any> } | null;
+    }
+
     interface TitanRequest {
+        /** The parsed body: MessagePack or CBOR by `Content-Type`, else JSON when it parses and text when it doesn't. Parsed when first read. */
         body: any;
         method: "GET" | "POST" | "PUT" | "DELETE" | "PATCH";
         path: string;
@@ -58,30 +523,399 @@ declare global {
             authorization?: string;
             [key: string]: string | undefined;
         };
-        params: Record<string, string>;
-        query: Record<string, string>;
+        /** Catch-all params, `[...name]` in the action's path, are arrays of segments. */
+        params: Record<string, string | string[]>;
+        /** One string per name, or arrays and objects when `query` in titan.config picks a structured mode. */
+        query: Record<string, any>;
+        /** Cookies from the `Cookie` header, decoded. Signed cookies are only in `signedCookies`. */
+        cookies: Record<string, string>;
+        /** Cookies set with `signed: true` whose signature is valid; tampered ones are left out. */
+        signedCookies: Record<string, string>;
+        /** The body exactly as it was received. Null without a body, and for multipart and streamed bodies. */
+        rawBody(): ArrayBuffer | null;
+        /** The body decoded as UTF-8; `""` without a body. */
+        text(): string;
+        /** The body parsed as JSON on first call; throws when it isn't JSON. Null without a body. */
+        json<T = any>(): T | null;
+        /** Present when the request was a WebSocket upgrade. */
+        websocket?: TitanSocket;
+        /** The client's address, through `trusted_proxies`; what rate limiting and `req.geo` go by. */
+        ip?: string;
+        /** The same context the action gets as its third argument. */
+        ctx: TitanContext;
+        /** Text fields of a `multipart/form-data` or URL-encoded body; repeated names and names ending in `[]` become arrays. */
+        fields?: Record<string, string | string[]>;
+        /** The same fields as `fields`; `{}` when the body isn't a form. */
+        form(): Record<string, string | string[]>;
+        /** Uploaded files of a `multipart/form-data` body, optionally only those of one field. */
+        files?: (field?: string) => TitanUploadedFile[];
+        /** Verified JWT claims, when `auth` is configured and a valid bearer token was sent. */
+        auth?: { claims: Record<string, any> };
+        /** Present when `session` is configured. Changes are saved once the action returns. */
+        session?: TitanSession;
+        /** Where the client is, when `geoip` is configured and the address is in the database. */
+        geo?: TitanGeo;
+        /** Set for app/actions/_not_found, _method_not_allowed and _error: what they answer for. */
+        error?: { status: number; message: string; action?: string; stack?: string; allow?: string[] };
+    }
+
+    interface TitanGeo {
+        /** ISO 3166-1 country code, e.g. `"DE"`. */
+        country?: string;
+        country_name?: string;
+        continent?: string;
+        /** ISO 3166-2 code of the first subdivision, e.g. `"BY"`. */
+        region?: string;
+        region_name?: string;
+        city?: string;
+        postal_code?: string;
+        latitude?: number;
+        longitude?: number;
+        /** IANA time zone, e.g. `"Europe/Berlin"`. */
+        timezone?: string;
+        /** From an ASN database. */
+        asn?: number;
+        as_org?: string;
+    }
+
+    interface TitanSession {
+        get<T = any>(key: string): T | undefined;
+        set(key: string, value: any): TitanSession;
+        delete(key: string): TitanSession;
+        all(): Record<string, any>;
+        /** Keeps the data under a new session id, e.g. after logging in. */
+        regenerate(): void;
+        /** Ends the session and clears its cookie. */
+        destroy(): void;
+    }
+
+    /**
+     * A file part of a multipart request, already stored on disk. It is
+     * deleted once the request has been answered.
+     */
+    interface TitanUploadedFile {
+        field: string;
+        filename: string;
+        contentType: string;
+        path: string;
+        size: number;
+        bytes(): ArrayBuffer;
+        text(): string;
+    }
+
+    /**
+     * Child spans of the current request's trace. `t.fetch` forwards the
+     * innermost open span as `traceparent`.
+     */
+    var trace: {
+        span<T>(name: string, fn: () => T): T;
+    };
+
+    /**
+     * Background task queue. `name` is a file in app/tasks, whose default
+     * export is called with `payload` and `{ id, attempt }` on a worker, off
+     * the request path. Failing runs are retried with exponential backoff.
+     */
+    var tasks: {
+        /** Returns the task id. `delay` is in milliseconds; `retries` defaults to 3. */
+        enqueue(name: string, payload?: any, options?: { delay?: number; retries?: number }): string;
+    };
+
+    /**
+     * Keys declared in titan.config.toml, validated at startup. Each comes from
+     * its `env` variable or its default; optional unset keys are undefined and
+     * undeclared ones throw.
+     */
+    var config: {
+        readonly get: <T = any>(key: string) => T | undefined;
+    };
+
+    /**
+     * Entries of `fs.readDir()`, sorted by name.
+     */
+    interface TitanDirEntry {
+        name: string;
+        isFile: boolean;
+        isDir: boolean;
+    }
+
+    /**
+     * Async file access, limited to the `fs.allow` directories of titan.config.
+     * Paths are relative to the project root. `readFile` resolves to an
+     * ArrayBuffer unless an encoding of "utf8" is given.
+     */
+    var fs: {
+        readFile(path: string, options: "utf8" | "utf-8" | { encoding: "utf8" | "utf-8" }): Promise<string>;
+        readFile(path: string): Promise<ArrayBuffer>;
+        /** Creates missing parent directories. */
+        writeFile(path: string, data: string | ArrayBuffer | ArrayBufferView, options?: { append?: boolean }): Promise<void>;
+        readDir(path: string): Promise<TitanDirEntry[]>;
+        /** `modified` is in milliseconds since the epoch. */
+        stat(path: string): Promise<{ size: number; isFile: boolean; isDir: boolean; modified: number | null }>;
+    };
+
+    /**
+     * A WebAssembly instance. `view()` reads and writes its exported memory in
+     * place; views go stale when the memory grows, so take a new one after.
+     */
+    interface TitanWasmInstance {
+        instance: WebAssembly.Instance;
+        exports: WebAssembly.Exports;
+        memory?: WebAssembly.Memory;
+        view(ptr?: number, length?: number): Uint8Array;
+    }
+
+    /**
+     * WebAssembly for CPU-heavy work. A path is read relative to the project
+     * root and compiled once per worker.
+     */
+    var wasm: {
+        compile(source: string | ArrayBuffer | ArrayBufferView | WebAssembly.Module): WebAssembly.Module;
+        instantiate(
+            source: string | ArrayBuffer | ArrayBufferView | WebAssembly.Module,
+            imports?: WebAssembly.Imports
+        ): TitanWasmInstance;
+    };
+
+    /**
+     * Key-value store shared by every worker thread. Values are JSON; expired
+     * keys read as `null`. Each operation is atomic, and none survive a restart.
+     */
+    var kv: {
+        get(key: string): any;
+        /** `ttl` is in milliseconds; without it the key never expires. */
+        set(key: string, value: any, options?: { ttl?: number }): void;
+        /** Adds `by` (default 1) to a numeric key, starting from 0. Returns the new value. */
+        incr(key: string, by?: number): number;
+        /** Sets the key's TTL in milliseconds, or clears it with `null`. False if the key is missing. */
+        expire(key: string, ttl: number | null): boolean;
+        /** Stores `value` only if the key holds `expected` (`undefined`: is missing). True if it did. */
+        cas(key: string, expected: any, value: any, options?: { ttl?: number }): boolean;
+        delete(key: string): boolean;
+    };
+
+    /** The response cache set up by `cache` in titan.config. */
+    var cache: {
+        /** Drops the cached responses for a path (all its queries and variants) or a tag. Returns how many went. */
+        purge(pathOrTag: string): number;
+    };
+
+    /** Signature checks for incoming webhooks, over `req.rawBody()`. */
+    var webhooks: {
+        /**
+         * True if the request is a genuine, fresh delivery from `provider`.
+         * The secret defaults to `STRIPE_WEBHOOK_SECRET`, `GITHUB_WEBHOOK_SECRET`
+         * or `SLACK_SIGNING_SECRET`; `tolerance` is in seconds (default 300, 0: off).
+         * Throws for an unknown provider or a missing secret.
+         */
+        verify(
+            provider: "stripe" | "github" | "slack",
+            req: TitanRequest,
+            options?: { secret?: string; tolerance?: number }
+        ): boolean;
+    };
+
+    /** Paths built from the route table, so links follow the router. */
+    var url: {
+        /**
+         * The path of a route served by `action`, from the route table: GET routes first, then exact, dynamic and
+         * file routes. Params are percent-encoded; catch-alls take an array of segments. Throws when no route of the
+         * action can be filled from `params`.
+         */
+        for(action: string, params?: Record<string, string | number | boolean | Array<string | number>>, query?: Record<string, any>): string;
+    };
+
+    /**
+     * In-process pub/sub between worker isolates, e.g. to push events published
+     * by one action to SSE streams or WebSockets served by other workers.
+     */
+    var bus: {
+        /** Delivers `message` (any JSON value) to subscribers of `topic` on every worker. */
+        publish(topic: string, message?: any): void;
+        /**
+         * Calls `handler` for each message on `topic` until the returned function
+         * is called. Made in an action, the subscription ends when the action
+         * returns; made for a WebSocket, when the socket closes.
+         */
+        subscribe(topic: string, handler: (message: any, topic: string) => void): () => void;
+    };
+
+    /**
+     * WebSocket connection bound to the worker that ran the action.
+     */
+    interface TitanSocket {
+        id: number;
+        send(data: string | ArrayBuffer | Uint8Array | object): void;
+        close(): void;
+        onMessage(handler: (data: string | ArrayBuffer) => void): void;
+        onClose(handler: () => void): void;
+    }
+
+    /**
+     * Response writer passed as the second argument to actions.
+     * Status, headers and cookies apply to whatever the action responds with.
+     * The first `write()` sends them; later chunks follow as they are written.
+     */
+    interface TitanResponseWriter {
+        status(code: number): TitanResponseWriter;
+        header(name: string, value: string): TitanResponseWriter;
+        /** Adds a Set-Cookie line. `signed` appends an HMAC of the value made with `cookies.secret`. */
+        setCookie(name: string, value: string, options?: TitanCookieOptions): TitanResponseWriter;
+        /** Same as `setCookie`. */
+        cookie(name: string, value: string, options?: TitanCookieOptions): TitanResponseWriter;
+        /** Sets the ETag instead of the one hashed from the body; quotes are added when missing. */
+        etag(tag: string, options?: { weak?: boolean }): TitanResponseWriter;
+        /** Sets Last-Modified, which `If-Modified-Since` is checked against. */
+        lastModified(date: Date | string | number): TitanResponseWriter;
+        /** The response to return for a redirect: a 302, or `status` when given first. */
+        redirect(location: string): any;
+        redirect(status: number, location: string): any;
+        /**
+         * Runs the handler for the type `Accept` ranks highest, keyed by media type or `json`, `html`, `text`, `csv`, `xml`,
+         * and answers with that type. `default` runs when none is acceptable; without it the answer is a 406.
+         */
+        format(handlers: Record<string, () => any>): any;
+        /**
+         * Sends a chunk and returns false once more than 1 MB is waiting for the client. The chunk is still sent; `await
+         * drain()` before writing more. Past 64 MB waiting the stream is cut off.
+         */
+        write(chunk: string | ArrayBuffer | Uint8Array | object): boolean;
+        /** Resolves once the client has caught up after `write()` returned false. */
+        drain(): Promise<void>;
+        end(chunk?: string | ArrayBuffer | Uint8Array | object): void;
+        /** Sends binary data as the whole response body. The buffer is transferred and becomes unusable. */
+        sendBytes(data: ArrayBuffer | ArrayBufferView, mime?: string): void;
+        /** Switches the response to Server-Sent Events. The stream closes when the action returns. */
+        sse(): TitanSseEmitter;
+        /**
+         * Streams newline-delimited JSON, one line per value. Given a source, writes every value it yields and ends the
+         * response (a promise for async sources, or once the client falls behind); otherwise `send()` values until the action returns.
+         */
+        ndjson(): TitanNdjsonEmitter;
+        ndjson(source: Iterable<any> | AsyncIterable<any> | (() => Iterable<any> | AsyncIterable<any>)): void | Promise<void>;
+        /**
+         * Streams a React element as HTML with react-dom's renderToReadableStream, in .jsx/.tsx actions. The shell goes out
+         * first and each Suspense boundary follows as it resolves. `data` reaches the client as `window.__TITAN_DATA__`;
+         * `waitForAll` holds the response until everything has rendered. Other options go to React.
+         */
+        renderStream(element: any, options?: {
+            data?: any;
+            waitForAll?: boolean;
+            bootstrapScripts?: string[];
+            bootstrapModules?: string[];
+            bootstrapScriptContent?: string;
+            identifierPrefix?: string;
+            nonce?: string;
+            onError?: (error: unknown) => void;
+            [option: string]: any;
+        }): Promise<void>;
+    }
+
+    interface TitanCookieOptions {
+        maxAge?: number;
+        expires?: Date | string | number;
+        domain?: string;
+        path?: string;
+        secure?: boolean;
+        httpOnly?: boolean;
+        sameSite?: "Strict" | "Lax" | "None";
+        /** Sign the value so it comes back in `req.signedCookies`. Needs `cookies.secret`. */
+        signed?: boolean;
+    }
+
+    interface TitanNdjsonEmitter {
+        send(value: any): TitanNdjsonEmitter;
+        close(): void;
+    }
+
+    interface TitanSseEmitter {
+        send(data: any, options?: { event?: string; id?: string | number; retry?: number }): TitanSseEmitter;
+        comment(text: string): TitanSseEmitter;
+        close(): void;
     }
 
     interface DbConnection {
         query(sql: string, params?: any[]): any[];
     }
 
-    function defineAction<T>(actionFn: (req: TitanRequest) => T): (req: TitanRequest) => T;
+    /** Commands issued in the same tick are sent to Redis as one pipeline. */
+    interface RedisClient {
+        get(key: string): Promise<string | null>;
+        set(key: string, value: any, options?: { ex?: number; px?: number; nx?: boolean; xx?: boolean }): Promise<string | null>;
+        del(...keys: string[]): Promise<number>;
+        incr(key: string, by?: number): Promise<number>;
+        expire(key: string, seconds: number): Promise<number>;
+        publish(channel: string, message: any): Promise<number>;
+        eval(script: string, keys?: string[], args?: any[]): Promise<any>;
+        /** Any other command, e.g. `command("HSET", "user:1", "name", "Ada")`. */
+        command(...args: any[]): Promise<any>;
+    }
+
+    function defineAction<T>(actionFn: (req: TitanRequest, res: TitanResponseWriter, ctx: TitanContext) => T, schema?: TitanActionSchema, config?: TitanActionConfig): (req: TitanRequest) => T;
 
     var req: TitanRequest;
 
+    // Each worker runs its own event loop, so actions may be async functions.
+    // Timers belonging to a request are dropped once it has responded.
+    function setTimeout(handler: (...args: any[]) => void, ms?: number, ...args: any[]): number;
+    function setInterval(handler: (...args: any[]) => void, ms?: number, ...args: any[]): number;
+    function clearTimeout(id?: number): void;
+    function clearInterval(id?: number): void;
+    function queueMicrotask(callback: () => void): void;
+
+    /** Awaitable HTTP request. Unlike `drift(t.fetch())`, the action is not replayed. */
+    function fetch(url: string, options?: {
+        method?: "GET" | "POST" | "PUT" | "DELETE" | "PATCH";
+        headers?: Record<string, string>;
+        body?: string | ArrayBuffer | ArrayBufferView | object;
+        /** Abort after this many milliseconds, body included. */
+        timeout?: number;
+    }): Promise<TitanFetchResponse>;
+
+    /** Thrown by `fetch` and Redis commands while the upstream's circuit breaker is open. */
+    class CircuitOpenError extends Error {
+        name: "CircuitOpenError";
+        /** `fetch:<host>`, `db:<dbname>@<host>` or `redis:<host>:<port>`. */
+        target: string;
+        state: "open" | "half_open";
+        /** Until a probe is let through; null while probes are out. */
+        retryAfterMs: number | null;
+    }
+
+    /** Set on a drift() result that failed because the upstream's circuit breaker is open. */
+    interface TitanCircuit {
+        target: string;
+        state: "open" | "half_open";
+        retry_after_ms: number | null;
+    }
+
+    interface TitanFetchResponse {
+        ok: boolean;
+        status: number;
+        headers: { get(name: string): string | null; has(name: string): boolean; entries(): IterableIterator<[string, string]> };
+        arrayBuffer(): Promise<ArrayBuffer>;
+        text(): Promise<string>;
+        json(): Promise<any>;
+    }
+
     interface TitanRuntimeUtils {
         log(...args: any[]): void;
+        /** Changes the log level of every worker and returns the previous one. */
+        setLogLevel(level: "trace" | "debug" | "info" | "warn" | "error" | "off"): string;
         read(path: string): string;
         fetch(url: string, options?: {
             method?: "GET" | "POST" | "PUT" | "DELETE" | "PATCH";
             headers?: Record<string, string>;
-            body?: string | object;
+            body?: string | ArrayBuffer | ArrayBufferView | object;
+            /** Abort after this many milliseconds, body included. */
+            timeout?: number;
         }): {
             ok: boolean;
             status?: number;
+            headers?: Record<string, string>;
             body?: string;
             error?: string;
+            circuit?: TitanCircuit;
         };
 
         jwt: {
@@ -96,9 +930,42 @@ declare global {
 
         /** ### `db` (Database Connection) */
         db: {
-            connect(url: string): DbConnection;
+            /** Uses the pool shared by all workers for `url`; `max` (default 10) caps its connections. */
+            connect(url: string, options?: { max?: number }): DbConnection;
+        };
+
+        /** ### `redis` */
+        redis: {
+            /** Uses the pool shared by all workers for `url`; `max` (default 4) caps its connections. */
+            connect(url: string, options?: { max?: number }): RedisClient;
         };
 
+        /** Counts a hit against `key`, with counters shared by every worker. Apply `headers` to the response to report the limit. */
+        rateLimit(key: string, options: TitanRateLimitPolicy): {
+            allowed: boolean;
+            limit: number;
+            remaining: number;
+            /** Milliseconds until the limit is back to full, or until a denied caller may retry. */
+            reset: number;
+            headers: Record<string, string>;
+        };
+
+        /**
+         * Calls `fn` until it resolves or `attempts` (default 3) have failed, sleeping on a native timer in between so the
+         * isolate keeps serving. Send `idempotencyKey` as an `Idempotency-Key` header to make retried POSTs safe.
+         */
+        retry<T>(fn: (attempt: { attempt: number; idempotencyKey: string }) => T | Promise<T>, options?: {
+            attempts?: number;
+            /** Delay before the second attempt, or how delays grow: each is `factor` (default 2) times the last, up to `max_ms`. */
+            backoff?: number | { initial_ms?: number; max_ms?: number; factor?: number };
+            /** Share of each delay that is random; `true` (default) for all of it. */
+            jitter?: boolean | number;
+            /** Whether `error` is worth another attempt. By default anything but a `CircuitOpenError` is. */
+            retry_on?: (error: any, attempt: number) => boolean;
+            /** Passed to every attempt unchanged. Defaults to a random UUID. */
+            idempotency_key?: string;
+        }): Promise<T>;
+
         /** ### `fs` (File System) */
         fs: TitanCore.FileSystem;
 
@@ -136,6 +1003,12 @@ declare global {
         /** ### `url` (URL) */
         url: TitanCore.URLModule;
 
+        /**
+         * Renders the Handlebars template `name` (its path under app/views without the extension) with `data`.
+         * Templates are compiled once at startup; `{{value}}` is HTML-escaped and `{{{value}}}` is not.
+         */
+        render(name: string, data?: any): string;
+
         /** ### `response` (HTTP Response Builder) */
         response: TitanCore.ResponseModule;
 
@@ -241,6 +1114,8 @@ declare global {
             (options: any): any;
             text(content: string, status?: number): any;
             html(content: string, status?: number): any;
+            /** An HTML response from the template `name`, as `t.render` renders it. */
+            render(name: string, data?: any, status?: number): any;
             json(content: any, status?: number): any;
             redirect(url: string, status?: number): any;
             empty(status?: number): any;
@@ -10,6 +10,7 @@ const green = (t) => `\x1b[32m${t}\x1b[0m`;
 const routes = {};
 const dynamicRoutes = {};
 const actionMap = {};
+const serverConfig = {};
 
 function addRoute(method, route) {
     const key = `${method.toUpperCase()}:${route}`;
@@ -73,6 +74,14 @@ const t = {
         console.log(`[\x1b[35m${module}\x1b[0m] ${msg}`);
     },
 
+    /**
+     * Set server options written to routes.json (`__config`)
+     * @param {Record<string, any>} options e.g. { timeout_ms: 10000 }
+     */
+    config(options) {
+        Object.assign(serverConfig, options);
+    },
+
     /**
      * Start the Titan Server
      * @param {number} [port=3000] 
@@ -96,7 +105,7 @@ const t = {
                 routesPath,
                 JSON.stringify(
                     {
-                        __config: { port, threads },
+                        __config: { ...serverConfig, port, threads },
                         routes,
                         __dynamic_routes: Object.values(dynamicRoutes).flat()
                     },
@@ -7,7 +7,25 @@
 /**
  * The Titan Request Object passed to actions.
  */
+/** Passed to every action as its third argument, and as `req.ctx`. */
+interface TitanContext {
+    /** The X-Request-Id sent or generated. */
+    requestId: string;
+    /** The W3C trace context, continuing the caller's `traceparent`. */
+    traceId?: string;
+    spanId?: string;
+    /** When the server stops waiting for the result, in epoch milliseconds; null without a request timeout. */
+    deadline: number | null;
+    /** Milliseconds until `deadline`; `Infinity` without one. */
+    remaining(): number;
+    /** Aborted when the client disconnects (`AbortError`) or the deadline passes (`TimeoutError`). */
+    signal: AbortSignal;
+    /** Same as `req.auth`; null without verified claims. */
+    auth: { claims: Record<string, any> } | null;
+}
+
 interface TitanRequest {
+    /** The parsed body: MessagePack or CBOR by `Content-Type`, else JSON when it parses and text when it doesn't. Parsed when first read. */
     body: any;
     method: "GET" | "POST" | "PUT" | "DELETE" | "PATCH";
     path: string;
@@ -18,8 +36,238 @@ interface TitanRequest {
         authorization?: string;
         [key: string]: string | undefined;
     };
-    params: Record<string, string>;
-    query: Record<string, string>;
+    params: Record<string, string | string[]>;
+    /** One string per name, or arrays and objects when `query` in titan.config picks a structured mode. */
+    query: Record<string, any>;
+    /** Cookies from the `Cookie` header, decoded. Signed cookies are only in `signedCookies`. */
+    cookies: Record<string, string>;
+    /** Cookies set with `signed: true` whose signature is valid; tampered ones are left out. */
+    signedCookies: Record<string, string>;
+    /** The body exactly as it was received. Null without a body, and for multipart and streamed bodies. */
+    rawBody(): ArrayBuffer | null;
+    /** The body decoded as UTF-8; `""` without a body. */
+    text(): string;
+    /** The body parsed as JSON on first call; throws when it isn't JSON. Null without a body. */
+    json<T = any>(): T | null;
+    /** The client's address, through `trusted_proxies`; what rate limiting and `req.geo` go by. */
+    ip?: string;
+    /** The same context the action gets as its third argument. */
+    ctx: TitanContext;
+    /** Text fields of a `multipart/form-data` or URL-encoded body; repeated names and names ending in `[]` become arrays. */
+    fields?: Record<string, string | string[]>;
+    /** The same fields as `fields`; `{}` when the body isn't a form. */
+    form(): Record<string, string | string[]>;
+    /** Uploaded files of a `multipart/form-data` body, optionally only those of one field. */
+    files?: (field?: string) => TitanUploadedFile[];
+    /** Verified JWT claims, when `auth` is configured and a valid bearer token was sent. */
+    auth?: { claims: Record<string, any> };
+    /** Present when `session` is configured. Changes are saved once the action returns. */
+    session?: TitanSession;
+    /** Where the client is, when `geoip` is configured and the address is in the database. */
+    geo?: TitanGeo;
+    /** Set for app/actions/_not_found, _method_not_allowed and _error: what they answer for. */
+    error?: { status: number; message: string; action?: string; stack?: string; allow?: string[] };
+}
+
+interface TitanGeo {
+    /** ISO 3166-1 country code, e.g. `"DE"`. */
+    country?: string;
+    country_name?: string;
+    continent?: string;
+    /** ISO 3166-2 code of the first subdivision, e.g. `"BY"`. */
+    region?: string;
+    region_name?: string;
+    city?: string;
+    postal_code?: string;
+    latitude?: number;
+    longitude?: number;
+    /** IANA time zone, e.g. `"Europe/Berlin"`. */
+    timezone?: string;
+    /** From an ASN database. */
+    asn?: number;
+    as_org?: string;
+}
+
+interface TitanSession {
+    get<T = any>(key: string): T | undefined;
+    set(key: string, value: any): TitanSession;
+    delete(key: string): TitanSession;
+    all(): Record<string, any>;
+    /** Keeps the data under a new session id, e.g. after logging in. */
+    regenerate(): void;
+    /** Ends the session and clears its cookie. */
+    destroy(): void;
+}
+
+/**
+ * A file part of a multipart request, already stored on disk. It is
+ * deleted once the request has been answered.
+ */
+interface TitanUploadedFile {
+    field: string;
+    filename: string;
+    contentType: string;
+    path: string;
+    size: number;
+    bytes(): ArrayBuffer;
+    text(): string;
+}
+
+/**
+ * Child spans of the current request's trace. `t.fetch` forwards the
+ * innermost open span as `traceparent`.
+ */
+declare const trace: {
+    span<T>(name: string, fn: () => T): T;
+};
+
+/**
+ * Background task queue. `name` is a file in app/tasks, whose default
+ * export is called with `payload` and `{ id, attempt }` on a worker, off
+ * the request path. Failing runs are retried with exponential backoff.
+ */
+declare const tasks: {
+    /** Returns the task id. `delay` is in milliseconds; `retries` defaults to 3. */
+    enqueue(name: string, payload?: any, options?: { delay?: number; retries?: number }): string;
+};
+
+/**
+ * Key-value store shared by every worker thread. Values are JSON; expired
+ * keys read as `null`. Each operation is atomic, and none survive a restart.
+ */
+declare const kv: {
+    get(key: string): any;
+    /** `ttl` is in milliseconds; without it the key never expires. */
+    set(key: string, value: any, options?: { ttl?: number }): void;
+    /** Adds `by` (default 1) to a numeric key, starting from 0. Returns the new value. */
+    incr(key: string, by?: number): number;
+    /** Sets the key's TTL in milliseconds, or clears it with `null`. False if the key is missing. */
+    expire(key: string, ttl: number | null): boolean;
+    /** Stores `value` only if the key holds `expected` (`undefined`: is missing). True if it did. */
+    cas(key: string, expected: any, value: any, options?: { ttl?: number }): boolean;
+    delete(key: string): boolean;
+};
+
+/**
+ * In-process pub/sub between worker isolates, e.g. to push events published
+ * by one action to SSE streams or WebSockets served by other workers.
+ */
+declare const bus: {
+    /** Delivers `message` (any JSON value) to subscribers of `topic` on every worker. */
+    publish(topic: string, message?: any): void;
+    /**
+     * Calls `handler` for each message on `topic` until the returned function
+     * is called. Made in an action, the subscription ends when the action
+     * returns; made for a WebSocket, when the socket closes.
+     */
+    subscribe(topic: string, handler: (message: any, topic: string) => void): () => void;
+};
+
+/** Signature checks for incoming webhooks, over `req.rawBody()`. */
+declare const webhooks: {
+    /**
+     * True if the request is a genuine, fresh delivery from `provider`.
+     * The secret defaults to `STRIPE_WEBHOOK_SECRET`, `GITHUB_WEBHOOK_SECRET`
+     * or `SLACK_SIGNING_SECRET`; `tolerance` is in seconds (default 300, 0: off).
+     * Throws for an unknown provider or a missing secret.
+     */
+    verify(
+        provider: "stripe" | "github" | "slack",
+        req: TitanRequest,
+        options?: { secret?: string; tolerance?: number }
+    ): boolean;
+};
+
+/** Paths built from the route table, so links follow the router. */
+declare const url: {
+    /**
+     * The path of a route served by `action`, from the route table: GET routes first, then exact, dynamic and
+     * file routes. Params are percent-encoded; catch-alls take an array of segments. Throws when no route of the
+     * action can be filled from `params`.
+     */
+    for(action: string, params?: Record<string, string | number | boolean | Array<string | number>>, query?: Record<string, any>): string;
+};
+
+/**
+ * Response writer passed as the second argument to actions. Status, headers
+ * and cookies apply to whatever the action responds with.
+ */
+interface TitanResponseWriter {
+    status(code: number): TitanResponseWriter;
+    header(name: string, value: string): TitanResponseWriter;
+    /** Adds a Set-Cookie line. `signed` appends an HMAC of the value made with `cookies.secret`. */
+    setCookie(name: string, value: string, options?: TitanCookieOptions): TitanResponseWriter;
+    /** Same as `setCookie`. */
+    cookie(name: string, value: string, options?: TitanCookieOptions): TitanResponseWriter;
+    /** Sets the ETag instead of the one hashed from the body; quotes are added when missing. */
+    etag(tag: string, options?: { weak?: boolean }): TitanResponseWriter;
+    /** Sets Last-Modified, which `If-Modified-Since` is checked against. */
+    lastModified(date: Date | string | number): TitanResponseWriter;
+    /** The response to return for a redirect: a 302, or `status` when given first. */
+    redirect(location: string): any;
+    redirect(status: number, location: string): any;
+    /**
+     * Runs the handler for the type `Accept` ranks highest, keyed by media type or `json`, `html`, `text`, `csv`, `xml`,
+     * and answers with that type. `default` runs when none is acceptable; without it the answer is a 406.
+     */
+    format(handlers: Record<string, () => any>): any;
+    /**
+     * Sends a chunk and returns false once more than 1 MB is waiting for the client. The chunk is still sent; `await
+     * drain()` before writing more. Past 64 MB waiting the stream is cut off.
+     */
+    write(chunk: string | ArrayBuffer | Uint8Array | object): boolean;
+    /** Resolves once the client has caught up after `write()` returned false. */
+    drain(): Promise<void>;
+    end(chunk?: string | ArrayBuffer | Uint8Array | object): void;
+    /** Sends binary data as the whole response body. The buffer is transferred and becomes unusable. */
+    sendBytes(data: ArrayBuffer | ArrayBufferView, mime?: string): void;
+    /** Switches the response to Server-Sent Events. The stream closes when the action returns. */
+    sse(): TitanSseEmitter;
+    /**
+     * Streams newline-delimited JSON, one line per value. Given a source, writes every value it yields and ends the
+     * response (a promise for async sources, or once the client falls behind); otherwise `send()` values until the action returns.
+     */
+    ndjson(): TitanNdjsonEmitter;
+    ndjson(source: Iterable<any> | AsyncIterable<any> | (() => Iterable<any> | AsyncIterable<any>)): void | Promise<void>;
+    /**
+     * Streams a React element as HTML with react-dom's renderToReadableStream, in .jsx/.tsx actions. The shell goes out
+     * first and each Suspense boundary follows as it resolves. `data` reaches the client as `window.__TITAN_DATA__`;
+     * `waitForAll` holds the response until everything has rendered. Other options go to React.
+     */
+    renderStream(element: any, options?: {
+        data?: any;
+        waitForAll?: boolean;
+        bootstrapScripts?: string[];
+        bootstrapModules?: string[];
+        bootstrapScriptContent?: string;
+        identifierPrefix?: string;
+        nonce?: string;
+        onError?: (error: unknown) => void;
+        [option: string]: any;
+    }): Promise<void>;
+}
+
+interface TitanCookieOptions {
+    maxAge?: number;
+    expires?: Date | string | number;
+    domain?: string;
+    path?: string;
+    secure?: boolean;
+    httpOnly?: boolean;
+    sameSite?: "Strict" | "Lax" | "None";
+    /** Sign the value so it comes back in `req.signedCookies`. Needs `cookies.secret`. */
+    signed?: boolean;
+}
+
+interface TitanNdjsonEmitter {
+    send(value: any): TitanNdjsonEmitter;
+    close(): void;
+}
+
+interface TitanSseEmitter {
+    send(data: any, options?: { event?: string; id?: string | number; retry?: number }): TitanSseEmitter;
+    comment(text: string): TitanSseEmitter;
+    close(): void;
 }
 
 interface DbConnection {
@@ -31,6 +279,56 @@ interface DbConnection {
     query(sql: string, params?: any[]): any[];
 }
 
+/** Commands issued in the same tick are sent to Redis as one pipeline. */
+interface RedisClient {
+    get(key: string): Promise<string | null>;
+    set(key: string, value: any, options?: { ex?: number; px?: number; nx?: boolean; xx?: boolean }): Promise<string | null>;
+    del(...keys: string[]): Promise<number>;
+    incr(key: string, by?: number): Promise<number>;
+    expire(key: string, seconds: number): Promise<number>;
+    publish(channel: string, message: any): Promise<number>;
+    eval(script: string, keys?: string[], args?: any[]): Promise<any>;
+    /** Any other command, e.g. `command("HSET", "user:1", "name", "Ada")`. */
+    command(...args: any[]): Promise<any>;
+}
+
+/**
+ * Default export of `app/middleware.{js,ts}` (a single function or an array).
+ * Runs before every action; return a value to respond with it instead.
+ */
+type TitanMiddleware = (req: TitanRequest, res: TitanResponseWriter) => any;
+
+/** A JSON Schema, or a zod-like schema with a `toJSONSchema()` method. */
+type TitanSchema = Record<string, any> | { toJSONSchema(): Record<string, any> };
+
+/** What an action exports as `schema`. It is documented at `/docs`, and requests are checked against it. */
+interface TitanActionSchema {
+    summary?: string;
+    description?: string;
+    tags?: string[];
+    deprecated?: boolean;
+    /** Left out of the document. */
+    hidden?: boolean;
+    /** Object schemas whose properties are the path, query and header parameters. */
+    params?: TitanSchema;
+    query?: TitanSchema;
+    headers?: TitanSchema;
+    /** The JSON request body. */
+    body?: TitanSchema;
+    /** The 200 response, or responses keyed by status code. */
+    response?: TitanSchema | Record<number | "default", TitanSchema>;
+}
+
+/** What an action exports as `config`: how the server runs it. */
+interface TitanActionConfig {
+    /** Requests of this action running at once, at most; the rest wait for a slot within their deadline. */
+    concurrency?: number;
+    /** Deadline of this action's requests in milliseconds, instead of `timeout_ms`; 0 for none. */
+    timeoutMs?: number;
+    /** Largest request body this action takes, in bytes, instead of `body.max_mb`; 0 for no limit. */
+    maxBodyBytes?: number;
+}
+
 /**
  * Define a Titan Action with type inference.
  * @example
@@ -38,7 +336,103 @@ interface DbConnection {
  *   return req.headers;
  * });
  */
-declare function defineAction<T>(actionFn: (req: TitanRequest) => T): (req: TitanRequest) => T;
+declare function defineAction<T>(actionFn: (req: TitanRequest, res: TitanResponseWriter, ctx: TitanContext) => T, schema?: TitanActionSchema, config?: TitanActionConfig): (req: TitanRequest) => T;
+
+/**
+ * Each worker runs its own event loop, so actions may be async functions.
+ * Timers belonging to a request are dropped once it has responded.
+ */
+declare function setTimeout(handler: (...args: any[]) => void, ms?: number, ...args: any[]): number;
+declare function setInterval(handler: (...args: any[]) => void, ms?: number, ...args: any[]): number;
+declare function clearTimeout(id?: number): void;
+declare function clearInterval(id?: number): void;
+declare function queueMicrotask(callback: () => void): void;
+
+interface TitanFetchResponse {
+    ok: boolean;
+    status: number;
+    headers: { get(name: string): string | null; has(name: string): boolean; entries(): IterableIterator<[string, string]> };
+    arrayBuffer(): Promise<ArrayBuffer>;
+    text(): Promise<string>;
+    json(): Promise<any>;
+}
+
+/**
+ * Awaitable HTTP request. Unlike `drift(t.fetch())`, the action is not replayed.
+ */
+declare function fetch(url: string, options?: {
+    method?: "GET" | "POST" | "PUT" | "DELETE" | "PATCH";
+    headers?: Record<string, string>;
+    body?: string | ArrayBuffer | ArrayBufferView | object;
+    /** Abort after this many milliseconds, body included. */
+    timeout?: number;
+}): Promise<TitanFetchResponse>;
+
+/**
+ * Thrown by `fetch` and Redis commands while the upstream's circuit breaker is open.
+ */
+declare class CircuitOpenError extends Error {
+    name: "CircuitOpenError";
+    target: string;
+    state: "open" | "half_open";
+    retryAfterMs: number | null;
+}
+
+/**
+ * Keys declared in titan.config.toml, validated at startup. Each comes from
+ * its `env` variable or its default; optional unset keys are undefined and
+ * undeclared ones throw.
+ */
+declare const config: {
+    readonly get: <T = any>(key: string) => T | undefined;
+};
+
+/**
+ * Entries of `fs.readDir()`, sorted by name.
+ */
+interface TitanDirEntry {
+    name: string;
+    isFile: boolean;
+    isDir: boolean;
+}
+
+/**
+ * Async file access, limited to the `fs.allow` directories of titan.config.
+ * Paths are relative to the project root. `readFile` resolves to an
+ * ArrayBuffer unless an encoding of "utf8" is given.
+ */
+declare const fs: {
+    readFile(path: string, options: "utf8" | "utf-8" | { encoding: "utf8" | "utf-8" }): Promise<string>;
+    readFile(path: string): Promise<ArrayBuffer>;
+    /** Creates missing parent directories. */
+    writeFile(path: string, data: string | ArrayBuffer | ArrayBufferView, options?: { append?: boolean }): Promise<void>;
+    readDir(path: string): Promise<TitanDirEntry[]>;
+    /** `modified` is in milliseconds since the epoch. */
+    stat(path: string): Promise<{ size: number; isFile: boolean; isDir: boolean; modified: number | null }>;
+};
+
+/**
+ * A WebAssembly instance. `view()` reads and writes its exported memory in
+ * place; views go stale when the memory grows, so take a new one after.
+ */
+interface TitanWasmInstance {
+    instance: WebAssembly.Instance;
+    exports: WebAssembly.Exports;
+    memory?: WebAssembly.Memory;
+    view(ptr?: number, length?: number): Uint8Array;
+}
+
+/**
+ * WebAssembly for CPU-heavy work. A path is read relative to the project
+ * root and compiled once per worker.
+ */
+declare const wasm: {
+    compile(source: string | ArrayBuffer | ArrayBufferView | WebAssembly.Module): WebAssembly.Module;
+    instantiate(
+        source: string | ArrayBuffer | ArrayBufferView | WebAssembly.Module,
+        imports?: WebAssembly.Imports
+    ): TitanWasmInstance;
+};
 
 /**
  * Titan Runtime Utilities
@@ -49,6 +443,11 @@ declare const t: {
      */
     log(...args: any[]): void;
 
+    /**
+     * Change the log level of every worker; returns the previous one.
+     */
+    setLogLevel(level: "trace" | "debug" | "info" | "warn" | "error" | "off"): string;
+
     /**
      * Read a file contents as string.
      * @param path Relative path to the file from project root.
@@ -58,12 +457,17 @@ declare const t: {
     fetch(url: string, options?: {
         method?: "GET" | "POST" | "PUT" | "DELETE" | "PATCH";
         headers?: Record<string, string>;
-        body?: string | object;
+        body?: string | ArrayBuffer | ArrayBufferView | object;
+        /** Abort after this many milliseconds, body included. */
+        timeout?: number;
     }): {
         ok: boolean;
         status?: number;
+        headers?: Record<string, string>;
         body?: string;
         error?: string;
+        /** Set when the host's circuit breaker turned the request away. */
+        circuit?: { target: string; state: "open" | "half_open"; retry_after_ms: number | null };
     };
 
     jwt: {
@@ -81,7 +485,43 @@ declare const t: {
     };
 
     db: {
-        connect(url: string): DbConnection;
+        /** Uses the pool shared by all workers for `url`; `max` (default 10) caps its connections. */
+        connect(url: string, options?: { max?: number }): DbConnection;
     };
+
+    redis: {
+        /** Uses the pool shared by all workers for `url`; `max` (default 4) caps its connections. */
+        connect(url: string, options?: { max?: number }): RedisClient;
+    };
+
+    /** Counts a hit against `key`, with counters shared by every worker. Apply `headers` to the response to report the limit. */
+    rateLimit(key: string, options: {
+        limit: number;
+        window_ms?: number;
+        strategy?: "sliding_window" | "token_bucket";
+        burst?: number;
+    }): {
+        allowed: boolean;
+        limit: number;
+        remaining: number;
+        reset: number;
+        headers: Record<string, string>;
+    };
+
+    /** Renders the Handlebars template `name` from app/views with `data`. */
+    render(name: string, data?: any): string;
+
+    /** Calls `fn` until it resolves or `attempts` (default 3) have failed, sleeping on a native timer in between. */
+    retry<T>(fn: (attempt: { attempt: number; idempotencyKey: string }) => T | Promise<T>, options?: {
+        attempts?: number;
+        /** Delay before the second attempt, or how delays grow: each is `factor` (default 2) times the last, up to `max_ms`. */
+        backoff?: number | { initial_ms?: number; max_ms?: number; factor?: number };
+        /** Share of each delay that is random; `true` (default) for all of it. */
+        jitter?: boolean | number;
+        /** Whether `error` is worth another attempt. By default anything but a `CircuitOpenError` is. */
+        retry_on?: (error: any, attempt: number) => boolean;
+        /** Passed to every attempt unchanged. Defaults to a random UUID. */
+        idempotency_key?: string;
+    }): Promise<T>;
 };
 
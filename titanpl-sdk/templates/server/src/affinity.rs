@@ -0,0 +1,87 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use crate::runtime::RequestTask;
+
+// What a request is pinned by
+enum Key {
+    // Every request for the action
+    Action,
+    Path,
+    Header(String),
+    Query(String),
+    Param(String),
+    Cookie(String),
+}
+
+/// The `affinity` block of titan.config: actions whose requests always go to
+/// the same worker for the same key, so caches an isolate builds up (compiled
+/// templates, loaded models) are hit instead of rebuilt on every worker.
+/// Keys are `"action"`, `"path"`, `"session"`, or `"header:<name>"`,
+/// `"query:<name>"`, `"param:<name>"` and `"cookie:<name>"`. Requests
+/// without the key are dispatched as usual.
+pub struct Affinity {
+    actions: HashMap<String, Key>,
+}
+
+impl Affinity {
+    /// None when no action is pinned. `session` pins by the session cookie,
+    /// named `session_cookie`.
+    pub fn from_config(config: &Value, session_cookie: &str) -> Result<Option<Self>, String> {
+        let Some(rules) = config.as_object() else {
+            return Ok(None);
+        };
+        let mut actions = HashMap::new();
+        for (action, rule) in rules {
+            let rule = rule.as_str().ok_or_else(|| format!("affinity.{}: expected a string", action))?;
+            let key = match rule.split_once(':') {
+                None if rule == "action" => Key::Action,
+                None if rule == "path" => Key::Path,
+                None if rule == "session" => Key::Cookie(session_cookie.to_string()),
+                Some(("header", name)) if !name.is_empty() => Key::Header(name.to_ascii_lowercase()),
+                Some(("query", name)) if !name.is_empty() => Key::Query(name.to_string()),
+                Some(("param", name)) if !name.is_empty() => Key::Param(name.to_string()),
+                Some(("cookie", name)) if !name.is_empty() => Key::Cookie(name.to_string()),
+                _ => return Err(format!("affinity.{}: unknown key \"{}\"", action, rule)),
+            };
+            actions.insert(action.clone(), key);
+        }
+        Ok((!actions.is_empty()).then_some(Self { actions }))
+    }
+
+    /// The hash of the task's affinity key, if its action has one and the
+    /// request carries it.
+    pub fn key(&self, task: &RequestTask) -> Option<u64> {
+        let find = |pairs: &[(String, String)], name: &str| pairs.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone());
+        let value = match self.actions.get(&task.action_name)? {
+            Key::Action => String::new(),
+            Key::Path => task.path.clone(),
+            Key::Header(name) => find(&task.headers, name)?,
+            Key::Query(name) => find(&task.query, name)?,
+            Key::Param(name) => find(&task.params, name)?,
+            Key::Cookie(name) => task
+                .headers
+                .iter()
+                .filter(|(k, _)| k == "cookie")
+                .flat_map(|(_, v)| v.split(';'))
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(k, _)| k.trim() == name)
+                .map(|(_, v)| v.trim().to_string())?,
+        };
+        let mut hasher = DefaultHasher::new();
+        (&task.action_name, value).hash(&mut hasher);
+        Some(hasher.finish())
+    }
+}
+
+/// The worker among `workers` that `key` belongs to, by rendezvous hashing:
+/// each worker scores the key and the highest wins. A worker starting or
+/// stopping only moves the keys it wins or held; the rest stay put.
+pub fn pick(key: u64, workers: impl Iterator<Item = usize>) -> Option<usize> {
+    workers.max_by_key(|worker| {
+        let mut hasher = DefaultHasher::new();
+        (key, *worker).hash(&mut hasher);
+        hasher.finish()
+    })
+}
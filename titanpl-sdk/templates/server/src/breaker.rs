@@ -0,0 +1,187 @@
+//! Circuit breakers for the upstreams actions call out to. Each host a
+//! fetch goes to, each database and each Redis server has its own circuit:
+//! after enough failures in a row it opens and calls to it fail at once,
+//! instead of every worker waiting out the same timeout. Once `open_ms` has
+//! passed, a few probes are let through; one success closes the circuit, a
+//! failure opens it again.
+
+use dashmap::DashMap;
+use serde_json::Value;
+use std::fmt::Write as _;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+
+static BREAKER: OnceLock<Breaker> = OnceLock::new();
+
+struct Breaker {
+    failures: u32,
+    open_for: Duration,
+    probes: u32,
+    circuits: DashMap<String, Circuit>,
+}
+
+#[derive(Default)]
+struct Circuit {
+    // Failures since the last success
+    failures: u32,
+    state: State,
+    rejected: u64,
+}
+
+#[derive(Default, Clone, Copy)]
+enum State {
+    #[default]
+    Closed,
+    Open { until: Instant },
+    // Probes let through and not yet answered
+    HalfOpen { probes: u32 },
+}
+
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            State::Closed => "closed",
+            State::Open { .. } => "open",
+            State::HalfOpen { .. } => "half_open",
+        }
+    }
+}
+
+/// The `circuit_breaker` block of titan.config: `true` for the defaults, or
+/// `{ failures, open_ms, half_open_probes }`. Off unless set.
+pub fn configure(config: &Value) {
+    if !(config.as_bool() == Some(true) || config.is_object()) {
+        return;
+    }
+    let breaker = Breaker {
+        failures: config["failures"].as_u64().filter(|n| *n > 0).map_or(5, |n| n as u32),
+        open_for: Duration::from_millis(config["open_ms"].as_u64().unwrap_or(30_000)),
+        probes: config["half_open_probes"].as_u64().filter(|n| *n > 0).map_or(1, |n| n as u32),
+        circuits: DashMap::new(),
+    };
+    tracing::info!(
+        "Circuit breakers open after {} failure(s) for {} ms",
+        breaker.failures,
+        breaker.open_for.as_millis()
+    );
+    let _ = BREAKER.set(breaker);
+}
+
+/// Why a call was turned away.
+pub struct Open {
+    pub target: String,
+    pub state: &'static str,
+    /// Until the circuit lets a probe through; None while probes are out.
+    pub retry_after: Option<Duration>,
+}
+
+impl Open {
+    /// The op result actions see: an `{ error }` like any other failure,
+    /// plus the `circuit` it came from.
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({
+            "error": format!("Circuit open for {}", self.target),
+            "circuit": {
+                "target": self.target,
+                "state": self.state,
+                "retry_after_ms": self.retry_after.map(|d| d.as_millis() as u64),
+            }
+        })
+    }
+}
+
+/// Whether a call to `target` may go ahead. Every admitted call must be
+/// followed by a `record` of how it went.
+pub fn admit(target: &str) -> Result<(), Open> {
+    let Some(breaker) = BREAKER.get() else {
+        return Ok(());
+    };
+    let Some(mut circuit) = breaker.circuits.get_mut(target) else {
+        return Ok(());
+    };
+    let now = Instant::now();
+    let retry_after = match circuit.state {
+        State::Closed => return Ok(()),
+        State::Open { until } if until <= now => {
+            circuit.state = State::HalfOpen { probes: 1 };
+            return Ok(());
+        }
+        State::HalfOpen { probes } if probes < breaker.probes => {
+            circuit.state = State::HalfOpen { probes: probes + 1 };
+            return Ok(());
+        }
+        State::Open { until } => Some(until - now),
+        State::HalfOpen { .. } => None,
+    };
+    circuit.rejected += 1;
+    Err(Open { target: target.to_string(), state: circuit.state.name(), retry_after })
+}
+
+/// How an admitted call to `target` went. `ok` is false only when the
+/// upstream itself misbehaved; a query it rejected was still answered.
+pub fn record(target: &str, ok: bool) {
+    let Some(breaker) = BREAKER.get() else {
+        return;
+    };
+    if ok {
+        // Healthy upstreams never get an entry, so the hot path stays a lookup
+        if let Some(mut circuit) = breaker.circuits.get_mut(target) {
+            if let State::HalfOpen { .. } | State::Open { .. } = circuit.state {
+                tracing::info!("Circuit for {} closed", target);
+            }
+            circuit.failures = 0;
+            circuit.state = State::Closed;
+        }
+        return;
+    }
+
+    let mut circuit = breaker.circuits.entry(target.to_string()).or_default();
+    circuit.failures += 1;
+    let reopen = match circuit.state {
+        State::Closed => circuit.failures >= breaker.failures,
+        State::HalfOpen { .. } => true,
+        State::Open { .. } => false,
+    };
+    if reopen {
+        tracing::warn!(
+            "Circuit for {} opened after {} failure(s); retrying in {} ms",
+            target,
+            circuit.failures,
+            breaker.open_for.as_millis()
+        );
+        circuit.state = State::Open { until: Instant::now() + breaker.open_for };
+    }
+}
+
+/// The circuit of a fetch to `url`: its host and port.
+pub fn fetch_target(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("fetch:{}:{}", host, port),
+        None => format!("fetch:{}", host),
+    })
+}
+
+/// Prometheus text exposition of every circuit that has seen a failure.
+pub fn render(out: &mut String) {
+    let Some(breaker) = BREAKER.get() else {
+        return;
+    };
+    let now = Instant::now();
+    metrics::header(out, "titan_circuit_state", "gauge", "Circuit state per upstream: 0 closed, 1 open, 2 half-open.");
+    for circuit in breaker.circuits.iter() {
+        let state = match circuit.state {
+            State::Closed => 0,
+            State::Open { until } if until > now => 1,
+            State::Open { .. } | State::HalfOpen { .. } => 2,
+        };
+        let _ = writeln!(out, "titan_circuit_state{{target=\"{}\"}} {}", metrics::escape_label(circuit.key()), state);
+    }
+    metrics::header(out, "titan_circuit_rejections_total", "counter", "Calls failed fast by an open circuit.");
+    for circuit in breaker.circuits.iter() {
+        let _ = writeln!(out, "titan_circuit_rejections_total{{target=\"{}\"}} {}", metrics::escape_label(circuit.key()), circuit.rejected);
+    }
+}
@@ -0,0 +1,269 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header};
+use bytes::Bytes;
+use dashmap::DashMap;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+
+// Entries past their stale window are dropped on access; the sweep reclaims the rest
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+// Bigger responses are passed through, so a few downloads can't fill the cache
+const MAX_ENTRY_BYTES: usize = 1024 * 1024;
+
+pub const STATUS_HEADER: &str = "x-titan-cache";
+
+static CACHE: OnceLock<ResponseCache> = OnceLock::new();
+
+/// Marks the background request that refreshes a stale entry, which skips
+/// the lookup and always stores what it gets.
+#[derive(Clone, Copy)]
+pub struct Revalidate;
+
+/// How one action's responses are cached.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub ttl: Duration,
+    /// How long past `ttl` a stale copy is still served while it is refreshed.
+    pub swr: Duration,
+    /// Request headers whose values get separate entries, lowercased.
+    pub vary: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl Rule {
+    fn from_options(options: &Value) -> Option<Self> {
+        let ttl = options["ttl_ms"].as_u64().filter(|ms| *ms > 0)?;
+        let strings = |key: &str| -> Vec<String> {
+            options[key].as_array().map_or_else(Vec::new, |v| v.iter().filter_map(Value::as_str).map(str::to_string).collect())
+        };
+        Some(Self {
+            ttl: Duration::from_millis(ttl),
+            swr: Duration::from_millis(options["swr_ms"].as_u64().unwrap_or(0)),
+            vary: strings("vary").into_iter().map(|h| h.to_ascii_lowercase()).collect(),
+            tags: strings("tags"),
+        })
+    }
+}
+
+struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_at: Instant,
+    ttl: Duration,
+    swr: Duration,
+    path: String,
+    tags: Vec<String>,
+    // Set while one request refreshes the entry, so the others just serve it
+    revalidating: AtomicBool,
+}
+
+impl Entry {
+    fn age(&self, now: Instant) -> Duration {
+        now.duration_since(self.stored_at)
+    }
+
+    fn is_live(&self, now: Instant) -> bool {
+        self.age(now) < self.ttl + self.swr
+    }
+
+    fn response(&self, now: Instant, state: &'static str) -> axum::response::Response {
+        let mut response = axum::response::Response::new(axum::body::Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response.headers_mut().insert(header::AGE, HeaderValue::from(self.age(now).as_secs()));
+        response.headers_mut().insert(HeaderName::from_static(STATUS_HEADER), HeaderValue::from_static(state));
+        response
+    }
+}
+
+pub enum Lookup {
+    Fresh(axum::response::Response),
+    /// Past its TTL but within the stale window. `revalidate` is set for the
+    /// one request that should refresh it.
+    Stale { response: axum::response::Response, revalidate: bool },
+    Miss,
+}
+
+/// The in-memory response cache from `__config.cache`. Only the actions in
+/// its `routes` are cached, and only their GET responses; `max_entries`
+/// (10000 by default) caps how many are kept at once.
+pub struct ResponseCache {
+    routes: HashMap<String, Rule>,
+    max_entries: usize,
+    entries: DashMap<String, Entry>,
+    hits: AtomicU64,
+    stale: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResponseCache {
+    /// Installs the cache when `routes` names at least one action.
+    pub fn from_config(config: &Value) -> Result<Option<&'static Self>, String> {
+        let Some(routes) = config["routes"].as_object() else {
+            return Ok(None);
+        };
+        let routes = routes
+            .iter()
+            .map(|(action, options)| match Rule::from_options(options) {
+                Some(rule) => Ok((action.clone(), rule)),
+                None => Err(format!("cache: route {} needs a positive ttl_ms", action)),
+            })
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        if routes.is_empty() {
+            return Ok(None);
+        }
+        let cache = Self {
+            routes,
+            max_entries: config["max_entries"].as_u64().filter(|n| *n > 0).map_or(10_000, |n| n as usize),
+            entries: DashMap::new(),
+            hits: AtomicU64::new(0),
+            stale: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        };
+        Ok(Some(CACHE.get_or_init(|| cache)))
+    }
+
+    /// The cache, when one is configured.
+    pub fn get() -> Option<&'static Self> {
+        CACHE.get()
+    }
+
+    pub fn rule_for(&self, action: &str) -> Option<&Rule> {
+        self.routes.get(action)
+    }
+
+    /// What a request is stored under: its path and query, the parts of
+    /// `Accept` and `Accept-Encoding` that change the body, and the headers
+    /// the rule varies on.
+    pub fn key(rule: &Rule, uri: &axum::http::Uri, headers: &HeaderMap, variant: &str) -> String {
+        let mut key = format!("{}\n{}", uri.path_and_query().map_or("/", |p| p.as_str()), variant);
+        for name in &rule.vary {
+            let value = headers.get(name.as_str()).and_then(|v| v.to_str().ok()).unwrap_or("");
+            let _ = write!(key, "\n{}", value);
+        }
+        key
+    }
+
+    pub fn lookup(&self, key: &str) -> Lookup {
+        let now = Instant::now();
+        if let Some(entry) = self.entries.get(key) {
+            if entry.age(now) < entry.ttl {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Lookup::Fresh(entry.response(now, "hit"));
+            }
+            if entry.is_live(now) {
+                self.stale.fetch_add(1, Ordering::Relaxed);
+                let revalidate = !entry.revalidating.swap(true, Ordering::AcqRel);
+                return Lookup::Stale { response: entry.response(now, "stale"), revalidate };
+            }
+        }
+        self.entries.remove_if(key, |_, entry| !entry.is_live(now));
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        Lookup::Miss
+    }
+
+    /// Keeps a successful response. Ones that set cookies, say `no-store` or
+    /// `private`, or are too big are left out.
+    pub fn store(&self, key: String, action: &str, rule: &Rule, status: StatusCode, headers: &HeaderMap, body: Bytes) {
+        let cache_control = headers.get_all(header::CACHE_CONTROL).iter().filter_map(|v| v.to_str().ok()).collect::<Vec<_>>().join(",");
+        if !status.is_success()
+            || body.len() > MAX_ENTRY_BYTES
+            || headers.contains_key(header::SET_COOKIE)
+            || cache_control.split(',').any(|d| matches!(d.trim().to_ascii_lowercase().as_str(), "no-store" | "private"))
+        {
+            return;
+        }
+        if self.entries.len() >= self.max_entries && !self.entries.contains_key(&key) {
+            self.evict();
+        }
+        // CORS headers depend on who asked, so each hit gets its own
+        let mut headers = headers.clone();
+        headers.remove("server-timing");
+        for name in ["access-control-allow-origin", "access-control-allow-credentials", "access-control-expose-headers"] {
+            headers.remove(name);
+        }
+        let path = key.split(['?', '\n']).next().unwrap_or("").to_string();
+        let mut tags = rule.tags.clone();
+        tags.push(action.to_string());
+        self.entries.insert(
+            key,
+            Entry {
+                status,
+                headers,
+                body,
+                stored_at: Instant::now(),
+                ttl: rule.ttl,
+                swr: rule.swr,
+                path,
+                tags,
+                revalidating: AtomicBool::new(false),
+            },
+        );
+    }
+
+    /// Lets the next stale hit try again when a refresh didn't store anything.
+    pub fn revalidated(&self, key: &str) {
+        if let Some(entry) = self.entries.get(key) {
+            entry.revalidating.store(false, Ordering::Release);
+        }
+    }
+
+    /// Drops the entries for a path (every query and variant of it) or
+    /// carrying a tag. Every entry is tagged with its action's name.
+    pub fn purge(&self, target: &str) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| entry.path != target && !entry.tags.iter().any(|t| t == target));
+        before.saturating_sub(self.entries.len())
+    }
+
+    /// Drops every entry.
+    pub fn clear(&self) -> usize {
+        let before = self.entries.len();
+        self.entries.clear();
+        before
+    }
+
+    // Makes room by dropping dead entries, or the oldest one when all are live
+    fn evict(&self) {
+        let now = Instant::now();
+        self.entries.retain(|_, entry| entry.is_live(now));
+        if self.entries.len() < self.max_entries {
+            return;
+        }
+        let oldest = self.entries.iter().min_by_key(|entry| entry.stored_at).map(|entry| entry.key().clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Periodically drops entries past their stale window.
+    pub fn start_sweeper(&'static self) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let now = Instant::now();
+                self.entries.retain(|_, entry| entry.is_live(now));
+            }
+        });
+    }
+}
+
+/// Prometheus text exposition of the cache size and lookups.
+pub fn render(out: &mut String) {
+    let Some(cache) = CACHE.get() else {
+        return;
+    };
+    metrics::header(out, "titan_cache_entries", "gauge", "Responses in the cache, including ones not yet swept.");
+    let _ = writeln!(out, "titan_cache_entries {}", cache.entries.len());
+    metrics::header(out, "titan_cache_lookups_total", "counter", "Cache lookups by outcome.");
+    for (result, count) in [("hit", &cache.hits), ("stale", &cache.stale), ("miss", &cache.misses)] {
+        let _ = writeln!(out, "titan_cache_lookups_total{{result=\"{}\"}} {}", result, count.load(Ordering::Relaxed));
+    }
+}
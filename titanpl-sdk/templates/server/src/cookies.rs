@@ -0,0 +1,101 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ring::hmac;
+use serde_json::Value;
+use std::sync::OnceLock;
+
+// Marks a value written by `res.setCookie(..., { signed: true })`
+const SIGNED_PREFIX: &str = "s:";
+
+static KEYS: OnceLock<Vec<hmac::Key>> = OnceLock::new();
+
+/// Loads the signing keys from `__config.cookies.secret`: one secret, or a
+/// list whose first entry signs and whose others are still accepted, so a
+/// secret can be rotated without logging everyone out.
+/// `TITAN_COOKIE_SECRET` takes the signing spot when set.
+pub fn configure(config: &Value) -> usize {
+    let mut secrets: Vec<String> = match &config["secret"] {
+        Value::String(secret) => vec![secret.clone()],
+        Value::Array(list) => list.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+        _ => Vec::new(),
+    };
+    if let Ok(secret) = std::env::var("TITAN_COOKIE_SECRET") {
+        secrets.insert(0, secret);
+    }
+    let keys: Vec<hmac::Key> = secrets
+        .iter()
+        .filter(|s| !s.is_empty())
+        .map(|s| hmac::Key::new(hmac::HMAC_SHA256, s.as_bytes()))
+        .collect();
+    let count = keys.len();
+    let _ = KEYS.set(keys);
+    count
+}
+
+fn keys() -> &'static [hmac::Key] {
+    KEYS.get().map_or(&[], Vec::as_slice)
+}
+
+// The name is signed too, so a signed value can't be replayed under another cookie
+fn signature(key: &hmac::Key, name: &str, value: &str) -> hmac::Tag {
+    hmac::sign(key, format!("{}={}", name, value).as_bytes())
+}
+
+/// The value to store for a signed cookie, or None without a secret.
+pub fn sign(name: &str, value: &str) -> Option<String> {
+    let key = keys().first()?;
+    Some(format!("{}{}.{}", SIGNED_PREFIX, value, URL_SAFE_NO_PAD.encode(signature(key, name, value))))
+}
+
+fn unsign(name: &str, stored: &str) -> Option<String> {
+    let (value, tag) = stored.strip_prefix(SIGNED_PREFIX)?.rsplit_once('.')?;
+    let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+    let message = format!("{}={}", name, value);
+    keys()
+        .iter()
+        .any(|key| hmac::verify(key, message.as_bytes(), &tag).is_ok())
+        .then(|| value.to_string())
+}
+
+/// The cookies a request came with. Signed ones are only in `signed`, and
+/// only when their signature checks out.
+#[derive(Default)]
+pub struct Jar {
+    pub plain: Vec<(String, String)>,
+    pub signed: Vec<(String, String)>,
+}
+
+/// Parses a `Cookie` header. Values are percent-decoded and unquoted; when a
+/// name repeats, the first one wins, since browsers send the most specific
+/// path first.
+pub fn parse(header: &str) -> Jar {
+    let mut jar = Jar::default();
+    let signing = !keys().is_empty();
+    for pair in header.split(';') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let name = name.trim();
+        let value = value.trim();
+        let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+        if name.is_empty() || jar.plain.iter().chain(&jar.signed).any(|(n, _)| n == name) {
+            continue;
+        }
+        let value = percent_encoding::percent_decode_str(value).decode_utf8_lossy();
+        if signing && value.starts_with(SIGNED_PREFIX) {
+            if let Some(value) = unsign(name, &value) {
+                jar.signed.push((name.to_string(), value));
+            }
+        } else {
+            jar.plain.push((name.to_string(), value.into_owned()));
+        }
+    }
+    jar
+}
+
+/// All of a request's `Cookie` headers as one. HTTP/2 and HTTP/3 clients may
+/// send each cookie in a header of its own.
+pub fn header(headers: &axum::http::HeaderMap) -> Option<String> {
+    let values: Vec<&str> = headers.get_all(axum::http::header::COOKIE).iter().filter_map(|v| v.to_str().ok()).collect();
+    (!values.is_empty()).then(|| values.join("; "))
+}
@@ -0,0 +1,210 @@
+use axum::body::Body;
+use axum::http::{HeaderMap, Response, StatusCode, header};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::runtime::ResponseHeaders;
+
+const DEFAULT_METHODS: &str = "GET, HEAD, POST, PUT, PATCH, DELETE";
+const DEFAULT_MAX_AGE: u64 = 600;
+
+enum Origins {
+    Any,
+    /// Exact origins, or `https://*.example.com` to match its subdomains.
+    List(Vec<String>),
+}
+
+impl Origins {
+    fn allows(&self, origin: &str) -> bool {
+        match self {
+            Origins::Any => true,
+            Origins::List(list) => list.iter().any(|allowed| match allowed.split_once("*.") {
+                Some((scheme, domain)) => origin
+                    .strip_prefix(scheme)
+                    .and_then(|host| host.strip_suffix(domain))
+                    .is_some_and(|sub| sub.ends_with('.') && sub.len() > 1),
+                None => allowed.eq_ignore_ascii_case(origin),
+            }),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Policy {
+    origins: Arc<Origins>,
+    methods: String,
+    /// None echoes whatever the preflight asks for.
+    headers: Option<String>,
+    expose: Option<String>,
+    credentials: bool,
+    max_age: u64,
+}
+
+impl Policy {
+    fn with_overrides(base: Option<&Policy>, options: &Value) -> Self {
+        let list = |value: &Value| match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Array(items) => Some(items.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(", ")),
+            _ => None,
+        };
+        let origins = match &options["origins"] {
+            Value::String(s) if s == "*" => Arc::new(Origins::Any),
+            Value::String(s) => Arc::new(Origins::List(vec![s.trim_end_matches('/').to_string()])),
+            Value::Array(items) => Arc::new(Origins::List(
+                items.iter().filter_map(|o| o.as_str().map(|o| o.trim_end_matches('/').to_string())).collect(),
+            )),
+            _ => base.map_or_else(|| Arc::new(Origins::Any), |p| p.origins.clone()),
+        };
+        Self {
+            origins,
+            methods: list(&options["methods"])
+                .map(|m| m.to_ascii_uppercase())
+                .or_else(|| base.map(|p| p.methods.clone()))
+                .unwrap_or_else(|| DEFAULT_METHODS.to_string()),
+            headers: list(&options["headers"]).or_else(|| base.and_then(|p| p.headers.clone())),
+            expose: list(&options["expose_headers"]).or_else(|| base.and_then(|p| p.expose.clone())),
+            credentials: options["credentials"].as_bool().or(base.map(|p| p.credentials)).unwrap_or(false),
+            max_age: options["max_age_s"].as_u64().or(base.map(|p| p.max_age)).unwrap_or(DEFAULT_MAX_AGE),
+        }
+    }
+
+    /// `Access-Control-Allow-Origin` for `origin`, or None when it isn't
+    /// allowed. A credentialed response may not use `*`, so it echoes the
+    /// origin instead.
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        if !self.origins.allows(origin) {
+            return None;
+        }
+        match (&*self.origins, self.credentials) {
+            (Origins::Any, false) => Some("*".to_string()),
+            _ => Some(origin.to_string()),
+        }
+    }
+
+    /// Whether the answer depends on `Origin`, which caches must be told.
+    fn varies_by_origin(&self) -> bool {
+        !matches!((&*self.origins, self.credentials), (Origins::Any, false))
+    }
+}
+
+/// The `cors` block of titan.config: a default policy for every action and
+/// per-action ones under `routes` (`false` turns CORS off for one). `true`
+/// allows any origin without credentials.
+pub struct CorsConfig {
+    default: Option<Policy>,
+    routes: HashMap<String, Option<Policy>>,
+}
+
+impl CorsConfig {
+    pub fn from_config(config: &Value) -> Option<Self> {
+        let options = match config {
+            Value::Bool(true) => &Value::Null,
+            Value::Object(_) => config,
+            _ => return None,
+        };
+        // A block with only `routes` leaves the other actions without CORS
+        let only_routes = options.as_object().is_some_and(|o| o.len() == 1 && o.contains_key("routes"));
+        let default = (!only_routes).then(|| Policy::with_overrides(None, options));
+        let routes = options["routes"]
+            .as_object()
+            .map(|routes| {
+                routes
+                    .iter()
+                    .map(|(action, route)| {
+                        let policy = (route.as_bool() != Some(false)).then(|| Policy::with_overrides(default.as_ref(), route));
+                        (action.clone(), policy)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self { default, routes })
+    }
+
+    fn policy_for(&self, action: &str) -> Option<&Policy> {
+        match self.routes.get(action) {
+            Some(policy) => policy.as_ref(),
+            None => self.default.as_ref(),
+        }
+    }
+
+    /// The answer to a preflight for `action`, sent without involving a
+    /// worker. A disallowed origin, method or header gets the same 204 with
+    /// no `Access-Control-*` headers, which the browser treats as a refusal.
+    pub fn preflight(&self, action: &str, headers: &HeaderMap) -> Response<Body> {
+        let mut builder = Response::builder().status(StatusCode::NO_CONTENT);
+        let Some(policy) = self.policy_for(action) else {
+            return builder.body(Body::empty()).unwrap();
+        };
+        let requested_headers = headers
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        let vary = match (policy.varies_by_origin(), policy.headers.is_none()) {
+            (true, true) => "origin, access-control-request-method, access-control-request-headers",
+            (true, false) => "origin, access-control-request-method",
+            (false, true) => "access-control-request-headers",
+            (false, false) => "",
+        };
+        if !vary.is_empty() {
+            builder = builder.header(header::VARY, vary);
+        }
+
+        let origin = headers.get(header::ORIGIN).and_then(|v| v.to_str().ok()).unwrap_or("");
+        let method = requested_method(headers).unwrap_or_default();
+        let method_allowed = policy.methods.split(',').any(|m| m.trim() == method);
+        let headers_allowed = match &policy.headers {
+            None => true,
+            Some(allowed) => requested_headers
+                .split(',')
+                .map(str::trim)
+                .filter(|h| !h.is_empty())
+                .all(|h| allowed.split(',').any(|a| a.trim().eq_ignore_ascii_case(h))),
+        };
+        let Some(allow_origin) = policy.allow_origin(origin).filter(|_| method_allowed && headers_allowed) else {
+            return builder.body(Body::empty()).unwrap();
+        };
+
+        builder = builder
+            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin)
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, &policy.methods)
+            .header(header::ACCESS_CONTROL_MAX_AGE, policy.max_age.to_string());
+        let allow_headers = policy.headers.as_deref().unwrap_or(requested_headers);
+        if !allow_headers.is_empty() {
+            builder = builder.header(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+        }
+        if policy.credentials {
+            builder = builder.header(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    /// Adds the CORS headers of `action` to its response.
+    pub fn apply(&self, action: &str, origin: Option<&str>, headers: &mut ResponseHeaders) {
+        let Some(policy) = self.policy_for(action) else {
+            return;
+        };
+        // Cached responses must not be reused for another origin if they depend on it
+        if policy.varies_by_origin() {
+            headers.push(("vary".to_string(), "origin".to_string()));
+        }
+        let Some(allow_origin) = origin.and_then(|o| policy.allow_origin(o)) else {
+            return;
+        };
+        headers.push(("access-control-allow-origin".to_string(), allow_origin));
+        if policy.credentials {
+            headers.push(("access-control-allow-credentials".to_string(), "true".to_string()));
+        }
+        if let Some(expose) = &policy.expose {
+            headers.push(("access-control-expose-headers".to_string(), expose.clone()));
+        }
+    }
+}
+
+/// The method a preflight asks about, or None when `headers` aren't a
+/// preflight's.
+pub fn requested_method(headers: &HeaderMap) -> Option<String> {
+    headers.get(header::ORIGIN)?;
+    let method = headers.get(header::ACCESS_CONTROL_REQUEST_METHOD)?.to_str().ok()?;
+    Some(method.trim().to_ascii_uppercase())
+}
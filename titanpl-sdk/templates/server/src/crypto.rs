@@ -0,0 +1,203 @@
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{aead, digest, hmac};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CryptoError {
+    #[error("Unrecognized algorithm name: {0}")]
+    Unsupported(String),
+    #[error("AES-GCM keys must be 128 or 256 bits")]
+    KeyLength,
+    #[error("AES-GCM needs a 96-bit iv and a 128-bit tag")]
+    Parameters,
+    /// WebCrypto's (deliberately vague) OperationError, e.g. a bad tag.
+    #[error("The operation failed for an operation-specific reason")]
+    OperationFailed,
+}
+
+/// Digest of `data` with a WebCrypto hash name, or BLAKE3.
+pub fn digest(algorithm: &str, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let algorithm: &digest::Algorithm = match algorithm.to_ascii_uppercase().as_str() {
+        "SHA-1" => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        "SHA-256" => &digest::SHA256,
+        "SHA-384" => &digest::SHA384,
+        "SHA-512" => &digest::SHA512,
+        "BLAKE3" => return Ok(blake3(data).to_vec()),
+        _ => return Err(CryptoError::Unsupported(algorithm.to_string())),
+    };
+    Ok(digest::digest(algorithm, data).as_ref().to_vec())
+}
+
+fn hmac_key(hash: &str, key: &[u8]) -> Result<hmac::Key, CryptoError> {
+    let algorithm = match hash.to_ascii_uppercase().as_str() {
+        "SHA-1" => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+        "SHA-256" => hmac::HMAC_SHA256,
+        "SHA-384" => hmac::HMAC_SHA384,
+        "SHA-512" => hmac::HMAC_SHA512,
+        _ => return Err(CryptoError::Unsupported(format!("HMAC with {}", hash))),
+    };
+    Ok(hmac::Key::new(algorithm, key))
+}
+
+pub fn hmac_sign(hash: &str, key: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    Ok(hmac::sign(&hmac_key(hash, key)?, data).as_ref().to_vec())
+}
+
+/// Compares in constant time.
+pub fn hmac_verify(hash: &str, key: &[u8], data: &[u8], signature: &[u8]) -> Result<bool, CryptoError> {
+    Ok(hmac::verify(&hmac_key(hash, key)?, data, signature).is_ok())
+}
+
+/// AES-GCM with a 128-bit tag appended to the ciphertext, as WebCrypto lays
+/// it out.
+pub fn aes_gcm(encrypt: bool, key: &[u8], iv: &[u8], aad: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let algorithm = match key.len() {
+        16 => &aead::AES_128_GCM,
+        32 => &aead::AES_256_GCM,
+        _ => return Err(CryptoError::KeyLength),
+    };
+    let key = aead::LessSafeKey::new(aead::UnboundKey::new(algorithm, key).map_err(|_| CryptoError::KeyLength)?);
+    let nonce = aead::Nonce::try_assume_unique_for_key(iv).map_err(|_| CryptoError::Parameters)?;
+    let mut buf = data.to_vec();
+    if encrypt {
+        key.seal_in_place_append_tag(nonce, aead::Aad::from(aad), &mut buf)
+            .map_err(|_| CryptoError::OperationFailed)?;
+        Ok(buf)
+    } else {
+        let len = key
+            .open_in_place(nonce, aead::Aad::from(aad), &mut buf)
+            .map_err(|_| CryptoError::OperationFailed)?
+            .len();
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    let _ = SystemRandom::new().fill(&mut bytes);
+    bytes
+}
+
+// BLAKE3, hash mode with the default 32-byte output
+// (https://github.com/BLAKE3-team/BLAKE3-specs)
+
+const IV: [u32; 8] = [0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A, 0x510E527F, 0x9B05688C, 0x1F83D9AB, 0x5BE0CD19];
+const MSG_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+const CHUNK_LEN: usize = 1024;
+const BLOCK_LEN: usize = 64;
+const CHUNK_START: u32 = 1;
+const CHUNK_END: u32 = 2;
+const PARENT: u32 = 4;
+const ROOT: u32 = 8;
+
+fn g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(mx);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(my);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+fn compress(cv: &[u32; 8], block: &[u32; 16], counter: u64, block_len: u32, flags: u32) -> [u32; 16] {
+    let mut state = [
+        cv[0], cv[1], cv[2], cv[3], cv[4], cv[5], cv[6], cv[7],
+        IV[0], IV[1], IV[2], IV[3],
+        counter as u32, (counter >> 32) as u32, block_len, flags,
+    ];
+    let mut m = *block;
+    for round in 0..7 {
+        g(&mut state, 0, 4, 8, 12, m[0], m[1]);
+        g(&mut state, 1, 5, 9, 13, m[2], m[3]);
+        g(&mut state, 2, 6, 10, 14, m[4], m[5]);
+        g(&mut state, 3, 7, 11, 15, m[6], m[7]);
+        g(&mut state, 0, 5, 10, 15, m[8], m[9]);
+        g(&mut state, 1, 6, 11, 12, m[10], m[11]);
+        g(&mut state, 2, 7, 8, 13, m[12], m[13]);
+        g(&mut state, 3, 4, 9, 14, m[14], m[15]);
+        if round < 6 {
+            m = std::array::from_fn(|i| m[MSG_PERMUTATION[i]]);
+        }
+    }
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= cv[i];
+    }
+    state
+}
+
+fn first_8(words: [u32; 16]) -> [u32; 8] {
+    std::array::from_fn(|i| words[i])
+}
+
+fn block_words(bytes: &[u8]) -> [u32; 16] {
+    let mut padded = [0u8; BLOCK_LEN];
+    padded[..bytes.len()].copy_from_slice(bytes);
+    std::array::from_fn(|i| u32::from_le_bytes(padded[i * 4..i * 4 + 4].try_into().unwrap()))
+}
+
+/// A compression that hasn't run yet: the last block of a chunk or a parent,
+/// which becomes either a chaining value or, with ROOT, the hash.
+struct Output {
+    cv: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        first_8(compress(&self.cv, &self.block, self.counter, self.block_len, self.flags))
+    }
+}
+
+fn chunk_output(chunk: &[u8], counter: u64) -> Output {
+    let mut cv = IV;
+    let blocks: Vec<&[u8]> = if chunk.is_empty() { vec![chunk] } else { chunk.chunks(BLOCK_LEN).collect() };
+    let last = blocks.len() - 1;
+    for (i, block) in blocks[..last].iter().enumerate() {
+        let start = if i == 0 { CHUNK_START } else { 0 };
+        cv = first_8(compress(&cv, &block_words(block), counter, BLOCK_LEN as u32, start));
+    }
+    Output {
+        cv,
+        block: block_words(blocks[last]),
+        counter,
+        block_len: blocks[last].len() as u32,
+        flags: CHUNK_END | if last == 0 { CHUNK_START } else { 0 },
+    }
+}
+
+fn parent_output(left: &[u32; 8], right: &[u32; 8]) -> Output {
+    let block = std::array::from_fn(|i| if i < 8 { left[i] } else { right[i - 8] });
+    Output { cv: IV, block, counter: 0, block_len: BLOCK_LEN as u32, flags: PARENT }
+}
+
+fn blake3(data: &[u8]) -> [u8; 32] {
+    let chunks: Vec<&[u8]> = if data.is_empty() { vec![data] } else { data.chunks(CHUNK_LEN).collect() };
+    let last = chunks.len() - 1;
+    // Chaining values of complete subtrees, merged as soon as a pair exists
+    let mut stack: Vec<[u32; 8]> = Vec::new();
+    for (i, chunk) in chunks[..last].iter().enumerate() {
+        let mut cv = chunk_output(chunk, i as u64).chaining_value();
+        let mut total = i as u64 + 1;
+        while total & 1 == 0 {
+            cv = parent_output(&stack.pop().unwrap(), &cv).chaining_value();
+            total >>= 1;
+        }
+        stack.push(cv);
+    }
+    let mut output = chunk_output(chunks[last], last as u64);
+    while let Some(left) = stack.pop() {
+        output = parent_output(&left, &output.chaining_value());
+    }
+    let words = compress(&output.cv, &output.block, 0, output.block_len, output.flags | ROOT);
+    let mut hash = [0u8; 32];
+    for (i, word) in words[..8].iter().enumerate() {
+        hash[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    hash
+}
@@ -0,0 +1,178 @@
+//! The live dashboard of the admin API: a page that draws what `events`
+//! sends once a second from the pools' metrics, so a running server can be
+//! watched without a Prometheus stack.
+
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::runtime::RuntimeManager;
+
+/// Turns each pool's busy-time counters into utilization between samples.
+pub struct Sampler {
+    apps: Vec<(PathBuf, Arc<RuntimeManager>)>,
+    // Busy microseconds of every worker of every app at the last sample
+    busy: Vec<Vec<u64>>,
+    at: Instant,
+}
+
+impl Sampler {
+    pub fn new(apps: Vec<(PathBuf, Arc<RuntimeManager>)>) -> Self {
+        Self { apps, busy: Vec::new(), at: Instant::now() }
+    }
+
+    /// Every app's pool, with `utilization` (0 to 1) of each worker since the
+    /// previous sample. The first sample has none.
+    pub fn sample(&mut self) -> Value {
+        let wall_us = self.at.elapsed().as_micros().max(1) as f64;
+        self.at = Instant::now();
+        let mut busy = Vec::with_capacity(self.apps.len());
+        let apps: Vec<Value> = self
+            .apps
+            .iter()
+            .enumerate()
+            .map(|(app, (root, runtime))| {
+                let mut stats = runtime.dashboard();
+                let mut now = Vec::new();
+                if let Some(workers) = stats["workers"].as_array_mut() {
+                    for (i, worker) in workers.iter_mut().enumerate() {
+                        let us = worker["busy_us"].as_u64().unwrap_or(0);
+                        let utilization = self
+                            .busy
+                            .get(app)
+                            .and_then(|before| before.get(i))
+                            .map(|before| (us.saturating_sub(*before) as f64 / wall_us).min(1.0));
+                        worker["utilization"] = json!(utilization);
+                        now.push(us);
+                    }
+                }
+                busy.push(now);
+                stats["root"] = json!(root);
+                stats
+            })
+            .collect();
+        self.busy = busy;
+        let at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        json!({ "at": at, "apps": apps })
+    }
+}
+
+pub const PAGE: &str = r##"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Titan dashboard</title>
+<style>
+body { font: 14px/1.4 system-ui, sans-serif; margin: 0; background: #111417; color: #dde2e6; }
+header { padding: 12px 20px; background: #1b2025; display: flex; gap: 16px; align-items: baseline; }
+header h1 { font-size: 16px; margin: 0; }
+#status { color: #8a949c; }
+main { padding: 16px 20px; display: grid; gap: 16px; grid-template-columns: repeat(auto-fit, minmax(420px, 1fr)); }
+section { background: #1b2025; border-radius: 6px; padding: 12px 16px; }
+h2 { font-size: 13px; text-transform: uppercase; letter-spacing: .05em; color: #8a949c; margin: 0 0 8px; }
+h3 { font-size: 14px; margin: 20px 20px 0; }
+table { width: 100%; border-collapse: collapse; }
+td, th { text-align: left; padding: 3px 6px; border-bottom: 1px solid #2a3137; }
+th { color: #8a949c; font-weight: normal; }
+.num { text-align: right; font-variant-numeric: tabular-nums; }
+.bar { height: 8px; background: #2a3137; border-radius: 4px; overflow: hidden; }
+.bar > div { height: 100%; background: #4fa3e0; }
+canvas { width: 100%; height: 120px; }
+details { border-bottom: 1px solid #2a3137; padding: 4px 0; }
+summary { cursor: pointer; }
+pre { white-space: pre-wrap; color: #e08a8a; margin: 4px 0 0; font-size: 12px; }
+.muted { color: #8a949c; }
+</style>
+</head>
+<body>
+<header><h1>Titan</h1><span id="status">connecting…</span></header>
+<div id="apps"></div>
+<script>
+// The API key, when one is set, is passed in the fragment (#key=...) so it never reaches a log
+const key = new URLSearchParams(location.hash.slice(1)).get("key");
+const history = new Map();
+const esc = (s) => String(s ?? "").replace(/[&<>"]/g, (c) => ({ "&": "&amp;", "<": "&lt;", ">": "&gt;", '"': "&quot;" })[c]);
+const mb = (bytes) => (bytes / 1048576).toFixed(1) + " MB";
+const ms = (v) => (v == null ? "> 10 s" : v < 10 ? v.toFixed(2) + " ms" : Math.round(v) + " ms");
+
+function chart(canvas, points, limit) {
+  const ctx = canvas.getContext("2d");
+  const w = (canvas.width = canvas.clientWidth * devicePixelRatio);
+  const h = (canvas.height = canvas.clientHeight * devicePixelRatio);
+  const max = Math.max(1, limit || 0, ...points);
+  ctx.clearRect(0, 0, w, h);
+  ctx.strokeStyle = "#4fa3e0";
+  ctx.lineWidth = 2 * devicePixelRatio;
+  ctx.beginPath();
+  points.forEach((p, i) => {
+    const x = (i / 119) * w, y = h - (p / max) * (h - 4) - 2;
+    i ? ctx.lineTo(x, y) : ctx.moveTo(x, y);
+  });
+  ctx.stroke();
+  ctx.fillStyle = "#8a949c";
+  ctx.font = 11 * devicePixelRatio + "px system-ui";
+  ctx.fillText("max " + max, 4, 12 * devicePixelRatio);
+}
+
+function render(data) {
+  const root = document.getElementById("apps");
+  // Redrawn every second, so expanded errors are kept open
+  const open = new Set([...root.querySelectorAll("details[open]")].map((d) => d.dataset.id));
+  root.innerHTML = data.apps.map((app, n) => {
+    const points = history.get(n) || [];
+    points.push(app.queue.depth);
+    if (points.length > 120) points.shift();
+    history.set(n, points);
+    const workers = app.workers.map((w) => `<tr><td>${w.id}${w.running ? "" : ' <span class="muted">stopped</span>'}</td>
+      <td style="width:40%"><div class="bar"><div style="width:${((w.utilization ?? 0) * 100).toFixed(0)}%"></div></div></td>
+      <td class="num">${w.utilization == null ? "–" : ((w.utilization * 100).toFixed(0) + "%")}</td>
+      <td class="num">${w.executions}</td><td class="num">${mb(w.heap_used)} / ${mb(w.heap_total)}</td></tr>`).join("");
+    const slowest = app.slowest.map((a) => `<tr><td>${esc(a.action)}</td><td class="num">${a.count}</td>
+      <td class="num">${ms(a.mean_ms)}</td><td class="num">${ms(a.p95_ms)}</td><td class="num">${a.errors}</td></tr>`).join("");
+    const errors = app.recent_errors.map((e) => `<details data-id="${e.at}-${esc(e.request_id)}"${open.has(e.at + "-" + esc(e.request_id)) ? " open" : ""}><summary>${new Date(e.at).toLocaleTimeString()} <b>${esc(e.action)}</b>
+      ${esc(e.message)} <span class="muted">${esc(e.request_id)}</span></summary>${e.stack ? `<pre>${esc(e.stack)}</pre>` : ""}</details>`).join("");
+    return `${data.apps.length > 1 ? `<h3>${esc(app.root)}</h3>` : ""}<main>
+      <section><h2>Workers</h2><table><tr><th>#</th><th>Utilization</th><th></th><th class="num">Runs</th><th class="num">Heap</th></tr>${workers}</table></section>
+      <section><h2>Queue depth, last 2 minutes</h2><canvas data-app="${n}"></canvas>
+        <div class="muted">${app.queue.depth} waiting · ${app.queue.in_flight} in flight · limit ${app.queue.limit ?? "none"} · ${app.requests} requests, ${app.errors} errors</div></section>
+      <section><h2>Slowest actions</h2><table><tr><th>Action</th><th class="num">Requests</th><th class="num">Mean</th><th class="num">p95 ≤</th><th class="num">Errors</th></tr>${slowest}</table></section>
+      <section><h2>Recent errors</h2>${errors || '<span class="muted">none</span>'}</section></main>`;
+  }).join("");
+  root.querySelectorAll("canvas").forEach((c) => chart(c, history.get(+c.dataset.app), data.apps[+c.dataset.app].queue.limit));
+}
+
+// EventSource can't send a header, so the stream is read with fetch
+async function connect() {
+  const status = document.getElementById("status");
+  try {
+    const res = await fetch("dashboard/events", { headers: key ? { Authorization: "Bearer " + key } : {} });
+    if (res.status === 401) {
+      status.textContent = "unauthorized: open this page as /dashboard#key=<api key>";
+      return;
+    }
+    status.textContent = "live";
+    const reader = res.body.pipeThrough(new TextDecoderStream()).getReader();
+    let buffer = "";
+    for (;;) {
+      const { value, done } = await reader.read();
+      if (done) break;
+      buffer += value;
+      let end;
+      while ((end = buffer.indexOf("\n\n")) >= 0) {
+        const event = buffer.slice(0, end);
+        buffer = buffer.slice(end + 2);
+        const data = event.split("\n").filter((l) => l.startsWith("data:")).map((l) => l.slice(5)).join("\n");
+        if (data) render(JSON.parse(data));
+      }
+    }
+  } catch (e) {}
+  status.textContent = "disconnected, retrying…";
+  setTimeout(connect, 2000);
+}
+connect();
+</script>
+</body>
+</html>
+"##;
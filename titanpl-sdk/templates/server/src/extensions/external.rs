@@ -5,7 +5,6 @@ use std::fs;
 use std::sync::{Mutex, Arc};
 use walkdir::WalkDir;
 use libloading::Library;
-use crate::utils::{blue, green, red};
 use super::{TitanRuntime, v8_str, throw};
 use serde_json::Value;
 
@@ -134,20 +133,20 @@ pub fn load_project_extensions(root: PathBuf) {
                                           all_natives.push(NativeFnEntry { symbol_ptr: *symbol as usize, sig: Signature { params, ret } });
                                           mod_natives_map.insert(fn_name, idx);
                                      } else {
-                                          println!("{} {} {} -> {}", blue("[Titan]"), red("Symbol not found:"), fn_conf.symbol, config.name);
+                                          tracing::error!("Symbol not found: {} -> {}", fn_conf.symbol, config.name);
                                      }
                                  }
                                  libs.push(lib);
                             },
                             Err(e) => {
-                                println!("{} {} {} -> {:?}", blue("[Titan]"), red("Failed to load native lib:"), config.name, e);
+                                tracing::error!("Failed to load native lib: {} -> {:?}", config.name, e);
                             }
                          }
                      }
                 }
                 let js_path = dir.join(&config.main);
                 modules.push(ModuleDef { name: config.name.clone(), js: fs::read_to_string(js_path).unwrap_or_default(), native_indices: mod_natives_map });
-                println!("{} {} {}", blue("[Titan]"), green("Extension loaded:"), config.name);
+                tracing::info!("Extension loaded: {}", config.name);
             }
         }
     };
@@ -166,6 +165,11 @@ pub fn load_project_extensions(root: PathBuf) {
     *REGISTRY.lock().unwrap() = Some(Registry { _libs: libs, modules, natives: all_natives });
 }
 
+pub fn external_references() -> Vec<v8::ExternalReference<'static>> {
+    use v8::MapFnTo;
+    vec![v8::ExternalReference { function: native_invoke_extension.map_fn_to() }]
+}
+
 pub fn inject_external_extensions(scope: &mut v8::HandleScope, global: v8::Local<v8::Object>, t_obj: v8::Local<v8::Object>) {
     let invoke_fn = v8::Function::new(scope, native_invoke_extension).unwrap();
     let invoke_key = v8_str(scope, "__titan_invoke_native");
@@ -1,18 +1,22 @@
 #![allow(unused)]
 pub mod builtin;
 pub mod external;
+pub mod modules;
 
-use crate::action_management::scan_actions;
-use crate::utils::{blue, gray, green, red};
+use crate::action_management::{scan_actions, MIDDLEWARE_BUNDLE};
+use crate::error::TitanError;
+use crate::sourcemap;
 use bytes::Bytes;
 use crossbeam::channel::Sender;
 use dashmap::DashMap;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Once;
 use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
 use tokio::sync::broadcast;
 use v8;
 
@@ -54,19 +58,46 @@ pub enum TitanAsyncOp {
     Fetch {
         url: String,
         method: String,
-        body: Option<String>,
+        body: Option<Bytes>,
         headers: Vec<(String, String)>,
+        timeout_ms: Option<u64>,
     },
     DbQuery {
         conn: String,
         query: String,
+        params: Vec<serde_json::Value>,
     },
     FsRead {
+        root: PathBuf,
         path: String,
     },
+    // One pipeline of Redis commands, each a list of arguments
+    Redis {
+        url: String,
+        commands: Vec<Vec<String>>,
+    },
+    // Next chunk of a streamed request body
+    BodyRead {
+        id: u64,
+    },
+    // `fs.*` inside the fs.allow directories
+    Fs(crate::files::FsOp),
     Batch(Vec<TitanAsyncOp>),
 }
 
+/// Result handed to the promise of a `t._async_start` op.
+pub enum AsyncOutcome {
+    Json(Value),
+    /// A `fetch()` response; the body reaches JS as an ArrayBuffer.
+    Response {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Bytes,
+    },
+    /// Raw bytes, handed to JS as an ArrayBuffer.
+    Bytes(Bytes),
+}
+
 pub struct WorkerAsyncResult {
     pub drift_id: u32,
     pub result: serde_json::Value,
@@ -81,8 +112,15 @@ pub struct AsyncOpRequest {
     pub respond_tx: tokio::sync::oneshot::Sender<WorkerAsyncResult>,
 }
 
+/// Where a request's response, or the error that replaced it, goes.
+pub type ResponseSender = tokio::sync::oneshot::Sender<Result<crate::runtime::WorkerResult, TitanError>>;
+
 pub struct TitanRuntime {
     pub id: usize,
+    // Declared before the isolate so that they are dropped first, the
+    // profiler's session before the inspector it runs on
+    pub profiler: Option<crate::profiler::Profiler>,
+    pub inspector: Option<crate::inspector::Inspector>,
     pub isolate: v8::OwnedIsolate,
     pub context: v8::Global<v8::Context>,
     pub actions: HashMap<String, v8::Global<v8::Function>>,
@@ -92,7 +130,7 @@ pub struct TitanRuntime {
     pub async_rx: crossbeam::channel::Receiver<WorkerAsyncResult>,
     pub async_tx: crossbeam::channel::Sender<WorkerAsyncResult>,
     pub pending_drifts: HashMap<u32, v8::Global<v8::PromiseResolver>>,
-    pub pending_requests: HashMap<u32, tokio::sync::oneshot::Sender<crate::runtime::WorkerResult>>,
+    pub pending_requests: HashMap<u32, ResponseSender>,
     pub drift_counter: u32,
     pub request_counter: u32,
     
@@ -103,6 +141,167 @@ pub struct TitanRuntime {
     pub completed_drifts: HashMap<u32, serde_json::Value>,
     pub active_requests: HashMap<u32, RequestData>,
     pub request_start_counters: HashMap<u32, u32>,
+    pub streams: HashMap<u32, ResponseStream>,
+    pub sockets: HashMap<u32, tokio::sync::mpsc::UnboundedSender<crate::websocket::WsMessage>>,
+    // Set by the heap callback or watchdog just before they terminate the isolate
+    pub limit_exceeded: Arc<Mutex<Option<crate::runtime::LimitExceeded>>>,
+
+    // Event loop: promise-returning ops (`fetch()`) and timers, keyed by id
+    pub pending_ops: HashMap<u32, PendingOp>,
+    pub op_counter: u32,
+    pub timers: BTreeMap<(Instant, u32), u32>,
+    // Outcomes of side-effecting natives (tasks, kv, bus), per request
+    pub replay_logs: HashMap<u32, ReplayLog>,
+
+    // Pub/sub: this isolate's subscriptions and the topics its bridge forwards
+    pub bus_subscriptions: HashMap<u32, BusSubscription>,
+    pub bus_topics: Arc<crate::bus::Topics>,
+}
+
+/// An async op started with `t._async_start`. Unlike a drift it doesn't
+/// suspend the action; its promise is resolved when the result comes back.
+pub struct PendingOp {
+    pub resolver: v8::Global<v8::PromiseResolver>,
+    pub request_id: u32,
+}
+
+/// A `bus.subscribe()` handler. It belongs to the request that registered it,
+/// or to its WebSocket, and receives nothing once that owner is gone.
+pub struct BusSubscription {
+    pub topic: String,
+    pub request_id: u32,
+    pub socket_id: Option<u32>,
+}
+
+/// Outcomes of the side-effecting natives a request has called, in call
+/// order. Replays get the outcomes of earlier executions back instead of
+/// repeating the effect.
+#[derive(Default)]
+pub struct ReplayLog {
+    pub results: Vec<Result<String, String>>,
+    pub cursor: usize,
+}
+
+impl ReplayLog {
+    /// Runs `op` unless an earlier execution of the request already did.
+    /// Calls outside a request (id 0) are never replayed, so aren't logged.
+    pub fn once(
+        logs: &mut HashMap<u32, ReplayLog>,
+        request_id: u32,
+        op: impl FnOnce() -> Result<String, String>,
+    ) -> Result<String, String> {
+        if request_id == 0 {
+            return op();
+        }
+        let log = logs.entry(request_id).or_default();
+        let result = match log.results.get(log.cursor) {
+            Some(result) => result.clone(),
+            None => {
+                let result = op();
+                log.results.push(result.clone());
+                result
+            }
+        };
+        log.cursor += 1;
+        result
+    }
+}
+
+/// Body channel of a request that is streaming its response via `res.write()`.
+/// Actions are replayed after every drift, so chunks are counted per execution
+/// and only the ones past `sent` are forwarded.
+pub struct ResponseStream {
+    pub tx: Option<StreamSender>,
+    pub sent: u32,
+    pub cursor: u32,
+    /// The writes that returned false, so a replay answers them the same way.
+    pub backed_up: Vec<u32>,
+}
+
+/// Bytes waiting for the client past which `res.write()` returns false.
+pub const STREAM_HIGH_WATER: usize = 1024 * 1024;
+/// Bytes waiting for the client past which the stream is cut off, for an
+/// action that ignores `res.write()` returning false.
+pub const STREAM_MAX_QUEUED: usize = 64 * 1024 * 1024;
+
+/// The worker's end of a streamed body. Sending never waits, so a slow
+/// client can't hold the isolate; it counts what the client hasn't taken yet.
+pub struct StreamSender {
+    tx: tokio::sync::mpsc::UnboundedSender<Bytes>,
+    backlog: Arc<StreamBacklog>,
+}
+
+/// The HTTP layer's end of a streamed body.
+pub struct StreamReceiver {
+    rx: tokio::sync::mpsc::UnboundedReceiver<Bytes>,
+    backlog: Arc<StreamBacklog>,
+}
+
+/// Bytes the client hasn't taken yet, and the wakeup for `res.drain()` once
+/// they drop back to `STREAM_HIGH_WATER`.
+#[derive(Default)]
+pub struct StreamBacklog {
+    queued: AtomicUsize,
+    drained: tokio::sync::Notify,
+}
+
+pub fn stream_channel() -> (StreamSender, StreamReceiver) {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    let backlog = Arc::new(StreamBacklog::default());
+    (StreamSender { tx, backlog: backlog.clone() }, StreamReceiver { rx, backlog })
+}
+
+impl StreamSender {
+    /// Queues `chunk` and returns the bytes now waiting for the client, or
+    /// None if the client went away.
+    pub fn send(&self, chunk: Bytes) -> Option<usize> {
+        let len = chunk.len();
+        let queued = self.backlog.queued.fetch_add(len, Ordering::AcqRel) + len;
+        self.tx.send(chunk).ok().map(|_| queued)
+    }
+
+    pub fn queued(&self) -> usize {
+        self.backlog.queued.load(Ordering::Acquire)
+    }
+
+    pub fn backlog(&self) -> Arc<StreamBacklog> {
+        self.backlog.clone()
+    }
+}
+
+impl StreamBacklog {
+    /// Resolves once the client is back under `STREAM_HIGH_WATER`, or gone.
+    pub async fn drained(&self) {
+        loop {
+            let notified = self.drained.notified();
+            tokio::pin!(notified);
+            // Registered before the check, so a drop in between still wakes us
+            notified.as_mut().enable();
+            if self.queued.load(Ordering::Acquire) <= STREAM_HIGH_WATER {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl StreamReceiver {
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        let chunk = self.rx.recv().await?;
+        let before = self.backlog.queued.fetch_sub(chunk.len(), Ordering::AcqRel);
+        if before > STREAM_HIGH_WATER && before - chunk.len() <= STREAM_HIGH_WATER {
+            self.backlog.drained.notify_waiters();
+        }
+        Some(chunk)
+    }
+}
+
+impl Drop for StreamReceiver {
+    // Nothing is taken once the client is gone, so nobody should wait on it
+    fn drop(&mut self) {
+        self.backlog.queued.store(0, Ordering::Release);
+        self.backlog.drained.notify_waiters();
+    }
 }
 
 #[derive(Clone)]
@@ -114,6 +313,19 @@ pub struct RequestData {
     pub headers: Vec<(String, String)>,
     pub params: Vec<(String, String)>,
     pub query: Vec<(String, String)>,
+    pub socket_id: Option<u32>,
+    pub ticket: u64,
+    pub correlation_id: String,
+    pub trace: Option<crate::telemetry::TraceContext>,
+    pub form: Option<std::sync::Arc<crate::multipart::Form>>,
+    pub ip: Option<std::net::IpAddr>,
+    pub auth: Option<std::sync::Arc<serde_json::Value>>,
+    pub session: Option<std::sync::Arc<serde_json::Value>>,
+    pub geo: Option<std::sync::Arc<serde_json::Value>>,
+    pub middleware: Option<std::sync::Arc<[String]>>,
+    pub error: Option<std::sync::Arc<serde_json::Value>>,
+    pub deadline: Option<std::time::SystemTime>,
+    pub body_stream: Option<std::sync::Arc<crate::body::StreamedBody>>,
 }
 
 unsafe impl Send for TitanRuntime {}
@@ -143,68 +355,55 @@ pub fn init_runtime_worker(
     tokio_handle: tokio::runtime::Handle,
     global_async_tx: tokio::sync::mpsc::Sender<AsyncOpRequest>,
     stack_size: usize,
+    max_heap_bytes: Option<usize>,
 ) -> TitanRuntime {
     init_v8();
 
-    // Memory optimization strategy
-    let params = v8::CreateParams::default();
+    let mut params = v8::CreateParams::default();
+    if let Some(max) = max_heap_bytes {
+        params = params.heap_limits(0, max);
+    }
+
+    // Scripts restored from a snapshot have no source to show in DevTools
+    let snapshot = if crate::inspector::enabled() || RELOADED.load(Ordering::SeqCst) { None } else { startup_snapshot(&root) };
+    if let Some(blob) = snapshot {
+        // Boot from the pre-evaluated heap: no parsing or module evaluation
+        params = params
+            .snapshot_blob(blob)
+            .external_references(&**external_references());
+    }
+    let from_snapshot = snapshot.is_some();
     let mut isolate = v8::Isolate::new(params);
     
     let (global_context, actions_map) = {
         let handle_scope = &mut v8::HandleScope::new(&mut isolate);
         let context = v8::Context::new(handle_scope, v8::ContextOptions::default());
         let scope = &mut v8::ContextScope::new(handle_scope, context);
-        let global = context.global(scope);
 
-        // Inject Titan Runtime APIs
-        inject_extensions(scope, global);
-
-        // Root Metadata (Dynamic per app instance)
-        let root_str = v8::String::new(scope, root.to_str().unwrap_or(".")).unwrap();
-        let root_key = v8_str(scope, "__titan_root");
-        global.set(scope, root_key.into(), root_str.into());
-
-        // Load Actions (Cold start optimization target)
-        let mut map = HashMap::new();
-        let action_files = scan_actions(&root);
-        for (name, path) in action_files {
-            if let Ok(code) = fs::read_to_string(&path) {
-                // Wrap action in an IIFE to capture its exports and register it globally
-                let wrapped_source =
-                    format!("(function() {{ {} }})(); globalThis[\"{}\"];", code, name);
-                let source_str = v8_str(scope, &wrapped_source);
-                let try_catch = &mut v8::TryCatch::new(scope);
-                if let Some(script) = v8::Script::compile(try_catch, source_str, None) {
-                    if let Some(val) = script.run(try_catch) {
-                        if val.is_function() {
-                            let func = v8::Local::<v8::Function>::try_from(val).unwrap();
-                            map.insert(name.clone(), v8::Global::new(try_catch, func));
-                        } else if id == 0 {
-                            println!("[V8] Action '{}' did not evaluate to a function: {:?}", name, val.to_rust_string_lossy(try_catch));
-                        }
-                    } else if id == 0 {
-                        let msg = try_catch
-                            .message()
-                            .map(|m| m.get(try_catch).to_rust_string_lossy(try_catch))
-                            .unwrap_or("Unknown run error".to_string());
-                        println!("[V8] Failed to run action '{}': {}", name, msg);
-                    }
-                } else if id == 0 {
-                    let msg = try_catch
-                        .message()
-                        .map(|m| m.get(try_catch).to_rust_string_lossy(try_catch))
-                        .unwrap_or("Unknown compile error".to_string());
-                    println!("[V8] Failed to compile action '{}': {}", name, msg);
-                }
-            }
+        if !from_snapshot {
+            load_actions(scope, context, &root, id);
+        } else {
+            // The snapshot holds the root it was built in, which a standalone
+            // binary's extraction dir need not match
+            let global = context.global(scope);
+            let root_str = v8_str(scope, root.to_str().unwrap_or("."));
+            let root_key = v8_str(scope, "__titan_root");
+            global.set(scope, root_key.into(), root_str.into());
         }
+        let map = collect_actions(scope, context, &root);
         (v8::Global::new(scope, context), map)
     };
 
     let (async_tx, async_rx) = crossbeam::channel::unbounded();
+    let bus_topics = crate::bus::bridge(&tokio_handle, worker_tx.clone());
+    let mut inspector = (crate::inspector::enabled() || crate::profiler::enabled())
+        .then(|| crate::inspector::Inspector::new(&mut isolate, &global_context, id));
+    let profiler = inspector.as_mut().and_then(crate::profiler::Profiler::start);
 
     TitanRuntime {
         id,
+        profiler,
+        inspector,
         isolate,
         context: global_context,
         actions: actions_map,
@@ -222,7 +421,210 @@ pub fn init_runtime_worker(
         completed_drifts: HashMap::new(),
         active_requests: HashMap::new(),
         request_start_counters: HashMap::new(),
+        streams: HashMap::new(),
+        sockets: HashMap::new(),
+        pending_ops: HashMap::new(),
+        op_counter: 0,
+        timers: BTreeMap::new(),
+        replay_logs: HashMap::new(),
+        bus_subscriptions: HashMap::new(),
+        bus_topics,
+        limit_exceeded: Arc::new(Mutex::new(None)),
+    }
+}
+
+// ----------------------------------------------------------------------------
+// STARTUP SNAPSHOT
+// ----------------------------------------------------------------------------
+
+static EXTERNAL_REFERENCES: OnceLock<v8::ExternalReferences> = OnceLock::new();
+// One per app root, since each app's workers evaluate different actions;
+// None once building one has failed
+type Snapshot = Option<&'static [u8]>;
+static STARTUP_SNAPSHOTS: Mutex<Vec<(PathBuf, Snapshot)>> = Mutex::new(Vec::new());
+// Where `titan-server build` saved a snapshot, when one may be used
+static SNAPSHOT_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+// Set once actions are swapped at runtime, which leaves the snapshot behind
+static RELOADED: AtomicBool = AtomicBool::new(false);
+
+// Starts a snapshot file, ahead of the fingerprint line and the blob
+const SNAPSHOT_MAGIC: &[u8] = b"TITANSNAP1\n";
+
+fn external_references() -> &'static v8::ExternalReferences {
+    EXTERNAL_REFERENCES.get_or_init(|| {
+        let mut refs = builtin::external_references();
+        refs.extend(external::external_references());
+        v8::ExternalReferences::new(&refs)
+    })
+}
+
+/// The snapshot is built once per app by whichever of its workers starts
+/// first; the others wait for it and boot from the same blob. One saved by
+/// `build` is used instead when it was made by this binary from these actions.
+fn startup_snapshot(root: &PathBuf) -> Option<&'static [u8]> {
+    let mut snapshots = STARTUP_SNAPSHOTS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, blob)) = snapshots.iter().find(|(built_for, _)| built_for == root) {
+        return *blob;
+    }
+    // A standalone binary's snapshot, or a saved one, is of the main app
+    let primary = PROJECT_ROOT.get() == Some(root);
+    let blob = if let Some((_, blob)) = crate::standalone::snapshot().and_then(split_snapshot).filter(|_| primary) {
+        Some(blob.to_vec().into_boxed_slice())
+    } else if let Some(blob) = SNAPSHOT_FILE.get().filter(|_| primary).and_then(|path| read_snapshot(path, root)) {
+        Some(blob)
+    } else {
+        let blob = build_snapshot(root);
+        if blob.is_none() {
+            tracing::error!("Startup snapshot failed, falling back to cold start");
+        }
+        blob
+    };
+    let blob: Snapshot = blob.map(|blob| &*Box::leak(blob));
+    snapshots.push((root.clone(), blob));
+    blob
+}
+
+/// Evaluates the Titan core and every action in a snapshot-creator isolate and
+/// serializes the resulting heap.
+pub fn build_snapshot(root: &PathBuf) -> Option<Box<[u8]>> {
+    init_v8();
+
+    let mut creator = v8::Isolate::snapshot_creator(Some(external_references()), None);
+    {
+        let handle_scope = &mut v8::HandleScope::new(&mut creator);
+        let context = v8::Context::new(handle_scope, v8::ContextOptions::default());
+        let scope = &mut v8::ContextScope::new(handle_scope, context);
+        load_actions(scope, context, root, 0);
+        scope.set_default_context(context);
+    }
+
+    creator
+        .create_blob(v8::FunctionCodeHandling::Keep)
+        .map(|blob| blob.to_vec().into_boxed_slice())
+}
+
+/// Lets the workers boot from the snapshot at `path`, if it is current.
+pub fn use_snapshot_file(path: PathBuf) {
+    let _ = SNAPSHOT_FILE.set(path);
+}
+
+// What a saved snapshot depends on: the binary, whose native callbacks it
+// refers to by position, and every action file
+fn snapshot_fingerprint(root: &PathBuf) -> Option<String> {
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    context.update(&fs::read(std::env::current_exe().ok()?).ok()?);
+    let mut actions: Vec<_> = scan_actions(root).into_iter().collect();
+    actions.sort();
+    for (name, path) in actions {
+        context.update(name.as_bytes());
+        context.update(&fs::read(path).ok()?);
+    }
+    Some(context.finish().as_ref().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Builds the startup snapshot and saves it to `path`, returning its size.
+pub fn write_snapshot(root: &PathBuf, path: &std::path::Path) -> Result<usize, String> {
+    let fingerprint = snapshot_fingerprint(root).ok_or("could not read the binary or the actions")?;
+    let blob = build_snapshot(root).ok_or("V8 could not create the snapshot")?;
+    let mut file = SNAPSHOT_MAGIC.to_vec();
+    file.extend_from_slice(fingerprint.as_bytes());
+    file.push(b'\n');
+    file.extend_from_slice(&blob);
+    fs::write(path, &file).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(file.len())
+}
+
+// A snapshot file's fingerprint and blob
+fn split_snapshot(file: &[u8]) -> Option<(&[u8], &[u8])> {
+    let rest = file.strip_prefix(SNAPSHOT_MAGIC)?;
+    let newline = rest.iter().position(|b| *b == b'\n')?;
+    Some((&rest[..newline], &rest[newline + 1..]))
+}
+
+fn read_snapshot(path: &std::path::Path, root: &PathBuf) -> Option<Box<[u8]>> {
+    let file = fs::read(path).ok()?;
+    let (fingerprint, blob) = split_snapshot(&file)?;
+    if snapshot_fingerprint(root)?.as_bytes() != fingerprint {
+        tracing::warn!("{} is out of date; building the snapshot again", path.display());
+        return None;
+    }
+    tracing::info!("Booting from {}", path.display());
+    Some(blob.to_vec().into_boxed_slice())
+}
+
+/// Injects the runtime APIs and evaluates every action into `context`.
+fn load_actions(scope: &mut v8::HandleScope, context: v8::Local<v8::Context>, root: &PathBuf, id: usize) {
+    let global = context.global(scope);
+
+    // Inject Titan Runtime APIs
+    inject_extensions(scope, global);
+
+    // Root Metadata (Dynamic per app instance)
+    let root_str = v8::String::new(scope, root.to_str().unwrap_or(".")).unwrap();
+    let root_key = v8_str(scope, "__titan_root");
+    global.set(scope, root_key.into(), root_str.into());
+
+    // Load Actions (Cold start optimization target)
+    let action_files = scan_actions(root);
+    for (name, path) in action_files {
+        if let Err(e) = load_action_file(scope, root, &name, &path)
+            && id == 0
+        {
+            tracing::error!(action = %name, "{}", e);
+        }
+    }
+    modules::reset();
+}
+
+// Evaluates one action file, which registers the action on `globalThis`
+fn load_action_file(scope: &mut v8::HandleScope, root: &PathBuf, name: &str, path: &PathBuf) -> Result<(), String> {
+    // Unbundled `.ts` and `.mjs` actions are ES modules
+    if path.extension().is_some_and(|ext| ext == "ts" || ext == "mjs") {
+        return modules::load_action(scope, path, root, name).map_err(|e| format!("Failed to load action '{}': {}", name, e));
+    }
+    let code = fs::read_to_string(path).map_err(|e| format!("Failed to read action '{}': {}", name, e))?;
+    // Wrap action in an IIFE to capture its exports and register it globally.
+    // The opener sits on its own line and the origin starts at line -1, so
+    // stack positions line up with the bundle and its source map.
+    let wrapped_source = format!("(function() {{\n{}\n}})(); globalThis[\"{}\"];", code, name);
+    let source_str = v8_str(scope, &wrapped_source);
+    let resource = path.strip_prefix(root).unwrap_or(path).to_string_lossy().replace('\\', "/");
+    sourcemap::register(&resource, path, root);
+    let resource_name = v8_str(scope, &resource);
+    let origin = v8::ScriptOrigin::new(scope, resource_name.into(), -1, 0, false, 0, None, false, false, false, None);
+    let try_catch = &mut v8::TryCatch::new(scope);
+    let message = |try_catch: &mut v8::TryCatch<v8::HandleScope>, fallback: &str| {
+        try_catch
+            .message()
+            .map(|m| m.get(try_catch).to_rust_string_lossy(try_catch))
+            .unwrap_or(fallback.to_string())
+    };
+    let Some(script) = v8::Script::compile(try_catch, source_str, Some(&origin)) else {
+        return Err(format!("Failed to compile action '{}': {}", name, message(try_catch, "Unknown compile error")));
+    };
+    let Some(val) = script.run(try_catch) else {
+        return Err(format!("Failed to run action '{}': {}", name, message(try_catch, "Unknown run error")));
+    };
+    if !val.is_function() && name != MIDDLEWARE_BUNDLE {
+        return Err(format!("Action '{}' did not evaluate to a function: {:?}", name, val.to_rust_string_lossy(try_catch)));
+    }
+    Ok(())
+}
+
+/// Looks up the action functions registered on `globalThis` by `load_actions`.
+fn collect_actions(scope: &mut v8::HandleScope, context: v8::Local<v8::Context>, root: &PathBuf) -> HashMap<String, v8::Global<v8::Function>> {
+    let global = context.global(scope);
+    let mut map = HashMap::new();
+    for name in scan_actions(root).into_keys() {
+        let key = v8_str(scope, &name);
+        if let Some(val) = global.get(scope, key.into())
+            && let Ok(func) = v8::Local::<v8::Function>::try_from(val)
+        {
+            map.insert(name, v8::Global::new(scope, func));
+        }
     }
+    map
 }
 
 pub fn inject_extensions(scope: &mut v8::HandleScope, global: v8::Local<v8::Object>) {
@@ -247,27 +649,50 @@ pub fn v8_to_json<'s>(
     scope: &mut v8::HandleScope<'s>,
     value: v8::Local<v8::Value>,
 ) -> serde_json::Value {
+    try_v8_to_json(scope, value).unwrap_or(serde_json::Value::Null)
+}
+
+// Anything nested deeper is almost certainly a circular reference
+const MAX_JSON_DEPTH: usize = 128;
+
+/// Like `v8_to_json`, but fails on values nested past `MAX_JSON_DEPTH`
+/// instead of overflowing the stack on a circular reference.
+pub fn try_v8_to_json<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    value: v8::Local<v8::Value>,
+) -> Result<serde_json::Value, String> {
+    to_json_at(scope, value, 0)
+}
+
+fn to_json_at<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    value: v8::Local<v8::Value>,
+    depth: usize,
+) -> Result<serde_json::Value, String> {
+    if depth > MAX_JSON_DEPTH {
+        return Err(format!("value nests deeper than {} levels (circular reference?)", MAX_JSON_DEPTH));
+    }
     if value.is_null_or_undefined() {
-        return serde_json::Value::Null;
+        return Ok(serde_json::Value::Null);
     }
 
     // Boolean
     if value.is_boolean() {
-        return serde_json::Value::Bool(value.boolean_value(scope));
+        return Ok(serde_json::Value::Bool(value.boolean_value(scope)));
     }
 
     // Number
     if value.is_number() {
         let n = value.number_value(scope).unwrap_or(0.0);
-        return serde_json::Value::Number(
+        return Ok(serde_json::Value::Number(
             serde_json::Number::from_f64(n).unwrap_or_else(|| serde_json::Number::from(0)),
-        );
+        ));
     }
 
     // String
     if value.is_string() {
         let s = value.to_string(scope).unwrap().to_rust_string_lossy(scope);
-        return serde_json::Value::String(s);
+        return Ok(serde_json::Value::String(s));
     }
 
     // Array
@@ -278,9 +703,9 @@ pub fn v8_to_json<'s>(
             let element = arr
                 .get_index(scope, i)
                 .unwrap_or_else(|| v8::null(scope).into());
-            list.push(v8_to_json(scope, element));
+            list.push(to_json_at(scope, element, depth + 1)?);
         }
-        return serde_json::Value::Array(list);
+        return Ok(serde_json::Value::Array(list));
     }
 
     // Object
@@ -307,19 +732,36 @@ pub fn v8_to_json<'s>(
                 .get(scope, key_val.into())
                 .unwrap_or_else(|| v8::null(scope).into());
 
-            map.insert(key, v8_to_json(scope, val));
+            map.insert(key, to_json_at(scope, val, depth + 1)?);
         }
 
-        return serde_json::Value::Object(map);
+        return Ok(serde_json::Value::Object(map));
     }
 
-    serde_json::Value::Null
+    Ok(serde_json::Value::Null)
 }
 
 // ----------------------------------------------------------------------------
 // EXECUTION HELPERS
 // ----------------------------------------------------------------------------
 
+/// What `req.body` reads as: MessagePack and CBOR decoded straight from the
+/// bytes, anything else parsed as JSON when it is JSON and left as text when
+/// it isn't.
+fn parse_body<'s>(scope: &mut v8::HandleScope<'s>, bytes: &[u8], content_type: Option<&str>) -> v8::Local<'s, v8::Value> {
+    use crate::formats::Format;
+    if let Some(format @ (Format::MsgPack | Format::Cbor)) = content_type.and_then(Format::from_content_type) {
+        // Already validated by the HTTP layer
+        return format.to_v8(scope, bytes).unwrap_or_else(|_| v8::null(scope).into());
+    }
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return v8::null(scope).into();
+    };
+    let text = v8_str(scope, text);
+    let try_catch = &mut v8::TryCatch::new(scope);
+    v8::json::parse(try_catch, text).unwrap_or(text.into())
+}
+
 pub fn execute_action_optimized(
     runtime: &mut TitanRuntime,
     request_id: u32,
@@ -331,6 +773,16 @@ pub fn execute_action_optimized(
     params: &[(String, String)],
     query: &[(String, String)],
 ) {
+    // Replays start writing from the first chunk again
+    if let Some(stream) = runtime.streams.get_mut(&request_id) {
+        stream.cursor = 0;
+    }
+    if let Some(log) = runtime.replay_logs.get_mut(&request_id) {
+        log.cursor = 0;
+    }
+
+    let correlation_id = correlation_id(runtime, request_id);
+
     // Execute action in V8
     let context_global = runtime.context.clone();
     let actions_map = runtime.actions.clone(); // Clone the map of globals (cheap)
@@ -354,16 +806,15 @@ pub fn execute_action_optimized(
     let p_val = v8_str(scope, req_path);
     req_obj.set(scope, p_key.into(), p_val.into());
 
-    let body_val: v8::Local<v8::Value> = if let Some(bytes) = req_body {
-        let vec = bytes.to_vec();
-        let store = v8::ArrayBuffer::new_backing_store_from_boxed_slice(vec.into_boxed_slice());
+    // The body goes in as it arrived; req.body, req.text() and req.json() are
+    // parsed from it in titan_core.js the first time an action asks
+    if let Some(bytes) = req_body {
+        // Vec::from reuses the allocation when the Bytes is uniquely owned
+        let store = v8::ArrayBuffer::new_backing_store_from_boxed_slice(Vec::from(bytes).into_boxed_slice());
         let ab = v8::ArrayBuffer::with_backing_store(scope, &store.make_shared());
-        ab.into()
-    } else {
-        v8::null(scope).into()
-    };
-    let rb_key = v8_str(scope, "rawBody");
-    req_obj.set(scope, rb_key.into(), body_val);
+        let rb_key = v8_str(scope, "__titan_raw_body");
+        req_obj.set(scope, rb_key.into(), ab.into());
+    }
 
     let h_obj = v8::Object::new(scope);
     for (k, v) in headers {
@@ -374,23 +825,176 @@ pub fn execute_action_optimized(
     let h_key = v8_str(scope, "headers");
     req_obj.set(scope, h_key.into(), h_obj.into());
 
+    // Catch-alls in the action's own path are arrays of segments
+    let arrays: Vec<&str> = crate::router::array_params(action_name).collect();
     let p_obj = v8::Object::new(scope);
     for (k, v) in params {
         let k_v8 = v8_str(scope, k);
-        let v_v8 = v8_str(scope, v);
-        p_obj.set(scope, k_v8.into(), v_v8.into());
+        let v_v8: v8::Local<v8::Value> = if arrays.contains(&k.as_str()) {
+            let segments: Vec<v8::Local<v8::Value>> = v.split('/').filter(|s| !s.is_empty()).map(|s| v8_str(scope, s).into()).collect();
+            v8::Array::new_with_elements(scope, &segments).into()
+        } else {
+            v8_str(scope, v).into()
+        };
+        p_obj.set(scope, k_v8.into(), v_v8);
     }
     let params_key = v8_str(scope, "params");
     req_obj.set(scope, params_key.into(), p_obj.into());
 
-    let q_obj = v8::Object::new(scope);
-    for (k, v) in query {
-        let k_v8 = v8_str(scope, k);
-        let v_v8 = v8_str(scope, v);
-        q_obj.set(scope, k_v8.into(), v_v8.into());
-    }
+    let q_val: v8::Local<v8::Value> = if crate::query::is_structured() {
+        // JSON.parse makes `__proto__` an own key rather than the prototype
+        let json = v8_str(scope, &crate::query::structured(query).to_string());
+        v8::json::parse(scope, json).unwrap_or_else(|| v8::Object::new(scope).into())
+    } else {
+        let q_obj = v8::Object::new(scope);
+        for (k, v) in query {
+            let k_v8 = v8_str(scope, k);
+            let v_v8 = v8_str(scope, v);
+            q_obj.set(scope, k_v8.into(), v_v8.into());
+        }
+        q_obj.into()
+    };
     let q_key = v8_str(scope, "query");
-    req_obj.set(scope, q_key.into(), q_obj.into());
+    req_obj.set(scope, q_key.into(), q_val);
+
+    let jar = headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("cookie"))
+        .map(|(_, v)| crate::cookies::parse(v))
+        .unwrap_or_default();
+    for (key, cookies) in [("cookies", &jar.plain), ("signedCookies", &jar.signed)] {
+        let c_obj = v8::Object::new(scope);
+        for (name, value) in cookies {
+            let name_v8 = v8_str(scope, name);
+            let value_v8 = v8_str(scope, value);
+            c_obj.set(scope, name_v8.into(), value_v8.into());
+        }
+        let c_key = v8_str(scope, key);
+        req_obj.set(scope, c_key.into(), c_obj.into());
+    }
+
+    if let Some(socket_id) = runtime.active_requests.get(&request_id).and_then(|r| r.socket_id) {
+        let s_key = v8_str(scope, "__titan_socket_id");
+        let s_val = v8::Integer::new_from_unsigned(scope, socket_id);
+        req_obj.set(scope, s_key.into(), s_val.into());
+    }
+
+    if let Some(ip) = runtime.active_requests.get(&request_id).and_then(|r| r.ip) {
+        let ip_key = v8_str(scope, "ip");
+        let ip_val = v8_str(scope, &ip.to_string());
+        req_obj.set(scope, ip_key.into(), ip_val.into());
+    }
+
+    if let Some(correlation_id) = &correlation_id {
+        let c_key = v8_str(scope, "__titan_correlation_id");
+        let c_val = v8_str(scope, correlation_id);
+        req_obj.set(scope, c_key.into(), c_val.into());
+    }
+
+    if let Some(trace) = runtime.active_requests.get(&request_id).and_then(|r| r.trace.clone()) {
+        let trace_obj = v8::Object::new(scope);
+        let tid_key = v8_str(scope, "traceId");
+        let tid_val = v8_str(scope, &trace.trace_id);
+        trace_obj.set(scope, tid_key.into(), tid_val.into());
+        let sid_key = v8_str(scope, "spanId");
+        let sid_val = v8_str(scope, &trace.span_id);
+        trace_obj.set(scope, sid_key.into(), sid_val.into());
+        let sampled_key = v8_str(scope, "sampled");
+        let sampled_val = v8::Boolean::new(scope, trace.sampled);
+        trace_obj.set(scope, sampled_key.into(), sampled_val.into());
+        let t_key = v8_str(scope, "__titan_trace");
+        req_obj.set(scope, t_key.into(), trace_obj.into());
+    }
+
+    if let Some(form) = runtime.active_requests.get(&request_id).and_then(|r| r.form.clone()) {
+        let form_obj = v8::Object::new(scope);
+        let fields = v8::Array::new(scope, form.fields.len() as i32);
+        for (i, (name, value)) in form.fields.iter().enumerate() {
+            let pair = v8::Array::new(scope, 2);
+            let name_v8 = v8_str(scope, name);
+            let value_v8 = v8_str(scope, value);
+            pair.set_index(scope, 0, name_v8.into());
+            pair.set_index(scope, 1, value_v8.into());
+            fields.set_index(scope, i as u32, pair.into());
+        }
+        let fields_key = v8_str(scope, "fields");
+        form_obj.set(scope, fields_key.into(), fields.into());
+
+        let files = v8::Array::new(scope, form.files.len() as i32);
+        for (i, file) in form.files.iter().enumerate() {
+            let file_obj = v8::Object::new(scope);
+            for (key, value) in [
+                ("field", file.field.as_str()),
+                ("filename", file.filename.as_str()),
+                ("contentType", file.content_type.as_str()),
+                ("path", &file.path.to_string_lossy()),
+            ] {
+                let k_v8 = v8_str(scope, key);
+                let v_v8 = v8_str(scope, value);
+                file_obj.set(scope, k_v8.into(), v_v8.into());
+            }
+            let size_key = v8_str(scope, "size");
+            let size_val = v8::Number::new(scope, file.size as f64);
+            file_obj.set(scope, size_key.into(), size_val.into());
+            files.set_index(scope, i as u32, file_obj.into());
+        }
+        let files_key = v8_str(scope, "files");
+        form_obj.set(scope, files_key.into(), files.into());
+
+        let f_key = v8_str(scope, "__titan_form");
+        req_obj.set(scope, f_key.into(), form_obj.into());
+    }
+
+    if let Some(claims) = runtime.active_requests.get(&request_id).and_then(|r| r.auth.clone()) {
+        let auth_obj = v8::Object::new(scope);
+        let claims_json = v8_str(scope, &claims.to_string());
+        let claims_val = v8::json::parse(scope, claims_json).unwrap_or_else(|| v8::null(scope).into());
+        let claims_key = v8_str(scope, "claims");
+        auth_obj.set(scope, claims_key.into(), claims_val);
+        let a_key = v8_str(scope, "auth");
+        req_obj.set(scope, a_key.into(), auth_obj.into());
+    }
+
+    if let Some(session) = runtime.active_requests.get(&request_id).and_then(|r| r.session.clone()) {
+        let session_json = v8_str(scope, &session.to_string());
+        let session_val = v8::json::parse(scope, session_json).unwrap_or_else(|| v8::Object::new(scope).into());
+        let s_key = v8_str(scope, "__titan_session");
+        req_obj.set(scope, s_key.into(), session_val);
+    }
+
+    if let Some(geo) = runtime.active_requests.get(&request_id).and_then(|r| r.geo.clone()) {
+        let geo_json = v8_str(scope, &geo.to_string());
+        let geo_val = v8::json::parse(scope, geo_json).unwrap_or_else(|| v8::null(scope).into());
+        let g_key = v8_str(scope, "geo");
+        req_obj.set(scope, g_key.into(), geo_val);
+    }
+
+    if let Some(error) = runtime.active_requests.get(&request_id).and_then(|r| r.error.clone()) {
+        let error_json = v8_str(scope, &error.to_string());
+        let error_val = v8::json::parse(scope, error_json).unwrap_or_else(|| v8::null(scope).into());
+        let e_key = v8_str(scope, "error");
+        req_obj.set(scope, e_key.into(), error_val);
+    }
+
+    if let Some(names) = runtime.active_requests.get(&request_id).and_then(|r| r.middleware.clone()) {
+        let names: Vec<v8::Local<v8::Value>> = names.iter().map(|name| v8_str(scope, name).into()).collect();
+        let m_val = v8::Array::new_with_elements(scope, &names);
+        let m_key = v8_str(scope, "__titan_group_middleware");
+        req_obj.set(scope, m_key.into(), m_val.into());
+    }
+
+    if let Some(deadline) = runtime.active_requests.get(&request_id).and_then(|r| r.deadline) {
+        let at = deadline.duration_since(std::time::UNIX_EPOCH).map_or(0.0, |d| d.as_millis() as f64);
+        let d_key = v8_str(scope, "__titan_deadline");
+        let d_val = v8::Number::new(scope, at);
+        req_obj.set(scope, d_key.into(), d_val.into());
+    }
+
+    if let Some(stream_id) = runtime.active_requests.get(&request_id).and_then(|r| r.body_stream.as_ref().map(|s| s.id)) {
+        let bs_key = v8_str(scope, "__titan_body_stream");
+        let bs_val = v8::Number::new(scope, stream_id as f64);
+        req_obj.set(scope, bs_key.into(), bs_val.into());
+    }
 
     let global = context.global(scope);
     let req_tr_key = v8_str(scope, "__titan_req");
@@ -407,29 +1011,405 @@ pub fn execute_action_optimized(
             return;
         }
         
-        let msg = try_catch
-            .message()
-            .map(|m| m.get(try_catch).to_rust_string_lossy(try_catch))
-            .unwrap_or("Unknown error".to_string());
+        let limit = if try_catch.has_terminated() {
+            runtime.limit_exceeded.lock().unwrap().take()
+        } else {
+            None
+        };
+        let error = if let Some(limit) = limit {
+            TitanError::Limit(limit)
+        } else if try_catch.has_terminated() {
+            TitanError::Terminated
+        } else {
+            TitanError::JsException {
+                message: try_catch
+                    .message()
+                    .map(|m| m.get(try_catch).to_rust_string_lossy(try_catch))
+                    .unwrap_or("Unknown error".to_string()),
+                stack: try_catch.stack_trace().map(|s| sourcemap::rewrite(&s.to_rust_string_lossy(try_catch))),
+            }
+        };
         
-        if msg.contains("SUSPEND") {
+        if error.to_string().contains("SUSPEND") {
             return;
         }
 
-        println!("[Isolate {}] Action Error: {}", runtime.id, msg);
+        tracing::error!(worker = runtime.id, request_id = correlation_id.as_deref(), "Action Error: {}", error);
+        runtime.streams.remove(&request_id);
         if let Some(tx) = runtime.pending_requests.remove(&request_id) {
-             let _ = tx.send(crate::runtime::WorkerResult { 
-                 json: serde_json::json!({"error": msg}),
-                 timings: vec![]
-             });
+             let _ = tx.send(Err(error));
         }
     } else {
         if let Some(tx) = runtime.pending_requests.remove(&request_id) {
-             let _ = tx.send(crate::runtime::WorkerResult { 
-                 json: serde_json::json!({"error": format!("Action '{}' not found", action_name)}),
-                 timings: vec![]
-             });
+             let _ = tx.send(Err(TitanError::ActionNotFound(action_name.to_string())));
+        }
+    }
+}
+
+/// Delivers a WebSocket frame (or the close event when `message` is None) to
+/// the handlers the action registered through `req.websocket`.
+pub fn dispatch_socket_event(
+    runtime: &mut TitanRuntime,
+    socket_id: u32,
+    message: Option<crate::websocket::WsMessage>,
+) {
+    use crate::websocket::WsMessage;
+
+    let context_global = runtime.context.clone();
+    let handle_scope = &mut v8::HandleScope::new(&mut runtime.isolate);
+    let context = v8::Local::new(handle_scope, context_global);
+    let scope = &mut v8::ContextScope::new(handle_scope, context);
+    let global = context.global(scope);
+
+    let dispatch_key = v8_str(scope, "__titan_ws_dispatch");
+    let Some(dispatch) = global
+        .get(scope, dispatch_key.into())
+        .and_then(|v| v8::Local::<v8::Function>::try_from(v).ok())
+    else {
+        return;
+    };
+
+    let (event, data): (&str, v8::Local<v8::Value>) = match message {
+        Some(WsMessage::Text(text)) => ("message", v8_str(scope, &text).into()),
+        Some(WsMessage::Binary(bytes)) => {
+            let store = v8::ArrayBuffer::new_backing_store_from_boxed_slice(bytes.to_vec().into_boxed_slice());
+            ("message", v8::ArrayBuffer::with_backing_store(scope, &store.make_shared()).into())
+        }
+        Some(_) => return,
+        None => ("close", v8::undefined(scope).into()),
+    };
+
+    let id_val = v8::Integer::new_from_unsigned(scope, socket_id);
+    let event_val = v8_str(scope, event);
+    let try_catch = &mut v8::TryCatch::new(scope);
+    if dispatch.call(try_catch, global.into(), &[id_val.into(), event_val.into(), data]).is_none() {
+        let msg = try_catch
+            .message()
+            .map(|m| m.get(try_catch).to_rust_string_lossy(try_catch))
+            .unwrap_or("Unknown error".to_string());
+        tracing::error!(worker = runtime.id, "WebSocket Handler Error: {}", msg);
+    }
+}
+
+// ----------------------------------------------------------------------------
+// EVENT LOOP
+// ----------------------------------------------------------------------------
+
+/// Whether callbacks for `request_id` should still run. Request 0 is code
+/// outside any action (module scope), which never finishes.
+fn request_is_live(runtime: &TitanRuntime, request_id: u32) -> bool {
+    request_id == 0 || runtime.pending_requests.contains_key(&request_id) || runtime.streams.contains_key(&request_id)
+}
+
+/// When the earliest timer is due, if any is armed.
+pub fn next_timer(runtime: &TitanRuntime) -> Option<Instant> {
+    runtime.timers.keys().next().map(|(at, _)| *at)
+}
+
+/// Takes the earliest timer if it is due, returning its id and request.
+pub fn pop_due_timer(runtime: &mut TitanRuntime, now: Instant) -> Option<(u32, u32)> {
+    let entry = runtime.timers.first_entry().filter(|e| e.key().0 <= now)?;
+    let timer_id = entry.key().1;
+    Some((timer_id, entry.remove()))
+}
+
+/// The `X-Request-Id` of `request_id`, for log lines about it; none once
+/// the request is gone.
+pub fn correlation_id(runtime: &TitanRuntime, request_id: u32) -> Option<String> {
+    runtime.active_requests.get(&request_id).map(|r| r.correlation_id.clone())
+}
+
+/// Runs the JS callback of a due timer. Timers of requests that already
+/// responded are dropped.
+pub fn fire_timer(runtime: &mut TitanRuntime, timer_id: u32, request_id: u32) {
+    if !request_is_live(runtime, request_id) {
+        return;
+    }
+
+    let correlation_id = correlation_id(runtime, request_id);
+    let context_global = runtime.context.clone();
+    let terminated = {
+        let handle_scope = &mut v8::HandleScope::new(&mut runtime.isolate);
+        let context = v8::Local::new(handle_scope, context_global);
+        let scope = &mut v8::ContextScope::new(handle_scope, context);
+        let global = context.global(scope);
+
+        let fire_key = v8_str(scope, "__titan_fire_timer");
+        let Some(fire) = global
+            .get(scope, fire_key.into())
+            .and_then(|v| v8::Local::<v8::Function>::try_from(v).ok())
+        else {
+            return;
+        };
+
+        let id_val = v8::Integer::new_from_unsigned(scope, timer_id);
+        let try_catch = &mut v8::TryCatch::new(scope);
+        if fire.call(try_catch, global.into(), &[id_val.into()]).is_none() && !try_catch.has_terminated() {
+            let msg = try_catch
+                .message()
+                .map(|m| m.get(try_catch).to_rust_string_lossy(try_catch))
+                .unwrap_or("Unknown error".to_string());
+            tracing::error!(worker = runtime.id, request_id = correlation_id.as_deref(), "Timer Error: {}", msg);
+        }
+        try_catch.has_terminated()
+    };
+    if terminated {
+        fail_terminated(runtime, request_id);
+    }
+}
+
+/// Fires the `ctx.signal` of a request the HTTP layer gave up on, `reason`
+/// being `disconnect` or `timeout`. Requests that already responded are left.
+/// With `cancel` the request is dropped afterwards, so drift() results that
+/// come back for it no longer replay the action.
+pub fn abort_request(runtime: &mut TitanRuntime, request_id: u32, reason: &str, cancel: bool) {
+    if !runtime.pending_requests.contains_key(&request_id) {
+        return;
+    }
+
+    let correlation_id = correlation_id(runtime, request_id);
+    let context_global = runtime.context.clone();
+    let terminated = {
+        let handle_scope = &mut v8::HandleScope::new(&mut runtime.isolate);
+        let context = v8::Local::new(handle_scope, context_global);
+        let scope = &mut v8::ContextScope::new(handle_scope, context);
+        let global = context.global(scope);
+
+        let abort_key = v8_str(scope, "__titan_abort");
+        let Some(abort) = global
+            .get(scope, abort_key.into())
+            .and_then(|v| v8::Local::<v8::Function>::try_from(v).ok())
+        else {
+            return;
+        };
+
+        let id_val = v8::Integer::new_from_unsigned(scope, request_id);
+        let reason_val = v8_str(scope, reason);
+        let cancel_val = v8::Boolean::new(scope, cancel);
+        let try_catch = &mut v8::TryCatch::new(scope);
+        if abort.call(try_catch, global.into(), &[id_val.into(), reason_val.into(), cancel_val.into()]).is_none() && !try_catch.has_terminated() {
+            let msg = try_catch
+                .message()
+                .map(|m| m.get(try_catch).to_rust_string_lossy(try_catch))
+                .unwrap_or("Unknown error".to_string());
+            tracing::error!(worker = runtime.id, request_id = correlation_id.as_deref(), "Abort Handler Error: {}", msg);
+        }
+        try_catch.has_terminated()
+    };
+    if terminated {
+        fail_terminated(runtime, request_id);
+    } else if cancel {
+        runtime.streams.remove(&request_id);
+        runtime.pending_requests.remove(&request_id);
+    }
+}
+
+/// Subscriptions on `topic`, with the request each one runs for and whether
+/// its owner is still around.
+pub fn bus_subscribers(runtime: &TitanRuntime, topic: &str) -> Vec<(u32, u32, bool)> {
+    let mut subscribers: Vec<(u32, u32, bool)> = runtime
+        .bus_subscriptions
+        .iter()
+        .filter(|(_, sub)| sub.topic == topic)
+        .map(|(id, sub)| {
+            let live = match sub.socket_id {
+                Some(socket_id) => runtime.sockets.contains_key(&socket_id),
+                None => request_is_live(runtime, sub.request_id),
+            };
+            (*id, sub.request_id, live)
+        })
+        .collect();
+    subscribers.sort_unstable();
+    subscribers
+}
+
+/// What each action carries under `property`, as JSON: `__titanSchema` is
+/// its `export const schema`, `__titanConfig` its `export const config`.
+/// Actions without one are left out.
+pub fn action_exports(runtime: &mut TitanRuntime, property: &str) -> HashMap<String, serde_json::Value> {
+    let context_global = runtime.context.clone();
+    let handle_scope = &mut v8::HandleScope::new(&mut runtime.isolate);
+    let context = v8::Local::new(handle_scope, context_global);
+    let scope = &mut v8::ContextScope::new(handle_scope, context);
+    let key = v8_str(scope, property);
+
+    let mut exports = HashMap::new();
+    for (name, action) in &runtime.actions {
+        let action = v8::Local::new(scope, action);
+        let Some(value) = action.get(scope, key.into()).filter(|v| v.is_object()) else {
+            continue;
+        };
+        match try_v8_to_json(scope, value) {
+            Ok(value) => {
+                exports.insert(name.clone(), value);
+            }
+            Err(e) => {
+                let export = property.trim_start_matches("__titan").to_ascii_lowercase();
+                tracing::warn!(worker = runtime.id, "{} of action {} is not JSON: {}", export, name, e)
+            }
+        }
+    }
+    exports
+}
+
+/// Workers started from now on load the actions from disk, not the snapshot.
+pub fn actions_reloaded() {
+    RELOADED.store(true, Ordering::SeqCst);
+}
+
+/// Evaluates the changed action files into this worker's context and points
+/// its action table at what they registered. An action whose file fails to
+/// load keeps its previous version.
+pub fn reload_actions(runtime: &mut TitanRuntime, changes: &crate::watch::Changes) -> Result<(), String> {
+    let context_global = runtime.context.clone();
+    let handle_scope = &mut v8::HandleScope::new(&mut runtime.isolate);
+    let context = v8::Local::new(handle_scope, context_global);
+    let scope = &mut v8::ContextScope::new(handle_scope, context);
+    let global = context.global(scope);
+
+    let mut errors = Vec::new();
+    for (name, path) in &changes.changed {
+        if let Err(e) = load_action_file(scope, &changes.root, name, path) {
+            errors.push(e);
+            continue;
+        }
+        let key = v8_str(scope, name);
+        if let Some(val) = global.get(scope, key.into())
+            && let Ok(func) = v8::Local::<v8::Function>::try_from(val)
+        {
+            runtime.actions.insert(name.clone(), v8::Global::new(scope, func));
+        }
+    }
+    for name in &changes.removed {
+        let key = v8_str(scope, name);
+        global.delete(scope, key.into());
+        runtime.actions.remove(name);
+    }
+    modules::reset();
+    if errors.is_empty() { Ok(()) } else { Err(errors.join("; ")) }
+}
+
+/// Runs one subscription's handler for a published message. Without a
+/// message the subscription is dropped instead, its owner being gone.
+pub fn deliver_bus_message(
+    runtime: &mut TitanRuntime,
+    subscription_id: u32,
+    request_id: u32,
+    message: Option<&crate::bus::BusMessage>,
+) {
+    if message.is_none()
+        && let Some(sub) = runtime.bus_subscriptions.remove(&subscription_id)
+    {
+        runtime.bus_topics.remove(&sub.topic);
+    }
+
+    let correlation_id = correlation_id(runtime, request_id);
+    let context_global = runtime.context.clone();
+    let terminated = {
+        let handle_scope = &mut v8::HandleScope::new(&mut runtime.isolate);
+        let context = v8::Local::new(handle_scope, context_global);
+        let scope = &mut v8::ContextScope::new(handle_scope, context);
+        let global = context.global(scope);
+
+        let dispatch_key = v8_str(scope, "__titan_bus_dispatch");
+        let Some(dispatch) = global
+            .get(scope, dispatch_key.into())
+            .and_then(|v| v8::Local::<v8::Function>::try_from(v).ok())
+        else {
+            return;
+        };
+
+        let mut call_args: Vec<v8::Local<v8::Value>> = vec![v8::Integer::new_from_unsigned(scope, subscription_id).into()];
+        if let Some(message) = message {
+            call_args.push(v8_str(scope, &message.topic).into());
+            call_args.push(v8_str(scope, &message.payload).into());
         }
+        let try_catch = &mut v8::TryCatch::new(scope);
+        if dispatch.call(try_catch, global.into(), &call_args).is_none() && !try_catch.has_terminated() {
+            let msg = try_catch
+                .message()
+                .map(|m| m.get(try_catch).to_rust_string_lossy(try_catch))
+                .unwrap_or("Unknown error".to_string());
+            tracing::error!(worker = runtime.id, request_id = correlation_id.as_deref(), "Bus Handler Error: {}", msg);
+        }
+        try_catch.has_terminated()
+    };
+    if terminated {
+        fail_terminated(runtime, request_id);
+    }
+}
+
+/// Answers a request whose callback was cut off by a resource limit; the
+/// action's own error handling never got to run.
+fn fail_terminated(runtime: &mut TitanRuntime, request_id: u32) {
+    let limit = runtime.limit_exceeded.lock().unwrap().take();
+    runtime.streams.remove(&request_id);
+    if let Some(tx) = runtime.pending_requests.remove(&request_id) {
+        let _ = tx.send(Err(limit.map_or(TitanError::Terminated, TitanError::Limit)));
+    }
+}
+
+/// Resolves the promise of a finished `t._async_start` op and runs the
+/// continuations waiting on it.
+pub fn settle_async_op(runtime: &mut TitanRuntime, op_id: u32, outcome: AsyncOutcome) {
+    let Some(op) = runtime.pending_ops.remove(&op_id) else {
+        return;
+    };
+    if !request_is_live(runtime, op.request_id) {
+        return;
+    }
+
+    let context_global = runtime.context.clone();
+    let terminated = {
+        let handle_scope = &mut v8::HandleScope::new(&mut runtime.isolate);
+        let context = v8::Local::new(handle_scope, context_global);
+        let scope = &mut v8::ContextScope::new(handle_scope, context);
+
+        let value: v8::Local<v8::Value> = match outcome {
+            AsyncOutcome::Json(result) => {
+                let json_str = serde_json::to_string(&result).unwrap_or_else(|_| "null".to_string());
+                let json_val = v8_str(scope, &json_str);
+                v8::json::parse(scope, json_val).unwrap_or_else(|| v8::null(scope).into())
+            }
+            AsyncOutcome::Response { status, headers, body } => {
+                let obj = v8::Object::new(scope);
+                let status_key = v8_str(scope, "status");
+                let status_val = v8::Integer::new_from_unsigned(scope, status as u32);
+                obj.set(scope, status_key.into(), status_val.into());
+
+                let header_list = v8::Array::new(scope, headers.len() as i32);
+                for (i, (name, value)) in headers.iter().enumerate() {
+                    let name_val = v8_str(scope, name);
+                    let value_val = v8_str(scope, value);
+                    let pair = v8::Array::new_with_elements(scope, &[name_val.into(), value_val.into()]);
+                    header_list.set_index(scope, i as u32, pair.into());
+                }
+                let headers_key = v8_str(scope, "headers");
+                obj.set(scope, headers_key.into(), header_list.into());
+
+                // Vec::from reuses the allocation when the Bytes is uniquely owned
+                let store = v8::ArrayBuffer::new_backing_store_from_boxed_slice(Vec::from(body).into_boxed_slice());
+                let buffer = v8::ArrayBuffer::with_backing_store(scope, &store.make_shared());
+                let body_key = v8_str(scope, "body");
+                obj.set(scope, body_key.into(), buffer.into());
+                obj.into()
+            }
+            AsyncOutcome::Bytes(bytes) => {
+                let store = v8::ArrayBuffer::new_backing_store_from_boxed_slice(Vec::from(bytes).into_boxed_slice());
+                v8::ArrayBuffer::with_backing_store(scope, &store.make_shared()).into()
+            }
+        };
+
+        let resolver = v8::Local::new(scope, &op.resolver);
+        let try_catch = &mut v8::TryCatch::new(scope);
+        resolver.resolve(try_catch, value);
+        // Resolving from Rust doesn't leave a JS call, so V8 won't run the
+        // continuations on its own
+        try_catch.perform_microtask_checkpoint();
+        try_catch.has_terminated()
+    };
+    if terminated {
+        fail_terminated(runtime, op.request_id);
     }
 }
 
@@ -8,47 +8,1087 @@ if (!globalThis.__TITAN_CORE_LOADED__) {
     // ensure t exists early
     if (!globalThis.t) globalThis.t = {};
 
+    // -----------------------------
+    // Console
+    // -----------------------------
+    // Goes to the server's logger, tagged with the worker, action and request
+    globalThis.console = {
+        log: (...args) => t._console("info", ...args),
+        info: (...args) => t._console("info", ...args),
+        debug: (...args) => t._console("debug", ...args),
+        trace: (...args) => t._console("trace", ...args),
+        warn: (...args) => t._console("warn", ...args),
+        error: (...args) => t._console("error", ...args),
+    };
+
+    // -----------------------------
+    // Streaming response writer
+    // -----------------------------
+    // Status, headers and cookies set through `res`, sent with whichever
+    // response the action produces
+    function createResponseHead() {
+        return { status: 200, headers: {}, cookies: [] };
+    }
+
+    function serializeHead(head) {
+        const headers = Object.entries(head.headers);
+        for (const cookie of head.cookies) headers.push(["set-cookie", cookie]);
+        // Saved by the server, which also sets the session cookie
+        if (head.session) headers.push(["x-titan-session", JSON.stringify(head.session)]);
+        return { status: head.status, headers };
+    }
+
+    function serializeCookie(name, value, options) {
+        let cookie = `${name}=${encodeURIComponent(String(value))}`;
+        if (options.maxAge !== undefined) cookie += `; Max-Age=${Math.floor(options.maxAge)}`;
+        if (options.expires !== undefined) cookie += `; Expires=${new Date(options.expires).toUTCString()}`;
+        if (options.domain) cookie += `; Domain=${options.domain}`;
+        cookie += `; Path=${options.path || "/"}`;
+        if (options.secure) cookie += "; Secure";
+        if (options.httpOnly) cookie += "; HttpOnly";
+        if (options.sameSite) cookie += `; SameSite=${options.sameSite}`;
+        return cookie;
+    }
+
+    // Short names res.format() takes in place of a media type
+    const MEDIA_TYPES = {
+        json: "application/json",
+        html: "text/html",
+        text: "text/plain",
+        csv: "text/csv",
+        xml: "application/xml",
+    };
+
+    // `render` is React's renderToReadableStream, bundled with .jsx/.tsx
+    // actions; `accept` is the request's Accept header
+    function createResponseWriter(requestId, head, render, accept) {
+        return {
+            status(code) {
+                head.status = code;
+                return this;
+            },
+            header(name, value) {
+                const key = String(name).toLowerCase();
+                // Each cookie needs a Set-Cookie line of its own
+                if (key === "set-cookie") head.cookies.push(...[].concat(value).map(String));
+                else head.headers[key] = String(value);
+                return this;
+            },
+            setCookie(name, value, options = {}) {
+                let stored = String(value);
+                if (options.signed) {
+                    stored = t._cookie_sign(String(name), stored);
+                    if (stored === null) throw new Error("res.setCookie(): signed cookies need cookies.secret in the config");
+                }
+                head.cookies.push(serializeCookie(name, stored, options));
+                return this;
+            },
+            cookie(name, value, options = {}) {
+                return this.setCookie(name, value, options);
+            },
+            // Replaces the ETag the server would derive from the body
+            etag(tag, options = {}) {
+                let value = String(tag);
+                if (!/^(W\/)?"/.test(value)) value = `"${value}"`;
+                if (options.weak && !value.startsWith("W/")) value = `W/${value}`;
+                return this.header("ETag", value);
+            },
+            lastModified(date) {
+                return this.header("Last-Modified", new Date(date).toUTCString());
+            },
+            // The response to return for a redirect: `res.redirect(url)` is a
+            // 302, `res.redirect(301, url)` picks the status
+            redirect(status, location) {
+                if (location === undefined) [status, location] = [302, status];
+                head.status = Number(status);
+                return { _isResponse: true, status: head.status, headers: { Location: String(location) }, redirect: String(location) };
+            },
+            // Runs the handler for the type Accept ranks highest and answers
+            // with that type. `default` runs when none is acceptable; without
+            // it the answer is a 406. A string a handler returns is the body
+            // as it is, unless the type is JSON
+            format(handlers) {
+                const keys = Object.keys(handlers).filter((key) => key !== "default");
+                const types = keys.map((key) => MEDIA_TYPES[key] || key);
+                const vary = head.headers["vary"];
+                if (!vary || !/\baccept\b/i.test(vary)) this.header("Vary", vary ? `${vary}, Accept` : "Accept");
+                const i = t._negotiate(accept ?? "", types);
+                if (i < 0) {
+                    if (typeof handlers.default === "function") return handlers.default();
+                    this.status(406);
+                    return { error: "Not Acceptable", accepts: types };
+                }
+                const type = types[i];
+                if (!head.headers["content-type"]) {
+                    this.header("Content-Type", type.startsWith("text/") && !/charset=/i.test(type) ? `${type}; charset=utf-8` : type);
+                }
+                const raw = (out) => typeof out === "string" && !/json/i.test(type)
+                    ? { _isResponse: true, body: out }
+                    : out;
+                const out = handlers[keys[i]]();
+                return out && typeof out.then === "function" ? out.then(raw) : raw(out);
+            },
+            // False once the client has fallen behind; the chunk is still
+            // sent. `await res.drain()` before writing more
+            write(chunk) {
+                return t._stream_write(requestId, chunk, serializeHead(head));
+            },
+            drain() {
+                return t._stream_drain(requestId);
+            },
+            end(chunk) {
+                if (chunk !== undefined) this.write(chunk);
+                t._stream_end(requestId, serializeHead(head));
+            },
+            sendBytes(data, mime) {
+                t._send_bytes(requestId, data, mime, serializeHead(head));
+            },
+            sse() {
+                this.header("Content-Type", "text/event-stream");
+                this.header("Cache-Control", "no-cache");
+                // Flush the headers now so the client connects before the first event
+                this.write(": connected\n\n");
+                return createSseEmitter(this);
+            },
+            // One JSON object per line, each written as it is produced. With a
+            // source (an iterable, async iterable or generator function) the
+            // stream is drained and ended; without one, `send()` each value
+            ndjson(source) {
+                if (!head.headers["content-type"]) this.header("Content-Type", "application/x-ndjson");
+                if (source === undefined) return createNdjsonEmitter(this);
+                const values = typeof source === "function" ? source() : source;
+                if (values && typeof values[Symbol.asyncIterator] === "function") {
+                    return (async () => {
+                        for await (const value of values) {
+                            if (!this.write(ndjsonLine(value))) await this.drain();
+                        }
+                        this.end();
+                    })();
+                }
+                // Synchronous until the client falls behind, then resumed
+                // once it catches up
+                const iterator = values[Symbol.iterator]();
+                const pump = () => {
+                    for (let step = iterator.next(); !step.done; step = iterator.next()) {
+                        if (!this.write(ndjsonLine(step.value))) return this.drain().then(pump);
+                    }
+                    this.end();
+                };
+                return pump();
+            },
+            // Streams a React tree as HTML: the shell first, then each
+            // Suspense boundary as it resolves, hydrated in place by React's
+            // inline scripts. `data` is handed to the client as window.__TITAN_DATA__
+            renderStream(element, options = {}) {
+                if (!render) throw new Error("res.renderStream(): only .jsx and .tsx actions can render React (is react-dom installed?)");
+                const { data, waitForAll, ...renderOptions } = options;
+                if (data !== undefined) {
+                    // Escaped so the JSON can't close the script it sits in
+                    const json = JSON.stringify(data).replace(/</g, "\\u003c").replace(/\u2028/g, "\\u2028").replace(/\u2029/g, "\\u2029");
+                    renderOptions.bootstrapScriptContent = `window.__TITAN_DATA__=${json};${renderOptions.bootstrapScriptContent || ""}`;
+                }
+                renderOptions.onError ??= (error) => console.error(error);
+                if (!head.headers["content-type"]) this.header("Content-Type", "text/html; charset=utf-8");
+                return render(element, renderOptions).then(async (stream) => {
+                    if (waitForAll) await stream.allReady;
+                    for await (const chunk of stream) {
+                        if (!this.write(chunk)) await this.drain();
+                    }
+                    this.end();
+                });
+            }
+        };
+    }
+
+    function ndjsonLine(value) {
+        const line = JSON.stringify(value);
+        // Like JSON.stringify in an array, undefined becomes null
+        return `${line === undefined ? "null" : line}\n`;
+    }
+
+    function createNdjsonEmitter(writer) {
+        return {
+            send(value) {
+                writer.write(ndjsonLine(value));
+                return this;
+            },
+            close() {
+                writer.end();
+            }
+        };
+    }
+
+    function sseFrame(data, options) {
+        let frame = "";
+        if (options.event) frame += `event: ${options.event}\n`;
+        if (options.id !== undefined) frame += `id: ${options.id}\n`;
+        if (options.retry !== undefined) frame += `retry: ${options.retry}\n`;
+        const text = typeof data === "string" ? data : JSON.stringify(data);
+        for (const line of text.split(/\r\n|\r|\n/)) {
+            frame += `data: ${line}\n`;
+        }
+        return frame + "\n";
+    }
+
+    function createSseEmitter(writer) {
+        return {
+            send(data, options = {}) {
+                writer.write(sseFrame(data, options));
+                return this;
+            },
+            comment(text) {
+                writer.write(`: ${text}\n\n`);
+                return this;
+            },
+            close() {
+                writer.end();
+            }
+        };
+    }
+
+    // -----------------------------
+    // WebSocket connections
+    // -----------------------------
+    const sockets = new Map();
+
+    function createSocket(socketId) {
+        const handlers = { message: [], close: [] };
+        sockets.set(socketId, handlers);
+
+        return {
+            id: socketId,
+            send(data) {
+                t._ws_send(socketId, data);
+            },
+            close() {
+                t._ws_close(socketId);
+            },
+            onMessage(fn) {
+                handlers.message.push(fn);
+            },
+            onClose(fn) {
+                handlers.close.push(fn);
+            }
+        };
+    }
+
+    // Set while a socket's handlers run, so subscriptions made there belong to it
+    let dispatchingSocket;
+
+    globalThis.__titan_ws_dispatch = (socketId, event, data) => {
+        const handlers = sockets.get(socketId);
+        if (!handlers) return;
+        if (event === "close") {
+            sockets.delete(socketId);
+            dropSubscriptions((sub) => sub.socketId === socketId);
+        }
+
+        dispatchingSocket = socketId;
+        try {
+            for (const fn of handlers[event]) {
+                fn(data);
+            }
+        } finally {
+            dispatchingSocket = undefined;
+        }
+    };
+
+    // -----------------------------
+    // Pub/sub across workers
+    // -----------------------------
+    // A subscription made by an action ends when the action returns, unless
+    // the action serves a WebSocket: then it lasts until the socket closes.
+    const subscriptions = new Map();
+    let subscriptionCounter = 0;
+
+    const unsubscribe = (id) => {
+        if (subscriptions.delete(id)) t._bus_unsubscribe(id);
+    };
+
+    function dropSubscriptions(match) {
+        for (const [id, sub] of subscriptions) {
+            if (match(sub)) unsubscribe(id);
+        }
+    }
+
+    globalThis.bus = {
+        publish(topic, message) {
+            t._bus_publish(String(topic), JSON.stringify(message === undefined ? null : message));
+        },
+        subscribe(topic, fn) {
+            if (typeof fn !== 'function') throw new TypeError("bus.subscribe() callback must be a function");
+            const req = globalThis.__titan_req;
+            const inSocket = dispatchingSocket !== undefined;
+            const sub = {
+                fn,
+                req,
+                requestId: !inSocket && req ? req.__titan_request_id : 0,
+                socketId: inSocket ? dispatchingSocket : req && req.__titan_socket_id,
+            };
+            const id = ++subscriptionCounter;
+            t._bus_subscribe(id, String(topic), sub.requestId, sub.socketId);
+            subscriptions.set(id, sub);
+            return () => unsubscribe(id);
+        }
+    };
+
+    // Called without a topic when the subscription's owner has gone away
+    globalThis.__titan_bus_dispatch = (id, topic, payload) => {
+        const sub = subscriptions.get(id);
+        if (!sub) return;
+        if (topic === undefined) {
+            subscriptions.delete(id);
+            return;
+        }
+        globalThis.__titan_req = sub.req;
+        sub.fn(JSON.parse(payload), topic);
+    };
+
+    // -----------------------------
+    // Tracing
+    // -----------------------------
+    // Trace of the request currently running in this isolate. `spanId` is the
+    // innermost open span, so nested `trace.span()` calls and outgoing fetches
+    // are parented correctly.
+    let activeTrace = null;
+
+    const randomSpanId = () => {
+        let id = "";
+        for (let i = 0; i < 16; i++) id += Math.floor(Math.random() * 16).toString(16);
+        return id;
+    };
+
+    const traceparentOf = (trace) =>
+        `00-${trace.traceId}-${trace.spanId}-${trace.sampled ? "01" : "00"}`;
+
+    globalThis.trace = {
+        span(name, fn) {
+            const parent = activeTrace;
+            if (!parent) return fn();
+
+            const span = { traceId: parent.traceId, spanId: randomSpanId(), sampled: parent.sampled };
+            const start = Date.now();
+            const finish = (err) => {
+                // A suspended span is replayed later and exported from that run
+                if (err && isSuspend(err)) return;
+                if (span.sampled) {
+                    t._trace_span(span.traceId, span.spanId, parent.spanId, String(name), start, Date.now(),
+                        err ? (err.message || String(err)) : null);
+                }
+            };
+
+            activeTrace = span;
+            try {
+                const result = fn();
+                if (result && typeof result.then === 'function') {
+                    return result.then(
+                        (value) => { finish(null); return value; },
+                        (err) => { finish(err); throw err; }
+                    );
+                }
+                finish(null);
+                return result;
+            } catch (err) {
+                finish(err);
+                throw err;
+            } finally {
+                activeTrace = parent;
+            }
+        }
+    };
+
+    function isSuspend(err) {
+        const msg = err && (err.message || String(err));
+        return msg && (msg.includes("__SUSPEND__") || msg.includes("SUSPEND"));
+    }
+
+    // -----------------------------
+    // Middleware (app/middleware.{js,ts})
+    // -----------------------------
+    // Each one runs before the action. Returning anything other than
+    // undefined (or a promise of it) responds with that value instead.
+    // Route groups add their named exports after the default chain.
+    function runMiddleware(req, res, action) {
+        const named = globalThis.__titan_named_middleware || {};
+        const grouped = (req.__titan_group_middleware || []).map((name) => {
+            if (typeof named[name] !== 'function') {
+                throw new Error(`Route group middleware "${name}" is not exported by app/middleware`);
+            }
+            return named[name];
+        });
+        const chain = (globalThis.__titan_middleware || []).concat(grouped);
+        const step = (i) => {
+            if (i >= chain.length) return action();
+            const out = chain[i](req, res);
+            if (out && typeof out.then === 'function') {
+                return out.then((value) => value === undefined ? step(i + 1) : value);
+            }
+            return out === undefined ? step(i + 1) : out;
+        };
+        return step(0);
+    }
+
+    // -----------------------------
+    // Request bodies
+    // -----------------------------
+    // The bytes are handed over untouched and parsed the first time they're
+    // read, so an action that only checks a signature or reads a binary
+    // protocol never pays for a JSON parse. Assigning req.body replaces it.
+    function attachBody(req, raw) {
+        let text, parsed;
+        let hasText = false, hasParsed = false;
+        req.rawBody = () => raw;
+        req.text = () => {
+            if (!hasText) {
+                text = raw === null ? "" : t.decodeUtf8(raw);
+                hasText = true;
+            }
+            return text;
+        };
+        req.json = () => {
+            if (!hasParsed) {
+                parsed = raw === null ? null : JSON.parse(req.text());
+                hasParsed = true;
+            }
+            return parsed;
+        };
+        if (raw === null) return;
+        let body, hasBody = false;
+        Object.defineProperty(req, "body", {
+            get() {
+                if (!hasBody) {
+                    body = t._parse_body(raw, req.headers["content-type"]);
+                    hasBody = true;
+                }
+                return body;
+            },
+            set(value) {
+                body = value;
+                hasBody = true;
+            },
+            enumerable: true,
+            configurable: true,
+        });
+    }
+
+    // -----------------------------
+    // Forms
+    // -----------------------------
+    // Multipart and URL-encoded bodies are parsed by the server. Files were
+    // streamed to disk and are deleted once the request is answered; their
+    // contents are only read on demand.
+    function attachForm(req, form) {
+        // Built in a Map so a field named __proto__ stays a plain field
+        const grouped = new Map();
+        for (const [field, value] of form.fields) {
+            const list = field.endsWith("[]");
+            const name = list ? field.slice(0, -2) : field;
+            const seen = grouped.get(name);
+            if (seen === undefined) grouped.set(name, list ? [value] : value);
+            else if (Array.isArray(seen)) seen.push(value);
+            else grouped.set(name, [seen, value]);
+        }
+        const fields = Object.fromEntries(grouped);
+        const requestId = req.__titan_request_id;
+        const files = form.files.map((file, index) => ({
+            ...file,
+            bytes: () => t._read_upload(requestId, index),
+            text: () => t.decodeUtf8(new Uint8Array(t._read_upload(requestId, index))),
+        }));
+        req.fields = fields;
+        req.form = () => fields;
+        req.files = (field) => field === undefined ? files : files.filter((f) => f.field === field);
+    }
+
+    // Changes are recorded on the response head and committed in Rust once the
+    // action is done; until then the data is a plain copy.
+    function createSession(data, head) {
+        const change = () => (head.session ||= { data, regenerate: false, destroy: false });
+        return {
+            get: (key) => data[key],
+            set(key, value) {
+                data[key] = value;
+                change().data = data;
+                return this;
+            },
+            delete(key) {
+                delete data[key];
+                change().data = data;
+                return this;
+            },
+            all: () => ({ ...data }),
+            // A new id for the same data, e.g. right after logging in
+            regenerate() {
+                change().regenerate = true;
+                head.session.data = data;
+            },
+            destroy() {
+                data = {};
+                change().destroy = true;
+                head.session.data = null;
+            },
+        };
+    }
+
+    // -----------------------------
+    // Background tasks (app/tasks)
+    // -----------------------------
+    globalThis.tasks = {
+        enqueue(name, payload, options = {}) {
+            const delay = Math.max(0, Number(options.delay) || 0);
+            const retries = options.retries === undefined ? 3 : Math.max(0, Number(options.retries) || 0);
+            return t._task_enqueue(String(name), JSON.stringify(payload === undefined ? null : payload), delay, retries);
+        }
+    };
+
+    // -----------------------------
+    // App config (titan.config.toml)
+    // -----------------------------
+    globalThis.config = Object.freeze({
+        get: (key) => t._config_get(String(key))
+    });
+
+    // -----------------------------
+    // File system (fs.allow directories)
+    // -----------------------------
+    // Runs on the blocking pool; the action keeps its request context across the await
+    const fsCall = (op, path, data) => {
+        const req = globalThis.__titan_req;
+        return t._async_start({ type: "fs", data: { op, path: String(path), ...data } }).then((result) => {
+            globalThis.__titan_req = req;
+            if (result && result.error) throw new Error(`fs.${op}(): ${result.error}`);
+            return result;
+        });
+    };
+
+    globalThis.fs = {
+        readFile(path, options) {
+            const encoding = typeof options === "string" ? options : options && options.encoding;
+            return fsCall("readFile", path, { text: /^utf-?8$/i.test(encoding || "") });
+        },
+        writeFile(path, data, options) {
+            if (typeof data !== "string" && !(data instanceof ArrayBuffer) && !ArrayBuffer.isView(data)) {
+                return Promise.reject(new TypeError("fs.writeFile(): data must be a string, ArrayBuffer or typed array"));
+            }
+            return fsCall("writeFile", path, { data, append: !!(options && options.append) }).then(() => undefined);
+        },
+        readDir: (path) => fsCall("readDir", path),
+        stat: (path) => fsCall("stat", path)
+    };
+
+    // -----------------------------
+    // WebAssembly
+    // -----------------------------
+    // Compiled once per worker for each file; instances are cheap
+    const wasmModules = new Map();
+
+    const wasmModule = (source) => {
+        if (source instanceof WebAssembly.Module) return source;
+        if (typeof source === "string") {
+            let module = wasmModules.get(source);
+            if (!module) {
+                module = new WebAssembly.Module(t._read_bytes(source));
+                wasmModules.set(source, module);
+            }
+            return module;
+        }
+        if (source instanceof ArrayBuffer || ArrayBuffer.isView(source)) return new WebAssembly.Module(source);
+        throw new TypeError("wasm: expected a path, bytes or a WebAssembly.Module");
+    };
+
+    globalThis.wasm = {
+        compile: wasmModule,
+        instantiate(source, imports = {}) {
+            const instance = new WebAssembly.Instance(wasmModule(source), imports);
+            const memory = instance.exports.memory;
+            return {
+                instance,
+                exports: instance.exports,
+                memory,
+                // Views share the instance's memory; take new ones after it grows
+                view(ptr = 0, length) {
+                    if (!(memory instanceof WebAssembly.Memory)) throw new Error("wasm: the module exports no memory");
+                    return new Uint8Array(memory.buffer, ptr, length === undefined ? memory.buffer.byteLength - ptr : length);
+                }
+            };
+        }
+    };
+
+    // -----------------------------
+    // WebCrypto subset
+    // -----------------------------
+    // Raw key bytes stay out of reach of enumeration and JSON.stringify
+    const keyBytes = new WeakMap();
+    const hashName = (hash) => String(typeof hash === "string" ? hash : hash?.name).toUpperCase();
+    const algorithmName = (algorithm) => String(typeof algorithm === "string" ? algorithm : algorithm?.name).toUpperCase();
+    const cryptoAsync = (fn) => new Promise((resolve) => resolve(fn()));
+
+    class CryptoKey {
+        constructor(algorithm, extractable, usages, bytes) {
+            this.type = "secret";
+            this.algorithm = algorithm;
+            this.extractable = !!extractable;
+            this.usages = [...usages];
+            keyBytes.set(this, bytes);
+        }
+    }
+
+    const keyFor = (key, algorithm, usage) => {
+        if (!(key instanceof CryptoKey) || key.algorithm.name !== algorithm) {
+            throw new TypeError(`crypto.subtle: expected a ${algorithm} key`);
+        }
+        if (!key.usages.includes(usage)) throw new TypeError(`crypto.subtle: key does not allow '${usage}'`);
+        return keyBytes.get(key);
+    };
+
+    const keyAlgorithm = (algorithm, bytes) => {
+        const name = algorithmName(algorithm);
+        if (name === "HMAC") return { name, hash: { name: hashName(algorithm.hash) }, length: bytes.byteLength * 8 };
+        if (name === "AES-GCM") return { name, length: bytes.byteLength * 8 };
+        throw new TypeError(`crypto.subtle: unsupported key algorithm ${name}`);
+    };
+
+    const randomBytes = (length) => new Uint8Array(t._crypto_random(length));
+
+    const gcm = (encrypt, algorithm, key, data) => {
+        if ((algorithm.tagLength ?? 128) !== 128) throw new TypeError("crypto.subtle: AES-GCM supports 128-bit tags only");
+        const bytes = keyFor(key, "AES-GCM", encrypt ? "encrypt" : "decrypt");
+        return t._crypto_aes_gcm(encrypt, bytes, algorithm.iv, algorithm.additionalData, data);
+    };
+
+    globalThis.CryptoKey = CryptoKey;
+    globalThis.crypto = {
+        getRandomValues(view) {
+            if (!ArrayBuffer.isView(view) || view instanceof Float32Array || view instanceof Float64Array || view instanceof DataView) {
+                throw new TypeError("crypto.getRandomValues(): expected an integer typed array");
+            }
+            if (view.byteLength > 65536) throw new RangeError("crypto.getRandomValues(): at most 65536 bytes at a time");
+            new Uint8Array(view.buffer, view.byteOffset, view.byteLength).set(randomBytes(view.byteLength));
+            return view;
+        },
+        randomUUID() {
+            const b = randomBytes(16);
+            b[6] = (b[6] & 0x0f) | 0x40;
+            b[8] = (b[8] & 0x3f) | 0x80;
+            const hex = Array.from(b, (x) => x.toString(16).padStart(2, "0")).join("");
+            return `${hex.slice(0, 8)}-${hex.slice(8, 12)}-${hex.slice(12, 16)}-${hex.slice(16, 20)}-${hex.slice(20)}`;
+        },
+        subtle: {
+            digest: (algorithm, data) => cryptoAsync(() => t._crypto_digest(algorithmName(algorithm), data)),
+            importKey: (format, keyData, algorithm, extractable, usages) => cryptoAsync(() => {
+                if (format !== "raw") throw new TypeError("crypto.subtle.importKey(): only 'raw' keys are supported");
+                const bytes = typeof keyData === "string"
+                    ? Uint8Array.from(unescape(encodeURIComponent(keyData)), (c) => c.charCodeAt(0))
+                    : new Uint8Array(ArrayBuffer.isView(keyData) ? keyData.buffer.slice(keyData.byteOffset, keyData.byteOffset + keyData.byteLength) : keyData.slice(0));
+                return new CryptoKey(keyAlgorithm(algorithm, bytes), extractable, usages, bytes);
+            }),
+            exportKey: (format, key) => cryptoAsync(() => {
+                if (format !== "raw") throw new TypeError("crypto.subtle.exportKey(): only 'raw' keys are supported");
+                if (!(key instanceof CryptoKey) || !key.extractable) throw new TypeError("crypto.subtle.exportKey(): key is not extractable");
+                return keyBytes.get(key).slice().buffer;
+            }),
+            generateKey: (algorithm, extractable, usages) => cryptoAsync(() => {
+                const name = algorithmName(algorithm);
+                // HMAC keys default to the hash's block size
+                const bits = algorithm.length ?? (name === "HMAC" && /384|512/.test(hashName(algorithm.hash)) ? 1024 : 512);
+                if (name === "AES-GCM" && bits !== 128 && bits !== 256) throw new TypeError("crypto.subtle: AES-GCM keys are 128 or 256 bits");
+                const bytes = randomBytes(Math.ceil(bits / 8));
+                return new CryptoKey(keyAlgorithm(algorithm, bytes), extractable, usages, bytes);
+            }),
+            sign: (algorithm, key, data) => cryptoAsync(() => {
+                const bytes = keyFor(key, algorithmName(algorithm), "sign");
+                return t._crypto_hmac(key.algorithm.hash.name, bytes, data);
+            }),
+            verify: (algorithm, key, signature, data) => cryptoAsync(() => {
+                const bytes = keyFor(key, algorithmName(algorithm), "verify");
+                return t._crypto_hmac_verify(key.algorithm.hash.name, bytes, data, signature);
+            }),
+            encrypt: (algorithm, key, data) => cryptoAsync(() => gcm(true, algorithm, key, data)),
+            decrypt: (algorithm, key, data) => cryptoAsync(() => gcm(false, algorithm, key, data))
+        }
+    };
+
+    // -----------------------------
+    // Shared kv store (all workers)
+    // -----------------------------
+    const kvJson = (value) => JSON.stringify(value === undefined ? null : value);
+    const kvTtl = (options) => (options && options.ttl > 0 ? Number(options.ttl) : 0);
+
+    globalThis.kv = {
+        get(key) {
+            return JSON.parse(t._kv_get(String(key)));
+        },
+        set(key, value, options) {
+            t._kv_set(String(key), kvJson(value), kvTtl(options));
+        },
+        incr(key, by = 1) {
+            return JSON.parse(t._kv_incr(String(key), Number(by)));
+        },
+        expire(key, ttl) {
+            return JSON.parse(t._kv_expire(String(key), ttl > 0 ? Number(ttl) : 0));
+        },
+        cas(key, expected, value, options) {
+            const current = expected === undefined ? undefined : kvJson(expected);
+            return JSON.parse(t._kv_cas(String(key), current, kvJson(value), kvTtl(options)));
+        },
+        delete(key) {
+            return JSON.parse(t._kv_delete(String(key)));
+        }
+    };
+
+    // Drops the response cache's entries for a path or a tag (action names are tags too)
+    globalThis.cache = {
+        purge(pathOrTag) {
+            return t._cache_purge(String(pathOrTag));
+        }
+    };
+
+    // -----------------------------
+    // Webhook signatures, checked against the raw body
+    // -----------------------------
+    const webhookSecrets = { stripe: "STRIPE_WEBHOOK_SECRET", github: "GITHUB_WEBHOOK_SECRET", slack: "SLACK_SIGNING_SECRET" };
+
+    globalThis.webhooks = {
+        verify(provider, req, options = {}) {
+            const name = String(provider).toLowerCase();
+            const secret = options.secret ?? globalThis.process?.env?.[webhookSecrets[name]] ?? "";
+            const tolerance = options.tolerance === undefined ? 300 : Number(options.tolerance);
+            return t._webhook_verify(name, String(secret), JSON.stringify(req.headers || {}), req.rawBody?.() ?? null, tolerance);
+        }
+    };
+
+    // -----------------------------
+    // URLs from the route table
+    // -----------------------------
+    globalThis.url = {
+        for(action, params = {}, query = {}) {
+            return t._url_for(String(action), JSON.stringify(params ?? {}), JSON.stringify(query ?? {}));
+        }
+    };
+
+    // Shared by every worker, like kv; counters are separate from the routes' rate_limit
+    t.rateLimit = (key, options = {}) => JSON.parse(t._rate_limit(String(key), JSON.stringify(options)));
+
+    // -----------------------------
+    // Request context: deadline and abort signal
+    // -----------------------------
+    const abortError = (name, message) => Object.assign(new Error(message), { name });
+
+    if (typeof globalThis.AbortController !== 'function') {
+        const listeners = Symbol("listeners");
+
+        class AbortSignal {
+            constructor() {
+                this.aborted = false;
+                this.reason = undefined;
+                this.onabort = null;
+                this[listeners] = [];
+            }
+            addEventListener(type, listener) {
+                if (type === 'abort' && typeof listener === 'function') this[listeners].push(listener);
+            }
+            removeEventListener(type, listener) {
+                this[listeners] = this[listeners].filter((l) => l !== listener);
+            }
+            throwIfAborted() {
+                if (this.aborted) throw this.reason;
+            }
+            static abort(reason) {
+                const controller = new AbortController();
+                controller.abort(reason);
+                return controller.signal;
+            }
+        }
+
+        class AbortController {
+            constructor() {
+                this.signal = new AbortSignal();
+            }
+            abort(reason) {
+                const signal = this.signal;
+                if (signal.aborted) return;
+                signal.aborted = true;
+                signal.reason = reason === undefined ? abortError("AbortError", "This operation was aborted") : reason;
+                const event = { type: 'abort', target: signal };
+                const handlers = [signal.onabort, ...signal[listeners]].filter((h) => typeof h === 'function');
+                signal[listeners] = [];
+                // Every listener runs even when one throws; the first error is rethrown
+                let failure;
+                for (const handler of handlers) {
+                    try {
+                        handler.call(signal, event);
+                    } catch (err) {
+                        failure ??= err;
+                    }
+                }
+                if (failure !== undefined) throw failure;
+            }
+        }
+
+        globalThis.AbortSignal = AbortSignal;
+        globalThis.AbortController = AbortController;
+    }
+
+    // By worker request id. A drift() replay gets the same controller, so an
+    // abort reaches whichever run is listening
+    const contexts = new Map();
+
+    const requestContext = (req) => {
+        let entry = contexts.get(req.__titan_request_id);
+        if (!entry) {
+            entry = { controller: new AbortController() };
+            contexts.set(req.__titan_request_id, entry);
+        }
+        entry.req = req;
+        const deadline = req.__titan_deadline ?? null;
+        const ctx = {
+            requestId: req.__titan_correlation_id,
+            deadline,
+            remaining: () => deadline === null ? Infinity : Math.max(0, deadline - Date.now()),
+            signal: entry.controller.signal,
+            auth: req.auth ?? null,
+        };
+        if (activeTrace) {
+            ctx.traceId = activeTrace.traceId;
+            ctx.spanId = activeTrace.spanId;
+        }
+        return ctx;
+    };
+
+    // The server gave up on the request: its client disconnected or its deadline
+    // passed. A cancelled request won't run again, so what it holds goes too
+    globalThis.__titan_abort = (requestId, reason, cancel) => {
+        const entry = contexts.get(requestId);
+        if (!entry) return;
+        globalThis.__titan_req = entry.req;
+        if (cancel) {
+            contexts.delete(requestId);
+            dropSubscriptions((sub) => sub.requestId === requestId && sub.socketId === undefined);
+        }
+        entry.controller.abort(reason === "timeout"
+            ? abortError("TimeoutError", "The request deadline passed")
+            : abortError("AbortError", "The client disconnected"));
+    };
+
     // -----------------------------
     // defineAction identity helper
     // -----------------------------
-    globalThis.defineAction = (fn) => {
-        if (fn.__titanWrapped) return fn;
+    // A schema's parts may be JSON Schema or zod-like objects that can
+    // produce it; these are turned into JSON Schema once, when defined.
+    const toJsonSchema = (part) => {
+        if (!part || typeof part !== 'object') return part;
+        if (typeof part.toJSONSchema === 'function') return part.toJSONSchema();
+        return part;
+    };
+
+    const describeAction = (schema) => {
+        if (!schema || typeof schema !== 'object') return undefined;
+        const described = { ...schema };
+        for (const key of ['params', 'query', 'headers', 'body']) {
+            if (key in described) described[key] = toJsonSchema(described[key]);
+        }
+        // `response` is one schema for a 200, or schemas keyed by status
+        const response = described.response;
+        const byStatus = response && typeof response === 'object' && typeof response.toJSONSchema !== 'function'
+            && Object.keys(response).length > 0
+            && Object.keys(response).every((code) => /^[1-5]\d\d$|^default$/.test(code));
+        if (response !== undefined) {
+            described.response = Object.fromEntries(
+                Object.entries(byStatus ? response : { 200: response }).map(([code, part]) => [code, toJsonSchema(part)])
+            );
+        }
+        return described;
+    };
+
+    globalThis.defineAction = (fn, schema, config) => {
+        if (fn.__titanWrapped) {
+            if (schema) fn.__titanSchema = describeAction(schema);
+            if (config) fn.__titanConfig = config;
+            return fn;
+        }
 
         const wrapped = function (req) {
             const requestId = req.__titan_request_id;
 
-            const isSuspend = (err) => {
-                const msg = err && (err.message || String(err));
-                return msg && (msg.includes("__SUSPEND__") || msg.includes("SUSPEND"));
+            activeTrace = req.__titan_trace || null;
+            const ctx = req.ctx = requestContext(req);
+
+            attachBody(req, req.__titan_raw_body ?? null);
+
+            if (req.__titan_socket_id !== undefined) {
+                req.websocket = createSocket(req.__titan_socket_id);
+            }
+
+            if (req.__titan_form) {
+                attachForm(req, req.__titan_form);
+            } else {
+                req.form = () => ({});
+            }
+
+            if (req.__titan_body_stream !== undefined) {
+                req.body = createBodyStream(req);
+            }
+
+            const head = createResponseHead();
+
+            if (req.__titan_session !== undefined) {
+                req.session = createSession(req.__titan_session, head);
+            }
+
+            // A replay starts over, so earlier runs' subscriptions go
+            dropSubscriptions((sub) => sub.requestId === requestId);
+            const finished = () => {
+                contexts.delete(requestId);
+                dropSubscriptions((sub) => sub.requestId === requestId && sub.socketId === undefined);
             };
 
             try {
-                const result = fn(req);
+                const res = createResponseWriter(requestId, head, wrapped.__titan_render, req.headers?.accept);
+                // Jobs and tasks have no client, so HTTP middleware doesn't apply
+                const background = req.method === "JOB" || req.method === "TASK";
+                const result = background ? fn(req, res, ctx) : runMiddleware(req, res, () => fn(req, res, ctx));
 
                 if (result && typeof result.then === 'function') {
                     result.then(
                         (data) => {
-                            t._finish_request(requestId, data);
+                            finished();
+                            t._finish_request(requestId, data, serializeHead(head));
                         },
                         (err) => {
                             if (isSuspend(err)) return;
-                            t._finish_request(requestId, { error: err.message || String(err) });
+                            finished();
+                            t._fail_request(requestId, err?.message || String(err), err?.stack);
                         }
                     );
                 } else {
-                    t._finish_request(requestId, result);
+                    finished();
+                    t._finish_request(requestId, result, serializeHead(head));
                 }
             } catch (err) {
                 if (isSuspend(err)) return;
-                t._finish_request(requestId, { error: err.message || String(err) });
+                finished();
+                t._fail_request(requestId, err?.message || String(err), err?.stack);
             }
         };
 
         wrapped.__titanWrapped = true;
+        wrapped.__titanSchema = describeAction(schema);
+        wrapped.__titanConfig = config;
         return wrapped;
     };
 
 
+    // -----------------------------
+    // Event loop: timers and promise-based fetch
+    // -----------------------------
+    // Callbacks run with the request that scheduled them as `__titan_req`, so
+    // drift() and res.* keep working inside them. Timers of a request that
+    // already responded are dropped.
+    const timers = new Map();
+    let timerCounter = 0;
+
+    const armTimer = (fn, ms, args, repeat) => {
+        if (typeof fn !== 'function') throw new TypeError("Timer callback must be a function");
+        const id = ++timerCounter;
+        const delay = Math.max(0, Number(ms) || 0);
+        timers.set(id, { fn, args, delay, repeat, req: globalThis.__titan_req });
+        t._set_timer(id, delay);
+        return id;
+    };
+
+    globalThis.setTimeout = (fn, ms, ...args) => armTimer(fn, ms, args, false);
+    globalThis.setInterval = (fn, ms, ...args) => armTimer(fn, ms, args, true);
+    globalThis.clearTimeout = globalThis.clearInterval = (id) => {
+        if (timers.delete(id)) t._clear_timer(id);
+    };
+    globalThis.queueMicrotask = (fn) => {
+        Promise.resolve().then(fn);
+    };
+
+    globalThis.__titan_fire_timer = (id) => {
+        const timer = timers.get(id);
+        if (!timer) return;
+        globalThis.__titan_req = timer.req;
+        if (timer.repeat) {
+            t._set_timer(id, timer.delay);
+        } else {
+            timers.delete(id);
+        }
+        timer.fn(...timer.args);
+    };
+
+    // An upstream's circuit breaker is open. drift() results carry the same
+    // details as a `circuit` field next to `error`
+    class CircuitOpenError extends Error {
+        constructor(result) {
+            super(result.error);
+            this.name = "CircuitOpenError";
+            this.target = result.circuit.target;
+            this.state = result.circuit.state;
+            this.retryAfterMs = result.circuit.retry_after_ms;
+        }
+    }
+    globalThis.CircuitOpenError = CircuitOpenError;
+    const opError = (result) => result.circuit ? new CircuitOpenError(result) : new Error(result.error);
+
+    // Web-style fetch(): resolves without suspending the action, unlike drift(t.fetch())
+    globalThis.fetch = (url, options) => {
+        const req = globalThis.__titan_req;
+        return t._async_start(t.fetch(String(url), options)).then((res) => {
+            globalThis.__titan_req = req;
+            if (res && res.error) throw opError(res);
+            return createFetchResponse(res);
+        });
+    };
+
+    // Calls `fn` until it resolves, waiting on a native timer between attempts
+    // so the isolate serves other requests meanwhile. Each attempt gets the
+    // same idempotency key, for upstreams that dedupe retried POSTs
+    t.retry = async (fn, options = {}) => {
+        if (typeof fn !== "function") throw new TypeError("t.retry(): expected a function");
+        const attempts = Math.max(1, Number(options.attempts ?? 3) | 0);
+        const backoff = typeof options.backoff === "number" ? { initial_ms: options.backoff } : (options.backoff || {});
+        const initial = Math.max(0, Number(backoff.initial_ms ?? 100));
+        const max = Math.max(initial, Number(backoff.max_ms ?? 10000));
+        const factor = Math.max(1, Number(backoff.factor ?? 2));
+        // Share of each delay that is randomized: true (the default) is all of it
+        const jitter = options.jitter === false ? 0 : options.jitter === true || options.jitter === undefined ? 1 : Math.min(1, Math.max(0, Number(options.jitter)));
+        // An open circuit fails just as fast on the next attempt
+        const retryOn = options.retry_on || ((error) => !(error instanceof CircuitOpenError));
+        const idempotencyKey = options.idempotency_key ?? crypto.randomUUID();
+
+        for (let attempt = 1; ; attempt++) {
+            try {
+                return await fn({ attempt, idempotencyKey });
+            } catch (error) {
+                if (attempt >= attempts || !retryOn(error, attempt)) throw error;
+                const delay = Math.min(max, initial * factor ** (attempt - 1));
+                await new Promise((resolve) => armTimer(resolve, delay * (1 - jitter * Math.random()), [], false));
+            }
+        }
+    };
+
+    // `res.body` is an ArrayBuffer, `res.headers` a list of [name, value] pairs
+    function createFetchResponse(res) {
+        const headers = new Map();
+        for (const [name, value] of res.headers) {
+            headers.set(name, headers.has(name) ? `${headers.get(name)}, ${value}` : value);
+        }
+        const text = () => t.decodeUtf8(res.body);
+        return {
+            ok: res.status >= 200 && res.status < 300,
+            status: res.status,
+            headers: {
+                get: (name) => headers.get(String(name).toLowerCase()) ?? null,
+                has: (name) => headers.has(String(name).toLowerCase()),
+                entries: () => headers.entries(),
+            },
+            arrayBuffer: async () => res.body,
+            text: async () => text(),
+            json: async () => JSON.parse(text()),
+        };
+    }
+
     // -----------------------------
     // TextDecoder Polyfill
     // -----------------------------
@@ -58,6 +1098,164 @@ if (!globalThis.__TITAN_CORE_LOADED__) {
         }
     };
 
+    // -----------------------------
+    // TextEncoder
+    // -----------------------------
+    globalThis.TextEncoder = class TextEncoder {
+        get encoding() {
+            return "utf-8";
+        }
+
+        encode(input = "") {
+            return new Uint8Array(t._encode_utf8(String(input)));
+        }
+
+        // Whole characters only, as many as fit in `dest`
+        encodeInto(input, dest) {
+            let read = 0;
+            let written = 0;
+            for (const ch of String(input)) {
+                let cp = ch.codePointAt(0);
+                if (cp >= 0xd800 && cp <= 0xdfff) cp = 0xfffd; // Lone surrogate
+                const size = cp < 0x80 ? 1 : cp < 0x800 ? 2 : cp < 0x10000 ? 3 : 4;
+                if (written + size > dest.length) break;
+                if (size === 1) {
+                    dest[written++] = cp;
+                } else {
+                    dest[written++] = size === 2 ? 0xc0 | (cp >> 6) : size === 3 ? 0xe0 | (cp >> 12) : 0xf0 | (cp >> 18);
+                    if (size === 4) dest[written++] = 0x80 | ((cp >> 12) & 0x3f);
+                    if (size >= 3) dest[written++] = 0x80 | ((cp >> 6) & 0x3f);
+                    dest[written++] = 0x80 | (cp & 0x3f);
+                }
+                read += ch.length;
+            }
+            return { read, written };
+        }
+    };
+
+    globalThis.performance ??= {
+        timeOrigin: Date.now(),
+        now() {
+            return Date.now() - this.timeOrigin;
+        }
+    };
+
+    // -----------------------------
+    // ReadableStream (pull-based subset)
+    // -----------------------------
+    globalThis.ReadableStream ??= class ReadableStream {
+        #source;
+        #controller;
+        #queue = [];
+        #waiting = [];
+        #state = "readable";
+        #error;
+        #pulling = false;
+
+        constructor(source = {}) {
+            this.#source = source;
+            this.locked = false;
+            this.#controller = {
+                enqueue: (value) => {
+                    if (this.#state !== "readable") return;
+                    const waiter = this.#waiting.shift();
+                    if (waiter) waiter.resolve({ value, done: false });
+                    else this.#queue.push(value);
+                },
+                close: () => this.#settle("closed"),
+                error: (err) => {
+                    this.#queue = [];
+                    this.#settle("errored", err);
+                },
+            };
+            source.start?.(this.#controller);
+        }
+
+        #settle(state, err) {
+            if (this.#state !== "readable") return;
+            this.#state = state;
+            this.#error = err;
+            for (const waiter of this.#waiting.splice(0)) {
+                if (state === "errored") waiter.reject(err);
+                else waiter.resolve({ value: undefined, done: true });
+            }
+        }
+
+        #pull() {
+            if (this.#pulling || !this.#source.pull) return;
+            this.#pulling = true;
+            Promise.resolve()
+                .then(() => this.#source.pull(this.#controller))
+                .then(
+                    () => {
+                        this.#pulling = false;
+                        if (this.#waiting.length && this.#state === "readable") this.#pull();
+                    },
+                    (err) => {
+                        this.#pulling = false;
+                        this.#controller.error(err);
+                    }
+                );
+        }
+
+        #read() {
+            if (this.#queue.length) return Promise.resolve({ value: this.#queue.shift(), done: false });
+            if (this.#state === "closed") return Promise.resolve({ value: undefined, done: true });
+            if (this.#state === "errored") return Promise.reject(this.#error);
+            return new Promise((resolve, reject) => {
+                this.#waiting.push({ resolve, reject });
+                this.#pull();
+            });
+        }
+
+        getReader() {
+            if (this.locked) throw new TypeError("ReadableStream is locked");
+            this.locked = true;
+            return {
+                read: () => this.#read(),
+                releaseLock: () => {
+                    this.locked = false;
+                },
+                cancel: (reason) => this.cancel(reason),
+            };
+        }
+
+        cancel(reason) {
+            this.#queue = [];
+            this.#settle("closed");
+            return Promise.resolve(this.#source.cancel?.(reason));
+        }
+
+        async *[Symbol.asyncIterator]() {
+            const reader = this.getReader();
+            try {
+                for (;;) {
+                    const { value, done } = await reader.read();
+                    if (done) return;
+                    yield value;
+                }
+            } finally {
+                reader.releaseLock();
+            }
+        }
+    };
+
+    // A streamed request body; each read pulls the next chunk from the client
+    function createBodyStream(req) {
+        const id = req.__titan_body_stream;
+        return new ReadableStream({
+            pull(controller) {
+                globalThis.__titan_req = req;
+                return t._async_start({ type: "body_read", data: { id } }).then((chunk) => {
+                    globalThis.__titan_req = req;
+                    if (chunk === null) controller.close();
+                    else if (chunk.error) controller.error(new Error(chunk.error));
+                    else controller.enqueue(new Uint8Array(chunk));
+                });
+            },
+        });
+    }
+
     // -----------------------------
     // process.env
     // -----------------------------
@@ -126,6 +1324,11 @@ if (!globalThis.__TITAN_CORE_LOADED__) {
         }
     };
 
+    // Templates under app/views, compiled and rendered by the server
+    t.render = (name, data = {}) => t._render(String(name), JSON.stringify(data));
+    titanResponse.render = (name, data, status = 200, extraHeaders = {}) =>
+        titanResponse.html(t.render(name, data), status, extraHeaders);
+
     t.response = titanResponse;
 
     // -----------------------------
@@ -152,8 +1355,14 @@ if (!globalThis.__TITAN_CORE_LOADED__) {
     // fetch
     if (t.fetch && !t.fetch.__titanWrapped) {
         const nativeFetch = t.fetch;
-        t.fetch = function (...args) {
-            return createAsyncOp(nativeFetch(...args));
+        t.fetch = function (url, options) {
+            if (activeTrace) {
+                const headers = { ...(options && options.headers) };
+                const hasParent = Object.keys(headers).some((k) => k.toLowerCase() === "traceparent");
+                if (!hasParent) headers.traceparent = traceparentOf(activeTrace);
+                options = { ...options, headers };
+            }
+            return createAsyncOp(nativeFetch(url, options));
         };
         t.fetch.__titanWrapped = true;
     }
@@ -162,16 +1371,15 @@ if (!globalThis.__TITAN_CORE_LOADED__) {
     if (t.db && !t.db.__titanWrapped) {
         const nativeDbConnect = t.db.connect;
 
-        t.db.connect = function (connString) {
-            const conn = nativeDbConnect(connString);
+        t.db.connect = function (connString, options) {
+            const conn = nativeDbConnect(connString, options);
 
             if (!conn.query.__titanWrapped) {
-                const nativeQuery = conn.query;
-                conn.query = (sql) => {
+                conn.query = (sql, params = []) => {
                     return createAsyncOp({
                         __titanAsync: true,
                         type: "db_query",
-                        data: { conn: connString, query: sql }
+                        data: { conn: connString, query: sql, params }
                     });
                 };
                 conn.query.__titanWrapped = true;
@@ -183,4 +1391,63 @@ if (!globalThis.__TITAN_CORE_LOADED__) {
         t.db.__titanWrapped = true;
     }
 
+    // redis.connect: commands issued in the same tick go out as one pipeline
+    if (t._redis_connect && !t.redis) {
+        const redisArg = (value) => {
+            if (typeof value === "string") return value;
+            if (value !== null && typeof value === "object") return JSON.stringify(value);
+            return String(value);
+        };
+
+        t.redis = {
+            connect(url, options = {}) {
+                t._redis_connect(url, options.max);
+
+                let queue = [];
+                const flush = () => {
+                    const batch = queue;
+                    queue = [];
+                    const req = globalThis.__titan_req;
+                    t._async_start({
+                        __titanAsync: true,
+                        type: "redis",
+                        data: { url, commands: batch.map((entry) => entry.args) }
+                    }).then((replies) => {
+                        globalThis.__titan_req = req;
+                        batch.forEach((entry, i) => {
+                            const reply = Array.isArray(replies) ? replies[i] : replies;
+                            if (reply && typeof reply === "object" && !Array.isArray(reply) && reply.error) {
+                                entry.reject(opError(reply));
+                            } else {
+                                entry.resolve(reply);
+                            }
+                        });
+                    });
+                };
+                const command = (...args) => new Promise((resolve, reject) => {
+                    if (queue.length === 0) queueMicrotask(flush);
+                    queue.push({ args: args.map(redisArg), resolve, reject });
+                });
+
+                return {
+                    command,
+                    get: (key) => command("GET", key),
+                    set: (key, value, opts = {}) => {
+                        const args = ["SET", key, value];
+                        if (opts.ex !== undefined) args.push("EX", opts.ex);
+                        if (opts.px !== undefined) args.push("PX", opts.px);
+                        if (opts.nx) args.push("NX");
+                        if (opts.xx) args.push("XX");
+                        return command(...args);
+                    },
+                    del: (...keys) => command("DEL", ...keys),
+                    incr: (key, by = 1) => command("INCRBY", key, by),
+                    expire: (key, seconds) => command("EXPIRE", key, seconds),
+                    publish: (channel, message) => command("PUBLISH", channel, message),
+                    eval: (script, keys = [], args = []) => command("EVAL", script, keys.length, ...keys, ...args)
+                };
+            }
+        };
+    }
+
 }
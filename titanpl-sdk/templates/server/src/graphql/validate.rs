@@ -0,0 +1,393 @@
+use std::collections::{HashMap, HashSet};
+
+use super::GraphqlError;
+use super::parser::{Argument, Directive, Document, Operation, OperationKind, Pos, Selection, Type, TypeKind, Value};
+use super::schema::Schema;
+
+// Where a variable is used, and the type expected there
+struct Usage {
+    name: String,
+    ty: Type,
+    has_default: bool,
+    pos: Pos,
+}
+
+struct Validator<'a> {
+    schema: &'a Schema,
+    doc: &'a Document,
+    max_depth: usize,
+    introspection: bool,
+    errors: Vec<GraphqlError>,
+    used_fragments: HashSet<&'a str>,
+    too_deep: bool,
+}
+
+/// Checks a query against the schema: the rules of the spec's Validation
+/// section that catch mistakes, plus a limit on how deeply fields nest.
+/// Overlapping fields with the same response key are merged as the
+/// executor finds them rather than rejected. Without `introspection`,
+/// asking for `__schema` or `__type` is an error.
+pub fn validate(schema: &Schema, doc: &Document, max_depth: usize, introspection: bool) -> Vec<GraphqlError> {
+    let mut v = Validator { schema, doc, max_depth, introspection, errors: Vec::new(), used_fragments: HashSet::new(), too_deep: false };
+
+    let anonymous = doc.operations.iter().filter(|op| op.name.is_none()).count();
+    if anonymous > 0 && doc.operations.len() > 1 {
+        for op in doc.operations.iter().filter(|op| op.name.is_none()) {
+            v.error("This anonymous operation must be the only defined operation.", op.pos);
+        }
+    }
+    let mut names = HashSet::new();
+    for op in &doc.operations {
+        if let Some(name) = &op.name
+            && !names.insert(name.as_str())
+        {
+            v.error(format!("There can be only one operation named \"{}\".", name), op.pos);
+        }
+    }
+    let mut fragment_names = HashSet::new();
+    for fragment in &doc.fragments {
+        if !fragment_names.insert(fragment.name.as_str()) {
+            v.error(format!("There can be only one fragment named \"{}\".", fragment.name), fragment.pos);
+        }
+        match schema.get(&fragment.type_condition) {
+            None => v.error(format!("Unknown type \"{}\".", fragment.type_condition), fragment.pos),
+            Some(_) if !schema.is_composite(&fragment.type_condition) => v.error(
+                format!("Fragment \"{}\" cannot condition on non composite type \"{}\".", fragment.name, fragment.type_condition),
+                fragment.pos,
+            ),
+            Some(_) => {}
+        }
+        v.directives(&fragment.directives, "FRAGMENT_DEFINITION", &mut Vec::new());
+    }
+
+    for op in &doc.operations {
+        v.operation(op);
+    }
+    for fragment in &doc.fragments {
+        if !v.used_fragments.contains(fragment.name.as_str()) {
+            v.error(format!("Fragment \"{}\" is never used.", fragment.name), fragment.pos);
+        }
+    }
+
+    // A fragment used by several operations is checked once for each
+    let mut seen = HashSet::new();
+    v.errors.retain(|e| seen.insert((e.message.clone(), e.locations.clone())));
+    v.errors
+}
+
+impl<'a> Validator<'a> {
+    fn error(&mut self, message: impl Into<String>, pos: Pos) {
+        self.errors.push(GraphqlError::new(message, Some(pos)));
+    }
+
+    fn operation(&mut self, op: &'a Operation) {
+        let Some(root) = self.schema.root(op.kind) else {
+            self.error(format!("Schema is not configured to execute {} operation.", op.kind), op.pos);
+            return;
+        };
+        if op.kind == OperationKind::Subscription {
+            self.error("Subscriptions are not supported.", op.pos);
+            return;
+        }
+
+        let mut defined: HashMap<&str, (&Type, bool)> = HashMap::new();
+        for var in &op.variables {
+            if defined.insert(&var.name, (&var.ty, var.default.as_ref().is_some_and(|d| *d != Value::Null))).is_some() {
+                self.error(format!("There can be only one variable named \"${}\".", var.name), var.pos);
+            }
+            if self.schema.get(var.ty.name()).is_none() {
+                self.error(format!("Unknown type \"{}\".", var.ty.name()), var.pos);
+                continue;
+            }
+            if !self.schema.is_input(var.ty.name()) {
+                self.error(format!("Variable \"${}\" cannot be non-input type \"{}\".", var.name, var.ty), var.pos);
+                continue;
+            }
+            if let Some(default) = &var.default
+                && let Some(message) = self.value(default, &var.ty, false, var.pos, &mut Vec::new())
+            {
+                self.error(message, var.pos);
+            }
+        }
+
+        let mut usages = Vec::new();
+        self.directives(&op.directives, &op.kind.to_string().to_ascii_uppercase(), &mut usages);
+        self.too_deep = false;
+        self.selections(root, &op.selections, 0, &mut Vec::new(), &mut usages);
+
+        let mut used = HashSet::new();
+        for usage in &usages {
+            used.insert(usage.name.as_str());
+            let Some((var_ty, var_default)) = defined.get(usage.name.as_str()) else {
+                let message = match &op.name {
+                    Some(name) => format!("Variable \"${}\" is not defined by operation \"{}\".", usage.name, name),
+                    None => format!("Variable \"${}\" is not defined.", usage.name),
+                };
+                self.error(message, usage.pos);
+                continue;
+            };
+            if !allowed(var_ty, *var_default, &usage.ty, usage.has_default) {
+                self.error(
+                    format!("Variable \"${}\" of type \"{}\" used in position expecting type \"{}\".", usage.name, var_ty, usage.ty),
+                    usage.pos,
+                );
+            }
+        }
+        for var in &op.variables {
+            if !used.contains(var.name.as_str()) {
+                let message = match &op.name {
+                    Some(name) => format!("Variable \"${}\" is never used in operation \"{}\".", var.name, name),
+                    None => format!("Variable \"${}\" is never used.", var.name),
+                };
+                self.error(message, var.pos);
+            }
+        }
+    }
+
+    fn selections(
+        &mut self,
+        parent: &'a str,
+        selections: &'a [Selection],
+        depth: usize,
+        stack: &mut Vec<&'a str>,
+        usages: &mut Vec<Usage>,
+    ) {
+        for selection in selections {
+            match selection {
+                Selection::Field(field) => {
+                    self.directives(&field.directives, "FIELD", usages);
+                    if !self.introspection && matches!(field.name.as_str(), "__schema" | "__type") {
+                        self.error("GraphQL introspection is not allowed.", field.pos);
+                        continue;
+                    }
+                    let Some(def) = self.schema.field(parent, &field.name) else {
+                        self.error(format!("Cannot query field \"{}\" on type \"{}\".", field.name, parent), field.pos);
+                        continue;
+                    };
+                    let label = format!("field \"{}.{}\"", parent, field.name);
+                    let args: Vec<_> = def.args.iter().map(|a| (a.name.as_str(), &a.ty, a.default.is_some())).collect();
+                    self.arguments(&field.arguments, &args, &label, field.pos, usages);
+
+                    let inner = def.ty.name();
+                    if self.schema.is_composite(inner) {
+                        if field.selections.is_empty() {
+                            self.error(
+                                format!(
+                                    "Field \"{}\" of type \"{}\" must have a selection of subfields. Did you mean \"{} {{ ... }}\"?",
+                                    field.name, def.ty, field.name
+                                ),
+                                field.pos,
+                            );
+                        } else if depth + 1 >= self.max_depth {
+                            if !self.too_deep {
+                                self.too_deep = true;
+                                self.error(format!("Query is nested deeper than {} levels.", self.max_depth), field.pos);
+                            }
+                        } else {
+                            self.selections(inner, &field.selections, depth + 1, stack, usages);
+                        }
+                    } else if !field.selections.is_empty() {
+                        self.error(
+                            format!("Field \"{}\" must not have a selection since type \"{}\" has no subfields.", field.name, def.ty),
+                            field.pos,
+                        );
+                    }
+                }
+                Selection::Spread(spread) => {
+                    self.directives(&spread.directives, "FRAGMENT_SPREAD", usages);
+                    let Some(fragment) = self.doc.fragment(&spread.name) else {
+                        self.error(format!("Unknown fragment \"{}\".", spread.name), spread.pos);
+                        continue;
+                    };
+                    self.used_fragments.insert(&fragment.name);
+                    let condition = fragment.type_condition.as_str();
+                    if !self.schema.is_composite(condition) {
+                        continue;
+                    }
+                    if !self.schema.overlap(parent, condition) {
+                        self.error(
+                            format!(
+                                "Fragment \"{}\" cannot be spread here as objects of type \"{}\" can never be of type \"{}\".",
+                                spread.name, parent, condition
+                            ),
+                            spread.pos,
+                        );
+                        continue;
+                    }
+                    if stack.contains(&fragment.name.as_str()) {
+                        self.error(format!("Cannot spread fragment \"{}\" within itself.", spread.name), spread.pos);
+                        continue;
+                    }
+                    stack.push(&fragment.name);
+                    self.selections(condition, &fragment.selections, depth, stack, usages);
+                    stack.pop();
+                }
+                Selection::Inline(inline) => {
+                    self.directives(&inline.directives, "INLINE_FRAGMENT", usages);
+                    let condition = match &inline.type_condition {
+                        None => parent,
+                        Some(condition) => {
+                            if self.schema.get(condition).is_none() {
+                                self.error(format!("Unknown type \"{}\".", condition), inline.pos);
+                                continue;
+                            }
+                            if !self.schema.is_composite(condition) {
+                                self.error(format!("Fragment cannot condition on non composite type \"{}\".", condition), inline.pos);
+                                continue;
+                            }
+                            if !self.schema.overlap(parent, condition) {
+                                self.error(
+                                    format!(
+                                        "Fragment cannot be spread here as objects of type \"{}\" can never be of type \"{}\".",
+                                        parent, condition
+                                    ),
+                                    inline.pos,
+                                );
+                                continue;
+                            }
+                            condition.as_str()
+                        }
+                    };
+                    self.selections(condition, &inline.selections, depth, stack, usages);
+                }
+            }
+        }
+    }
+
+    fn directives(&mut self, directives: &'a [Directive], location: &str, usages: &mut Vec<Usage>) {
+        let if_type = Type::NonNull(Box::new(Type::Named("Boolean".into())));
+        for directive in directives {
+            match directive.name.as_str() {
+                "skip" | "include" => {
+                    if !matches!(location, "FIELD" | "FRAGMENT_SPREAD" | "INLINE_FRAGMENT") {
+                        self.error(format!("Directive \"@{}\" may not be used on {}.", directive.name, location), directive.pos);
+                        continue;
+                    }
+                    let label = format!("directive \"@{}\"", directive.name);
+                    self.arguments(&directive.arguments, &[("if", &if_type, false)], &label, directive.pos, usages);
+                }
+                "deprecated" | "specifiedBy" | "oneOf" => {
+                    self.error(format!("Directive \"@{}\" may not be used on {}.", directive.name, location), directive.pos);
+                }
+                _ => self.error(format!("Unknown directive \"@{}\".", directive.name), directive.pos),
+            }
+        }
+    }
+
+    // `defs` are (name, type, has a default)
+    fn arguments(&mut self, arguments: &[Argument], defs: &[(&str, &Type, bool)], label: &str, pos: Pos, usages: &mut Vec<Usage>) {
+        let mut given = HashSet::new();
+        for arg in arguments {
+            if !given.insert(arg.name.as_str()) {
+                self.error(format!("There can be only one argument named \"{}\".", arg.name), arg.pos);
+                continue;
+            }
+            let Some((_, ty, has_default)) = defs.iter().find(|(name, _, _)| *name == arg.name) else {
+                self.error(format!("Unknown argument \"{}\" on {}.", arg.name, label), arg.pos);
+                continue;
+            };
+            if let Some(message) = self.value(&arg.value, ty, *has_default, arg.pos, usages) {
+                self.error(message, arg.pos);
+            }
+        }
+        for (name, ty, has_default) in defs {
+            if matches!(ty, Type::NonNull(_)) && !has_default && !given.contains(name) {
+                let mut label = label.to_string();
+                if let Some(first) = label.get_mut(0..1) {
+                    first.make_ascii_uppercase();
+                }
+                self.error(format!("{} argument \"{}\" of type \"{}\" is required, but it was not provided.", label, name, ty), pos);
+            }
+        }
+    }
+
+    // Why `value` can't be a `ty`, if it can't. Variables are noted in
+    // `usages` and checked against their definitions afterwards.
+    fn value(&self, value: &Value, ty: &Type, has_default: bool, pos: Pos, usages: &mut Vec<Usage>) -> Option<String> {
+        if let Value::Variable(name) = value {
+            usages.push(Usage { name: name.clone(), ty: ty.clone(), has_default, pos });
+            return None;
+        }
+        match (ty, value) {
+            (Type::NonNull(_), Value::Null) => Some(format!("Expected value of type \"{}\", found null.", ty)),
+            (Type::NonNull(inner), _) => self.value(value, inner, false, pos, usages),
+            (_, Value::Null) => None,
+            (Type::List(inner), Value::List(items)) => items.iter().find_map(|item| self.value(item, inner, false, pos, usages)),
+            (Type::List(inner), _) => self.value(value, inner, false, pos, usages),
+            (Type::Named(name), _) => {
+                let ok = match self.schema.get(name).map(|d| &d.kind) {
+                    Some(TypeKind::Scalar) => match name.as_str() {
+                        "Int" => match value {
+                            Value::Int(n) if i32::try_from(*n).is_err() => {
+                                return Some(format!("Int cannot represent non 32-bit signed integer value: {}", n));
+                            }
+                            Value::Int(_) => true,
+                            _ => false,
+                        },
+                        "Float" => matches!(value, Value::Int(_) | Value::Float(_)),
+                        "String" => matches!(value, Value::String(_)),
+                        "Boolean" => matches!(value, Value::Boolean(_)),
+                        "ID" => matches!(value, Value::String(_) | Value::Int(_)),
+                        // Custom scalars take any literal
+                        _ => true,
+                    },
+                    Some(TypeKind::Enum { values }) => match value {
+                        Value::Enum(v) if values.iter().any(|e| e.name == *v) => true,
+                        Value::Enum(v) => return Some(format!("Value \"{}\" does not exist in \"{}\" enum.", v, name)),
+                        _ => return Some(format!("Enum \"{}\" cannot represent non-enum value: {}.", name, value)),
+                    },
+                    Some(TypeKind::Input { fields }) => {
+                        let Value::Object(given) = value else {
+                            return Some(format!("Expected value of type \"{}\", found {}.", name, value));
+                        };
+                        for (field, _) in given {
+                            if !fields.iter().any(|f| f.name == *field) {
+                                return Some(format!("Field \"{}\" is not defined by type \"{}\".", field, name));
+                            }
+                        }
+                        for field in fields {
+                            match given.iter().find(|(n, _)| *n == field.name) {
+                                Some((_, v)) => {
+                                    if let Some(message) = self.value(v, &field.ty, field.default.is_some(), pos, usages) {
+                                        return Some(message);
+                                    }
+                                }
+                                None if matches!(field.ty, Type::NonNull(_)) && field.default.is_none() => {
+                                    return Some(format!(
+                                        "Field \"{}.{}\" of required type \"{}\" was not provided.",
+                                        name, field.name, field.ty
+                                    ));
+                                }
+                                None => {}
+                            }
+                        }
+                        true
+                    }
+                    _ => false,
+                };
+                (!ok).then(|| format!("{} cannot represent value: {}", name, value))
+            }
+        }
+    }
+}
+
+// Whether a variable of `var` type can go where a `location` is expected
+fn allowed(var: &Type, var_default: bool, location: &Type, location_default: bool) -> bool {
+    if let (Type::NonNull(inner), false) = (location, matches!(var, Type::NonNull(_)))
+        && (var_default || location_default)
+    {
+        return subtype(var, inner);
+    }
+    subtype(var, location)
+}
+
+fn subtype(a: &Type, b: &Type) -> bool {
+    match (a, b) {
+        (Type::NonNull(a), Type::NonNull(b)) => subtype(a, b),
+        (_, Type::NonNull(_)) => false,
+        (Type::NonNull(a), b) => subtype(a, b),
+        (Type::List(a), Type::List(b)) => subtype(a, b),
+        (Type::Named(a), Type::Named(b)) => a == b,
+        _ => false,
+    }
+}
@@ -0,0 +1,965 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+// Well-known types new projects import; their JSON forms are handled by the codec
+const WELL_KNOWN: [(&str, &str); 6] = [
+    ("google/protobuf/empty.proto", "syntax = \"proto3\"; package google.protobuf; message Empty {}"),
+    (
+        "google/protobuf/timestamp.proto",
+        "syntax = \"proto3\"; package google.protobuf; message Timestamp { int64 seconds = 1; int32 nanos = 2; }",
+    ),
+    (
+        "google/protobuf/duration.proto",
+        "syntax = \"proto3\"; package google.protobuf; message Duration { int64 seconds = 1; int32 nanos = 2; }",
+    ),
+    (
+        "google/protobuf/wrappers.proto",
+        r#"syntax = "proto3"; package google.protobuf;
+        message DoubleValue { double value = 1; } message FloatValue { float value = 1; }
+        message Int64Value { int64 value = 1; } message UInt64Value { uint64 value = 1; }
+        message Int32Value { int32 value = 1; } message UInt32Value { uint32 value = 1; }
+        message BoolValue { bool value = 1; } message StringValue { string value = 1; }
+        message BytesValue { bytes value = 1; }"#,
+    ),
+    (
+        "google/protobuf/struct.proto",
+        r#"syntax = "proto3"; package google.protobuf;
+        message Struct { map<string, Value> fields = 1; }
+        message Value {
+          oneof kind {
+            NullValue null_value = 1; double number_value = 2; string string_value = 3;
+            bool bool_value = 4; Struct struct_value = 5; ListValue list_value = 6;
+          }
+        }
+        enum NullValue { NULL_VALUE = 0; }
+        message ListValue { repeated Value values = 1; }"#,
+    ),
+    (
+        "google/protobuf/field_mask.proto",
+        "syntax = \"proto3\"; package google.protobuf; message FieldMask { repeated string paths = 1; }",
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scalar {
+    Double,
+    Float,
+    Int64,
+    Uint64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Bytes,
+    Uint32,
+    Sfixed32,
+    Sfixed64,
+    Sint32,
+    Sint64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "double" => Scalar::Double,
+            "float" => Scalar::Float,
+            "int64" => Scalar::Int64,
+            "uint64" => Scalar::Uint64,
+            "int32" => Scalar::Int32,
+            "fixed64" => Scalar::Fixed64,
+            "fixed32" => Scalar::Fixed32,
+            "bool" => Scalar::Bool,
+            "string" => Scalar::String,
+            "bytes" => Scalar::Bytes,
+            "uint32" => Scalar::Uint32,
+            "sfixed32" => Scalar::Sfixed32,
+            "sfixed64" => Scalar::Sfixed64,
+            "sint32" => Scalar::Sint32,
+            "sint64" => Scalar::Sint64,
+            _ => return None,
+        })
+    }
+
+    /// Whether repeated values of it can be packed into one record.
+    pub fn packable(self) -> bool {
+        !matches!(self, Scalar::String | Scalar::Bytes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldType {
+    Scalar(Scalar),
+    Message(String),
+    Enum(String),
+}
+
+#[derive(Debug)]
+pub struct Field {
+    pub name: String,
+    /// The lowerCamelCase name JSON uses, unless `json_name` says otherwise.
+    pub json_name: String,
+    pub number: u32,
+    pub ty: FieldType,
+    pub repeated: bool,
+    pub packed: bool,
+    /// `map<K, V>`: the key type; `ty` is the value type.
+    pub map_key: Option<Scalar>,
+    /// Set only when present on the wire: proto2, `optional` and oneof members.
+    pub presence: bool,
+}
+
+#[derive(Debug)]
+pub struct Message {
+    pub fields: Vec<Field>,
+}
+
+impl Message {
+    pub fn field(&self, number: u32) -> Option<&Field> {
+        self.fields.iter().find(|f| f.number == number)
+    }
+}
+
+#[derive(Debug)]
+pub struct Enum {
+    pub name: String,
+    pub values: Vec<(String, i32)>,
+}
+
+#[derive(Debug)]
+pub struct Method {
+    pub name: String,
+    pub input: String,
+    pub output: String,
+    pub client_streaming: bool,
+    pub server_streaming: bool,
+}
+
+#[derive(Debug)]
+pub struct Service {
+    /// Full name, package included.
+    pub name: String,
+    pub methods: Vec<Method>,
+}
+
+/// The messages, enums and services of a set of .proto files, with every
+/// type reference resolved to a full name.
+#[derive(Debug, Default)]
+pub struct Pool {
+    pub messages: HashMap<String, Message>,
+    pub enums: HashMap<String, Enum>,
+    pub services: Vec<Service>,
+}
+
+impl Pool {
+    /// Reads every .proto file under `dir`. Imports are looked up under
+    /// `dir`; the well-known types under google/protobuf are built in.
+    pub fn load(dir: &Path) -> Result<Pool, String> {
+        let mut files = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending: Vec<String> = walkdir::WalkDir::new(dir)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "proto"))
+            .filter_map(|e| e.path().strip_prefix(dir).ok().map(|p| p.to_string_lossy().replace('\\', "/")))
+            .collect();
+        pending.sort();
+        if pending.is_empty() {
+            return Err(format!("no .proto files in {}", dir.display()));
+        }
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let src = match WELL_KNOWN.iter().find(|(n, _)| *n == name) {
+                Some((_, src)) => src.to_string(),
+                None => match std::fs::read_to_string(dir.join(&name)) {
+                    Ok(src) => src,
+                    Err(_) => {
+                        // Often only needed for options, like google/api/annotations.proto
+                        tracing::warn!("grpc: import {} not found; types from it can't be used", name);
+                        continue;
+                    }
+                },
+            };
+            let file = Parser::new(&src).map_err(|e| format!("{}: {}", name, e))?.file().map_err(|e| format!("{}: {}", name, e))?;
+            pending.extend(file.imports.iter().cloned());
+            files.push(file);
+        }
+
+        let mut pool = Pool::default();
+        let mut names: HashMap<String, bool> = HashMap::new(); // full name → is a message
+        for file in &files {
+            for message in &file.messages {
+                names.insert(message.name.clone(), true);
+            }
+            for e in &file.enums {
+                names.insert(e.name.clone(), false);
+            }
+        }
+        let resolve = |name: &str, scope: &str| -> Result<FieldType, String> {
+            if let Some(scalar) = Scalar::parse(name) {
+                return Ok(FieldType::Scalar(scalar));
+            }
+            let found = match name.strip_prefix('.') {
+                Some(full) => names.get_key_value(full),
+                None => {
+                    // Innermost scope first, as protoc does
+                    let mut scope = scope;
+                    loop {
+                        let candidate = if scope.is_empty() { name.to_string() } else { format!("{}.{}", scope, name) };
+                        if let Some(found) = names.get_key_value(candidate.as_str()) {
+                            break Some(found);
+                        }
+                        if scope.is_empty() {
+                            break None;
+                        }
+                        scope = scope.rfind('.').map_or("", |i| &scope[..i]);
+                    }
+                }
+            };
+            match found {
+                Some((full, true)) => Ok(FieldType::Message(full.clone())),
+                Some((full, false)) => Ok(FieldType::Enum(full.clone())),
+                None => Err(format!("unknown type {} in {}", name, if scope.is_empty() { "(root)" } else { scope })),
+            }
+        };
+
+        for file in files {
+            for message in file.messages {
+                let mut fields = Vec::with_capacity(message.fields.len());
+                for field in message.fields {
+                    let ty = resolve(&field.type_name, &message.name)?;
+                    let packable = matches!(ty, FieldType::Scalar(s) if s.packable()) || matches!(ty, FieldType::Enum(_));
+                    fields.push(Field {
+                        json_name: field.json_name.unwrap_or_else(|| camel_case(&field.name)),
+                        name: field.name,
+                        number: field.number,
+                        packed: field.repeated && packable && field.packed.unwrap_or(!file.proto2),
+                        repeated: field.repeated,
+                        map_key: field.map_key,
+                        presence: file.proto2 || field.optional || field.oneof,
+                        ty,
+                    });
+                }
+                pool.messages.insert(message.name, Message { fields });
+            }
+            for e in file.enums {
+                pool.enums.insert(e.name.clone(), e);
+            }
+            for service in file.services {
+                let mut methods = Vec::with_capacity(service.methods.len());
+                for method in service.methods {
+                    let scope = service.name.rfind('.').map_or("", |i| &service.name[..i]);
+                    let message = |name: &str| match resolve(name, scope)? {
+                        FieldType::Message(full) => Ok(full),
+                        _ => Err(format!("{}.{}: {} is not a message", service.name, method.name, name)),
+                    };
+                    methods.push(Method {
+                        input: message(&method.input)?,
+                        output: message(&method.output)?,
+                        name: method.name,
+                        client_streaming: method.client_streaming,
+                        server_streaming: method.server_streaming,
+                    });
+                }
+                pool.services.push(Service { name: service.name, methods });
+            }
+        }
+        Ok(pool)
+    }
+}
+
+// lower_snake → lowerCamel, the way protoc derives json_name
+fn camel_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// Parser ---------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Num(String),
+    Str(String),
+    Sym(char),
+}
+
+struct RawField {
+    name: String,
+    type_name: String,
+    number: u32,
+    repeated: bool,
+    optional: bool,
+    oneof: bool,
+    map_key: Option<Scalar>,
+    packed: Option<bool>,
+    json_name: Option<String>,
+}
+
+struct RawMessage {
+    name: String,
+    fields: Vec<RawField>,
+}
+
+struct RawService {
+    name: String,
+    methods: Vec<Method>,
+}
+
+struct File {
+    proto2: bool,
+    imports: Vec<String>,
+    messages: Vec<RawMessage>,
+    enums: Vec<Enum>,
+    services: Vec<RawService>,
+}
+
+struct Parser {
+    toks: Vec<(Tok, usize)>,
+    at: usize,
+    package: String,
+}
+
+impl Parser {
+    fn new(src: &str) -> Result<Parser, String> {
+        let chars: Vec<char> = src.chars().collect();
+        let mut toks = Vec::new();
+        let mut line = 1;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\n' => {
+                    line += 1;
+                    i += 1;
+                }
+                c if c.is_whitespace() => i += 1,
+                '/' if chars.get(i + 1) == Some(&'/') => {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                }
+                '/' if chars.get(i + 1) == Some(&'*') => {
+                    i += 2;
+                    while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                        if chars[i] == '\n' {
+                            line += 1;
+                        }
+                        i += 1;
+                    }
+                    i += 2;
+                }
+                '"' | '\'' => {
+                    let quote = c;
+                    let mut s = String::new();
+                    i += 1;
+                    loop {
+                        match chars.get(i) {
+                            None | Some('\n') => return Err(format!("line {}: unterminated string", line)),
+                            Some(&q) if q == quote => break,
+                            Some('\\') => {
+                                i += 1;
+                                match chars.get(i) {
+                                    Some('n') => s.push('\n'),
+                                    Some('t') => s.push('\t'),
+                                    Some('r') => s.push('\r'),
+                                    Some('0'..='7') => {
+                                        let digits: String = chars[i..].iter().take(3).take_while(|c| c.is_digit(8)).collect();
+                                        s.push(u8::from_str_radix(&digits, 8).unwrap_or(0) as char);
+                                        i += digits.len() - 1;
+                                    }
+                                    Some('x' | 'X') => {
+                                        let digits: String = chars[i + 1..].iter().take(2).take_while(|c| c.is_ascii_hexdigit()).collect();
+                                        s.push(u8::from_str_radix(&digits, 16).unwrap_or(0) as char);
+                                        i += digits.len();
+                                    }
+                                    Some(&other) => s.push(other),
+                                    None => return Err(format!("line {}: unterminated string", line)),
+                                }
+                            }
+                            Some(&other) => s.push(other),
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                    toks.push((Tok::Str(s), line));
+                }
+                c if c.is_ascii_alphabetic() || c == '_' => {
+                    let start = i;
+                    while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                        i += 1;
+                    }
+                    toks.push((Tok::Ident(chars[start..i].iter().collect()), line));
+                }
+                c if c.is_ascii_digit() => {
+                    let start = i;
+                    while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                        // Exponents may carry a sign
+                        if matches!(chars[i], 'e' | 'E') && matches!(chars.get(i + 1), Some('+' | '-')) {
+                            i += 1;
+                        }
+                        i += 1;
+                    }
+                    toks.push((Tok::Num(chars[start..i].iter().collect()), line));
+                }
+                c => {
+                    toks.push((Tok::Sym(c), line));
+                    i += 1;
+                }
+            }
+        }
+        Ok(Parser { toks, at: 0, package: String::new() })
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.at).map(|(t, _)| t)
+    }
+
+    fn error(&self, message: impl std::fmt::Display) -> String {
+        let line = self.toks.get(self.at).or(self.toks.last()).map_or(1, |(_, l)| *l);
+        format!("line {}: {}", line, message)
+    }
+
+    fn next(&mut self) -> Result<Tok, String> {
+        let tok = self.toks.get(self.at).map(|(t, _)| t.clone()).ok_or_else(|| self.error("unexpected end of file"))?;
+        self.at += 1;
+        Ok(tok)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Tok::Sym(c)) {
+            self.at += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        if matches!(self.peek(), Some(Tok::Ident(w)) if w == word) {
+            self.at += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) { Ok(()) } else { Err(self.error(format!("expected '{}'", c))) }
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Tok::Ident(name) => Ok(name),
+            other => {
+                self.at -= 1;
+                Err(self.error(format!("expected a name, found {:?}", other)))
+            }
+        }
+    }
+
+    // `a.b.c`, or `.a.b.c` for a fully qualified type
+    fn full_ident(&mut self) -> Result<String, String> {
+        let mut name = String::new();
+        if self.eat('.') {
+            name.push('.');
+        }
+        name.push_str(&self.ident()?);
+        while self.eat('.') {
+            name.push('.');
+            name.push_str(&self.ident()?);
+        }
+        Ok(name)
+    }
+
+    fn int(&mut self) -> Result<i64, String> {
+        let negative = self.eat('-');
+        let n = match self.next()? {
+            Tok::Num(text) => {
+                let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None if text.len() > 1 && text.starts_with('0') => i64::from_str_radix(&text[1..], 8),
+                    None => text.parse(),
+                };
+                parsed.map_err(|_| self.error(format!("bad number {}", text)))?
+            }
+            Tok::Ident(word) if word == "max" => i64::from(i32::MAX),
+            other => return Err(self.error(format!("expected a number, found {:?}", other))),
+        };
+        Ok(if negative { -n } else { n })
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let mut s = match self.next()? {
+            Tok::Str(s) => s,
+            other => return Err(self.error(format!("expected a string, found {:?}", other))),
+        };
+        // Adjacent literals are joined
+        while let Some(Tok::Str(more)) = self.peek() {
+            s.push_str(&more.clone());
+            self.at += 1;
+        }
+        Ok(s)
+    }
+
+    // Skips to the end of a statement or block, whichever comes first
+    fn skip_statement(&mut self) -> Result<(), String> {
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                Tok::Sym('{') => depth += 1,
+                Tok::Sym('}') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.eat(';');
+                        return Ok(());
+                    }
+                }
+                Tok::Sym(';') if depth == 0 => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    // An option value, returned when it is a plain constant
+    fn constant(&mut self) -> Result<Option<Tok>, String> {
+        if self.peek() == Some(&Tok::Sym('{')) {
+            let mut depth = 0;
+            loop {
+                match self.next()? {
+                    Tok::Sym('{') => depth += 1,
+                    Tok::Sym('}') => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(None);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let sign = self.eat('-') || self.eat('+');
+        match self.peek() {
+            Some(Tok::Str(_)) if !sign => Ok(Some(Tok::Str(self.string()?))),
+            Some(Tok::Ident(_)) => Ok(Some(Tok::Ident(self.full_ident()?))),
+            _ => Ok(Some(self.next()?)),
+        }
+    }
+
+    fn file(mut self) -> Result<File, String> {
+        let mut file = File { proto2: false, imports: Vec::new(), messages: Vec::new(), enums: Vec::new(), services: Vec::new() };
+        while let Some(tok) = self.peek().cloned() {
+            self.at += 1;
+            match tok {
+                Tok::Sym(';') => {}
+                Tok::Ident(word) => match word.as_str() {
+                    "syntax" | "edition" => {
+                        self.expect('=')?;
+                        file.proto2 = self.string()? == "proto2";
+                        self.expect(';')?;
+                    }
+                    "package" => {
+                        self.package = self.full_ident()?;
+                        self.expect(';')?;
+                    }
+                    "import" => {
+                        let _ = self.eat_word("public") || self.eat_word("weak");
+                        file.imports.push(self.string()?);
+                        self.expect(';')?;
+                    }
+                    "option" | "extend" => self.skip_statement()?,
+                    "message" => {
+                        let scope = self.package.clone();
+                        self.message(&scope, &mut file)?;
+                    }
+                    "enum" => {
+                        let scope = self.package.clone();
+                        let e = self.enumeration(&scope)?;
+                        file.enums.push(e);
+                    }
+                    "service" => {
+                        let s = self.service()?;
+                        file.services.push(s);
+                    }
+                    other => return Err(self.error(format!("unexpected {}", other))),
+                },
+                other => return Err(self.error(format!("unexpected {:?}", other))),
+            }
+        }
+        Ok(file)
+    }
+
+    fn message(&mut self, scope: &str, file: &mut File) -> Result<(), String> {
+        let name = qualify(scope, &self.ident()?);
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        while !self.eat('}') {
+            match self.peek().cloned() {
+                Some(Tok::Sym(';')) => self.at += 1,
+                Some(Tok::Ident(word)) => match word.as_str() {
+                    "message" => {
+                        self.at += 1;
+                        self.message(&name, file)?;
+                    }
+                    "enum" => {
+                        self.at += 1;
+                        let e = self.enumeration(&name)?;
+                        file.enums.push(e);
+                    }
+                    "option" | "reserved" | "extensions" | "extend" => {
+                        self.at += 1;
+                        self.skip_statement()?;
+                    }
+                    "oneof" => {
+                        self.at += 1;
+                        self.ident()?;
+                        self.expect('{')?;
+                        while !self.eat('}') {
+                            if self.eat(';') {
+                                continue;
+                            }
+                            if self.eat_word("option") {
+                                self.skip_statement()?;
+                                continue;
+                            }
+                            let mut field = self.field()?;
+                            field.oneof = true;
+                            fields.push(field);
+                        }
+                    }
+                    _ => fields.push(self.field()?),
+                },
+                _ => return Err(self.error("expected a field")),
+            }
+        }
+        file.messages.push(RawMessage { name, fields });
+        Ok(())
+    }
+
+    fn field(&mut self) -> Result<RawField, String> {
+        let mut repeated = false;
+        let mut optional = false;
+        if self.eat_word("repeated") {
+            repeated = true;
+        } else if self.eat_word("optional") {
+            optional = true;
+        } else {
+            self.eat_word("required");
+        }
+        let mut map_key = None;
+        let type_name = if matches!(self.peek(), Some(Tok::Ident(w)) if w == "map") && self.toks.get(self.at + 1).map(|t| &t.0) == Some(&Tok::Sym('<')) {
+            self.at += 2;
+            let key = self.ident()?;
+            map_key = Some(Scalar::parse(&key).filter(|k| !matches!(k, Scalar::Double | Scalar::Float | Scalar::Bytes)).ok_or_else(|| self.error(format!("bad map key type {}", key)))?);
+            self.expect(',')?;
+            let value = self.full_ident()?;
+            self.expect('>')?;
+            repeated = true;
+            value
+        } else {
+            self.full_ident()?
+        };
+        if type_name == "group" {
+            return Err(self.error("groups are not supported"));
+        }
+        let name = self.ident()?;
+        self.expect('=')?;
+        let number = u32::try_from(self.int()?).map_err(|_| self.error("bad field number"))?;
+        let mut field = RawField { name, type_name, number, repeated, optional, oneof: false, map_key, packed: None, json_name: None };
+        if self.eat('[') {
+            loop {
+                let mut option = String::new();
+                while !self.eat('=') {
+                    match self.next()? {
+                        Tok::Ident(part) => option.push_str(&part),
+                        Tok::Sym(c) => option.push(c),
+                        _ => return Err(self.error("bad option")),
+                    }
+                }
+                match (option.as_str(), self.constant()?) {
+                    ("packed", Some(Tok::Ident(value))) => field.packed = Some(value == "true"),
+                    ("json_name", Some(Tok::Str(value))) => field.json_name = Some(value),
+                    _ => {}
+                }
+                if !self.eat(',') {
+                    break;
+                }
+            }
+            self.expect(']')?;
+        }
+        self.expect(';')?;
+        Ok(field)
+    }
+
+    fn enumeration(&mut self, scope: &str) -> Result<Enum, String> {
+        let name = qualify(scope, &self.ident()?);
+        self.expect('{')?;
+        let mut values = Vec::new();
+        while !self.eat('}') {
+            if self.eat(';') {
+                continue;
+            }
+            if self.eat_word("option") || self.eat_word("reserved") {
+                self.skip_statement()?;
+                continue;
+            }
+            let value = self.ident()?;
+            self.expect('=')?;
+            let number = i32::try_from(self.int()?).map_err(|_| self.error("bad enum value"))?;
+            if self.eat('[') {
+                while !self.eat(']') {
+                    self.next()?;
+                }
+            }
+            self.expect(';')?;
+            values.push((value, number));
+        }
+        Ok(Enum { name, values })
+    }
+
+    fn service(&mut self) -> Result<RawService, String> {
+        let name = self.ident()?;
+        let name = qualify(&self.package, &name);
+        self.expect('{')?;
+        let mut methods = Vec::new();
+        while !self.eat('}') {
+            if self.eat(';') {
+                continue;
+            }
+            if self.eat_word("option") {
+                self.skip_statement()?;
+                continue;
+            }
+            if !self.eat_word("rpc") {
+                return Err(self.error("expected rpc"));
+            }
+            let method = self.ident()?;
+            let stream_type = |p: &mut Parser| -> Result<(bool, String), String> {
+                p.expect('(')?;
+                // `stream` is a keyword only when a type follows it
+                let stream = matches!(p.peek(), Some(Tok::Ident(w)) if w == "stream")
+                    && matches!(p.toks.get(p.at + 1).map(|t| &t.0), Some(Tok::Ident(_) | Tok::Sym('.')));
+                if stream {
+                    p.at += 1;
+                }
+                let ty = p.full_ident()?;
+                p.expect(')')?;
+                Ok((stream, ty))
+            };
+            let (client_streaming, input) = stream_type(self)?;
+            if !self.eat_word("returns") {
+                return Err(self.error("expected returns"));
+            }
+            let (server_streaming, output) = stream_type(self)?;
+            if self.peek() == Some(&Tok::Sym('{')) {
+                self.skip_statement()?;
+            } else {
+                self.expect(';')?;
+            }
+            methods.push(Method { name: method, input, output, client_streaming, server_streaming });
+        }
+        Ok(RawService { name, methods })
+    }
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() { name.to_string() } else { format!("{}.{}", scope, name) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Writes `files` under a fresh directory and loads it
+    fn load(test: &str, files: &[(&str, &str)]) -> Result<Pool, String> {
+        let dir = std::env::temp_dir().join(format!("titan-proto-{}-{}", std::process::id(), test));
+        for (name, src) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, src).unwrap();
+        }
+        let pool = Pool::load(&dir);
+        let _ = std::fs::remove_dir_all(&dir);
+        pool
+    }
+
+    fn field<'a>(pool: &'a Pool, message: &str, name: &str) -> &'a Field {
+        pool.messages[message].fields.iter().find(|f| f.name == name).unwrap()
+    }
+
+    const COMMON: &str = r#"
+        syntax = "proto3";
+        package acme.common;
+        import "google/protobuf/timestamp.proto";
+        message Money { string currency = 1; int64 units = 2; google.protobuf.Timestamp at = 3; }
+        enum Status { STATUS_UNKNOWN = 0; ACTIVE = 1; }
+    "#;
+
+    const SHOP: &str = r#"
+        syntax = "proto3";
+        package acme.shop.v1;
+        import "common/types.proto";
+        import public "google/protobuf/empty.proto";
+        option go_package = "example.com/shop;shop";
+
+        /* An order and its lines */
+        message Order {
+          message Line {
+            string sku = 1;
+            uint32 qty = 2;
+            acme.common.Money price = 3;
+          }
+          enum Kind { KIND_UNSPECIFIED = 0; PICKUP = 1 [deprecated = true]; }
+          reserved 20 to 30;
+
+          string order_id = 1;
+          Kind kind = 2;
+          acme.common.Status status = 3;
+          oneof payment {
+            string card = 10;
+            .acme.common.Money cash = 11;
+          }
+          map<string, Line> lines = 5;
+          repeated string tags = 6;
+          repeated Kind kinds = 7;
+          repeated int32 legacy = 8 [packed = false, json_name = "old"];
+          optional string note = 9;
+        }
+
+        service Orders {
+          option deprecated = false;
+          rpc Get(Order) returns (Order);
+          rpc Watch(Order) returns (stream Order.Line) { option (google.api.http) = { get: "/orders/{order_id}" }; }
+          rpc Upload(stream .acme.common.Money) returns (google.protobuf.Empty);
+          rpc Chat(stream Order) returns (stream Order);
+        }
+    "#;
+
+    #[test]
+    fn imports_and_packages() {
+        let pool = load("imports", &[("common/types.proto", COMMON), ("shop.proto", SHOP)]).unwrap();
+        for name in ["acme.common.Money", "acme.shop.v1.Order", "google.protobuf.Timestamp", "google.protobuf.Empty"] {
+            assert!(pool.messages.contains_key(name), "{}", name);
+        }
+        assert_eq!(field(&pool, "acme.common.Money", "at").ty, FieldType::Message("google.protobuf.Timestamp".into()));
+        assert_eq!(field(&pool, "acme.shop.v1.Order", "status").ty, FieldType::Enum("acme.common.Status".into()));
+        assert_eq!(pool.services[0].name, "acme.shop.v1.Orders");
+
+        let err = load("unknown", &[("a.proto", "syntax = \"proto3\"; package a; message A { b.B b = 1; }")]).unwrap_err();
+        assert!(err.contains("unknown type b.B"), "{}", err);
+        // A missing import only matters once a type from it is used
+        assert!(load("missing", &[("a.proto", "syntax = \"proto3\"; import \"google/api/annotations.proto\"; message A {}")]).is_ok());
+    }
+
+    #[test]
+    fn nested_messages_and_enums() {
+        let pool = load("nested", &[("common/types.proto", COMMON), ("shop.proto", SHOP)]).unwrap();
+        assert_eq!(field(&pool, "acme.shop.v1.Order.Line", "price").ty, FieldType::Message("acme.common.Money".into()));
+        assert_eq!(field(&pool, "acme.shop.v1.Order", "kind").ty, FieldType::Enum("acme.shop.v1.Order.Kind".into()));
+        let kind = &pool.enums["acme.shop.v1.Order.Kind"];
+        assert_eq!(kind.values, vec![("KIND_UNSPECIFIED".to_string(), 0), ("PICKUP".to_string(), 1)]);
+        assert_eq!(field(&pool, "acme.shop.v1.Order", "order_id").json_name, "orderId");
+    }
+
+    #[test]
+    fn oneof_map_and_repeated() {
+        let pool = load("fields", &[("common/types.proto", COMMON), ("shop.proto", SHOP)]).unwrap();
+        let order = "acme.shop.v1.Order";
+        let cash = field(&pool, order, "cash");
+        assert!(cash.presence && !cash.repeated);
+        assert_eq!(cash.ty, FieldType::Message("acme.common.Money".into()));
+        assert!(field(&pool, order, "card").presence);
+        assert!(field(&pool, order, "note").presence);
+        assert!(!field(&pool, order, "order_id").presence);
+
+        let lines = field(&pool, order, "lines");
+        assert_eq!(lines.map_key, Some(Scalar::String));
+        assert_eq!(lines.ty, FieldType::Message("acme.shop.v1.Order.Line".into()));
+        assert!(lines.repeated && !lines.packed);
+
+        let tags = field(&pool, order, "tags");
+        assert!(tags.repeated && !tags.packed);
+        // proto3 packs scalars and enums unless told not to
+        assert!(field(&pool, order, "kinds").packed);
+        let legacy = field(&pool, order, "legacy");
+        assert!(legacy.repeated && !legacy.packed);
+        assert_eq!(legacy.json_name, "old");
+
+        let err = load("map-key", &[("a.proto", "syntax = \"proto3\"; message A { map<double, string> m = 1; }")]).unwrap_err();
+        assert!(err.contains("bad map key type double"), "{}", err);
+    }
+
+    #[test]
+    fn streaming_rpcs() {
+        let pool = load("rpc", &[("common/types.proto", COMMON), ("shop.proto", SHOP)]).unwrap();
+        let methods: Vec<(&str, &str, &str, bool, bool)> = pool.services[0]
+            .methods
+            .iter()
+            .map(|m| (m.name.as_str(), m.input.as_str(), m.output.as_str(), m.client_streaming, m.server_streaming))
+            .collect();
+        assert_eq!(
+            methods,
+            vec![
+                ("Get", "acme.shop.v1.Order", "acme.shop.v1.Order", false, false),
+                ("Watch", "acme.shop.v1.Order", "acme.shop.v1.Order.Line", false, true),
+                ("Upload", "acme.common.Money", "google.protobuf.Empty", true, false),
+                ("Chat", "acme.shop.v1.Order", "acme.shop.v1.Order", true, true),
+            ]
+        );
+        // `stream` is a type name when nothing follows it
+        let pool = load("stream-type", &[("a.proto", "syntax = \"proto3\"; message stream {} service S { rpc M(stream) returns (stream); }")]).unwrap();
+        let m = &pool.services[0].methods[0];
+        assert_eq!((m.input.as_str(), m.client_streaming, m.server_streaming), ("stream", false, false));
+    }
+
+    #[test]
+    fn wire_round_trip() {
+        // The examples of the protobuf encoding guide, in one message
+        let src = r#"
+            syntax = "proto3";
+            message Inner { int32 a = 1; }
+            message Test {
+              int32 a = 1;
+              string b = 2;
+              Inner c = 3;
+              repeated int32 d = 4;
+              map<string, int32> m = 5;
+              sint32 e = 6;
+              fixed32 f = 7;
+            }
+        "#;
+        let pool = load("wire", &[("test.proto", src)]).unwrap();
+        let value = json!({ "a": 150, "b": "testing", "c": { "a": 150 }, "d": [3, 270, 86942], "m": { "k": 1 }, "e": -1, "f": 1 });
+        let expected: &[u8] = &[
+            0x08, 0x96, 0x01, // a
+            0x12, 0x07, b't', b'e', b's', b't', b'i', b'n', b'g', // b
+            0x1a, 0x03, 0x08, 0x96, 0x01, // c
+            0x22, 0x06, 0x03, 0x8e, 0x02, 0x9e, 0xa7, 0x05, // d, packed
+            0x2a, 0x05, 0x0a, 0x01, b'k', 0x10, 0x01, // m
+            0x30, 0x01, // e, zigzag
+            0x3d, 0x01, 0x00, 0x00, 0x00, // f
+        ];
+        let bytes = pool.encode("Test", &value).unwrap();
+        assert_eq!(bytes, expected);
+        assert_eq!(pool.decode("Test", &bytes).unwrap(), value);
+
+        // Unpacked records of a packed field read the same
+        let unpacked = [0x20, 0x03, 0x20, 0x8e, 0x02, 0x20, 0x9e, 0xa7, 0x05];
+        assert_eq!(pool.decode("Test", &unpacked).unwrap()["d"], json!([3, 270, 86942]));
+        assert!(pool.decode("Test", &[0x12, 0x07, b't']).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use axum::Router;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{HeaderName, HeaderValue, Method, Request, Version, header};
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::StreamExt;
+use quinn::VarInt;
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use crate::qpack;
+use crate::tls::Tls;
+
+// Frame and stream types (RFC 9114 §7.2, §6.2)
+const FRAME_DATA: u64 = 0x00;
+const FRAME_HEADERS: u64 = 0x01;
+const FRAME_SETTINGS: u64 = 0x04;
+const STREAM_CONTROL: u64 = 0x00;
+
+// Error codes (RFC 9114 §8.1)
+const H3_NO_ERROR: u32 = 0x100;
+const H3_INTERNAL_ERROR: u32 = 0x102;
+const H3_MESSAGE_ERROR: u32 = 0x10e;
+
+// Largest frame payload read into memory; request bodies arrive as many DATA frames
+const MAX_FRAME: u64 = 16 * 1024 * 1024;
+
+// Hop-by-hop headers have no meaning in HTTP/3 and must not be sent
+const CONNECTION_HEADERS: [HeaderName; 5] = [
+    header::CONNECTION,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+];
+
+/// The `http3` option of titan.config: `true` listens on UDP at the HTTP
+/// port, `{ port }` picks another one. Needs `tls`, since QUIC is always
+/// encrypted.
+pub struct Http3Config {
+    pub port: u16,
+}
+
+impl Http3Config {
+    pub fn from_config(config: &Value, http_port: u16) -> Option<Self> {
+        match config {
+            Value::Bool(true) => Some(Self { port: http_port }),
+            Value::Object(options) => Some(Self {
+                port: options.get("port").and_then(Value::as_u64).map_or(http_port, |p| p as u16),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Adds `Alt-Svc` to every response so browsers switch to HTTP/3 on their
+/// next request.
+pub fn advertise(app: Router, port: u16) -> Router {
+    let alt_svc = HeaderValue::from_str(&format!("h3=\":{}\"; ma=86400", port)).expect("valid header value");
+    app.layer(axum::middleware::map_response(move |mut res: axum::response::Response| {
+        let alt_svc = alt_svc.clone();
+        async move {
+            res.headers_mut().insert(header::ALT_SVC, alt_svc);
+            res
+        }
+    }))
+}
+
+/// Starts the experimental HTTP/3 listener. Requests go through the same
+/// router as HTTP/1.1 and HTTP/2; WebSocket upgrades and server push are not
+/// supported over it.
+pub fn listen(tls: &Tls, config: &Http3Config, app: Router) -> Result<quinn::Endpoint, String> {
+    let crypto = tls.server_config(&[&rustls::version::TLS13], &[b"h3"])?;
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(crypto).map_err(|e| e.to_string())?;
+    let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
+    let socket = crate::restart::udp_socket(addr).map_err(|e| format!("http3: can't listen on UDP port {}: {}", config.port, e))?;
+    let runtime = quinn::default_runtime().ok_or("http3: no async runtime")?;
+    let endpoint = quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        Some(quinn::ServerConfig::with_crypto(Arc::new(crypto))),
+        socket,
+        runtime,
+    )
+    .map_err(|e| format!("http3: can't listen on UDP port {}: {}", config.port, e))?;
+
+    let accepting = endpoint.clone();
+    tokio::spawn(async move {
+        while let Some(incoming) = accepting.accept().await {
+            let app = app.clone();
+            tokio::spawn(async move {
+                if let Ok(conn) = incoming.await {
+                    serve_connection(conn, app).await;
+                }
+            });
+        }
+    });
+    tracing::info!("HTTP/3 listening on UDP port {} (experimental)", config.port);
+    Ok(endpoint)
+}
+
+/// Closes every HTTP/3 connection, for shutdown.
+pub fn close(endpoint: &quinn::Endpoint) {
+    endpoint.close(VarInt::from_u32(H3_NO_ERROR), b"");
+}
+
+async fn serve_connection(conn: quinn::Connection, app: Router) {
+    // Our control stream must stay open as long as the connection; SETTINGS
+    // is empty, leaving the QPACK dynamic table disabled
+    let Ok(mut control) = conn.open_uni().await else {
+        return;
+    };
+    let mut preface = Vec::new();
+    put_varint(&mut preface, STREAM_CONTROL);
+    put_varint(&mut preface, FRAME_SETTINGS);
+    put_varint(&mut preface, 0);
+    if control.write_all(&preface).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            stream = conn.accept_bi() => match stream {
+                Ok((send, recv)) => {
+                    tokio::spawn(serve_request(send, recv, conn.remote_address(), app.clone()));
+                }
+                Err(_) => return,
+            },
+            // The peer's control and QPACK streams carry nothing we act on
+            stream = conn.accept_uni() => match stream {
+                Ok(mut recv) => {
+                    tokio::spawn(async move { while let Ok(Some(_)) = recv.read_chunk(64 * 1024, true).await {} });
+                }
+                Err(_) => return,
+            },
+        }
+    }
+}
+
+async fn serve_request(mut send: quinn::SendStream, recv: quinn::RecvStream, remote: SocketAddr, app: Router) {
+    let mut frames = Frames { recv, buf: BytesMut::new() };
+    let mut request = match frames.headers().await.and_then(|fields| request(fields, frames)) {
+        Ok(request) => request,
+        Err(_) => {
+            let _ = send.reset(VarInt::from_u32(H3_MESSAGE_ERROR));
+            return;
+        }
+    };
+    request.extensions_mut().insert(ConnectInfo(crate::ClientAddr(remote)));
+    let head = request.method() == Method::HEAD;
+    let (parts, body) = app.oneshot(request).await.unwrap_or_else(|e| match e {}).into_parts();
+
+    let mut headers = parts.headers;
+    for name in &CONNECTION_HEADERS {
+        headers.remove(name);
+    }
+    let mut block = Vec::new();
+    qpack::encode(&mut block, parts.status.as_u16(), &headers);
+    if write_frame(&mut send, FRAME_HEADERS, &block).await.is_err() {
+        return;
+    }
+    if !head {
+        let mut body = body.into_data_stream();
+        while let Some(chunk) = body.next().await {
+            let Ok(chunk) = chunk else {
+                let _ = send.reset(VarInt::from_u32(H3_INTERNAL_ERROR));
+                return;
+            };
+            if write_frame(&mut send, FRAME_DATA, &chunk).await.is_err() {
+                return;
+            }
+        }
+    }
+    let _ = send.finish();
+}
+
+/// Builds the request from its decoded headers, with the rest of the stream
+/// as the body.
+fn request(fields: Vec<qpack::Field>, frames: Frames) -> Result<Request<Body>, String> {
+    let mut builder = Request::builder().version(Version::HTTP_3);
+    let (mut method, mut scheme, mut authority, mut path) = (None, None, None, None);
+    let mut has_host = false;
+    for (name, value) in fields {
+        let text = || String::from_utf8(value.clone()).map_err(|_| format!("{} is not UTF-8", name));
+        match name.as_str() {
+            ":method" => method = Some(text()?),
+            ":scheme" => scheme = Some(text()?),
+            ":authority" => authority = Some(text()?),
+            ":path" => path = Some(text()?),
+            _ if name.starts_with(':') => return Err(format!("unknown pseudo-header {}", name)),
+            _ => {
+                has_host |= name == "host";
+                builder = builder.header(name, value);
+            }
+        }
+    }
+    let (Some(method), Some(path)) = (method, path) else {
+        return Err(":method and :path are required".to_string());
+    };
+    let uri = match &authority {
+        Some(authority) => format!("{}://{}{}", scheme.as_deref().unwrap_or("https"), authority, path),
+        None => path,
+    };
+    // Actions read `host` like they would over HTTP/1.1
+    if let (false, Some(authority)) = (has_host, authority) {
+        builder = builder.header(header::HOST, authority);
+    }
+
+    let body = Body::from_stream(futures_util::stream::unfold(Some(frames), |frames| async move {
+        let mut frames = frames?;
+        loop {
+            match frames.next().await {
+                Ok(Some((FRAME_DATA, data))) => return Some((Ok(data), Some(frames))),
+                // Trailers and unknown frame types are skipped
+                Ok(Some(_)) => continue,
+                Ok(None) => return None,
+                Err(e) => return Some((Err(e), None)),
+            }
+        }
+    }));
+    builder.method(method.as_str()).uri(uri).body(body).map_err(|e| e.to_string())
+}
+
+/// Reads the frames of a request stream.
+struct Frames {
+    recv: quinn::RecvStream,
+    buf: BytesMut,
+}
+
+impl Frames {
+    /// The next frame's type and payload, or None at the end of the stream.
+    async fn next(&mut self) -> Result<Option<(u64, Bytes)>, String> {
+        loop {
+            let mut header = &self.buf[..];
+            if let (Some(kind), Some(len)) = (varint(&mut header), varint(&mut header)) {
+                if len > MAX_FRAME {
+                    return Err("frame is too large".to_string());
+                }
+                let start = self.buf.len() - header.len();
+                if self.buf.len() - start >= len as usize {
+                    self.buf.advance(start);
+                    return Ok(Some((kind, self.buf.split_to(len as usize).freeze())));
+                }
+            }
+            match self.recv.read_chunk(64 * 1024, true).await.map_err(|e| e.to_string())? {
+                Some(chunk) => self.buf.extend_from_slice(&chunk.bytes),
+                None if self.buf.is_empty() => return Ok(None),
+                None => return Err("stream ended inside a frame".to_string()),
+            }
+        }
+    }
+
+    /// The request's field section, which comes in the first HEADERS frame.
+    async fn headers(&mut self) -> Result<Vec<qpack::Field>, String> {
+        loop {
+            match self.next().await? {
+                Some((FRAME_HEADERS, block)) => return qpack::decode(&block),
+                Some((FRAME_DATA, _)) | None => return Err("request has no HEADERS frame".to_string()),
+                Some(_) => continue,
+            }
+        }
+    }
+}
+
+async fn write_frame(send: &mut quinn::SendStream, kind: u64, payload: &[u8]) -> Result<(), quinn::WriteError> {
+    let mut header = Vec::with_capacity(16);
+    put_varint(&mut header, kind);
+    put_varint(&mut header, payload.len() as u64);
+    send.write_all(&header).await?;
+    send.write_all(payload).await
+}
+
+/// QUIC variable-length integer (RFC 9000 §16), advancing `buf` past it.
+fn varint(buf: &mut &[u8]) -> Option<u64> {
+    let first = *buf.first()?;
+    let len = 1usize << (first >> 6);
+    if buf.len() < len {
+        return None;
+    }
+    let mut value = u64::from(first & 0x3f);
+    for &byte in &buf[1..len] {
+        value = (value << 8) | u64::from(byte);
+    }
+    *buf = &buf[len..];
+    Some(value)
+}
+
+fn put_varint(out: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0x3f => out.push(value as u8),
+        0x40..=0x3fff => out.extend_from_slice(&(value as u16 | 0x4000).to_be_bytes()),
+        0x4000..=0x3fff_ffff => out.extend_from_slice(&(value as u32 | 0x8000_0000).to_be_bytes()),
+        _ => out.extend_from_slice(&(value | 0xc000_0000_0000_0000).to_be_bytes()),
+    }
+}
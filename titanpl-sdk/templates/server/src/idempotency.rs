@@ -0,0 +1,304 @@
+//! `Idempotency-Key` handling. The first request with a key runs as usual
+//! and its response is kept; a retry with the same key gets that response
+//! back instead of running the action again, so a client that timed out on
+//! a payment or a webhook sender that redelivers can't do the work twice.
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bytes::Bytes;
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+
+/// Set on responses that were replayed from the store.
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+// Bigger responses aren't kept; a retry of one runs the action again
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+// Longest key accepted
+const MAX_KEY_LEN: usize = 255;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// Outcomes by kind, for /metrics: new, replayed, in progress, mismatch
+static OUTCOMES: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+const OUTCOME_NAMES: [&str; 4] = ["new", "replayed", "in_progress", "mismatch"];
+
+/// What a key holds. Until the first request finishes there is no response.
+#[derive(Serialize, Deserialize, Clone)]
+struct Record {
+    fingerprint: String,
+    response: Option<Stored>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Stored {
+    status: u16,
+    headers: Vec<(String, String)>,
+    // Base64, so Redis can hold any body
+    body: String,
+}
+
+enum Store {
+    Memory(DashMap<String, (Record, Instant)>),
+    Redis(String),
+}
+
+/// What to do with a request.
+pub enum Claim {
+    /// Nothing to check: no key, or a method or action that isn't covered.
+    Skip,
+    /// The first request with its key. Run it, then hand the response to
+    /// `finish`.
+    New(Ticket),
+    /// Answer with this instead of running the action: the stored response,
+    /// or the conflict the key is in.
+    Answer(Response),
+}
+
+/// The claim on a key, held while its first request runs.
+pub struct Ticket {
+    key: String,
+    fingerprint: String,
+}
+
+/// The `idempotency` block of titan.config: `header` (default
+/// `Idempotency-Key`), the `methods` it applies to (POST and PATCH by
+/// default), optional `actions` to limit it to, how long responses are kept
+/// (`ttl_ms`, a day by default) and how long a running first request holds
+/// its key (`lock_ms`, a minute). Responses live in memory unless `store` is
+/// `"redis"` with a `redis_url`, which instances behind one balancer share.
+pub struct IdempotencyConfig {
+    header: String,
+    methods: Vec<String>,
+    actions: Option<Vec<String>>,
+    ttl: Duration,
+    lock: Duration,
+    store: Store,
+}
+
+impl IdempotencyConfig {
+    pub fn from_config(config: &Value) -> Result<Option<Arc<Self>>, String> {
+        if !config.is_object() && config != &Value::Bool(true) {
+            return Ok(None);
+        }
+        let strings = |key: &str| -> Option<Vec<String>> {
+            config[key].as_array().map(|v| v.iter().filter_map(Value::as_str).map(str::to_string).collect())
+        };
+        let store = match config["store"].as_str().unwrap_or("memory") {
+            "memory" => Store::Memory(DashMap::new()),
+            "redis" => Store::Redis(
+                config["redis_url"]
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or("idempotency: \"redis\" store needs \"redis_url\"")?,
+            ),
+            other => return Err(format!("idempotency: unknown store \"{}\"", other)),
+        };
+        Ok(Some(Arc::new(Self {
+            header: config["header"].as_str().unwrap_or("idempotency-key").to_ascii_lowercase(),
+            methods: strings("methods")
+                .map(|m| m.iter().map(|m| m.to_ascii_uppercase()).collect())
+                .unwrap_or_else(|| vec!["POST".to_string(), "PATCH".to_string()]),
+            actions: strings("actions"),
+            ttl: Duration::from_millis(config["ttl_ms"].as_u64().filter(|ms| *ms > 0).unwrap_or(24 * 60 * 60 * 1000)),
+            lock: Duration::from_millis(config["lock_ms"].as_u64().filter(|ms| *ms > 0).unwrap_or(60_000)),
+            store,
+        })))
+    }
+
+    /// Drops expired keys from the memory store; Redis expires them itself.
+    pub fn start_sweeper(self: &Arc<Self>) {
+        if !matches!(self.store, Store::Memory(_)) {
+            return;
+        }
+        let config = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Store::Memory(map) = &config.store {
+                    let now = Instant::now();
+                    map.retain(|_, (_, expires)| *expires > now);
+                }
+            }
+        });
+    }
+
+    /// Claims the request's key, or says how to answer a retry. Keys are
+    /// scoped to the action and the `Authorization` header, so one caller
+    /// can't replay another's response. The method, path, query and body
+    /// make up the fingerprint a retry must match.
+    pub async fn claim(&self, action: &str, method: &str, uri: &axum::http::Uri, headers: &HashMap<String, String>, body: &[u8]) -> Claim {
+        if !self.methods.iter().any(|m| m == method) || self.actions.as_ref().is_some_and(|a| !a.iter().any(|a| a == action)) {
+            return Claim::Skip;
+        }
+        let Some(raw) = headers.get(&self.header).map(|k| k.trim()).filter(|k| !k.is_empty()) else {
+            return Claim::Skip;
+        };
+        if raw.len() > MAX_KEY_LEN {
+            let message = format!("{} is longer than {} characters", self.header, MAX_KEY_LEN);
+            return Claim::Answer((StatusCode::BAD_REQUEST, axum::Json(json!({ "error": message }))).into_response());
+        }
+        let authorization = headers.get("authorization").map_or("", String::as_str);
+        let key = hex(&[action.as_bytes(), authorization.as_bytes(), raw.as_bytes()]);
+        let path = uri.path_and_query().map_or("/", |p| p.as_str());
+        let fingerprint = hex(&[method.as_bytes(), path.as_bytes(), body]);
+        let pending = Record { fingerprint: fingerprint.clone(), response: None };
+
+        let existing = match &self.store {
+            Store::Memory(map) => {
+                let now = Instant::now();
+                match map.entry(key.clone()) {
+                    Entry::Occupied(entry) if entry.get().1 > now => Some(entry.get().0.clone()),
+                    Entry::Occupied(mut entry) => {
+                        entry.insert((pending, now + self.lock));
+                        None
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert((pending, now + self.lock));
+                        None
+                    }
+                }
+            }
+            Store::Redis(url) => {
+                let json = serde_json::to_string(&pending).unwrap_or_default();
+                let lock = self.lock.as_millis().to_string();
+                let commands = vec![
+                    ["SET", &redis_key(&key), &json, "NX", "PX", &lock].map(str::to_string).to_vec(),
+                    vec!["GET".to_string(), redis_key(&key)],
+                ];
+                match crate::redis::pipeline(url, commands).await.as_deref() {
+                    Ok([Value::String(ok), _]) if ok == "OK" => None,
+                    Ok([_, Value::String(json)]) => serde_json::from_str(json).ok(),
+                    // Expired between the two commands; the retry will get it
+                    Ok(_) => Some(pending),
+                    Err(e) => {
+                        tracing::error!("Idempotency store unavailable: {}", e);
+                        let error = json!({ "error": "Idempotency store unavailable" });
+                        return Claim::Answer((StatusCode::SERVICE_UNAVAILABLE, axum::Json(error)).into_response());
+                    }
+                }
+            }
+        };
+
+        let Some(record) = existing else {
+            OUTCOMES[0].fetch_add(1, Ordering::Relaxed);
+            return Claim::New(Ticket { key, fingerprint });
+        };
+        if record.fingerprint != fingerprint {
+            OUTCOMES[3].fetch_add(1, Ordering::Relaxed);
+            let message = format!("{} was already used for a different request", self.header);
+            return Claim::Answer((StatusCode::UNPROCESSABLE_ENTITY, axum::Json(json!({ "error": message }))).into_response());
+        }
+        match record.response {
+            Some(stored) => {
+                OUTCOMES[1].fetch_add(1, Ordering::Relaxed);
+                Claim::Answer(stored.response())
+            }
+            None => {
+                OUTCOMES[2].fetch_add(1, Ordering::Relaxed);
+                let message = format!("A request with this {} is still in progress", self.header);
+                let mut response = (StatusCode::CONFLICT, axum::Json(json!({ "error": message }))).into_response();
+                response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from_static("1"));
+                Claim::Answer(response)
+            }
+        }
+    }
+
+    /// Keeps the response of a claimed request for `ttl_ms`. Streams, server
+    /// errors, 408s and 429s free the key instead, since a retry of those
+    /// should run again; `body` is None for a stream.
+    pub async fn finish(&self, ticket: Ticket, status: StatusCode, headers: &HeaderMap, body: Option<&Bytes>) {
+        let keep = body.filter(|body| {
+            body.len() <= MAX_BODY_BYTES
+                && !status.is_server_error()
+                && status != StatusCode::REQUEST_TIMEOUT
+                && status != StatusCode::TOO_MANY_REQUESTS
+        });
+        let Some(body) = keep else {
+            match &self.store {
+                Store::Memory(map) => {
+                    map.remove(&ticket.key);
+                }
+                Store::Redis(url) => {
+                    let _ = crate::redis::pipeline(url, vec![vec!["DEL".to_string(), redis_key(&ticket.key)]]).await;
+                }
+            }
+            return;
+        };
+        // Cookies and timings belong to the first response only
+        let headers = headers
+            .iter()
+            .filter(|(name, _)| *name != header::SET_COOKIE && name.as_str() != "server-timing")
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let record = Record {
+            fingerprint: ticket.fingerprint,
+            response: Some(Stored { status: status.as_u16(), headers, body: STANDARD.encode(body) }),
+        };
+        match &self.store {
+            Store::Memory(map) => {
+                map.insert(ticket.key, (record, Instant::now() + self.ttl));
+            }
+            Store::Redis(url) => {
+                let json = serde_json::to_string(&record).unwrap_or_default();
+                let command = ["SET", &redis_key(&ticket.key), &json, "PX", &self.ttl.as_millis().to_string()];
+                if let Err(e) = crate::redis::pipeline(url, vec![command.map(str::to_string).to_vec()]).await {
+                    tracing::error!("Idempotency store unavailable: {}", e);
+                }
+            }
+        }
+    }
+}
+
+impl Stored {
+    fn response(&self) -> Response {
+        let mut response = Response::new(axum::body::Body::from(STANDARD.decode(&self.body).unwrap_or_default()));
+        *response.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                response.headers_mut().append(name, value);
+            }
+        }
+        response.headers_mut().insert(HeaderName::from_static(REPLAYED_HEADER), HeaderValue::from_static("true"));
+        response
+    }
+}
+
+fn redis_key(key: &str) -> String {
+    format!("titan:idempotency:{}", key)
+}
+
+// SHA-256 of the parts, each ended by a newline
+fn hex(parts: &[&[u8]]) -> String {
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    for part in parts {
+        ctx.update(part);
+        ctx.update(b"\n");
+    }
+    ctx.finish().as_ref().iter().fold(String::with_capacity(64), |mut out, b| {
+        let _ = write!(out, "{:02x}", b);
+        out
+    })
+}
+
+/// Prometheus text exposition of how keyed requests were handled.
+pub fn render(out: &mut String) {
+    if OUTCOMES.iter().all(|n| n.load(Ordering::Relaxed) == 0) {
+        return;
+    }
+    metrics::header(out, "titan_idempotency_requests_total", "counter", "Requests with an idempotency key, by outcome.");
+    for (name, count) in OUTCOME_NAMES.iter().zip(&OUTCOMES) {
+        let _ = writeln!(out, "titan_idempotency_requests_total{{outcome=\"{}\"}} {}", name, count.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,245 @@
+use axum::http::StatusCode;
+use bytes::Bytes;
+use serde_json::{Map, Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use crate::action_management::{DynamicRoute, RouteVal};
+use crate::router::FileRouter;
+use crate::runtime::RuntimeManager;
+
+// Workers answer between two requests; a busy one shouldn't hold up the docs
+const SCHEMA_TIMEOUT: Duration = Duration::from_secs(5);
+
+// One method of one path, and the action behind it
+struct Operation {
+    method: String,
+    action: String,
+    // Path parameters and their JSON Schema type
+    params: Vec<(String, &'static str)>,
+}
+
+/// The `docs` block of titan.config: an OpenAPI 3.1 document of every action
+/// route at `<path>/openapi.json`, and Swagger UI at `path` (default
+/// `/docs`) unless `ui` is false. Routes come from the same tables the
+/// server matches requests against; what each one takes and returns comes
+/// from the `schema` its action exports.
+pub struct Docs {
+    pub path: String,
+    pub ui: bool,
+    info: Value,
+    // OpenAPI path -> its operations, in routing order
+    paths: BTreeMap<String, Vec<Operation>>,
+    document: tokio::sync::OnceCell<Bytes>,
+}
+
+impl Docs {
+    /// On when `docs` is true or an object without `"enabled": false`.
+    pub fn from_config(
+        config: &Value,
+        routes: &HashMap<String, RouteVal>,
+        dynamic_routes: &[DynamicRoute],
+        file_routes: &FileRouter,
+    ) -> Option<Self> {
+        if !(config.as_bool() == Some(true) || (config.is_object() && config["enabled"].as_bool() != Some(false))) {
+            return None;
+        }
+        let path = config["path"].as_str().unwrap_or("/docs").trim_end_matches('/').to_string();
+        let mut info = Map::new();
+        info.insert("title".into(), config["title"].as_str().unwrap_or("Titan API").into());
+        info.insert("version".into(), config["version"].as_str().unwrap_or("1.0.0").into());
+        if let Some(description) = config["description"].as_str() {
+            info.insert("description".into(), description.into());
+        }
+
+        let mut docs = Self {
+            path: if path.is_empty() { "/docs".to_string() } else { path },
+            ui: config["ui"].as_bool().unwrap_or(true),
+            info: Value::Object(info),
+            paths: BTreeMap::new(),
+            document: tokio::sync::OnceCell::new(),
+        };
+        // Same precedence as routing: routes.json, then its patterns, then files
+        let mut exact: Vec<_> = routes.iter().filter(|(_, route)| route.r#type == "action").collect();
+        exact.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, route) in exact {
+            let (method, path) = key.split_once(':').unwrap_or(("GET", key));
+            if let Some(action) = route.value.as_str() {
+                docs.add(method, path.to_string(), action, Vec::new());
+            }
+        }
+        for route in dynamic_routes {
+            let (path, params) = template(&route.pattern);
+            docs.add(&route.method, path, &route.action, params);
+        }
+        for (method, path, action) in file_routes.routes() {
+            let params = path
+                .split('/')
+                .filter_map(|s| s.strip_prefix('{')?.strip_suffix('}'))
+                .map(|name| (name.to_string(), "string"))
+                .collect();
+            docs.add(&method, path, &action, params);
+        }
+        Some(docs)
+    }
+
+    // The first route for a method and path wins, as it does for requests
+    fn add(&mut self, method: &str, path: String, action: &str, params: Vec<(String, &'static str)>) {
+        let method = method.to_ascii_lowercase();
+        let operations = self.paths.entry(path).or_default();
+        if !operations.iter().any(|op| op.method == method) {
+            operations.push(Operation { method, action: action.to_string(), params });
+        }
+    }
+
+    pub fn operations(&self) -> usize {
+        self.paths.values().map(Vec::len).sum()
+    }
+
+    /// The document, built once the workers report the actions' schemas.
+    /// Until one answers, it is built without them and not kept.
+    pub async fn document(&self, runtime: &RuntimeManager) -> Bytes {
+        let built = self
+            .document
+            .get_or_try_init(|| async {
+                let schemas = runtime.action_schemas(SCHEMA_TIMEOUT).await.ok_or(())?;
+                Ok::<_, ()>(self.build(&schemas))
+            })
+            .await;
+        match built {
+            Ok(document) => document.clone(),
+            Err(()) => self.build(&HashMap::new()),
+        }
+    }
+
+    fn build(&self, schemas: &HashMap<String, Value>) -> Bytes {
+        let mut paths = Map::new();
+        for (path, operations) in &self.paths {
+            let mut item = Map::new();
+            for op in operations {
+                let schema = schemas.get(&op.action).unwrap_or(&Value::Null);
+                if schema["hidden"].as_bool() == Some(true) {
+                    continue;
+                }
+                item.insert(op.method.clone(), operation(op, schema));
+            }
+            if !item.is_empty() {
+                paths.insert(path.clone(), Value::Object(item));
+            }
+        }
+        let document = json!({
+            "openapi": "3.1.0",
+            "info": self.info,
+            "paths": paths,
+        });
+        Bytes::from(serde_json::to_vec(&document).unwrap_or_default())
+    }
+
+    /// Swagger UI pointed at the document.
+    pub fn ui_page(&self) -> String {
+        let title = self.info["title"].as_str().unwrap_or("").replace('&', "&amp;").replace('<', "&lt;");
+        format!(
+            r##"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js" crossorigin></script>
+<script>
+window.ui = SwaggerUIBundle({{ url: {url}, dom_id: "#swagger-ui" }});
+</script>
+</body>
+</html>
+"##,
+            title = title,
+            url = Value::from(format!("{}/openapi.json", self.path)),
+        )
+    }
+}
+
+// `/users/:id<number>` as `/users/{id}`, with the parameter's type
+fn template(pattern: &str) -> (String, Vec<(String, &'static str)>) {
+    let mut params = Vec::new();
+    let segments: Vec<String> = pattern
+        .trim_matches('/')
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(inner) => {
+                let (name, ty) = inner.split_once('<').map_or((inner, "string"), |(n, t)| (n, t.trim_end_matches('>')));
+                params.push((name.to_string(), if ty == "number" { "integer" } else { "string" }));
+                format!("{{{}}}", name)
+            }
+            None => segment.to_string(),
+        })
+        .collect();
+    (format!("/{}", segments.join("/")), params)
+}
+
+// The properties of an object schema, as parameters found in `location`
+fn parameters(schema: &Value, location: &str, out: &mut Vec<Value>) {
+    let Some(properties) = schema["properties"].as_object() else {
+        return;
+    };
+    let required = |name: &str| schema["required"].as_array().is_some_and(|r| r.iter().any(|n| n == name));
+    for (name, property) in properties {
+        let mut parameter = json!({ "name": name, "in": location, "schema": property });
+        if required(name) {
+            parameter["required"] = true.into();
+        }
+        if let Some(description) = property.get("description") {
+            parameter["description"] = description.clone();
+        }
+        out.push(parameter);
+    }
+}
+
+fn operation(op: &Operation, schema: &Value) -> Value {
+    let mut operation = Map::new();
+    operation.insert("operationId".into(), op.action.replace('/', "_").into());
+    for key in ["summary", "description", "tags", "deprecated"] {
+        if let Some(value) = schema.get(key) {
+            operation.insert(key.into(), value.clone());
+        }
+    }
+
+    // Declared path parameters replace the bare ones the route implies
+    let mut params = Vec::new();
+    for (name, ty) in &op.params {
+        let property = schema["params"]["properties"].get(name).cloned().unwrap_or_else(|| json!({ "type": ty }));
+        params.push(json!({ "name": name, "in": "path", "required": true, "schema": property }));
+    }
+    parameters(&schema["query"], "query", &mut params);
+    parameters(&schema["headers"], "header", &mut params);
+    if !params.is_empty() {
+        operation.insert("parameters".into(), params.into());
+    }
+
+    if let Some(body) = schema.get("body").filter(|b| !b.is_null()) {
+        operation.insert(
+            "requestBody".into(),
+            json!({ "required": true, "content": { "application/json": { "schema": body } } }),
+        );
+    }
+
+    let mut responses = Map::new();
+    if let Some(declared) = schema["response"].as_object() {
+        for (code, body) in declared {
+            let reason = code.parse().ok().and_then(|c| StatusCode::from_u16(c).ok()).and_then(|s| s.canonical_reason());
+            let mut response = json!({ "description": reason.unwrap_or("Response") });
+            if !body.is_null() {
+                response["content"] = json!({ "application/json": { "schema": body } });
+            }
+            responses.insert(code.clone(), response);
+        }
+    }
+    if responses.is_empty() {
+        responses.insert("200".into(), json!({ "description": "OK" }));
+    }
+    operation.insert("responses".into(), Value::Object(responses));
+    Value::Object(operation)
+}
@@ -0,0 +1,245 @@
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::ptr::addr_of;
+use std::sync::{Mutex, OnceLock};
+use v8::inspector::{ChannelBase, ChannelImpl, StringBuffer, StringView, V8InspectorSession};
+use v8::{UniquePtr, UniqueRef};
+
+use crate::action_management::scan_actions;
+use crate::inspector::Inspector;
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+// Samples of isolates recycled since the last dump
+static RETIRED: Mutex<Vec<Stacks>> = Mutex::new(Vec::new());
+
+struct Settings {
+    interval_us: u64,
+    // Script name of each action's file, to the action's name
+    actions: HashMap<String, String>,
+}
+
+/// Sample counts by stack, `action;outer frame;...;inner frame`.
+pub type Stacks = HashMap<String, u64>;
+
+/// The `profile` block of titan.config, or TITAN_PROFILE=1: samples every
+/// worker's CPU every `interval_us` (default 1000) while the server runs.
+pub fn configure(config: &Value, root: &PathBuf) {
+    let from_env = std::env::var("TITAN_PROFILE").is_ok_and(|v| v == "1" || v == "true");
+    let from_config = config.as_bool().unwrap_or(false) || (config.is_object() && config["enabled"].as_bool().unwrap_or(true));
+    if !from_env && !from_config {
+        return;
+    }
+    let actions = scan_actions(root)
+        .into_iter()
+        .map(|(name, path)| (path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/"), name))
+        .collect();
+    let interval_us = config["interval_us"].as_u64().filter(|us| *us > 0).unwrap_or(1_000);
+    let _ = SETTINGS.set(Settings { interval_us, actions });
+}
+
+pub fn enabled() -> bool {
+    SETTINGS.get().is_some()
+}
+
+pub fn interval_us() -> u64 {
+    SETTINGS.get().map_or(0, |s| s.interval_us)
+}
+
+/// A worker's sampling profiler: a session on its isolate's inspector,
+/// driven with the DevTools protocol. It must go before the inspector does.
+pub struct Profiler {
+    // Dropped before its channel
+    session: UniqueRef<V8InspectorSession>,
+    channel: Box<Responses>,
+    next_id: i32,
+}
+
+// Keeps the response to the last call; the profiler answers synchronously
+struct Responses {
+    base: ChannelBase,
+    last: Option<String>,
+}
+
+impl ChannelImpl for Responses {
+    fn base(&self) -> &ChannelBase {
+        &self.base
+    }
+    fn base_mut(&mut self) -> &mut ChannelBase {
+        &mut self.base
+    }
+    unsafe fn base_ptr(this: *const Self) -> *const ChannelBase {
+        unsafe { addr_of!((*this).base) }
+    }
+    fn send_response(&mut self, _call_id: i32, message: UniquePtr<StringBuffer>) {
+        self.last = message.as_ref().map(|m| m.string().to_string());
+    }
+    fn send_notification(&mut self, _message: UniquePtr<StringBuffer>) {}
+    fn flush_protocol_notifications(&mut self) {}
+}
+
+impl Profiler {
+    /// Starts sampling the isolate of `inspector`, if profiling is on.
+    pub fn start(inspector: &mut Inspector) -> Option<Profiler> {
+        let settings = SETTINGS.get()?;
+        let mut channel = Box::new(Responses { base: ChannelBase::new::<Responses>(), last: None });
+        let session = inspector.connect(&mut *channel);
+
+        let mut profiler = Profiler { session, channel, next_id: 0 };
+        profiler.call("Profiler.enable", json!({}));
+        profiler.call("Profiler.setSamplingInterval", json!({ "interval": settings.interval_us }));
+        profiler.call("Profiler.start", json!({}));
+        Some(profiler)
+    }
+
+    /// The samples taken since the last call; sampling carries on.
+    pub fn collect(&mut self) -> Stacks {
+        let stacks = self.stop();
+        self.call("Profiler.start", json!({}));
+        stacks
+    }
+
+    fn stop(&mut self) -> Stacks {
+        self.call("Profiler.stop", json!({})).map(|result| fold(&result["profile"])).unwrap_or_default()
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Option<Value> {
+        self.next_id += 1;
+        let message = json!({ "id": self.next_id, "method": method, "params": params }).to_string();
+        self.session.dispatch_protocol_message(StringView::from(message.as_bytes()));
+        let response: Value = serde_json::from_str(&self.channel.last.take()?).ok()?;
+        response.get("result").cloned()
+    }
+}
+
+/// Keeps the samples of an isolate that is being recycled for the next dump.
+pub fn retire(profiler: Option<Profiler>) {
+    if let Some(mut profiler) = profiler {
+        let stacks = profiler.stop();
+        RETIRED.lock().unwrap_or_else(|e| e.into_inner()).push(stacks);
+    }
+}
+
+/// The samples of isolates recycled since the last call.
+pub fn take_retired() -> Stacks {
+    let mut stacks = Stacks::new();
+    for retired in std::mem::take(&mut *RETIRED.lock().unwrap_or_else(|e| e.into_inner())) {
+        merge(&mut stacks, retired);
+    }
+    stacks
+}
+
+pub fn merge(into: &mut Stacks, from: Stacks) {
+    for (stack, count) in from {
+        *into.entry(stack).or_insert(0) += count;
+    }
+}
+
+// Sums a DevTools `Profile` by stack. Each stack starts at the frame of an
+// action's file, or at `(runtime)` for work outside any action. Idle time
+// is left out.
+fn fold(profile: &Value) -> Stacks {
+    let Some(settings) = SETTINGS.get() else {
+        return Stacks::new();
+    };
+    let Some(nodes) = profile["nodes"].as_array() else {
+        return Stacks::new();
+    };
+    let by_id: HashMap<u64, &Value> = nodes.iter().filter_map(|n| Some((n["id"].as_u64()?, n))).collect();
+    let mut parents = HashMap::new();
+    for node in nodes {
+        for child in node["children"].as_array().into_iter().flatten().filter_map(Value::as_u64) {
+            parents.insert(child, node["id"].as_u64().unwrap_or(0));
+        }
+    }
+
+    let mut stacks = Stacks::new();
+    for node in nodes {
+        let hits = node["hitCount"].as_u64().unwrap_or(0);
+        let Some(id) = node["id"].as_u64().filter(|_| hits > 0) else {
+            continue;
+        };
+        // Root to leaf, without V8's `(root)` node
+        let mut path = vec![node];
+        let mut at = id;
+        while let Some(parent) = parents.get(&at).and_then(|p| by_id.get(p)) {
+            path.push(parent);
+            at = parent["id"].as_u64().unwrap_or(0);
+        }
+        path.pop();
+        path.reverse();
+        if path.last().is_some_and(|n| n["callFrame"]["functionName"] == "(idle)") {
+            continue;
+        }
+
+        let entry = path.iter().position(|n| n["callFrame"]["url"].as_str().is_some_and(|url| settings.actions.contains_key(url)));
+        let (action, frames) = match entry {
+            Some(i) => (settings.actions[path[i]["callFrame"]["url"].as_str().unwrap_or_default()].as_str(), &path[i..]),
+            None => ("(runtime)", &path[..]),
+        };
+        let mut stack = action.replace(';', ",");
+        for frame in frames {
+            stack.push(';');
+            stack.push_str(&label(&frame["callFrame"]));
+        }
+        *stacks.entry(stack).or_insert(0) += hits;
+    }
+    stacks
+}
+
+// `function file:line:column`, at the original source when there's a map
+fn label(frame: &Value) -> String {
+    let name = frame["functionName"].as_str().filter(|n| !n.is_empty()).unwrap_or("(anonymous)");
+    let url = frame["url"].as_str().unwrap_or_default();
+    let label = if url.is_empty() {
+        name.to_string()
+    } else {
+        let line = frame["lineNumber"].as_i64().unwrap_or(0) + 1;
+        let column = frame["columnNumber"].as_i64().unwrap_or(0) + 1;
+        format!("{} {}", name, crate::sourcemap::rewrite(&format!("{}:{}:{}", url, line, column)))
+    };
+    label.replace(';', ",")
+}
+
+/// `stacks` in the folded format flamegraph.pl, inferno and speedscope read:
+/// one `frame;frame;frame count` line per stack, most samples first.
+pub fn folded(stacks: &Stacks) -> String {
+    let mut lines: Vec<_> = stacks.iter().collect();
+    lines.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    lines.iter().map(|(stack, count)| format!("{} {}\n", stack, count)).collect()
+}
+
+/// Per action: its samples and time, and the functions that spent the
+/// most of it themselves.
+pub fn summary(stacks: &Stacks) -> Value {
+    let interval_ms = interval_us() as f64 / 1000.0;
+    let mut actions: HashMap<&str, (u64, HashMap<&str, u64>)> = HashMap::new();
+    for (stack, count) in stacks {
+        let (action, frames) = stack.split_once(';').unwrap_or((stack, ""));
+        let leaf = frames.rsplit(';').next().unwrap_or_default();
+        let (total, hot) = actions.entry(action).or_default();
+        *total += count;
+        *hot.entry(leaf).or_insert(0) += count;
+    }
+    let mut actions: Vec<_> = actions.into_iter().collect();
+    actions.sort_by_key(|a| std::cmp::Reverse(a.1.0));
+    Value::Array(
+        actions
+            .into_iter()
+            .map(|(action, (samples, hot))| {
+                let mut hot: Vec<_> = hot.into_iter().collect();
+                hot.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+                hot.truncate(10);
+                json!({
+                    "action": action,
+                    "samples": samples,
+                    "ms": samples as f64 * interval_ms,
+                    "hot": hot
+                        .into_iter()
+                        .map(|(frame, samples)| json!({ "frame": frame, "samples": samples, "ms": samples as f64 * interval_ms }))
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect(),
+    )
+}
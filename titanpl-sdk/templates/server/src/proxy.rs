@@ -0,0 +1,103 @@
+//! The client's address behind load balancers. `Forwarded` and
+//! `X-Forwarded-For` are only believed when the peer is a trusted proxy,
+//! and then only back to the first hop that isn't one: everything left of
+//! it was written by the client and may be made up.
+
+use ipnet::IpNet;
+use serde_json::Value;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+
+static TRUSTED: OnceLock<Vec<IpNet>> = OnceLock::new();
+
+// What the shorthands in `trusted_proxies` stand for
+const LOOPBACK: &[&str] = &["127.0.0.0/8", "::1/128"];
+const PRIVATE: &[&str] = &["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "100.64.0.0/10", "fc00::/7"];
+const LINK_LOCAL: &[&str] = &["169.254.0.0/16", "fe80::/10"];
+
+/// The `trusted_proxies` key of titan.config: a list of addresses and CIDR
+/// ranges, plus `"loopback"`, `"private"` and `"linklocal"`. Unset, the
+/// socket's peer is always the client and forwarding headers are ignored.
+pub fn configure(config: &Value) -> Result<(), String> {
+    let entries: Vec<&str> = match config {
+        Value::String(entry) => entry.split(',').map(str::trim).filter(|e| !e.is_empty()).collect(),
+        Value::Array(list) => list.iter().filter_map(Value::as_str).collect(),
+        _ => return Ok(()),
+    };
+    let mut nets = Vec::new();
+    for entry in entries {
+        let ranges = match entry {
+            "loopback" => LOOPBACK,
+            "private" => PRIVATE,
+            "linklocal" => LINK_LOCAL,
+            _ => {
+                nets.push(parse_net(entry).ok_or_else(|| format!("trusted_proxies: \"{}\" is not an address or range", entry))?);
+                continue;
+            }
+        };
+        nets.extend(ranges.iter().filter_map(|r| r.parse::<IpNet>().ok()));
+    }
+    if !nets.is_empty() {
+        tracing::info!("Trusting forwarding headers from {} proxy range(s)", nets.len());
+        let _ = TRUSTED.set(nets);
+    }
+    Ok(())
+}
+
+fn parse_net(entry: &str) -> Option<IpNet> {
+    entry.parse::<IpNet>().ok().or_else(|| entry.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+fn trusted(ip: IpAddr) -> bool {
+    TRUSTED.get().is_some_and(|nets| nets.iter().any(|net| net.contains(&ip)))
+}
+
+/// Who sent the request: the peer, or when the peer is a trusted proxy, the
+/// nearest untrusted hop it forwarded for. `Forwarded` wins over
+/// `X-Forwarded-For` when both are present.
+pub fn client_ip<'a>(peer: Option<SocketAddr>, header: impl Fn(&str) -> Option<&'a str>) -> Option<IpAddr> {
+    let peer = peer?.ip().to_canonical();
+    if !trusted(peer) {
+        return Some(peer);
+    }
+    let hops: Vec<Option<IpAddr>> = if let Some(forwarded) = header("forwarded") {
+        forwarded
+            .split(',')
+            .map(|element| {
+                let node = element.split(';').find_map(|pair| {
+                    let (name, value) = pair.split_once('=')?;
+                    name.trim().eq_ignore_ascii_case("for").then_some(value)
+                });
+                node.and_then(parse_node)
+            })
+            .collect()
+    } else if let Some(chain) = header("x-forwarded-for") {
+        chain.split(',').map(parse_node).collect()
+    } else {
+        return Some(peer);
+    };
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        // An obfuscated or "unknown" hop can't be traced further
+        let Some(hop) = hop else {
+            break;
+        };
+        client = hop;
+        if !trusted(hop) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+// `1.2.3.4`, `1.2.3.4:80`, `"[2001:db8::1]:4711"` or `2001:db8::1`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip().to_canonical());
+    }
+    node.strip_prefix('[')?.split(']').next()?.parse::<IpAddr>().ok().map(|ip| ip.to_canonical())
+}
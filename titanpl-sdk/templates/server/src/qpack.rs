@@ -0,0 +1,399 @@
+use axum::http::HeaderMap;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// QPACK (RFC 9204) field sections for the HTTP/3 listener. Only the static
+// table is used: SETTINGS leaves the dynamic table capacity at 0, so peers
+// may not reference one.
+
+const STATIC_TABLE: [(&str, &str); 99] = [
+    (":authority", ""),
+    (":path", "/"),
+    ("age", "0"),
+    ("content-disposition", ""),
+    ("content-length", "0"),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("referer", ""),
+    ("set-cookie", ""),
+    (":method", "CONNECT"),
+    (":method", "DELETE"),
+    (":method", "GET"),
+    (":method", "HEAD"),
+    (":method", "OPTIONS"),
+    (":method", "POST"),
+    (":method", "PUT"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "103"),
+    (":status", "200"),
+    (":status", "304"),
+    (":status", "404"),
+    (":status", "503"),
+    ("accept", "*/*"),
+    ("accept", "application/dns-message"),
+    ("accept-encoding", "gzip, deflate, br"),
+    ("accept-ranges", "bytes"),
+    ("access-control-allow-headers", "cache-control"),
+    ("access-control-allow-headers", "content-type"),
+    ("access-control-allow-origin", "*"),
+    ("cache-control", "max-age=0"),
+    ("cache-control", "max-age=2592000"),
+    ("cache-control", "max-age=604800"),
+    ("cache-control", "no-cache"),
+    ("cache-control", "no-store"),
+    ("cache-control", "public, max-age=31536000"),
+    ("content-encoding", "br"),
+    ("content-encoding", "gzip"),
+    ("content-type", "application/dns-message"),
+    ("content-type", "application/javascript"),
+    ("content-type", "application/json"),
+    ("content-type", "application/x-www-form-urlencoded"),
+    ("content-type", "image/gif"),
+    ("content-type", "image/jpeg"),
+    ("content-type", "image/png"),
+    ("content-type", "text/css"),
+    ("content-type", "text/html; charset=utf-8"),
+    ("content-type", "text/plain"),
+    ("content-type", "text/plain;charset=utf-8"),
+    ("range", "bytes=0-"),
+    ("strict-transport-security", "max-age=31536000"),
+    ("strict-transport-security", "max-age=31536000; includesubdomains"),
+    ("strict-transport-security", "max-age=31536000; includesubdomains; preload"),
+    ("vary", "accept-encoding"),
+    ("vary", "origin"),
+    ("x-content-type-options", "nosniff"),
+    ("x-xss-protection", "1; mode=block"),
+    (":status", "100"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "302"),
+    (":status", "400"),
+    (":status", "403"),
+    (":status", "421"),
+    (":status", "425"),
+    (":status", "500"),
+    ("accept-language", ""),
+    ("access-control-allow-credentials", "FALSE"),
+    ("access-control-allow-credentials", "TRUE"),
+    ("access-control-allow-headers", "*"),
+    ("access-control-allow-methods", "get"),
+    ("access-control-allow-methods", "get, post, options"),
+    ("access-control-allow-methods", "options"),
+    ("access-control-expose-headers", "content-length"),
+    ("access-control-request-headers", "content-type"),
+    ("access-control-request-method", "get"),
+    ("access-control-request-method", "post"),
+    ("alt-svc", "clear"),
+    ("authorization", ""),
+    ("content-security-policy", "script-src 'none'; object-src 'none'; base-uri 'none'"),
+    ("early-data", "1"),
+    ("expect-ct", ""),
+    ("forwarded", ""),
+    ("if-range", ""),
+    ("origin", ""),
+    ("purpose", "prefetch"),
+    ("server", ""),
+    ("timing-allow-origin", "*"),
+    ("upgrade-insecure-requests", "1"),
+    ("user-agent", ""),
+    ("x-forwarded-for", ""),
+    ("x-frame-options", "deny"),
+    ("x-frame-options", "sameorigin"),
+];
+
+// Static index of the `:status` name, used for every response
+const STATUS_INDEX: u64 = 25;
+
+// The HPACK Huffman code (RFC 7541, Appendix B), shared by QPACK
+const HUFFMAN_CODES: [u32; 256] = [
+    0x1ff8, 0x7fffd8, 0xfffffe2, 0xfffffe3, 0xfffffe4, 0xfffffe5, 0xfffffe6, 0xfffffe7, 0xfffffe8,
+    0xffffea, 0x3ffffffc, 0xfffffe9, 0xfffffea, 0x3ffffffd, 0xfffffeb, 0xfffffec, 0xfffffed,
+    0xfffffee, 0xfffffef, 0xffffff0, 0xffffff1, 0xffffff2, 0x3ffffffe, 0xffffff3, 0xffffff4,
+    0xffffff5, 0xffffff6, 0xffffff7, 0xffffff8, 0xffffff9, 0xffffffa, 0xffffffb, 0x14, 0x3f8,
+    0x3f9, 0xffa, 0x1ff9, 0x15, 0xf8, 0x7fa, 0x3fa, 0x3fb, 0xf9, 0x7fb, 0xfa, 0x16, 0x17, 0x18,
+    0x0, 0x1, 0x2, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x5c, 0xfb, 0x7ffc, 0x20, 0xffb,
+    0x3fc, 0x1ffa, 0x21, 0x5d, 0x5e, 0x5f, 0x60, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f, 0x70, 0x71, 0x72, 0xfc, 0x73, 0xfd, 0x1ffb, 0x7fff0,
+    0x1ffc, 0x3ffc, 0x22, 0x7ffd, 0x3, 0x23, 0x4, 0x24, 0x5, 0x25, 0x26, 0x27, 0x6, 0x74, 0x75,
+    0x28, 0x29, 0x2a, 0x7, 0x2b, 0x76, 0x2c, 0x8, 0x9, 0x2d, 0x77, 0x78, 0x79, 0x7a, 0x7b, 0x7ffe,
+    0x7fc, 0x3ffd, 0x1ffd, 0xffffffc, 0xfffe6, 0x3fffd2, 0xfffe7, 0xfffe8, 0x3fffd3, 0x3fffd4,
+    0x3fffd5, 0x7fffd9, 0x3fffd6, 0x7fffda, 0x7fffdb, 0x7fffdc, 0x7fffdd, 0x7fffde, 0xffffeb,
+    0x7fffdf, 0xffffec, 0xffffed, 0x3fffd7, 0x7fffe0, 0xffffee, 0x7fffe1, 0x7fffe2, 0x7fffe3,
+    0x7fffe4, 0x1fffdc, 0x3fffd8, 0x7fffe5, 0x3fffd9, 0x7fffe6, 0x7fffe7, 0xffffef, 0x3fffda,
+    0x1fffdd, 0xfffe9, 0x3fffdb, 0x3fffdc, 0x7fffe8, 0x7fffe9, 0x1fffde, 0x7fffea, 0x3fffdd,
+    0x3fffde, 0xfffff0, 0x1fffdf, 0x3fffdf, 0x7fffeb, 0x7fffec, 0x1fffe0, 0x1fffe1, 0x3fffe0,
+    0x1fffe2, 0x7fffed, 0x3fffe1, 0x7fffee, 0x7fffef, 0xfffea, 0x3fffe2, 0x3fffe3, 0x3fffe4,
+    0x7ffff0, 0x3fffe5, 0x3fffe6, 0x7ffff1, 0x3ffffe0, 0x3ffffe1, 0xfffeb, 0x7fff1, 0x3fffe7,
+    0x7ffff2, 0x3fffe8, 0x1ffffec, 0x3ffffe2, 0x3ffffe3, 0x3ffffe4, 0x7ffffde, 0x7ffffdf,
+    0x3ffffe5, 0xfffff1, 0x1ffffed, 0x7fff2, 0x1fffe3, 0x3ffffe6, 0x7ffffe0, 0x7ffffe1, 0x3ffffe7,
+    0x7ffffe2, 0xfffff2, 0x1fffe4, 0x1fffe5, 0x3ffffe8, 0x3ffffe9, 0xffffffd, 0x7ffffe3, 0x7ffffe4,
+    0x7ffffe5, 0xfffec, 0xfffff3, 0xfffed, 0x1fffe6, 0x3fffe9, 0x1fffe7, 0x1fffe8, 0x7ffff3,
+    0x3fffea, 0x3fffeb, 0x1ffffee, 0x1ffffef, 0xfffff4, 0xfffff5, 0x3ffffea, 0x7ffff4, 0x3ffffeb,
+    0x7ffffe6, 0x3ffffec, 0x3ffffed, 0x7ffffe7, 0x7ffffe8, 0x7ffffe9, 0x7ffffea, 0x7ffffeb,
+    0xffffffe, 0x7ffffec, 0x7ffffed, 0x7ffffee, 0x7ffffef, 0x7fffff0, 0x3ffffee,
+];
+
+const HUFFMAN_LENGTHS: [u8; 256] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+];
+
+/// A header from a decoded field section. Values are bytes, names are
+/// lowercase ASCII in valid HTTP/3 requests.
+pub type Field = (String, Vec<u8>);
+
+/// Decodes a HEADERS frame payload.
+pub fn decode(block: &[u8]) -> Result<Vec<Field>, String> {
+    let mut pos = 0;
+    // Required Insert Count is 0 without a dynamic table; Delta Base is then meaningless
+    if integer(block, &mut pos, 8)? != 0 {
+        return Err("field section references the dynamic table".to_string());
+    }
+    integer(block, &mut pos, 7)?;
+
+    let mut fields = Vec::new();
+    while pos < block.len() {
+        let first = block[pos];
+        let field = if first & 0x80 != 0 {
+            // Indexed field line
+            let index = static_index(first & 0x40 != 0, integer(block, &mut pos, 6)?)?;
+            let (name, value) = STATIC_TABLE[index];
+            (name.to_string(), value.as_bytes().to_vec())
+        } else if first & 0x40 != 0 {
+            // Literal field line with name reference
+            let index = static_index(first & 0x10 != 0, integer(block, &mut pos, 4)?)?;
+            (STATIC_TABLE[index].0.to_string(), string(block, &mut pos, 7)?)
+        } else if first & 0x20 != 0 {
+            // Literal field line with literal name
+            let name = string(block, &mut pos, 3)?;
+            let name = String::from_utf8(name).map_err(|_| "header name is not UTF-8".to_string())?;
+            (name, string(block, &mut pos, 7)?)
+        } else {
+            return Err("field section references the dynamic table".to_string());
+        };
+        fields.push(field);
+    }
+    Ok(fields)
+}
+
+/// Encodes a response's status and headers as a HEADERS frame payload.
+/// Literals are written as-is; Huffman coding them isn't worth it here.
+pub fn encode(out: &mut Vec<u8>, status: u16, headers: &HeaderMap) {
+    out.extend_from_slice(&[0, 0]);
+    // Literal with a static name reference, T bit set
+    put_integer(out, 0x50, 4, STATUS_INDEX);
+    put_string(out, 7, status.to_string().as_bytes());
+    for (name, value) in headers {
+        put_integer(out, 0x20, 3, name.as_str().len() as u64);
+        out.extend_from_slice(name.as_str().as_bytes());
+        put_string(out, 7, value.as_bytes());
+    }
+}
+
+fn static_index(is_static: bool, index: u64) -> Result<usize, String> {
+    match usize::try_from(index) {
+        Ok(index) if is_static && index < STATIC_TABLE.len() => Ok(index),
+        _ if !is_static => Err("field section references the dynamic table".to_string()),
+        _ => Err(format!("static table index {} out of range", index)),
+    }
+}
+
+/// Prefixed integer (RFC 9204 §4.1.1) whose first byte keeps `bits` low bits.
+fn integer(buf: &[u8], pos: &mut usize, bits: u32) -> Result<u64, String> {
+    let truncated = || "truncated field section".to_string();
+    let mask = (1u64 << bits) - 1;
+    let mut value = u64::from(*buf.get(*pos).ok_or_else(truncated)?) & mask;
+    *pos += 1;
+    if value < mask {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let byte = *buf.get(*pos).ok_or_else(truncated)?;
+        *pos += 1;
+        if shift > 56 {
+            return Err("integer in field section is too large".to_string());
+        }
+        value += u64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn put_integer(out: &mut Vec<u8>, flags: u8, bits: u32, value: u64) {
+    let mask = (1u64 << bits) - 1;
+    if value < mask {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | mask as u8);
+    let mut rest = value - mask;
+    while rest >= 0x80 {
+        out.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+/// String literal whose length has a `bits`-bit prefix, with the Huffman
+/// flag just above it.
+fn string(buf: &[u8], pos: &mut usize, bits: u32) -> Result<Vec<u8>, String> {
+    let huffman = buf.get(*pos).is_some_and(|b| b & (1 << bits) != 0);
+    let len = integer(buf, pos, bits)? as usize;
+    let raw = buf.get(*pos..pos.saturating_add(len)).ok_or_else(|| "truncated field section".to_string())?;
+    *pos += len;
+    if huffman { huffman_decode(raw) } else { Ok(raw.to_vec()) }
+}
+
+fn put_string(out: &mut Vec<u8>, bits: u32, value: &[u8]) {
+    put_integer(out, 0, bits, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+fn huffman_decode(input: &[u8]) -> Result<Vec<u8>, String> {
+    static CODES: OnceLock<HashMap<(u8, u32), u8>> = OnceLock::new();
+    let codes = CODES.get_or_init(|| {
+        (0..=255u8)
+            .map(|byte| ((HUFFMAN_LENGTHS[byte as usize], HUFFMAN_CODES[byte as usize]), byte))
+            .collect()
+    });
+
+    let invalid = || "invalid Huffman string in field section".to_string();
+    let mut out = Vec::with_capacity(input.len() * 8 / 5);
+    let (mut acc, mut held) = (0u64, 0u8);
+    for &byte in input {
+        acc = (acc << 8) | u64::from(byte);
+        held += 8;
+        // Codes are 5 to 30 bits long
+        'symbol: while held >= 5 {
+            for len in 5..=held.min(30) {
+                let code = (acc >> (held - len)) as u32 & ((1u32 << len) - 1);
+                if let Some(&symbol) = codes.get(&(len, code)) {
+                    out.push(symbol);
+                    held -= len;
+                    acc &= (1u64 << held) - 1;
+                    continue 'symbol;
+                }
+            }
+            if held >= 30 {
+                return Err(invalid());
+            }
+            break;
+        }
+    }
+    // Padding is under a byte of 1 bits, the start of EOS
+    if held >= 8 || acc != (1u64 << held) - 1 {
+        return Err(invalid());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let digits: Vec<u8> = s.bytes().filter(u8::is_ascii_hexdigit).collect();
+        digits.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap()).collect()
+    }
+
+    fn fields(list: &[(&str, &str)]) -> Vec<Field> {
+        list.iter().map(|(name, value)| (name.to_string(), value.as_bytes().to_vec())).collect()
+    }
+
+    // RFC 7541, C.1
+    #[test]
+    fn prefixed_integers() {
+        for (bits, value, encoded) in [(5, 10, "0a"), (5, 1337, "1f9a0a"), (8, 42, "2a"), (4, 25, "0f0a")] {
+            let mut out = Vec::new();
+            put_integer(&mut out, 0, bits, value);
+            assert_eq!(out, hex(encoded), "{} in {} bits", value, bits);
+            let mut pos = 0;
+            assert_eq!(integer(&out, &mut pos, bits), Ok(value));
+            assert_eq!(pos, out.len());
+        }
+        // The bits above the prefix are left alone
+        let mut pos = 0;
+        assert_eq!(integer(&[0xea], &mut pos, 5), Ok(10));
+        assert!(integer(&[0x1f, 0x9a], &mut 0, 5).is_err());
+        assert!(integer(&hex("1fffffffffffffffffffff7f"), &mut 0, 5).is_err());
+    }
+
+    // RFC 7541, C.4.1 to C.4.3 and C.6.1
+    #[test]
+    fn huffman_strings() {
+        for (encoded, text) in [
+            ("f1e3 c2e5 f23a 6ba0 ab90 f4ff", "www.example.com"),
+            ("a8eb 1064 9cbf", "no-cache"),
+            ("25a8 49e9 5ba9 7d7f", "custom-key"),
+            ("25a8 49e9 5bb8 e8b4 bf", "custom-value"),
+            ("6402", "302"),
+            ("aec3 771a 4b", "private"),
+            ("d07a be94 1054 d444 a820 0595 040b 8166 e082 a62d 1bff", "Mon, 21 Oct 2013 20:13:21 GMT"),
+            ("9d29 ad17 1863 c78f 0b97 c8e9 ae82 ae43 d3", "https://www.example.com"),
+        ] {
+            assert_eq!(huffman_decode(&hex(encoded)).as_deref(), Ok(text.as_bytes()), "{}", text);
+        }
+        // EOS inside the string, padding of a full byte, and padding that isn't all 1 bits
+        for bad in ["ffff ffff", "a8eb 1064 9cbf ff", "a8eb 1064 9cbe"] {
+            assert!(huffman_decode(&hex(bad)).is_err(), "{}", bad);
+        }
+    }
+
+    // RFC 9204, B.1: a literal with a static name reference
+    #[test]
+    fn decodes_static_references() {
+        assert_eq!(decode(&hex("0000 510b 2f69 6e64 6578 2e68 746d 6c")), Ok(fields(&[(":path", "/index.html")])));
+
+        // Indexed lines (:method GET, :scheme https) and a Huffman-coded :authority
+        let block = hex("0000 d1d7 508c f1e3 c2e5 f23a 6ba0 ab90 f4ff");
+        assert_eq!(
+            decode(&block),
+            Ok(fields(&[(":method", "GET"), (":scheme", "https"), (":authority", "www.example.com")]))
+        );
+
+        // A literal name, then a Huffman-coded value
+        let block = hex("0000 2703 6375 7374 6f6d 2d6b 6579 8925 a849 e95b b8e8 b4bf");
+        assert_eq!(decode(&block), Ok(fields(&[("custom-key", "custom-value")])));
+    }
+
+    #[test]
+    fn refuses_the_dynamic_table() {
+        // A nonzero Required Insert Count, a dynamic indexed line, and a post-base index
+        for block in ["0200 80", "0000 80", "0000 10"] {
+            assert!(decode(&hex(block)).unwrap_err().contains("dynamic table"), "{}", block);
+        }
+        assert!(decode(&hex("0000 ff24")).unwrap_err().contains("out of range"));
+        assert!(decode(&hex("0000 510b 2f69 6e64")).is_err());
+        assert!(decode(&[]).is_err());
+    }
+
+    #[test]
+    fn encodes_responses() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "text/html".parse().unwrap());
+        let mut out = Vec::new();
+        encode(&mut out, 200, &headers);
+        // :status by static name reference (index 25), then content-type by literal name
+        let expected = hex("0000 5f0a 0332 3030 2705 636f 6e74 656e 742d 7479 7065 0974 6578 742f 6874 6d6c");
+        assert_eq!(out, expected);
+        assert_eq!(decode(&out), Ok(fields(&[(":status", "200"), ("content-type", "text/html")])));
+    }
+}
@@ -0,0 +1,298 @@
+use dashmap::DashMap;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+use crate::middleware::{Decision, Interceptor};
+use crate::runtime::{ResponseHeaders, WorkerResult};
+
+// Counters idle for this many windows are dropped by the sweep
+const IDLE_WINDOWS: u32 = 2;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+static LIMITER: OnceLock<Limiter> = OnceLock::new();
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// Refills `limit` tokens per window, up to `burst`.
+    TokenBucket,
+    /// Counts requests over the last window, weighting the previous fixed
+    /// window by how much of it still overlaps.
+    SlidingWindow,
+}
+
+#[derive(Clone)]
+pub struct Policy {
+    pub limit: u32,
+    pub window: Duration,
+    pub strategy: Strategy,
+    /// Most tokens a bucket holds. Defaults to `limit`.
+    pub burst: Option<u32>,
+}
+
+impl Policy {
+    /// `base` with the fields set in `options` replaced, or None without a
+    /// usable `limit`.
+    pub fn with_overrides(base: Option<&Policy>, options: &Value) -> Option<Self> {
+        let limit = options["limit"].as_u64().map(|n| n as u32).or(base.map(|p| p.limit)).filter(|n| *n > 0)?;
+        Some(Self {
+            limit,
+            window: options["window_ms"]
+                .as_u64()
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis)
+                .or(base.map(|p| p.window))
+                .unwrap_or(Duration::from_secs(60)),
+            strategy: match options["strategy"].as_str() {
+                Some("token_bucket") => Strategy::TokenBucket,
+                Some(_) => Strategy::SlidingWindow,
+                None => base.map_or(Strategy::SlidingWindow, |p| p.strategy),
+            },
+            burst: options["burst"].as_u64().map(|n| n as u32).or(base.and_then(|p| p.burst)),
+        })
+    }
+}
+
+/// The verdict for one request.
+pub struct Outcome {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Until the limit is back to full, or until a denied request may retry.
+    pub reset: Duration,
+}
+
+impl Outcome {
+    /// `RateLimit-*` response headers, plus `Retry-After` when denied.
+    pub fn headers(&self, policy: &Policy) -> ResponseHeaders {
+        let reset = self.reset.as_secs_f64().ceil() as u64;
+        let mut headers = ResponseHeaders::new();
+        headers.push(("ratelimit-limit".to_string(), self.limit.to_string()));
+        headers.push(("ratelimit-remaining".to_string(), self.remaining.to_string()));
+        headers.push(("ratelimit-reset".to_string(), reset.to_string()));
+        headers.push(("ratelimit-policy".to_string(), format!("{};w={}", policy.limit, policy.window.as_secs().max(1))));
+        if !self.allowed {
+            headers.push(("retry-after".to_string(), reset.max(1).to_string()));
+        }
+        headers
+    }
+}
+
+enum Counter {
+    Bucket { tokens: f64, updated: Instant },
+    Window { start: Instant, current: u32, previous: u32 },
+}
+
+impl Counter {
+    fn new(policy: &Policy, now: Instant) -> Self {
+        match policy.strategy {
+            Strategy::TokenBucket => Counter::Bucket { tokens: policy.burst.unwrap_or(policy.limit) as f64, updated: now },
+            Strategy::SlidingWindow => Counter::Window { start: now, current: 0, previous: 0 },
+        }
+    }
+
+    fn last_used(&self) -> Instant {
+        match self {
+            Counter::Bucket { updated, .. } => *updated,
+            Counter::Window { start, .. } => *start,
+        }
+    }
+
+    fn take(&mut self, policy: &Policy, now: Instant) -> Outcome {
+        let window = policy.window.as_secs_f64();
+        match self {
+            Counter::Bucket { tokens, updated } => {
+                let capacity = policy.burst.unwrap_or(policy.limit).max(1) as f64;
+                let rate = policy.limit as f64 / window;
+                *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * rate).min(capacity);
+                *updated = now;
+                let allowed = *tokens >= 1.0;
+                if allowed {
+                    *tokens -= 1.0;
+                }
+                let until = if allowed { capacity - *tokens } else { 1.0 - *tokens };
+                Outcome {
+                    allowed,
+                    limit: capacity as u32,
+                    remaining: *tokens as u32,
+                    reset: Duration::from_secs_f64(until / rate),
+                }
+            }
+            Counter::Window { start, current, previous } => {
+                let elapsed = now.duration_since(*start);
+                if elapsed >= policy.window {
+                    let windows = elapsed.as_nanos() / policy.window.as_nanos();
+                    *previous = if windows == 1 { *current } else { 0 };
+                    *current = 0;
+                    *start += policy.window * windows as u32;
+                }
+                let into = now.duration_since(*start).as_secs_f64() / window;
+                let estimate = *previous as f64 * (1.0 - into) + *current as f64;
+                let allowed = estimate + 1.0 <= policy.limit as f64;
+                if allowed {
+                    *current += 1;
+                }
+                let used = if allowed { estimate + 1.0 } else { estimate };
+                Outcome {
+                    allowed,
+                    limit: policy.limit,
+                    remaining: (policy.limit as f64 - used).max(0.0) as u32,
+                    reset: policy.window.saturating_sub(now.duration_since(*start)),
+                }
+            }
+        }
+    }
+}
+
+/// Counters shared by every HTTP thread and worker, keyed by policy scope
+/// and client.
+#[derive(Default)]
+struct Limiter {
+    counters: DashMap<String, (Counter, Duration)>,
+    rejected: Mutex<HashMap<String, u64>>,
+}
+
+impl Limiter {
+    fn get() -> &'static Self {
+        LIMITER.get_or_init(Self::default)
+    }
+
+    fn sweep(&self) {
+        let now = Instant::now();
+        self.counters.retain(|_, (counter, window)| now.duration_since(counter.last_used()) < *window * IDLE_WINDOWS);
+    }
+}
+
+/// Counts one hit against `key` under `policy`. `scope` names the policy in
+/// metrics and keeps its counters apart from other policies'.
+pub fn check(scope: &str, key: &str, policy: &Policy) -> Outcome {
+    let limiter = Limiter::get();
+    let now = Instant::now();
+    let mut entry = limiter
+        .counters
+        .entry(format!("{}\0{}", scope, key))
+        .or_insert_with(|| (Counter::new(policy, now), policy.window));
+    let outcome = entry.0.take(policy, now);
+    drop(entry);
+    if !outcome.allowed {
+        *limiter.rejected.lock().unwrap().entry(scope.to_string()).or_default() += 1;
+    }
+    outcome
+}
+
+pub fn start_sweeper() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            Limiter::get().sweep();
+        }
+    });
+}
+
+/// What identifies a client.
+enum KeySource {
+    Ip,
+    /// First comma-separated value of a request header, falling back to
+    /// the IP when it's missing.
+    Header(String),
+}
+
+/// The `rate_limit` block of titan.config: a default policy for every action
+/// and per-action ones under `routes` (`false` exempts one).
+pub struct RateLimitConfig {
+    default: Option<Policy>,
+    routes: HashMap<String, Option<Policy>>,
+    key: KeySource,
+}
+
+impl RateLimitConfig {
+    pub fn from_config(config: &Value) -> Option<Self> {
+        if !config.is_object() {
+            return None;
+        }
+        let default = Policy::with_overrides(None, config);
+        let routes: HashMap<String, Option<Policy>> = config["routes"]
+            .as_object()
+            .map(|routes| {
+                routes
+                    .iter()
+                    .map(|(action, options)| {
+                        let policy = (options.as_bool() != Some(false))
+                            .then(|| Policy::with_overrides(default.as_ref(), options))
+                            .flatten();
+                        (action.clone(), policy)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        if default.is_none() && routes.values().all(Option::is_none) {
+            return None;
+        }
+        let key = match config["key"]["header"].as_str() {
+            Some(name) => KeySource::Header(name.to_ascii_lowercase()),
+            None => KeySource::Ip,
+        };
+        Some(Self { default, routes, key })
+    }
+
+    fn policy_for(&self, action: &str) -> Option<&Policy> {
+        match self.routes.get(action) {
+            Some(policy) => policy.as_ref(),
+            None => self.default.as_ref(),
+        }
+    }
+}
+
+/// Enforces `config` before requests are queued: over-limit requests get a
+/// 429 without reaching a worker. Jobs and tasks have no client and are not
+/// limited.
+pub fn interceptor(config: RateLimitConfig) -> Interceptor {
+    Box::new(move |task| {
+        let Some(ip) = task.client_ip() else {
+            return Decision::Continue;
+        };
+        let Some(policy) = config.policy_for(&task.action_name) else {
+            return Decision::Continue;
+        };
+        let header = match &config.key {
+            KeySource::Header(name) => task
+                .headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .and_then(|(_, v)| v.split(',').next())
+                .map(str::trim)
+                .filter(|v| !v.is_empty()),
+            KeySource::Ip => None,
+        };
+        let key = header.map_or_else(|| ip.to_string(), str::to_string);
+        // Actions with their own policy count separately; the rest share the default
+        let scope = if config.routes.contains_key(&task.action_name) { task.action_name.as_str() } else { "default" };
+
+        let outcome = check(scope, &key, policy);
+        let headers = outcome.headers(policy);
+        if outcome.allowed {
+            task.response_headers.extend(headers);
+            return Decision::Continue;
+        }
+        let mut denied = WorkerResult::error(429, "Too Many Requests");
+        denied.headers.extend(headers);
+        Decision::Respond(Box::new(denied))
+    })
+}
+
+/// Prometheus text exposition of rejected requests per policy.
+pub fn render(out: &mut String) {
+    let Some(limiter) = LIMITER.get() else {
+        return;
+    };
+    metrics::header(out, "titan_rate_limited_total", "counter", "Requests turned away with 429, by policy.");
+    for (scope, count) in limiter.rejected.lock().unwrap().iter() {
+        let _ = writeln!(out, "titan_rate_limited_total{{policy=\"{}\"}} {}", metrics::escape_label(scope), count);
+    }
+    metrics::header(out, "titan_rate_limit_keys", "gauge", "Clients with a live rate limit counter.");
+    let _ = writeln!(out, "titan_rate_limit_keys {}", limiter.counters.len());
+}
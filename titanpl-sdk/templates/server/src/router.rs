@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+
+use crate::groups::Groups;
+use crate::routing::Routing;
+
+const METHODS: [&str; 7] = ["get", "post", "put", "patch", "delete", "head", "options"];
+
+/// Routes derived from the layout of the actions directory.
+///
+/// A file named after an HTTP method is served at the path of its directory:
+/// `actions/users/[id]/get.ts` answers `GET /users/:id`, and `actions/get.ts`
+/// answers `GET /`. `[name]` captures one segment and `[...name]` the rest of
+/// the path; `[[name]]` and `[[...name]]` may also match nothing, so
+/// `[[lang]]/docs` answers both `/docs` and `/en/docs`. Other action files
+/// are only reachable through routes.json.
+///
+/// Routes are kept in a prefix tree keyed by path segment. Static segments
+/// win over parameters, which win over catch-alls; a path without an
+/// optional segment is routed as if the segment weren't in the directory.
+#[derive(Default)]
+pub struct FileRouter {
+    root: Node,
+    len: usize,
+}
+
+#[derive(Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    param: Option<(String, Box<Node>)>,
+    catch_all: Option<(String, HashMap<String, String>)>,
+    // Uppercase method -> action name
+    actions: HashMap<String, String>,
+}
+
+pub enum Segment<'a> {
+    Static(&'a str),
+    Param(&'a str),
+    CatchAll(&'a str),
+    OptionalParam(&'a str),
+    OptionalCatchAll(&'a str),
+}
+
+pub fn parse_segment(segment: &str) -> Segment<'_> {
+    if let Some(inner) = segment.strip_prefix("[[").and_then(|s| s.strip_suffix("]]")) {
+        return match inner.strip_prefix("...") {
+            Some(name) => Segment::OptionalCatchAll(name),
+            None => Segment::OptionalParam(inner),
+        };
+    }
+    match segment.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        Some(inner) => match inner.strip_prefix("...") {
+            Some(name) => Segment::CatchAll(name),
+            None => Segment::Param(inner),
+        },
+        None => Segment::Static(segment),
+    }
+}
+
+/// The method and directories of the file route an action is served at,
+/// group prefixes included; None for an action not named after a method.
+pub fn file_route(action: &str, groups: &Groups) -> Option<(String, Vec<String>)> {
+    let mut segments: Vec<&str> = action.split('/').collect();
+    let file = segments.pop()?;
+    if !METHODS.contains(&file) {
+        return None;
+    }
+    Some((file.to_ascii_uppercase(), groups.route_segments(action, &segments)))
+}
+
+/// The catch-all params of an action, `[...name]` or `[[...name]]` in its
+/// path, which reach `req.params` as arrays of segments.
+pub fn array_params(action: &str) -> impl Iterator<Item = &str> {
+    action.split('/').filter_map(|segment| match parse_segment(segment) {
+        Segment::CatchAll(name) | Segment::OptionalCatchAll(name) => Some(name),
+        _ => None,
+    })
+}
+
+// Where a route goes in: `implied` routes come from leaving out an optional
+// segment and never replace a route spelled out in full
+struct Insert<'a> {
+    action: &'a str,
+    method: &'a str,
+    implied: bool,
+}
+
+impl FileRouter {
+    /// Builds the table from action names, which are paths relative to the
+    /// actions directory without extension (`users/[id]/get`), with the
+    /// prefixes of their route groups.
+    pub fn from_actions<'a>(actions: impl IntoIterator<Item = &'a String>, groups: &Groups) -> Self {
+        let mut router = Self::default();
+        let mut actions: Vec<&String> = actions.into_iter().collect();
+        actions.sort(); // Deterministic conflict resolution
+        for action in actions {
+            router.insert(action, groups);
+        }
+        router
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn insert(&mut self, action: &str, groups: &Groups) {
+        let Some((method, segments)) = file_route(action, groups) else {
+            return;
+        };
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        let route = Insert { action, method: &method, implied: false };
+        match self.root.insert(&segments, &route) {
+            Ok(added) => self.len += added,
+            Err(reason) => conflict(action, &reason),
+        }
+    }
+
+    /// Every route as (method, path, action), with parameters written as
+    /// `{name}` the way OpenAPI templates paths. A catch-all is a `{name}`
+    /// too, though it spans several segments.
+    pub fn routes(&self) -> Vec<(String, String, String)> {
+        let mut routes = Vec::with_capacity(self.len);
+        self.root.collect("", &mut routes);
+        routes.sort();
+        routes
+    }
+
+    /// Resolves a request to an action and the params captured from its path.
+    /// No file route ends in a slash, so with a strict trailing slash a path
+    /// that does matches none.
+    pub fn match_route(&self, method: &str, path: &str, routing: &Routing) -> Option<(String, HashMap<String, String>)> {
+        if routing.strict_slash() && path.len() > 1 && path.ends_with('/') {
+            return None;
+        }
+        let segments: Vec<&str> = path.trim_matches('/').split('/').filter(|s| !s.is_empty()).collect();
+        let mut params = Vec::new();
+        let action = self.root.find(method, &segments, &mut params, routing)?;
+        Some((action.clone(), params.into_iter().collect()))
+    }
+}
+
+impl Node {
+    // Adds a route below this node, returning how many paths it answers
+    fn insert(&mut self, segments: &[&str], route: &Insert) -> Result<usize, String> {
+        let Some((first, rest)) = segments.split_first() else {
+            return Ok(route.add(&mut self.actions));
+        };
+        match parse_segment(first) {
+            Segment::Static(s) => self.statics.entry(s.to_string()).or_default().insert(rest, route),
+            Segment::Param(name) => self.param_child(name)?.insert(rest, route),
+            Segment::OptionalParam(name) => {
+                let with = self.param_child(name)?.insert(rest, route)?;
+                let without = self.insert(rest, &Insert { implied: true, ..*route })?;
+                Ok(with + without)
+            }
+            Segment::CatchAll(name) => self.catch_all_route(name, rest, route, false),
+            Segment::OptionalCatchAll(name) => self.catch_all_route(name, rest, route, true),
+        }
+    }
+
+    fn catch_all_route(&mut self, name: &str, rest: &[&str], route: &Insert, optional: bool) -> Result<usize, String> {
+        if !rest.is_empty() {
+            return Err("a catch-all must be the last directory".to_string());
+        }
+        let (existing, actions) = self.catch_all.get_or_insert_with(|| (name.to_string(), HashMap::new()));
+        if existing != name {
+            return Err(format!("catch-all [...{}] is already named [...{}] here", name, existing));
+        }
+        let mut added = route.add(actions);
+        if optional {
+            added += Insert { implied: true, ..*route }.add(&mut self.actions);
+        }
+        Ok(added)
+    }
+
+    fn param_child(&mut self, name: &str) -> Result<&mut Node, String> {
+        let (existing, child) = self.param.get_or_insert_with(|| (name.to_string(), Box::default()));
+        if existing != name {
+            return Err(format!("parameter [{}] is already named [{}] here", name, existing));
+        }
+        Ok(child)
+    }
+
+    fn collect(&self, prefix: &str, routes: &mut Vec<(String, String, String)>) {
+        let path = if prefix.is_empty() { "/" } else { prefix };
+        for (method, action) in &self.actions {
+            routes.push((method.clone(), path.to_string(), action.clone()));
+        }
+        for (segment, child) in &self.statics {
+            child.collect(&format!("{}/{}", prefix, segment), routes);
+        }
+        if let Some((name, child)) = &self.param {
+            child.collect(&format!("{}/{{{}}}", prefix, name), routes);
+        }
+        if let Some((name, actions)) = &self.catch_all {
+            for (method, action) in actions {
+                routes.push((method.clone(), format!("{}/{{{}}}", prefix, name), action.clone()));
+            }
+        }
+    }
+
+    fn find<'a>(&'a self, method: &str, segments: &[&str], params: &mut Vec<(String, String)>, routing: &Routing) -> Option<&'a String> {
+        let Some((first, rest)) = segments.split_first() else {
+            return self.actions.get(method);
+        };
+
+        let child = self.statics.get(*first).or_else(|| {
+            // The directory as spelled on disk, whatever case the path uses
+            let found = routing.case_insensitive.then(|| self.statics.iter().find(|(segment, _)| segment.eq_ignore_ascii_case(first)));
+            found.flatten().map(|(_, child)| child)
+        });
+        if let Some(child) = child
+            && let Some(action) = child.find(method, rest, params, routing)
+        {
+            return Some(action);
+        }
+
+        if let Some((name, child)) = &self.param {
+            params.push((name.clone(), (*first).to_string()));
+            if let Some(action) = child.find(method, rest, params, routing) {
+                return Some(action);
+            }
+            params.pop(); // Backtrack
+        }
+
+        let (name, actions) = self.catch_all.as_ref()?;
+        let action = actions.get(method)?;
+        params.push((name.clone(), segments.join("/")));
+        Some(action)
+    }
+}
+
+impl Insert<'_> {
+    fn add(&self, actions: &mut HashMap<String, String>) -> usize {
+        if self.implied && actions.contains_key(self.method) {
+            return 0;
+        }
+        let replaced = actions.insert(self.method.to_string(), self.action.to_string());
+        usize::from(replaced.is_none())
+    }
+}
+
+fn conflict(action: &str, reason: &str) {
+    tracing::warn!("Skipping file route {}: {}", action, reason);
+}
@@ -0,0 +1,92 @@
+//! How a request path is compared with the routes: what a trailing slash
+//! means, and whether letter case matters.
+
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TrailingSlash {
+    // `/users/` is the same route as `/users`
+    Merge,
+    // `/users/` and `/users` are different routes
+    Strict,
+    // Like strict, but the other spelling is redirected to the one routed
+    Redirect,
+}
+
+/// The `routing` block of titan.config: `{ trailing_slash, case_insensitive }`.
+/// `trailing_slash` is `"merge"` (the default), `"strict"` or `"redirect"`;
+/// `case_insensitive` matches the fixed parts of paths regardless of case,
+/// params keeping the case they were sent in.
+pub struct Routing {
+    pub trailing_slash: TrailingSlash,
+    pub case_insensitive: bool,
+    // Lowercased exact route keys to the keys as declared
+    folded: HashMap<String, String>,
+}
+
+impl Routing {
+    pub fn from_config<'a>(config: &Value, exact: impl Iterator<Item = &'a String>) -> Result<Self, String> {
+        let trailing_slash = match config["trailing_slash"].as_str() {
+            None | Some("merge") => TrailingSlash::Merge,
+            Some("strict") => TrailingSlash::Strict,
+            Some("redirect") => TrailingSlash::Redirect,
+            Some(other) => return Err(format!("routing.trailing_slash: unknown mode \"{}\" (expected merge, strict or redirect)", other)),
+        };
+        let case_insensitive = config["case_insensitive"].as_bool().unwrap_or(false);
+        let folded = if case_insensitive { exact.map(|key| (key.to_ascii_lowercase(), key.clone())).collect() } else { HashMap::new() };
+        Ok(Self { trailing_slash, case_insensitive, folded })
+    }
+
+    /// Whether `/users/` may only match a route written with the slash.
+    pub fn strict_slash(&self) -> bool {
+        self.trailing_slash != TrailingSlash::Merge
+    }
+
+    /// The exact route declared under `key`, in another case if case doesn't matter.
+    pub fn exact<'a, T>(&self, routes: &'a HashMap<String, T>, key: &str) -> Option<&'a T> {
+        routes.get(key).or_else(|| {
+            let declared = self.folded.get(&key.to_ascii_lowercase())?;
+            routes.get(declared)
+        })
+    }
+
+    /// Whether a fixed segment of a route matches a segment of the path.
+    pub fn same_segment(&self, route: &str, path: &str) -> bool {
+        route == path || (self.case_insensitive && route.eq_ignore_ascii_case(path))
+    }
+}
+
+/// The path with its trailing slash removed, or added; none for `/` and
+/// paths ending in an empty segment.
+pub fn toggled(path: &str) -> Option<String> {
+    match path.strip_suffix('/') {
+        Some(bare) if bare.is_empty() || bare.ends_with('/') => None,
+        Some(bare) => Some(bare.to_string()),
+        None => Some(format!("{}/", path)),
+    }
+}
+
+/// Sends the client from `path` to `target`, its other spelling: 301 for
+/// GET and HEAD, 308 for the rest so the method and body are kept. The
+/// location is relative, so it stays right behind a proxy or an app prefix.
+pub fn redirect(method: &str, path: &str, target: &str, query: Option<&str>) -> Response {
+    let last = |p: &str| p.trim_end_matches('/').rsplit('/').next().unwrap_or_default().to_string();
+    let mut location = if path.ends_with('/') {
+        // From `/a/b/` the parent is `/a/b`, so `../b` is `/a/b`
+        format!("../{}", last(target))
+    } else {
+        // `./` keeps a segment with a colon from reading as a scheme
+        format!("./{}/", last(path))
+    };
+    if let Some(query) = query {
+        location = format!("{}?{}", location, query);
+    }
+    let status = if matches!(method, "GET" | "HEAD") { StatusCode::MOVED_PERMANENTLY } else { StatusCode::PERMANENT_REDIRECT };
+    match HeaderValue::from_str(&location) {
+        Ok(location) => (status, [(header::LOCATION, location)]).into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
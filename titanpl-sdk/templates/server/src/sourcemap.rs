@@ -0,0 +1,166 @@
+use dashmap::DashMap;
+use regex::{Captures, Regex};
+use serde_json::Value;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+// Bundles by the script name they were compiled under
+static BUNDLES: OnceLock<DashMap<String, Arc<Bundle>>> = OnceLock::new();
+static FRAME: OnceLock<Regex> = OnceLock::new();
+
+struct Bundle {
+    path: PathBuf,
+    root: PathBuf,
+    // Parsed on the first stack trace that points into the bundle
+    map: OnceLock<Option<SourceMap>>,
+}
+
+struct Mapping {
+    column: u32,
+    source: u32,
+    line: u32,
+    source_column: u32,
+}
+
+/// A decoded source map v3: for every generated line, its segments sorted by
+/// column.
+struct SourceMap {
+    sources: Vec<String>,
+    lines: Vec<Vec<Mapping>>,
+}
+
+impl SourceMap {
+    /// Sources are resolved against the map's directory and shown relative to
+    /// the project root (`app/actions/hello.ts`).
+    fn parse(json: &str, map_dir: &Path, root: &Path) -> Option<Self> {
+        let map: Value = serde_json::from_str(json).ok()?;
+        let source_root = map["sourceRoot"].as_str().unwrap_or("");
+        let sources = map["sources"]
+            .as_array()?
+            .iter()
+            .map(|s| {
+                let path = normalize(&map_dir.join(source_root).join(s.as_str().unwrap_or("")));
+                path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/")
+            })
+            .collect();
+
+        let (mut source, mut line, mut source_column) = (0i64, 0i64, 0i64);
+        let mut lines = Vec::new();
+        for generated in map["mappings"].as_str()?.split(';') {
+            let mut column = 0i64;
+            let mut segments = Vec::new();
+            for segment in generated.split(',').filter(|s| !s.is_empty()) {
+                let fields = decode_vlq(segment)?;
+                column += fields[0];
+                // One-field segments map to nothing
+                if fields.len() >= 4 {
+                    source += fields[1];
+                    line += fields[2];
+                    source_column += fields[3];
+                    segments.push(Mapping {
+                        column: column as u32,
+                        source: source as u32,
+                        line: line as u32,
+                        source_column: source_column as u32,
+                    });
+                }
+            }
+            lines.push(segments);
+        }
+        Some(Self { sources, lines })
+    }
+
+    /// The original position of a 1-based generated `line` and `column`.
+    fn lookup(&self, line: u32, column: u32) -> Option<(&str, u32, u32)> {
+        let segments = self.lines.get(line.checked_sub(1)? as usize)?;
+        let column = column.saturating_sub(1);
+        let index = segments.partition_point(|m| m.column <= column).checked_sub(1)?;
+        let mapping = &segments[index];
+        let source = self.sources.get(mapping.source as usize)?;
+        Some((source, mapping.line + 1, mapping.source_column + 1))
+    }
+}
+
+/// Remembers that the script compiled as `name` came from `bundle`, whose
+/// source map (if any) sits next to it as `<bundle>.map`.
+pub fn register(name: &str, bundle: &Path, root: &Path) {
+    let bundles = BUNDLES.get_or_init(DashMap::new);
+    // A transpiled source moves to a new cache file when it changes
+    if bundles.get(name).is_some_and(|b| b.path == bundle) {
+        return;
+    }
+    bundles.insert(
+        name.to_string(),
+        Arc::new(Bundle { path: bundle.to_path_buf(), root: root.to_path_buf(), map: OnceLock::new() }),
+    );
+}
+
+/// Rewrites `bundle:line:column` locations in a V8 stack trace to the
+/// original source files. Frames without a source map are left alone.
+pub fn rewrite(stack: &str) -> String {
+    let Some(bundles) = BUNDLES.get() else {
+        return stack.to_string();
+    };
+    let frame = FRAME.get_or_init(|| Regex::new(r"([^\s()]+):(\d+):(\d+)").unwrap());
+    frame
+        .replace_all(stack, |caps: &Captures| {
+            let original = || caps[0].to_string();
+            let Some(bundle) = bundles.get(&caps[1]).map(|b| b.clone()) else {
+                return original();
+            };
+            let map = bundle.map.get_or_init(|| {
+                let mut map_path = bundle.path.clone().into_os_string();
+                map_path.push(".map");
+                let map_path = PathBuf::from(map_path);
+                let json = std::fs::read_to_string(&map_path).ok()?;
+                SourceMap::parse(&json, map_path.parent()?, &bundle.root)
+            });
+            let (Ok(line), Ok(column)) = (caps[2].parse(), caps[3].parse()) else {
+                return original();
+            };
+            match map.as_ref().and_then(|m| m.lookup(line, column)) {
+                Some((source, line, column)) => format!("{}:{}:{}", source, line, column),
+                None => original(),
+            }
+        })
+        .into_owned()
+}
+
+fn decode_vlq(segment: &str) -> Option<Vec<i64>> {
+    let mut fields = Vec::with_capacity(5);
+    let (mut value, mut shift) = (0i64, 0u32);
+    for byte in segment.bytes() {
+        let digit = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        } as i64;
+        value += (digit & 31) << shift;
+        if digit & 32 != 0 {
+            shift += 5;
+            continue;
+        }
+        fields.push(if value & 1 == 1 { -(value >> 1) } else { value >> 1 });
+        (value, shift) = (0, 0);
+    }
+    (!fields.is_empty()).then_some(fields)
+}
+
+/// Resolves `.` and `..` without touching the filesystem; the sources may
+/// not exist where the server runs.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
@@ -0,0 +1,138 @@
+//! Apps compiled into the binary by `titan build --standalone`. The build
+//! packs routes.json, the action bundles and the files they read into one
+//! archive, and `cargo build --features standalone` embeds it with the
+//! startup snapshot. At boot the archive is unpacked once into a directory
+//! keyed by its hash, which then serves as the project root.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const PACK_MAGIC: &[u8] = b"TITANPACK1\n";
+// Beside routes.json and the actions, whatever the app reads from its root
+const EXTRA_FILES: [&str; 3] = ["titan.config.toml", "app/schema.graphql", "app/protos"];
+
+#[cfg(feature = "standalone")]
+const BUNDLE: Option<&[u8]> = Some(include_bytes!(concat!(env!("TITAN_STANDALONE_DIR"), "/bundle.bin")));
+#[cfg(not(feature = "standalone"))]
+const BUNDLE: Option<&[u8]> = None;
+
+#[cfg(feature = "standalone")]
+const SNAPSHOT: Option<&[u8]> = Some(include_bytes!(concat!(env!("TITAN_STANDALONE_DIR"), "/titan.snapshot")));
+#[cfg(not(feature = "standalone"))]
+const SNAPSHOT: Option<&[u8]> = None;
+
+static ROOT: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// The directory the embedded app was unpacked into, or None for a binary
+/// built without one. Unpacking failures end the process; there is nothing
+/// else to serve.
+pub fn root() -> Option<&'static Path> {
+    ROOT.get_or_init(|| {
+        let bundle = BUNDLE?;
+        match unpack(bundle) {
+            Ok(dir) => Some(dir),
+            Err(e) => {
+                eprintln!("titan-server: could not unpack the embedded app: {}", e);
+                std::process::exit(1);
+            }
+        }
+    })
+    .as_deref()
+}
+
+/// The embedded startup snapshot, as saved by `build`.
+pub fn snapshot() -> Option<&'static [u8]> {
+    SNAPSHOT
+}
+
+/// Writes the archive of the app at `root` to `out`, returning how many
+/// files went in. `dirs` are the configured ones, `public_dir` and `views_dir`.
+pub fn pack(root: &Path, config: &Path, dirs: &[&str], out: &Path) -> Result<usize, String> {
+    let actions = crate::action_management::find_actions_dir(&root.to_path_buf()).ok_or("no actions directory found")?;
+    let mut files: Vec<(String, PathBuf)> = vec![("routes.json".to_string(), config.to_path_buf())];
+    collect(&actions, "actions", &mut files);
+    for dir in dirs {
+        collect(&root.join(dir), dir.trim_matches('/'), &mut files);
+    }
+    for extra in EXTRA_FILES {
+        collect(&root.join(extra), extra, &mut files);
+    }
+
+    let mut archive = PACK_MAGIC.to_vec();
+    for (name, path) in &files {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        archive.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        archive.extend_from_slice(&data);
+    }
+    std::fs::write(out, &archive).map_err(|e| format!("{}: {}", out.display(), e))?;
+    Ok(files.len())
+}
+
+// Every file at or under `path`, named by where it goes in the archive
+fn collect(path: &Path, name: &str, files: &mut Vec<(String, PathBuf)>) {
+    if path.is_file() {
+        files.push((name.to_string(), path.to_path_buf()));
+        return;
+    }
+    if !path.is_dir() {
+        return;
+    }
+    for entry in walkdir::WalkDir::new(path).sort_by_file_name().into_iter().filter_map(Result::ok) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Ok(relative) = entry.path().strip_prefix(path) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        files.push((format!("{}/{}", name, relative), entry.path().to_path_buf()));
+    }
+}
+
+// Unpacks into `TITAN_EXTRACT_DIR`, or a directory under the system's temp
+// dir named by the archive's hash, so every process of one build shares it
+fn unpack(bundle: &'static [u8]) -> Result<PathBuf, String> {
+    let digest = ring::digest::digest(&ring::digest::SHA256, bundle);
+    let hash: String = digest.as_ref()[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    let dir = std::env::var("TITAN_EXTRACT_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| std::env::temp_dir().join(format!("titan-{}", hash)));
+    let done = dir.join(".unpacked");
+    if done.exists() {
+        return Ok(dir);
+    }
+
+    let mut rest = bundle.strip_prefix(PACK_MAGIC).ok_or("not a Titan archive")?;
+    let mut take = |n: usize| -> Result<&'static [u8], String> {
+        if rest.len() < n {
+            return Err("archive is truncated".into());
+        }
+        let (head, tail) = rest.split_at(n);
+        rest = tail;
+        Ok(head)
+    };
+    let mut entries = Vec::new();
+    while let Ok(len) = take(4) {
+        let name_len = u32::from_le_bytes(len.try_into().unwrap_or_default()) as usize;
+        let name = std::str::from_utf8(take(name_len)?).map_err(|_| "archive has a name that isn't UTF-8")?;
+        let data_len = u64::from_le_bytes(take(8)?.try_into().unwrap_or_default()) as usize;
+        let data = take(data_len)?;
+        // Names come from `pack`, but never write outside the directory
+        if name.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+            return Err(format!("archive entry '{}' is not a relative path", name));
+        }
+        entries.push((name, data));
+    }
+
+    for (name, data) in entries {
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
+        }
+        std::fs::write(&path, data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    std::fs::write(&done, hash).map_err(|e| format!("{}: {}", done.display(), e))?;
+    Ok(dir)
+}
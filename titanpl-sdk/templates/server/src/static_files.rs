@@ -0,0 +1,326 @@
+use axum::body::Body;
+use axum::http::{HeaderMap, Response, StatusCode, header};
+use bytes::Bytes;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Serves files from the public directory straight from the HTTP layer, so
+/// assets never queue behind actions.
+///
+/// Responses carry an ETag (answered with 304 on `If-None-Match`), honour
+/// single `Range` requests, and prefer a `.br` or `.gz` sibling of the file
+/// when the client accepts that encoding.
+pub struct StaticFiles {
+    root: PathBuf,
+}
+
+struct Variant {
+    path: PathBuf,
+    encoding: Option<&'static str>,
+    len: u64,
+    etag: String,
+}
+
+impl StaticFiles {
+    /// None when `root` isn't a directory, which turns static serving off.
+    pub fn new(root: PathBuf) -> Option<Self> {
+        let root = root.canonicalize().ok()?;
+        root.is_dir().then_some(Self { root })
+    }
+
+    /// The response for a GET or HEAD of `path`, or None when there is no such
+    /// file and the request should be a 404 as usual.
+    pub async fn serve(&self, method: &str, path: &str, headers: &HeaderMap) -> Option<Response<Body>> {
+        if method != "GET" && method != "HEAD" {
+            return None;
+        }
+        let file = self.resolve(path).await?;
+        let content_type = content_type(&file);
+
+        // Byte ranges refer to the identity encoding, so ranged requests skip the precompressed copies
+        let range = header_str(headers, header::RANGE);
+        let accept = header_str(headers, header::ACCEPT_ENCODING).unwrap_or("");
+        let mut candidates = Vec::new();
+        if range.is_none() {
+            if accepts(accept, "br") {
+                candidates.push("br");
+            }
+            if accepts(accept, "gzip") {
+                candidates.push("gzip");
+            }
+        }
+        let mut variant = None;
+        for encoding in candidates {
+            let suffix = if encoding == "br" { "br" } else { "gz" };
+            let Some(sibling) = self.sibling(&file, suffix).await else {
+                continue;
+            };
+            if let Some(v) = variant_of(sibling, Some(encoding)).await {
+                variant = Some(v);
+                break;
+            }
+        }
+        let variant = match variant {
+            Some(v) => v,
+            None => variant_of(file, None).await?,
+        };
+
+        let mut response = Response::builder()
+            .header(header::ETAG, &variant.etag)
+            .header(header::ACCEPT_RANGES, "bytes")
+            .header(header::VARY, "accept-encoding")
+            .header(header::CONTENT_TYPE, content_type);
+        if let Some(encoding) = variant.encoding {
+            response = response.header(header::CONTENT_ENCODING, encoding);
+        }
+
+        if header_str(headers, header::IF_NONE_MATCH).is_some_and(|tags| crate::etag::matches(tags, &variant.etag)) {
+            return response.status(StatusCode::NOT_MODIFIED).body(Body::empty()).ok();
+        }
+
+        // `If-Range` with a stale validator means the client wants the whole new file
+        let range = range.filter(|_| {
+            header_str(headers, header::IF_RANGE).is_none_or(|tag| tag.trim() == variant.etag)
+        });
+        let (status, start, len) = match range.map_or(ByteRange::Whole, |r| parse_range(r, variant.len)) {
+            ByteRange::Partial(start, end) => {
+                response = response.header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, variant.len),
+                );
+                (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+            }
+            ByteRange::Unsatisfiable => {
+                return response
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::CONTENT_RANGE, format!("bytes */{}", variant.len))
+                    .body(Body::empty())
+                    .ok();
+            }
+            ByteRange::Whole => (StatusCode::OK, 0, variant.len),
+        };
+
+        let response = response.status(status).header(header::CONTENT_LENGTH, len);
+        if method == "HEAD" {
+            return response.body(Body::empty()).ok();
+        }
+        let body = file_body(&variant.path, start, len).await?;
+        response.body(body).ok()
+    }
+
+    /// Maps a URL path onto a file under the root. `..` segments are refused
+    /// and symlinks must not lead outside the root; directories serve their
+    /// `index.html`.
+    async fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let decoded = percent_encoding::percent_decode_str(path).decode_utf8().ok()?;
+        let relative = Path::new(decoded.trim_start_matches('/'));
+        if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+            return None;
+        }
+
+        let mut full = self.root.join(relative);
+        if tokio::fs::metadata(&full).await.ok()?.is_dir() {
+            full.push("index.html");
+        }
+        let full = tokio::fs::canonicalize(&full).await.ok()?;
+        if !full.starts_with(&self.root) || !tokio::fs::metadata(&full).await.ok()?.is_file() {
+            return None;
+        }
+        Some(full)
+    }
+
+    /// The precompressed copy `file.<suffix>`, held to the same symlink rule
+    /// as the file itself.
+    async fn sibling(&self, file: &Path, suffix: &str) -> Option<PathBuf> {
+        let mut name = file.as_os_str().to_owned();
+        name.push(".");
+        name.push(suffix);
+        let sibling = tokio::fs::canonicalize(name).await.ok()?;
+        sibling.starts_with(&self.root).then_some(sibling)
+    }
+}
+
+async fn variant_of(path: PathBuf, encoding: Option<&'static str>) -> Option<Variant> {
+    let meta = tokio::fs::metadata(&path).await.ok()?;
+    if !meta.is_file() {
+        return None;
+    }
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    // Size and mtime change whenever the file does; no need to hash contents
+    let etag = match encoding {
+        Some(encoding) => format!("\"{:x}-{:x}-{}\"", meta.len(), modified, encoding),
+        None => format!("\"{:x}-{:x}\"", meta.len(), modified),
+    };
+    Some(Variant { path, encoding, len: meta.len(), etag })
+}
+
+/// Streams `len` bytes of the file starting at `start`.
+async fn file_body(path: &Path, start: u64, len: u64) -> Option<Body> {
+    let mut file = tokio::fs::File::open(path).await.ok()?;
+    if start > 0 {
+        file.seek(std::io::SeekFrom::Start(start)).await.ok()?;
+    }
+    let reader = file.take(len);
+    let stream = futures_util::stream::unfold(reader, |mut reader| async move {
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        match reader.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok::<_, std::io::Error>(Bytes::from(buf)), reader))
+            }
+            Err(e) => Some((Err(e), reader)),
+        }
+    });
+    Some(Body::from_stream(stream))
+}
+
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Whole,
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header into inclusive bounds. Only single `bytes=`
+/// ranges are supported; anything else, including a range that doesn't
+/// parse, is answered with the whole file as RFC 9110 §14.2 allows. Only a
+/// range starting at or past the end is unsatisfiable.
+fn parse_range(header: &str, len: u64) -> ByteRange {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return ByteRange::Whole;
+    };
+    let Some((start, end)) = spec.split_once('-').filter(|_| !spec.contains(',')) else {
+        return ByteRange::Whole;
+    };
+    match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(n) if n > 0 && len > 0 => ByteRange::Partial(len.saturating_sub(n), len - 1),
+            Ok(_) => ByteRange::Unsatisfiable,
+            Err(_) => ByteRange::Whole,
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return ByteRange::Whole;
+            };
+            let end = match end {
+                "" => u64::MAX,
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return ByteRange::Whole,
+                },
+            };
+            if start >= len {
+                return ByteRange::Unsatisfiable;
+            }
+            ByteRange::Partial(start, end.min(len - 1))
+        }
+    }
+}
+
+fn accepts(accept_encoding: &str, encoding: &str) -> bool {
+    accept_encoding.split(',').any(|entry| {
+        let mut parts = entry.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let refused = parts.any(|p| matches!(p.trim(), "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+        name.eq_ignore_ascii_case(encoding) && !refused
+    })
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ByteRange::Partial(0, 99));
+        assert_eq!(parse_range("bytes=900-", 1000), ByteRange::Partial(900, 999));
+        assert_eq!(parse_range("bytes=-100", 1000), ByteRange::Partial(900, 999));
+        assert_eq!(parse_range("bytes=-5000", 1000), ByteRange::Partial(0, 999));
+        assert_eq!(parse_range("bytes=500-5000", 1000), ByteRange::Partial(500, 999));
+        assert_eq!(parse_range(" bytes= 7 - 7 ", 1000), ByteRange::Partial(7, 7));
+    }
+
+    #[test]
+    fn invalid_ranges_serve_the_whole_file() {
+        for header in ["bytes=abc-", "bytes=5-3", "bytes=-abc", "bytes=1-x", "bytes=-", "bytes=0-1,5-6", "items=0-1", "bytes=5"] {
+            assert_eq!(parse_range(header, 1000), ByteRange::Whole, "{}", header);
+        }
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_unsatisfiable() {
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=1000-2000", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-10", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn precompressed_copies_stay_under_the_root() {
+        let base = std::env::temp_dir().join(format!("titan-static-{}", std::process::id()));
+        let root = base.join("public");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("app.js"), "console.log(1)").unwrap();
+        std::fs::write(root.join("app.js.gz"), "inside").unwrap();
+        std::fs::write(base.join("secret"), "outside").unwrap();
+        std::os::unix::fs::symlink(base.join("secret"), root.join("app.js.br")).unwrap();
+
+        let files = StaticFiles::new(root.clone()).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, "br, gzip".parse().unwrap());
+        let response = files.serve("GET", "/app.js", &headers).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"inside");
+
+        std::fs::remove_file(root.join("app.js.gz")).unwrap();
+        let response = files.serve("GET", "/app.js", &headers).await.unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        let _ = std::fs::remove_dir_all(&base);
+    }
+}
@@ -0,0 +1,163 @@
+//! Tenants with workers of their own. Each pool under `tenants.pools` is a
+//! separate worker pool with its own queue, heap and CPU limits, so a
+//! tenant stuck in a hot loop or flooding the server only backs up its own
+//! queue. Requests whose tenant has no pool go to the main workers.
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::runtime::{QueuePolicy, RequestTask, RuntimeLimits, RuntimeManager};
+
+// Where the tenant id of a request is read from
+enum Key {
+    Header(String),
+    Query(String),
+    Cookie(String),
+    // A claim of the verified JWT
+    Claim(String),
+    // The first label of the host name
+    Subdomain,
+}
+
+/// The `tenants` block of titan.config: `key` says how a request names its
+/// tenant (`"header:<name>"`, `"query:<name>"`, `"cookie:<name>"`,
+/// `"claim:<name>"` or `"subdomain"`), and each entry of `pools` is
+/// `{ threads, max_heap_mb, max_execution_ms, queue_limit, tenants }`.
+/// A pool serves the tenant it is named after, or the ids in `tenants`;
+/// the limits it leaves out are those of the main pool.
+pub struct TenantConfig {
+    key: Key,
+    pub pools: Vec<TenantPool>,
+}
+
+pub struct TenantPool {
+    pub name: String,
+    pub threads: usize,
+    ids: Vec<String>,
+    max_heap_mb: Option<u64>,
+    max_execution_ms: Option<u64>,
+    queue_limit: Option<usize>,
+}
+
+impl TenantConfig {
+    pub fn from_config(config: &Value) -> Result<Option<Self>, String> {
+        let Some(pools) = config["pools"].as_object() else {
+            return Ok(None);
+        };
+        let rule = config["key"].as_str().unwrap_or("header:x-tenant-id");
+        let key = match rule.split_once(':') {
+            None if rule == "subdomain" => Key::Subdomain,
+            Some(("header", name)) if !name.is_empty() => Key::Header(name.to_ascii_lowercase()),
+            Some(("query", name)) if !name.is_empty() => Key::Query(name.to_string()),
+            Some(("cookie", name)) if !name.is_empty() => Key::Cookie(name.to_string()),
+            Some(("claim", name)) if !name.is_empty() => Key::Claim(name.to_string()),
+            _ => return Err(format!("tenants.key: unknown key \"{}\"", rule)),
+        };
+        let mut parsed = Vec::new();
+        for (name, pool) in pools {
+            let threads = pool["threads"].as_u64().unwrap_or(1);
+            if threads == 0 {
+                return Err(format!("tenants.pools.{}: threads must be at least 1", name));
+            }
+            let ids = match &pool["tenants"] {
+                Value::Array(ids) => ids.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+                _ => vec![name.clone()],
+            };
+            parsed.push(TenantPool {
+                name: name.clone(),
+                threads: threads as usize,
+                ids,
+                max_heap_mb: pool["max_heap_mb"].as_u64(),
+                max_execution_ms: pool["max_execution_ms"].as_u64(),
+                queue_limit: pool["queue_limit"].as_u64().map(|n| n as usize),
+            });
+        }
+        Ok((!parsed.is_empty()).then_some(Self { key, pools: parsed }))
+    }
+
+    /// The id of the tenant the task belongs to, if it names one.
+    fn tenant(&self, task: &RequestTask) -> Option<String> {
+        let find = |pairs: &[(String, String)], name: &str| pairs.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone());
+        match &self.key {
+            Key::Header(name) => find(&task.headers, name),
+            Key::Query(name) => find(&task.query, name),
+            Key::Cookie(name) => task
+                .headers
+                .iter()
+                .filter(|(k, _)| k == "cookie")
+                .flat_map(|(_, v)| v.split(';'))
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(k, _)| k.trim() == name)
+                .map(|(_, v)| v.trim().to_string()),
+            Key::Claim(name) => task.auth.as_ref().and_then(|claims| match &claims[name] {
+                Value::String(id) => Some(id.clone()),
+                Value::Number(id) => Some(id.to_string()),
+                _ => None,
+            }),
+            Key::Subdomain => {
+                let host = find(&task.headers, "host")?;
+                let (label, _) = host.split_once('.')?;
+                Some(label.to_ascii_lowercase())
+            }
+        }
+    }
+}
+
+impl TenantPool {
+    /// The main pool's limits with the ones this pool sets (0 turns a limit off).
+    pub fn limits(&self, base: RuntimeLimits) -> RuntimeLimits {
+        RuntimeLimits {
+            max_heap_bytes: match self.max_heap_mb {
+                Some(mb) => (mb > 0).then(|| (mb as usize) * 1024 * 1024),
+                None => base.max_heap_bytes,
+            },
+            max_execution_ms: match self.max_execution_ms {
+                Some(ms) => (ms > 0).then_some(ms),
+                None => base.max_execution_ms,
+            },
+        }
+    }
+
+    /// The main pool's queue policy with this pool's bound. The bound is
+    /// fixed: the adaptive controller only runs on the main queue.
+    pub fn queue(&self, base: QueuePolicy) -> QueuePolicy {
+        QueuePolicy {
+            max_len: self.queue_limit.map_or(base.max_len, |n| (n > 0).then_some(n)),
+            adaptive: None,
+            ..base
+        }
+    }
+}
+
+/// The tenant pools of a `RuntimeManager`.
+pub struct Tenants {
+    config: TenantConfig,
+    // Tenant id to its index in `pools`
+    ids: HashMap<String, usize>,
+    pools: Vec<(String, Arc<RuntimeManager>)>,
+}
+
+impl Tenants {
+    /// `pools` holds a manager for each of `config.pools`, in order.
+    pub fn new(config: TenantConfig, pools: Vec<(String, Arc<RuntimeManager>)>) -> Self {
+        let ids = config
+            .pools
+            .iter()
+            .enumerate()
+            .flat_map(|(i, pool)| pool.ids.iter().map(move |id| (id.clone(), i)))
+            .collect();
+        Self { config, ids, pools }
+    }
+
+    /// The pool that serves the task's tenant, if it has one.
+    pub fn pool(&self, task: &RequestTask) -> Option<&Arc<RuntimeManager>> {
+        let tenant = self.config.tenant(task)?;
+        self.ids.get(&tenant).map(|&i| &self.pools[i].1)
+    }
+
+    /// Every pool with its name, for metrics and shutdown.
+    pub fn pools(&self) -> impl Iterator<Item = (&str, &Arc<RuntimeManager>)> {
+        self.pools.iter().map(|(name, pool)| (name.as_str(), pool))
+    }
+}
@@ -0,0 +1,90 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+// Bumped whenever the swc options change, so stale cache entries are never
+// picked up
+const CACHE_VERSION: &str = "2";
+
+// Workers boot in parallel; only one of them transpiles a given file
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Turns a `.ts` module into JavaScript the runtime can load, with swc. The
+/// output and its source map are cached under `.titan/cache/ts`, named by a
+/// hash of the file's path and source, so only changed files are transpiled
+/// again. Returns the path of the cached script.
+pub fn transpile(path: &Path, root: &Path) -> Result<PathBuf, String> {
+    let source = std::fs::read(path).map_err(|e| e.to_string())?;
+    let hash = cache_key(path, root, &source);
+
+    let dir = root.join(".titan").join("cache").join("ts");
+    let cached = dir.join(format!("{}.js", hash));
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if cached.exists() {
+        return Ok(cached);
+    }
+
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let tmp = dir.join(format!("{}.{}.tmp.js", hash, std::process::id()));
+    let output = Command::new(swc_binary(root))
+        .current_dir(root)
+        .arg(path)
+        .args(["--out-file"])
+        .arg(&tmp)
+        .args(["--source-maps", "true", "--no-swcrc"])
+        .args(["-C", "jsc.parser.syntax=typescript"])
+        .args(["-C", "jsc.target=es2020"])
+        .args(["-C", "module.type=es6"])
+        .output()
+        .map_err(|e| format!("could not run swc ({}); install @swc/cli and @swc/core", e))?;
+    if !output.status.success() {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+
+    // The map is renamed first so a cached script always has its map
+    let mut tmp_map = tmp.clone().into_os_string();
+    tmp_map.push(".map");
+    let mut cached_map = cached.clone().into_os_string();
+    cached_map.push(".map");
+    let _ = std::fs::rename(&tmp_map, &cached_map);
+    std::fs::rename(&tmp, &cached).map_err(|e| e.to_string())?;
+    Ok(cached)
+}
+
+// The path is part of the key: the source map names the file it came from,
+// so two files with the same source can't share an entry
+fn cache_key(path: &Path, root: &Path, source: &[u8]) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path).to_string_lossy().replace('\\', "/");
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    ctx.update(CACHE_VERSION.as_bytes());
+    ctx.update(&[0]);
+    ctx.update(relative.as_bytes());
+    ctx.update(&[0]);
+    ctx.update(source);
+    ctx.finish().as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// The project's own swc comes first, then one on PATH
+fn swc_binary(root: &Path) -> PathBuf {
+    let bin = if cfg!(windows) { "swc.cmd" } else { "swc" };
+    let local = root.join("node_modules").join(".bin").join(bin);
+    if local.exists() { local } else { PathBuf::from(bin) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_keys_cover_the_path() {
+        let root = Path::new("/srv/app");
+        let key = |path: &str, source: &str| cache_key(&root.join(path), root, source.as_bytes());
+        assert_eq!(key("app/actions/a.ts", "export {}"), key("app/actions/a.ts", "export {}"));
+        assert_ne!(key("app/actions/a.ts", "export {}"), key("app/actions/b.ts", "export {}"));
+        assert_ne!(key("app/actions/a.ts", "export {}"), key("app/actions/a.ts", "export const a = 1"));
+        // Moving the project doesn't invalidate its cache
+        let moved = cache_key(Path::new("/home/me/app/app/actions/a.ts"), Path::new("/home/me/app"), b"export {}");
+        assert_eq!(key("app/actions/a.ts", "export {}"), moved);
+    }
+}
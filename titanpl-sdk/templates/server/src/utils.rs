@@ -1,15 +1,9 @@
 pub fn blue(s: &str) -> String {
     format!("\x1b[38;5;39m{}\x1b[0m", s)
 }
-pub fn white(s: &str) -> String {
-    format!("\x1b[39m{}\x1b[0m", s)
-}
 pub fn yellow(s: &str) -> String {
     format!("\x1b[33m{}\x1b[0m", s)
 }
-pub fn green(s: &str) -> String {
-    format!("\x1b[32m{}\x1b[0m", s)
-}
 pub fn gray(s: &str) -> String {
     format!("\x1b[90m{}\x1b[0m", s)
 }
@@ -30,4 +24,45 @@ pub fn parse_expires_in(value: &str) -> Option<u64> {
     }
 }
 
+/// Year, month and day of a count of days since 1970-01-01 (Howard Hinnant's
+/// days-to-civil algorithm).
+pub fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u64;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u64;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
 
+/// Days since 1970-01-01 of a civil date, the inverse of `civil_from_days`.
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Seconds since the epoch of an HTTP date in the preferred IMF-fixdate form,
+/// e.g. `Sun, 06 Nov 1994 08:49:37 GMT`. The obsolete forms aren't accepted.
+pub fn parse_http_date(value: &str) -> Option<i64> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let mut parts = value.trim().split_once(", ")?.1.split(' ');
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|n| n.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if parts.next() != Some("GMT") || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second)
+}
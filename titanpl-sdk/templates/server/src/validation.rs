@@ -0,0 +1,458 @@
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+
+// Enough to fix a request; the rest would only repeat the same mistakes
+const MAX_VIOLATIONS: usize = 20;
+// Schemas referring to themselves deeper than this are cut off
+const MAX_DEPTH: usize = 64;
+
+/// One way a request differs from its action's schema.
+#[derive(Debug, Serialize)]
+pub struct Violation {
+    /// `params`, `query`, `headers` or `body`.
+    #[serde(rename = "in")]
+    pub location: &'static str,
+    /// A JSON pointer into that part of the request; empty for all of it.
+    pub path: String,
+    pub message: String,
+}
+
+// The parts of one action's schema that requests are checked against
+struct Rules {
+    params: Value,
+    query: Value,
+    headers: Value,
+    body: Value,
+}
+
+/// A request body, as far as the checks are concerned.
+pub enum Body {
+    Missing,
+    // Uploads, streams and formats with no JSON reading go through as they are
+    Unchecked,
+    Decoded(Result<Value, String>),
+}
+
+/// Checks requests against the `params`, `query`, `headers` and `body` of
+/// the schema each action exports, before they are queued for a worker.
+/// Path, query and header values are strings on the wire, so they are read
+/// as the number, boolean or array their property asks for first.
+///
+/// The keywords understood are the JSON Schema ones schema libraries emit:
+/// `type`, `enum`, `const`, the numeric, string and array bounds, `pattern`,
+/// `format`, `properties`, `required`, `additionalProperties`, `items`,
+/// `prefixItems`, `allOf`, `anyOf`, `oneOf`, `not` and local `$ref`s.
+/// Others are ignored.
+pub struct Validator {
+    actions: HashMap<String, Rules>,
+    patterns: HashMap<String, Regex>,
+}
+
+impl Validator {
+    /// None when no action declares anything to check. Fails on a `pattern`
+    /// that isn't a valid regex, so the mistake shows at boot.
+    pub fn from_schemas(schemas: &HashMap<String, Value>) -> Result<Option<Self>, String> {
+        let mut validator = Validator { actions: HashMap::new(), patterns: HashMap::new() };
+        for (action, schema) in schemas {
+            let rules = Rules {
+                params: schema["params"].clone(),
+                query: schema["query"].clone(),
+                headers: lowercase_properties(&schema["headers"]),
+                body: schema["body"].clone(),
+            };
+            if [&rules.params, &rules.query, &rules.headers, &rules.body].iter().all(|r| !r.is_object()) {
+                continue;
+            }
+            for part in [&rules.params, &rules.query, &rules.headers, &rules.body] {
+                validator.compile_patterns(part).map_err(|e| format!("schema of {}: {}", action, e))?;
+            }
+            validator.actions.insert(action.clone(), rules);
+        }
+        Ok((!validator.actions.is_empty()).then_some(validator))
+    }
+
+    fn compile_patterns(&mut self, schema: &Value) -> Result<(), String> {
+        match schema {
+            Value::Object(map) => {
+                if let Some(Value::String(pattern)) = map.get("pattern")
+                    && !self.patterns.contains_key(pattern)
+                {
+                    let regex = Regex::new(pattern).map_err(|e| format!("bad pattern {:?}: {}", pattern, e))?;
+                    self.patterns.insert(pattern.clone(), regex);
+                }
+                map.values().try_for_each(|v| self.compile_patterns(v))
+            }
+            Value::Array(items) => items.iter().try_for_each(|v| self.compile_patterns(v)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn covers(&self, action: &str) -> bool {
+        self.actions.contains_key(action)
+    }
+
+    /// Everything wrong with a request to `action`. A declared body is
+    /// required.
+    pub fn check(
+        &self,
+        action: &str,
+        params: &HashMap<String, String>,
+        query: &HashMap<String, String>,
+        headers: &HashMap<String, String>,
+        body: Body,
+    ) -> Vec<Violation> {
+        let Some(rules) = self.actions.get(action) else {
+            return Vec::new();
+        };
+        let mut out = Vec::new();
+        for (location, schema, input) in [
+            ("params", &rules.params, params),
+            ("query", &rules.query, query),
+            ("headers", &rules.headers, headers),
+        ] {
+            if schema.is_object() {
+                let value = coerce(schema, input);
+                Check { validator: self, root: schema, location, out: &mut out }.value(schema, &value, &mut String::new(), 0);
+            }
+        }
+        if rules.body.is_object() {
+            match body {
+                Body::Decoded(Ok(value)) => {
+                    Check { validator: self, root: &rules.body, location: "body", out: &mut out }.value(&rules.body, &value, &mut String::new(), 0);
+                }
+                Body::Decoded(Err(e)) => out.push(Violation { location: "body", path: String::new(), message: format!("could not be decoded: {}", e) }),
+                Body::Missing => out.push(Violation { location: "body", path: String::new(), message: "is required".into() }),
+                Body::Unchecked => {}
+            }
+        }
+        out.truncate(MAX_VIOLATIONS);
+        out
+    }
+}
+
+/// The 422 body for a request that failed its checks.
+pub fn response_body(violations: &[Violation]) -> Value {
+    json!({ "error": "Request validation failed", "kind": "validation", "errors": violations })
+}
+
+// Header names arrive lowercased, so the schema's are matched the same way
+fn lowercase_properties(schema: &Value) -> Value {
+    let mut schema = schema.clone();
+    if let Some(properties) = schema["properties"].as_object_mut() {
+        *properties = std::mem::take(properties).into_iter().map(|(k, v)| (k.to_ascii_lowercase(), v)).collect();
+    }
+    if let Some(required) = schema["required"].as_array_mut() {
+        for name in required.iter_mut() {
+            if let Some(s) = name.as_str() {
+                *name = s.to_ascii_lowercase().into();
+            }
+        }
+    }
+    schema
+}
+
+fn types(schema: &Value) -> Vec<&str> {
+    match &schema["type"] {
+        Value::String(t) => vec![t.as_str()],
+        Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Reads string inputs as the types their properties declare
+fn coerce(schema: &Value, strings: &HashMap<String, String>) -> Value {
+    let properties = schema["properties"].as_object();
+    let object: Map<String, Value> = strings
+        .iter()
+        .map(|(name, raw)| {
+            let property = properties.and_then(|p| p.get(name)).unwrap_or(&Value::Null);
+            (name.clone(), coerce_string(property, raw))
+        })
+        .collect();
+    Value::Object(object)
+}
+
+fn coerce_string(schema: &Value, raw: &str) -> Value {
+    for ty in types(schema) {
+        match ty {
+            "integer" => {
+                if let Ok(n) = raw.parse::<i64>() {
+                    return n.into();
+                }
+            }
+            "number" => {
+                if let Ok(n) = raw.parse::<f64>()
+                    && n.is_finite()
+                {
+                    return n.into();
+                }
+            }
+            "boolean" if raw == "true" || raw == "false" => return (raw == "true").into(),
+            "null" if raw.is_empty() => return Value::Null,
+            "array" => {
+                let items = &schema["items"];
+                return raw.split(',').filter(|s| !s.is_empty()).map(|s| coerce_string(items, s)).collect();
+            }
+            _ => {}
+        }
+    }
+    raw.into()
+}
+
+struct Check<'a> {
+    validator: &'a Validator,
+    root: &'a Value,
+    location: &'static str,
+    out: &'a mut Vec<Violation>,
+}
+
+impl Check<'_> {
+    fn fail(&mut self, path: &str, message: String) {
+        if self.out.len() < MAX_VIOLATIONS {
+            self.out.push(Violation { location: self.location, path: path.to_string(), message });
+        }
+    }
+
+    // Whether `value` passes `schema`, without reporting why not
+    fn passes(&mut self, schema: &Value, value: &Value, path: &mut String, depth: usize) -> bool {
+        let mut scratch = Vec::new();
+        Check { validator: self.validator, root: self.root, location: self.location, out: &mut scratch }.value(schema, value, path, depth);
+        scratch.is_empty()
+    }
+
+    fn value(&mut self, schema: &Value, value: &Value, path: &mut String, depth: usize) {
+        let Some(rules) = schema.as_object() else {
+            if schema == &Value::Bool(false) {
+                self.fail(path, "is not allowed".into());
+            }
+            return;
+        };
+        if depth > MAX_DEPTH {
+            return;
+        }
+        if let Some(reference) = rules.get("$ref").and_then(Value::as_str) {
+            let root = self.root;
+            match reference.strip_prefix('#').and_then(|pointer| root.pointer(pointer)) {
+                Some(target) => self.value(target, value, path, depth + 1),
+                None => self.fail(path, format!("refers to {}, which the schema doesn't define", reference)),
+            }
+        }
+
+        let expected = types(schema);
+        if !expected.is_empty() && !expected.iter().any(|t| is_type(value, t)) {
+            self.fail(path, format!("must be {}", expected.join(" or ")));
+            return;
+        }
+        if let Some(options) = rules.get("enum").and_then(Value::as_array)
+            && !options.iter().any(|o| equal(o, value))
+        {
+            let listed: Vec<String> = options.iter().map(Value::to_string).collect();
+            self.fail(path, format!("must be one of {}", listed.join(", ")));
+        }
+        if let Some(constant) = rules.get("const")
+            && !equal(constant, value)
+        {
+            self.fail(path, format!("must be {}", constant));
+        }
+
+        match value {
+            Value::Number(n) => self.number(rules, n.as_f64().unwrap_or(f64::NAN), path),
+            Value::String(s) => self.string(rules, s, path),
+            Value::Array(items) => self.array(rules, items, path, depth),
+            Value::Object(object) => self.object(rules, object, path, depth),
+            _ => {}
+        }
+
+        if let Some(all) = rules.get("allOf").and_then(Value::as_array) {
+            for sub in all {
+                self.value(sub, value, path, depth + 1);
+            }
+        }
+        if let Some(any) = rules.get("anyOf").and_then(Value::as_array)
+            && !any.iter().any(|sub| self.passes(sub, value, path, depth + 1))
+        {
+            self.fail(path, "must match at least one of anyOf".into());
+        }
+        if let Some(one) = rules.get("oneOf").and_then(Value::as_array) {
+            let matched = one.iter().filter(|sub| self.passes(sub, value, path, depth + 1)).count();
+            if matched != 1 {
+                self.fail(path, format!("must match exactly one of oneOf, matched {}", matched));
+            }
+        }
+        if let Some(not) = rules.get("not")
+            && self.passes(not, value, path, depth + 1)
+        {
+            self.fail(path, "must not match the schema in not".into());
+        }
+    }
+
+    fn number(&mut self, rules: &Map<String, Value>, n: f64, path: &str) {
+        let bound = |key: &str| rules.get(key).and_then(Value::as_f64);
+        if let Some(min) = bound("minimum")
+            && n < min
+        {
+            self.fail(path, format!("must be >= {}", min));
+        }
+        if let Some(max) = bound("maximum")
+            && n > max
+        {
+            self.fail(path, format!("must be <= {}", max));
+        }
+        if let Some(min) = bound("exclusiveMinimum")
+            && n <= min
+        {
+            self.fail(path, format!("must be > {}", min));
+        }
+        if let Some(max) = bound("exclusiveMaximum")
+            && n >= max
+        {
+            self.fail(path, format!("must be < {}", max));
+        }
+        if let Some(step) = bound("multipleOf").filter(|s| *s > 0.0) {
+            let ratio = n / step;
+            if (ratio - ratio.round()).abs() > 1e-9 {
+                self.fail(path, format!("must be a multiple of {}", step));
+            }
+        }
+    }
+
+    fn string(&mut self, rules: &Map<String, Value>, s: &str, path: &str) {
+        let len = s.chars().count() as u64;
+        if let Some(min) = rules.get("minLength").and_then(Value::as_u64)
+            && len < min
+        {
+            self.fail(path, format!("must have at least {} characters", min));
+        }
+        if let Some(max) = rules.get("maxLength").and_then(Value::as_u64)
+            && len > max
+        {
+            self.fail(path, format!("must have at most {} characters", max));
+        }
+        if let Some(pattern) = rules.get("pattern").and_then(Value::as_str)
+            && let Some(regex) = self.validator.patterns.get(pattern)
+            && !regex.is_match(s)
+        {
+            self.fail(path, format!("must match {}", pattern));
+        }
+        if let Some(format) = rules.get("format").and_then(Value::as_str)
+            && !has_format(format, s)
+        {
+            self.fail(path, format!("must be a valid {}", format));
+        }
+    }
+
+    fn array(&mut self, rules: &Map<String, Value>, items: &[Value], path: &mut String, depth: usize) {
+        let len = items.len() as u64;
+        if let Some(min) = rules.get("minItems").and_then(Value::as_u64)
+            && len < min
+        {
+            self.fail(path, format!("must have at least {} items", min));
+        }
+        if let Some(max) = rules.get("maxItems").and_then(Value::as_u64)
+            && len > max
+        {
+            self.fail(path, format!("must have at most {} items", max));
+        }
+        if rules.get("uniqueItems").and_then(Value::as_bool) == Some(true)
+            && items.iter().enumerate().any(|(i, a)| items[..i].iter().any(|b| equal(a, b)))
+        {
+            self.fail(path, "must not have duplicate items".into());
+        }
+        let prefix = rules.get("prefixItems").and_then(Value::as_array).map_or(&[][..], Vec::as_slice);
+        for (i, item) in items.iter().enumerate() {
+            let schema = match prefix.get(i) {
+                Some(schema) => schema,
+                None => match rules.get("items") {
+                    Some(schema) => schema,
+                    None => continue,
+                },
+            };
+            let len = path.len();
+            path.push_str(&format!("/{}", i));
+            self.value(schema, item, path, depth + 1);
+            path.truncate(len);
+        }
+    }
+
+    fn object(&mut self, rules: &Map<String, Value>, object: &Map<String, Value>, path: &mut String, depth: usize) {
+        if let Some(required) = rules.get("required").and_then(Value::as_array) {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(name) {
+                    self.fail(&format!("{}/{}", path, escape(name)), "is required".into());
+                }
+            }
+        }
+        let properties = rules.get("properties").and_then(Value::as_object);
+        let additional = rules.get("additionalProperties");
+        for (name, value) in object {
+            let schema = match properties.and_then(|p| p.get(name)) {
+                Some(schema) => schema,
+                None => match additional {
+                    Some(schema) => schema,
+                    None => continue,
+                },
+            };
+            let len = path.len();
+            path.push('/');
+            path.push_str(&escape(name));
+            if schema == &Value::Bool(false) {
+                self.fail(path, "is not an allowed property".into());
+            } else {
+                self.value(schema, value, path, depth + 1);
+            }
+            path.truncate(len);
+        }
+    }
+}
+
+// JSON pointer escaping of one token
+fn escape(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
+
+fn is_type(value: &Value, ty: &str) -> bool {
+    match ty {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "number" => value.is_number(),
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+// JSON Schema equality: 1 and 1.0 are the same number
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        (Value::Array(x), Value::Array(y)) => x.len() == y.len() && x.iter().zip(y).all(|(a, b)| equal(a, b)),
+        (Value::Object(x), Value::Object(y)) => x.len() == y.len() && x.iter().all(|(k, v)| y.get(k).is_some_and(|w| equal(v, w))),
+        _ => a == b,
+    }
+}
+
+// Formats are checked for shape, not looked up; unknown ones pass
+fn has_format(format: &str, s: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let date = |s: &str| {
+        let parts: Vec<&str> = s.split('-').collect();
+        parts.len() == 3 && parts[0].len() == 4 && parts[1].len() == 2 && parts[2].len() == 2 && parts.iter().all(|p| digits(p))
+    };
+    match format {
+        "email" => s.split_once('@').is_some_and(|(user, host)| !user.is_empty() && host.contains('.') && !host.starts_with('.') && !host.ends_with('.')),
+        "uuid" => {
+            let groups: Vec<&str> = s.split('-').collect();
+            groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12]) && groups.iter().all(|g| g.bytes().all(|b| b.is_ascii_hexdigit()))
+        }
+        "date" => date(s),
+        "date-time" => s.split_once(['T', 't', ' ']).is_some_and(|(d, time)| date(d) && time.len() >= 8 && time.as_bytes()[2] == b':'),
+        "uri" | "url" => s.split_once("://").is_some_and(|(scheme, rest)| !scheme.is_empty() && !rest.is_empty()),
+        "ipv4" => s.parse::<std::net::Ipv4Addr>().is_ok(),
+        "ipv6" => s.parse::<std::net::Ipv6Addr>().is_ok(),
+        _ => true,
+    }
+}
@@ -0,0 +1,510 @@
+//! Server-side templates in Handlebars syntax, compiled once at startup and
+//! rendered in Rust, so no isolate carries a template engine of its own.
+//!
+//! Supported: `{{path.to.value}}` (escaped), `{{{raw}}}` and `{{& raw}}`,
+//! `{{#if}}`, `{{#unless}}`, `{{#each}}` and `{{#with}}` with `{{else}}` and
+//! `{{else if}}`, `{{> partial}}` with an optional context, `this`, `../`,
+//! `@root`, `@index`, `@key`, `@first`, `@last`, `{{! comments }}` and `~`
+//! whitespace control. Custom helpers are not.
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+const EXTENSIONS: [&str; 4] = ["hbs", "handlebars", "mustache", "html"];
+// Partials including partials, past which a template is taken to recurse
+const MAX_DEPTH: usize = 32;
+
+static VIEWS: OnceLock<Views> = OnceLock::new();
+
+struct Views {
+    dir: PathBuf,
+    // Recompile when the files change, for `start --watch`
+    watch: bool,
+    compiled: RwLock<Compiled>,
+}
+
+struct Compiled {
+    // The files' paths, sizes and times when they were compiled
+    stamp: u64,
+    templates: HashMap<String, Vec<Node>>,
+}
+
+enum Node {
+    Text(String),
+    Value { path: Expr, escape: bool },
+    Block { kind: Block, path: Expr, body: Vec<Node>, otherwise: Vec<Node> },
+    Partial { name: String, context: Option<Expr> },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Block {
+    If,
+    Unless,
+    Each,
+    With,
+}
+
+impl Block {
+    fn name(self) -> &'static str {
+        match self {
+            Block::If => "if",
+            Block::Unless => "unless",
+            Block::Each => "each",
+            Block::With => "with",
+        }
+    }
+}
+
+/// Where a value is looked up: `../../@root.a.b`, `@index`, `this`.
+struct Expr {
+    // How many `../` lead it
+    up: usize,
+    root: bool,
+    data: Option<Data>,
+    keys: Vec<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Data {
+    Index,
+    Key,
+    First,
+    Last,
+}
+
+/// The templates under `dir` (`app/views` unless `views_dir` says otherwise),
+/// each named by its path without the extension: `emails/welcome`. A missing
+/// directory means no templates; one that fails to compile stops startup.
+pub fn configure(dir: &Path, watch: bool) -> Result<(), String> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    let (stamp, files) = scan(dir);
+    let templates = compile(dir, &files)?;
+    tracing::info!("Compiled {} template(s) from {}", templates.len(), dir.display());
+    let _ = VIEWS.set(Views { dir: dir.to_path_buf(), watch, compiled: RwLock::new(Compiled { stamp, templates }) });
+    Ok(())
+}
+
+/// Renders the template `name` with `data` as its context.
+pub fn render(name: &str, data: &Value) -> Result<String, String> {
+    let views = VIEWS.get().ok_or("no templates loaded; put them in app/views or set views_dir")?;
+    if views.watch {
+        refresh(views);
+    }
+    let compiled = views.compiled.read().unwrap();
+    let template = compiled.templates.get(name).ok_or_else(|| format!("template '{}' not found", name))?;
+    let mut out = String::new();
+    let mut frames = vec![Frame { value: data, index: None, key: None, len: 0 }];
+    render_nodes(&compiled.templates, template, &mut frames, 0, &mut out)?;
+    Ok(out)
+}
+
+// Recompiles everything if a file changed; a template that no longer
+// compiles leaves the previous ones in place
+fn refresh(views: &Views) {
+    let (stamp, files) = scan(&views.dir);
+    if views.compiled.read().unwrap().stamp == stamp {
+        return;
+    }
+    match compile(&views.dir, &files) {
+        Ok(templates) => {
+            tracing::info!("↻ Recompiled {} template(s)", templates.len());
+            *views.compiled.write().unwrap() = Compiled { stamp, templates };
+        }
+        Err(e) => {
+            tracing::error!("Template reload failed: {}", e);
+            views.compiled.write().unwrap().stamp = stamp;
+        }
+    }
+}
+
+// Every template file under `dir`, and a stamp that moves when any changes
+fn scan(dir: &Path) -> (u64, Vec<PathBuf>) {
+    let mut hasher = DefaultHasher::new();
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(dir).sort_by_file_name().into_iter().filter_map(Result::ok) {
+        let path = entry.path();
+        if !entry.file_type().is_file() || !path.extension().and_then(|e| e.to_str()).is_some_and(|e| EXTENSIONS.contains(&e)) {
+            continue;
+        }
+        if let Ok(meta) = entry.metadata() {
+            (path, meta.len(), meta.modified().ok()).hash(&mut hasher);
+        }
+        files.push(path.to_path_buf());
+    }
+    (hasher.finish(), files)
+}
+
+fn compile(dir: &Path, files: &[PathBuf]) -> Result<HashMap<String, Vec<Node>>, String> {
+    let mut templates = HashMap::new();
+    for path in files {
+        let relative = path.strip_prefix(dir).unwrap_or(path).with_extension("");
+        let name = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let nodes = parse(&source).map_err(|e| format!("{}: {}", path.display(), e))?;
+        templates.insert(name, nodes);
+    }
+    Ok(templates)
+}
+
+// ----------------------------------------------------------------------------
+// PARSING
+// ----------------------------------------------------------------------------
+
+// A block whose closing tag hasn't been reached
+struct Open {
+    kind: Block,
+    path: Expr,
+    body: Vec<Node>,
+    otherwise: Option<Vec<Node>>,
+    line: usize,
+    // Opened by `{{else if}}`, so closed by its parent's `{{/if}}`
+    chained: bool,
+}
+
+impl Open {
+    fn into_node(self) -> Node {
+        Node::Block { kind: self.kind, path: self.path, body: self.body, otherwise: self.otherwise.unwrap_or_default() }
+    }
+}
+
+// Where the next node goes: the innermost open block, or the top level
+fn current<'a>(nodes: &'a mut Vec<Node>, stack: &'a mut [Open]) -> &'a mut Vec<Node> {
+    match stack.last_mut() {
+        Some(Open { otherwise: Some(otherwise), .. }) => otherwise,
+        Some(open) => &mut open.body,
+        None => nodes,
+    }
+}
+
+fn parse(source: &str) -> Result<Vec<Node>, String> {
+    let mut nodes = Vec::new();
+    let mut stack: Vec<Open> = Vec::new();
+    let mut rest = source;
+    let mut trim_next = false;
+    let line_at = |rest: &str| source[..source.len() - rest.len()].matches('\n').count() + 1;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find("{{") else {
+            let text = if trim_next { rest.trim_start() } else { rest };
+            if !text.is_empty() {
+                current(&mut nodes, &mut stack).push(Node::Text(text.to_string()));
+            }
+            break;
+        };
+        let line = line_at(&rest[start..]);
+        let raw = rest[start..].starts_with("{{{");
+        let open_len = if raw { 3 } else { 2 };
+        let after_open = &rest[start + open_len..];
+        let (inner, consumed) = if after_open.starts_with("!--") {
+            let end = after_open.find("--}}").ok_or_else(|| format!("line {}: unclosed comment", line))?;
+            ("!", end + 4)
+        } else {
+            let close = if raw { "}}}" } else { "}}" };
+            let end = after_open.find(close).ok_or_else(|| format!("line {}: unclosed tag", line))?;
+            (&after_open[..end], end + close.len())
+        };
+
+        let mut text = &rest[..start];
+        let mut inner = inner;
+        if trim_next {
+            text = text.trim_start();
+        }
+        if let Some(stripped) = inner.strip_prefix('~') {
+            text = text.trim_end();
+            inner = stripped;
+        }
+        trim_next = false;
+        if let Some(stripped) = inner.strip_suffix('~') {
+            trim_next = true;
+            inner = stripped;
+        }
+        if !text.is_empty() {
+            current(&mut nodes, &mut stack).push(Node::Text(text.to_string()));
+        }
+        rest = &after_open[consumed..];
+
+        let inner = inner.trim();
+        let fail = |message: String| format!("line {}: {}", line, message);
+        if raw {
+            let path = parse_expr(inner).map_err(fail)?;
+            current(&mut nodes, &mut stack).push(Node::Value { path, escape: false });
+            continue;
+        }
+        if inner.starts_with('!') {
+            continue;
+        }
+        if let Some(block) = inner.strip_prefix('#') {
+            let (name, arg) = block.split_once(char::is_whitespace).unwrap_or((block, ""));
+            let kind = block_kind(name).ok_or_else(|| fail(format!("unknown block helper '{}'", name)))?;
+            let path = parse_expr(arg).map_err(fail)?;
+            stack.push(Open { kind, path, body: Vec::new(), otherwise: None, line, chained: false });
+        } else if let Some(name) = inner.strip_prefix('/') {
+            let name = name.trim();
+            loop {
+                let open = stack.pop().ok_or_else(|| fail(format!("{{{{/{}}}}} closes nothing", name)))?;
+                // A chain is checked against the block that started it
+                if !open.chained && open.kind.name() != name {
+                    return Err(fail(format!("{{{{/{}}}}} closes the {{{{#{}}}}} on line {}", name, open.kind.name(), open.line)));
+                }
+                let chained = open.chained;
+                let node = open.into_node();
+                current(&mut nodes, &mut stack).push(node);
+                if !chained {
+                    break;
+                }
+            }
+        } else if inner == "else" || inner.starts_with("else ") {
+            let open = stack.last_mut().filter(|open| open.otherwise.is_none()).ok_or_else(|| fail("{{else}} outside a block".to_string()))?;
+            open.otherwise = Some(Vec::new());
+            if let Some(chain) = inner.strip_prefix("else ") {
+                let chain = chain.trim();
+                let (name, arg) = chain.split_once(char::is_whitespace).unwrap_or((chain, ""));
+                let kind = block_kind(name).ok_or_else(|| fail(format!("unknown block helper '{}'", name)))?;
+                let path = parse_expr(arg).map_err(fail)?;
+                stack.push(Open { kind, path, body: Vec::new(), otherwise: None, line, chained: true });
+            }
+        } else if let Some(partial) = inner.strip_prefix('>') {
+            let partial = partial.trim();
+            let (name, context) = partial.split_once(char::is_whitespace).unwrap_or((partial, ""));
+            if name.is_empty() {
+                return Err(fail("partial without a name".to_string()));
+            }
+            let context = if context.trim().is_empty() { None } else { Some(parse_expr(context).map_err(fail)?) };
+            current(&mut nodes, &mut stack).push(Node::Partial { name: name.to_string(), context });
+        } else {
+            let (escape, expr) = match inner.strip_prefix('&') {
+                Some(expr) => (false, expr),
+                None => (true, inner),
+            };
+            let path = parse_expr(expr).map_err(fail)?;
+            current(&mut nodes, &mut stack).push(Node::Value { path, escape });
+        }
+    }
+
+    match stack.pop() {
+        Some(open) => Err(format!("line {}: {{{{#{}}}}} is never closed", open.line, open.kind.name())),
+        None => Ok(nodes),
+    }
+}
+
+fn block_kind(name: &str) -> Option<Block> {
+    match name {
+        "if" => Some(Block::If),
+        "unless" => Some(Block::Unless),
+        "each" => Some(Block::Each),
+        "with" => Some(Block::With),
+        _ => None,
+    }
+}
+
+fn parse_expr(source: &str) -> Result<Expr, String> {
+    let mut rest = source.trim();
+    if rest.is_empty() {
+        return Err("missing expression".to_string());
+    }
+    if rest.contains(char::is_whitespace) {
+        return Err(format!("'{}': helpers are not supported", rest));
+    }
+    let mut expr = Expr { up: 0, root: false, data: None, keys: Vec::new() };
+    while let Some(parent) = rest.strip_prefix("../") {
+        expr.up += 1;
+        rest = parent;
+    }
+    if let Some(name) = rest.strip_prefix('@') {
+        let (name, keys) = name.split_once('.').unwrap_or((name, ""));
+        expr.data = match name {
+            "root" => {
+                expr.root = true;
+                None
+            }
+            "index" => Some(Data::Index),
+            "key" => Some(Data::Key),
+            "first" => Some(Data::First),
+            "last" => Some(Data::Last),
+            _ => return Err(format!("unknown data variable '@{}'", name)),
+        };
+        rest = keys;
+    } else if rest == "this" || rest == "." {
+        rest = "";
+    } else if let Some(keys) = rest.strip_prefix("this.") {
+        rest = keys;
+    }
+    if !rest.is_empty() {
+        for key in rest.split('.') {
+            if key.is_empty() {
+                return Err(format!("'{}': empty path segment", source.trim()));
+            }
+            expr.keys.push(key.to_string());
+        }
+    }
+    Ok(expr)
+}
+
+// ----------------------------------------------------------------------------
+// RENDERING
+// ----------------------------------------------------------------------------
+
+struct Frame<'a> {
+    value: &'a Value,
+    // Position in the `each` that pushed it
+    index: Option<usize>,
+    key: Option<&'a str>,
+    len: usize,
+}
+
+enum Resolved<'a> {
+    Ref(&'a Value),
+    // `@index` and the like, made up on the spot
+    Data(Value),
+}
+
+impl Resolved<'_> {
+    fn value(&self) -> &Value {
+        match self {
+            Resolved::Ref(value) => value,
+            Resolved::Data(value) => value,
+        }
+    }
+}
+
+fn resolve<'a>(expr: &Expr, frames: &[Frame<'a>]) -> Option<Resolved<'a>> {
+    let frame = if expr.root { frames.first()? } else { frames.get(frames.len().checked_sub(expr.up + 1)?)? };
+    if let Some(data) = expr.data {
+        let index = frame.index?;
+        return Some(Resolved::Data(match data {
+            Data::Index => Value::from(index),
+            Data::Key => frame.key.map_or_else(|| Value::from(index), Value::from),
+            Data::First => Value::Bool(index == 0),
+            Data::Last => Value::Bool(index + 1 == frame.len),
+        }));
+    }
+    let mut value = frame.value;
+    for key in &expr.keys {
+        value = match value {
+            Value::Object(map) => map.get(key)?,
+            Value::Array(items) if key == "length" => return Some(Resolved::Data(Value::from(items.len()))),
+            Value::Array(items) => items.get(key.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(Resolved::Ref(value))
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+fn render_nodes<'a>(
+    templates: &HashMap<String, Vec<Node>>,
+    nodes: &[Node],
+    frames: &mut Vec<Frame<'a>>,
+    depth: usize,
+    out: &mut String,
+) -> Result<(), String> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Value { path, escape } => {
+                if let Some(resolved) = resolve(path, frames) {
+                    write_value(out, resolved.value(), *escape);
+                }
+            }
+            Node::Block { kind: kind @ (Block::If | Block::Unless), path, body, otherwise } => {
+                let holds = resolve(path, frames).is_some_and(|r| truthy(r.value()));
+                let branch = if holds == (*kind == Block::If) { body } else { otherwise };
+                render_nodes(templates, branch, frames, depth, out)?;
+            }
+            Node::Block { kind: Block::Each, path, body, otherwise } => {
+                let resolved = resolve(path, frames);
+                let value = match &resolved {
+                    Some(Resolved::Ref(value)) => *value,
+                    _ => &Value::Null,
+                };
+                match value {
+                    Value::Array(items) if !items.is_empty() => {
+                        for (index, item) in items.iter().enumerate() {
+                            frames.push(Frame { value: item, index: Some(index), key: None, len: items.len() });
+                            let result = render_nodes(templates, body, frames, depth, out);
+                            frames.pop();
+                            result?;
+                        }
+                    }
+                    Value::Object(map) if !map.is_empty() => {
+                        for (index, (key, item)) in map.iter().enumerate() {
+                            frames.push(Frame { value: item, index: Some(index), key: Some(key), len: map.len() });
+                            let result = render_nodes(templates, body, frames, depth, out);
+                            frames.pop();
+                            result?;
+                        }
+                    }
+                    _ => render_nodes(templates, otherwise, frames, depth, out)?,
+                }
+            }
+            Node::Block { kind: Block::With, path, body, otherwise } => match resolve(path, frames) {
+                Some(Resolved::Ref(value)) if truthy(value) => {
+                    frames.push(Frame { value, index: None, key: None, len: 0 });
+                    let result = render_nodes(templates, body, frames, depth, out);
+                    frames.pop();
+                    result?;
+                }
+                _ => render_nodes(templates, otherwise, frames, depth, out)?,
+            },
+            Node::Partial { name, context } => {
+                if depth >= MAX_DEPTH {
+                    return Err(format!("partials nested more than {} deep at '{}'", MAX_DEPTH, name));
+                }
+                let partial = templates.get(name).ok_or_else(|| format!("partial '{}' not found", name))?;
+                match context {
+                    Some(expr) => {
+                        let value = match resolve(expr, frames) {
+                            Some(Resolved::Ref(value)) => value,
+                            _ => &Value::Null,
+                        };
+                        frames.push(Frame { value, index: None, key: None, len: 0 });
+                        let result = render_nodes(templates, partial, frames, depth + 1, out);
+                        frames.pop();
+                        result?;
+                    }
+                    None => render_nodes(templates, partial, frames, depth + 1, out)?,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_value(out: &mut String, value: &Value, escape: bool) {
+    let text = match value {
+        Value::Null => return,
+        Value::String(s) => std::borrow::Cow::Borrowed(s.as_str()),
+        other => std::borrow::Cow::Owned(other.to_string()),
+    };
+    if !escape {
+        out.push_str(&text);
+        return;
+    }
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#x27;"),
+            '`' => out.push_str("&#x60;"),
+            '=' => out.push_str("&#x3D;"),
+            _ => out.push(c),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! `start --watch`: swaps changed actions into the running workers instead of
+//! restarting the process. Every worker re-evaluates only the files whose
+//! contents changed, between two pieces of work, so open connections, the KV
+//! store, sessions and queued tasks all carry on.
+
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::action_management::scan_actions;
+use crate::runtime::RuntimeManager;
+
+// How often the actions are looked at
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+// How long every worker gets to take the new actions in
+const RELOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a reload hands every worker.
+pub struct Changes {
+    pub root: PathBuf,
+    // Action name -> the file it now loads from
+    pub changed: Vec<(String, PathBuf)>,
+    pub removed: Vec<String>,
+}
+
+// What a file looked like on the last scan
+struct Seen {
+    path: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+    hash: u64,
+}
+
+/// Watches the actions under `root` for as long as the server runs.
+pub fn spawn(runtime: Arc<RuntimeManager>, root: PathBuf) {
+    tokio::spawn(async move {
+        let mut seen = scan(&root, &HashMap::new());
+        tracing::info!("Watching {} action(s) for changes", seen.len());
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let mut current = scan(&root, &seen);
+            if same(&seen, &current) {
+                continue;
+            }
+            // A rebuild writes many files; wait for them to settle
+            loop {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                let settled = scan(&root, &current);
+                if same(&current, &settled) {
+                    break;
+                }
+                current = settled;
+            }
+
+            let changes = Changes {
+                root: root.clone(),
+                changed: current
+                    .iter()
+                    .filter(|(name, file)| seen.get(*name).is_none_or(|old| old.hash != file.hash || old.path != file.path))
+                    .map(|(name, file)| (name.clone(), file.path.clone()))
+                    .collect(),
+                removed: seen.keys().filter(|name| !current.contains_key(*name)).cloned().collect(),
+            };
+            seen = current;
+            // Touched but not changed, as most files are after a full rebuild
+            if changes.changed.is_empty() && changes.removed.is_empty() {
+                continue;
+            }
+            reload(&runtime, changes).await;
+        }
+    });
+}
+
+async fn reload(runtime: &RuntimeManager, changes: Changes) {
+    let mut names: Vec<&str> = changes.changed.iter().map(|(name, _)| name.as_str()).collect();
+    names.extend(changes.removed.iter().map(|name| name.as_str()));
+    let names = names.join(", ");
+
+    let started = Instant::now();
+    let (workers, errors) = runtime.reload_actions(Arc::new(changes), RELOAD_TIMEOUT).await;
+    let elapsed = started.elapsed().as_secs_f64() * 1000.0;
+    match errors.first() {
+        None => tracing::info!("↻ Reloaded {} in {:.1} ms ({} worker(s))", names, elapsed, workers),
+        Some(error) => tracing::error!("↻ Reload of {} failed after {:.1} ms: {}", names, elapsed, error),
+    }
+}
+
+// Every action file, hashing only those whose size or time moved since `before`
+fn scan(root: &PathBuf, before: &HashMap<String, Seen>) -> HashMap<String, Seen> {
+    let mut files = HashMap::new();
+    for (name, path) in scan_actions(root) {
+        let Ok(meta) = std::fs::metadata(&path) else {
+            continue;
+        };
+        let (len, modified) = (meta.len(), meta.modified().ok());
+        let hash = match before.get(&name) {
+            Some(old) if old.path == path && old.len == len && old.modified == modified => old.hash,
+            _ => {
+                let Ok(contents) = std::fs::read(&path) else {
+                    continue;
+                };
+                let mut hasher = DefaultHasher::new();
+                contents.hash(&mut hasher);
+                hasher.finish()
+            }
+        };
+        files.insert(name, Seen { path, len, modified, hash });
+    }
+    files
+}
+
+fn same(a: &HashMap<String, Seen>, b: &HashMap<String, Seen>) -> bool {
+    a.len() == b.len()
+        && a.iter().all(|(name, x)| {
+            b.get(name).is_some_and(|y| x.path == y.path && x.len == y.len && x.modified == y.modified)
+        })
+}
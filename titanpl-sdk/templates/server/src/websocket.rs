@@ -0,0 +1,176 @@
+use base64::Engine;
+use bytes::Bytes;
+use hyper::upgrade::Upgraded;
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+// RFC 6455 handshake GUID
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_FRAME_BYTES: u64 = 16 * 1024 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// A frame travelling between the socket and the worker owning the connection.
+#[derive(Debug, Clone)]
+pub enum WsMessage {
+    Text(String),
+    Binary(Bytes),
+    Pong(Bytes),
+    Close,
+}
+
+/// The end of a connection that takes the frames read from the socket.
+pub trait Peer: Send + 'static {
+    fn message(&self, message: WsMessage);
+    fn close(&self);
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+pub fn is_upgrade_request(headers: &axum::http::HeaderMap) -> bool {
+    let upgrade = headers
+        .get("upgrade")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false);
+    upgrade && headers.contains_key("sec-websocket-key")
+}
+
+pub fn accept_key(client_key: &str) -> String {
+    let digest = ring::digest::digest(
+        &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{}{}", client_key.trim(), WS_GUID).as_bytes(),
+    );
+    base64::engine::general_purpose::STANDARD.encode(digest.as_ref())
+}
+
+/// Pumps frames between the upgraded connection and its peer until either
+/// side closes.
+pub async fn run_connection(
+    upgraded: Upgraded,
+    session: impl Peer,
+    outbound_tx: mpsc::UnboundedSender<WsMessage>,
+    mut outbound_rx: mpsc::UnboundedReceiver<WsMessage>,
+) {
+    let (mut reader, mut writer) = tokio::io::split(TokioIo::new(upgraded));
+
+    let writer_task = tokio::spawn(async move {
+        while let Some(msg) = outbound_rx.recv().await {
+            let res = match msg {
+                WsMessage::Text(s) => write_frame(&mut writer, OP_TEXT, s.as_bytes()).await,
+                WsMessage::Binary(b) => write_frame(&mut writer, OP_BINARY, &b).await,
+                WsMessage::Pong(b) => write_frame(&mut writer, OP_PONG, &b).await,
+                WsMessage::Close => {
+                    let _ = write_frame(&mut writer, OP_CLOSE, &[]).await;
+                    break;
+                }
+            };
+            if res.is_err() {
+                break;
+            }
+        }
+        let _ = writer.shutdown().await;
+    });
+
+    // Fragmented messages are reassembled before they reach the worker
+    let mut partial: Option<(u8, Vec<u8>)> = None;
+
+    while let Ok(frame) = read_frame(&mut reader).await {
+        match frame.opcode {
+            OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                let (opcode, mut data) = match (frame.opcode, partial.take()) {
+                    (OP_CONTINUATION, Some(p)) => p,
+                    (OP_CONTINUATION, None) => break, // Protocol error
+                    (op, _) => (op, Vec::new()),
+                };
+                data.extend_from_slice(&frame.payload);
+                if !frame.fin {
+                    partial = Some((opcode, data));
+                    continue;
+                }
+                let msg = if opcode == OP_TEXT {
+                    WsMessage::Text(String::from_utf8_lossy(&data).into_owned())
+                } else {
+                    WsMessage::Binary(Bytes::from(data))
+                };
+                session.message(msg);
+            }
+            OP_PING => {
+                let _ = outbound_tx.send(WsMessage::Pong(Bytes::from(frame.payload)));
+            }
+            OP_PONG => {}
+            _ => break, // Close or unknown opcode
+        }
+    }
+
+    session.close();
+    let _ = outbound_tx.send(WsMessage::Close);
+    let _ = writer_task.await;
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<Frame> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).await?;
+
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0F;
+    let masked = head[1] & 0x80 != 0;
+
+    let mut len = (head[1] & 0x7F) as u64;
+    if len == 126 {
+        let mut ext = [0u8; 2];
+        reader.read_exact(&mut ext).await?;
+        len = u16::from_be_bytes(ext) as u64;
+    } else if len == 127 {
+        let mut ext = [0u8; 8];
+        reader.read_exact(&mut ext).await?;
+        len = u64::from_be_bytes(ext);
+    }
+    if len > MAX_FRAME_BYTES {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "WebSocket frame too large"));
+    }
+
+    let mut mask = [0u8; 4];
+    if masked {
+        reader.read_exact(&mut mask).await?;
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    if masked {
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+    }
+
+    Ok(Frame { fin, opcode, payload })
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+    let mut head = Vec::with_capacity(10);
+    head.push(0x80 | opcode);
+    match payload.len() {
+        n if n < 126 => head.push(n as u8),
+        n if n <= u16::MAX as usize => {
+            head.push(126);
+            head.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            head.push(127);
+            head.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+    writer.write_all(&head).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await
+}
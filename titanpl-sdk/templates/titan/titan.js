@@ -14,6 +14,7 @@ const green = (t) => `\x1b[32m${t}\x1b[0m`;
 const routes = {};
 const dynamicRoutes = {};
 const actionMap = {};
+const serverConfig = {};
 
 function addRoute(method, route) {
   const key = `${method.toUpperCase()}:${route}`;
@@ -67,6 +68,14 @@ const t = {
     console.log(`[\x1b[35m${module}\x1b[0m] ${msg}`);
   },
 
+  /**
+   * Set server options written to routes.json (`__config`),
+   * e.g. t.config({ timeout_ms: 10000 })
+   */
+  config(options) {
+    Object.assign(serverConfig, options);
+  },
+
   /**
    * Start the Titan Server
    * RULE: Only calls bundle() - does NOT handle esbuild errors
@@ -91,7 +100,7 @@ const t = {
         routesPath,
         JSON.stringify(
           {
-            __config: { port, threads, stack_mb },
+            __config: { ...serverConfig, port, threads, stack_mb },
             routes,
             __dynamic_routes: Object.values(dynamicRoutes).flat()
           },